@@ -0,0 +1,262 @@
+# Builtin rules for TypeScript and JavaScript. `ts_library`, `js_library`
+# and `js_bundle` are available in every BUILD file, the rest can be loaded
+# from `@razel//js:defs.bzl`.
+
+TSC_TOOLCHAIN_TYPE = "@razel//js:tsc_toolchain_type"
+
+ESBUILD_TOOLCHAIN_TYPE = "@razel//js:esbuild_toolchain_type"
+
+JsInfo = provider(
+    doc = "The sources of a JavaScript library and its transitive dependencies.",
+    fields = {
+        "sources": "The sources a bundler needs, including those of transitive dependencies.",
+        "node_modules": "The `node_modules` directories packages are resolved from.",
+        "direct_sources": "The library's own source files.",
+        "package_name": "The name the library is imported by, for npm packages.",
+        "deps": "The direct dependencies providing `JsInfo`.",
+        "strict_deps": (
+            "Whether Razel checks that every module the library imports " +
+            "is provided by a direct dependency."
+        ),
+    },
+)
+
+# Returns the `JsInfo` of a library in the workspace.
+def _js_info(ctx):
+    deps = [dep for dep in ctx.attr.deps if JsInfo in dep]
+    return JsInfo(
+        sources = depset(ctx.files.srcs, transitive = [dep[JsInfo].sources for dep in deps]),
+        node_modules = depset(transitive = [dep[JsInfo].node_modules for dep in deps]),
+        direct_sources = ctx.files.srcs,
+        package_name = None,
+        deps = deps,
+        strict_deps = True,
+    )
+
+TsInfo = provider(
+    doc = "The declarations of a TypeScript library, which is all dependents compile against.",
+    fields = ["declarations", "transitive_declarations"],
+)
+
+def _tsc_toolchain_impl(ctx):
+    return [platform_common.ToolchainInfo(tsc = ctx.executable.tsc, tsc_inputs = ctx.files.data)]
+
+tsc_toolchain = rule(
+    implementation = _tsc_toolchain_impl,
+    attrs = {
+        # Runs as `tsc --project <tsconfig>` from the exec root.
+        "tsc": attr.label(
+            executable = True,
+            allow_single_file = True,
+            mandatory = True,
+            cfg = "exec",
+        ),
+        "data": attr.label_list(allow_files = True, cfg = "exec"),
+    },
+)
+
+# Returns the relative path from the directory of `file` to the exec root.
+def _exec_root(file):
+    return "/".join([".." for _ in file.dirname.split("/")])
+
+def _ts_library_impl(ctx):
+    # tsc resolves relative imports to `.ts` files before `.d.ts` files, and
+    # the exec root links every source of the workspace. Sources are
+    # compiled from a tree of links to only this library's own sources, so
+    # imports of other libraries find nothing but their declarations in the
+    # bin directory, never their sources to compile again.
+    stage = "_" + ctx.label.name + "_tsc"
+    sources = []
+    outputs = []
+    emitted = []
+
+    # Handwritten declarations are linked next to emitted ones, where
+    # dependents resolve them.
+    linked = []
+    for src in ctx.files.srcs:
+        if src.basename.endswith(".d.ts"):
+            declaration = ctx.actions.declare_file(src.basename, sibling = src)
+            ctx.actions.symlink(output = declaration, target_file = src)
+            linked.append(declaration)
+            continue
+        stem = src.basename.removesuffix("." + src.extension)
+        js = ctx.actions.declare_file(stem + ".js", sibling = src)
+        outputs.append(js)
+        outputs.append(ctx.actions.declare_file(stem + ".js.map", sibling = src))
+        emitted.append(ctx.actions.declare_file(stem + ".d.ts", sibling = src))
+
+        # Staged at the same path relative to the stage as its outputs have
+        # relative to the bin directory.
+        staged = ctx.actions.declare_file(
+            stage + "/" + js.dirname.removeprefix(ctx.bin_dir.path + "/") + "/" + src.basename,
+        )
+        ctx.actions.symlink(output = staged, target_file = src)
+        sources.append(staged)
+
+    # Dependents only see declarations, so changes to the implementation of
+    # a library never recompile them.
+    transitive = []
+    for dep in ctx.attr.deps:
+        if TsInfo in dep:
+            transitive.append(dep[TsInfo].transitive_declarations)
+        else:
+            # Packages such as `@npm//:@types/node` contribute their typings.
+            transitive.append(depset([
+                file
+                for file in dep[DefaultInfo].files.to_list()
+                if file.basename.endswith(".d.ts") or file.basename == "package.json"
+            ]))
+    dep_declarations = depset(transitive = transitive)
+    declarations = emitted + linked
+
+    if sources:
+        tsc = ctx.toolchains[TSC_TOOLCHAIN_TYPE]
+        config = ctx.actions.declare_file(ctx.label.name + ".tsconfig.json")
+        root = _exec_root(config)
+        bin_dir = root + "/" + ctx.bin_dir.path
+        stage_dir = root + "/" + config.dirname + "/" + stage
+        tsconfig = {
+            "compilerOptions": {
+                "rootDir": stage_dir,
+                "outDir": bin_dir,
+                "rootDirs": [stage_dir, bin_dir],
+                # Keeps the staged links from resolving back into the
+                # workspace, where the sources of other libraries are.
+                "preserveSymlinks": True,
+                "declaration": True,
+                "sourceMap": True,
+            },
+            "files": [
+                root + "/" + file.path
+                for file in sources + linked + dep_declarations.to_list()
+            ],
+        }
+        inputs = [config] + sources + linked + tsc.tsc_inputs
+        if ctx.file.tsconfig:
+            tsconfig["extends"] = root + "/" + ctx.file.tsconfig.path
+            inputs.append(ctx.file.tsconfig)
+        ctx.actions.write(output = config, content = json.encode(tsconfig))
+        ctx.actions.run(
+            outputs = outputs + emitted,
+            inputs = depset(inputs, transitive = [dep_declarations]),
+            executable = tsc.tsc,
+            arguments = ["--project", config.path],
+            mnemonic = "TypeScriptCompile",
+            progress_message = "Compiling TypeScript %{label}",
+        )
+
+    return [
+        DefaultInfo(files = depset(outputs + declarations)),
+        TsInfo(
+            declarations = depset(declarations),
+            transitive_declarations = depset(declarations, transitive = [dep_declarations]),
+        ),
+        _js_info(ctx),
+    ]
+
+ts_library = rule(
+    implementation = _ts_library_impl,
+    attrs = {
+        "srcs": attr.label_list(allow_files = [".ts", ".tsx"]),
+        "deps": attr.label_list(),
+
+        # A tsconfig.json whose options the generated config extends. Its
+        # `files`, `include`, `rootDir` and `outDir` are always overridden.
+        "tsconfig": attr.label(allow_single_file = [".json"]),
+    },
+    toolchains = [TSC_TOOLCHAIN_TYPE],
+)
+
+def _js_library_impl(ctx):
+    return [DefaultInfo(files = depset(ctx.files.srcs)), _js_info(ctx)]
+
+js_library = rule(
+    implementation = _js_library_impl,
+    attrs = {
+        "srcs": attr.label_list(allow_files = [".js", ".jsx", ".mjs", ".cjs", ".d.ts", ".json"]),
+        "deps": attr.label_list(),
+    },
+)
+
+def _esbuild_toolchain_impl(ctx):
+    return [platform_common.ToolchainInfo(
+        esbuild = ctx.executable.esbuild,
+        esbuild_inputs = ctx.files.data,
+    )]
+
+esbuild_toolchain = rule(
+    implementation = _esbuild_toolchain_impl,
+    attrs = {
+        "esbuild": attr.label(
+            executable = True,
+            allow_single_file = True,
+            mandatory = True,
+            cfg = "exec",
+        ),
+        "data": attr.label_list(allow_files = True, cfg = "exec"),
+    },
+)
+
+def _js_bundle_impl(ctx):
+    if ctx.attr.splitting and ctx.attr.format != "esm":
+        fail("Code splitting requires format = \"esm\".")
+
+    # Bundlers compile TypeScript themselves, so read sources rather than the
+    # outputs of `ts_library`.
+    transitive = []
+    node_modules = []
+    for dep in ctx.attr.deps:
+        if JsInfo in dep:
+            transitive.append(dep[JsInfo].sources)
+            node_modules.append(dep[JsInfo].node_modules)
+        else:
+            transitive.append(dep[DefaultInfo].files)
+
+    esbuild = ctx.toolchains[ESBUILD_TOOLCHAIN_TYPE]
+    out = ctx.actions.declare_directory(ctx.label.name)
+
+    # esbuild's metafile, which maps every hashed output file to the entry
+    # point or chunk it contains.
+    manifest = ctx.actions.declare_file(ctx.label.name + ".manifest.json")
+    arguments = [
+        ctx.file.entry_point.path,
+        "--bundle",
+        "--format=" + ctx.attr.format,
+        "--outdir=" + out.path,
+        "--entry-names=[name]-[hash]",
+        "--chunk-names=chunks/[name]-[hash]",
+        "--asset-names=assets/[name]-[hash]",
+        "--sourcemap",
+        "--metafile=" + manifest.path,
+    ]
+    if ctx.attr.splitting:
+        arguments.append("--splitting")
+    if ctx.attr._minify[BuildSettingInfo].value:
+        arguments.append("--minify")
+    ctx.actions.run(
+        outputs = [out, manifest],
+        inputs = depset([ctx.file.entry_point] + esbuild.esbuild_inputs, transitive = transitive),
+        executable = esbuild.esbuild,
+        arguments = arguments,
+        env = {"NODE_PATH": ":".join(depset(transitive = node_modules).to_list())},
+        mnemonic = "JsBundle",
+        progress_message = "Bundling %{label}",
+    )
+    return [DefaultInfo(files = depset([out, manifest]))]
+
+js_bundle = rule(
+    implementation = _js_bundle_impl,
+    attrs = {
+        "entry_point": attr.label(
+            allow_single_file = [".js", ".jsx", ".mjs", ".ts", ".tsx"],
+            mandatory = True,
+        ),
+        "deps": attr.label_list(),
+        "format": attr.string(default = "esm", values = ["esm", "iife", "cjs"]),
+
+        # Moves code shared between dynamic imports into separate chunks.
+        "splitting": attr.bool(),
+        "_minify": attr.label(default = "@razel//js:minify"),
+    },
+    toolchains = [ESBUILD_TOOLCHAIN_TYPE],
+)
@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+use crate::label::Label;
+use super::artifact::Artifact;
+
+/// A step of the build which generates output files from input files,
+/// registered by a rule implementation through `ctx.actions`.
+#[derive(Debug, PartialEq)]
+pub struct Action {
+  /// The target whose rule registered the action.
+  pub owner: Label,
+
+  /// A short name for the kind of work the action does, such as `TsCompile`.
+  pub mnemonic: String,
+
+  pub kind: ActionKind,
+  pub inputs: Vec<Rc<Artifact>>,
+  pub outputs: Vec<Rc<Artifact>>,
+
+  /// A message shown while the action runs.
+  pub progress_message: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ActionKind {
+  /// Runs a program in the exec root, from `ctx.actions.run()`.
+  Run {
+    /// The exec path of the program, or a bare name looked up on `PATH`.
+    executable: String,
+    arguments: Vec<String>,
+    env: BTreeMap<String, String>,
+  },
+
+  /// Writes a fixed string, from `ctx.actions.write()`.
+  Write {
+    content: String,
+    executable: bool,
+  },
+
+  /// Symlinks the output to another file, from `ctx.actions.symlink()`.
+  Symlink {
+    target: Rc<Artifact>,
+    executable: bool,
+  },
+
+  /// Copies a template with each substitution applied in order, from
+  /// `ctx.actions.expand_template()`.
+  ExpandTemplate {
+    template: Rc<Artifact>,
+    substitutions: Vec<(String, String)>,
+    executable: bool,
+  },
+}
+
+impl Display for Action {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    match &self.progress_message {
+      Some(message) => {
+        let output = self.outputs.first().map_or("", |output| output.path.as_str());
+        let owner = self.owner.to_string();
+        write!(f, "{}", message.replace("%{label}", &owner).replace("%{output}", output))
+      },
+      None => write!(f, "{} {}", self.mnemonic, self.owner),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn displays_progress_message_with_placeholders() {
+    let owner = Label { repo: "".to_owned(), package: "pkg".to_owned(), name: "lib".to_owned() };
+    let output = Rc::new(Artifact::generated("razel-out/fastbuild/bin", &owner, "lib.js", false));
+    let mut action = Action {
+      owner,
+      mnemonic: "TsCompile".to_owned(),
+      kind: ActionKind::Write { content: "".to_owned(), executable: false },
+      inputs: vec![],
+      outputs: vec![output],
+      progress_message: None,
+    };
+    assert_eq!(action.to_string(), "TsCompile //pkg:lib");
+
+    action.progress_message = Some("Compiling %{label} into %{output}".to_owned());
+    assert_eq!(action.to_string(), "Compiling //pkg:lib into razel-out/fastbuild/bin/pkg/lib.js");
+  }
+}
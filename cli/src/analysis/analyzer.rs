@@ -0,0 +1,704 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::rc::Rc;
+use crate::host::host::EntryKind;
+use crate::label::Label;
+use crate::package::{package_id, ConfigSetting, PackageLoader, Target};
+use crate::starlark::error::EvalError;
+use crate::starlark::ast::BinaryOp;
+use crate::starlark::eval::{binary_op, Evaluator};
+use crate::starlark::value::{Args, Object, Struct, Value};
+use super::action::Action;
+use super::artifact::Artifact;
+use super::config::Configuration;
+use super::context::{files, ActionRegistry, RuleContext};
+use super::depset::Depset;
+use super::provider::{Info, Provider};
+use super::rule::{AllowFiles, AttrKind, AttrSpec};
+use super::select::{Select, SelectPart, DEFAULT_CONDITION};
+use super::toolchain::{has_constraint, platform_constraints, registered_toolchains, resolve_toolchains, ToolchainContext};
+use super::transition::Cfg;
+use super::visibility::is_visible;
+
+/// A target after analysis: the providers it returned and the actions it
+/// registered.
+pub struct ConfiguredTarget {
+  pub label: Label,
+  pub config: Rc<Configuration>,
+  pub providers: Vec<Rc<Info>>,
+  pub actions: Vec<Rc<Action>>,
+
+  /// Whether this is a source file rather than a rule or one of its outputs.
+  pub is_source: bool,
+}
+
+impl ConfiguredTarget {
+  /// Returns the instance of the given provider, if this target returned one.
+  pub fn provider(&self, provider: &Provider) -> Option<Rc<Info>> {
+    self.providers.iter().find(|info| info.provider.equals(provider)).cloned()
+  }
+
+  /// The files to build when this target is requested, from `DefaultInfo`.
+  pub fn files(&self) -> Vec<Rc<Artifact>> {
+    self.default_info("files")
+      .and_then(|files| files.downcast::<Depset>())
+      .map(|depset| depset.items().iter().filter_map(|item| item.downcast::<Artifact>()).collect())
+      .unwrap_or_default()
+  }
+
+  /// The executable of this target, from `DefaultInfo`.
+  pub fn executable(&self) -> Option<Rc<Artifact>> {
+    self.default_info("executable").and_then(|executable| executable.downcast::<Artifact>())
+  }
+
+  fn default_info(&self, field: &str) -> Option<Value> {
+    let default_info = self.providers.iter().find(|info| info.provider.is_default_info())?;
+    default_info.fields.get(field).cloned()
+  }
+}
+
+impl Object for ConfiguredTarget {
+  fn type_name(&self) -> String {
+    "Target".to_owned()
+  }
+
+  fn get_attr(&self, name: &str) -> Option<Value> {
+    match name {
+      "label" => Some(Value::object(self.label.clone())),
+      _ => None,
+    }
+  }
+
+  fn attr_names(&self) -> Vec<String> {
+    vec!["label".to_owned()]
+  }
+
+  fn index(&self, key: &Value) -> Result<Value, EvalError> {
+    let provider = key.expect_object::<Provider>("index", "Provider")?;
+    self.provider(&provider)
+      .map(|info| Value::Object(info))
+      .ok_or_else(|| EvalError::msg(format!(
+        "{} does not provide {}.",
+        self.label,
+        provider.name(),
+      )))
+  }
+
+  fn contains(&self, key: &Value) -> Result<bool, EvalError> {
+    let provider = key.expect_object::<Provider>("left operand of `in`", "Provider")?;
+    Ok(self.provider(&provider).is_some())
+  }
+
+  fn repr(&self) -> String {
+    format!("<target {} ({})>", self.label, self.config.mnemonic())
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+/// Identifies a target analyzed in a specific configuration.
+type TargetKey = (Label, Rc<Configuration>);
+
+/// Analyzes targets by running their rule implementations, after analyzing
+/// their dependencies. Each target is analyzed at most once.
+pub struct Analyzer<'a> {
+  packages: &'a PackageLoader,
+  targets: RefCell<HashMap<TargetKey, Rc<ConfiguredTarget>>>,
+
+  /// The targets currently being analyzed, to detect dependency cycles.
+  stack: RefCell<Vec<TargetKey>>,
+
+  /// The toolchains registered in `MODULE.razel`, loaded when first needed.
+  registered_toolchains: RefCell<Option<Rc<Vec<Label>>>>,
+
+  /// Toolchain resolution is explained for targets and toolchain types whose
+  /// label contains this filter.
+  toolchain_resolution_debug: Option<String>,
+}
+
+impl Analyzer<'_> {
+  pub fn new(packages: &PackageLoader) -> Analyzer<'_> {
+    Analyzer {
+      packages,
+      targets: RefCell::new(HashMap::new()),
+      stack: RefCell::new(Vec::new()),
+      registered_toolchains: RefCell::new(None),
+      toolchain_resolution_debug: None,
+    }
+  }
+
+  /// Prints how toolchains are resolved for targets and toolchain types whose
+  /// label contains `filter`.
+  pub fn with_toolchain_resolution_debug(mut self, filter: Option<String>) -> Self {
+    self.toolchain_resolution_debug = filter;
+    self
+  }
+
+  /// Returns the actions registered by every target analyzed so far.
+  pub fn actions(&self) -> Vec<Rc<Action>> {
+    let targets = self.targets.borrow();
+    let mut keys: Vec<_> = targets.keys().collect();
+    keys.sort();
+    keys.iter().flat_map(|key| targets[*key].actions.iter().cloned()).collect()
+  }
+
+  /// Returns every target analyzed so far, ordered by label.
+  pub fn targets(&self) -> Vec<Rc<ConfiguredTarget>> {
+    let mut targets: Vec<_> = self.targets.borrow().values().cloned().collect();
+    targets.sort_by(|a, b| (&a.label, &a.config).cmp(&(&b.label, &b.config)));
+    targets.dedup_by(|a, b| Rc::ptr_eq(a, b));
+    targets
+  }
+
+  /// Analyzes the target with the given label in the given configuration.
+  pub fn analyze(&self, label: &Label, config: &Rc<Configuration>) -> Result<Rc<ConfiguredTarget>, Box<dyn Error>> {
+    let key = (label.clone(), config.clone());
+    if let Some(target) = self.targets.borrow().get(&key) {
+      return Ok(target.clone());
+    }
+
+    // Rules with an incoming transition are analyzed in the configuration it
+    // returns instead.
+    let package = self.packages.package_of(label)?;
+    if let Some(target) = package.targets.get(&label.name).filter(|target| !matches!(target.rule.cfg, Cfg::Target)) {
+      let error = |message: String| target_error(target, message);
+      let attr = self.transition_attrs(target, config, &error)?;
+      let transitioned = target.rule.cfg.apply(config, &attr)
+        .map_err(|err| error(format!("in incoming transition of {}: {}", target.rule.name(), err)))?;
+      if transitioned != *config {
+        let configured = self.analyze(label, &transitioned)?;
+        self.targets.borrow_mut().insert(key, configured.clone());
+        return Ok(configured);
+      }
+    }
+
+    let mut stack = self.stack.borrow_mut();
+    if let Some(start) = stack.iter().position(|other| *other == key) {
+      let cycle: Vec<_> = stack[start..].iter().chain([&key]).map(|(label, _)| label.to_string()).collect();
+      return Err(Box::new(AnalysisError(format!("Dependency cycle: {}", cycle.join(" -> ")))));
+    }
+    stack.push(key.clone());
+    drop(stack);
+
+    let result = self.analyze_uncached(label, config);
+    self.stack.borrow_mut().pop();
+
+    let target = Rc::new(result?);
+    self.targets.borrow_mut().insert(key, target.clone());
+    Ok(target)
+  }
+
+  fn analyze_uncached(&self, label: &Label, config: &Rc<Configuration>) -> Result<ConfiguredTarget, Box<dyn Error>> {
+    let package = self.packages.package_of(label)?;
+    if let Some(target) = package.targets.get(&label.name) {
+      return self.analyze_rule(target, config);
+    }
+
+    if let Some(setting) = package.build_settings.get(&label.name) {
+      let value = config.build_settings.get(label).unwrap_or(&setting.default);
+      let fields = BTreeMap::from([("value".to_owned(), setting.value(value))]);
+      let mut target = file_target(label, config, None, false);
+      target.providers.push(Rc::new(Info { provider: Provider::build_setting_info(), fields }));
+      return Ok(target);
+    }
+
+    // Outputs are generated by their rule, so only need to refer to its file.
+    if let Some(name) = package.outputs.get(&label.name) {
+      let rule = self.analyze(&Label { name: name.clone(), ..label.clone() }, config)?;
+      let output = Rc::new(Artifact::generated(&config.bin_dir(), &rule.label, &label.name, false));
+      return Ok(file_target(label, config, Some(output), false));
+    }
+
+    let path = Path::new(&label.package).join(&label.name);
+    match self.packages.repositories().get(&label.repo)?.resolve(&path) {
+      Ok(entry) if entry.kind == EntryKind::File => {
+        Ok(file_target(label, config, Some(Rc::new(Artifact::source(label))), true))
+      },
+      _ => Err(Box::new(AnalysisError(format!(
+        "No such target `{}`: not declared in package `{}` and not a source file.",
+        label,
+        package_id(&label.repo, &label.package),
+      )))),
+    }
+  }
+
+  fn analyze_rule(&self, target: &Target, config: &Rc<Configuration>) -> Result<ConfiguredTarget, Box<dyn Error>> {
+    let label = &target.label;
+    let rule = &target.rule;
+    let error = |message: String| target_error(target, message);
+
+    // The attributes transitions are called with, only needed when a label
+    // attribute has one.
+    let mut transition_attrs = None;
+
+    let mut attr = BTreeMap::from([("name".to_owned(), Value::str(&label.name))]);
+    let mut ctx_files = BTreeMap::new();
+    let mut ctx_file = BTreeMap::new();
+    let mut ctx_executable = BTreeMap::new();
+    let mut ctx_outputs = BTreeMap::new();
+    let mut predeclared = Vec::new();
+    for (name, spec) in &rule.attrs {
+      let in_attr = |err: EvalError| error(format!("attribute `{}`: {}", name, err.message));
+      let value = target.attrs.get(name).unwrap_or(&spec.default);
+      let value = &self.resolve(&target.label, value, config, &|message| {
+        error(format!("attribute `{}`: {}", name, message))
+      })?;
+      match spec.kind {
+        AttrKind::Label | AttrKind::LabelList => {
+          let deps = match (spec.kind, value) {
+            (AttrKind::Label, Value::None) => Vec::new(),
+            (AttrKind::Label, value) => vec![value.clone()],
+            (_, value) => value.expect_list(name).map_err(in_attr)?,
+          };
+
+          let dep_config = match spec.cfg {
+            Cfg::Target => config.clone(),
+            _ => {
+              if transition_attrs.is_none() {
+                transition_attrs = Some(self.transition_attrs(target, config, &error)?);
+              }
+              spec.cfg.apply(config, transition_attrs.as_ref().unwrap())
+                .map_err(|err| error(format!("in transition of attribute `{}`: {}", name, err)))?
+            },
+          };
+
+          let mut targets = Vec::new();
+          let mut dep_files = Vec::new();
+          for dep in deps {
+            let dep = self.dependency(target, spec, &dep, &dep_config, &|message| {
+              error(format!("attribute `{}`: {}", name, message))
+            })?;
+            // Files of rules are filtered by extension, where source files
+            // must match it.
+            dep_files.extend(dep.files().into_iter().filter(|file| match spec.allow_files {
+              AllowFiles::Extensions(_) => spec.allow_files.allows(&file.path),
+              _ => true,
+            }));
+            targets.push(dep);
+          }
+
+          let files_value = Value::list(
+            dep_files.iter().map(|file| Value::Object(file.clone())).collect());
+          if spec.kind == AttrKind::Label {
+            let dep = targets.first();
+            attr.insert(name.clone(), dep.map_or(Value::None, |dep| Value::Object(dep.clone())));
+            if spec.single_file {
+              if dep.is_some() && dep_files.len() != 1 {
+                return Err(Box::new(error(format!(
+                  "attribute `{}` must refer to exactly one file, got {}.",
+                  name,
+                  dep_files.len(),
+                ))));
+              }
+              let file = dep_files.first()
+                .map_or(Value::None, |file| Value::Object(file.clone()));
+              ctx_file.insert(name.clone(), file);
+            }
+            if spec.executable {
+              let executable = match dep {
+                Some(dep) if dep.is_source => dep.files().first().cloned(),
+                Some(dep) => dep.executable(),
+                None => None,
+              };
+              if dep.is_some() && executable.is_none() {
+                return Err(Box::new(error(format!(
+                  "attribute `{}` must refer to an executable.",
+                  name,
+                ))));
+              }
+              let executable = executable
+                .map_or(Value::None, |executable| Value::Object(executable));
+              ctx_executable.insert(name.clone(), executable);
+            }
+          } else {
+            let deps = targets.into_iter().map(|dep| Value::Object(dep)).collect();
+            attr.insert(name.clone(), Value::list(deps));
+          }
+          ctx_files.insert(name.clone(), files_value);
+        },
+        AttrKind::Output | AttrKind::OutputList => {
+          let outputs = match (spec.kind, value) {
+            (AttrKind::Output, Value::None) => Vec::new(),
+            (AttrKind::Output, value) => vec![value.clone()],
+            (_, value) => value.expect_list(name).map_err(in_attr)?,
+          };
+          let mut labels = Vec::new();
+          let mut artifacts = Vec::new();
+          for output in outputs {
+            let output = Label::parse_in(output.expect_str(name).map_err(in_attr)?, &label.repo, &label.package)?;
+            let artifact = Rc::new(Artifact::generated(&config.bin_dir(), label, &output.name, false));
+            predeclared.push(artifact.clone());
+            artifacts.push(Value::Object(artifact));
+            labels.push(Value::object(output));
+          }
+          if spec.kind == AttrKind::Output {
+            attr.insert(name.clone(), labels.pop().unwrap_or(Value::None));
+            ctx_outputs.insert(name.clone(), artifacts.pop().unwrap_or(Value::None));
+          } else {
+            attr.insert(name.clone(), Value::list(labels));
+            ctx_outputs.insert(name.clone(), Value::list(artifacts));
+          }
+        },
+        kind => {
+          attr.insert(name.clone(), coerce(kind, spec, value).map_err(in_attr)?);
+        },
+      }
+    }
+
+    let toolchains = self.toolchains(target, config)?;
+    let actions = Rc::new(ActionRegistry::new(label, &config.bin_dir(), predeclared.clone()));
+    let ctx = Value::object(RuleContext {
+      label: label.clone(),
+      attr: Value::object(Struct { fields: attr }),
+      files: Value::object(Struct { fields: ctx_files }),
+      file: Value::object(Struct { fields: ctx_file }),
+      executable: Value::object(Struct { fields: ctx_executable }),
+      outputs: Value::object(Struct { fields: ctx_outputs }),
+      var: make_variables(config),
+      toolchains: Value::object(ToolchainContext { toolchains }),
+      bin_dir: Value::object(Struct {
+        fields: BTreeMap::from([("path".to_owned(), Value::str(&config.bin_dir()))]),
+      }),
+      actions: actions.clone(),
+    });
+
+    let in_rule = |err: EvalError| error(format!("in {} rule: {}", rule.name(), err));
+    let mut eval = Evaluator::new();
+    let returned = eval.call(&rule.implementation, Args::positional(vec![ctx])).map_err(in_rule)?;
+    let actions = actions.finish().map_err(in_rule)?;
+
+    let returned = match returned {
+      Value::None => Vec::new(),
+      value @ Value::Object(_) => vec![value],
+      value => value.expect_list("rule implementation result").map_err(in_rule)?,
+    };
+    let mut providers: Vec<Rc<Info>> = Vec::new();
+    for value in returned {
+      let info = value.expect_object::<Info>("rule implementation result", "provider instance")
+        .map_err(in_rule)?;
+      if providers.iter().any(|other| other.provider.equals(info.provider.as_ref())) {
+        return Err(Box::new(error(format!(
+          "Provider {} was returned more than once.",
+          info.provider.name(),
+        ))));
+      }
+      info.freeze();
+      providers.push(info);
+    }
+
+    // Normalize `DefaultInfo`, whose files default to the predeclared outputs.
+    let default_info = providers.iter().position(|info| info.provider.is_default_info())
+      .map(|index| providers.remove(index));
+    let mut fields = default_info.map(|info| info.fields.clone()).unwrap_or_default();
+    let files = match fields.get("files") {
+      Some(Value::None) | None => {
+        let items = predeclared.iter().map(|file| Value::Object(file.clone()));
+        Rc::new(Depset::new(items.collect(), &[]))
+      },
+      Some(files) => files.expect_object::<Depset>("DefaultInfo.files", "depset").map_err(in_rule)?,
+    };
+    self::files(Some(&Value::Object(files.clone())), "DefaultInfo.files").map_err(in_rule)?;
+    let executable = match fields.get("executable") {
+      Some(Value::None) | None => None,
+      Some(executable) => Some(
+        executable.expect_object::<Artifact>("DefaultInfo.executable", "File")
+          .map_err(in_rule)?),
+    };
+    if rule.executable && executable.is_none() {
+      return Err(Box::new(error(format!(
+        "{} is an executable rule but did not return `DefaultInfo(executable = ...)`.",
+        rule.name(),
+      ))));
+    }
+    let mut files = files.items().to_vec();
+    if let Some(executable) = &executable {
+      files.push(Value::Object(executable.clone()));
+    }
+    fields.insert("files".to_owned(), Value::object(Depset::new(files, &[])));
+    let executable = executable
+      .map_or(Value::None, |executable| Value::Object(executable));
+    fields.insert("executable".to_owned(), executable);
+    providers.insert(0, Rc::new(Info { provider: Provider::default_info(), fields }));
+
+    Ok(ConfiguredTarget { label: label.clone(), config: config.clone(), providers, actions, is_source: false })
+  }
+
+  /// Resolves the toolchains of a rule target, returning the `ToolchainInfo`
+  /// of each by toolchain type.
+  fn toolchains(&self, target: &Target, config: &Rc<Configuration>) -> Result<BTreeMap<Label, Value>, Box<dyn Error>> {
+    let types = &target.rule.toolchains;
+    if types.is_empty() {
+      return Ok(BTreeMap::new());
+    }
+    if self.registered_toolchains.borrow().is_none() {
+      let registered = registered_toolchains(self.packages)?;
+      *self.registered_toolchains.borrow_mut() = Some(Rc::new(registered));
+    }
+    let registered = self.registered_toolchains.borrow().clone().unwrap();
+
+    let mut trace = Vec::new();
+    let resolved = resolve_toolchains(self.packages, &registered, types, &target.label, config, &mut trace);
+    if let Some(filter) = &self.toolchain_resolution_debug {
+      if types.iter().chain([&target.label]).any(|label| label.to_string().contains(filter.as_str())) {
+        for line in trace {
+          eprintln!("INFO: ToolchainResolution: {}", line);
+        }
+      }
+    }
+
+    let mut toolchains = BTreeMap::new();
+    for (toolchain_type, label) in resolved.map_err(|err| target_error(target, err.to_string()))? {
+      let info = self.analyze(&label, config)?.provider(&Provider::toolchain_info()).ok_or_else(|| {
+        target_error(target, format!("toolchain {} of type {} does not provide ToolchainInfo.", label, toolchain_type))
+      })?;
+      toolchains.insert(toolchain_type, Value::Object(info));
+    }
+    Ok(toolchains)
+  }
+
+  /// Returns whether a `config_setting` matches the configuration, including
+  /// its `flag_values` and `constraint_values`.
+  fn matches(&self, setting: &ConfigSetting, config: &Configuration) -> Result<bool, Box<dyn Error>> {
+    if !setting.matches(config) {
+      return Ok(false);
+    }
+    for (label, expected) in &setting.flag_values {
+      let build_setting = self.packages.build_setting(label)?;
+      let expected = build_setting.parse(expected)
+        .map_err(|err| AnalysisError(format!("Invalid value for {}: {}", label, err)))?;
+      if *config.build_settings.get(label).unwrap_or(&build_setting.default) != expected {
+        return Ok(false);
+      }
+    }
+    if !setting.constraint_values.is_empty() {
+      let constraints = platform_constraints(self.packages, config.platform.as_ref())?;
+      for value in &setting.constraint_values {
+        if !has_constraint(self.packages, &constraints, value)? {
+          return Ok(false);
+        }
+      }
+    }
+    Ok(true)
+  }
+
+  /// Returns the attributes of a target passed to transitions, as a struct of
+  /// their values as written with any `select()` resolved.
+  fn transition_attrs(&self, target: &Target, config: &Configuration, error: &dyn Fn(String) -> AnalysisError) ->
+      Result<Value, Box<dyn Error>> {
+    let mut fields = BTreeMap::from([("name".to_owned(), Value::str(&target.label.name))]);
+    for (name, spec) in &target.rule.attrs {
+      let value = target.attrs.get(name).unwrap_or(&spec.default);
+      let value = self.resolve(&target.label, value, config, &|message| {
+        error(format!("attribute `{}`: {}", name, message))
+      })?;
+      fields.insert(name.clone(), value);
+    }
+    Ok(Value::object(Struct { fields }))
+  }
+
+  /// Resolves any `select()` in an attribute value by picking the branches
+  /// whose conditions match the configuration.
+  fn resolve(
+    &self,
+    label: &Label,
+    value: &Value,
+    config: &Configuration,
+    error: &dyn Fn(String) -> AnalysisError,
+  ) -> Result<Value, Box<dyn Error>> {
+    let Some(select) = value.downcast::<Select>() else {
+      return Ok(value.clone());
+    };
+
+    let mut resolved: Option<Value> = None;
+    for part in &select.parts {
+      let value = match part {
+        SelectPart::Value(value) => value.clone(),
+        SelectPart::Branches { branches, no_match_error } => {
+          self.select_branch(label, branches, no_match_error.as_deref(), config, error)?
+        },
+      };
+      resolved = Some(match resolved {
+        Some(resolved) => binary_op(BinaryOp::Add, &resolved, &value).map_err(|err| error(err.message))?,
+        None => value,
+      });
+    }
+    Ok(resolved.unwrap())
+  }
+
+  /// Returns the value of the branch of a `select()` whose condition matches,
+  /// preferring conditions which refine all other matching ones.
+  fn select_branch(
+    &self,
+    label: &Label,
+    branches: &[(String, Value)],
+    no_match_error: Option<&str>,
+    config: &Configuration,
+    error: &dyn Fn(String) -> AnalysisError,
+  ) -> Result<Value, Box<dyn Error>> {
+    let mut default = None;
+    let mut matching: Vec<(&str, Rc<ConfigSetting>, &Value)> = Vec::new();
+    for (condition, value) in branches {
+      if condition == DEFAULT_CONDITION {
+        default = Some(value);
+        continue;
+      }
+      let condition_label = Label::parse_in(condition, &label.repo, &label.package).map_err(|err| error(err.0))?;
+      let setting = self.packages.package_of(&condition_label)?
+        .config_settings
+        .get(&condition_label.name)
+        .cloned()
+        .ok_or_else(|| error(format!("select() condition `{}` is not a config_setting.", condition)))?;
+      if self.matches(&setting, config).map_err(|err| error(format!("select() condition `{}`: {}", condition, err)))? {
+        matching.push((condition, setting, value));
+      }
+    }
+
+    let best = matching.iter().find(|(_, setting, _)| {
+      matching.iter().all(|(_, other, _)| setting.refines(other))
+    });
+    match (best, matching.as_slice(), default) {
+      (Some((_, _, value)), _, _) => Ok((*value).clone()),
+      (None, [], Some(default)) => Ok(default.clone()),
+      (None, [], None) => Err(Box::new(error(match no_match_error {
+        Some(message) => message.to_owned(),
+        None => format!(
+          "No condition of select() matches the configuration. Conditions checked: {}.",
+          branches.iter().map(|(condition, _)| condition.as_str()).collect::<Vec<_>>().join(", "),
+        ),
+      }))),
+      (None, [(first, ..), (second, ..), ..], _) => Err(Box::new(error(format!(
+        "select() conditions `{}` and `{}` both match, but neither refines the other.",
+        first,
+        second,
+      )))),
+      (None, [_], _) => unreachable!(),
+    }
+  }
+
+  /// Analyzes one dependency of a label attribute and checks it is allowed.
+  fn dependency(
+    &self,
+    target: &Target,
+    spec: &AttrSpec,
+    value: &Value,
+    config: &Rc<Configuration>,
+    error: &dyn Fn(String) -> AnalysisError,
+  ) -> Result<Rc<ConfiguredTarget>, Box<dyn Error>> {
+    let value = value.expect_str("label").map_err(|err| error(err.message))?;
+    let label = Label::parse_in(value, &target.label.repo, &target.label.package).map_err(|err| error(err.0))?;
+    let dep = self.analyze(&label, config)?;
+
+    if !is_visible(self.packages, &label, &target.label)? {
+      return Err(Box::new(error(format!(
+        "target {} is not visible from target {}. Check the visibility declaration of the former \
+          target if the dependency is legitimate.",
+        label,
+        target.label,
+      ))));
+    }
+
+    if dep.is_source && !dep.files().iter().all(|file| spec.allow_files.allows(&file.path)) {
+      return Err(Box::new(error(format!("source file {} is not allowed here.", label))));
+    }
+    for provider in &spec.providers {
+      if dep.provider(provider).is_none() {
+        return Err(Box::new(error(format!("{} does not provide {}.", label, provider.name()))));
+      }
+    }
+
+    Ok(dep)
+  }
+}
+
+/// Returns an error about a target, prefixed with where it was defined.
+fn target_error(target: &Target, message: String) -> AnalysisError {
+  match &target.location {
+    Some(location) => AnalysisError(format!("{}: {}: {}", location, target.label, message)),
+    None => AnalysisError(format!("{}: {}", target.label, message)),
+  }
+}
+
+/// Returns the target for a single source or generated file, or a target
+/// without any files.
+fn file_target(label: &Label, config: &Rc<Configuration>, file: Option<Rc<Artifact>>, is_source: bool) ->
+    ConfiguredTarget {
+  let files = Depset::new(file.into_iter().map(|file| Value::Object(file)).collect(), &[]);
+  let fields = BTreeMap::from([
+    ("files".to_owned(), Value::object(files)),
+    ("executable".to_owned(), Value::None),
+  ]);
+  ConfiguredTarget {
+    label: label.clone(),
+    config: config.clone(),
+    providers: vec![Rc::new(Info { provider: Provider::default_info(), fields })],
+    actions: Vec::new(),
+    is_source,
+  }
+}
+
+/// Returns the `ctx.var` dict of a configuration.
+fn make_variables(config: &Configuration) -> Value {
+  let mode = config.option("compilation_mode").unwrap_or_default();
+  let variables = [("COMPILATION_MODE", mode)].into_iter()
+    .chain(config.defines.iter().map(|(name, value)| (name.as_str(), value.as_str())))
+    .map(|(name, value)| (Value::str(name), Value::str(value)))
+    .collect();
+  let dict = Value::dict(variables).unwrap();
+  dict.freeze();
+  dict
+}
+
+/// Checks the value of a non-label attribute against its type.
+fn coerce(kind: AttrKind, spec: &AttrSpec, value: &Value) -> Result<Value, EvalError> {
+  match kind {
+    AttrKind::String => {
+      let string = value.expect_str("value")?;
+      if !spec.values.is_empty() && !spec.values.iter().any(|allowed| allowed == string) {
+        return Err(EvalError::msg(format!(
+          "value {} is not one of {}.",
+          value.repr(),
+          Value::list(spec.values.iter().map(|allowed| Value::str(allowed)).collect()).repr(),
+        )));
+      }
+    },
+    AttrKind::StringList => {
+      value.expect_str_list("value")?;
+    },
+    AttrKind::StringDict => {
+      for (key, value) in value.expect_dict("value")? {
+        key.expect_str("key")?;
+        value.expect_str("value")?;
+      }
+    },
+    AttrKind::Int => {
+      value.expect_int("value")?;
+    },
+    AttrKind::Bool => {
+      value.expect_bool("value")?;
+    },
+    _ => unreachable!(),
+  }
+  Ok(value.clone())
+}
+
+/// An error thrown when a target fails analysis.
+#[derive(Debug)]
+pub struct AnalysisError(pub String);
+
+impl Display for AnalysisError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for AnalysisError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
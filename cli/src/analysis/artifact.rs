@@ -0,0 +1,144 @@
+use std::any::Any;
+use std::path::Path;
+use std::rc::Rc;
+use crate::label::Label;
+use crate::starlark::value::{Object, Value};
+
+/// A source or generated file (or directory) used by actions.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Artifact {
+  /// The path relative to the exec root, such as `pkg/file.txt` for sources
+  /// or `razel-out/bin/pkg/file.txt` for generated files.
+  pub path: String,
+
+  /// The path relative to the root the file is in, such as `pkg/file.txt`.
+  pub short_path: String,
+
+  /// The source file's own label or the label of the generating target.
+  pub owner: Label,
+
+  pub is_source: bool,
+  pub is_directory: bool,
+}
+
+impl Artifact {
+  /// Returns the artifact for the source file with the given label.
+  pub fn source(label: &Label) -> Artifact {
+    Artifact {
+      path: join(&label.package_path(), &label.name),
+      short_path: short_path(label, &label.name),
+      owner: label.clone(),
+      is_source: true,
+      is_directory: false,
+    }
+  }
+
+  /// Returns the artifact for a file generated by `owner` at the given
+  /// package-relative path, in the output directory `root` of its
+  /// configuration.
+  pub fn generated(root: &str, owner: &Label, name: &str, is_directory: bool) -> Artifact {
+    Artifact {
+      path: format!("{}/{}", root, join(&owner.package_path(), name)),
+      short_path: short_path(owner, name),
+      owner: owner.clone(),
+      is_source: false,
+      is_directory,
+    }
+  }
+
+  pub fn basename(&self) -> &str {
+    self.path.rsplit('/').next().unwrap()
+  }
+
+  pub fn dirname(&self) -> &str {
+    self.path.rsplit_once('/').map_or("", |(dir, _)| dir)
+  }
+
+  pub fn extension(&self) -> &str {
+    Path::new(self.basename()).extension().map_or("", |ext| ext.to_str().unwrap())
+  }
+}
+
+fn join(package: &str, name: &str) -> String {
+  if package.is_empty() { name.to_owned() } else { format!("{}/{}", package, name) }
+}
+
+/// The path of a file relative to its root, which starts with `../<repo>` for
+/// files of external repositories.
+fn short_path(owner: &Label, name: &str) -> String {
+  let path = join(&owner.package, name);
+  if owner.repo.is_empty() { path } else { format!("../{}/{}", owner.repo, path) }
+}
+
+impl Object for Artifact {
+  fn type_name(&self) -> String {
+    "File".to_owned()
+  }
+
+  fn get_attr(&self, name: &str) -> Option<Value> {
+    Some(match name {
+      "path" => Value::str(&self.path),
+      "short_path" => Value::str(&self.short_path),
+      "basename" => Value::str(self.basename()),
+      "dirname" => Value::str(self.dirname()),
+      "extension" => Value::str(self.extension()),
+      "is_source" => Value::Bool(self.is_source),
+      "is_directory" => Value::Bool(self.is_directory),
+      "owner" => Value::object(self.owner.clone()),
+      _ => return None,
+    })
+  }
+
+  fn attr_names(&self) -> Vec<String> {
+    ["basename", "dirname", "extension", "is_directory", "is_source", "owner", "path", "short_path"]
+      .into_iter()
+      .map(|name| name.to_owned())
+      .collect()
+  }
+
+  fn equals(&self, other: &dyn Object) -> bool {
+    let other: &dyn Any = other;
+    other.downcast_ref::<Artifact>() == Some(self)
+  }
+
+  fn is_hashable(&self) -> bool {
+    true
+  }
+
+  fn repr(&self) -> String {
+    let kind = if self.is_source { "source" } else { "generated" };
+    let file = if self.is_directory { "directory" } else { "file" };
+    format!("<{} {} {}>", kind, file, self.path)
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn artifacts_have_exec_paths() {
+    let owner = Label { repo: "".to_owned(), package: "web/app".to_owned(), name: "bundle".to_owned() };
+    let generated = Artifact::generated("razel-out/opt/bin", &owner, "out/main.js", false);
+    assert_eq!(generated.path, "razel-out/opt/bin/web/app/out/main.js");
+    assert_eq!(generated.short_path, "web/app/out/main.js");
+    assert_eq!(generated.dirname(), "razel-out/opt/bin/web/app/out");
+    assert_eq!(generated.extension(), "js");
+
+    let root = Label { repo: "".to_owned(), package: "".to_owned(), name: "README".to_owned() };
+    let source = Artifact::source(&root);
+    assert_eq!(source.path, "README");
+    assert_eq!(source.dirname(), "");
+    assert_eq!(source.extension(), "");
+
+    let external = Label { repo: "lib".to_owned(), package: "src".to_owned(), name: "index.ts".to_owned() };
+    assert_eq!(Artifact::source(&external).path, "external/lib/src/index.ts");
+    assert_eq!(Artifact::source(&external).short_path, "../lib/src/index.ts");
+    let generated = Artifact::generated("razel-out/opt/bin", &external, "index.js", false);
+    assert_eq!(generated.path, "razel-out/opt/bin/external/lib/src/index.js");
+  }
+}
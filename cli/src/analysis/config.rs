@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use sha2::{Digest, Sha256};
+use crate::label::Label;
+
+/// The options a configuration is made of which can be matched by a
+/// `config_setting`'s `values`.
+pub const OPTIONS: [&str; 1] = ["compilation_mode"];
+
+/// The values of `--compilation_mode`.
+pub const COMPILATION_MODES: [&str; 3] = ["fastbuild", "dbg", "opt"];
+
+/// The directory of the exec root the outputs of every configuration are
+/// placed in, which `razel build` also links to from the workspace.
+pub const OUTPUT_DIR: &str = "razel-out";
+
+/// A set of option values which affect how targets are built. Every target is
+/// analyzed in a configuration, and the files it generates are placed in an
+/// output directory specific to that configuration.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Configuration {
+  /// Values of the options in `OPTIONS`.
+  pub options: BTreeMap<String, String>,
+
+  /// Arbitrary key-value pairs from `--define`.
+  pub defines: BTreeMap<String, String>,
+
+  /// Values of user-defined build settings in canonical form, only for
+  /// settings which differ from their default.
+  pub build_settings: BTreeMap<Label, String>,
+
+  /// The platform outputs are built for, from `--platforms`. `None` is the
+  /// default platform, which has no constraint values.
+  pub platform: Option<Label>,
+
+  /// The platform actions run on, from `--host_platform`, which is also the
+  /// target platform of the exec configuration.
+  pub host_platform: Option<Label>,
+
+  /// Whether this is the configuration tools run during the build are built
+  /// in.
+  pub is_exec: bool,
+}
+
+impl Default for Configuration {
+  fn default() -> Configuration {
+    Configuration {
+      options: BTreeMap::from([("compilation_mode".to_owned(), "fastbuild".to_owned())]),
+      defines: BTreeMap::new(),
+      build_settings: BTreeMap::new(),
+      platform: None,
+      host_platform: None,
+      is_exec: false,
+    }
+  }
+}
+
+impl Configuration {
+  pub fn option(&self, name: &str) -> Option<&str> {
+    self.options.get(name).map(|value| value.as_str())
+  }
+
+  /// Returns the configuration tools are built in, which is optimized and
+  /// does not depend on any target configuration.
+  pub fn exec(&self) -> Configuration {
+    let mut exec = Configuration {
+      platform: self.host_platform.clone(),
+      host_platform: self.host_platform.clone(),
+      is_exec: true,
+      ..Configuration::default()
+    };
+    exec.options.insert("compilation_mode".to_owned(), "opt".to_owned());
+    exec
+  }
+
+  /// A short name identifying the configuration, such as `fastbuild`,
+  /// `opt-exec` or `opt-0123abcd`. Configurations differing by more than the
+  /// compilation mode get a hash of their options appended.
+  pub fn mnemonic(&self) -> String {
+    let mode = self.option("compilation_mode").unwrap_or("fastbuild");
+    let mut mnemonic = mode.to_owned();
+    if self.is_exec {
+      mnemonic.push_str("-exec");
+    }
+
+    let mut base = Configuration { is_exec: self.is_exec, ..Configuration::default() };
+    base.options.insert("compilation_mode".to_owned(), mode.to_owned());
+    if *self != base {
+      let digest = self.digest();
+      mnemonic.push('-');
+      mnemonic.extend(digest[..4].iter().map(|byte| format!("{:02x}", byte)));
+    }
+    mnemonic
+  }
+
+  /// A SHA-256 digest over every field of the configuration. Output
+  /// directories are named after it, so it must not depend on the Rust
+  /// version the way `std::hash` does.
+  fn digest(&self) -> Vec<u8> {
+    let mut digest = Sha256::new();
+    let mut field = |kind: &str, parts: &[&str]| {
+      digest.update(kind);
+      for part in parts {
+        digest.update([0]);
+        digest.update(part);
+      }
+      digest.update([0xff]);
+    };
+    for (name, value) in &self.options {
+      field("option", &[name, value]);
+    }
+    for (name, value) in &self.defines {
+      field("define", &[name, value]);
+    }
+    for (label, value) in &self.build_settings {
+      field("build_setting", &[&label.to_string(), value]);
+    }
+    if let Some(platform) = &self.platform {
+      field("platform", &[&platform.to_string()]);
+    }
+    if let Some(platform) = &self.host_platform {
+      field("host_platform", &[&platform.to_string()]);
+    }
+    if self.is_exec {
+      field("exec", &[]);
+    }
+    digest.finalize().to_vec()
+  }
+
+  /// The exec path of the directory generated files are placed in.
+  pub fn bin_dir(&self) -> String {
+    format!("{}/{}/bin", OUTPUT_DIR, self.mnemonic())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use assertables::assert_starts_with;
+  use super::*;
+
+  #[test]
+  fn bin_dir_is_distinct_per_configuration() {
+    let fastbuild = Configuration::default();
+    assert_eq!(fastbuild.bin_dir(), "razel-out/fastbuild/bin");
+
+    let mut opt = Configuration::default();
+    opt.options.insert("compilation_mode".to_owned(), "opt".to_owned());
+    assert_eq!(opt.bin_dir(), "razel-out/opt/bin");
+
+    let mut prod = opt.clone();
+    prod.defines.insert("env".to_owned(), "prod".to_owned());
+    assert_starts_with!(prod.bin_dir(), "razel-out/opt-");
+    assert_ne!(prod.bin_dir(), opt.bin_dir());
+    assert_eq!(prod.bin_dir(), prod.clone().bin_dir());
+
+    assert_eq!(prod.exec().bin_dir(), "razel-out/opt-exec/bin");
+  }
+
+  #[test]
+  fn mnemonic_is_stable() {
+    // Pinned so a change of hash function, which would move every output
+    // directory, is caught.
+    let mut prod = Configuration::default();
+    prod.options.insert("compilation_mode".to_owned(), "opt".to_owned());
+    prod.defines.insert("env".to_owned(), "prod".to_owned());
+    assert_eq!(prod.mnemonic(), "opt-d2cd37b6");
+  }
+}
@@ -0,0 +1,397 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use crate::label::Label;
+use crate::starlark::error::EvalError;
+use crate::starlark::value::{Args, Object, Value};
+use super::action::{Action, ActionKind};
+use super::artifact::Artifact;
+use super::depset::Depset;
+
+/// The `ctx` argument passed to rule implementations.
+pub struct RuleContext {
+  pub label: Label,
+
+  /// Attribute values, with dependencies as `Target`s.
+  pub attr: Value,
+
+  /// The files of every label attribute, as lists.
+  pub files: Value,
+
+  /// The file of every `allow_single_file` label attribute.
+  pub file: Value,
+
+  /// The executable of every `executable` label attribute.
+  pub executable: Value,
+
+  /// The predeclared files of every output attribute.
+  pub outputs: Value,
+
+  /// Make variables of the configuration, such as `COMPILATION_MODE` and the
+  /// values of `--define`.
+  pub var: Value,
+
+  /// The `ToolchainInfo` of the toolchain resolved for each toolchain type of
+  /// the rule.
+  pub toolchains: Value,
+
+  /// The output directory of the configuration, with its exec path as
+  /// `path`.
+  pub bin_dir: Value,
+
+  pub actions: Rc<ActionRegistry>,
+}
+
+impl Object for RuleContext {
+  fn type_name(&self) -> String {
+    "ctx".to_owned()
+  }
+
+  fn get_attr(&self, name: &str) -> Option<Value> {
+    Some(match name {
+      "label" => Value::object(self.label.clone()),
+      "attr" => self.attr.clone(),
+      "files" => self.files.clone(),
+      "file" => self.file.clone(),
+      "executable" => self.executable.clone(),
+      "outputs" => self.outputs.clone(),
+      "var" => self.var.clone(),
+      "toolchains" => self.toolchains.clone(),
+      "bin_dir" => self.bin_dir.clone(),
+      "actions" => Value::object(Actions(self.actions.clone())),
+      _ => return None,
+    })
+  }
+
+  fn attr_names(&self) -> Vec<String> {
+    [
+      "actions", "attr", "bin_dir", "executable", "file", "files", "label",
+      "outputs", "toolchains", "var",
+    ]
+      .into_iter()
+      .map(|name| name.to_owned())
+      .collect()
+  }
+
+  fn repr(&self) -> String {
+    format!("<rule context for {}>", self.label)
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+/// The files declared and actions registered by one rule implementation.
+pub struct ActionRegistry {
+  owner: Label,
+
+  /// The output directory of the owner's configuration.
+  root: String,
+
+  /// Set once the implementation returns, after which nothing can be added.
+  closed: Cell<bool>,
+
+  /// Every file the rule may generate, predeclared or declared.
+  outputs: RefCell<Vec<Rc<Artifact>>>,
+
+  actions: RefCell<Vec<Rc<Action>>>,
+}
+
+impl ActionRegistry {
+  pub fn new(owner: &Label, root: &str, predeclared: Vec<Rc<Artifact>>) -> ActionRegistry {
+    ActionRegistry {
+      owner: owner.clone(),
+      root: root.to_owned(),
+      closed: Cell::new(false),
+      outputs: RefCell::new(predeclared),
+      actions: RefCell::new(Vec::new()),
+    }
+  }
+
+  /// Stops accepting actions and returns them, failing unless every output is
+  /// generated by exactly one action.
+  pub fn finish(&self) -> Result<Vec<Rc<Action>>, EvalError> {
+    self.closed.set(true);
+
+    let actions = self.actions.borrow();
+    for output in self.outputs.borrow().iter() {
+      let generating = actions.iter()
+        .filter(|action| action.outputs.contains(output))
+        .count();
+      match generating {
+        0 => return Err(EvalError::msg(format!(
+          "Output `{}` was declared but not generated by any action.",
+          output.short_path,
+        ))),
+        1 => {},
+        _ => return Err(EvalError::msg(format!(
+          "Output `{}` is generated by more than one action.",
+          output.short_path,
+        ))),
+      }
+    }
+
+    Ok(actions.clone())
+  }
+
+  fn check_open(&self) -> Result<(), EvalError> {
+    if self.closed.get() {
+      return Err(EvalError::msg(format!(
+        "Actions of {} can only be registered during its analysis.",
+        self.owner,
+      )));
+    }
+    Ok(())
+  }
+
+  fn declare(&self, args: Args, function: &str, is_directory: bool) -> Result<Value, EvalError> {
+    self.check_open()?;
+    let [filename, sibling] = args.bind(function, &["filename"], &["sibling"])?
+      .try_into()
+      .unwrap();
+    let filename = filename.unwrap();
+    let filename = filename.expect_str("filename")?;
+
+    let name = match sibling {
+      Some(Value::None) | None => filename.to_owned(),
+      Some(sibling) => {
+        let sibling = sibling.expect_object::<Artifact>("sibling", "File")?;
+        let repo_path = match self.owner.repo.as_str() {
+          "" => Some(sibling.short_path.as_str()),
+          repo => sibling.short_path.strip_prefix(&format!("../{}/", repo)),
+        }.unwrap_or(&sibling.short_path);
+        let dir = repo_path.strip_prefix(&self.owner.package).unwrap_or(repo_path);
+        let dir = dir.trim_start_matches('/').rsplit_once('/').map_or("", |(dir, _)| dir);
+        if dir.is_empty() { filename.to_owned() } else { format!("{}/{}", dir, filename) }
+      },
+    };
+    if name.is_empty() || name.split('/').any(|segment| matches!(segment, "" | "." | "..")) {
+      return Err(EvalError::msg(format!("Invalid output file name `{}`.", name)));
+    }
+
+    let artifact = Rc::new(Artifact::generated(&self.root, &self.owner, &name, is_directory));
+    let mut outputs = self.outputs.borrow_mut();
+    if outputs.iter().any(|output| output.path == artifact.path) {
+      return Err(EvalError::msg(format!("File `{}` was already declared.", name)));
+    }
+    outputs.push(artifact.clone());
+
+    Ok(Value::Object(artifact))
+  }
+
+  fn output(&self, value: &Value) -> Result<Rc<Artifact>, EvalError> {
+    let artifact = value.expect_object::<Artifact>("output", "File")?;
+    if !self.outputs.borrow().contains(&artifact) {
+      return Err(EvalError::msg(format!(
+        "`{}` is not an output declared by {}.",
+        artifact.path,
+        self.owner,
+      )));
+    }
+    Ok(artifact)
+  }
+
+  fn register(&self, action: Action) -> Result<Value, EvalError> {
+    self.check_open()?;
+    self.actions.borrow_mut().push(Rc::new(action));
+    Ok(Value::None)
+  }
+
+  fn run(&self, args: Args) -> Result<Value, EvalError> {
+    let [
+      outputs, inputs, executable, tools, arguments, mnemonic, progress_message,
+      env,
+    ] = args.bind(
+      "run",
+      &["outputs"],
+      &[
+        "inputs", "executable", "tools", "arguments", "mnemonic",
+        "progress_message", "env",
+      ],
+    )?.try_into().unwrap();
+
+    let outputs = outputs.unwrap().expect_list("outputs")?.iter()
+      .map(|output| self.output(output))
+      .collect::<Result<Vec<_>, _>>()?;
+    let mut inputs = files(inputs.as_ref(), "inputs")?;
+    inputs.extend(files(tools.as_ref(), "tools")?);
+    let executable = match executable {
+      Some(Value::Str(program)) => program.to_string(),
+      Some(executable) => {
+        let executable = executable.expect_object::<Artifact>("executable", "File or string")?;
+        inputs.push(executable.clone());
+        // Paths without a `/` would be looked up on `PATH` instead.
+        if executable.path.contains('/') { executable.path.clone() } else { format!("./{}", executable.path) }
+      },
+      None => return Err(EvalError::msg("run() missing required argument `executable`.")),
+    };
+    let arguments = match arguments {
+      Some(arguments) => arguments.expect_list("arguments")?.iter()
+        .map(|argument| match argument.downcast::<Artifact>() {
+          Some(artifact) => Ok(artifact.path.clone()),
+          None => argument.expect_str("argument").map(|argument| argument.to_owned()),
+        })
+        .collect::<Result<Vec<_>, _>>()?,
+      None => Vec::new(),
+    };
+    let env = match env {
+      Some(env) => env.expect_dict("env")?.iter()
+        .map(|(name, value)| Ok((
+          name.expect_str("env")?.to_owned(),
+          value.expect_str("env")?.to_owned(),
+        )))
+        .collect::<Result<BTreeMap<_, _>, EvalError>>()?,
+      None => BTreeMap::new(),
+    };
+
+    self.register(Action {
+      owner: self.owner.clone(),
+      mnemonic: mnemonic.map_or(Ok("Action".to_owned()), |m| {
+        m.expect_str("mnemonic").map(|m| m.to_owned())
+      })?,
+      kind: ActionKind::Run { executable, arguments, env },
+      inputs: dedup(inputs),
+      outputs,
+      progress_message: progress_message
+        .map(|m| m.expect_str("progress_message").map(|m| m.to_owned()))
+        .transpose()?,
+    })
+  }
+
+  fn write(&self, args: Args) -> Result<Value, EvalError> {
+    let [output, content, executable] = args
+      .bind("write", &["output", "content"], &["is_executable"])?
+      .try_into()
+      .unwrap();
+    let output = self.output(&output.unwrap())?;
+    let content = content.unwrap().expect_str("content")?.to_owned();
+
+    self.register(Action {
+      owner: self.owner.clone(),
+      mnemonic: "FileWrite".to_owned(),
+      kind: ActionKind::Write { content, executable: is_executable(executable)? },
+      inputs: Vec::new(),
+      outputs: vec![output],
+      progress_message: None,
+    })
+  }
+
+  fn symlink(&self, args: Args) -> Result<Value, EvalError> {
+    let [output, target, executable] = args
+      .bind("symlink", &["output", "target_file"], &["is_executable"])?
+      .try_into()
+      .unwrap();
+    let output = self.output(&output.unwrap())?;
+    let target = target.unwrap().expect_object::<Artifact>("target_file", "File")?;
+
+    self.register(Action {
+      owner: self.owner.clone(),
+      mnemonic: "Symlink".to_owned(),
+      kind: ActionKind::Symlink { target: target.clone(), executable: is_executable(executable)? },
+      inputs: vec![target],
+      outputs: vec![output],
+      progress_message: None,
+    })
+  }
+
+  fn expand_template(&self, args: Args) -> Result<Value, EvalError> {
+    let [template, output, substitutions, executable] = args.bind(
+      "expand_template",
+      &["template", "output"],
+      &["substitutions", "is_executable"],
+    )?.try_into().unwrap();
+    let template = template.unwrap().expect_object::<Artifact>("template", "File")?;
+    let output = self.output(&output.unwrap())?;
+    let substitutions = match substitutions {
+      Some(substitutions) => substitutions.expect_dict("substitutions")?.iter()
+        .map(|(key, value)| {
+          Ok((
+            key.expect_str("substitutions")?.to_owned(),
+            value.expect_str("substitutions")?.to_owned(),
+          ))
+        })
+        .collect::<Result<Vec<_>, EvalError>>()?,
+      None => Vec::new(),
+    };
+
+    self.register(Action {
+      owner: self.owner.clone(),
+      mnemonic: "TemplateExpand".to_owned(),
+      kind: ActionKind::ExpandTemplate {
+        template: template.clone(),
+        substitutions,
+        executable: is_executable(executable)?,
+      },
+      inputs: vec![template],
+      outputs: vec![output],
+      progress_message: None,
+    })
+  }
+}
+
+/// The `ctx.actions` object.
+pub struct Actions(pub Rc<ActionRegistry>);
+
+const METHODS: [&str; 6] = [
+  "declare_directory", "declare_file", "expand_template", "run", "symlink",
+  "write",
+];
+
+impl Object for Actions {
+  fn type_name(&self) -> String {
+    "actions".to_owned()
+  }
+
+  fn get_attr(&self, name: &str) -> Option<Value> {
+    let registry = self.0.clone();
+    Some(match name {
+      "declare_file" => Value::builtin(name, move |_, args| {
+        registry.declare(args, "declare_file", false)
+      }),
+      "declare_directory" => Value::builtin(name, move |_, args| {
+        registry.declare(args, "declare_directory", true)
+      }),
+      "run" => Value::builtin(name, move |_, args| registry.run(args)),
+      "write" => Value::builtin(name, move |_, args| registry.write(args)),
+      "symlink" => Value::builtin(name, move |_, args| registry.symlink(args)),
+      "expand_template" => Value::builtin(name, move |_, args| registry.expand_template(args)),
+      _ => return None,
+    })
+  }
+
+  fn attr_names(&self) -> Vec<String> {
+    METHODS.iter().map(|name| name.to_string()).collect()
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+/// Returns the files in a list or depset of `File`s.
+pub fn files(value: Option<&Value>, what: &str) -> Result<Vec<Rc<Artifact>>, EvalError> {
+  let items = match value {
+    Some(Value::None) | None => return Ok(Vec::new()),
+    Some(value) => match value.downcast::<Depset>() {
+      Some(depset) => depset.items().to_vec(),
+      None => value.expect_list(what)?,
+    },
+  };
+  items.iter().map(|item| item.expect_object::<Artifact>(what, "File")).collect()
+}
+
+fn is_executable(value: Option<Value>) -> Result<bool, EvalError> {
+  value.map_or(Ok(false), |value| value.expect_bool("is_executable"))
+}
+
+fn dedup(artifacts: Vec<Rc<Artifact>>) -> Vec<Rc<Artifact>> {
+  let mut unique: Vec<Rc<Artifact>> = Vec::new();
+  for artifact in artifacts {
+    if !unique.contains(&artifact) {
+      unique.push(artifact);
+    }
+  }
+  unique
+}
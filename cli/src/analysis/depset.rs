@@ -0,0 +1,103 @@
+use std::any::Any;
+use std::rc::Rc;
+use crate::starlark::error::EvalError;
+use crate::starlark::value::{Object, Value};
+
+/// An immutable set of values which preserves order, used to accumulate
+/// files across the dependency graph. Transitive items come before direct
+/// ones and duplicates keep their first position.
+pub struct Depset {
+  items: Vec<Value>,
+}
+
+impl Depset {
+  pub fn new(direct: Vec<Value>, transitive: &[Rc<Depset>]) -> Depset {
+    let mut items: Vec<Value> = Vec::new();
+    let all = transitive.iter().flat_map(|depset| depset.items.iter()).chain(direct.iter());
+    for item in all {
+      if !items.contains(item) {
+        items.push(item.clone());
+      }
+    }
+    Depset { items }
+  }
+
+  pub fn items(&self) -> &[Value] {
+    &self.items
+  }
+}
+
+impl Object for Depset {
+  fn type_name(&self) -> String {
+    "depset".to_owned()
+  }
+
+  fn get_attr(&self, name: &str) -> Option<Value> {
+    match name {
+      "to_list" => {
+        let items = self.items.clone();
+        Some(Value::builtin("to_list", move |_, args| {
+          args.none("to_list")?;
+          Ok(Value::list(items.clone()))
+        }))
+      },
+      _ => None,
+    }
+  }
+
+  fn attr_names(&self) -> Vec<String> {
+    vec!["to_list".to_owned()]
+  }
+
+  fn repr(&self) -> String {
+    format!("depset({})", Value::list(self.items.clone()).repr())
+  }
+
+  fn freeze(&self) {
+    self.items.iter().for_each(|item| item.freeze());
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+/// The `depset()` builtin.
+pub fn depset_builtin() -> Value {
+  Value::builtin("depset", |_, args| {
+    let [direct, order, transitive] = args.bind("depset", &[], &["direct", "order", "transitive"])?
+      .try_into()
+      .unwrap();
+    if let Some(order) = order {
+      if order.expect_str("order")? != "default" {
+        return Err(EvalError::msg("Only the `default` depset order is supported."));
+      }
+    }
+    let direct = match direct {
+      Some(Value::None) | None => Vec::new(),
+      Some(direct) => direct.expect_list("direct")?,
+    };
+    let transitive = match transitive {
+      Some(Value::None) | None => Vec::new(),
+      Some(transitive) => transitive.expect_list("transitive")?.iter()
+        .map(|depset| depset.expect_object::<Depset>("transitive item", "depset"))
+        .collect::<Result<Vec<_>, _>>()?,
+    };
+
+    Ok(Value::object(Depset::new(direct, &transitive)))
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn depsets_flatten_transitive_items_first() {
+    let inner = Rc::new(Depset::new(vec![Value::Int(1), Value::Int(2)], &[]));
+    let other = Rc::new(Depset::new(vec![Value::Int(2), Value::Int(3)], &[]));
+    let outer = Depset::new(vec![Value::Int(4), Value::Int(1)], &[inner, other]);
+
+    assert_eq!(outer.items(), &[Value::Int(1), Value::Int(2), Value::Int(3), Value::Int(4)]);
+  }
+}
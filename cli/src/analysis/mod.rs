@@ -0,0 +1,13 @@
+pub mod action;
+pub mod analyzer;
+pub mod artifact;
+pub mod config;
+pub mod context;
+pub mod depset;
+pub mod provider;
+pub mod rule;
+pub mod select;
+pub mod strict_deps;
+pub mod toolchain;
+pub mod transition;
+pub mod visibility;
@@ -0,0 +1,205 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use crate::starlark::error::EvalError;
+use crate::starlark::eval::Evaluator;
+use crate::starlark::value::{Args, Object, Value};
+
+/// A provider type created by `provider()`, such as `DefaultInfo`.
+pub struct Provider {
+  /// The file and global name the provider was exported as, which identifies
+  /// it even if the file is evaluated more than once. `None` until exported.
+  pub key: RefCell<Option<(String, String)>>,
+
+  /// The allowed field names, or `None` to allow any.
+  pub fields: Option<Vec<String>>,
+}
+
+impl Provider {
+  /// Returns the builtin `DefaultInfo` provider.
+  pub fn default_info() -> Rc<Provider> {
+    Provider::builtin("DefaultInfo", Some(&["executable", "files"]))
+  }
+
+  /// Returns the builtin `BuildSettingInfo` provider, returned by build
+  /// settings with their value in the current configuration.
+  pub fn build_setting_info() -> Rc<Provider> {
+    Provider::builtin("BuildSettingInfo", Some(&["value"]))
+  }
+
+  /// Returns the builtin `platform_common.ToolchainInfo` provider, which the
+  /// implementation of a toolchain returns with any fields rules using it
+  /// need.
+  pub fn toolchain_info() -> Rc<Provider> {
+    Provider::builtin("ToolchainInfo", None)
+  }
+
+  /// Builtin providers are exported from no file, so never equal a
+  /// user-defined provider.
+  fn builtin(name: &str, fields: Option<&[&str]>) -> Rc<Provider> {
+    Rc::new(Provider {
+      key: RefCell::new(Some(("".to_owned(), name.to_owned()))),
+      fields: fields.map(|fields| fields.iter().map(|field| field.to_string()).collect()),
+    })
+  }
+
+  /// The name the provider was exported as.
+  pub fn name(&self) -> String {
+    match &*self.key.borrow() {
+      Some((_, name)) => name.clone(),
+      None => "<unexported provider>".to_owned(),
+    }
+  }
+
+  pub fn is_default_info(&self) -> bool {
+    self.key.borrow().as_ref().is_some_and(|(file, name)| file.is_empty() && name == "DefaultInfo")
+  }
+}
+
+impl Object for Provider {
+  fn type_name(&self) -> String {
+    "Provider".to_owned()
+  }
+
+  fn call(self: Rc<Self>, _eval: &mut Evaluator, args: Args) -> Result<Value, EvalError> {
+    if !args.positional.is_empty() {
+      return Err(EvalError::msg(format!("{}() only accepts keyword arguments.", self.name())));
+    }
+    if let Some(fields) = &self.fields {
+      if let Some((name, _)) = args.named.iter().find(|(name, _)| !fields.contains(name)) {
+        return Err(EvalError::msg(format!(
+          "{}() got an unexpected field `{}`.",
+          self.name(),
+          name,
+        )));
+      }
+    }
+
+    Ok(Value::object(Info { provider: self, fields: args.named.into_iter().collect() }))
+  }
+
+  fn equals(&self, other: &dyn Object) -> bool {
+    let other: &dyn Any = other;
+    match other.downcast_ref::<Provider>() {
+      Some(other) => std::ptr::eq(self, other) || (
+        self.key.borrow().is_some() && *self.key.borrow() == *other.key.borrow()
+      ),
+      None => false,
+    }
+  }
+
+  fn is_hashable(&self) -> bool {
+    true
+  }
+
+  fn repr(&self) -> String {
+    format!("<provider {}>", self.name())
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+/// An instance of a provider returned by a rule implementation.
+pub struct Info {
+  pub provider: Rc<Provider>,
+  pub fields: BTreeMap<String, Value>,
+}
+
+impl Object for Info {
+  fn type_name(&self) -> String {
+    self.provider.name()
+  }
+
+  fn get_attr(&self, name: &str) -> Option<Value> {
+    self.fields.get(name).cloned()
+  }
+
+  fn attr_names(&self) -> Vec<String> {
+    self.fields.keys().cloned().collect()
+  }
+
+  fn repr(&self) -> String {
+    format!(
+      "{}({})",
+      self.provider.name(),
+      self.fields.iter()
+        .map(|(name, value)| format!("{} = {}", name, value.repr()))
+        .collect::<Vec<_>>()
+        .join(", "),
+    )
+  }
+
+  fn freeze(&self) {
+    self.fields.values().for_each(|value| value.freeze());
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+/// The `provider()` builtin of .bzl files.
+pub fn provider_builtin() -> Value {
+  Value::builtin("provider", |_, args| {
+    let [_doc, fields] = args.bind("provider", &[], &["doc", "fields"])?.try_into().unwrap();
+    let fields = match fields {
+      Some(Value::None) | None => None,
+      Some(fields @ Value::Dict(_)) => Some(fields.expect_dict("fields")?.iter()
+        .map(|(name, _)| name.expect_str("field name").map(|name| name.to_owned()))
+        .collect::<Result<Vec<_>, _>>()?),
+      Some(fields) => Some(fields.expect_str_list("fields")?),
+    };
+
+    Ok(Value::object(Provider { key: RefCell::new(None), fields }))
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use std::collections::HashMap;
+  use crate::starlark::eval::ModuleEnv;
+  use crate::starlark::parser::parse;
+  use super::*;
+
+  fn eval(source: &str) -> Result<Rc<ModuleEnv>, EvalError> {
+    let predeclared = HashMap::from([
+      ("provider".to_owned(), provider_builtin()),
+      ("DefaultInfo".to_owned(), Value::Object(Provider::default_info())),
+    ]);
+    let env = ModuleEnv::new("test.bzl", Rc::new(predeclared));
+    Evaluator::new().eval_module(&parse("test.bzl", source)?, &env)?;
+    Ok(env)
+  }
+
+  #[test]
+  fn providers_create_infos() {
+    let env = eval(
+      "FooInfo = provider(fields = [\"a\"])\ninfo = FooInfo(a = 1)\na = info.a",
+    ).unwrap();
+    assert_eq!(env.get("a"), Some(Value::Int(1)));
+    assert_eq!(env.get("info").unwrap().type_name(), "<unexported provider>");
+
+    let err = eval("FooInfo = provider(fields = [\"a\"])\nFooInfo(b = 1)").err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      "test.bzl:2:8: <unexported provider>() got an unexpected field `b`.",
+    );
+  }
+
+  #[test]
+  fn providers_are_identified_by_export_key() {
+    let key = || RefCell::new(Some(("a.bzl".to_owned(), "A".to_owned())));
+    let a = Provider { key: key(), fields: None };
+    let b = Provider { key: key(), fields: None };
+    let unexported = Provider { key: RefCell::new(None), fields: None };
+    let other_unexported = Provider { key: RefCell::new(None), fields: None };
+
+    assert!(a.equals(&b));
+    assert!(!a.equals(&unexported));
+    assert!(!unexported.equals(&other_unexported));
+    assert!(Provider::default_info().equals(Provider::default_info().as_ref()));
+  }
+}
@@ -0,0 +1,349 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use crate::label::Label;
+use crate::package::PackageContext;
+use crate::starlark::error::EvalError;
+use crate::starlark::eval::Evaluator;
+use crate::starlark::value::{Args, Object, Struct, Value};
+use super::provider::Provider;
+use super::transition::Cfg;
+
+/// A rule created by `rule()` in a .bzl file.
+pub struct RuleDef {
+  /// The global name the rule was exported as, `None` until exported.
+  pub name: RefCell<Option<String>>,
+
+  /// The function called with `ctx` to analyze each target of the rule.
+  pub implementation: Value,
+
+  pub attrs: BTreeMap<String, Rc<AttrSpec>>,
+
+  /// Whether targets of the rule produce an executable.
+  pub executable: bool,
+
+  /// The incoming transition applied to every target of the rule.
+  pub cfg: Cfg,
+
+  /// The toolchain types resolved for every target of the rule.
+  pub toolchains: Vec<Label>,
+
+  /// The documentation from `rule(doc = ...)`, empty without any.
+  pub doc: String,
+}
+
+impl RuleDef {
+  pub fn name(&self) -> String {
+    self.name.borrow().clone().unwrap_or_else(|| "<unexported rule>".to_owned())
+  }
+}
+
+impl Object for RuleDef {
+  fn type_name(&self) -> String {
+    "rule".to_owned()
+  }
+
+  fn call(self: Rc<Self>, eval: &mut Evaluator, args: Args) -> Result<Value, EvalError> {
+    let context = eval.context::<PackageContext>().ok_or_else(|| {
+      EvalError::msg(format!("Rule `{}` can only be called from a BUILD file.", self.name()))
+    })?;
+    let location = eval.location().cloned();
+    context.add_target(&self, args, location)?;
+
+    Ok(Value::None)
+  }
+
+  fn repr(&self) -> String {
+    format!("<rule {}>", self.name())
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+/// The type of an attribute.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AttrKind {
+  String,
+  StringList,
+  StringDict,
+  Int,
+  Bool,
+  Label,
+  LabelList,
+
+  /// A file generated by the rule, which is predeclared before analysis.
+  Output,
+  OutputList,
+}
+
+impl AttrKind {
+  pub fn name(&self) -> &'static str {
+    match self {
+      AttrKind::String => "string",
+      AttrKind::StringList => "string_list",
+      AttrKind::StringDict => "string_dict",
+      AttrKind::Int => "int",
+      AttrKind::Bool => "bool",
+      AttrKind::Label => "label",
+      AttrKind::LabelList => "label_list",
+      AttrKind::Output => "output",
+      AttrKind::OutputList => "output_list",
+    }
+  }
+}
+
+/// Which source files may be passed to a label attribute.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AllowFiles {
+  No,
+  Any,
+
+  /// Only files ending in one of the given extensions, such as `.ts`.
+  Extensions(Vec<String>),
+}
+
+impl AllowFiles {
+  pub fn allows(&self, path: &str) -> bool {
+    match self {
+      AllowFiles::No => false,
+      AllowFiles::Any => true,
+      AllowFiles::Extensions(extensions) => {
+        extensions.iter().any(|ext| path.ends_with(ext.as_str()))
+      },
+    }
+  }
+}
+
+/// The schema of one attribute, created by the `attr` module.
+pub struct AttrSpec {
+  pub kind: AttrKind,
+  pub default: Value,
+  pub mandatory: bool,
+  pub allow_files: AllowFiles,
+
+  /// Whether a label attribute must refer to exactly one file, exposed as
+  /// `ctx.file.<name>`.
+  pub single_file: bool,
+
+  /// Whether a label attribute must refer to an executable, exposed as
+  /// `ctx.executable.<name>`.
+  pub executable: bool,
+
+  /// Providers every dependency in a label attribute must return.
+  pub providers: Vec<Rc<Provider>>,
+
+  /// The allowed values of a string attribute, if restricted.
+  pub values: Vec<String>,
+
+  /// The configuration dependencies of a label attribute are built in.
+  pub cfg: Cfg,
+
+  /// The documentation from `doc = ...`, empty without any.
+  pub doc: String,
+}
+
+impl Object for AttrSpec {
+  fn type_name(&self) -> String {
+    "Attribute".to_owned()
+  }
+
+  fn repr(&self) -> String {
+    format!("<attr.{}>", self.kind.name())
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+/// The `rule()` builtin of .bzl files.
+pub fn rule_builtin() -> Value {
+  Value::builtin("rule", |_, args| {
+    let [implementation, attrs, executable, cfg, toolchains, doc] = args.bind(
+      "rule",
+      &["implementation"],
+      &["attrs", "executable", "cfg", "toolchains", "doc"],
+    )?.try_into().unwrap();
+
+    let implementation = implementation.unwrap();
+    if !matches!(implementation, Value::Function(_)) {
+      return Err(implementation.type_error("implementation", "function"));
+    }
+
+    let mut specs = BTreeMap::new();
+    for (name, spec) in attrs.map_or(Ok(Vec::new()), |attrs| attrs.expect_dict("attrs"))? {
+      let name = name.expect_str("attribute name")?.to_owned();
+      if name == "name" || name == "visibility" {
+        return Err(EvalError::msg(format!("Attribute `{}` is reserved.", name)));
+      }
+      specs.insert(name, spec.expect_object::<AttrSpec>("attribute", "Attribute")?);
+    }
+
+    Ok(Value::object(RuleDef {
+      name: RefCell::new(None),
+      implementation,
+      attrs: specs,
+      executable: executable.map_or(Ok(false), |executable| executable.expect_bool("executable"))?,
+      cfg: Cfg::parse(cfg, false)?,
+      toolchains: toolchains.map_or(Ok(Vec::new()), |toolchains| toolchains.expect_str_list("toolchains"))?.iter()
+        .map(|label| Label::parse(label, "").map_err(|err| EvalError::msg(err.0)))
+        .collect::<Result<_, _>>()?,
+      doc: doc.map_or(Ok(String::new()), |doc| doc.expect_str("doc").map(str::to_owned))?,
+    }))
+  })
+}
+
+/// The `attr` module of .bzl files, whose functions create attribute schemas.
+pub fn attr_module() -> Value {
+  let functions = [
+    (AttrKind::String, Value::str("")),
+    (AttrKind::StringList, Value::list(Vec::new())),
+    (AttrKind::StringDict, Value::Dict(Default::default())),
+    (AttrKind::Int, Value::Int(0)),
+    (AttrKind::Bool, Value::Bool(false)),
+    (AttrKind::Label, Value::None),
+    (AttrKind::LabelList, Value::list(Vec::new())),
+    (AttrKind::Output, Value::None),
+    (AttrKind::OutputList, Value::list(Vec::new())),
+  ];
+
+  let fields = functions.into_iter()
+    .map(|(kind, default)| {
+      // Defaults are shared between every target, so must never be mutated.
+      default.freeze();
+      let function = Value::builtin(kind.name(), move |_, args| attr(kind, default.clone(), args));
+      (kind.name().to_owned(), function)
+    })
+    .collect();
+  Value::object(Struct { fields })
+}
+
+fn attr(kind: AttrKind, default: Value, args: Args) -> Result<Value, EvalError> {
+  let is_label = matches!(kind, AttrKind::Label | AttrKind::LabelList);
+  let mut optional = vec!["mandatory", "doc"];
+  if !matches!(kind, AttrKind::Output | AttrKind::OutputList) {
+    optional.push("default");
+  }
+  if is_label {
+    optional.extend(["allow_files", "providers", "cfg"]);
+  }
+  if kind == AttrKind::Label {
+    optional.extend(["allow_single_file", "executable"]);
+  }
+  if kind == AttrKind::String {
+    optional.push("values");
+  }
+
+  let bound = args.bind(kind.name(), &[], &optional)?;
+  let get = |name: &str| {
+    optional.iter().position(|param| *param == name).and_then(|index| bound[index].clone())
+  };
+
+  let mut allow_files = match get("allow_files") {
+    Some(value) => allow_files(&value)?,
+    None => AllowFiles::No,
+  };
+  let single_file = match get("allow_single_file") {
+    Some(value) => {
+      allow_files = self::allow_files(&value)?;
+      allow_files != AllowFiles::No
+    },
+    None => false,
+  };
+  let providers = match get("providers") {
+    Some(providers) => providers.expect_list("providers")?.iter()
+      .map(|provider| provider.expect_object::<Provider>("provider", "Provider"))
+      .collect::<Result<Vec<_>, _>>()?,
+    None => Vec::new(),
+  };
+
+  let default = get("default").unwrap_or(default);
+  default.freeze();
+  Ok(Value::object(AttrSpec {
+    kind,
+    default,
+    mandatory: get("mandatory").map_or(Ok(false), |value| value.expect_bool("mandatory"))?,
+    allow_files,
+    single_file,
+    executable: get("executable").map_or(Ok(false), |value| value.expect_bool("executable"))?,
+    providers,
+    values: get("values").map_or(Ok(Vec::new()), |value| value.expect_str_list("values"))?,
+    cfg: Cfg::parse(get("cfg"), true)?,
+    doc: get("doc").map_or(Ok(String::new()), |doc| doc.expect_str("doc").map(str::to_owned))?,
+  }))
+}
+
+fn allow_files(value: &Value) -> Result<AllowFiles, EvalError> {
+  match value {
+    Value::Bool(true) => Ok(AllowFiles::Any),
+    Value::Bool(false) | Value::None => Ok(AllowFiles::No),
+    value => Ok(AllowFiles::Extensions(value.expect_str_list("allow_files")?)),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::collections::HashMap;
+  use crate::starlark::eval::ModuleEnv;
+  use crate::starlark::parser::parse;
+  use super::*;
+
+  fn eval(source: &str) -> Result<Rc<ModuleEnv>, EvalError> {
+    let predeclared = HashMap::from([
+      ("rule".to_owned(), rule_builtin()),
+      ("attr".to_owned(), attr_module()),
+    ]);
+    let env = ModuleEnv::new("test.bzl", Rc::new(predeclared));
+    Evaluator::new().eval_module(&parse("test.bzl", source)?, &env)?;
+    Ok(env)
+  }
+
+  #[test]
+  fn rule_creates_rule_with_attribute_schemas() {
+    let env = eval(r#"
+def _impl(ctx):
+    pass
+
+my_rule = rule(
+    implementation = _impl,
+    attrs = {
+        "srcs": attr.label_list(allow_files = [".ts"], mandatory = True),
+        "tool": attr.label(allow_single_file = True, executable = True),
+        "mode": attr.string(default = "fast", values = ["fast", "slow"]),
+        "out": attr.output(),
+    },
+)
+"#).unwrap();
+
+    let rule = env.get("my_rule").unwrap().downcast::<RuleDef>().unwrap();
+    assert_eq!(rule.attrs.keys().collect::<Vec<_>>(), ["mode", "out", "srcs", "tool"]);
+
+    let srcs = &rule.attrs["srcs"];
+    assert_eq!(srcs.kind, AttrKind::LabelList);
+    assert!(srcs.mandatory);
+    assert_eq!(srcs.allow_files, AllowFiles::Extensions(vec![".ts".to_owned()]));
+
+    let tool = &rule.attrs["tool"];
+    assert!(tool.single_file && tool.executable);
+    assert_eq!(tool.allow_files, AllowFiles::Any);
+
+    assert_eq!(rule.attrs["mode"].default, Value::str("fast"));
+    assert_eq!(rule.attrs["out"].kind, AttrKind::Output);
+  }
+
+  #[test]
+  fn rule_errors_on_invalid_attributes() {
+    assert_eq!(
+      eval("attr.output(default = \"x\")").err().unwrap().to_string(),
+      "test.bzl:1:12: output() got an unexpected keyword argument `default`.",
+    );
+    assert_eq!(
+      eval("def f(ctx):\n    pass\nrule(f, attrs = {\"name\": attr.string()})")
+        .err().unwrap().to_string(),
+      "test.bzl:3:5: Attribute `name` is reserved.",
+    );
+  }
+}
@@ -0,0 +1,98 @@
+use std::any::Any;
+use std::rc::Rc;
+use crate::starlark::ast::BinaryOp;
+use crate::starlark::error::EvalError;
+use crate::starlark::value::{Object, Value};
+
+/// The condition of a `select()` branch taken when no other branch matches.
+pub const DEFAULT_CONDITION: &str = "//conditions:default";
+
+/// A configurable attribute value from `select()`, possibly concatenated with
+/// plain values and other selects, which is resolved during analysis.
+pub struct Select {
+  pub parts: Vec<SelectPart>,
+}
+
+#[derive(Clone)]
+pub enum SelectPart {
+  Value(Value),
+  Branches {
+    /// The condition labels, as written, with their values.
+    branches: Vec<(String, Value)>,
+
+    /// A custom error for when no condition matches.
+    no_match_error: Option<String>,
+  },
+}
+
+impl Object for Select {
+  fn type_name(&self) -> String {
+    "select".to_owned()
+  }
+
+  fn binary_op(&self, op: BinaryOp, other: &Value, left: bool) -> Option<Result<Value, EvalError>> {
+    if op != BinaryOp::Add {
+      return None;
+    }
+    let other_parts = match other.downcast::<Select>() {
+      Some(select) => select.parts.clone(),
+      None => vec![SelectPart::Value(other.clone())],
+    };
+    let own_parts = self.parts.iter().cloned();
+    let parts = if left {
+      own_parts.chain(other_parts).collect()
+    } else {
+      other_parts.into_iter().chain(own_parts).collect()
+    };
+    Some(Ok(Value::object(Select { parts })))
+  }
+
+  fn repr(&self) -> String {
+    self.parts.iter()
+      .map(|part| match part {
+        SelectPart::Value(value) => value.repr(),
+        SelectPart::Branches { branches, .. } => {
+          let branches: Vec<_> = branches.iter()
+            .map(|(condition, value)| format!("{}: {}", Value::str(condition).repr(), value.repr()))
+            .collect();
+          format!("select({{{}}})", branches.join(", "))
+        },
+      })
+      .collect::<Vec<_>>()
+      .join(" + ")
+  }
+
+  fn freeze(&self) {
+    for part in &self.parts {
+      match part {
+        SelectPart::Value(value) => value.freeze(),
+        SelectPart::Branches { branches, .. } => branches.iter().for_each(|(_, value)| value.freeze()),
+      }
+    }
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+/// Returns the `select()` builtin.
+pub fn select_builtin() -> Value {
+  Value::builtin("select", |_, args| {
+    let [branches, no_match_error] = args.bind("select", &["x"], &["no_match_error"])?
+      .try_into()
+      .unwrap();
+    let branches = branches.unwrap().expect_dict("select")?.iter()
+      .map(|(condition, value)| Ok((condition.expect_str("select() condition")?.to_owned(), value.clone())))
+      .collect::<Result<Vec<_>, EvalError>>()?;
+    if branches.is_empty() {
+      return Err(EvalError::msg("select() requires at least one condition."));
+    }
+    let no_match_error = match no_match_error {
+      Some(Value::None) | None => None,
+      Some(message) => Some(message.expect_str("no_match_error")?.to_owned()),
+    };
+
+    Ok(Value::object(Select { parts: vec![SelectPart::Branches { branches, no_match_error }] }))
+  })
+}
@@ -0,0 +1,542 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::rc::Rc;
+use serde_json::Value as Json;
+use crate::execution::read_source;
+use crate::host::host::Host;
+use crate::label::{package_path, Label};
+use crate::repository::{Repositories, BUILTIN_REPO};
+use crate::starlark::value::{Object, Value};
+use super::analyzer::ConfiguredTarget;
+use super::artifact::Artifact;
+use super::provider::Info;
+
+/// Extensions of the files a specifier may refer to without naming them,
+/// longest first so `.d.ts` is stripped before `.ts`.
+const MODULE_EXTENSIONS: [&str; 10] = [
+  ".d.ts", ".tsx", ".ts", ".mts", ".cts", ".jsx", ".js", ".mjs", ".cjs", ".json",
+];
+
+/// Modules built into Node.js, which no dependency needs to provide.
+const NODE_BUILTINS: [&str; 31] = [
+  "assert", "async_hooks", "buffer", "child_process", "cluster", "console",
+  "crypto", "dgram", "dns", "events", "fs", "http", "http2", "https",
+  "inspector", "module", "net", "os", "path", "perf_hooks", "process",
+  "querystring", "readline", "stream", "string_decoder", "timers", "tty", "url",
+  "util", "worker_threads", "zlib",
+];
+
+/// A module specifier imported by a source file.
+#[derive(Debug, PartialEq)]
+pub struct Import {
+  pub specifier: String,
+  pub line: usize,
+}
+
+/// Returns the specifiers of the `import` declarations, `export ... from`
+/// declarations, dynamic `import()`s and `require()` calls of a JavaScript or
+/// TypeScript source. Specifiers in comments and other strings are skipped.
+pub fn scan_imports(source: &str) -> Vec<Import> {
+  let chars: Vec<char> = source.chars().collect();
+  let mut imports = Vec::new();
+  let mut line = 1;
+
+  // The last two tokens, which decide whether a string is a specifier.
+  let mut previous = (String::new(), String::new());
+  let push = |token: String, previous: &mut (String, String)| {
+    previous.0 = std::mem::replace(&mut previous.1, token);
+  };
+
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    match c {
+      '\n' => line += 1,
+      c if c.is_whitespace() => {},
+      '/' if chars.get(i + 1) == Some(&'/') => {
+        while i < chars.len() && chars[i] != '\n' {
+          i += 1;
+        }
+        continue;
+      },
+      '/' if chars.get(i + 1) == Some(&'*') => {
+        i += 2;
+        while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+          if chars[i] == '\n' {
+            line += 1;
+          }
+          i += 1;
+        }
+        i += 2;
+        continue;
+      },
+      '\'' | '"' | '`' => {
+        let start = line;
+        let mut value = String::new();
+        i += 1;
+        while i < chars.len() && chars[i] != c {
+          if chars[i] == '\\' {
+            i += 1;
+          }
+          if let Some(&next) = chars.get(i) {
+            if next == '\n' {
+              line += 1;
+            }
+            value.push(next);
+          }
+          i += 1;
+        }
+        let is_specifier = c != '`' && matches!(
+          (previous.0.as_str(), previous.1.as_str()),
+          (_, "from") | (_, "import") | ("require", "(") | ("import", "("),
+        );
+        if is_specifier {
+          imports.push(Import { specifier: value, line: start });
+        }
+        push("<string>".to_owned(), &mut previous);
+      },
+      c if c.is_alphanumeric() || c == '_' || c == '$' => {
+        let start = i;
+        while i < chars.len() &&
+            (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+          i += 1;
+        }
+        push(chars[start..i].iter().collect(), &mut previous);
+        continue;
+      },
+      c => push(c.to_string(), &mut previous),
+    }
+    i += 1;
+  }
+
+  imports
+}
+
+/// Fails unless every module imported by the sources of libraries whose
+/// `JsInfo` enables `strict_deps` is one of the library's own sources or
+/// provided by one of its direct dependencies. Libraries that only build
+/// because a transitive dependency provides a module are reported with the
+/// labels to add to their `deps`. Generated sources are not checked since
+/// they do not exist until execution.
+pub fn check_strict_deps(repositories: &Repositories,
+    targets: &[Rc<ConfiguredTarget>]) -> Result<(), Box<dyn Error>> {
+  let ts_paths = TsPaths::read(&**repositories.main())?;
+  let mut checked = HashSet::new();
+  let mut errors = Vec::new();
+  for target in targets {
+    let Some(info) = js_info(target) else {
+      continue;
+    };
+    if !matches!(info.fields.get("strict_deps"), Some(Value::Bool(true))) ||
+        !checked.insert(target.label.clone()) {
+      continue;
+    }
+
+    let own: HashSet<_> = sources(&info).iter()
+      .flat_map(|file| module_keys(&file.short_path))
+      .collect();
+    let direct = Provided::of(&deps(&info));
+    let transitive = Provided::transitive(&deps(&info));
+
+    let mut violations = Vec::new();
+    let mut missing = BTreeSet::new();
+    for src in sources(&info).iter().filter(|file| file.is_source) {
+      for import in scan_imports(&read_source(repositories, &src.path)?) {
+        let exists = |key: &str| own.contains(key) || transitive.modules.contains_key(key);
+        let provider = match ts_paths.classify(&src.short_path, &import.specifier, exists) {
+          Specifier::Builtin | Specifier::Asset => continue,
+          Specifier::Module(key) if own.contains(&key) => continue,
+          Specifier::Module(key) if direct.modules.contains_key(&key) => continue,
+          Specifier::Module(key) => transitive.modules.get(&key),
+          Specifier::Package(name) if direct.packages.contains_key(&name) => continue,
+          Specifier::Package(name) => transitive.packages.get(&name),
+        };
+        let location = format!("{}:{}", src.short_path, import.line);
+        violations.push(match provider {
+          Some(label) => {
+            missing.insert(format!("\"{}\"", label));
+            format!("  {} imports \"{}\" from {}", location, import.specifier, label)
+          },
+          None => format!(
+            "  {} imports \"{}\", which no dependency provides",
+            location,
+            import.specifier,
+          ),
+        });
+      }
+    }
+
+    if !violations.is_empty() {
+      let mut error = format!(
+        "{} imports modules its direct dependencies do not provide:\n{}",
+        target.label,
+        violations.join("\n"),
+      );
+      if !missing.is_empty() {
+        let missing: Vec<_> = missing.into_iter().collect();
+        error.push_str(&format!(
+          "\nAdd to the `deps` of {}: {}",
+          target.label,
+          missing.join(", "),
+        ));
+      }
+      errors.push(error);
+    }
+  }
+
+  if errors.is_empty() {
+    Ok(())
+  } else {
+    Err(Box::new(StrictDepsError(errors.join("\n"))))
+  }
+}
+
+/// The modules and npm packages a set of targets provides, by the label of
+/// the nearest target providing each.
+#[derive(Default)]
+struct Provided {
+  modules: HashMap<String, Label>,
+  packages: HashMap<String, Label>,
+}
+
+impl Provided {
+  fn of(targets: &[Rc<ConfiguredTarget>]) -> Provided {
+    let mut provided = Provided::default();
+    for target in targets {
+      provided.add(target);
+    }
+    provided
+  }
+
+  /// Returns what the targets and their transitive dependencies provide,
+  /// preferring targets closer to them.
+  fn transitive(targets: &[Rc<ConfiguredTarget>]) -> Provided {
+    let mut provided = Provided::default();
+    let mut visited = HashSet::new();
+    let mut queue: VecDeque<_> = targets.iter().cloned().collect();
+    while let Some(target) = queue.pop_front() {
+      if !visited.insert(target.label.clone()) {
+        continue;
+      }
+      provided.add(&target);
+      if let Some(info) = js_info(&target) {
+        queue.extend(deps(&info));
+      }
+    }
+    provided
+  }
+
+  fn add(&mut self, target: &ConfiguredTarget) {
+    let Some(info) = js_info(target) else {
+      return;
+    };
+    for file in sources(&info) {
+      for key in module_keys(&file.short_path) {
+        self.modules.entry(key).or_insert_with(|| target.label.clone());
+      }
+    }
+    if let Some(Value::Str(name)) = info.fields.get("package_name") {
+      self.packages.entry(name.to_string()).or_insert_with(|| target.label.clone());
+    }
+  }
+}
+
+/// What an import specifier refers to.
+#[derive(Debug, PartialEq)]
+pub enum Specifier {
+  /// A module built into Node.js.
+  Builtin,
+
+  /// A relative import of a file which is not a module, such as a stylesheet.
+  Asset,
+
+  /// A relative import, by the module key of the file it resolves to.
+  Module(String),
+
+  /// An npm package, by its name.
+  Package(String),
+}
+
+/// Classifies a specifier imported by the file with the given short path.
+pub fn classify(importer: &str, specifier: &str) -> Specifier {
+  if specifier.starts_with("./") || specifier.starts_with("../") {
+    let mut segments: Vec<&str> = importer.split('/').collect();
+    segments.pop();
+    for segment in specifier.split('/') {
+      match segment {
+        "." | "" => {},
+        ".." => {
+          segments.pop();
+        },
+        segment => segments.push(segment),
+      }
+    }
+    let path = segments.join("/");
+    let name = segments.last().copied().unwrap_or_default();
+    return match MODULE_EXTENSIONS.iter().find_map(|extension| path.strip_suffix(extension)) {
+      Some(key) => Specifier::Module(key.to_owned()),
+      None if name.contains('.') => Specifier::Asset,
+      None => Specifier::Module(path),
+    };
+  }
+
+  if specifier.starts_with("node:") {
+    return Specifier::Builtin;
+  }
+  let mut segments = specifier.split('/');
+  let first = segments.next().unwrap_or_default();
+  if NODE_BUILTINS.contains(&first) {
+    return Specifier::Builtin;
+  }
+  match (first.starts_with('@'), segments.next()) {
+    (true, Some(name)) => Specifier::Package(format!("{}/{}", first, name)),
+    _ => Specifier::Package(first.to_owned()),
+  }
+}
+
+/// Returns the keys a file can be imported by: its short path without its
+/// extension, and the path of its directory for index files.
+pub fn module_keys(short_path: &str) -> Vec<String> {
+  let key = MODULE_EXTENSIONS.iter()
+    .find_map(|extension| short_path.strip_suffix(extension));
+  let Some(key) = key else {
+    return Vec::new();
+  };
+  match key.strip_suffix("/index") {
+    Some(dir) => vec![key.to_owned(), dir.to_owned()],
+    None => vec![key.to_owned()],
+  }
+}
+
+/// The `compilerOptions.paths` mappings of a `tsconfig.json`, which map bare
+/// specifiers to modules of the workspace.
+#[derive(Default)]
+pub struct TsPaths {
+  /// The directory the mappings are relative to, from `baseUrl`.
+  base: String,
+
+  /// The patterns of specifiers, with at most one `*`, and their targets.
+  paths: Vec<(String, Vec<String>)>,
+}
+
+impl TsPaths {
+  /// Reads the mappings of the `tsconfig.json` at the root of a repository,
+  /// if it has one.
+  pub fn read(host: &dyn Host) -> Result<TsPaths, Box<dyn Error>> {
+    let Ok(source) = host.read_to_string(Path::new("tsconfig.json")) else {
+      return Ok(TsPaths::default());
+    };
+    let config: Json = serde_json::from_str(&strip_json_comments(&source))
+      .map_err(|err| {
+        StrictDepsError(format!("Failed to parse tsconfig.json: {}", err))
+      })?;
+    let options = &config["compilerOptions"];
+    let base = normalize(options["baseUrl"].as_str().unwrap_or("."));
+    let paths = options["paths"].as_object().into_iter().flatten()
+      .map(|(pattern, targets)| {
+        let targets = targets.as_array().into_iter().flatten()
+          .filter_map(Json::as_str)
+          .map(str::to_owned)
+          .collect();
+        (pattern.clone(), targets)
+      })
+      .collect();
+    Ok(TsPaths { base, paths })
+  }
+
+  /// Classifies a specifier imported by the file with the given short path
+  /// like `classify`, except that a bare specifier mapped to a module for
+  /// which `exists` returns true refers to that module rather than a package.
+  pub fn classify(&self, importer: &str, specifier: &str,
+      exists: impl Fn(&str) -> bool) -> Specifier {
+    match classify(importer, specifier) {
+      Specifier::Package(name) => self.resolve(specifier).into_iter()
+        .find(|key| exists(key))
+        .map_or(Specifier::Package(name), Specifier::Module),
+      classified => classified,
+    }
+  }
+
+  /// Returns the module keys a specifier is mapped to, in order of preference.
+  fn resolve(&self, specifier: &str) -> Vec<String> {
+    let mut keys = Vec::new();
+    for (pattern, targets) in &self.paths {
+      let matched = match pattern.split_once('*') {
+        Some((prefix, suffix)) => specifier.strip_prefix(prefix)
+          .and_then(|rest| rest.strip_suffix(suffix)),
+        None if pattern == specifier => Some(""),
+        None => None,
+      };
+      if let Some(matched) = matched {
+        let paths = targets.iter().map(|target| target.replacen('*', matched, 1));
+        keys.extend(paths.map(|path| self.key(&path)));
+      }
+    }
+    keys
+  }
+
+  /// Returns the module key of a path relative to `baseUrl`.
+  fn key(&self, path: &str) -> String {
+    let path = normalize(&format!("{}/{}", self.base, path));
+    module_keys(&path).into_iter().next().unwrap_or(path)
+  }
+}
+
+/// Removes `.` segments and resolves `..` segments of a relative path.
+fn normalize(path: &str) -> String {
+  let mut segments = Vec::new();
+  for segment in path.split('/') {
+    match segment {
+      "" | "." => {},
+      ".." => {
+        segments.pop();
+      },
+      segment => segments.push(segment),
+    }
+  }
+  segments.join("/")
+}
+
+/// Removes the `//` and `/* */` comments `tsconfig.json` files allow.
+fn strip_json_comments(source: &str) -> String {
+  let mut stripped = String::new();
+  let mut chars = source.chars().peekable();
+  let mut in_string = false;
+  while let Some(c) = chars.next() {
+    match c {
+      '"' => in_string = !in_string,
+      '\\' if in_string => {
+        stripped.push(c);
+        stripped.extend(chars.next());
+        continue;
+      },
+      '/' if !in_string && chars.peek() == Some(&'/') => {
+        while chars.next_if(|&c| c != '\n').is_some() {}
+        continue;
+      },
+      '/' if !in_string && chars.peek() == Some(&'*') => {
+        chars.next();
+        let mut previous = ' ';
+        for c in chars.by_ref() {
+          if previous == '*' && c == '/' {
+            break;
+          }
+          previous = c;
+        }
+        continue;
+      },
+      _ => {},
+    }
+    stripped.push(c);
+  }
+  stripped
+}
+
+/// Returns the `JsInfo` of `@razel//js:defs.bzl` a target returned, if any.
+fn js_info(target: &ConfiguredTarget) -> Option<Rc<Info>> {
+  let file = format!("{}/defs.bzl", package_path(BUILTIN_REPO, "js"));
+  target.providers.iter().find(|info| {
+    info.provider.key.borrow().as_ref()
+      .is_some_and(|(key_file, name)| *key_file == file && name == "JsInfo")
+  }).cloned()
+}
+
+fn sources(info: &Info) -> Vec<Rc<Artifact>> {
+  list_field(info, "direct_sources")
+}
+
+fn deps(info: &Info) -> Vec<Rc<ConfiguredTarget>> {
+  list_field(info, "deps")
+}
+
+fn list_field<T: Object>(info: &Info, field: &str) -> Vec<Rc<T>> {
+  info.fields.get(field)
+    .and_then(|value| value.expect_list(field).ok())
+    .map(|items| items.iter().filter_map(|item| item.downcast::<T>()).collect())
+    .unwrap_or_default()
+}
+
+/// An error thrown when a library imports a module that none of its direct
+/// dependencies provide.
+#[derive(Debug)]
+pub struct StrictDepsError(pub String);
+
+impl Display for StrictDepsError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for StrictDepsError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn scan_imports_finds_specifiers_outside_comments_and_strings() {
+    let source = r#"import { a } from "./a";
+import type { B } from '../b/b.js';
+import "side-effect";
+export * from "@scope/pkg/sub";
+// import { c } from "./commented";
+/* require("./block
+comment") */
+const d = await import("./d");
+const e = require('e');
+const text = "from './not-an-import'";
+"#;
+    assert_eq!(scan_imports(source), [
+      Import { specifier: "./a".to_owned(), line: 1 },
+      Import { specifier: "../b/b.js".to_owned(), line: 2 },
+      Import { specifier: "side-effect".to_owned(), line: 3 },
+      Import { specifier: "@scope/pkg/sub".to_owned(), line: 4 },
+      Import { specifier: "./d".to_owned(), line: 8 },
+      Import { specifier: "e".to_owned(), line: 9 },
+    ]);
+  }
+
+  #[test]
+  fn classify_resolves_specifiers() {
+    assert_eq!(
+      classify("app/ui/view.tsx", "../lib/util"),
+      Specifier::Module("app/lib/util".to_owned()),
+    );
+    assert_eq!(classify("app/main.ts", "./util.js"), Specifier::Module("app/util".to_owned()));
+    assert_eq!(classify("app/main.ts", "./style.css"), Specifier::Asset);
+    assert_eq!(
+      classify("app/main.ts", "@scope/pkg/sub"),
+      Specifier::Package("@scope/pkg".to_owned()),
+    );
+    assert_eq!(classify("app/main.ts", "lodash/fp"), Specifier::Package("lodash".to_owned()));
+    assert_eq!(classify("app/main.ts", "node:fs/promises"), Specifier::Builtin);
+    assert_eq!(classify("app/main.ts", "path"), Specifier::Builtin);
+    assert_eq!(module_keys("lib/index.d.ts"), ["lib/index", "lib"]);
+  }
+
+  #[test]
+  fn ts_paths_classify_aliases_of_existing_modules() {
+    let paths = TsPaths {
+      base: "src".to_owned(),
+      paths: vec![(
+        "@lib/*".to_owned(),
+        vec!["gone/*".to_owned(), "../lib/*.ts".to_owned()],
+      )],
+    };
+    let classify = |specifier| {
+      paths.classify("app/main.ts", specifier, |key| key == "lib/util")
+    };
+    assert_eq!(classify("@lib/util"), Specifier::Module("lib/util".to_owned()));
+    assert_eq!(
+      classify("@lib/other"),
+      Specifier::Package("@lib/other".to_owned()),
+    );
+    assert_eq!(classify("./util"), Specifier::Module("app/util".to_owned()));
+  }
+}
@@ -0,0 +1,322 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::rc::Rc;
+use crate::label::Label;
+use crate::module::Module;
+use crate::package::PackageLoader;
+use crate::starlark::error::EvalError;
+use crate::starlark::value::{Object, Value};
+use crate::target_pattern::PatternScope;
+use super::analyzer::AnalysisError;
+use super::config::Configuration;
+
+/// Returns the labels of the toolchains registered in `MODULE.razel`, in
+/// order of preference. Toolchains matched by the same pattern are ordered
+/// by label.
+pub fn registered_toolchains(packages: &PackageLoader) -> Result<Vec<Label>, Box<dyn Error>> {
+  let module = Module::load(packages.host().as_ref())?;
+  let mut toolchains = Vec::new();
+  for pattern in &module.toolchains {
+    match &pattern.scope {
+      PatternScope::SingleTarget(name) => {
+        toolchains.push(Label { repo: pattern.repo.clone(), package: pattern.package.clone(), name: name.clone() });
+      },
+      PatternScope::Package | PatternScope::Descendants => {
+        for package in pattern.packages(packages.repositories().get(&pattern.repo)?.as_ref())? {
+          toolchains.extend(packages.load(&pattern.repo, &package)?.toolchains.keys().map(|name| Label {
+            repo: pattern.repo.clone(),
+            package: package.clone(),
+            name: name.clone(),
+          }));
+        }
+      },
+    }
+  }
+  Ok(toolchains)
+}
+
+/// Selects a toolchain for each of `types` for the target `label`: the first
+/// registered toolchain of the type whose constraints are satisfied by the
+/// execution and target platforms of the configuration. Explains every
+/// decision in `trace`, for `--toolchain_resolution_debug`.
+pub fn resolve_toolchains(
+  packages: &PackageLoader,
+  registered: &[Label],
+  types: &[Label],
+  label: &Label,
+  config: &Configuration,
+  trace: &mut Vec<String>,
+) -> Result<BTreeMap<Label, Label>, Box<dyn Error>> {
+  let target_platform = platform_constraints(packages, config.platform.as_ref())?;
+  let exec_platform = platform_constraints(packages, config.host_platform.as_ref())?;
+  let target_name = platform_name(config.platform.as_ref());
+  let exec_name = platform_name(config.host_platform.as_ref());
+
+  let mut resolved = BTreeMap::new();
+  for toolchain_type in types {
+    packages.check_toolchain_type(toolchain_type)?;
+    let prefix = format!("Type {}: target platform {}", toolchain_type, target_name);
+
+    let mut selected = None;
+    for candidate in registered {
+      let toolchain = packages.toolchain(candidate)?;
+      if toolchain.toolchain_type != *toolchain_type {
+        continue;
+      }
+
+      let exec_mismatches = mismatches(packages, &exec_platform, &toolchain.exec_compatible_with)?;
+      if !exec_mismatches.is_empty() {
+        trace.push(format!(
+          "{}: execution platform {}: Rejected toolchain {}; mismatching values: {}",
+          prefix,
+          exec_name,
+          candidate,
+          exec_mismatches.join(", "),
+        ));
+        continue;
+      }
+      let target_mismatches = mismatches(packages, &target_platform, &toolchain.target_compatible_with)?;
+      if !target_mismatches.is_empty() {
+        trace.push(format!(
+          "{}: Rejected toolchain {}; mismatching values: {}",
+          prefix,
+          candidate,
+          target_mismatches.join(", "),
+        ));
+        continue;
+      }
+
+      trace.push(format!("{}: execution platform {}: Selected toolchain {}", prefix, exec_name, candidate));
+      selected = Some(toolchain.toolchain.clone());
+      break;
+    }
+
+    let Some(selected) = selected else {
+      trace.push(format!("{}: No toolchains found.", prefix));
+      return Err(Box::new(AnalysisError(format!(
+        "No matching toolchain found for type {} with target platform {} and execution platform {}. \
+          To debug, rerun with --toolchain_resolution_debug={}",
+        toolchain_type,
+        target_name,
+        exec_name,
+        toolchain_type,
+      ))));
+    };
+    resolved.insert(toolchain_type.clone(), selected);
+  }
+
+  trace.push(format!(
+    "Target {}: Selected execution platform {}, {}",
+    label,
+    exec_name,
+    resolved.iter()
+      .map(|(toolchain_type, toolchain)| format!("type {} -> toolchain {}", toolchain_type, toolchain))
+      .collect::<Vec<_>>()
+      .join(", "),
+  ));
+  Ok(resolved)
+}
+
+/// Returns the constraint values of a platform by their constraint setting.
+pub fn platform_constraints(packages: &PackageLoader, platform: Option<&Label>) ->
+    Result<BTreeMap<Label, Label>, Box<dyn Error>> {
+  let mut constraints = BTreeMap::new();
+  let Some(platform) = platform else {
+    return Ok(constraints);
+  };
+  for value in &packages.platform(platform)?.constraint_values {
+    let setting = packages.constraint_value(value)?.setting.clone();
+    if let Some(other) = constraints.insert(setting.clone(), value.clone()) {
+      return Err(Box::new(AnalysisError(format!(
+        "Platform {} has both {} and {} for constraint setting {}.",
+        platform,
+        other,
+        value,
+        setting,
+      ))));
+    }
+  }
+  Ok(constraints)
+}
+
+/// Returns whether a platform has a constraint value, either explicitly or
+/// as the default of its setting.
+pub fn has_constraint(packages: &PackageLoader, constraints: &BTreeMap<Label, Label>, value: &Label) ->
+    Result<bool, Box<dyn Error>> {
+  let setting = &packages.constraint_value(value)?.setting;
+  Ok(match constraints.get(setting) {
+    Some(actual) => actual == value,
+    None => packages.constraint_setting(setting)?.default_value.as_ref() == Some(value),
+  })
+}
+
+/// Returns the names of the required constraint values a platform lacks.
+fn mismatches(packages: &PackageLoader, constraints: &BTreeMap<Label, Label>, required: &[Label]) ->
+    Result<Vec<String>, Box<dyn Error>> {
+  let mut mismatches = Vec::new();
+  for value in required {
+    if !has_constraint(packages, constraints, value)? {
+      mismatches.push(value.name.clone());
+    }
+  }
+  Ok(mismatches)
+}
+
+fn platform_name(platform: Option<&Label>) -> String {
+  platform.map_or("<default platform>".to_owned(), |platform| platform.to_string())
+}
+
+/// The `ctx.toolchains` of a rule implementation, which maps each toolchain
+/// type of the rule to the `ToolchainInfo` of the toolchain resolved for it.
+pub struct ToolchainContext {
+  pub toolchains: BTreeMap<Label, Value>,
+}
+
+impl Object for ToolchainContext {
+  fn type_name(&self) -> String {
+    "ToolchainContext".to_owned()
+  }
+
+  fn index(&self, key: &Value) -> Result<Value, EvalError> {
+    let toolchain_type = Label::parse(key.expect_str("toolchain type")?, "").map_err(|err| EvalError::msg(err.0))?;
+    self.toolchains.get(&toolchain_type).cloned().ok_or_else(|| {
+      EvalError::msg(format!("Toolchain type {} is not declared by the rule.", toolchain_type))
+    })
+  }
+
+  fn contains(&self, key: &Value) -> Result<bool, EvalError> {
+    let toolchain_type = Label::parse(key.expect_str("toolchain type")?, "").map_err(|err| EvalError::msg(err.0))?;
+    Ok(self.toolchains.contains_key(&toolchain_type))
+  }
+
+  fn repr(&self) -> String {
+    format!(
+      "<toolchain context for {}>",
+      self.toolchains.keys().map(|toolchain_type| toolchain_type.to_string()).collect::<Vec<_>>().join(", "),
+    )
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::path::Path;
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+  use super::*;
+
+  fn label(label: &str) -> Label {
+    Label::parse(label, "").unwrap()
+  }
+
+  #[test]
+  fn resolve_toolchains_selects_first_compatible_toolchain() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("MODULE.razel"), TestContents::File(
+        "register_toolchains(\"//toolchains:node_mac\", \"//toolchains:all\")",
+      )),
+      (Path::new("platforms/BUILD"), TestContents::File(r#"
+constraint_setting(name = "os", default_constraint_value = ":linux")
+constraint_value(name = "linux", constraint_setting = ":os")
+constraint_value(name = "macos", constraint_setting = ":os")
+constraint_setting(name = "cpu")
+constraint_value(name = "arm64", constraint_setting = ":cpu")
+
+platform(name = "mac_arm64", constraint_values = [":macos", ":arm64"])
+platform(name = "linux_any", constraint_values = [":linux"])
+platform(name = "invalid", constraint_values = [":linux", ":macos"])
+"#)),
+      (Path::new("toolchains/BUILD"), TestContents::File(r#"
+toolchain_type(name = "node")
+toolchain_type(name = "tsc")
+
+toolchain(
+    name = "node_mac",
+    toolchain_type = ":node",
+    toolchain = ":node_mac_impl",
+    exec_compatible_with = ["//platforms:macos"],
+    target_compatible_with = ["//platforms:arm64"],
+)
+toolchain(
+    name = "node_linux",
+    toolchain_type = ":node",
+    toolchain = ":node_linux_impl",
+    target_compatible_with = ["//platforms:linux"],
+)
+"#)),
+    ])?;
+    let packages = PackageLoader::new(Rc::new(FsHost::from(&dir.root)?));
+    let registered = registered_toolchains(&packages)?;
+    assert_eq!(registered, [
+      label("//toolchains:node_mac"),
+      label("//toolchains:node_linux"),
+      label("//toolchains:node_mac"),
+    ]);
+    let resolve = |toolchain_type: &str, platform: Option<&str>, host_platform: Option<&str>| {
+      let config = Configuration {
+        platform: platform.map(label),
+        host_platform: host_platform.map(label),
+        ..Configuration::default()
+      };
+      let mut trace = Vec::new();
+      let resolved = resolve_toolchains(&packages, &registered, &[label(toolchain_type)], &label("//:app"), &config, &mut trace);
+      (resolved.map_err(|err| err.to_string()), trace)
+    };
+
+    let (resolved, trace) = resolve("//toolchains:node", Some("//platforms:mac_arm64"), Some("//platforms:mac_arm64"));
+    assert_eq!(resolved?, BTreeMap::from([(label("//toolchains:node"), label("//toolchains:node_mac_impl"))]));
+    assert_eq!(trace, [
+      "Type //toolchains:node: target platform //platforms:mac_arm64: execution platform //platforms:mac_arm64: \
+        Selected toolchain //toolchains:node_mac",
+      "Target //:app: Selected execution platform //platforms:mac_arm64, \
+        type //toolchains:node -> toolchain //toolchains:node_mac_impl",
+    ]);
+
+    // The default platform has the default value of every constraint setting.
+    let (resolved, trace) = resolve("//toolchains:node", None, None);
+    assert_eq!(resolved?[&label("//toolchains:node")], label("//toolchains:node_linux_impl"));
+    assert_eq!(
+      trace[0],
+      "Type //toolchains:node: target platform <default platform>: execution platform <default platform>: \
+        Rejected toolchain //toolchains:node_mac; mismatching values: macos",
+    );
+
+    let (resolved, trace) = resolve("//toolchains:node", Some("//platforms:mac_arm64"), None);
+    assert_eq!(
+      resolved.unwrap_err(),
+      "No matching toolchain found for type //toolchains:node with target platform //platforms:mac_arm64 and \
+        execution platform <default platform>. To debug, rerun with --toolchain_resolution_debug=//toolchains:node",
+    );
+    assert_eq!(
+      trace[1],
+      "Type //toolchains:node: target platform //platforms:mac_arm64: Rejected toolchain //toolchains:node_linux; \
+        mismatching values: linux",
+    );
+    assert_eq!(trace[3], "Type //toolchains:node: target platform //platforms:mac_arm64: No toolchains found.");
+
+    assert_eq!(
+      resolve("//toolchains:tsc", None, None).0.unwrap_err(),
+      "No matching toolchain found for type //toolchains:tsc with target platform <default platform> and \
+        execution platform <default platform>. To debug, rerun with --toolchain_resolution_debug=//toolchains:tsc",
+    );
+    assert_eq!(
+      resolve("//toolchains:node_mac", None, None).0.unwrap_err(),
+      "`//toolchains:node_mac` is not a toolchain_type.",
+    );
+    assert_eq!(
+      platform_constraints(&packages, Some(&label("//platforms:invalid"))).err().unwrap().to_string(),
+      "Platform //platforms:invalid has both //platforms:linux and //platforms:macos for constraint setting \
+        //platforms:os.",
+    );
+    assert_eq!(
+      platform_constraints(&packages, Some(&label("//platforms:linux_x64"))).err().unwrap().to_string(),
+      "`//platforms:linux_x64` is not a platform.",
+    );
+
+    Ok(())
+  }
+}
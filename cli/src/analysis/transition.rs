@@ -0,0 +1,176 @@
+use std::any::Any;
+use std::rc::Rc;
+use crate::label::Label;
+use crate::starlark::error::EvalError;
+use crate::starlark::eval::Evaluator;
+use crate::starlark::value::{Args, Object, Value};
+use super::config::{Configuration, COMPILATION_MODES};
+
+/// The prefix of the settings transitions read and write which correspond to
+/// command-line options.
+pub const COMMAND_LINE_OPTION: &str = "//command_line_option:";
+
+/// The command-line options transitions may read and write.
+const TRANSITION_OPTIONS: [&str; 3] = ["compilation_mode", "define", "platforms"];
+
+/// How the configuration of a dependency is derived from its consumer's, from
+/// the `cfg` parameter of label attributes and rules.
+#[derive(Clone)]
+pub enum Cfg {
+  /// The dependency is built in the same configuration.
+  Target,
+
+  /// The dependency is a tool run during the build, built in the exec
+  /// configuration.
+  Exec,
+
+  /// The dependency is built in the configuration returned by a transition.
+  Transition(Rc<Transition>),
+}
+
+impl Cfg {
+  /// Parses the `cfg` parameter of a label attribute or rule.
+  pub fn parse(value: Option<Value>, allow_exec: bool) -> Result<Cfg, EvalError> {
+    match value {
+      Some(Value::None) | None => Ok(Cfg::Target),
+      Some(Value::Str(cfg)) if &*cfg == "target" => Ok(Cfg::Target),
+      Some(Value::Str(cfg)) if &*cfg == "exec" && allow_exec => Ok(Cfg::Exec),
+      Some(value) => match value.downcast::<Transition>() {
+        Some(transition) => Ok(Cfg::Transition(transition)),
+        None if allow_exec => Err(EvalError::msg(format!(
+          "`cfg` must be \"target\", \"exec\" or a transition, got {}.",
+          value.repr(),
+        ))),
+        None => Err(EvalError::msg(format!("`cfg` must be a transition, got {}.", value.repr()))),
+      },
+    }
+  }
+
+  /// Returns the configuration to analyze a dependency in, given its
+  /// consumer's configuration and attribute values.
+  pub fn apply(&self, config: &Rc<Configuration>, attr: &Value) -> Result<Rc<Configuration>, EvalError> {
+    match self {
+      Cfg::Target => Ok(config.clone()),
+      Cfg::Exec => Ok(Rc::new(config.exec())),
+      Cfg::Transition(transition) => {
+        let transitioned = transition.apply(config, attr)?;
+        Ok(if transitioned == **config { config.clone() } else { Rc::new(transitioned) })
+      },
+    }
+  }
+}
+
+/// A user-defined transition created by `transition()` in a .bzl file.
+pub struct Transition {
+  /// The function called with the input settings and the attributes of the
+  /// target, returning the output settings.
+  pub implementation: Value,
+  pub inputs: Vec<String>,
+  pub outputs: Vec<String>,
+}
+
+impl Transition {
+  pub fn apply(&self, config: &Configuration, attr: &Value) -> Result<Configuration, EvalError> {
+    let settings = self.inputs.iter()
+      .map(|input| (Value::str(input), get_setting(config, input)))
+      .collect();
+    let settings = Value::dict(settings)?;
+    settings.freeze();
+
+    let result = Evaluator::new().call(&self.implementation, Args::positional(vec![settings, attr.clone()]))?;
+    let result = result.expect_dict("transition result")?;
+
+    let mut transitioned = config.clone();
+    for (name, value) in &result {
+      let name = name.expect_str("transition result key")?;
+      if !self.outputs.iter().any(|output| output == name) {
+        return Err(EvalError::msg(format!("Transition returned `{}`, which is not one of its outputs.", name)));
+      }
+      set_setting(&mut transitioned, name, value)?;
+    }
+    if let Some(missing) = self.outputs.iter().find(|output| !result.iter().any(|(name, _)| name == &Value::str(output))) {
+      return Err(EvalError::msg(format!("Transition did not return its output `{}`.", missing)));
+    }
+
+    Ok(transitioned)
+  }
+}
+
+impl Object for Transition {
+  fn type_name(&self) -> String {
+    "transition".to_owned()
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+fn option_name(setting: &str) -> Option<&str> {
+  setting.strip_prefix(COMMAND_LINE_OPTION).filter(|option| TRANSITION_OPTIONS.contains(option))
+}
+
+fn get_setting(config: &Configuration, setting: &str) -> Value {
+  match option_name(setting) {
+    Some("define") => Value::list(config.defines.iter()
+      .map(|(name, value)| Value::str(&format!("{}={}", name, value)))
+      .collect()),
+    Some("platforms") => Value::list(config.platform.iter().map(|platform| Value::str(&platform.to_string())).collect()),
+    Some(option) => config.option(option).map_or(Value::None, Value::str),
+    None => Value::None,
+  }
+}
+
+fn set_setting(config: &mut Configuration, setting: &str, value: &Value) -> Result<(), EvalError> {
+  match option_name(setting) {
+    Some("define") => {
+      config.defines.clear();
+      for define in value.expect_str_list(setting)? {
+        let (name, value) = define.split_once('=')
+          .ok_or_else(|| EvalError::msg(format!("Invalid define `{}`, expected `KEY=VALUE`.", define)))?;
+        config.defines.insert(name.to_owned(), value.to_owned());
+      }
+    },
+    Some("platforms") => {
+      let platforms = match value {
+        Value::Str(platform) => vec![platform.to_string()],
+        value => value.expect_str_list(setting)?,
+      };
+      config.platform = match platforms.as_slice() {
+        [] => None,
+        [platform] => Some(Label::parse(platform, "").map_err(|err| EvalError::msg(err.0))?),
+        _ => return Err(EvalError::msg("Only a single target platform is supported.")),
+      };
+    },
+    Some("compilation_mode") => {
+      let mode = value.expect_str(setting)?;
+      if !COMPILATION_MODES.contains(&mode) {
+        return Err(EvalError::msg(format!("Invalid compilation mode `{}`.", mode)));
+      }
+      config.options.insert("compilation_mode".to_owned(), mode.to_owned());
+    },
+    _ => unreachable!(),
+  }
+  Ok(())
+}
+
+/// The `transition()` builtin of .bzl files.
+pub fn transition_builtin() -> Value {
+  Value::builtin("transition", |_, args| {
+    let [implementation, inputs, outputs] = args.bind("transition", &["implementation", "inputs", "outputs"], &[])?
+      .try_into()
+      .unwrap();
+    let implementation = implementation.unwrap();
+    if !matches!(implementation, Value::Function(_)) {
+      return Err(implementation.type_error("implementation", "function"));
+    }
+
+    let inputs = inputs.unwrap().expect_str_list("inputs")?;
+    let outputs = outputs.unwrap().expect_str_list("outputs")?;
+    if let Some(setting) = inputs.iter().chain(&outputs).find(|setting| option_name(setting).is_none()) {
+      return Err(EvalError::msg(format!("Unknown setting `{}` in transition.", setting)));
+    }
+
+    Ok(Value::object(Transition { implementation, inputs, outputs }))
+  })
+}
@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+use std::error::Error;
+use crate::label::Label;
+use crate::package::{private, public, PackageLoader};
+use super::analyzer::AnalysisError;
+
+/// Returns whether the target `dep` may be depended on by the target `from`.
+/// Targets are always visible within their own package, and source files are
+/// visible everywhere.
+pub fn is_visible(packages: &PackageLoader, dep: &Label, from: &Label) -> Result<bool, Box<dyn Error>> {
+  if dep.repo == from.repo && dep.package == from.package {
+    return Ok(true);
+  }
+
+  let dep_package = packages.package_of(dep)?;
+  let target = dep_package.targets.get(&dep.name).or_else(|| {
+    let rule = dep_package.outputs.get(&dep.name)?;
+    dep_package.targets.get(rule)
+  });
+  let Some(target) = target else {
+    return Ok(true);
+  };
+
+  let mut visited = HashSet::new();
+  for spec in &target.visibility {
+    if includes(packages, spec, from, &mut visited)? {
+      return Ok(true);
+    }
+  }
+  Ok(false)
+}
+
+/// Returns whether a visibility label includes the package of `from`.
+fn includes(packages: &PackageLoader, spec: &Label, from: &Label, visited: &mut HashSet<Label>) ->
+    Result<bool, Box<dyn Error>> {
+  let package = from.package.as_str();
+  // The special visibilities mean the same in every repository.
+  let special = Label { repo: String::new(), ..spec.clone() };
+  if special == public() {
+    return Ok(true);
+  }
+  if special == private() {
+    return Ok(false);
+  }
+  match spec.name.as_str() {
+    "__pkg__" => return Ok(spec.repo == from.repo && spec.package == package),
+    "__subpackages__" => {
+      return Ok(spec.repo == from.repo && (spec.package.is_empty() || spec.package == package ||
+        package.strip_prefix(&spec.package).is_some_and(|rest| rest.starts_with('/'))));
+    },
+    _ => {},
+  }
+
+  // Anything else refers to a package group, which may include others.
+  if !visited.insert(spec.clone()) {
+    return Ok(false);
+  }
+  let group = packages.package_of(spec)?.package_groups.get(&spec.name).cloned()
+    .ok_or_else(|| AnalysisError(format!("Invalid visibility `{}`, it is not a package_group.", spec)))?;
+  if group.packages.iter().any(|pattern| pattern.repo == from.repo && pattern.matches_package(package)) {
+    return Ok(true);
+  }
+  for include in &group.includes {
+    if includes(packages, include, from, visited)? {
+      return Ok(true);
+    }
+  }
+  Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+  use std::path::Path;
+  use std::rc::Rc;
+  use assertables::assert_contains;
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+  use super::*;
+
+  fn label(package: &str, name: &str) -> Label {
+    Label { repo: "".to_owned(), package: package.to_owned(), name: name.to_owned() }
+  }
+
+  #[test]
+  fn is_visible_checks_visibility_and_package_groups() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("defs.bzl"), TestContents::File(
+        "noop = rule(implementation = lambda ctx: None, attrs = {\"out\": attr.output()})",
+      )),
+      (Path::new("lib/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "noop")
+
+package(default_visibility = [":friends"])
+
+package_group(name = "friends", packages = ["//app/..."], includes = [":tools"])
+package_group(name = "tools", packages = ["//tools"])
+
+noop(name = "default", out = "default.out")
+noop(name = "private", visibility = ["//visibility:private"])
+noop(name = "public", visibility = ["//visibility:public"])
+noop(name = "subpackages", visibility = ["//web:__subpackages__"])
+noop(name = "invalid", visibility = ["//lib:default"])
+"#)),
+    ])?;
+    let packages = PackageLoader::new(Rc::new(FsHost::from(&dir.root)?));
+
+    assert!(is_visible(&packages, &label("lib", "default"), &label("app/web", "x"))?);
+    assert!(is_visible(&packages, &label("lib", "default.out"), &label("tools", "x"))?);
+    assert!(!is_visible(&packages, &label("lib", "default"), &label("tools/sub", "x"))?);
+    assert!(!is_visible(&packages, &label("lib", "private"), &label("app", "x"))?);
+    assert!(is_visible(&packages, &label("lib", "private"), &label("lib", "x"))?);
+    assert!(is_visible(&packages, &label("lib", "public"), &label("other", "x"))?);
+    assert!(is_visible(&packages, &label("lib", "subpackages"), &label("web/sub", "x"))?);
+    assert!(!is_visible(&packages, &label("lib", "subpackages"), &label("webapp", "x"))?);
+    assert!(is_visible(&packages, &label("lib", "BUILD"), &label("other", "x"))?);
+    assert_contains!(
+      is_visible(&packages, &label("lib", "invalid"), &label("other", "x")).err().unwrap().to_string(),
+      "Invalid visibility `//lib:default`",
+    );
+
+    Ok(())
+  }
+}
@@ -0,0 +1,799 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rand::random;
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_json::Value as Json;
+use crate::analysis::action::{Action, ActionKind};
+use crate::analysis::artifact::Artifact;
+use crate::execution::exec_path;
+use crate::grpc::GrpcCall;
+use crate::host::host::Host;
+use crate::label::Label;
+use crate::lsp::file_uri;
+
+/// The method of a build event service which build tools publish to.
+const PUBLISH: &str =
+  "/google.devtools.build.v1.PublishBuildEvent/PublishBuildToolEventStream";
+
+/// The type of the events in the `Any` published to a build event service.
+const BAZEL_EVENT: &str = "type.googleapis.com/build_event_stream.BuildEvent";
+
+/// `Aborted.AbortReason.INCOMPLETE`, for events announced but never posted
+/// because the build failed first.
+const INCOMPLETE: (i32, &str) = (10, "INCOMPLETE");
+
+/// `StreamId.BuildComponent.TOOL`, the component publishing events.
+const TOOL: (i32, &str) = (3, "TOOL");
+
+/// `BuildComponentStreamFinished.FinishType.FINISHED`.
+const FINISHED: (i32, &str) = (1, "FINISHED");
+
+/// Writes the events of a build in Bazel's Build Event Protocol, as
+/// newline-delimited JSON or length-delimited `BuildEvent` protobufs, to
+/// files and optionally publishes them to a build event service. Razel has no
+/// test command yet, so no `testResult` events are posted.
+///
+/// Every event but the first is announced as a child of an earlier one.
+/// Events no other event announces, such as completed actions, are announced
+/// by a chain of progress events, each also announcing the next.
+pub struct BuildEventStream {
+  workspace: PathBuf,
+  output_base: PathBuf,
+
+  /// The UUID of the build, which the started event reports.
+  invocation_id: String,
+
+  /// Where events are written, with whether in JSON rather than binary.
+  files: RefCell<Vec<(Box<dyn Write>, bool)>>,
+
+  /// The build event service events are published to.
+  backend: RefCell<Option<Backend>>,
+
+  /// The ids of events announced but not posted yet.
+  pending: RefCell<Vec<Message>>,
+
+  /// The count of the next progress event.
+  progress: Cell<i32>,
+
+  /// The number of named sets of files posted.
+  named_sets: Cell<usize>,
+
+  /// The first error writing an event, reported once the build finishes.
+  error: RefCell<Option<String>>,
+}
+
+impl BuildEventStream {
+  /// Returns a stream for a build of the workspace of `host`, not written
+  /// anywhere yet.
+  pub fn new(host: &dyn Host) -> BuildEventStream {
+    BuildEventStream {
+      workspace: host.source_root().to_owned(),
+      output_base: host.output_base().to_owned(),
+      invocation_id: uuid(),
+      files: RefCell::new(Vec::new()),
+      backend: RefCell::new(None),
+      pending: RefCell::new(Vec::new()),
+      progress: Cell::new(0),
+      named_sets: Cell::new(0),
+      error: RefCell::new(None),
+    }
+  }
+
+  /// Also writes events to a file, one JSON object per line.
+  pub fn with_json_file(self, path: &Path) -> Result<BuildEventStream, Box<dyn Error>> {
+    self.files.borrow_mut().push((Box::new(File::create(path)?), true));
+    Ok(self)
+  }
+
+  /// Also writes events to a file, each protobuf preceded by its length as a
+  /// varint.
+  pub fn with_binary_file(self, path: &Path) -> Result<BuildEventStream, Box<dyn Error>> {
+    self.files.borrow_mut().push((Box::new(File::create(path)?), false));
+    Ok(self)
+  }
+
+  /// Also publishes events to the build event service at `host:port`,
+  /// optionally prefixed with `grpc://`, in a `PublishBuildToolEventStream`
+  /// call.
+  pub fn with_backend(self, address: &str) -> Result<BuildEventStream, Box<dyn Error>> {
+    let call = GrpcCall::start(address, PUBLISH).map_err(|err| {
+      BuildEventError(format!("Cannot publish build events to {}: {}", address, err))
+    })?;
+    let backend = Backend { call, build_id: uuid(), sequence: 0 };
+    *self.backend.borrow_mut() = Some(backend);
+    Ok(self)
+  }
+
+  /// Posts the first event, announcing the expansion of the patterns and the
+  /// end of the build.
+  pub fn started(&self, command: &str, patterns: &[String]) {
+    let mut children = vec![progress_id(0), finished_id()];
+    if !patterns.is_empty() {
+      children.insert(1, pattern_id(patterns));
+    }
+    *self.pending.borrow_mut() = children.clone();
+
+    let now = SystemTime::now();
+    let started = Message::new()
+      .string(1, "uuid", self.invocation_id.as_str())
+      .int64(2, "startTimeMillis", millis(now))
+      .string(3, "buildToolVersion", env!("CARGO_PKG_VERSION"))
+      .string(5, "command", command)
+      .string(6, "workingDirectory", env::current_dir().unwrap_or_default().to_string_lossy())
+      .string(7, "workspaceDirectory", self.workspace.to_string_lossy())
+      .timestamp(9, "startTime", now);
+    self.write(&event(id(3, "started", Message::new()), children, (5, "started", started), false));
+  }
+
+  /// Posts the labels the patterns expanded to, announcing that each target
+  /// is configured.
+  pub fn pattern_expanded(&self, patterns: &[String], labels: &[Label]) {
+    let children = labels.iter().map(configured_id).collect();
+    self.post(pattern_id(patterns), children, (6, "expanded", Message::new()));
+  }
+
+  /// Posts that a requested target was analyzed in a configuration,
+  /// announcing its completion.
+  pub fn target_configured(&self, label: &Label, kind: &str, config: &str) {
+    let configured = Message::new().string(1, "targetKind", kind);
+    self.post(
+      configured_id(label),
+      vec![completed_id(label, config)],
+      (18, "configured", configured),
+    );
+  }
+
+  /// Posts that an action which started at `start` finished.
+  pub fn action_completed(&self, action: &Action, success: bool, start: SystemTime) {
+    let primary_output = action.outputs.first()
+      .map(|output| output.path.clone())
+      .unwrap_or_default();
+    let owner = action.owner.to_string();
+    let id = id(6, "actionCompleted", Message::new()
+      .string(1, "primaryOutput", primary_output.as_str())
+      .string(2, "label", owner.as_str()));
+    let mut executed = Message::new()
+      .bool(1, "success", success)
+      .string(5, "label", owner)
+      .string(8, "type", action.mnemonic.as_str())
+      .timestamp(12, "startTime", start)
+      .timestamp(13, "endTime", SystemTime::now());
+    if let Some(output) = action.outputs.first() {
+      executed = executed.message(6, "primaryOutput", self.file(output));
+    }
+    if let ActionKind::Run { executable, arguments, .. } = &action.kind {
+      let command_line = [executable].into_iter().chain(arguments)
+        .map(|arg| Field::String(arg.clone()))
+        .collect();
+      executed = executed.field(9, "commandLine", Field::Repeated(command_line));
+    }
+    self.post(id, Vec::new(), (7, "action", executed));
+  }
+
+  /// Posts that a requested target was built, or failed to, with its files
+  /// in a named set referenced by its default output group.
+  pub fn target_completed(&self, label: &Label, config: &str,
+      files: &[Rc<Artifact>], success: bool) {
+    let mut completed = Message::new().bool(1, "success", success);
+    if success {
+      let named_set = Message::new().string(1, "id", self.named_sets.get().to_string());
+      self.named_sets.set(self.named_sets.get() + 1);
+      let files = files.iter().map(|file| Field::Message(self.file(file))).collect();
+      self.post(
+        id(13, "namedSet", named_set.clone()),
+        Vec::new(),
+        (15, "namedSetOfFiles", Message::new().field(1, "files", Field::Repeated(files))),
+      );
+      let output_group = Message::new()
+        .string(1, "name", "default")
+        .field(3, "fileSets", Field::Repeated(vec![Field::Message(named_set)]));
+      let output_groups = vec![Field::Message(output_group)];
+      completed = completed.field(2, "outputGroup", Field::Repeated(output_groups));
+    }
+    self.post(completed_id(label, config), Vec::new(), (8, "completed", completed));
+  }
+
+  /// Posts the last event after aborting every event announced but not
+  /// posted, then waits for the build event service, if any, to acknowledge
+  /// every event.
+  pub fn finished(&self, success: bool) -> Result<(), Box<dyn Error>> {
+    let finished_id = finished_id();
+    let pending = self.pending.take();
+    for id in pending.into_iter().filter(|id| *id != finished_id) {
+      let payload = if id.0[0].1 == "progress" {
+        (3, "progress", Message::new())
+      } else {
+        let description = "The build failed before this event.";
+        let aborted = Message::new()
+          .field(1, "reason", Field::Enum(INCOMPLETE.0, INCOMPLETE.1))
+          .string(2, "description", description);
+        (4, "aborted", aborted)
+      };
+      self.write(&event(id, Vec::new(), payload, false));
+    }
+
+    let now = SystemTime::now();
+    let (name, code) = if success { ("SUCCESS", 0) } else { ("BUILD_FAILURE", 1) };
+    let finished = Message::new()
+      .bool(1, "overallSuccess", success)
+      .int64(2, "finishTimeMillis", millis(now))
+      .message(3, "exitCode", Message::new().string(1, "name", name).int32(2, "code", code))
+      .timestamp(5, "finishTime", now);
+    self.write(&event(finished_id, Vec::new(), (14, "finished", finished), true));
+
+    for (file, _) in self.files.borrow_mut().iter_mut() {
+      if let Err(err) = file.flush() {
+        self.fail(err.to_string());
+      }
+    }
+    if let Some(backend) = self.backend.take() {
+      if let Err(err) = self.finish_publishing(backend) {
+        self.fail(err.to_string());
+      }
+    }
+    match self.error.take() {
+      Some(error) => Err(BuildEventError(format!("Cannot publish build events: {}", error)).into()),
+      None => Ok(()),
+    }
+  }
+
+  /// Posts an event, first announcing it with a progress event if no earlier
+  /// event did.
+  fn post(&self, id: Message, children: Vec<Message>, payload: (u32, &'static str, Message)) {
+    let announced = {
+      let mut pending = self.pending.borrow_mut();
+      let position = pending.iter().position(|pending| *pending == id);
+      position.map(|position| pending.remove(position)).is_some()
+    };
+    if !announced {
+      let progress = progress_id(self.progress.get());
+      self.progress.set(self.progress.get() + 1);
+      let next = progress_id(self.progress.get());
+      self.pending.borrow_mut().retain(|pending| *pending != progress);
+      self.pending.borrow_mut().push(next.clone());
+      self.write(&event(progress, vec![id.clone(), next], (3, "progress", Message::new()), false));
+    }
+    self.pending.borrow_mut().extend(children.iter().cloned());
+    self.write(&event(id, children, payload, false));
+  }
+
+  fn write(&self, event: &Message) {
+    let binary = delimited(&event.encode());
+    for (file, json) in self.files.borrow_mut().iter_mut() {
+      let written = match json {
+        true => writeln!(file, "{}", event.to_json()),
+        false => file.write_all(&binary),
+      };
+      if let Err(err) = written {
+        self.fail(err.to_string());
+      }
+    }
+    let mut backend = self.backend.borrow_mut();
+    if let Some(published) = backend.as_mut() {
+      let bazel_event = Message::new()
+        .string(1, "typeUrl", BAZEL_EVENT)
+        .field(2, "value", Field::Bytes(event.encode()));
+      let build_event = Message::new()
+        .timestamp(1, "eventTime", SystemTime::now())
+        .message(60, "bazelEvent", bazel_event);
+      // The call is abandoned once a request cannot be sent.
+      if let Err(err) = self.publish(published, build_event) {
+        self.fail(err.to_string());
+        *backend = None;
+      }
+    }
+  }
+
+  /// Sends a `google.devtools.build.v1.BuildEvent` of the build tool's
+  /// stream to the build event service.
+  fn publish(&self, backend: &mut Backend, event: Message) ->
+      Result<(), Box<dyn Error>> {
+    backend.sequence += 1;
+    let stream_id = Message::new()
+      .string(1, "buildId", backend.build_id.as_str())
+      .field(3, "component", Field::Enum(TOOL.0, TOOL.1))
+      .string(6, "invocationId", self.invocation_id.as_str());
+    let ordered = Message::new()
+      .message(1, "streamId", stream_id)
+      .int64(2, "sequenceNumber", backend.sequence)
+      .message(3, "event", event);
+    let request = Message::new().message(4, "orderedBuildEvent", ordered);
+    backend.call.send(&request.encode())
+  }
+
+  /// Ends the build tool's stream and the call, checking that the build event
+  /// service acknowledged every event.
+  fn finish_publishing(&self, mut backend: Backend) ->
+      Result<(), Box<dyn Error>> {
+    let finished = Message::new()
+      .field(1, "type", Field::Enum(FINISHED.0, FINISHED.1));
+    let event = Message::new()
+      .timestamp(1, "eventTime", SystemTime::now())
+      .message(59, "componentStreamFinished", finished);
+    self.publish(&mut backend, event)?;
+    let sent = backend.sequence;
+    let responses = backend.call.finish()?;
+    let acknowledged = responses.iter()
+      .filter_map(|response| match decode_field(response, 2) {
+        Some(Encoded::Varint(sequence)) => Some(sequence),
+        _ => None,
+      })
+      .max()
+      .unwrap_or(0);
+    if acknowledged != sent as u64 {
+      return Err(BuildEventError(format!(
+        "the build event service acknowledged {} of {} events.",
+        acknowledged,
+        sent,
+      )).into());
+    }
+    Ok(())
+  }
+
+  fn fail(&self, error: String) {
+    self.error.borrow_mut().get_or_insert(error);
+  }
+
+  /// Returns a `File` message locating an artifact.
+  fn file(&self, artifact: &Artifact) -> Message {
+    let path = match artifact.is_source {
+      true => self.workspace.join(&artifact.path),
+      false => self.output_base.join(exec_path(&artifact.path)),
+    };
+    Message::new().string(1, "name", artifact.path.as_str()).string(2, "uri", file_uri(&path))
+  }
+}
+
+/// A `PublishBuildToolEventStream` call to a build event service.
+struct Backend {
+  call: GrpcCall,
+  build_id: String,
+
+  /// The sequence number of the last event sent.
+  sequence: i64,
+}
+
+fn event(id: Message, children: Vec<Message>,
+    payload: (u32, &'static str, Message), last: bool) -> Message {
+  Message::new()
+    .message(1, "id", id)
+    .field(2, "children", Field::Repeated(children.into_iter().map(Field::Message).collect()))
+    .message(payload.0, payload.1, payload.2)
+    .bool(20, "lastMessage", last)
+}
+
+/// Returns a `BuildEventId` with one kind of id set.
+fn id(number: u32, name: &'static str, id: Message) -> Message {
+  Message::new().message(number, name, id)
+}
+
+fn progress_id(count: i32) -> Message {
+  id(2, "progress", Message::new().int32(1, "opaqueCount", count))
+}
+
+fn pattern_id(patterns: &[String]) -> Message {
+  let patterns = patterns.iter().map(|pattern| Field::String(pattern.clone())).collect();
+  id(4, "pattern", Message::new().field(1, "pattern", Field::Repeated(patterns)))
+}
+
+fn configured_id(label: &Label) -> Message {
+  id(16, "targetConfigured", Message::new().string(1, "label", label.to_string()))
+}
+
+fn completed_id(label: &Label, config: &str) -> Message {
+  id(5, "targetCompleted", Message::new()
+    .string(1, "label", label.to_string())
+    .message(3, "configuration", Message::new().string(1, "id", config)))
+}
+
+fn finished_id() -> Message {
+  id(9, "buildFinished", Message::new())
+}
+
+/// Returns a random version 4 UUID.
+fn uuid() -> String {
+  let mut bytes: [u8; 16] = random();
+  bytes[6] = (bytes[6] & 0x0f) | 0x40;
+  bytes[8] = (bytes[8] & 0x3f) | 0x80;
+  let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+  format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+fn millis(time: SystemTime) -> i64 {
+  time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64
+}
+
+/// A protobuf message as its fields in order, written both in the binary
+/// encoding and in the proto3 JSON mapping. Fields with default values are
+/// left out of both, like proto3 does.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Message(Vec<(u32, &'static str, Field)>);
+
+#[derive(Clone, Debug, PartialEq)]
+enum Field {
+  Bool(bool),
+  Int32(i32),
+  Int64(i64),
+
+  /// An enum value by number and name.
+  Enum(i32, &'static str),
+
+  String(String),
+  Bytes(Vec<u8>),
+
+  /// A `google.protobuf.Timestamp`.
+  Timestamp(SystemTime),
+
+  Message(Message),
+  Repeated(Vec<Field>),
+}
+
+impl Field {
+  fn is_default(&self) -> bool {
+    match self {
+      Field::Bool(value) => !value,
+      Field::Int32(value) | Field::Enum(value, _) => *value == 0,
+      Field::Int64(value) => *value == 0,
+      Field::String(value) => value.is_empty(),
+      Field::Bytes(value) => value.is_empty(),
+      Field::Repeated(items) => items.is_empty(),
+      Field::Timestamp(_) | Field::Message(_) => false,
+    }
+  }
+}
+
+impl Message {
+  fn new() -> Message {
+    Message::default()
+  }
+
+  fn field(mut self, number: u32, name: &'static str, field: Field) -> Message {
+    self.0.push((number, name, field));
+    self
+  }
+
+  fn bool(self, number: u32, name: &'static str, value: bool) -> Message {
+    self.field(number, name, Field::Bool(value))
+  }
+
+  fn int32(self, number: u32, name: &'static str, value: i32) -> Message {
+    self.field(number, name, Field::Int32(value))
+  }
+
+  fn int64(self, number: u32, name: &'static str, value: i64) -> Message {
+    self.field(number, name, Field::Int64(value))
+  }
+
+  fn string(self, number: u32, name: &'static str, value: impl Into<String>) -> Message {
+    self.field(number, name, Field::String(value.into()))
+  }
+
+  fn timestamp(self, number: u32, name: &'static str, value: SystemTime) -> Message {
+    self.field(number, name, Field::Timestamp(value))
+  }
+
+  fn message(self, number: u32, name: &'static str, value: Message) -> Message {
+    self.field(number, name, Field::Message(value))
+  }
+
+  fn encode(&self) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (number, _, field) in self.0.iter().filter(|(_, _, field)| !field.is_default()) {
+      encode_field(*number, field, &mut bytes);
+    }
+    bytes
+  }
+
+  fn to_json(&self) -> Json {
+    let fields: serde_json::Map<String, Json> = self.0.iter()
+      .filter(|(_, _, field)| !field.is_default())
+      .map(|(_, name, field)| (name.to_string(), field_json(field)))
+      .collect();
+    Json::Object(fields)
+  }
+}
+
+fn encode_field(number: u32, field: &Field, bytes: &mut Vec<u8>) {
+  // Wire types 0 for varints and 2 for length-delimited values.
+  let mut length_delimited = |contents: &[u8]| {
+    varint((number as u64) << 3 | 2, bytes);
+    bytes.extend(delimited(contents));
+  };
+  match field {
+    Field::Repeated(items) => {
+      for item in items {
+        encode_field(number, item, bytes);
+      }
+    },
+    Field::String(value) => length_delimited(value.as_bytes()),
+    Field::Bytes(value) => length_delimited(value),
+    Field::Message(message) => length_delimited(&message.encode()),
+    Field::Timestamp(time) => length_delimited(&timestamp(*time).encode()),
+    Field::Bool(value) => {
+      varint((number as u64) << 3, bytes);
+      varint(*value as u64, bytes);
+    },
+    Field::Int32(value) | Field::Enum(value, _) => {
+      varint((number as u64) << 3, bytes);
+      varint(*value as i64 as u64, bytes);
+    },
+    Field::Int64(value) => {
+      varint((number as u64) << 3, bytes);
+      varint(*value as u64, bytes);
+    },
+  }
+}
+
+fn field_json(field: &Field) -> Json {
+  match field {
+    Field::Bool(value) => Json::from(*value),
+    Field::Int32(value) => Json::from(*value),
+    // 64-bit integers are strings in JSON so they keep their precision.
+    Field::Int64(value) => Json::from(value.to_string()),
+    Field::Enum(_, name) => Json::from(*name),
+    Field::String(value) => Json::from(value.as_str()),
+    Field::Bytes(value) => Json::from(BASE64.encode(value)),
+    Field::Timestamp(time) => Json::from(rfc3339(*time)),
+    Field::Message(message) => message.to_json(),
+    Field::Repeated(items) => Json::Array(items.iter().map(field_json).collect()),
+  }
+}
+
+fn timestamp(time: SystemTime) -> Message {
+  let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+  Message::new()
+    .int64(1, "seconds", since_epoch.as_secs() as i64)
+    .int32(2, "nanos", since_epoch.subsec_nanos() as i32)
+}
+
+/// Formats a time like `2024-05-01T12:00:00.250Z`, with 0, 3, 6 or 9
+/// fractional digits as the proto3 JSON mapping of timestamps requires.
+fn rfc3339(time: SystemTime) -> String {
+  let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+  let seconds = since_epoch.as_secs() as i64;
+  let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+  let time_of_day = seconds.rem_euclid(86_400);
+  let nanos = since_epoch.subsec_nanos();
+  let fraction = match nanos {
+    0 => String::new(),
+    _ if nanos.is_multiple_of(1_000_000) => format!(".{:03}", nanos / 1_000_000),
+    _ if nanos.is_multiple_of(1_000) => format!(".{:06}", nanos / 1_000),
+    _ => format!(".{:09}", nanos),
+  };
+  format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+    year, month, day, time_of_day / 3600, time_of_day / 60 % 60, time_of_day % 60, fraction)
+}
+
+/// Returns the year, month and day of a number of days since 1970-01-01 in
+/// the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+  let days = days + 719_468;
+  let era = days.div_euclid(146_097);
+  let day_of_era = days.rem_euclid(146_097);
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524
+    - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let shifted_month = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+  let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+  (year_of_era + era * 400 + (month <= 2) as i64, month, day)
+}
+
+fn varint(mut value: u64, bytes: &mut Vec<u8>) {
+  while value >= 0x80 {
+    bytes.push(value as u8 | 0x80);
+    value >>= 7;
+  }
+  bytes.push(value as u8);
+}
+
+/// A field of an encoded message, a varint or length-delimited.
+#[derive(Debug, PartialEq)]
+enum Encoded<'a> {
+  Varint(u64),
+  Bytes(&'a [u8]),
+}
+
+/// Returns the first field numbered `number` of an encoded message, if it is a
+/// varint or length-delimited.
+fn decode_field(message: &[u8], number: u64) -> Option<Encoded<'_>> {
+  let mut offset = 0;
+  while offset < message.len() {
+    let key = read_varint(message, &mut offset)?;
+    let field = match key & 7 {
+      0 => Encoded::Varint(read_varint(message, &mut offset)?),
+      2 => {
+        let length = read_varint(message, &mut offset)? as usize;
+        let bytes = message.get(offset..offset.checked_add(length)?)?;
+        offset += length;
+        Encoded::Bytes(bytes)
+      },
+      1 => {
+        offset += 8;
+        continue;
+      },
+      5 => {
+        offset += 4;
+        continue;
+      },
+      _ => return None,
+    };
+    if key >> 3 == number {
+      return Some(field);
+    }
+  }
+  None
+}
+
+fn read_varint(bytes: &[u8], offset: &mut usize) -> Option<u64> {
+  let mut value = 0;
+  for shift in (0..64).step_by(7) {
+    let byte = *bytes.get(*offset)?;
+    *offset += 1;
+    value |= ((byte & 0x7f) as u64) << shift;
+    if byte & 0x80 == 0 {
+      return Some(value);
+    }
+  }
+  None
+}
+
+/// Returns bytes preceded by their length as a varint.
+fn delimited(contents: &[u8]) -> Vec<u8> {
+  let mut bytes = Vec::with_capacity(contents.len() + 10);
+  varint(contents.len() as u64, &mut bytes);
+  bytes.extend_from_slice(contents);
+  bytes
+}
+
+/// An error thrown when build events cannot be written or published.
+#[derive(Debug)]
+pub struct BuildEventError(pub String);
+
+impl Display for BuildEventError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for BuildEventError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::fs;
+  use std::net::TcpListener;
+  use std::time::Duration;
+  use assertables::assert_contains;
+  use crate::grpc::test_server::serve;
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+  use super::*;
+
+  /// Returns the `OrderedBuildEvent` of a `PublishBuildToolEventStreamRequest`.
+  fn ordered_event(request: &[u8]) -> Option<&[u8]> {
+    match decode_field(request, 4) {
+      Some(Encoded::Bytes(ordered)) => Some(ordered),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn message_encodes_as_protobuf_and_json() {
+    let message = Message::new()
+      .int32(1, "count", 150)
+      .string(2, "name", "a")
+      .bool(3, "skipped", false)
+      .field(4, "items", Field::Repeated(vec![Field::Message(Message::new().int64(1, "size", 3))]))
+      .timestamp(5, "time", UNIX_EPOCH + Duration::from_millis(1_714_564_800_250));
+
+    assert_eq!(message.encode(), [
+      0x08, 0x96, 0x01, 0x12, 0x01, b'a', 0x22, 0x02, 0x08, 0x03,
+      0x2a, 0x0b, 0x08, 0xc0, 0xdd, 0xc8, 0xb1, 0x06, 0x10, 0x80, 0xe5, 0x9a, 0x77,
+    ]);
+    assert_eq!(message.to_json(), serde_json::json!({
+      "count": 150,
+      "name": "a",
+      "items": [{ "size": "3" }],
+      "time": "2024-05-01T12:00:00.250Z",
+    }));
+  }
+
+  #[test]
+  fn stream_announces_events_and_aborts_those_never_posted() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([(Path::new("wksp"), TestContents::Directory)])?;
+    let host = FsHost::with_output_base(&dir.root.join("wksp"), &dir.root.join("out"))?;
+
+    // A build event service acknowledging each event by its sequence number.
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = format!("grpc://{}", listener.local_addr()?);
+    let service = serve(listener, 256, "0", |request| {
+      let sequence = ordered_event(request)
+        .and_then(|ordered| decode_field(ordered, 2));
+      let Some(Encoded::Varint(sequence)) = sequence else { return Vec::new() };
+      Message::new().int64(2, "sequenceNumber", sequence as i64).encode()
+    });
+
+    let json_file = dir.root.join("events.json");
+    let events = BuildEventStream::new(&host)
+      .with_json_file(&json_file)?
+      .with_backend(&address)?;
+    let (a, b) = (Label::parse("//pkg:a", "")?, Label::parse("//pkg:b", "")?);
+    events.started("build", &["//pkg:all".to_owned()]);
+    events.pattern_expanded(&["//pkg:all".to_owned()], &[a.clone(), b.clone()]);
+    events.target_configured(&a, "message rule", "fastbuild");
+    events.target_completed(&a, "fastbuild", &[], true);
+    events.finished(false)?;
+
+    let posted: Vec<Json> = fs::read_to_string(&json_file)?.lines()
+      .map(serde_json::from_str)
+      .collect::<Result<_, _>>()?;
+    let published = service.join().unwrap()?;
+    assert_eq!(published.path, PUBLISH);
+    assert_eq!(published.requests.len(), posted.len() + 1);
+    for (index, request) in published.requests.iter().enumerate() {
+      let ordered = ordered_event(request).unwrap();
+      let sequence = decode_field(ordered, 2);
+      assert_eq!(sequence, Some(Encoded::Varint(index as u64 + 1)));
+      let Some(Encoded::Bytes(event)) = decode_field(ordered, 3) else {
+        panic!("Request {} has no event.", index);
+      };
+      // Each event of the stream is published in order, then its end.
+      let payload = if index < posted.len() { 60 } else { 59 };
+      assert!(matches!(decode_field(event, payload), Some(Encoded::Bytes(_))));
+    }
+
+    // Every event but the first is announced by an earlier one.
+    let mut announced = vec![posted[0]["id"].clone()];
+    for event in &posted {
+      assert_contains!(announced, &event["id"]);
+      announced.extend(event["children"].as_array().into_iter().flatten().cloned());
+    }
+    let payload = |event: &Json| event.as_object().unwrap().keys()
+      .find(|key| !["id", "children", "lastMessage"].contains(&key.as_str()))
+      .cloned()
+      .unwrap_or_default();
+    assert_eq!(
+      posted.iter().map(payload).collect::<Vec<_>>(),
+      [
+        "started", "expanded", "configured", "progress", "namedSetOfFiles",
+        "completed", "aborted", "progress", "finished",
+      ],
+    );
+    assert_eq!(posted[6]["id"], serde_json::json!({ "targetConfigured": { "label": "//pkg:b" } }));
+    assert_eq!(posted[6]["aborted"]["reason"], "INCOMPLETE");
+    assert_eq!(posted[8]["finished"]["exitCode"]["name"], "BUILD_FAILURE");
+    assert_eq!(posted[8]["lastMessage"], true);
+    Ok(())
+  }
+
+  #[test]
+  fn stream_fails_unless_the_service_acknowledges_every_event() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([(Path::new("wksp"), TestContents::Directory)])?;
+    let host = FsHost::with_output_base(&dir.root.join("wksp"), &dir.root.join("out"))?;
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?.to_string();
+    let service = serve(listener, 65_535, "0", |_| Vec::new());
+
+    let events = BuildEventStream::new(&host).with_backend(&address)?;
+    events.started("build", &[]);
+    let err = events.finished(true).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Cannot publish build events: \
+        the build event service acknowledged 0 of 4 events.",
+    );
+    service.join().unwrap()?;
+    Ok(())
+  }
+}
@@ -0,0 +1,1335 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use crate::analysis::analyzer::Analyzer;
+use crate::analysis::artifact::Artifact;
+use crate::analysis::config::{Configuration, OUTPUT_DIR};
+use crate::analysis::strict_deps::check_strict_deps;
+use crate::analysis::toolchain::platform_constraints;
+use crate::bep::BuildEventStream;
+use crate::execution::{exec_path, Executor};
+use crate::label::EXTERNAL_DIR;
+use crate::host::host::Host;
+use crate::host::source_writer::SourceWriter;
+use crate::label::Label;
+use crate::module::{load_repositories, RepositoryOptions};
+use crate::package::PackageLoader;
+use crate::profile::Profiler;
+use crate::target_pattern::{PatternScope, TargetPattern};
+use crate::workspace::WORKSPACE_FILE;
+
+/// A requested target which was built, with the files it generated.
+pub struct BuiltTarget {
+  pub label: Label,
+  pub files: Vec<Rc<Artifact>>,
+}
+
+/// The targets of a successful build and the files of the main repository it
+/// read, which must be rebuilt from when any of them changes.
+pub struct BuildResult {
+  pub targets: Vec<BuiltTarget>,
+
+  /// Workspace-relative paths of `MODULE.razel`, the BUILD and .bzl files that
+  /// were evaluated, the directories globs listed and the source files
+  /// actions read.
+  pub inputs: Vec<PathBuf>,
+}
+
+/// Options of a build which do not affect its outputs.
+#[derive(Default)]
+pub struct BuildOptions {
+  /// Explains toolchain resolution for targets and toolchain types whose
+  /// label contains this filter.
+  pub toolchain_resolution_debug: Option<String>,
+
+  pub repositories: RepositoryOptions,
+
+  /// Records the phases of the build, the packages it loads, its action cache
+  /// lookups and the actions it executes.
+  pub profiler: Option<Arc<Profiler>>,
+
+  /// Receives the build's events after `started()`, which the caller posts
+  /// along with `finished()`.
+  pub build_events: Option<Rc<BuildEventStream>>,
+}
+
+/// Builds every target matched by the given patterns: loads their packages,
+/// analyzes the targets in the top-level configuration, checks the imports of
+/// JavaScript libraries and runs the actions generating their default outputs.
+pub fn build(host: Rc<dyn Host>, patterns: &[TargetPattern], config: Configuration, options: &BuildOptions) ->
+    Result<Vec<BuiltTarget>, Box<dyn Error>> {
+  Ok(build_with_inputs(host, patterns, config, options)?.targets)
+}
+
+/// Points the `razel-out` symlink at the root of the workspace to the outputs
+/// in the exec root, so the paths of built files resolve from the workspace.
+pub fn link_outputs(host: &dyn Host) -> Result<(), Box<dyn Error>> {
+  let target = host.output_base().join(exec_path(OUTPUT_DIR));
+  SourceWriter::new(host).symlink(Path::new(OUTPUT_DIR), &target)
+}
+
+/// Builds like `build()` and also returns the files the build read.
+pub fn build_with_inputs(host: Rc<dyn Host>, patterns: &[TargetPattern], config: Configuration,
+    options: &BuildOptions) -> Result<BuildResult, Box<dyn Error>> {
+  let profiler = options.profiler.as_deref();
+  let events = options.build_events.as_deref();
+  let phase = |name: &str| profiler.map(|profiler| profiler.span("phase", name));
+
+  let span = phase("load repositories");
+  let repositories = load_repositories(host, &options.repositories)?;
+  let packages = PackageLoader::with_repositories(repositories)
+    .with_profiler(options.profiler.clone());
+  drop(span);
+
+  // Expand patterns into the labels they refer to.
+  let span = phase("expand patterns");
+  let mut labels = Vec::new();
+  for pattern in patterns {
+    match &pattern.scope {
+      PatternScope::SingleTarget(name) => {
+        labels.push(Label { repo: pattern.repo.clone(), package: pattern.package.clone(), name: name.clone() });
+      },
+      PatternScope::Package | PatternScope::Descendants => {
+        for package in pattern.packages(packages.repositories().get(&pattern.repo)?.as_ref())? {
+          labels.extend(packages.load(&pattern.repo, &package)?.targets.values().map(|target| target.label.clone()));
+        }
+      },
+    }
+  }
+  labels.sort();
+  labels.dedup();
+  if let Some(events) = events {
+    let patterns: Vec<String> = patterns.iter().map(ToString::to_string).collect();
+    events.pattern_expanded(&patterns, &labels);
+  }
+  drop(span);
+
+  let span = phase("analyze");
+  let config = Rc::new(resolve_build_settings(&packages, config)?);
+  for platform in config.platform.iter().chain(&config.host_platform) {
+    platform_constraints(&packages, Some(platform))?;
+  }
+  let analyzer = Analyzer::new(&packages)
+    .with_toolchain_resolution_debug(options.toolchain_resolution_debug.clone());
+  let mut built = Vec::new();
+  for label in labels {
+    let _span = profiler.map(|profiler| profiler.span("analysis", label.to_string()));
+    let target = analyzer.analyze(&label, &config)?;
+    if let Some(events) = events {
+      events.target_configured(&label, &target_kind(&packages, &label)?, &config.mnemonic());
+    }
+    built.push(BuiltTarget { label, files: target.files() });
+  }
+  drop(span);
+
+  let span = phase("check strict deps");
+  check_strict_deps(packages.repositories(), &analyzer.targets())?;
+  drop(span);
+
+  let span = phase("execute");
+  let actions = analyzer.actions();
+  let mut executor = Executor::new(packages.repositories(), &actions, profiler, events)?;
+  for target in &built {
+    let generated = target.files.iter().try_for_each(|file| executor.generate(file));
+    if let Some(events) = events {
+      events.target_completed(&target.label, &config.mnemonic(), &target.files, generated.is_ok());
+    }
+    generated?;
+  }
+  drop(span);
+  let outputs: Vec<_> = built.iter().flat_map(|target| target.files.iter().cloned()).collect();
+
+  let external = format!("{}/", EXTERNAL_DIR);
+  let mut inputs = packages.loaded_files();
+  inputs.push(PathBuf::from(WORKSPACE_FILE));
+  inputs.extend(actions.iter().flat_map(|action| action.inputs.iter()).chain(&outputs)
+    .filter(|artifact| artifact.is_source && !artifact.path.starts_with(&external))
+    .map(|artifact| Path::new(&artifact.path).to_path_buf()));
+  inputs.sort();
+  inputs.dedup();
+
+  Ok(BuildResult { targets: built, inputs })
+}
+
+/// Returns the kind of a target as the build event protocol reports it, such
+/// as `ts_library rule`.
+fn target_kind(packages: &PackageLoader, label: &Label) -> Result<String, Box<dyn Error>> {
+  let package = packages.load(&label.repo, &label.package)?;
+  Ok(match package.targets.get(&label.name) {
+    Some(target) => format!("{} rule", target.rule.name()),
+    None if package.outputs.contains_key(&label.name) => "generated file".to_owned(),
+    None => "source file".to_owned(),
+  })
+}
+
+/// Validates the values of build settings set on the command line and drops
+/// those set to their default, so they do not affect output paths.
+fn resolve_build_settings(packages: &PackageLoader, mut config: Configuration) ->
+    Result<Configuration, Box<dyn Error>> {
+  let mut build_settings = BTreeMap::new();
+  for (label, value) in config.build_settings {
+    let setting = packages.build_setting(&label)?;
+    let value = setting.parse(&value).map_err(|err| BuildError(format!("Invalid value for --{}: {}", label, err)))?;
+    if value != setting.default {
+      build_settings.insert(label, value);
+    }
+  }
+  config.build_settings = build_settings;
+
+  Ok(config)
+}
+
+/// An error thrown when the build is invoked incorrectly.
+#[derive(Debug)]
+pub struct BuildError(pub String);
+
+impl Display for BuildError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for BuildError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::path::{Path, PathBuf};
+  use serde_json::{json, Value as Json};
+  use assertables::assert_contains;
+  use crate::execution::exec_path;
+  use crate::gen::generate;
+  use crate::host::fs_host::{normalize, FsHost};
+  use crate::host::test_dir::{TestContents, TestDir};
+  use super::*;
+
+  const DEFS: &str = r#"
+noop = rule(implementation = lambda ctx: None)
+
+MessageInfo = provider(fields = ["message", "file"])
+
+def _message_impl(ctx):
+    out = ctx.actions.declare_file(ctx.label.name + ".txt")
+    ctx.actions.write(output = out, content = ctx.attr.message)
+    return [MessageInfo(message = ctx.attr.message, file = out), DefaultInfo(files = depset([out]))]
+
+message = rule(implementation = _message_impl, attrs = {
+    "message": attr.string(),
+})
+
+def _greeting_impl(ctx):
+    messages = [dep[MessageInfo].message for dep in ctx.attr.deps]
+    script = ctx.actions.declare_file(ctx.label.name + ".sh")
+    ctx.actions.expand_template(
+        template = ctx.file.template,
+        output = script,
+        substitutions = {"{MESSAGES}": " ".join(messages)},
+        is_executable = True,
+    )
+    ctx.actions.run(
+        outputs = [ctx.outputs.out],
+        inputs = [dep[MessageInfo].file for dep in ctx.attr.deps],
+        executable = script,
+        arguments = [ctx.outputs.out.path],
+        mnemonic = "Greet",
+    )
+    link = ctx.actions.declare_file(ctx.label.name + ".link")
+    ctx.actions.symlink(output = link, target_file = ctx.outputs.out)
+    return [DefaultInfo(files = depset([ctx.outputs.out, link]))]
+
+greeting = rule(implementation = _greeting_impl, attrs = {
+    "deps": attr.label_list(providers = [MessageInfo]),
+    "template": attr.label(allow_single_file = [".tpl"]),
+    "out": attr.output(),
+})
+"#;
+
+  fn build_dir(dir: &TestDir, patterns: &[&str]) -> Result<Vec<BuiltTarget>, Box<dyn Error>> {
+    build_config(dir, patterns, Configuration::default())
+  }
+
+  fn build_config(dir: &TestDir, patterns: &[&str], config: Configuration) ->
+      Result<Vec<BuiltTarget>, Box<dyn Error>> {
+    let host = FsHost::with_output_base(&dir.root.join("wksp"), &dir.root.join("out"))?;
+    let patterns = patterns.iter()
+      .map(|pattern| TargetPattern::parse(pattern))
+      .collect::<Result<Vec<_>, _>>()?;
+    build(Rc::new(host), &patterns, config, &BuildOptions::default())
+  }
+
+  #[test]
+  fn build_runs_actions_of_requested_targets_and_their_deps() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("wksp/pkg/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "greeting", "message")
+
+message(name = "hello", message = "hello")
+message(name = "world", message = "world")
+greeting(name = "greet", deps = [":hello", ":world"], template = "greet.tpl", out = "greet.out")
+"#)),
+      (Path::new("wksp/pkg/greet.tpl"), TestContents::File(
+        "#!/bin/sh\necho \"{MESSAGES}\" > \"$1\"\ncat razel-out/fastbuild/bin/pkg/hello.txt >> \"$1\"\n",
+      )),
+    ])?;
+
+    let built = build_dir(&dir, &["//pkg:greet"])?;
+    assert_eq!(built.len(), 1);
+    assert_eq!(
+      built[0].files.iter().map(|file| file.path.as_str()).collect::<Vec<_>>(),
+      ["razel-out/fastbuild/bin/pkg/greet.out", "razel-out/fastbuild/bin/pkg/greet.link"],
+    );
+
+    let out = dir.root.join("out");
+    let read = |path: &str| std::fs::read_to_string(out.join(exec_path(path)));
+    assert_eq!(read("razel-out/fastbuild/bin/pkg/greet.out")?, "hello world\nhello");
+    assert_eq!(read("razel-out/fastbuild/bin/pkg/greet.link")?, "hello world\nhello");
+    assert_eq!(
+      std::fs::read_link(out.join(exec_path("razel-out/fastbuild/bin/pkg/greet.link")))?,
+      PathBuf::from("../../../../razel-out/fastbuild/bin/pkg/greet.out"),
+    );
+
+    // Built files resolve from the workspace, where builds ignore the link.
+    link_outputs(&FsHost::with_output_base(&dir.root.join("wksp"), &out)?)?;
+    let greet = dir.root.join("wksp").join(&built[0].files[0].path);
+    assert_eq!(std::fs::read_to_string(greet)?, "hello world\nhello");
+
+    // Targets which were not requested are only built if depended on.
+    let built = build_dir(&dir, &["//pkg:all"])?;
+    assert_eq!(
+      built.iter().map(|target| target.label.to_string()).collect::<Vec<_>>(),
+      ["//pkg:greet", "//pkg:hello", "//pkg:world"],
+    );
+    assert_eq!(read("razel-out/fastbuild/bin/pkg/world.txt")?, "world");
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_skips_actions_whose_inputs_did_not_change() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("wksp/pkg/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "greeting", "message")
+
+message(name = "hello", message = "hello")
+greeting(name = "greet", deps = [":hello"], template = "greet.tpl", out = "greet.out")
+"#)),
+    ])?;
+    let runs = dir.root.join("runs.log");
+    let template = |greeting: &str| format!(
+      "#!/bin/sh\necho run >> \"{}\"\necho {} > \"$1\"\n\
+      cat razel-out/fastbuild/bin/pkg/hello.txt >> \"$1\"\n",
+      runs.to_str().unwrap(),
+      greeting,
+    );
+    let wksp = dir.root.join("wksp");
+    std::fs::write(wksp.join("pkg/greet.tpl"), template("hi"))?;
+    let out = dir.root.join("out").join(exec_path("razel-out/fastbuild/bin/pkg/greet.out"));
+    let runs_after_build = || -> Result<usize, Box<dyn Error>> {
+      build_dir(&dir, &["//pkg:greet"])?;
+      Ok(std::fs::read_to_string(&runs)?.lines().count())
+    };
+
+    assert_eq!(runs_after_build()?, 1);
+    assert_eq!(runs_after_build()?, 1);
+    assert_eq!(std::fs::read_to_string(&out)?, "hi\nhello");
+
+    // A changed generated input reruns the action.
+    let build_file = std::fs::read_to_string(wksp.join("pkg/BUILD"))?;
+    std::fs::write(
+      wksp.join("pkg/BUILD"),
+      build_file.replace("message = \"hello\"", "message = \"there\""),
+    )?;
+    assert_eq!(runs_after_build()?, 2);
+    assert_eq!(std::fs::read_to_string(&out)?, "hi\nthere");
+
+    // So does a changed source input.
+    std::fs::write(wksp.join("pkg/greet.tpl"), template("hey"))?;
+    assert_eq!(runs_after_build()?, 3);
+    assert_eq!(std::fs::read_to_string(&out)?, "hey\nthere");
+
+    // And a missing output.
+    std::fs::remove_file(&out)?;
+    assert_eq!(runs_after_build()?, 4);
+    assert_eq!(runs_after_build()?, 4);
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_posts_events_of_targets_and_actions() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("wksp/pkg/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "greeting", "message")
+
+message(name = "hello", message = "hello")
+greeting(name = "greet", deps = [":hello"], template = "greet.tpl", out = "greet.out")
+"#)),
+      (Path::new("wksp/pkg/greet.tpl"), TestContents::File("#!/bin/sh\nexit 1\n")),
+    ])?;
+    let host = FsHost::with_output_base(&dir.root.join("wksp"), &dir.root.join("out"))?;
+    let json_file = dir.root.join("events.json");
+    let events = Rc::new(BuildEventStream::new(&host).with_json_file(&json_file)?);
+    let patterns = [TargetPattern::parse("//pkg:greet")?, TargetPattern::parse("//pkg:hello")?];
+    events.started("build", &patterns.iter().map(ToString::to_string).collect::<Vec<_>>());
+    let options = BuildOptions { build_events: Some(events.clone()), ..BuildOptions::default() };
+    let result = build(Rc::new(host), &patterns, Configuration::default(), &options);
+    events.finished(result.is_ok())?;
+    assert!(result.is_err());
+
+    let posted: Vec<Json> = std::fs::read_to_string(&json_file)?.lines()
+      .map(serde_json::from_str)
+      .collect::<Result<_, _>>()?;
+    let find = |payload: &str, field: &str, value: &str| posted.iter()
+      .find(|event| event["id"][payload][field] == value)
+      .cloned()
+      .unwrap_or_default();
+    let configured = find("targetConfigured", "label", "//pkg:greet");
+    assert_eq!(configured["configured"]["targetKind"], "greeting rule");
+    let hello = find("actionCompleted", "primaryOutput", "razel-out/fastbuild/bin/pkg/hello.txt");
+    assert_eq!(hello["action"]["type"], "FileWrite");
+    assert_eq!(hello["action"]["success"], true);
+    let greet = find("actionCompleted", "primaryOutput", "razel-out/fastbuild/bin/pkg/greet.out");
+    assert_eq!(greet["action"]["type"], "Greet");
+    assert_eq!(greet["action"]["success"], Json::Null);
+    assert_eq!(find("targetCompleted", "label", "//pkg:greet")["completed"], json!({}));
+    assert_eq!(find("targetCompleted", "label", "//pkg:hello")["aborted"]["reason"], "INCOMPLETE");
+    assert_eq!(posted.last().unwrap()["finished"]["overallSuccess"], Json::Null);
+    Ok(())
+  }
+
+  #[test]
+  fn build_errors_on_invalid_dependencies() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("wksp/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "greeting", "noop")
+
+noop(name = "noop")
+greeting(name = "cycle_a", deps = [":cycle_b"])
+greeting(name = "cycle_b", deps = [":cycle_a"])
+greeting(name = "missing_provider", deps = [":noop"])
+greeting(name = "bad_template", template = "BUILD")
+greeting(name = "not_visible", deps = ["//lib:message"])
+"#)),
+      (Path::new("wksp/lib/BUILD"), TestContents::File(
+        "load(\"//:defs.bzl\", \"message\")\nmessage(name = \"message\")",
+      )),
+    ])?;
+
+    assert_eq!(
+      build_dir(&dir, &["//:cycle_a"]).err().unwrap().to_string(),
+      "Dependency cycle: //:cycle_a -> //:cycle_b -> //:cycle_a",
+    );
+    assert_eq!(
+      build_dir(&dir, &["//:missing_provider"]).err().unwrap().to_string(),
+      "BUILD:7:9: //:missing_provider: attribute `deps`: //:noop does not provide MessageInfo.",
+    );
+    assert_contains!(
+      build_dir(&dir, &["//:bad_template"]).err().unwrap().to_string(),
+      "source file //:BUILD is not allowed here.",
+    );
+    assert_contains!(
+      build_dir(&dir, &["//:not_visible"]).err().unwrap().to_string(),
+      "BUILD:9:9: //:not_visible: attribute `deps`: target //lib:message is not visible from target //:not_visible.",
+    );
+    assert_contains!(
+      build_dir(&dir, &["//:nope"]).err().unwrap().to_string(),
+      "No such target `//:nope`",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_resolves_select_in_configuration() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("wksp/conditions/BUILD"), TestContents::File(r#"
+config_setting(name = "opt", values = {"compilation_mode": "opt"})
+config_setting(name = "opt_prod", values = {"compilation_mode": "opt"}, define_values = {"env": "prod"})
+"#)),
+      (Path::new("wksp/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "message")
+
+message(name = "mode", message = select({
+    "//conditions:opt": "opt",
+    "//conditions:opt_prod": "opt prod",
+    "//conditions:default": "default",
+}) + select({"//conditions:default": " in " + package_name() + "/"}))
+message(name = "strict", message = select(
+    {"//conditions:opt": "opt"},
+    no_match_error = "Only builds with -c opt.",
+))
+"#)),
+    ])?;
+    let out = dir.root.join("out");
+
+    let built = build_dir(&dir, &["//:mode"])?;
+    assert_eq!(built[0].files[0].path, "razel-out/fastbuild/bin/mode.txt");
+    assert_eq!(std::fs::read_to_string(out.join(exec_path(&built[0].files[0].path)))?, "default in /");
+
+    let mut opt = Configuration::default();
+    opt.options.insert("compilation_mode".to_owned(), "opt".to_owned());
+    let built = build_config(&dir, &["//:mode", "//:strict"], opt.clone())?;
+    assert_eq!(built[0].files[0].path, "razel-out/opt/bin/mode.txt");
+    assert_eq!(std::fs::read_to_string(out.join(exec_path(&built[0].files[0].path)))?, "opt in /");
+
+    let mut prod = opt.clone();
+    prod.defines.insert("env".to_owned(), "prod".to_owned());
+    let built = build_config(&dir, &["//:mode"], prod.clone())?;
+    assert_eq!(built[0].files[0].path, format!("{}/mode.txt", prod.bin_dir()));
+    assert_eq!(std::fs::read_to_string(out.join(exec_path(&built[0].files[0].path)))?, "opt prod in /");
+
+    assert_eq!(
+      build_dir(&dir, &["//:strict"]).err().unwrap().to_string(),
+      "BUILD:9:8: //:strict: attribute `message`: Only builds with -c opt.",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_falls_back_to_default_select_condition() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("wksp/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "message")
+
+config_setting(name = "opt", values = {"compilation_mode": "opt"})
+config_setting(name = "dbg", values = {"compilation_mode": "dbg"})
+
+message(name = "mode", message = select({
+    ":opt": "opt",
+    "//conditions:default": "default",
+    ":dbg": "dbg",
+}))
+"#)),
+    ])?;
+    let out = dir.root.join("out");
+
+    let built = build_dir(&dir, &["//:mode"])?;
+    assert_eq!(std::fs::read_to_string(out.join(exec_path(&built[0].files[0].path)))?, "default");
+
+    let mut dbg = Configuration::default();
+    dbg.options.insert("compilation_mode".to_owned(), "dbg".to_owned());
+    let built = build_config(&dir, &["//:mode"], dbg)?;
+    assert_eq!(std::fs::read_to_string(out.join(exec_path(&built[0].files[0].path)))?, "dbg");
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_errors_when_no_select_condition_matches() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("wksp/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "message")
+
+config_setting(name = "opt", values = {"compilation_mode": "opt"})
+config_setting(name = "dbg", values = {"compilation_mode": "dbg"})
+
+message(name = "mode", message = select({":opt": "opt", ":dbg": "dbg"}))
+"#)),
+    ])?;
+
+    assert_eq!(
+      build_dir(&dir, &["//:mode"]).err().unwrap().to_string(),
+      "BUILD:7:8: //:mode: attribute `message`: No condition of select() matches the \
+        configuration. Conditions checked: :opt, :dbg.",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_errors_on_ambiguous_select_conditions() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("wksp/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "message")
+
+config_setting(name = "opt", values = {"compilation_mode": "opt"})
+config_setting(name = "prod", define_values = {"env": "prod"})
+
+message(name = "mode", message = select({
+    ":opt": "opt",
+    ":prod": "prod",
+    "//conditions:default": "default",
+}))
+"#)),
+    ])?;
+
+    let mut opt_prod = Configuration::default();
+    opt_prod.options.insert("compilation_mode".to_owned(), "opt".to_owned());
+    opt_prod.defines.insert("env".to_owned(), "prod".to_owned());
+    assert_eq!(
+      build_config(&dir, &["//:mode"], opt_prod).err().unwrap().to_string(),
+      "BUILD:7:8: //:mode: attribute `message`: select() conditions `:opt` and `:prod` both \
+        match, but neither refines the other.",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_errors_on_select_condition_which_is_not_a_config_setting() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("wksp/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "message")
+
+message(name = "other", message = "other")
+message(name = "mode", message = select({":other": "other"}))
+"#)),
+    ])?;
+
+    assert_eq!(
+      build_dir(&dir, &["//:mode"]).err().unwrap().to_string(),
+      "BUILD:5:8: //:mode: attribute `message`: select() condition `:other` is not a \
+        config_setting.",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_analyzes_dependencies_in_transitioned_configurations() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(r#"
+load(":messages.bzl", "message")
+
+def _to_mode(settings, attr):
+    return {"//command_line_option:compilation_mode": attr.mode}
+
+to_mode = transition(
+    implementation = _to_mode,
+    inputs = [],
+    outputs = ["//command_line_option:compilation_mode"],
+)
+
+def _server(settings, attr):
+    return {"//command_line_option:define": settings["//command_line_option:define"] + ["target=server"]}
+
+server = transition(
+    implementation = _server,
+    inputs = ["//command_line_option:define"],
+    outputs = ["//command_line_option:define"],
+)
+
+def _bundle_impl(ctx):
+    out = ctx.actions.declare_file(ctx.label.name + ".txt")
+    ctx.actions.run(
+        outputs = [out],
+        inputs = ctx.files.deps,
+        executable = ctx.executable.tool,
+        arguments = [out.path] + [file.path for file in ctx.files.deps],
+    )
+    return [DefaultInfo(files = depset([out]), executable = out)]
+
+bundle = rule(implementation = _bundle_impl, attrs = {
+    "deps": attr.label_list(allow_files = True, cfg = to_mode),
+    "mode": attr.string(),
+    "tool": attr.label(allow_files = True, executable = True, cfg = "exec"),
+})
+
+server_message = rule(implementation = message.implementation, attrs = message.attrs, cfg = server)
+"#)),
+      (Path::new("wksp/messages.bzl"), TestContents::File(r#"
+def _message_impl(ctx):
+    out = ctx.actions.declare_file(ctx.label.name + ".txt")
+    ctx.actions.write(output = out, content = ctx.var["COMPILATION_MODE"] + " " + ctx.var.get("target", ""))
+    return [DefaultInfo(files = depset([out]))]
+
+message = struct(implementation = _message_impl, attrs = {})
+"#)),
+      (Path::new("wksp/BUILD"), TestContents::File(r#"
+load(":defs.bzl", "bundle", "server_message")
+
+server_message(name = "server")
+
+bundle(name = "tool", mode = "dbg", deps = [":concat.sh"], tool = "copy.sh")
+bundle(name = "bundle", mode = "opt", deps = [":server"], tool = ":tool")
+"#)),
+      (Path::new("wksp/copy.sh"), TestContents::File("#!/bin/sh
+out=\"$1\"\nshift\ncp \"$1\" \"$out\"\nchmod +x \"$out\"\n")),
+      (Path::new("wksp/concat.sh"), TestContents::File("#!/bin/sh
+out=\"$1\"\nshift\ncat \"$@\" > \"$out\"\n")),
+    ])?;
+    std::fs::set_permissions(dir.root.join("wksp/copy.sh"), std::os::unix::fs::PermissionsExt::from_mode(0o755))?;
+    std::fs::set_permissions(dir.root.join("wksp/concat.sh"), std::os::unix::fs::PermissionsExt::from_mode(0o755))?;
+
+    let built = build_dir(&dir, &["//:bundle"])?;
+    assert_eq!(built[0].files[0].path, "razel-out/fastbuild/bin/bundle.txt");
+
+    let out = dir.root.join("out");
+    assert_eq!(std::fs::read_to_string(out.join(exec_path("razel-out/fastbuild/bin/bundle.txt")))?, "opt server");
+    assert!(out.join(exec_path("razel-out/opt-exec/bin/tool.txt")).exists());
+    let server_dirs: Vec<_> = std::fs::read_dir(out.join(exec_path("razel-out")))?
+      .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+      .filter(|name| name.starts_with("opt-") && name != "opt-exec")
+      .collect();
+    assert_eq!(server_dirs.len(), 1);
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_reads_build_settings_from_the_configuration() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(r#"
+def _settings_impl(ctx):
+    out = ctx.actions.declare_file(ctx.label.name + ".txt")
+    minify = ctx.attr._minify[BuildSettingInfo].value
+    ctx.actions.write(output = out, content = "minify={} mode={} {}".format(minify, ctx.attr.mode, ctx.attr.when_modern))
+    return [DefaultInfo(files = depset([out]))]
+
+settings = rule(implementation = _settings_impl, attrs = {
+    "mode": attr.string(),
+    "when_modern": attr.string(),
+    "_minify": attr.label(default = "//build:minify"),
+})
+"#)),
+      (Path::new("wksp/build/BUILD"), TestContents::File(r#"
+bool_flag(name = "minify", build_setting_default = True)
+string_flag(name = "target", build_setting_default = "es2015", values = ["es2015", "es2022"])
+
+config_setting(name = "modern", flag_values = {":target": "es2022"})
+"#)),
+      (Path::new("wksp/BUILD"), TestContents::File(r#"
+load(":defs.bzl", "settings")
+
+settings(
+    name = "settings",
+    mode = select({"//build:modern": "modern", "//conditions:default": "legacy"}),
+    when_modern = select({"//build:modern": "es2022!", "//conditions:default": ""}),
+)
+"#)),
+    ])?;
+    let out = dir.root.join("out");
+    let build_with = |settings: &[(&str, &str)]| {
+      let mut config = Configuration::default();
+      for (label, value) in settings {
+        config.build_settings.insert(Label::parse(label, "")?, value.to_string());
+      }
+      let built = build_config(&dir, &["//:settings"], config)?;
+      let path = built[0].files[0].path.clone();
+      Ok::<_, Box<dyn Error>>((path.clone(), std::fs::read_to_string(out.join(exec_path(&path)))?))
+    };
+
+    // Settings set to their default do not change the configuration.
+    assert_eq!(
+      build_with(&[("//build:minify", "yes"), ("//build:target", "es2015")])?,
+      ("razel-out/fastbuild/bin/settings.txt".to_owned(), "minify=True mode=legacy ".to_owned()),
+    );
+
+    let (path, content) = build_with(&[("//build:minify", "false"), ("//build:target", "es2022")])?;
+    assert_ne!(path, "razel-out/fastbuild/bin/settings.txt");
+    assert_eq!(content, "minify=False mode=modern es2022!");
+
+    assert_eq!(
+      build_with(&[("//build:target", "es5")]).err().unwrap().to_string(),
+      "Invalid value for --//build:target: `es5` is not one of: es2015, es2022.",
+    );
+    assert_eq!(
+      build_with(&[("//build:nope", "1")]).err().unwrap().to_string(),
+      "`//build:nope` is not a build setting.",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_resolves_toolchains_for_the_target_platform() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/MODULE.razel"), TestContents::File("register_toolchains(\"//toolchains:all\")")),
+      (Path::new("wksp/defs.bzl"), TestContents::File(r#"
+def _node_toolchain_impl(ctx):
+    return [platform_common.ToolchainInfo(version = ctx.attr.version)]
+
+node_toolchain = rule(implementation = _node_toolchain_impl, attrs = {"version": attr.string()})
+
+def _app_impl(ctx):
+    out = ctx.actions.declare_file(ctx.label.name + ".txt")
+    node = ctx.toolchains["//toolchains:node_type"]
+    ctx.actions.write(output = out, content = "node {} for {}".format(node.version, ctx.attr.os))
+    return [DefaultInfo(files = depset([out]))]
+
+app = rule(implementation = _app_impl, attrs = {"os": attr.string()}, toolchains = ["//toolchains:node_type"])
+"#)),
+      (Path::new("wksp/platforms/BUILD"), TestContents::File(r#"
+constraint_setting(name = "os", default_constraint_value = ":linux")
+constraint_value(name = "linux", constraint_setting = ":os")
+constraint_value(name = "windows", constraint_setting = ":os")
+
+platform(name = "windows_x64", constraint_values = [":windows"])
+
+config_setting(name = "is_windows", constraint_values = [":windows"])
+"#)),
+      (Path::new("wksp/toolchains/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "node_toolchain")
+
+toolchain_type(name = "node_type")
+
+node_toolchain(name = "node_linux_impl", version = "20-linux")
+node_toolchain(name = "node_windows_impl", version = "20-windows")
+
+toolchain(
+    name = "node_linux",
+    toolchain_type = ":node_type",
+    toolchain = ":node_linux_impl",
+    target_compatible_with = ["//platforms:linux"],
+)
+toolchain(
+    name = "node_windows",
+    toolchain_type = ":node_type",
+    toolchain = ":node_windows_impl",
+    target_compatible_with = ["//platforms:windows"],
+)
+"#)),
+      (Path::new("wksp/BUILD"), TestContents::File(r#"
+load(":defs.bzl", "app")
+
+app(name = "app", os = select({"//platforms:is_windows": "windows", "//conditions:default": "linux"}))
+"#)),
+    ])?;
+    let out = dir.root.join("out");
+    let build_for = |platform: Option<&str>| {
+      let platform = platform.map(|platform| Label::parse(platform, "").unwrap());
+      let config = Configuration { platform, ..Configuration::default() };
+      let built = build_config(&dir, &["//:app"], config)?;
+      Ok::<_, Box<dyn Error>>(std::fs::read_to_string(out.join(exec_path(&built[0].files[0].path)))?)
+    };
+
+    assert_eq!(build_for(None)?, "node 20-linux for linux");
+    assert_eq!(build_for(Some("//platforms:windows_x64"))?, "node 20-windows for windows");
+    assert_eq!(
+      build_for(Some("//platforms:windows")).err().unwrap().to_string(),
+      "`//platforms:windows` is not a platform.",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_uses_targets_of_resolved_modules() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/MODULE.razel"), TestContents::File("razel_dep(name = \"greetings\", version = \"1.0\")")),
+      (Path::new("wksp/BUILD"), TestContents::File(r#"
+load("@greetings//:defs.bzl", "greeting")
+
+greeting(name = "greet", deps = ["@greetings//:hello"], template = "@greetings//:greet.tpl", out = "greet.out")
+"#)),
+      (Path::new("registry/modules/greetings/1.0/MODULE.razel"), TestContents::File(
+        "module(name = \"greetings\", version = \"1.0\")",
+      )),
+      (Path::new("registry/modules/greetings/1.0/source.json"), TestContents::File(
+        r#"{"type": "local_path", "path": "sources/greetings"}"#,
+      )),
+      (Path::new("registry/sources/greetings/defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("registry/sources/greetings/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "message")
+
+message(name = "hello", message = "hello", visibility = ["//visibility:public"])
+"#)),
+      (Path::new("registry/sources/greetings/greet.tpl"), TestContents::File(
+        "#!/bin/sh\necho \"{MESSAGES}\" > \"$1\"\ncat razel-out/fastbuild/bin/external/greetings/hello.txt >> \"$1\"\n",
+      )),
+    ])?;
+    let host: Rc<dyn Host> = Rc::new(FsHost::with_output_base(&dir.root.join("wksp"), &dir.root.join("out"))?);
+    let options = BuildOptions {
+      repositories: RepositoryOptions { registry: Some(dir.root.join("registry")), ..RepositoryOptions::default() },
+      ..BuildOptions::default()
+    };
+
+    let built = build(host.clone(), &[TargetPattern::parse("//:greet")?], Configuration::default(), &options)?;
+    let out = dir.root.join("out");
+    assert_eq!(std::fs::read_to_string(out.join(exec_path(&built[0].files[0].path)))?, "hello\nhello");
+    assert!(dir.root.join("wksp/MODULE.razel.lock").is_file());
+
+    let built = build(host.clone(), &[TargetPattern::parse("@greetings//...")?], Configuration::default(), &options)?;
+    assert_eq!(built[0].label.to_string(), "@greetings//:hello");
+    assert_eq!(built[0].files[0].path, "razel-out/fastbuild/bin/external/greetings/hello.txt");
+
+    assert_eq!(
+      build(host, &[TargetPattern::parse("//:greet")?], Configuration::default(), &BuildOptions::default())
+        .err().unwrap().to_string(),
+      "`MODULE.razel` has dependencies, pass --registry=<dir> to resolve them.",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_uses_packages_of_npm_lockfile() -> Result<(), Box<dyn Error>> {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use flate2::{write::GzEncoder, Compression};
+    use sha2::{Digest, Sha512};
+
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let mut header = tar::Header::new_gnu();
+    header.set_size(9);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, "package/index.js", "hello npm".as_bytes())?;
+    let tarball = builder.into_inner()?.finish()?;
+    let lockfile = format!(
+      r#"{{"lockfileVersion": 3, "packages": {{
+        "": {{"dependencies": {{"greet": "^1"}}}},
+        "node_modules/greet": {{"version": "1.0.0", "integrity": "sha512-{}"}}
+      }}}}"#,
+      BASE64.encode(Sha512::digest(&tarball)),
+    );
+
+    let dir = TestDir::from([
+      (Path::new("wksp/MODULE.razel"), TestContents::File(
+        "npm_translate_lock(name = \"npm\", lockfile = \"//:package-lock.json\", mirror = \"../mirror\")",
+      )),
+      (Path::new("wksp/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "node")
+
+node(name = "hello", dep = "@npm//:greet")
+"#)),
+      (Path::new("wksp/defs.bzl"), TestContents::File(r#"
+load("@npm//:defs.bzl", "NpmPackageInfo")
+
+def _node_impl(ctx):
+    out = ctx.actions.declare_file(ctx.label.name + ".txt")
+    info = ctx.attr.dep[NpmPackageInfo]
+    ctx.actions.run(
+        outputs = [out],
+        inputs = info.files,
+        executable = "/bin/sh",
+        arguments = ["-c", "cat " + info.node_modules + "/greet/index.js > " + out.path],
+    )
+    return [DefaultInfo(files = depset([out]))]
+
+node = rule(implementation = _node_impl, attrs = {"dep": attr.label()})
+"#)),
+      (Path::new("wksp/package-lock.json"), TestContents::File(&lockfile)),
+    ])?;
+    std::fs::create_dir_all(dir.root.join("mirror/greet/-"))?;
+    std::fs::write(dir.root.join("mirror/greet/-/greet-1.0.0.tgz"), &tarball)?;
+    let host: Rc<dyn Host> = Rc::new(FsHost::with_output_base(&dir.root.join("wksp"), &dir.root.join("out"))?);
+
+    let built = build(host, &[TargetPattern::parse("//:hello")?], Configuration::default(), &BuildOptions::default())?;
+    assert_eq!(std::fs::read_to_string(dir.root.join("out").join(exec_path(&built[0].files[0].path)))?, "hello npm");
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_compiles_typescript_against_declarations_of_deps() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/MODULE.razel"), TestContents::File("register_toolchains(\"//tools:all\")")),
+      (Path::new("wksp/BUILD"), TestContents::File("")),
+      (Path::new("wksp/tsconfig.json"), TestContents::File(
+        "{\"compilerOptions\": {\"strict\": true}}",
+      )),
+      (Path::new("wksp/tools/BUILD"), TestContents::File(r#"
+load("@razel//js:defs.bzl", "tsc_toolchain")
+
+tsc_toolchain(name = "tsc_impl", tsc = "tsc.sh")
+
+toolchain(name = "tsc", toolchain_type = "@razel//js:tsc_toolchain_type", toolchain = ":tsc_impl")
+"#)),
+      // Emits every file of the generated config which is not a declaration,
+      // failing like tsc for files outside of `rootDir`.
+      (Path::new("wksp/tools/tsc.sh"), TestContents::File(r#"#!/bin/sh
+config="$2"
+dir=$(dirname "$config")
+root=$(sed 's/.*"rootDir":"\([^"]*\)".*/\1/' "$config")
+out=$(sed 's/.*"outDir":"\([^"]*\)".*/\1/' "$config")
+for file in $(sed 's/.*"files":\[\([^]]*\)\].*/\1/' "$config" | tr -d '"' | tr ',' ' '); do
+  case "$file" in
+    *.d.ts) continue;;
+    "$root"/*) ;;
+    *) echo "$file is not under rootDir" >&2; exit 1;;
+  esac
+  stem="$dir/$out/${file#$root/}"
+  stem="${stem%.*}"
+  echo "compiled" > "$stem.js"
+  echo "{}" > "$stem.js.map"
+  echo "export {};" > "$stem.d.ts"
+done
+"#)),
+      (Path::new("wksp/lib/BUILD"), TestContents::File(
+        "ts_library(name = \"lib\", srcs = [\"util.ts\", \"env.d.ts\"], \
+        visibility = [\"//visibility:public\"])",
+      )),
+      (Path::new("wksp/lib/util.ts"), TestContents::File("export const one = 1;")),
+      (Path::new("wksp/lib/env.d.ts"), TestContents::File("declare const env: string;")),
+      (Path::new("wksp/app/BUILD"), TestContents::File(
+        "ts_library(name = \"app\", srcs = [\"main.ts\", \"ui/view.tsx\"], \
+        deps = [\"//lib:lib\"], tsconfig = \"//:tsconfig.json\")",
+      )),
+      (Path::new("wksp/app/main.ts"), TestContents::File("import { one } from '../lib/util';")),
+      (Path::new("wksp/app/ui/view.tsx"), TestContents::File("export const view = <div/>;")),
+    ])?;
+    {
+      use std::os::unix::fs::PermissionsExt;
+      std::fs::set_permissions(
+        dir.root.join("wksp/tools/tsc.sh"),
+        std::fs::Permissions::from_mode(0o755),
+      )?;
+    }
+    let host: Rc<dyn Host> = Rc::new(FsHost::with_output_base(
+      &dir.root.join("wksp"),
+      &dir.root.join("out"),
+    )?);
+
+    let built = build(
+      host,
+      &[TargetPattern::parse("//app:app")?],
+      Configuration::default(),
+      &BuildOptions::default(),
+    )?;
+    assert_eq!(built[0].files.iter().map(|file| file.path.as_str()).collect::<Vec<_>>(), [
+      "razel-out/fastbuild/bin/app/main.js",
+      "razel-out/fastbuild/bin/app/main.js.map",
+      "razel-out/fastbuild/bin/app/ui/view.js",
+      "razel-out/fastbuild/bin/app/ui/view.js.map",
+      "razel-out/fastbuild/bin/app/main.d.ts",
+      "razel-out/fastbuild/bin/app/ui/view.d.ts",
+    ]);
+    let bin = dir.root.join("out").join(exec_path("razel-out/fastbuild/bin"));
+    assert_eq!(std::fs::read_to_string(bin.join("app/ui/view.js"))?, "compiled\n");
+    assert_eq!(std::fs::read_to_string(bin.join("lib/util.d.ts"))?, "export {};\n");
+
+    // Only the declarations of `//lib` are compiled against.
+    let tsconfig = std::fs::read_to_string(bin.join("app/app.tsconfig.json"))?;
+    assert_contains!(tsconfig, r#""extends":"../../../../tsconfig.json""#);
+    assert_contains!(
+      tsconfig,
+      concat!(
+        r#""files":["../../../../razel-out/fastbuild/bin/app/_app_tsc/app/main.ts","#,
+        r#""../../../../razel-out/fastbuild/bin/app/_app_tsc/app/ui/view.tsx","#,
+        r#""../../../../razel-out/fastbuild/bin/lib/util.d.ts","#,
+        r#""../../../../razel-out/fastbuild/bin/lib/env.d.ts"]"#,
+      ),
+    );
+
+    // Resolve `../lib/util` from `main.ts` the way tsc does with the config:
+    // relative to the importing file, then in every root dir, preferring
+    // `.ts` over `.d.ts`. Finding the source of `//lib` would make tsc
+    // compile it again, over the outputs of `//lib`.
+    let config: Json = serde_json::from_str(&tsconfig)?;
+    let options = &config["compilerOptions"];
+    assert_eq!(options["preserveSymlinks"], true);
+    let config_dir = bin.join("app");
+    let root_dirs = options["rootDirs"].as_array().unwrap().iter()
+      .map(|dir| normalize(&config_dir.join(dir.as_str().unwrap())))
+      .collect::<Result<Vec<_>, _>>()?;
+    let importer = config_dir.join(config["files"][0].as_str().unwrap());
+    let candidate = normalize(&importer.parent().unwrap().join("../lib/util"))?;
+    let suffix = root_dirs.iter()
+      .filter_map(|dir| candidate.strip_prefix(dir).ok())
+      .min_by_key(|suffix| suffix.components().count())
+      .unwrap();
+    let resolved = root_dirs.iter()
+      .flat_map(|dir| [".ts", ".tsx", ".d.ts"].map(|ext| {
+        PathBuf::from(format!("{}{}", dir.join(suffix).display(), ext))
+      }))
+      .find(|path| path.exists());
+    assert_eq!(resolved, Some(normalize(&bin.join("lib/util.d.ts"))?));
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_with_inputs_returns_files_the_build_read() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("wksp/pkg/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "greeting", "message")
+
+message(name = "hello", message = "hello")
+greeting(name = "greet", deps = [":hello"], template = glob(["*.tpl"])[0], out = "greet.out")
+"#)),
+      (Path::new("wksp/pkg/greet.tpl"), TestContents::File("#!/bin/sh
+echo \"{MESSAGES}\" > \"$1\"\n")),
+      (Path::new("wksp/other/BUILD"), TestContents::File("")),
+    ])?;
+    let host = FsHost::with_output_base(&dir.root.join("wksp"), &dir.root.join("out"))?;
+
+    let result = build_with_inputs(
+      Rc::new(host),
+      &[TargetPattern::parse("//pkg:greet")?],
+      Configuration::default(),
+      &BuildOptions::default(),
+    )?;
+    assert_eq!(result.inputs, [
+      PathBuf::from("MODULE.razel"),
+      PathBuf::from("defs.bzl"),
+      PathBuf::from("pkg"),
+      PathBuf::from("pkg/BUILD"),
+      PathBuf::from("pkg/greet.tpl"),
+    ]);
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_errors_on_imports_of_modules_from_transitive_deps() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/npm.bzl"), TestContents::File(r#"
+load("@razel//js:defs.bzl", "JsInfo")
+
+def _fake_npm_impl(ctx):
+    return [JsInfo(
+        sources = depset(),
+        node_modules = depset(),
+        direct_sources = [],
+        package_name = ctx.attr.package_name,
+        deps = [],
+        strict_deps = False,
+    )]
+
+fake_npm = rule(implementation = _fake_npm_impl, attrs = {"package_name": attr.string()})
+"#)),
+      (Path::new("wksp/npm/BUILD"), TestContents::File(r#"
+load("//:npm.bzl", "fake_npm")
+
+fake_npm(name = "left-pad", package_name = "left-pad", visibility = ["//visibility:public"])
+"#)),
+      (Path::new("wksp/lib/BUILD"), TestContents::File(
+        "js_library(name = \"lib\", srcs = [\"util.js\"], deps = [\"//npm:left-pad\"], \
+        visibility = [\"//visibility:public\"])",
+      )),
+      (Path::new("wksp/lib/util.js"), TestContents::File("import pad from \"left-pad\";")),
+      (Path::new("wksp/mid/BUILD"), TestContents::File(
+        "js_library(name = \"mid\", srcs = [\"index.js\"], deps = [\"//lib:lib\"], \
+        visibility = [\"//visibility:public\"])",
+      )),
+      (Path::new("wksp/mid/index.js"), TestContents::File("export * from \"../lib/util.js\";")),
+      (Path::new("wksp/app/BUILD"), TestContents::File(
+        "js_library(name = \"app\", srcs = [\"main.js\"], deps = [\"//mid:mid\"])",
+      )),
+      (Path::new("wksp/app/main.js"), TestContents::File(r#"import { mid } from "../mid";
+import { pad } from "left-pad";
+import fs from "node:fs";
+import "./style.css";
+const util = require("../lib/util.js");
+import { x } from "./missing";
+"#)),
+    ])?;
+
+    let err = build_dir(&dir, &["//app:app"]).err().unwrap();
+    assert_eq!(err.to_string(), r#"//app:app imports modules its direct dependencies do not provide:
+  app/main.js:2 imports "left-pad" from //npm:left-pad
+  app/main.js:5 imports "../lib/util.js" from //lib:lib
+  app/main.js:6 imports "./missing", which no dependency provides
+Add to the `deps` of //app:app: "//lib:lib", "//npm:left-pad""#);
+
+    std::fs::write(
+      dir.root.join("wksp/app/main.js"),
+      "import { mid } from \"../mid\";\nimport \"./style.css\";\n",
+    )?;
+    build_dir(&dir, &["//app:app"])?;
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_checks_generated_deps_of_tsconfig_path_aliases() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/tsconfig.json"), TestContents::File(
+        r#"{"compilerOptions": {"baseUrl": ".", "paths": {"@lib/*": ["lib/*"]}}}"#,
+      )),
+      (Path::new("wksp/app/main.js"), TestContents::File("import { util } from \"@lib/util\";")),
+      (Path::new("wksp/lib/util.js"), TestContents::File("export const util = 1;")),
+    ])?;
+    let host = FsHost::with_output_base(&dir.root.join("wksp"), &dir.root.join("out"))?;
+    generate(&host)?;
+    build_dir(&dir, &["//app:app"])?;
+
+    std::fs::write(
+      dir.root.join("wksp/app/BUILD.razel"),
+      "js_library(name = \"app\", srcs = [\"main.js\"])",
+    )?;
+    let err = build_dir(&dir, &["//app:app"]).err().unwrap();
+    assert_eq!(err.to_string(), r#"//app:app imports modules its direct dependencies do not provide:
+  app/main.js:1 imports "@lib/util", which no dependency provides"#);
+    Ok(())
+  }
+
+  #[test]
+  fn build_bundles_javascript_with_hashed_outputs() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/MODULE.razel"), TestContents::File("register_toolchains(\"//tools:all\")")),
+      (Path::new("wksp/tools/BUILD"), TestContents::File(r#"
+load("@razel//js:defs.bzl", "esbuild_toolchain", "tsc_toolchain")
+
+esbuild_toolchain(name = "esbuild_impl", esbuild = "esbuild.sh")
+
+toolchain(
+    name = "esbuild",
+    toolchain_type = "@razel//js:esbuild_toolchain_type",
+    toolchain = ":esbuild_impl",
+)
+
+tsc_toolchain(name = "tsc_impl", tsc = "esbuild.sh")
+
+toolchain(name = "tsc", toolchain_type = "@razel//js:tsc_toolchain_type", toolchain = ":tsc_impl")
+"#)),
+      // Writes a hashed entry point and records its arguments and inputs.
+      (Path::new("wksp/tools/esbuild.sh"), TestContents::File(r#"#!/bin/sh
+for arg in "$@"; do
+  case "$arg" in
+    --outdir=*) out="${arg#--outdir=}";;
+    --metafile=*) meta="${arg#--metafile=}";;
+  esac
+done
+[ -n "$out" ] || exit 0
+mkdir -p "$out/chunks"
+cat "$1" > "$out/main-ABC123.js"
+echo "$@" > "$out/chunks/args-DEF456.txt"
+echo '{"outputs":{"main-ABC123.js":{}}}' > "$meta"
+"#)),
+      (Path::new("wksp/lib/BUILD"), TestContents::File(
+        "ts_library(name = \"lib\", srcs = [\"util.ts\"], visibility = [\"//visibility:public\"])",
+      )),
+      (Path::new("wksp/lib/util.ts"), TestContents::File("export const one = 1;")),
+      (Path::new("wksp/app/BUILD"), TestContents::File(r#"
+js_bundle(name = "app", entry_point = "main.ts", deps = ["//lib:lib"], splitting = True)
+
+js_bundle(name = "script", entry_point = "main.ts", format = "iife", splitting = True)
+"#)),
+      (Path::new("wksp/app/main.ts"), TestContents::File("import { one } from '../lib/util';")),
+    ])?;
+    {
+      use std::os::unix::fs::PermissionsExt;
+      std::fs::set_permissions(
+        dir.root.join("wksp/tools/esbuild.sh"),
+        std::fs::Permissions::from_mode(0o755),
+      )?;
+    }
+    let host: Rc<dyn Host> = Rc::new(FsHost::with_output_base(
+      &dir.root.join("wksp"),
+      &dir.root.join("out"),
+    )?);
+
+    let mut config = Configuration::default();
+    config.build_settings.insert(
+      Label::parse("@razel//js:minify", "")?, "true".to_owned());
+    let built = build(
+      host.clone(),
+      &[TargetPattern::parse("//app:app")?],
+      config,
+      &BuildOptions::default(),
+    )?;
+
+    // Setting `--@razel//js:minify` moves outputs to a configuration specific
+    // directory.
+    let files = built[0].files.iter().map(|file| file.path.as_str()).collect::<Vec<_>>();
+    let (bin, _) = files[0].split_once("/app/app").unwrap();
+    assert_eq!(files, [format!("{}/app/app", bin), format!("{}/app/app.manifest.json", bin)]);
+    let bin = dir.root.join("out").join(exec_path(bin));
+    assert_eq!(
+      std::fs::read_to_string(bin.join("app/app/main-ABC123.js"))?,
+      "import { one } from '../lib/util';",
+    );
+    assert_eq!(
+      std::fs::read_to_string(bin.join("app/app.manifest.json"))?,
+      "{\"outputs\":{\"main-ABC123.js\":{}}}\n",
+    );
+    let args = std::fs::read_to_string(bin.join("app/app/chunks/args-DEF456.txt"))?;
+    assert_contains!(args, "app/main.ts --bundle --format=esm --outdir=razel-out/fastbuild-");
+    assert_contains!(args, "--entry-names=[name]-[hash] --chunk-names=chunks/[name]-[hash]");
+    assert_contains!(args, "--splitting --minify\n");
+
+    let err = build(
+      host,
+      &[TargetPattern::parse("//app:script")?],
+      Configuration::default(),
+      &BuildOptions::default(),
+    ).err().unwrap();
+    assert_contains!(err.to_string(), "Code splitting requires format = \"esm\".");
+
+    Ok(())
+  }
+
+  #[test]
+  fn build_records_phases_packages_and_actions_with_the_profiler() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("wksp/pkg/BUILD"), TestContents::File(r#"
+load("//:defs.bzl", "greeting", "message")
+
+message(name = "hello", message = "hello")
+greeting(name = "greet", deps = [":hello"], template = "greet.tpl", out = "greet.out")
+"#)),
+      (Path::new("wksp/pkg/greet.tpl"), TestContents::File(
+        "#!/bin/sh\necho \"{MESSAGES}\" > \"$1\"\n",
+      )),
+    ])?;
+    let host = FsHost::with_output_base(&dir.root.join("wksp"), &dir.root.join("out"))?;
+    let profiler = Arc::new(Profiler::new(std::time::Instant::now()));
+    let options = BuildOptions { profiler: Some(profiler.clone()), ..BuildOptions::default() };
+    let host: Rc<dyn Host> = Rc::new(host);
+    let patterns = [TargetPattern::parse("//pkg:greet")?];
+    build(host.clone(), &patterns, Configuration::default(), &options)?;
+
+    let trace = profiler.to_json();
+    let lookups = |trace: &Json| -> Vec<(String, String)> {
+      trace["traceEvents"].as_array().unwrap().iter()
+        .filter(|event| event["cat"] == "cache")
+        .map(|event| (
+          event["name"].as_str().unwrap().to_owned(),
+          event["args"]["result"].as_str().unwrap().to_owned(),
+        ))
+        .collect()
+    };
+    let spans: Vec<(&str, &str)> = trace["traceEvents"].as_array().unwrap().iter()
+      .filter(|event| event["ph"] == "X")
+      .filter(|event| !["action", "cache"].contains(&event["cat"].as_str().unwrap()))
+      .map(|event| (event["cat"].as_str().unwrap(), event["name"].as_str().unwrap()))
+      .collect();
+    assert_eq!(spans, [
+      ("phase", "load repositories"),
+      ("phase", "expand patterns"),
+      ("package", "//pkg"),
+      ("analysis", "//pkg:greet"),
+      ("phase", "analyze"),
+      ("phase", "check strict deps"),
+      ("phase", "execute"),
+    ]);
+    let report = crate::profile::analyze(&trace)?;
+    assert_contains!(report, "Critical path");
+    assert_contains!(report, "Greet //pkg:greet");
+    let running: Vec<f64> = trace["traceEvents"].as_array().unwrap().iter()
+      .filter(|event| event["ph"] == "C" && event["name"] == "concurrent actions")
+      .map(|event| event["args"]["actions"].as_f64().unwrap())
+      .collect();
+    assert_eq!(running, [1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0]);
+    let lookup = |name: &str, result: &str| (name.to_owned(), result.to_owned());
+    assert_eq!(lookups(&trace), [
+      lookup("FileWrite //pkg:hello", "miss"),
+      lookup("TemplateExpand //pkg:greet", "miss"),
+      lookup("Greet //pkg:greet", "miss"),
+      lookup("Symlink //pkg:greet", "miss"),
+    ]);
+
+    // Nothing changed, so a second build runs no actions.
+    let profiler = Arc::new(Profiler::new(std::time::Instant::now()));
+    let options = BuildOptions { profiler: Some(profiler.clone()), ..BuildOptions::default() };
+    build(host, &[TargetPattern::parse("//pkg:greet")?], Configuration::default(), &options)?;
+    let trace = profiler.to_json();
+    assert_eq!(lookups(&trace), [
+      lookup("FileWrite //pkg:hello", "hit"),
+      lookup("TemplateExpand //pkg:greet", "hit"),
+      lookup("Greet //pkg:greet", "hit"),
+      lookup("Symlink //pkg:greet", "hit"),
+    ]);
+    assert!(!trace["traceEvents"].as_array().unwrap().iter().any(|event| event["cat"] == "action"));
+    Ok(())
+  }
+}
@@ -0,0 +1,455 @@
+use std::ops::Range;
+use std::rc::Rc;
+use crate::starlark::error::{EvalError, Location};
+use crate::starlark::lexer::{tokenize, Spanned, Token};
+
+/// A top-level call of a BUILD file, such as a rule instantiation or a
+/// `load()` statement.
+pub struct Call {
+  /// The name of the called function, such as `ts_library`.
+  pub kind: String,
+
+  /// The value of the `name` argument if it is a string literal.
+  pub name: Option<String>,
+
+  /// The arguments, in order. Positional arguments have no name.
+  pub args: Vec<Arg>,
+
+  /// Whether the call is marked `# keep`, on its first line or the line
+  /// before it, and must not be edited.
+  pub keep: bool,
+
+  /// The location of the function name.
+  pub location: Location,
+
+  /// The byte offsets of the function name and the closing parenthesis.
+  start: usize,
+  end: usize,
+}
+
+impl Call {
+  /// Returns the keyword argument `name`.
+  pub fn attr(&self, name: &str) -> Option<&Arg> {
+    self.args.iter().find(|arg| arg.name.as_deref() == Some(name))
+  }
+}
+
+/// An argument of a top-level call.
+pub struct Arg {
+  /// The keyword of a keyword argument.
+  pub name: Option<String>,
+
+  /// The value if it is a string literal.
+  pub string: Option<String>,
+
+  /// The elements of a value which is a list of string literals, with whether
+  /// each is marked `# keep`. `None` for any other value.
+  pub strings: Option<Vec<(String, bool)>>,
+
+  /// Whether the argument is marked `# keep` at the end of its value and must
+  /// not be edited.
+  pub keep: bool,
+
+  /// The location of the argument's first token.
+  pub location: Location,
+
+  /// The byte offsets of the argument's first token and range of its value.
+  start: usize,
+  value: Range<usize>,
+}
+
+/// A BUILD file whose top-level calls are edited in place, leaving all other
+/// text including comments and formatting untouched.
+pub struct BuildFileEditor {
+  source: String,
+  pub calls: Vec<Call>,
+
+  /// Replacements of byte ranges of the source, applied by `finish()`.
+  edits: Vec<(Range<usize>, String)>,
+}
+
+impl BuildFileEditor {
+  pub fn parse(file: &str, source: &str) -> Result<BuildFileEditor, EvalError> {
+    let tokens = tokenize(&Rc::from(file), source)?;
+    let line_starts: Vec<usize> = [0].into_iter()
+      .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+      .collect();
+    let offset = |location: &Location| {
+      let start = line_starts[location.line - 1];
+      source[start..].char_indices().nth(location.column - 1)
+        .map_or(source.len(), |(index, _)| start + index)
+    };
+    let line = |line: usize| {
+      let start = line_starts[line - 1];
+      source[start..].lines().next().unwrap_or_default()
+    };
+
+    let mut calls = Vec::new();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+      let statement_start = i == 0 || matches!(
+        tokens[i - 1].token,
+        Token::Newline | Token::Indent | Token::Dedent,
+      );
+      let kind = match &tokens[i].token {
+        Token::Ident(kind) => Some(kind.as_str()),
+        Token::Keyword("load") => Some("load"),
+        _ => None,
+      };
+      match (kind, tokens.get(i + 1).map(|next| &next.token)) {
+        (Some(kind), Some(Token::Punct("("))) if depth == 0 && statement_start => {
+          let location = &tokens[i].location;
+          let keep = has_keep(line(location.line))
+            || (location.line > 1 && line(location.line - 1).trim_start().starts_with('#')
+              && has_keep(line(location.line - 1)));
+          let (args, close) = parse_args(&tokens, i + 2, &offset, &line, source);
+          let name = args.iter()
+            .find(|arg| arg.name.as_deref() == Some("name"))
+            .and_then(|arg| arg.string.clone());
+          calls.push(Call {
+            kind: kind.to_owned(),
+            name,
+            args,
+            keep,
+            location: location.clone(),
+            start: offset(location),
+            end: offset(&tokens[close].location),
+          });
+          i = close + 1;
+          continue;
+        },
+        _ => {},
+      }
+      match &tokens[i].token {
+        Token::Punct("(" | "[" | "{") => depth += 1,
+        Token::Punct(")" | "]" | "}") => depth -= 1,
+        _ => {},
+      }
+      i += 1;
+    }
+
+    Ok(BuildFileEditor { source: source.to_owned(), calls, edits: Vec::new() })
+  }
+
+  /// Sets a keyword argument of a call to a list of strings, replacing its
+  /// value or adding it before the closing parenthesis. Elements marked as
+  /// kept are printed with a `# keep` comment.
+  pub fn set_list(&mut self, call: usize, name: &str, items: &[(String, bool)]) {
+    let call = &self.calls[call];
+    if let Some(attr) = call.attr(name) {
+      let indent = self.indentation(attr.start);
+      self.edits.push((attr.value.clone(), format_list(items, indent)));
+      return;
+    }
+
+    let before = self.source[..call.end].trim_end_matches([' ', '\t']);
+    if before.ends_with('\n') {
+      let indent = call.args.first().map_or(
+        self.indentation(call.start) + 4,
+        |arg| self.indentation(arg.start),
+      );
+      let text = format!("{}{} = {},\n", " ".repeat(indent), name, format_list(items, indent));
+      self.edits.push((before.len()..before.len(), text));
+    } else {
+      let separator = if before.ends_with(['(', ',']) { "" } else { ", " };
+      let text = format!("{}{} = {}", separator, name, format_list(items, 0).replace('\n', " "));
+      self.edits.push((call.end..call.end, text));
+    }
+  }
+
+  /// Removes an argument of a call with its separating comma, and its whole
+  /// line if it is on a line of its own.
+  pub fn remove_arg(&mut self, call: usize, arg: usize) {
+    let call = &self.calls[call];
+    let removed = &call.args[arg];
+    let line_start = self.line_start(removed.start);
+    let range = if self.source[line_start..removed.start].trim().is_empty() {
+      line_start..self.line_end(removed.value.end)
+    } else if let Some(next) = call.args.get(arg + 1) {
+      removed.start..next.start
+    } else if let Some(previous) = arg.checked_sub(1).map(|previous| &call.args[previous]) {
+      previous.value.end..removed.value.end
+    } else {
+      removed.start..call.end
+    };
+    self.edits.push((range, String::new()));
+  }
+
+  /// Removes a call with the lines it is on.
+  pub fn remove_call(&mut self, call: usize) {
+    let call = &self.calls[call];
+    self.edits.push((self.line_start(call.start)..self.line_end(call.end), String::new()));
+  }
+
+  /// Appends text after the last line, separated by a blank line.
+  pub fn append(&mut self, text: &str) {
+    let end = self.source.len();
+    let trimmed = self.source.trim_end();
+    let separator = if trimmed.is_empty() { "" } else { "\n\n" };
+    self.edits.push((trimmed.len()..end, format!("{}{}", separator, text)));
+  }
+
+  /// Returns the edited source.
+  pub fn finish(mut self) -> String {
+    self.edits.sort_by_key(|(range, _)| (range.start, range.end));
+    for (range, text) in self.edits.into_iter().rev() {
+      self.source.replace_range(range, &text);
+    }
+    self.source
+  }
+
+  /// Returns the column, from zero, of a byte offset.
+  fn indentation(&self, offset: usize) -> usize {
+    self.source[self.line_start(offset)..offset].chars().count()
+  }
+
+  /// Returns the offset of the start of the line containing an offset.
+  fn line_start(&self, offset: usize) -> usize {
+    self.source[..offset].rfind('\n').map_or(0, |index| index + 1)
+  }
+
+  /// Returns the offset after the newline ending the line containing an
+  /// offset.
+  fn line_end(&self, offset: usize) -> usize {
+    self.source[offset..].find('\n').map_or(self.source.len(), |index| offset + index + 1)
+  }
+}
+
+/// Parses the arguments of a call whose arguments start at token `start`,
+/// returning them with the index of the closing parenthesis.
+fn parse_args<'a>(tokens: &[Spanned], start: usize,
+    offset: &dyn Fn(&Location) -> usize, line: &dyn Fn(usize) -> &'a str,
+    source: &'a str) -> (Vec<Arg>, usize) {
+  let mut args = Vec::new();
+  let mut depth = 0;
+  let mut i = start;
+  let mut current: Option<(Option<String>, usize, usize)> = None;
+  while i < tokens.len() {
+    let token = &tokens[i].token;
+    let terminates = depth == 0 && matches!(token, Token::Punct("," | ")") | Token::Eof);
+    if terminates {
+      if let Some((name, start, value_index)) = current.take() {
+        let value_start = offset(&tokens[value_index].location);
+        let value = &source[value_start..offset(&tokens[i].location)];
+        let value_end = value_start + strip_trailing_comments(value).len();
+        let last_line = source[..value_end].matches('\n').count() + 1;
+        let string = match &tokens[value_index..i] {
+          [Spanned { token: Token::Str(value), .. }] => Some(value.clone()),
+          _ => None,
+        };
+        args.push(Arg {
+          name,
+          string,
+          strings: string_list(&tokens[value_index..i], line),
+          keep: has_keep(line(last_line)),
+          location: tokens[start].location.clone(),
+          start: offset(&tokens[start].location),
+          value: value_start..value_end,
+        });
+      }
+      if !matches!(token, Token::Punct(",")) {
+        return (args, i);
+      }
+    } else if depth == 0 && current.is_none() {
+      let next = tokens.get(i + 1).map(|next| &next.token);
+      if let (Token::Ident(name), Some(Token::Punct("="))) = (token, next) {
+        current = Some((Some(name.clone()), i, i + 2));
+        i += 2;
+        continue;
+      }
+      current = Some((None, i, i));
+    }
+    match token {
+      Token::Punct("(" | "[" | "{") => depth += 1,
+      Token::Punct(")" | "]" | "}") => depth -= 1,
+      _ => {},
+    }
+    i += 1;
+  }
+  (args, tokens.len() - 1)
+}
+
+/// Returns the elements of a value which is a list of string literals.
+fn string_list<'a>(tokens: &[Spanned], line: &dyn Fn(usize) -> &'a str) ->
+    Option<Vec<(String, bool)>> {
+  let brackets = (&tokens.first()?.token, &tokens.last()?.token);
+  let (Token::Punct("["), Token::Punct("]")) = brackets else {
+    return None;
+  };
+  let mut strings = Vec::new();
+  let mut expect_string = true;
+  for spanned in &tokens[1..tokens.len() - 1] {
+    match (&spanned.token, expect_string) {
+      (Token::Str(value), true) => {
+        strings.push((value.clone(), has_keep(line(spanned.location.line))));
+      },
+      (Token::Punct(","), false) => {},
+      _ => return None,
+    }
+    expect_string = !expect_string;
+  }
+  Some(strings)
+}
+
+/// Formats a list of strings on one line if it has at most one element and
+/// none are kept, otherwise with an element per line indented past `indent`.
+pub fn format_list(items: &[(String, bool)], indent: usize) -> String {
+  if items.len() <= 1 && !items.iter().any(|(_, keep)| *keep) {
+    return format!("[{}]", items.iter().map(|(item, _)| quote(item)).collect::<String>());
+  }
+  let mut list = "[\n".to_owned();
+  for (item, keep) in items {
+    list.push_str(&format!(
+      "{}{},{}\n",
+      " ".repeat(indent + 4),
+      quote(item),
+      if *keep { "  # keep" } else { "" },
+    ));
+  }
+  list.push_str(&" ".repeat(indent));
+  list.push(']');
+  list
+}
+
+/// Returns a string as a double-quoted Starlark literal.
+pub fn quote(value: &str) -> String {
+  let mut quoted = "\"".to_owned();
+  for c in value.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\n"),
+      '\r' => quoted.push_str("\\r"),
+      '\t' => quoted.push_str("\\t"),
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+/// Orders labels in lists the way BUILD files conventionally sort them:
+/// relative labels and file names, then labels of the main repository, then
+/// external labels.
+pub fn label_order(label: &str) -> (u8, &str) {
+  let rank = if label.starts_with('@') { 2 } else if label.starts_with("//") { 1 } else { 0 };
+  (rank, label)
+}
+
+/// Returns the comment at the end of a line of Starlark, if any.
+pub fn comment(line: &str) -> Option<&str> {
+  let mut quote = None;
+  let mut escaped = false;
+  for (index, c) in line.char_indices() {
+    match quote {
+      Some(_) if escaped => escaped = false,
+      Some(_) if c == '\\' => escaped = true,
+      Some(q) if c == q => quote = None,
+      Some(_) => {},
+      None if c == '"' || c == '\'' => quote = Some(c),
+      None if c == '#' => return Some(&line[index + 1..]),
+      None => {},
+    }
+  }
+  None
+}
+
+/// Returns whether a line ends with a `# keep` comment, optionally followed by
+/// a reason such as `# keep: generated by a script`.
+fn has_keep(line: &str) -> bool {
+  comment(line).is_some_and(|comment| {
+    let comment = comment.trim();
+    comment == "keep" || comment.starts_with("keep:") || comment.starts_with("keep ")
+  })
+}
+
+/// Removes trailing whitespace and comments from a value's source.
+pub fn strip_trailing_comments(mut text: &str) -> &str {
+  loop {
+    let trimmed = text.trim_end();
+    let line_start = trimmed.rfind('\n').map_or(0, |index| index + 1);
+    match comment(&trimmed[line_start..]) {
+      Some(comment) => text = &trimmed[..trimmed.len() - comment.len() - 1],
+      None => return trimmed,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn parse_finds_calls_and_kept_values() -> Result<(), EvalError> {
+    let file = BuildFileEditor::parse("BUILD", r#"load("//:defs.bzl", "rule")
+
+# keep
+rule(name = "kept")
+
+ts_library(
+    name = "lib",
+    srcs = glob(["*.ts"]),
+    deps = [
+        ":other",  # keep
+        "//a",
+    ],
+    data = ["x"],  # keep: generated
+)
+"#)?;
+    let calls = file.calls.iter()
+      .map(|call| (call.kind.as_str(), call.name.as_deref(), call.keep))
+      .collect::<Vec<_>>();
+    assert_eq!(calls, [
+      ("load", None, false),
+      ("rule", Some("kept"), true),
+      ("ts_library", Some("lib"), false),
+    ]);
+    let lib = &file.calls[2];
+    assert_eq!(
+      file.calls[0].args.iter().map(|arg| arg.string.as_deref()).collect::<Vec<_>>(),
+      [Some("//:defs.bzl"), Some("rule")],
+    );
+    assert_eq!(lib.attr("srcs").unwrap().strings, None);
+    assert_eq!(
+      lib.attr("deps").unwrap().strings,
+      Some(vec![(":other".to_owned(), true), ("//a".to_owned(), false)]),
+    );
+    assert!(!lib.attr("deps").unwrap().keep);
+    assert!(lib.attr("data").unwrap().keep);
+    Ok(())
+  }
+
+  #[test]
+  fn set_list_replaces_and_inserts_values_in_place() -> Result<(), EvalError> {
+    let mut file = BuildFileEditor::parse("BUILD", r#"# Hand-written.
+ts_library(
+    name = "lib",  # The library.
+    srcs = ["old.ts"],
+)
+
+js_library(name = "js")
+"#)?;
+    file.set_list(0, "srcs", &[("a.ts".to_owned(), false), ("b.ts".to_owned(), false)]);
+    file.set_list(0, "deps", &[(":kept".to_owned(), true)]);
+    file.set_list(1, "srcs", &[("a.js".to_owned(), false)]);
+    file.append("ts_library(name = \"new\")\n");
+    assert_eq!(file.finish(), r#"# Hand-written.
+ts_library(
+    name = "lib",  # The library.
+    srcs = [
+        "a.ts",
+        "b.ts",
+    ],
+    deps = [
+        ":kept",  # keep
+    ],
+)
+
+js_library(name = "js", srcs = ["a.js"])
+
+ts_library(name = "new")
+"#);
+    Ok(())
+  }
+}
@@ -0,0 +1,260 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::rc::Rc;
+use crate::analysis::depset::depset_builtin;
+use crate::analysis::provider::{provider_builtin, Provider};
+use crate::analysis::rule::{attr_module, rule_builtin, RuleDef};
+use crate::analysis::select::select_builtin;
+use crate::analysis::transition::transition_builtin;
+use crate::label::Label;
+use crate::repository::Repositories;
+use crate::starlark::error::EvalError;
+use crate::starlark::eval::{Evaluator, Loader, ModuleEnv};
+use crate::starlark::json::json_module;
+use crate::starlark::parser::parse;
+use crate::starlark::value::{Struct, Value};
+
+/// Loads the .bzl files referenced by `load()` statements. Each file is
+/// evaluated at most once and its globals are frozen so every file loading it
+/// sees the same values.
+pub struct BzlLoader {
+  repositories: Rc<Repositories>,
+
+  /// The builtins available to every .bzl file.
+  predeclared: Rc<HashMap<String, Value>>,
+
+  modules: RefCell<HashMap<Label, Rc<ModuleEnv>>>,
+
+  /// The files currently being evaluated, to detect load cycles.
+  loading: RefCell<Vec<Label>>,
+}
+
+impl BzlLoader {
+  pub fn new(repositories: Rc<Repositories>) -> Rc<BzlLoader> {
+    let predeclared = HashMap::from([
+      ("rule".to_owned(), rule_builtin()),
+      ("attr".to_owned(), attr_module()),
+      ("provider".to_owned(), provider_builtin()),
+      ("DefaultInfo".to_owned(), Value::Object(Provider::default_info())),
+      ("BuildSettingInfo".to_owned(), Value::Object(Provider::build_setting_info())),
+      ("depset".to_owned(), depset_builtin()),
+      ("select".to_owned(), select_builtin()),
+      ("transition".to_owned(), transition_builtin()),
+      ("json".to_owned(), json_module()),
+      ("platform_common".to_owned(), Value::object(Struct { fields: BTreeMap::from([
+        ("ToolchainInfo".to_owned(), Value::Object(Provider::toolchain_info())),
+      ]) })),
+    ]);
+
+    Rc::new(BzlLoader {
+      repositories,
+      predeclared: Rc::new(predeclared),
+      modules: RefCell::new(HashMap::new()),
+      loading: RefCell::new(Vec::new()),
+    })
+  }
+
+  /// Returns a `Loader` which resolves relative labels against `package` of
+  /// the repository `repo`.
+  pub fn for_package(self: &Rc<Self>, repo: &str, package: &str) -> Rc<dyn Loader> {
+    Rc::new(FileLoader { bzl: self.clone(), repo: repo.to_owned(), package: package.to_owned() })
+  }
+
+  /// The labels of every .bzl file evaluated so far.
+  pub fn loaded(&self) -> Vec<Label> {
+    self.modules.borrow().keys().cloned().collect()
+  }
+
+  /// Evaluates the .bzl file with the given label, unless it already was, and
+  /// returns its globals.
+  pub fn load(self: &Rc<Self>, label: &Label) -> Result<Rc<ModuleEnv>, EvalError> {
+    if !label.name.ends_with(".bzl") {
+      return Err(EvalError::msg(format!(
+        "Cannot load `{}`, only .bzl files can be loaded.",
+        label,
+      )));
+    }
+    if let Some(env) = self.modules.borrow().get(label) {
+      return Ok(env.clone());
+    }
+
+    let mut loading = self.loading.borrow_mut();
+    if let Some(start) = loading.iter().position(|other| other == label) {
+      let cycle: Vec<_> = loading[start..].iter().chain([label]).map(|label| label.to_string()).collect();
+      return Err(EvalError::msg(format!("Load cycle: {}", cycle.join(" -> "))));
+    }
+    loading.push(label.clone());
+    drop(loading);
+
+    let result = self.evaluate(label);
+    self.loading.borrow_mut().pop();
+
+    let env = result?;
+    self.modules.borrow_mut().insert(label.clone(), env.clone());
+    Ok(env)
+  }
+
+  fn evaluate(self: &Rc<Self>, label: &Label) -> Result<Rc<ModuleEnv>, EvalError> {
+    let source = self.repositories.get(&label.repo)
+      .and_then(|host| host.read_to_string(&Path::new(&label.package).join(&label.name)))
+      .map_err(|err| EvalError::msg(format!("Failed to load `{}`: {}", label, err)))?;
+
+    let path = Path::new(&label.package_path()).join(&label.name);
+    let file = path.to_str().unwrap();
+    let module = parse(file, &source)?;
+    let env = ModuleEnv::new(file, self.predeclared.clone());
+    let mut eval = Evaluator::new();
+    eval.set_loader(self.for_package(&label.repo, &label.package));
+    eval.eval_module(&module, &env)?;
+    export(&env);
+    for value in env.globals.borrow().values() {
+      value.freeze();
+    }
+
+    Ok(env)
+  }
+}
+
+/// Names every rule and provider by the alphabetically first global it is
+/// assigned to.
+fn export(env: &ModuleEnv) {
+  let globals = env.globals.borrow();
+  let mut names: Vec<_> = globals.keys().collect();
+  names.sort();
+
+  for name in names {
+    let value = &globals[name];
+    if let Some(rule) = value.downcast::<RuleDef>() {
+      rule.name.borrow_mut().get_or_insert_with(|| name.clone());
+    } else if let Some(provider) = value.downcast::<Provider>() {
+      provider.key.borrow_mut().get_or_insert_with(|| (env.file.to_string(), name.clone()));
+    }
+  }
+}
+
+/// Resolves the `load()` statements of a file in a specific package.
+struct FileLoader {
+  bzl: Rc<BzlLoader>,
+  repo: String,
+  package: String,
+}
+
+impl Loader for FileLoader {
+  fn load(&self, module: &str) -> Result<Rc<ModuleEnv>, EvalError> {
+    let label = Label::parse_in(module, &self.repo, &self.package).map_err(|err| EvalError::msg(err.0))?;
+    self.bzl.load(&label)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use assertables::assert_contains;
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+  use std::error::Error;
+  use super::*;
+
+  fn label(package: &str, name: &str) -> Label {
+    Label { repo: "".to_owned(), package: package.to_owned(), name: name.to_owned() }
+  }
+
+  #[test]
+  fn load_evaluates_files_and_exports_rules_and_providers() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("tools/defs.bzl"), TestContents::File(r#"
+load(":info.bzl", "FooInfo")
+
+def _impl(ctx):
+    return [FooInfo()]
+
+foo = rule(implementation = _impl)
+"#)),
+      (Path::new("tools/info.bzl"), TestContents::File("FooInfo = provider()\n_private = 1")),
+    ])?;
+    let bzl = BzlLoader::new(Rc::new(Repositories::new(Rc::new(FsHost::from(&dir.root)?))));
+
+    let env = bzl.load(&label("tools", "defs.bzl"))?;
+    let rule = env.get("foo").unwrap().downcast::<RuleDef>().unwrap();
+    assert_eq!(rule.name(), "foo");
+    let provider = env.get("FooInfo").unwrap().downcast::<Provider>().unwrap();
+    assert_eq!(
+      *provider.key.borrow(),
+      Some(("tools/info.bzl".to_owned(), "FooInfo".to_owned())),
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn load_errors_on_missing_files_and_symbols() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("a.bzl"), TestContents::File("load(\"//:b.bzl\", \"missing\")")),
+      (Path::new("b.bzl"), TestContents::File("_private = 1")),
+      (Path::new("c.bzl"), TestContents::File("load(\"//:b.bzl\", \"_private\")")),
+      (Path::new("d.bzl"), TestContents::File("load(\"//:nope.bzl\", \"x\")")),
+    ])?;
+    let bzl = BzlLoader::new(Rc::new(Repositories::new(Rc::new(FsHost::from(&dir.root)?))));
+
+    assert_eq!(
+      bzl.load(&label("", "a.bzl")).err().unwrap().to_string(),
+      "a.bzl:1:1: `//:b.bzl` does not export `missing`.",
+    );
+    assert_contains!(
+      bzl.load(&label("", "c.bzl")).err().unwrap().to_string(),
+      "does not export `_private`",
+    );
+    assert_contains!(
+      bzl.load(&label("", "d.bzl")).err().unwrap().to_string(),
+      "d.bzl:1:1: Failed to load `//:nope.bzl`",
+    );
+    assert_contains!(
+      bzl.load(&label("", "BUILD")).err().unwrap().to_string(),
+      "only .bzl files can be loaded",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn load_evaluates_each_file_once_and_freezes_it() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("common.bzl"), TestContents::File("SRCS = [\"a.ts\"]")),
+      (Path::new("a.bzl"), TestContents::File("load(\"//:common.bzl\", \"SRCS\")\nA = SRCS")),
+      (Path::new("b.bzl"), TestContents::File("load(\":common.bzl\", \"SRCS\")\nB = SRCS")),
+      (Path::new("mutate.bzl"), TestContents::File("load(\"//:common.bzl\", \"SRCS\")\nSRCS.append(\"b.ts\")")),
+    ])?;
+    let bzl = BzlLoader::new(Rc::new(Repositories::new(Rc::new(FsHost::from(&dir.root)?))));
+
+    let a = bzl.load(&label("", "a.bzl"))?;
+    let b = bzl.load(&label("", "b.bzl"))?;
+    match (a.get("A").unwrap(), b.get("B").unwrap()) {
+      (Value::List(a), Value::List(b)) => assert!(Rc::ptr_eq(&a, &b)),
+      _ => panic!("Expected lists."),
+    }
+    assert!(Rc::ptr_eq(&a, &bzl.load(&label("", "a.bzl"))?));
+    assert_eq!(
+      bzl.load(&label("", "mutate.bzl")).err().unwrap().to_string(),
+      "mutate.bzl:2:12: Cannot modify a frozen list.",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn load_errors_on_cycles() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("a.bzl"), TestContents::File("load(\"//pkg:b.bzl\", \"B\")\nA = 1")),
+      (Path::new("pkg/b.bzl"), TestContents::File("load(\":c.bzl\", \"C\")\nB = 1")),
+      (Path::new("pkg/c.bzl"), TestContents::File("load(\"//:a.bzl\", \"A\")\nC = 1")),
+    ])?;
+    let bzl = BzlLoader::new(Rc::new(Repositories::new(Rc::new(FsHost::from(&dir.root)?))));
+
+    assert_eq!(
+      bzl.load(&label("", "a.bzl")).err().unwrap().to_string(),
+      "pkg/c.bzl:1:1: Load cycle: //:a.bzl -> //pkg:b.bzl -> //pkg:c.bzl -> //:a.bzl",
+    );
+
+    Ok(())
+  }
+}
@@ -0,0 +1,340 @@
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+use crate::analysis::action::{Action, ActionKind};
+use crate::analysis::artifact::Artifact;
+use crate::bep::BuildEventStream;
+use crate::host::host::{list_all_files, EntryKind, Host, Process, EXEC_ROOT};
+use crate::label::EXTERNAL_DIR;
+use crate::profile::Profiler;
+use crate::repository::Repositories;
+
+/// The directory of the output base which stores the key of the last
+/// successful run of each action, by the digest of its first output's path.
+pub const ACTION_CACHE: &str = "action-cache";
+
+/// Runs the actions generating outputs, each after the actions generating
+/// its inputs and at most once. Actions whose key matches the one they last
+/// succeeded with and whose outputs still exist are skipped. Each cache lookup
+/// and action run is recorded by the profiler, and each action run is posted
+/// to the build event stream, if any.
+pub struct Executor<'a> {
+  repositories: &'a Repositories,
+
+  /// Maps the exec path of every generated file to the action generating it.
+  producers: HashMap<&'a str, &'a Rc<Action>>,
+
+  profiler: Option<&'a Profiler>,
+  events: Option<&'a BuildEventStream>,
+
+  /// The keys of actions which already ran or were up to date, by the exec
+  /// path of their first output.
+  done: HashMap<&'a str, String>,
+
+  /// Actions currently waiting on their inputs, to detect cycles.
+  running: Vec<&'a str>,
+}
+
+impl<'a> Executor<'a> {
+  /// Returns an executor of the given actions. External repositories are
+  /// linked into the exec root first so their sources resolve at the paths of
+  /// their artifacts.
+  pub fn new(repositories: &'a Repositories, actions: &'a [Rc<Action>],
+      profiler: Option<&'a Profiler>, events: Option<&'a BuildEventStream>) ->
+      Result<Executor<'a>, Box<dyn Error>> {
+    let host = repositories.main().as_ref();
+    for (name, repository) in repositories.external() {
+      let link = Path::new(EXEC_ROOT).join(EXTERNAL_DIR).join(name);
+      host.symlink_output(&link, repository.source_root())?;
+    }
+
+    let producers = actions.iter()
+      .flat_map(|action| action.outputs.iter().map(move |output| (output.path.as_str(), action)))
+      .collect();
+    Ok(Executor {
+      repositories,
+      producers,
+      profiler,
+      events,
+      done: HashMap::new(),
+      running: Vec::new(),
+    })
+  }
+
+  /// Generates a file, running the actions it needs which have not run yet.
+  pub fn generate(&mut self, artifact: &Artifact) -> Result<(), Box<dyn Error>> {
+    if artifact.is_source {
+      return Ok(());
+    }
+    let action = *self.producers.get(artifact.path.as_str())
+      .ok_or_else(|| ExecutionError(format!("No action generates `{}`.", artifact.path)))?;
+    let key = action.outputs[0].path.as_str();
+    if self.done.contains_key(key) {
+      return Ok(());
+    }
+    if self.running.contains(&key) {
+      return Err(Box::new(ExecutionError(format!(
+        "Action {} depends on its own outputs.",
+        action,
+      ))));
+    }
+
+    self.running.push(key);
+    for input in &action.inputs {
+      self.generate(input)?;
+    }
+    self.running.pop();
+
+    let action_key = self.action_key(action)?;
+    let host = self.repositories.main().as_ref();
+    let entry = Path::new(ACTION_CACHE).join(hex(&Sha256::digest(key)));
+    let span = self.profiler.map(|profiler| profiler.span("cache", action.to_string()));
+    let hit = up_to_date(host, action, &entry, &action_key)?;
+    drop(span.map(|span| span.arg("result", json!(if hit { "hit" } else { "miss" }))));
+    if hit {
+      self.done.insert(key, action_key);
+      return Ok(());
+    }
+
+    // Generated inputs and outputs let `analyze-profile` chain actions into
+    // the critical path.
+    let paths = |artifacts: &[Rc<Artifact>]| -> Vec<String> {
+      artifacts.iter()
+        .filter(|artifact| !artifact.is_source)
+        .map(|artifact| artifact.path.clone())
+        .collect()
+    };
+    let span = self.profiler.map(|profiler| profiler.action(action.to_string())
+      .arg("mnemonic", json!(action.mnemonic))
+      .arg("inputs", json!(paths(&action.inputs)))
+      .arg("outputs", json!(paths(&action.outputs))));
+    let start = SystemTime::now();
+    let result = run(self.repositories, action);
+    drop(span);
+    if let Some(events) = self.events {
+      events.action_completed(action, result.is_ok(), start);
+    }
+    result?;
+    host.write_output(&entry, action_key.as_bytes())?;
+    self.done.insert(key, action_key);
+    Ok(())
+  }
+
+  /// Returns a digest of everything an action's outputs depend on: what it
+  /// does, the paths of its outputs and the contents of its inputs. Generated
+  /// inputs contribute the key of the action generating them, which already
+  /// ran, so a change anywhere upstream changes the key.
+  fn action_key(&self, action: &Action) -> Result<String, Box<dyn Error>> {
+    let mut digest = Sha256::new();
+    let mut field = |value: &[u8]| {
+      digest.update((value.len() as u64).to_le_bytes());
+      digest.update(value);
+    };
+    field(action.mnemonic.as_bytes());
+    match &action.kind {
+      ActionKind::Run { executable, arguments, env } => {
+        field(b"run");
+        field(executable.as_bytes());
+        arguments.iter().for_each(|argument| field(argument.as_bytes()));
+        field(b"env");
+        env.iter().for_each(|(key, value)| { field(key.as_bytes()); field(value.as_bytes()); });
+      },
+      ActionKind::Write { content, executable } => {
+        field(b"write");
+        field(content.as_bytes());
+        field(&[*executable as u8]);
+      },
+      ActionKind::Symlink { target, executable } => {
+        field(b"symlink");
+        field(target.path.as_bytes());
+        field(&[*executable as u8]);
+      },
+      ActionKind::ExpandTemplate { template, substitutions, executable } => {
+        field(b"expand_template");
+        field(template.path.as_bytes());
+        substitutions.iter().for_each(|(key, value)| {
+          field(key.as_bytes());
+          field(value.as_bytes());
+        });
+        field(&[*executable as u8]);
+      },
+    }
+    for output in &action.outputs {
+      field(b"output");
+      field(output.path.as_bytes());
+      field(&[output.is_directory as u8]);
+    }
+    for input in &action.inputs {
+      field(b"input");
+      field(input.path.as_bytes());
+      if input.is_source {
+        field(&source_digest(self.repositories, &input.path)?);
+      } else {
+        let producer = self.producers[input.path.as_str()];
+        field(self.done[producer.outputs[0].path.as_str()].as_bytes());
+      }
+    }
+
+    Ok(hex(&digest.finalize()))
+  }
+}
+
+/// Returns whether an action last succeeded with the given key, stored at
+/// `entry`, and all of its outputs still exist.
+fn up_to_date(host: &dyn Host, action: &Action, entry: &Path, key: &str) ->
+    Result<bool, Box<dyn Error>> {
+  if host.output_kind(entry)?.is_none() || host.read_output(entry)? != key.as_bytes() {
+    return Ok(false);
+  }
+  for output in &action.outputs {
+    if host.output_kind(&exec_path(&output.path))?.is_none() {
+      return Ok(false);
+    }
+  }
+  Ok(true)
+}
+
+/// Returns a digest of the contents and executable bit of the source file at
+/// the given exec path, or of every file within it if it is a directory.
+fn source_digest(repositories: &Repositories, path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+  let (host, path) = source_host(repositories, path)?;
+  let files = match host.resolve(&path)?.kind {
+    EntryKind::Directory => list_all_files(host.as_ref(), &path)?,
+    _ => vec![path],
+  };
+  let mut digest = Sha256::new();
+  for file in files {
+    digest.update(file.as_os_str().as_encoded_bytes());
+    digest.update([0, host.is_executable(&file)? as u8]);
+    digest.update(Sha256::digest(host.read(&file)?));
+  }
+  Ok(digest.finalize().to_vec())
+}
+
+/// Formats bytes as lowercase hexadecimal.
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Runs a single action whose inputs are all available.
+fn run(repositories: &Repositories, action: &Action) -> Result<(), Box<dyn Error>> {
+  let host = repositories.main().as_ref();
+  let error = |message: String| ExecutionError(format!("{} failed: {}", action, message));
+
+  // Stale outputs are deleted so actions never observe their previous results.
+  for output in &action.outputs {
+    let path = exec_path(&output.path);
+    host.delete_output(&path)?;
+    if output.is_directory {
+      host.create_output_dir(&path)?;
+    } else {
+      host.create_output_dir(path.parent().unwrap())?;
+    }
+  }
+
+  match &action.kind {
+    ActionKind::Write { content, executable } => {
+      let path = exec_path(&action.outputs[0].path);
+      host.write_output(&path, content.as_bytes())?;
+      host.set_output_executable(&path, *executable)?;
+    },
+    ActionKind::Symlink { target, .. } => {
+      // Relative to the output's directory so the link works from anywhere
+      // the exec root is.
+      let output = &action.outputs[0];
+      let depth = output.path.matches('/').count();
+      let relative = format!("{}{}", "../".repeat(depth), target.path);
+      host.symlink_output(&exec_path(&output.path), Path::new(&relative))?;
+    },
+    ActionKind::ExpandTemplate { template, substitutions, executable } => {
+      let mut content = if template.is_source {
+        read_source(repositories, &template.path)?
+      } else {
+        String::from_utf8(host.read_output(&exec_path(&template.path))?)
+          .map_err(|err| error(format!("template `{}` is not UTF-8: {}", template.path, err)))?
+      };
+      for (key, value) in substitutions {
+        content = content.replace(key, value);
+      }
+      let path = exec_path(&action.outputs[0].path);
+      host.write_output(&path, content.as_bytes())?;
+      host.set_output_executable(&path, *executable)?;
+    },
+    ActionKind::Run { executable, arguments, env } => {
+      let output = host.execute(&Process {
+        program: executable.clone(),
+        args: arguments.clone(),
+        env: env.clone(),
+      }).map_err(|err| error(err.to_string()))?;
+      let stderr = String::from_utf8_lossy(&output.stderr);
+      match output.status {
+        Some(0) => {},
+        Some(status) => return Err(Box::new(error(format!(
+          "exit code {}.\n{}",
+          status,
+          stderr.trim_end(),
+        )))),
+        None => return Err(Box::new(error(format!(
+          "killed by a signal.\n{}",
+          stderr.trim_end(),
+        )))),
+      }
+      if !stderr.trim().is_empty() {
+        eprintln!("INFO: From {}:\n{}", action, stderr.trim_end());
+      }
+
+      for output in &action.outputs {
+        let expected = if output.is_directory { EntryKind::Directory } else { EntryKind::File };
+        match host.output_kind(&exec_path(&output.path))? {
+          Some(kind) if kind == expected || matches!(kind, EntryKind::Symlink(_)) => {},
+          _ => return Err(Box::new(error(format!("output `{}` was not created.", output.path)))),
+        }
+      }
+    },
+  }
+
+  Ok(())
+}
+
+/// Reads the source file at the given exec path from its repository.
+pub fn read_source(repositories: &Repositories, path: &str) -> Result<String, Box<dyn Error>> {
+  let (host, path) = source_host(repositories, path)?;
+  host.read_to_string(&path)
+}
+
+/// Returns the repository a source file at the given exec path is in and its
+/// path within that repository.
+fn source_host(repositories: &Repositories, path: &str) ->
+    Result<(Rc<dyn Host>, PathBuf), Box<dyn Error>> {
+  let external = path.strip_prefix(EXTERNAL_DIR).and_then(|path| path.strip_prefix('/'));
+  match external.and_then(|path| path.split_once('/')) {
+    Some((repo, path)) => Ok((repositories.get(repo)?, PathBuf::from(path))),
+    None => Ok((repositories.main().clone(), PathBuf::from(path))),
+  }
+}
+
+/// Returns the output base path of a file in the exec root.
+pub fn exec_path(path: &str) -> PathBuf {
+  Path::new(EXEC_ROOT).join(path)
+}
+
+/// An error thrown when an action fails.
+#[derive(Debug)]
+pub struct ExecutionError(pub String);
+
+impl Display for ExecutionError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for ExecutionError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
@@ -0,0 +1,734 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::path::PathBuf;
+use std::rc::Rc;
+use crate::build_file::{label_order, quote, strip_trailing_comments};
+use crate::host::host::{list_all_files, Host};
+use crate::host::source_writer::SourceWriter;
+use crate::package::BUILD_FILE_NAMES;
+use crate::starlark::error::{EvalError, Location};
+use crate::starlark::lexer::{tokenize_with_comments, Comment, Spanned, Token};
+use crate::workspace::WORKSPACE_FILE;
+
+/// The priorities of attributes which are not printed in their original
+/// order, the same as buildifier's. Others have priority 0 and keep their
+/// relative order.
+const ATTRIBUTE_ORDER: [(&str, i32); 10] = [
+  ("name", -99),
+  ("package_name", -97),
+  ("testonly", -93),
+  ("src", -92),
+  ("srcs", -90),
+  ("out", -89),
+  ("outs", -88),
+  ("hdrs", -87),
+  ("exports", 2),
+  ("deps", 4),
+];
+
+/// The attributes whose string lists are sorted and deduplicated.
+const SORTED_ATTRIBUTES: [&str; 4] = ["data", "deps", "exports", "srcs"];
+
+/// Calls whose arguments would make a line longer are split one per line.
+const MAX_LINE_LENGTH: usize = 100;
+
+/// Returns the BUILD, `.bzl` and `MODULE.razel` files under the given
+/// workspace-relative paths, or in the whole workspace without any.
+pub fn starlark_files(host: &dyn Host, paths: &[PathBuf]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+  let roots = if paths.is_empty() { vec![PathBuf::new()] } else { paths.to_vec() };
+  let mut files = Vec::new();
+  for root in roots {
+    let listed = match host.read(&root) {
+      Ok(_) => vec![root],
+      Err(_) => list_all_files(host, &root)?,
+    };
+    files.extend(listed.into_iter().filter(|path| {
+      let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+      BUILD_FILE_NAMES.contains(&name) || name == WORKSPACE_FILE || name.ends_with(".bzl")
+    }));
+  }
+  files.sort();
+  files.dedup();
+  Ok(files)
+}
+
+/// Formats the BUILD, `.bzl` and `MODULE.razel` files under the given
+/// workspace-relative paths, or in the whole workspace without any. Returns
+/// the files which were not formatted, leaving them unchanged if `check`.
+pub fn format_files(host: &dyn Host, paths: &[PathBuf], check: bool) ->
+    Result<Vec<PathBuf>, Box<dyn Error>> {
+  let mut changed = Vec::new();
+  for path in starlark_files(host, paths)? {
+    let source = host.read_to_string(&path)?;
+    let formatted = format(&path.to_string_lossy(), &source)?;
+    if formatted != source {
+      if !check {
+        SourceWriter::new(host).write(&path, formatted.as_bytes())?;
+      }
+      changed.push(path);
+    }
+  }
+  Ok(changed)
+}
+
+/// Formats a BUILD or `.bzl` file canonically, preserving its comments.
+/// Top-level calls are printed with one argument per line unless they have a
+/// single short argument, with attributes in a canonical order and `srcs`,
+/// `deps` and similar string lists sorted and deduplicated. Other statements
+/// and values are re-indented by 4 spaces per block and open bracket but
+/// otherwise kept as written. Blank lines are collapsed and the file ends with
+/// a single newline.
+pub fn format(file: &str, source: &str) -> Result<String, EvalError> {
+  let (tokens, comments) = tokenize_with_comments(&Rc::from(file), source)?;
+  let formatter = Formatter::new(source, &tokens, &comments);
+
+  let mut lines: Vec<String> = Vec::new();
+  let mut gap_start = 0;
+  for (first, newline) in formatter.statements() {
+    let start = formatter.offset(&tokens[first].location);
+    lines.extend(source[gap_start..start].lines().map(|line| line.trim_end().to_owned()));
+
+    let end = formatter.offset(&tokens[newline].location);
+    let statement = match formatter.call(first, newline) {
+      Some(call) => call,
+      None if formatter.call_kind(first, newline).is_some() => {
+        source[start..end].lines().map(str::trim_end).collect::<Vec<_>>().join("\n")
+      },
+      None => formatter.reindent(first, end, 0),
+    };
+    lines.extend(statement.lines().map(str::to_owned));
+    gap_start = (end + 1).min(source.len());
+  }
+  lines.extend(source[gap_start..].lines().map(|line| line.trim_end().to_owned()));
+
+  let mut formatted = String::new();
+  let mut blank = true;
+  for line in lines {
+    if line.is_empty() {
+      blank = true;
+      continue;
+    }
+    if blank && !formatted.is_empty() {
+      formatted.push('\n');
+    }
+    blank = false;
+    formatted.push_str(&line);
+    formatted.push('\n');
+  }
+  Ok(formatted)
+}
+
+struct Formatter<'a> {
+  source: &'a str,
+  tokens: &'a [Spanned],
+
+  /// The comments with their byte offsets and whether they are on a line of
+  /// their own.
+  comments: Vec<(usize, bool, &'a Comment)>,
+
+  line_starts: Vec<usize>,
+}
+
+/// An argument of a call, as token indices and byte offsets.
+struct Argument {
+  name: Option<String>,
+
+  /// The index of the first token of the value, and of the `,` or `)` after
+  /// it.
+  value_tokens: (usize, usize),
+
+  start: usize,
+  value_start: usize,
+  value_end: usize,
+
+  /// Full-line comments before the argument, and the comment after it on its
+  /// last line.
+  leading: Vec<String>,
+  trailing: Option<String>,
+}
+
+/// An element of a string list with its comments.
+struct Element {
+  value: String,
+  leading: Vec<String>,
+  trailing: Option<String>,
+}
+
+impl<'a> Formatter<'a> {
+  fn new(source: &'a str, tokens: &'a [Spanned], comments: &'a [Comment]) -> Formatter<'a> {
+    let line_starts: Vec<usize> = [0].into_iter()
+      .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+      .collect();
+    let mut formatter = Formatter { source, tokens, comments: Vec::new(), line_starts };
+    formatter.comments = comments.iter()
+      .map(|comment| {
+        let offset = formatter.offset(&comment.location);
+        let line_start = formatter.line_starts[comment.location.line - 1];
+        (offset, source[line_start..offset].trim().is_empty(), comment)
+      })
+      .collect();
+    formatter
+  }
+
+  fn offset(&self, location: &Location) -> usize {
+    let start = self.line_starts[location.line - 1];
+    self.source[start..].char_indices().nth(location.column - 1)
+      .map_or(self.source.len(), |(index, _)| start + index)
+  }
+
+  fn line_of(&self, offset: usize) -> usize {
+    self.line_starts.partition_point(|start| *start <= offset)
+  }
+
+  /// Returns the top-level statements as the indices of their first token
+  /// and of the `Newline` token ending them.
+  fn statements(&self) -> Vec<(usize, usize)> {
+    let mut statements = Vec::new();
+    let mut level = 0;
+    let mut first = None;
+    for (i, spanned) in self.tokens.iter().enumerate() {
+      match spanned.token {
+        Token::Indent => level += 1,
+        Token::Dedent => level -= 1,
+        Token::Eof => {},
+        Token::Newline => {
+          let dedents = self.tokens[i + 1..].iter()
+            .take_while(|next| next.token == Token::Dedent)
+            .count();
+          let indented = self.tokens.get(i + 1)
+            .is_some_and(|next| next.token == Token::Indent);
+          if let (Some(start), false, 0) = (first, indented, level - dedents as i32) {
+            statements.push((start, i));
+            first = None;
+          }
+        },
+        _ if first.is_none() && level == 0 => first = Some(i),
+        _ => {},
+      }
+    }
+    statements
+  }
+
+  /// Returns the name of the function a statement calls if it is a single
+  /// call.
+  fn call_kind(&self, first: usize, newline: usize) -> Option<&str> {
+    let kind = match &self.tokens[first].token {
+      Token::Ident(kind) => kind.as_str(),
+      Token::Keyword("load") => "load",
+      _ => return None,
+    };
+    let close = newline - 1;
+    if self.tokens.get(first + 1)?.token != Token::Punct("(")
+        || self.tokens[close].token != Token::Punct(")")
+        || self.matching(first + 1) != Some(close) {
+      return None;
+    }
+    Some(kind)
+  }
+
+  /// Formats a statement which is a single call, or returns `None` if it is
+  /// not one or should be kept as written, such as when a comment is
+  /// somewhere it cannot be moved from.
+  fn call(&self, first: usize, newline: usize) -> Option<String> {
+    let kind = self.call_kind(first, newline)?;
+    let close = newline - 1;
+    let open_offset = self.offset(&self.tokens[first + 1].location);
+    let close_offset = self.offset(&self.tokens[close].location);
+    let end = self.offset(&self.tokens[newline].location);
+    let open_line = self.tokens[first].location.line;
+    let close_line = self.tokens[close].location.line;
+
+    let mut args = self.arguments(first + 2, close);
+    let mut open_comment = None;
+    let mut close_comment = None;
+    let mut footer = Vec::new();
+    let comments = self.comments.iter()
+      .filter(|(offset, ..)| *offset > open_offset && *offset < end);
+    for &(offset, full_line, comment) in comments {
+      let line = comment.location.line;
+      let text = comment.text.clone();
+      if let Some(arg) = args.iter().find(|arg| offset >= arg.start && offset < arg.value_end) {
+        if offset < arg.value_start {
+          return None;
+        }
+        continue;
+      }
+      if offset > close_offset && line == close_line {
+        close_comment = Some(text);
+      } else if full_line {
+        match args.iter_mut().find(|arg| arg.start > offset) {
+          Some(arg) => arg.leading.push(text),
+          None => footer.push(text),
+        }
+      } else if let Some(arg) = args.iter_mut().rev().find(|arg| {
+        arg.value_end <= offset && self.line_of(arg.value_end) == line
+      }) {
+        arg.trailing.get_or_insert(text);
+      } else if line == open_line && open_comment.is_none() {
+        open_comment = Some(text);
+      } else {
+        return None;
+      }
+    }
+
+    if kind == "load" {
+      let module = args.iter().position(|arg| arg.name.is_none())?;
+      let module = args.remove(module);
+      args.sort_by_cached_key(|arg| self.load_symbol(arg));
+      args.dedup_by(|a, b| {
+        self.load_symbol(a) == self.load_symbol(b) && a.leading.is_empty() && a.trailing.is_none()
+      });
+      args.insert(0, module);
+    } else {
+      args.sort_by_key(|arg| match &arg.name {
+        None => i32::MIN,
+        Some(name) => ATTRIBUTE_ORDER.iter()
+          .find(|(attr, _)| attr == name)
+          .map_or(0, |(_, priority)| *priority),
+      });
+    }
+
+    let commented = open_comment.is_some() || !footer.is_empty()
+      || args.iter().any(|arg| !arg.leading.is_empty() || arg.trailing.is_some());
+    if !commented && (kind == "load" || args.len() <= 1) {
+      let values: Option<Vec<String>> = args.iter()
+        .map(|arg| self.argument(arg, 0, true))
+        .collect();
+      let line = format!("{}({})", kind, values?.join(", "));
+      if !line.contains('\n') && line.len() <= MAX_LINE_LENGTH {
+        return Some(match close_comment {
+          Some(text) => format!("{}  {}", line, text),
+          None => line,
+        });
+      }
+    }
+
+    // A comment after a call on a single line stays on its first line.
+    if open_line == close_line && open_comment.is_none() {
+      open_comment = close_comment.take();
+    }
+    let open_comment = open_comment.map(|text| format!("  {}", text));
+    let mut formatted = format!("{}({}\n", kind, open_comment.unwrap_or_default());
+    for arg in &args {
+      for text in &arg.leading {
+        formatted.push_str(&format!("    {}\n", text));
+      }
+      let trailing = arg.trailing.as_ref().map(|text| format!("  {}", text)).unwrap_or_default();
+      formatted.push_str(&format!("    {},{}\n", self.argument(arg, 4, false)?, trailing));
+    }
+    for text in &footer {
+      formatted.push_str(&format!("    {}\n", text));
+    }
+    formatted.push(')');
+    if let Some(text) = close_comment {
+      formatted.push_str(&format!("  {}", text));
+    }
+    Some(formatted)
+  }
+
+  /// Returns the index of the bracket closing the one at `open`.
+  fn matching(&self, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, spanned) in self.tokens.iter().enumerate().skip(open) {
+      match spanned.token {
+        Token::Punct("(" | "[" | "{") => depth += 1,
+        Token::Punct(")" | "]" | "}") => {
+          depth -= 1;
+          if depth == 0 {
+            return Some(i);
+          }
+        },
+        _ => {},
+      }
+    }
+    None
+  }
+
+  /// Splits the tokens between the parentheses of a call into arguments.
+  fn arguments(&self, start: usize, close: usize) -> Vec<Argument> {
+    let mut args = Vec::new();
+    let mut i = start;
+    while i < close {
+      let mut end = i;
+      let mut depth = 0;
+      while end < close && (depth > 0 || self.tokens[end].token != Token::Punct(",")) {
+        match self.tokens[end].token {
+          Token::Punct("(" | "[" | "{") => depth += 1,
+          Token::Punct(")" | "]" | "}") => depth -= 1,
+          _ => {},
+        }
+        end += 1;
+      }
+      let (name, value) = match (&self.tokens[i].token, &self.tokens[i + 1].token) {
+        (Token::Ident(name), Token::Punct("=")) if i + 2 < end => (Some(name.clone()), i + 2),
+        _ => (None, i),
+      };
+      let value_start = self.offset(&self.tokens[value].location);
+      let source = &self.source[value_start..self.offset(&self.tokens[end].location)];
+      let value_end = value_start + strip_trailing_comments(source).len();
+      args.push(Argument {
+        name,
+        value_tokens: (value, end),
+        start: self.offset(&self.tokens[i].location),
+        value_start,
+        value_end,
+        leading: Vec::new(),
+        trailing: None,
+      });
+      i = end + 1;
+    }
+    args
+  }
+
+  /// Returns the name a `load()` argument binds.
+  fn load_symbol(&self, arg: &Argument) -> String {
+    match (&arg.name, &self.tokens[arg.value_tokens.0].token) {
+      (Some(name), _) => name.clone(),
+      (None, Token::Str(symbol)) => symbol.clone(),
+      _ => String::new(),
+    }
+  }
+
+  /// Formats an argument starting at column `indent`. String lists are
+  /// printed on one line when `inline`.
+  fn argument(&self, arg: &Argument, indent: usize, inline: bool) -> Option<String> {
+    let sorted = arg.name.as_deref().is_some_and(|name| SORTED_ATTRIBUTES.contains(&name));
+    let value = self.value(arg, indent, inline, sorted)?;
+    Some(match &arg.name {
+      Some(name) => format!("{} = {}", name, value),
+      None => value,
+    })
+  }
+
+  fn value(&self, arg: &Argument, indent: usize, inline: bool, sorted: bool) -> Option<String> {
+    let (first, end) = arg.value_tokens;
+    let tokens = &self.tokens[first..end];
+    if let [Spanned { token: Token::Str(value), .. }] = tokens {
+      if !value.contains('\n') {
+        return Some(quote(value));
+      }
+    }
+    if let Some(elements) = self.string_list(first, end, arg.value_end) {
+      return Some(format_elements(elements, indent, inline, sorted));
+    }
+
+    Some(self.reindent(first, arg.value_end, indent))
+  }
+
+  /// Prints the source from the token at `first` up to the byte offset `end`
+  /// for a first line starting at column `indent`. Later lines are indented by
+  /// 4 spaces more per block, per bracket opened on an earlier line and for
+  /// a line continued with a backslash. Comment lines are indented like the
+  /// code after them, or before them when they are indented further, such as
+  /// at the end of a block. Lines within multi-line strings are kept as
+  /// written.
+  fn reindent(&self, first: usize, end: usize, indent: usize) -> String {
+    let first_line = self.tokens[first].location.line;
+    let old_indent = |line: usize| {
+      let text = &self.source[self.line_starts[line - 1]..];
+      text.chars().take_while(|c| *c == ' ' || *c == '\t').count()
+    };
+
+    // The old and new indentation of each line starting with a token, and
+    // whether it continues the previous line with a backslash.
+    let mut indents: BTreeMap<usize, (usize, usize, bool)> = BTreeMap::new();
+    let mut in_strings = HashSet::new();
+    let mut blocks = vec![indent];
+    let mut indented = false;
+    // The indentation of the lines opening the brackets which are open, and
+    // of the first line of the current statement.
+    let mut brackets: Vec<usize> = Vec::new();
+    let mut statement = None;
+    let mut line_indent = indent;
+    for (i, spanned) in self.tokens.iter().enumerate().skip(first) {
+      if self.offset(&spanned.location) >= end {
+        break;
+      }
+      let line = spanned.location.line;
+      match &spanned.token {
+        Token::Indent => indented = true,
+        Token::Dedent => { blocks.pop(); },
+        Token::Newline => statement = None,
+        token => {
+          if !indents.contains_key(&line) && !in_strings.contains(&line) {
+            line_indent = match (line == first_line, brackets.last(), statement) {
+              (true, ..) => indent,
+              (_, Some(&open), _) if matches!(token, Token::Punct(")" | "]" | "}")) => open,
+              (_, Some(&open), _) => open + 4,
+              (_, None, Some(statement)) => statement + 4,
+              (_, None, None) if indented => {
+                blocks.push(blocks.last().unwrap() + 4);
+                *blocks.last().unwrap()
+              },
+              (_, None, None) => *blocks.last().unwrap(),
+            };
+            indented = false;
+            let continued = brackets.is_empty() && statement.is_some();
+            statement.get_or_insert(line_indent);
+            indents.insert(line, (old_indent(line), line_indent, continued));
+          }
+          match token {
+            Token::Punct("(" | "[" | "{") => brackets.push(line_indent),
+            Token::Punct(")" | "]" | "}") => { brackets.pop(); },
+            Token::Str(_) => {
+              let next_line = self.tokens.get(i + 1).map_or(line, |next| next.location.line);
+              in_strings.extend(line + 1..=next_line);
+            },
+            _ => {},
+          }
+        },
+      }
+    }
+
+    let start = self.offset(&self.tokens[first].location);
+    let mut lines = Vec::new();
+    for (line, text) in (first_line..).zip(self.source[start..end].lines()) {
+      let trimmed = text.trim();
+      let new_indent = if line == first_line || in_strings.contains(&line) {
+        lines.push(if in_strings.contains(&line) { text } else { text.trim_end() }.to_owned());
+        continue;
+      } else if let Some((_, new_indent, _)) = indents.get(&line) {
+        *new_indent
+      } else if trimmed.is_empty() {
+        lines.push(String::new());
+        continue;
+      } else {
+        let old = old_indent(line);
+        let previous = indents.range(..line).rev()
+          .find(|(_, (previous_old, _, continued))| !continued && *previous_old <= old)
+          .map(|(_, (_, previous_new, _))| *previous_new);
+        match (indents.range(line..).next().map(|(_, indent)| *indent), previous) {
+          (Some((next_old, ..)), Some(previous_new)) if old > next_old => previous_new,
+          (Some((_, next_new, _)), _) => next_new,
+          (None, Some(previous_new)) => previous_new,
+          (None, None) => indent,
+        }
+      };
+      lines.push(format!("{}{}", " ".repeat(new_indent), trimmed));
+    }
+    lines.join("\n")
+  }
+
+  /// Returns the elements of a value which is a list of string literals with
+  /// their comments, or `None` for any other value or when a comment is not
+  /// next to an element.
+  fn string_list(&self, first: usize, end: usize, value_end: usize) -> Option<Vec<Element>> {
+    let tokens = &self.tokens[first..end];
+    let brackets = (&tokens.first()?.token, &tokens.last()?.token);
+    let (Token::Punct("["), Token::Punct("]")) = brackets else {
+      return None;
+    };
+    let mut elements: Vec<(usize, usize, Element)> = Vec::new();
+    for (i, spanned) in tokens[1..tokens.len() - 1].iter().enumerate() {
+      match (&spanned.token, i % 2) {
+        (Token::Str(value), 0) => {
+          let element = Element {
+            value: value.clone(),
+            leading: Vec::new(),
+            trailing: None,
+          };
+          let location = &spanned.location;
+          elements.push((self.offset(location), location.line, element));
+        },
+        (Token::Punct(","), 1) => {},
+        _ => return None,
+      }
+    }
+
+    let open = self.offset(&tokens[0].location);
+    let open_line = tokens[0].location.line;
+    let comments = self.comments.iter()
+      .filter(|(offset, ..)| *offset > open && *offset < value_end);
+    for &(offset, full_line, comment) in comments {
+      let line = comment.location.line;
+      let text = comment.text.clone();
+      if full_line {
+        let (_, _, element) = elements.iter_mut().find(|(start, ..)| *start > offset)?;
+        element.leading.push(text);
+      } else if line != open_line || elements.first()
+          .is_some_and(|(_, first_line, _)| *first_line == open_line) {
+        let (_, _, element) = elements.iter_mut().rev()
+          .find(|(start, element_line, _)| *start < offset && *element_line == line)?;
+        if element.trailing.replace(text).is_some() {
+          return None;
+        }
+      } else {
+        return None;
+      }
+    }
+    Some(elements.into_iter().map(|(_, _, element)| element).collect())
+  }
+}
+
+/// Formats the elements of a string list, sorting and deduplicating them if
+/// `sorted`. Lists are printed on one line if they have no comments and
+/// either `inline` or at most one element.
+fn format_elements(mut elements: Vec<Element>, indent: usize, inline: bool,
+    sorted: bool) -> String {
+  if sorted {
+    elements.sort_by(|a, b| label_order(&a.value).cmp(&label_order(&b.value)));
+    elements.dedup_by(|a, b| {
+      a.value == b.value && a.leading.is_empty() && a.trailing.is_none()
+    });
+  }
+  let commented = elements.iter()
+    .any(|element| !element.leading.is_empty() || element.trailing.is_some());
+  if !commented && (inline || elements.len() <= 1) {
+    let values: Vec<_> = elements.iter().map(|element| quote(&element.value)).collect();
+    return format!("[{}]", values.join(", "));
+  }
+
+  let mut list = "[\n".to_owned();
+  let padding = " ".repeat(indent + 4);
+  for element in elements {
+    for text in &element.leading {
+      list.push_str(&format!("{}{}\n", padding, text));
+    }
+    let trailing = element.trailing.map(|text| format!("  {}", text)).unwrap_or_default();
+    list.push_str(&format!("{}{},{}\n", padding, quote(&element.value), trailing));
+  }
+  list.push_str(&" ".repeat(indent));
+  list.push(']');
+  list
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn format_prints_calls_canonically_and_keeps_comments() -> Result<(), EvalError> {
+    let source = r#"
+# The app.
+load("@razel//js:defs.bzl", "ts_library", "js_library")
+
+
+package(default_visibility = [ "//visibility:public" ])
+ts_library(
+  deps = ["//lib", ":util",
+    # Generated.
+    ":gen",  # keep
+    "@npm//:react", ":util"],
+  name = "app", srcs = glob(["*.ts"]),  # All sources.
+  visibility = [
+      "//a",
+      "//b",
+  ],
+)
+js_library(name = "js")  # keep
+
+def helper(x):
+    # Indented.
+    return x
+"#;
+    let formatted = format("BUILD", source)?;
+    assert_eq!(formatted, r#"# The app.
+load("@razel//js:defs.bzl", "js_library", "ts_library")
+
+package(default_visibility = ["//visibility:public"])
+ts_library(
+    name = "app",
+    srcs = glob(["*.ts"]),  # All sources.
+    visibility = [
+        "//a",
+        "//b",
+    ],
+    deps = [
+        # Generated.
+        ":gen",  # keep
+        ":util",
+        "//lib",
+        "@npm//:react",
+    ],
+)
+js_library(name = "js")  # keep
+
+def helper(x):
+    # Indented.
+    return x
+"#);
+    assert_eq!(format("BUILD", &formatted)?, formatted);
+    Ok(())
+  }
+
+  #[test]
+  fn format_reindents_blocks_brackets_and_continuation_lines() -> Result<(), EvalError> {
+    let source = r#"
+def _impl(ctx):
+  """Builds it.
+
+      Indented docs stay.
+  """
+  # Inputs.
+  srcs = [
+          f for f in ctx.files.srcs
+     if f.extension == "ts"]
+  if srcs:
+        total = len(srcs) + \
+  1
+        # At the end of the block.
+  ctx.actions.run(
+    outputs = [ctx.outputs.out],
+      arguments = ["--out", ctx.outputs.out.path,
+  ctx.attr.flag],
+  )
+
+ENV = {
+  "A": "1",
+        "B": {
+   "C": "2",
+          },
+    }
+genrule(
+    name = "gen",
+    env = {
+  "A": "1",
+          "B": "2",
+      },
+)
+"#;
+    let formatted = format("defs.bzl", source)?;
+    assert_eq!(formatted, r#"def _impl(ctx):
+    """Builds it.
+
+      Indented docs stay.
+  """
+    # Inputs.
+    srcs = [
+        f for f in ctx.files.srcs
+        if f.extension == "ts"]
+    if srcs:
+        total = len(srcs) + \
+            1
+        # At the end of the block.
+    ctx.actions.run(
+        outputs = [ctx.outputs.out],
+        arguments = ["--out", ctx.outputs.out.path,
+            ctx.attr.flag],
+    )
+
+ENV = {
+    "A": "1",
+    "B": {
+        "C": "2",
+    },
+}
+genrule(
+    name = "gen",
+    env = {
+        "A": "1",
+        "B": "2",
+    },
+)
+"#);
+    assert_eq!(format("defs.bzl", &formatted)?, formatted);
+    Ok(())
+  }
+
+  #[test]
+  fn format_keeps_calls_with_comments_it_cannot_move() -> Result<(), EvalError> {
+    let source = "rule(\n    name = # The name.\n        \"x\",\n)\n";
+    assert_eq!(format("BUILD", source)?, source);
+    Ok(())
+  }
+}
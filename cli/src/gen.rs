@@ -0,0 +1,409 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use serde_json::Value as Json;
+use crate::analysis::strict_deps::{module_keys, scan_imports, Specifier, TsPaths};
+use crate::build_file::{format_list, label_order, BuildFileEditor};
+use crate::host::host::{list_all_files, Host};
+use crate::host::source_writer::SourceWriter;
+use crate::module::Module;
+use crate::package::BUILD_FILE_NAMES;
+use crate::repository::RepositoryRule;
+
+/// The extensions of sources of a `ts_library`, including declarations.
+const TS_EXTENSIONS: [&str; 4] = [".ts", ".tsx", ".mts", ".cts"];
+
+/// The extensions of sources of a `js_library`.
+const JS_EXTENSIONS: [&str; 4] = [".js", ".jsx", ".mjs", ".cjs"];
+
+/// The fields of `package.json` declaring the packages the workspace may
+/// import.
+const DEPENDENCY_FIELDS: [&str; 4] = [
+  "dependencies", "devDependencies", "peerDependencies", "optionalDependencies",
+];
+
+/// The name of the npm repository when `MODULE.razel` declares none.
+const DEFAULT_NPM_REPO: &str = "npm";
+
+/// Creates or updates the BUILD files of every directory of the workspace
+/// containing TypeScript or JavaScript sources, with a `ts_library` and a
+/// `js_library` whose `srcs` are the sources and whose `deps` provide the
+/// modules they import. Existing rules keep their names and other attributes,
+/// and values or list elements marked with a `# keep` comment are preserved.
+/// Returns the BUILD files written.
+pub fn generate(host: &dyn Host) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+  let mut packages: BTreeMap<String, Package> = BTreeMap::new();
+  let mut build_files = BTreeMap::new();
+  for path in list_all_files(host, Path::new(""))? {
+    let skipped = path.components().any(|component| {
+      component.as_os_str().to_str()
+        .is_none_or(|name| name == "node_modules" || name.starts_with('.'))
+    });
+    let dir = path.parent().and_then(Path::to_str);
+    let file = path.file_name().and_then(|name| name.to_str());
+    let (Some(dir), Some(file)) = (dir, file) else {
+      continue;
+    };
+    if skipped {
+      continue;
+    }
+    if BUILD_FILE_NAMES.contains(&file) {
+      build_files.entry(dir.to_owned()).or_insert(path.clone());
+    } else if TS_EXTENSIONS.iter().any(|extension| file.ends_with(extension)) {
+      packages.entry(dir.to_owned()).or_default().ts_srcs.push(file.to_owned());
+    } else if JS_EXTENSIONS.iter().any(|extension| file.ends_with(extension)) {
+      packages.entry(dir.to_owned()).or_default().js_srcs.push(file.to_owned());
+    }
+  }
+
+  // Rules already declared keep their names, so names are settled before any
+  // import is resolved.
+  let mut editors = HashMap::new();
+  for (dir, package) in &mut packages {
+    let default = dir.rsplit('/').next()
+      .filter(|name| !name.is_empty())
+      .unwrap_or("root")
+      .to_owned();
+    let editor = match build_files.get(dir) {
+      Some(path) => Some(BuildFileEditor::parse(
+        &path.to_string_lossy(),
+        &host.read_to_string(path)?,
+      )?),
+      None => None,
+    };
+    let existing = |kind: &str, default: &str| {
+      let calls: Vec<&str> = editor.iter()
+        .flat_map(|editor| &editor.calls)
+        .filter(|call| call.kind == kind)
+        .filter_map(|call| call.name.as_deref())
+        .collect();
+      match calls.as_slice() {
+        [name] => name.to_string(),
+        calls => calls.iter().find(|name| **name == default).unwrap_or(&default).to_string(),
+      }
+    };
+    package.ts_name = existing("ts_library", &default);
+    let js_default = if package.ts_srcs.is_empty() {
+      default.clone()
+    } else {
+      format!("{}_js", default)
+    };
+    package.js_name = existing("js_library", &js_default);
+    if let Some(editor) = editor {
+      editors.insert(dir.clone(), (build_files[dir].clone(), editor));
+    }
+  }
+
+  let mut modules = HashMap::new();
+  for (dir, package) in &packages {
+    for (srcs, name) in [
+      (&package.ts_srcs, &package.ts_name),
+      (&package.js_srcs, &package.js_name),
+    ] {
+      for src in srcs {
+        for key in module_keys(&join(dir, src)) {
+          modules.entry(key).or_insert_with(|| (dir.clone(), name.clone()));
+        }
+      }
+    }
+  }
+
+  let resolver = Resolver {
+    modules,
+    paths: TsPaths::read(host)?,
+    packages: declared_packages(host)?,
+    npm_repo: Module::load(host)?.repositories.iter()
+      .find(|(_, rule)| matches!(rule, RepositoryRule::NpmLock { .. }))
+      .map_or(DEFAULT_NPM_REPO.to_owned(), |(name, _)| name.clone()),
+  };
+
+  let mut written = Vec::new();
+  for (dir, package) in &packages {
+    let (path, mut editor) = match editors.remove(dir) {
+      Some(existing) => existing,
+      None => {
+        let path = Path::new(dir).join(BUILD_FILE_NAMES[0]);
+        let source = "package(default_visibility = [\"//visibility:public\"])\n";
+        (path, BuildFileEditor::parse("", source)?)
+      },
+    };
+    let original = host.read_to_string(&path).ok();
+
+    for (kind, name, srcs) in [
+      ("ts_library", &package.ts_name, &package.ts_srcs),
+      ("js_library", &package.js_name, &package.js_srcs),
+    ] {
+      if srcs.is_empty() {
+        continue;
+      }
+      let mut deps = BTreeSet::new();
+      for src in srcs {
+        let path = join(dir, src);
+        for import in scan_imports(&host.read_to_string(Path::new(&path))?) {
+          deps.extend(resolver.resolve(&path, &import.specifier, kind == "ts_library"));
+        }
+      }
+      deps.remove(&(dir.clone(), name.clone()));
+      let mut deps: Vec<String> = deps.iter()
+        .map(|(pkg, target)| shorten(dir, pkg, target))
+        .collect();
+      deps.sort_by(|a, b| label_order(a).cmp(&label_order(b)));
+      let mut srcs = srcs.clone();
+      srcs.sort();
+
+      let call = editor.calls.iter()
+        .position(|call| call.kind == kind && call.name.as_ref() == Some(name));
+      match call {
+        Some(call) => update(&mut editor, call, &[("srcs", srcs), ("deps", deps)]),
+        None => editor.append(&format_rule(kind, name, &srcs, &deps)),
+      }
+    }
+
+    let contents = editor.finish();
+    if original.as_ref() != Some(&contents) {
+      SourceWriter::new(host).write(&path, contents.as_bytes())?;
+      written.push(path);
+    }
+  }
+
+  Ok(written)
+}
+
+/// The sources of a directory and the names of the rules building them.
+#[derive(Default)]
+struct Package {
+  ts_srcs: Vec<String>,
+  js_srcs: Vec<String>,
+  ts_name: String,
+  js_name: String,
+}
+
+/// Resolves import specifiers to the targets providing them, as package and
+/// target name pairs.
+struct Resolver {
+  /// Maps the module keys of every source to the target building it.
+  modules: HashMap<String, (String, String)>,
+
+  paths: TsPaths,
+
+  /// The packages `package.json` declares.
+  packages: BTreeSet<String>,
+
+  npm_repo: String,
+}
+
+impl Resolver {
+  /// Returns the targets providing a specifier imported by the file with the
+  /// given path, none for builtin modules, assets and unknown packages. The
+  /// `@types` package of an npm package is added for TypeScript.
+  fn resolve(&self, importer: &str, specifier: &str, typescript: bool) -> Vec<(String, String)> {
+    let exists = |key: &str| self.modules.contains_key(key);
+    let name = match self.paths.classify(importer, specifier, exists) {
+      Specifier::Module(key) => return self.modules.get(&key).cloned().into_iter().collect(),
+      Specifier::Builtin | Specifier::Asset => return Vec::new(),
+      Specifier::Package(name) => name,
+    };
+
+    let types = match name.strip_prefix('@') {
+      Some(scoped) => format!("@types/{}", scoped.replacen('/', "__", 1)),
+      None => format!("@types/{}", name),
+    };
+    [name, types].into_iter()
+      .take(if typescript { 2 } else { 1 })
+      .filter(|name| self.packages.contains(name))
+      .map(|name| (format!("@{}//", self.npm_repo), name))
+      .collect()
+  }
+}
+
+/// Returns the packages declared by the workspace's `package.json`.
+fn declared_packages(host: &dyn Host) -> Result<BTreeSet<String>, Box<dyn Error>> {
+  let Ok(source) = host.read_to_string(Path::new("package.json")) else {
+    return Ok(BTreeSet::new());
+  };
+  let package: Json = serde_json::from_str(&source)
+    .map_err(|err| GenError(format!("Failed to parse package.json: {}", err)))?;
+  Ok(DEPENDENCY_FIELDS.iter()
+    .flat_map(|field| package[field].as_object().into_iter().flatten())
+    .map(|(name, _)| name.clone())
+    .collect())
+}
+
+/// Sets list attributes of an existing rule, unless the rule or attribute is
+/// marked `# keep` or its value is not a list literal. Kept elements remain,
+/// before the generated ones.
+fn update(editor: &mut BuildFileEditor, call: usize, attrs: &[(&str, Vec<String>)]) {
+  if editor.calls[call].keep {
+    return;
+  }
+  let mut edits = Vec::new();
+  for (name, generated) in attrs {
+    let current = match editor.calls[call].attr(name) {
+      Some(attr) if attr.keep => continue,
+      Some(attr) => match &attr.strings {
+        Some(strings) => strings.clone(),
+        None => continue,
+      },
+      None if generated.is_empty() => continue,
+      None => Vec::new(),
+    };
+    let mut items: Vec<(String, bool)> = current.iter()
+      .filter(|(_, keep)| *keep)
+      .cloned()
+      .collect();
+    for item in generated {
+      if !items.iter().any(|(kept, _)| kept == item) {
+        items.push((item.clone(), false));
+      }
+    }
+    if items != current || editor.calls[call].attr(name).is_none() {
+      edits.push((*name, items));
+    }
+  }
+  for (name, items) in edits {
+    editor.set_list(call, name, &items);
+  }
+}
+
+/// Formats a new rule with one attribute per line.
+fn format_rule(kind: &str, name: &str, srcs: &[String], deps: &[String]) -> String {
+  let list = |items: &[String]| {
+    let items: Vec<_> = items.iter().map(|item| (item.clone(), false)).collect();
+    format_list(&items, 4)
+  };
+  let mut rule = format!("{}(\n    name = {:?},\n    srcs = {},\n", kind, name, list(srcs));
+  if !deps.is_empty() {
+    rule.push_str(&format!("    deps = {},\n", list(deps)));
+  }
+  rule.push_str(")\n");
+  rule
+}
+
+/// Returns the shortest label of a target as written in the package `from`.
+/// External targets are passed with their repository as the package, such as
+/// `@npm//`.
+fn shorten(from: &str, package: &str, name: &str) -> String {
+  if package.starts_with('@') {
+    format!("{}:{}", package, name)
+  } else if package == from {
+    format!(":{}", name)
+  } else if package.rsplit('/').next() == Some(name) {
+    format!("//{}", package)
+  } else {
+    format!("//{}:{}", package, name)
+  }
+}
+
+fn join(dir: &str, path: &str) -> String {
+  if dir.is_empty() { path.to_owned() } else { format!("{}/{}", dir, path) }
+}
+
+/// An error thrown when a configuration file BUILD files are generated from
+/// is invalid.
+#[derive(Debug)]
+pub struct GenError(pub String);
+
+impl Display for GenError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for GenError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::fs;
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+  use super::*;
+
+  #[test]
+  fn generate_creates_and_updates_build_files_from_imports() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/package.json"), TestContents::File(
+        r#"{
+  "dependencies": {"left-pad": "1.3.0"},
+  "devDependencies": {"@types/left-pad": "1.2.0"}
+}"#,
+      )),
+      (Path::new("wksp/tsconfig.json"), TestContents::File(r#"{
+  // Shared libraries.
+  "compilerOptions": {"baseUrl": ".", "paths": {"@lib/*": ["lib/*"]}}
+}"#)),
+      (Path::new("wksp/app/main.ts"), TestContents::File(r#"
+import { util } from "@lib/util";
+import pad from "left-pad";
+import { helper } from "./helper";
+import * as fs from "node:fs";
+import "./style.css";
+import "unknown";
+"#)),
+      (Path::new("wksp/app/helper.ts"), TestContents::File("export const helper = 1;")),
+      (Path::new("wksp/app/legacy.js"), TestContents::File(
+        "const { helper } = require('./helper');",
+      )),
+      (Path::new("wksp/lib/util.ts"), TestContents::File("export const util = 1;")),
+      (Path::new("wksp/lib/old.ts"), TestContents::File("import { util } from './util';")),
+      (Path::new("wksp/lib/BUILD.razel"), TestContents::File(r#"# Shared code.
+ts_library(
+    name = "shared",
+    srcs = ["old.ts"],
+    deps = [
+        "//third_party:polyfills",  # keep
+        "//gone",
+    ],
+    visibility = ["//visibility:public"],
+)
+"#)),
+      (Path::new("wksp/node_modules/left-pad/index.js"), TestContents::File("")),
+    ])?;
+    let host = FsHost::with_output_base(&dir.root.join("wksp"), &dir.root.join("out"))?;
+
+    let written = generate(&host)?;
+    assert_eq!(written, [Path::new("app/BUILD.razel"), Path::new("lib/BUILD.razel")]);
+    let app = fs::read_to_string(dir.root.join("wksp/app/BUILD.razel"))?;
+    assert_eq!(app, r#"package(default_visibility = ["//visibility:public"])
+
+ts_library(
+    name = "app",
+    srcs = [
+        "helper.ts",
+        "main.ts",
+    ],
+    deps = [
+        "//lib:shared",
+        "@npm//:@types/left-pad",
+        "@npm//:left-pad",
+    ],
+)
+
+js_library(
+    name = "app_js",
+    srcs = ["legacy.js"],
+    deps = [":app"],
+)
+"#);
+    assert_eq!(fs::read_to_string(dir.root.join("wksp/lib/BUILD.razel"))?, r#"# Shared code.
+ts_library(
+    name = "shared",
+    srcs = [
+        "old.ts",
+        "util.ts",
+    ],
+    deps = [
+        "//third_party:polyfills",  # keep
+    ],
+    visibility = ["//visibility:public"],
+)
+"#);
+
+    assert_eq!(generate(&host)?, Vec::<PathBuf>::new());
+    Ok(())
+  }
+}
@@ -0,0 +1,363 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::host::host::{Entry, EntryKind, Host};
+use crate::host::path_pattern::PathPattern;
+use crate::host::walk::{walk, WalkOptions};
+use crate::label::Label;
+use crate::package::BUILD_FILE_NAMES;
+
+/// The arguments of a `glob()` call in a BUILD file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlobArgs {
+  /// Package-relative patterns of paths to include.
+  pub include: Vec<String>,
+
+  /// Package-relative patterns of paths to remove from the included paths.
+  pub exclude: Vec<String>,
+
+  /// Whether directories are left out of the result even if they match.
+  pub exclude_directories: bool,
+
+  /// Whether it is acceptable for the glob not to match anything.
+  pub allow_empty: bool,
+}
+
+impl Default for GlobArgs {
+  fn default() -> GlobArgs {
+    GlobArgs {
+      include: Vec::new(),
+      exclude: Vec::new(),
+      exclude_directories: true,
+      allow_empty: true,
+    }
+  }
+}
+
+/// The result of evaluating a `glob()` call.
+#[derive(Debug, PartialEq)]
+pub struct GlobResult {
+  /// Labels of all the matched paths in sorted order, as if the package was in
+  /// the main repository.
+  pub labels: Vec<Label>,
+
+  /// Workspace-relative paths of every directory listed while evaluating the
+  /// glob, in sorted order. Adding or removing an entry in any of them may
+  /// change the result, so the package must be reloaded when they change.
+  pub dependencies: Vec<PathBuf>,
+}
+
+/// Evaluates a `glob()` call in the given package. Patterns support `*`, `?`
+/// and `**` and are resolved relative to the package directory. Matching does
+/// not descend into subpackages (directories with their own BUILD file) or
+/// paths ignored by `.razelignore`.
+pub fn glob(host: &dyn Host, package: &str, args: &GlobArgs) ->
+    Result<GlobResult, Box<dyn Error>> {
+  let include = parse_patterns(&args.include)?;
+  let exclude = parse_patterns(&args.exclude)?;
+  let root = Path::new(package);
+  let relative = |path: &Path| path.strip_prefix(root).unwrap().to_path_buf();
+  let is_match = |path: &Path| {
+    include.iter().any(|pattern| pattern.matches(path))
+      && !exclude.iter().any(|pattern| pattern.matches(path))
+  };
+
+  // Only descend into directories which could contain a match and are not
+  // subpackages.
+  let subpackages = Mutex::new(HashSet::new());
+  let descend = |dir: &Path, entries: &[Entry]| {
+    let is_subpackage = entries.iter().any(|entry| BUILD_FILE_NAMES.iter()
+      .any(|name| entry.path.file_name().unwrap() == *name));
+    if is_subpackage {
+      subpackages.lock().unwrap().insert(dir.to_path_buf());
+      return false;
+    }
+
+    let dir = relative(dir);
+    include.iter().any(|pattern| pattern.matches_descendant_of(&dir))
+      || (!args.exclude_directories && is_match(&dir))
+  };
+  let options = WalkOptions { descend: &descend, ..WalkOptions::default() };
+
+  let entries = walk(host, root, &options, |entries| {
+    entries.collect::<Result<Vec<_>, _>>()
+  })??;
+
+  let subpackages = subpackages.into_inner().unwrap();
+  let mut labels = Vec::new();
+  let mut dependencies = vec![root.to_path_buf()];
+  for entry in entries {
+    let included = match entry.kind {
+      EntryKind::File => true,
+      EntryKind::Directory => {
+        dependencies.push(entry.path.clone());
+        !args.exclude_directories && !subpackages.contains(&entry.path)
+      },
+      _ => false,
+    };
+
+    let path = relative(&entry.path);
+    if included && is_match(&path) {
+      labels.push(Label {
+        repo: String::new(),
+        package: package.to_owned(),
+        name: path.to_str().unwrap().to_owned(),
+      });
+    }
+  }
+
+  if labels.is_empty() && !args.allow_empty {
+    return Err(Box::new(GlobError(format!(
+      "glob({:?}) in package `//{}` did not match any files and \
+allow_empty = False.",
+      args.include,
+      package,
+    ))));
+  }
+
+  labels.sort();
+  dependencies.sort();
+  Ok(GlobResult { labels, dependencies })
+}
+
+fn parse_patterns(patterns: &[String]) -> Result<Vec<PathPattern>, Box<dyn Error>> {
+  patterns.iter().map(|pattern| {
+    let parsed = PathPattern::parse(pattern);
+    let is_invalid = pattern.is_empty()
+      || pattern.starts_with('/')
+      || pattern.split('/').any(|segment| segment == "." || segment == "..")
+      || parsed.has_partial_recursive_wildcard();
+    if is_invalid {
+      return Err(Box::new(GlobError(format!(
+        "Invalid glob pattern `{}`, patterns must be relative paths within the \
+package and `**` must be a complete path segment.",
+        pattern,
+      ))) as Box<dyn Error>);
+    }
+
+    Ok(parsed)
+  }).collect()
+}
+
+/// An error from evaluating a `glob()` call.
+#[derive(Debug)]
+pub struct GlobError(pub String);
+
+impl Display for GlobError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", &self.0)
+  }
+}
+
+impl Error for GlobError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use assertables::assert_contains;
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+
+  fn glob_names(host: &dyn Host, package: &str, args: GlobArgs) ->
+      Result<Vec<String>, Box<dyn Error>> {
+    Ok(glob(host, package, &args)?.labels.into_iter()
+      .map(|label| label.name)
+      .collect())
+  }
+
+  fn include(patterns: &[&str]) -> GlobArgs {
+    GlobArgs {
+      include: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+      ..GlobArgs::default()
+    }
+  }
+
+  #[test]
+  fn glob_matches_files_in_package() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("app/BUILD"), TestContents::File("")),
+      (Path::new("app/b.ts"), TestContents::File("")),
+      (Path::new("app/a.ts"), TestContents::File("")),
+      (Path::new("app/c.js"), TestContents::File("")),
+      (Path::new("app/nested/d.ts"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(
+      glob(&host, "app", &include(&["*.ts"]))?.labels,
+      vec![
+        Label { repo: "".to_owned(), package: "app".to_owned(), name: "a.ts".to_owned() },
+        Label { repo: "".to_owned(), package: "app".to_owned(), name: "b.ts".to_owned() },
+      ],
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn glob_matches_recursive_and_single_character_wildcards() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("BUILD"), TestContents::File("")),
+      (Path::new("src/a1.ts"), TestContents::File("")),
+      (Path::new("src/a22.ts"), TestContents::File("")),
+      (Path::new("src/nested/deep/b3.ts"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(
+      glob_names(&host, "", include(&["src/**/?[0-9].ts"]))?,
+      vec!["src/a1.ts", "src/nested/deep/b3.ts"],
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn glob_removes_excluded_paths() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("BUILD"), TestContents::File("")),
+      (Path::new("foo.ts"), TestContents::File("")),
+      (Path::new("foo.test.ts"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(
+      glob_names(&host, "", GlobArgs {
+        exclude: vec!["**/*.test.ts".to_owned()],
+        ..include(&["**/*.ts"])
+      })?,
+      vec!["foo.ts"],
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn glob_stops_at_subpackages() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("BUILD"), TestContents::File("")),
+      (Path::new("foo.ts"), TestContents::File("")),
+      (Path::new("lib/bar.ts"), TestContents::File("")),
+      (Path::new("sub/BUILD.razel"), TestContents::File("")),
+      (Path::new("sub/baz.ts"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    let result = glob(&host, "", &include(&["**/*.ts"]))?;
+    assert_eq!(
+      result.labels.into_iter().map(|label| label.name).collect::<Vec<_>>(),
+      vec!["foo.ts", "lib/bar.ts"],
+    );
+    assert_eq!(
+      result.dependencies,
+      vec![PathBuf::from(""), PathBuf::from("lib"), PathBuf::from("sub")],
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn glob_skips_ignored_paths() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new(".razelignore"), TestContents::File("node_modules/\n")),
+      (Path::new("BUILD"), TestContents::File("")),
+      (Path::new("index.js"), TestContents::File("")),
+      (Path::new("node_modules/dep/index.js"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(
+      glob_names(&host, "", include(&["**/*.js"]))?,
+      vec!["index.js"],
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn glob_only_includes_directories_when_requested() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("BUILD"), TestContents::File("")),
+      (Path::new("assets/logo.png"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(
+      glob_names(&host, "", include(&["**"]))?,
+      vec!["BUILD", "assets/logo.png"],
+    );
+    assert_eq!(
+      glob_names(&host, "", GlobArgs {
+        exclude_directories: false,
+        ..include(&["**"])
+      })?,
+      vec!["BUILD", "assets", "assets/logo.png"],
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn glob_follows_symlinks() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("BUILD"), TestContents::File("")),
+      (Path::new("real/foo.ts"), TestContents::File("")),
+      (Path::new("link"), TestContents::Symlink("real")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(
+      glob_names(&host, "", include(&["**/*.ts"]))?,
+      vec!["link/foo.ts", "real/foo.ts"],
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn glob_errors_on_empty_result_when_not_allowed() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("BUILD"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert!(glob_names(&host, "", include(&["*.ts"]))?.is_empty());
+    let err = glob(&host, "", &GlobArgs {
+      allow_empty: false,
+      ..include(&["*.ts"])
+    }).unwrap_err();
+    assert_contains!(err.to_string(), "did not match any files");
+
+    Ok(())
+  }
+
+  #[test]
+  fn glob_errors_on_invalid_patterns() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("BUILD"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    for pattern in ["/abs.ts", "../up.ts", "src/**.ts", ""] {
+      let err = glob(&host, "", &include(&[pattern])).unwrap_err();
+      assert_contains!(err.to_string(), "Invalid glob pattern");
+    }
+
+    Ok(())
+  }
+}
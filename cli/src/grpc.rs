@@ -0,0 +1,616 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// How long to wait for the server whenever the call cannot continue without
+/// hearing from it.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The bytes every HTTP/2 connection starts with.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// Frame types, flags and settings, from RFC 9113.
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+const INITIAL_WINDOW_SIZE: u16 = 0x4;
+const MAX_FRAME_SIZE: u16 = 0x5;
+
+/// The flow control window of a new connection or stream.
+const DEFAULT_WINDOW: i64 = 65_535;
+
+/// The largest frame either side sends or accepts until settings say
+/// otherwise.
+const DEFAULT_MAX_FRAME: usize = 16_384;
+
+/// The stream of the call, the first one a client opens.
+const CALL_STREAM: u32 = 1;
+
+/// A client-streaming gRPC call over HTTP/2 without TLS. Requests are sent as
+/// soon as the server's flow control windows allow, and its responses are
+/// returned once the call finishes.
+pub struct GrpcCall {
+  stream: TcpStream,
+
+  /// How many more bytes of data the server accepts on the connection and on
+  /// the call's stream.
+  connection_window: i64,
+  stream_window: i64,
+
+  /// The stream window the server's settings give new streams.
+  initial_window: i64,
+
+  max_frame: usize,
+
+  /// The response body received so far, as length-prefixed messages.
+  body: Vec<u8>,
+
+  /// A header block being received, and whether it ends the call.
+  header_block: Vec<u8>,
+  header_block_ends: bool,
+
+  /// The response headers and trailers which were sent as literal strings.
+  metadata: Vec<(String, String)>,
+
+  /// Whether the server ended the call.
+  closed: bool,
+}
+
+impl GrpcCall {
+  /// Starts a call of the method at `path`, such as
+  /// `/package.Service/Method`, on the server at `host:port`, optionally
+  /// prefixed with `grpc://`.
+  pub fn start(address: &str, path: &str) -> Result<GrpcCall, Box<dyn Error>> {
+    let address = address.strip_prefix("grpc://").unwrap_or(address);
+    let stream = TcpStream::connect(address)
+      .map_err(|err| {
+        GrpcError(format!("Cannot connect to {}: {}", address, err))
+      })?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    let mut call = GrpcCall {
+      stream,
+      connection_window: DEFAULT_WINDOW,
+      stream_window: DEFAULT_WINDOW,
+      initial_window: DEFAULT_WINDOW,
+      max_frame: DEFAULT_MAX_FRAME,
+      body: Vec::new(),
+      header_block: Vec::new(),
+      header_block_ends: false,
+      metadata: Vec::new(),
+      closed: false,
+    };
+
+    call.stream.write_all(PREFACE)?;
+    write_frame(&mut call.stream, SETTINGS, 0, 0, &[])?;
+    let mut block = Vec::new();
+    let headers = [
+      (":method", "POST"),
+      (":scheme", "http"),
+      (":path", path),
+      (":authority", address),
+      ("content-type", "application/grpc"),
+      ("te", "trailers"),
+    ];
+    for (name, value) in headers {
+      encode_header(name, value, &mut block);
+    }
+    write_frame(&mut call.stream, HEADERS, END_HEADERS, CALL_STREAM, &block)?;
+    Ok(call)
+  }
+
+  /// Sends a serialized request message, waiting for the server to accept
+  /// more data if its windows are full.
+  pub fn send(&mut self, message: &[u8]) -> Result<(), Box<dyn Error>> {
+    self.send_data(&length_prefixed(message), false)
+  }
+
+  /// Ends the requests and waits for the server to end the call. Returns the
+  /// serialized response messages, or fails if the call did not succeed.
+  /// The status is only checked if the server sent it as a literal string,
+  /// rather than Huffman coded, so callers should also check the responses.
+  pub fn finish(mut self) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    self.send_data(&[], true)?;
+    while !self.closed {
+      self.receive()?;
+    }
+
+    let metadata = |name: &str| self.metadata.iter()
+      .find(|(key, _)| key == name)
+      .map(|(_, value)| value.as_str());
+    match metadata("grpc-status") {
+      None | Some("0") => {},
+      Some(status) => return Err(Box::new(GrpcError(format!(
+        "The call failed with status {}: {}",
+        status,
+        metadata("grpc-message").unwrap_or_default(),
+      )))),
+    }
+    let mut responses = Vec::new();
+    while let Some(response) = take_message(&mut self.body)? {
+      responses.push(response);
+    }
+    Ok(responses)
+  }
+
+  /// Sends data on the call's stream in frames as large as the server's
+  /// windows and settings allow, optionally ending the stream.
+  fn send_data(&mut self, data: &[u8], end: bool) ->
+      Result<(), Box<dyn Error>> {
+    let mut rest = data;
+    loop {
+      let window = self.connection_window
+        .min(self.stream_window)
+        .min(self.max_frame as i64);
+      if window <= 0 && !rest.is_empty() {
+        self.receive()?;
+        if self.closed {
+          let message = "The server ended the call early.";
+          return Err(Box::new(GrpcError(message.to_owned())));
+        }
+        continue;
+      }
+
+      let size = rest.len().min(window.max(0) as usize);
+      let (chunk, remaining) = rest.split_at(size);
+      let flags = if remaining.is_empty() && end { END_STREAM } else { 0 };
+      if !chunk.is_empty() || flags != 0 {
+        write_frame(&mut self.stream, DATA, flags, CALL_STREAM, chunk)?;
+        self.connection_window -= chunk.len() as i64;
+        self.stream_window -= chunk.len() as i64;
+      }
+      if remaining.is_empty() {
+        return Ok(());
+      }
+      rest = remaining;
+    }
+  }
+
+  /// Receives and handles a single frame from the server.
+  fn receive(&mut self) -> Result<(), Box<dyn Error>> {
+    let Frame { kind, flags, stream_id, payload } = read_frame(&mut self.stream)?;
+    match kind {
+      SETTINGS if flags & ACK == 0 => {
+        for setting in payload.chunks_exact(6) {
+          let value = u32::from_be_bytes(setting[2..].try_into().unwrap());
+          match u16::from_be_bytes(setting[..2].try_into().unwrap()) {
+            INITIAL_WINDOW_SIZE => {
+              self.stream_window += value as i64 - self.initial_window;
+              self.initial_window = value as i64;
+            },
+            MAX_FRAME_SIZE => self.max_frame = value as usize,
+            _ => {},
+          }
+        }
+        write_frame(&mut self.stream, SETTINGS, ACK, 0, &[])?;
+      },
+      PING if flags & ACK == 0 => {
+        write_frame(&mut self.stream, PING, ACK, 0, &payload)?;
+      },
+      WINDOW_UPDATE => {
+        let increment = window_increment(&payload)?;
+        match stream_id {
+          0 => self.connection_window += increment,
+          CALL_STREAM => self.stream_window += increment,
+          _ => {},
+        }
+      },
+      DATA if stream_id == CALL_STREAM => {
+        self.body.extend_from_slice(unpad(flags, &payload)?);
+        self.closed = flags & END_STREAM != 0;
+        // Received data is acknowledged so the server can keep sending.
+        if !payload.is_empty() {
+          let increment = (payload.len() as u32).to_be_bytes();
+          write_frame(&mut self.stream, WINDOW_UPDATE, 0, 0, &increment)?;
+          if !self.closed {
+            let stream_id = CALL_STREAM;
+            write_frame(&mut self.stream, WINDOW_UPDATE, 0, stream_id, &increment)?;
+          }
+        }
+      },
+      HEADERS if stream_id == CALL_STREAM => {
+        let mut block = unpad(flags, &payload)?;
+        if flags & PRIORITY != 0 {
+          block = block.get(5..).ok_or_else(|| malformed("HEADERS"))?;
+        }
+        self.header_block = block.to_vec();
+        self.header_block_ends = flags & END_STREAM != 0;
+        if flags & END_HEADERS != 0 {
+          self.end_header_block()?;
+        }
+      },
+      CONTINUATION if stream_id == CALL_STREAM => {
+        self.header_block.extend_from_slice(&payload);
+        if flags & END_HEADERS != 0 {
+          self.end_header_block()?;
+        }
+      },
+      RST_STREAM if stream_id == CALL_STREAM => {
+        let code = payload.get(..4).ok_or_else(|| malformed("RST_STREAM"))?;
+        return Err(Box::new(GrpcError(format!(
+          "The server reset the call with error code {}.",
+          u32::from_be_bytes(code.try_into().unwrap()),
+        ))));
+      },
+      GOAWAY if !self.closed => {
+        let code = payload.get(4..8).ok_or_else(|| malformed("GOAWAY"))?;
+        return Err(Box::new(GrpcError(format!(
+          "The server closed the connection with error code {}: {}",
+          u32::from_be_bytes(code.try_into().unwrap()),
+          String::from_utf8_lossy(&payload[8..]),
+        ))));
+      },
+      _ => {},
+    }
+    Ok(())
+  }
+
+  fn end_header_block(&mut self) -> Result<(), Box<dyn Error>> {
+    let block = std::mem::take(&mut self.header_block);
+    self.metadata.extend(decode_headers(&block)?);
+    self.closed = self.header_block_ends;
+    Ok(())
+  }
+}
+
+/// A frame as its type, flags, stream and payload.
+struct Frame {
+  kind: u8,
+  flags: u8,
+  stream_id: u32,
+  payload: Vec<u8>,
+}
+
+fn read_frame(stream: &mut impl Read) -> Result<Frame, Box<dyn Error>> {
+  let mut header = [0; 9];
+  stream.read_exact(&mut header)?;
+  let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+  if length > DEFAULT_MAX_FRAME {
+    return Err(Box::new(GrpcError(format!(
+      "Received a frame of {} bytes, more than allowed.",
+      length,
+    ))));
+  }
+  let stream_id = u32::from_be_bytes(header[5..].try_into().unwrap());
+  let mut payload = vec![0; length];
+  stream.read_exact(&mut payload)?;
+  Ok(Frame {
+    kind: header[3],
+    flags: header[4],
+    stream_id: stream_id & 0x7fff_ffff,
+    payload,
+  })
+}
+
+fn write_frame(stream: &mut impl Write, kind: u8, flags: u8, stream_id: u32,
+    payload: &[u8]) -> Result<(), Box<dyn Error>> {
+  let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+  frame.extend([kind, flags]);
+  frame.extend(stream_id.to_be_bytes());
+  frame.extend_from_slice(payload);
+  stream.write_all(&frame)?;
+  Ok(())
+}
+
+fn window_increment(payload: &[u8]) -> Result<i64, Box<dyn Error>> {
+  let bytes = payload.get(..4).ok_or_else(|| malformed("WINDOW_UPDATE"))?;
+  Ok((u32::from_be_bytes(bytes.try_into().unwrap()) & 0x7fff_ffff) as i64)
+}
+
+/// Returns the payload of a DATA or HEADERS frame without its padding.
+fn unpad(flags: u8, payload: &[u8]) -> Result<&[u8], Box<dyn Error>> {
+  if flags & PADDED == 0 {
+    return Ok(payload);
+  }
+  let padding = *payload.first().ok_or_else(|| malformed("padded"))? as usize;
+  let end = payload.len()
+    .checked_sub(padding)
+    .filter(|end| *end >= 1)
+    .ok_or_else(|| malformed("padded"))?;
+  Ok(&payload[1..end])
+}
+
+/// Prefixes a message with the byte marking it uncompressed and its length,
+/// as gRPC sends messages.
+fn length_prefixed(message: &[u8]) -> Vec<u8> {
+  let mut bytes = vec![0];
+  bytes.extend((message.len() as u32).to_be_bytes());
+  bytes.extend_from_slice(message);
+  bytes
+}
+
+/// Removes the first complete length-prefixed message from a body, if any.
+fn take_message(body: &mut Vec<u8>) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+  if body.len() < 5 {
+    return Ok(None);
+  }
+  if body[0] != 0 {
+    let message = "Received a compressed message, which is not supported.";
+    return Err(Box::new(GrpcError(message.to_owned())));
+  }
+  let length = u32::from_be_bytes(body[1..5].try_into().unwrap()) as usize;
+  if body.len() < 5 + length {
+    return Ok(None);
+  }
+  let message = body[5..5 + length].to_vec();
+  body.drain(..5 + length);
+  Ok(Some(message))
+}
+
+/// Appends a header in HPACK as a literal which is not indexed, with both
+/// strings uncompressed.
+fn encode_header(name: &str, value: &str, block: &mut Vec<u8>) {
+  block.push(0x00);
+  for string in [name, value] {
+    encode_integer(string.len(), 7, 0x00, block);
+    block.extend_from_slice(string.as_bytes());
+  }
+}
+
+/// Appends an HPACK integer with an `prefix`-bit prefix after the bits of
+/// `flags`.
+fn encode_integer(value: usize, prefix: u32, flags: u8, block: &mut Vec<u8>) {
+  let max = (1 << prefix) - 1;
+  if value < max {
+    block.push(flags | value as u8);
+    return;
+  }
+  block.push(flags | max as u8);
+  let mut rest = value - max;
+  while rest >= 0x80 {
+    block.push((rest % 0x80) as u8 | 0x80);
+    rest /= 0x80;
+  }
+  block.push(rest as u8);
+}
+
+/// Returns the headers of an HPACK block which are literals with a literal
+/// name. Other representations, and strings which are Huffman coded, are
+/// skipped, so no table of earlier headers needs to be kept.
+fn decode_headers(block: &[u8]) ->
+    Result<Vec<(String, String)>, Box<dyn Error>> {
+  let mut headers = Vec::new();
+  let mut offset = 0;
+  while offset < block.len() {
+    let byte = block[offset];
+    if byte & 0x80 != 0 {
+      // Indexed header field.
+      decode_integer(block, &mut offset, 7)?;
+      continue;
+    }
+    if byte & 0xe0 == 0x20 {
+      // Dynamic table size update.
+      decode_integer(block, &mut offset, 5)?;
+      continue;
+    }
+    // Literal with incremental indexing, without indexing or never indexed.
+    let prefix = if byte & 0xc0 == 0x40 { 6 } else { 4 };
+    let name = match decode_integer(block, &mut offset, prefix)? {
+      0 => decode_string(block, &mut offset)?,
+      _ => None,
+    };
+    let value = decode_string(block, &mut offset)?;
+    if let (Some(name), Some(value)) = (name, value) {
+      headers.push((name, value));
+    }
+  }
+  Ok(headers)
+}
+
+fn decode_integer(block: &[u8], offset: &mut usize, prefix: u32) ->
+    Result<usize, Box<dyn Error>> {
+  let max = (1 << prefix) - 1;
+  let first = *block.get(*offset).ok_or_else(|| malformed("header"))?;
+  let mut value = first as usize & max;
+  *offset += 1;
+  if value < max {
+    return Ok(value);
+  }
+  let mut shift = 0;
+  loop {
+    let byte = *block.get(*offset).ok_or_else(|| malformed("header"))?;
+    *offset += 1;
+    if shift > 28 {
+      return Err(Box::new(malformed("header")));
+    }
+    value = value.checked_add(((byte & 0x7f) as usize) << shift)
+      .ok_or_else(|| malformed("header"))?;
+    shift += 7;
+    if byte & 0x80 == 0 {
+      return Ok(value);
+    }
+  }
+}
+
+/// Decodes an HPACK string, or skips it returning `None` if Huffman coded.
+fn decode_string(block: &[u8], offset: &mut usize) ->
+    Result<Option<String>, Box<dyn Error>> {
+  let huffman = block.get(*offset).is_some_and(|byte| byte & 0x80 != 0);
+  let length = decode_integer(block, offset, 7)?;
+  let bytes = block.get(*offset..*offset + length)
+    .ok_or_else(|| malformed("header"))?;
+  *offset += length;
+  Ok((!huffman).then(|| String::from_utf8_lossy(bytes).into_owned()))
+}
+
+fn malformed(what: &str) -> GrpcError {
+  GrpcError(format!("Received a malformed {} frame.", what))
+}
+
+/// An error thrown when a gRPC call fails.
+#[derive(Debug)]
+pub struct GrpcError(pub String);
+
+impl Display for GrpcError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for GrpcError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+/// An in-process stand-in for a gRPC server, which tests call with.
+#[cfg(test)]
+pub mod test_server {
+  use std::net::TcpListener;
+  use std::thread::{self, JoinHandle};
+  use super::*;
+
+  /// A call the server received.
+  pub struct ReceivedCall {
+    pub path: String,
+    pub requests: Vec<Vec<u8>>,
+  }
+
+  /// Serves a single client-streaming call on another thread, answering each
+  /// request with `respond` and ending the call with `status`. Streams get a
+  /// window of `window` bytes, so clients sending more wait for updates.
+  pub fn serve(listener: TcpListener, window: u32, status: &'static str,
+      respond: impl Fn(&[u8]) -> Vec<u8> + Send + 'static) ->
+      JoinHandle<Result<ReceivedCall, String>> {
+    thread::spawn(move || {
+      let serve = || -> Result<ReceivedCall, Box<dyn Error>> {
+        let (mut stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        let mut preface = [0; PREFACE.len()];
+        stream.read_exact(&mut preface)?;
+        if preface != PREFACE {
+          let message = "The client sent no HTTP/2 preface.";
+          return Err(Box::new(GrpcError(message.to_owned())));
+        }
+        let mut settings = INITIAL_WINDOW_SIZE.to_be_bytes().to_vec();
+        settings.extend(window.to_be_bytes());
+        write_frame(&mut stream, SETTINGS, 0, 0, &settings)?;
+
+        let mut call = ReceivedCall {
+          path: String::new(),
+          requests: Vec::new(),
+        };
+        let mut body = Vec::new();
+        let mut responded = false;
+        loop {
+          let Frame { kind, flags, stream_id, payload } = read_frame(&mut stream)?;
+          match kind {
+            SETTINGS if flags & ACK == 0 => {
+              write_frame(&mut stream, SETTINGS, ACK, 0, &[])?;
+            },
+            HEADERS => {
+              let headers = decode_headers(&payload)?;
+              let path = headers.into_iter().find(|(name, _)| name == ":path");
+              call.path = path.map(|(_, path)| path).unwrap_or_default();
+            },
+            DATA => {
+              body.extend_from_slice(&payload);
+              if !payload.is_empty() && flags & END_STREAM == 0 {
+                let increment = (payload.len() as u32).to_be_bytes();
+                write_frame(&mut stream, WINDOW_UPDATE, 0, 0, &increment)?;
+                write_frame(&mut stream, WINDOW_UPDATE, 0, stream_id, &increment)?;
+              }
+              while let Some(request) = take_message(&mut body)? {
+                if !responded {
+                  // `:status: 200` from the static table.
+                  let mut block = vec![0x88];
+                  encode_header("content-type", "application/grpc", &mut block);
+                  write_frame(&mut stream, HEADERS, END_HEADERS, stream_id, &block)?;
+                  responded = true;
+                }
+                let response = length_prefixed(&respond(&request));
+                write_frame(&mut stream, DATA, 0, stream_id, &response)?;
+                call.requests.push(request);
+              }
+              if flags & END_STREAM != 0 {
+                let mut block = if responded { Vec::new() } else { vec![0x88] };
+                encode_header("grpc-status", status, &mut block);
+                let message = if status == "0" { "" } else { "Rejected." };
+                encode_header("grpc-message", message, &mut block);
+                let flags = END_STREAM | END_HEADERS;
+                write_frame(&mut stream, HEADERS, flags, stream_id, &block)?;
+                // The connection stays open until the client is done with it.
+                stream.read_to_end(&mut Vec::new())?;
+                return Ok(call);
+              }
+            },
+            _ => {},
+          }
+        }
+      };
+      serve().map_err(|err| err.to_string())
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::net::TcpListener;
+  use super::*;
+  use super::test_server::serve;
+
+  #[test]
+  fn call_sends_requests_within_flow_control_windows() -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = format!("grpc://{}", listener.local_addr()?);
+    let server = serve(listener, 100, "0", |request| request[..1].to_vec());
+
+    let mut call = GrpcCall::start(&address, "/test.Service/Stream")?;
+    // More than the default windows, so sending waits for window updates.
+    let requests: Vec<Vec<u8>> = (0..100u8)
+      .map(|index| vec![index; 20 + index as usize * 20])
+      .collect();
+    for request in &requests {
+      call.send(request)?;
+    }
+    let responses = call.finish()?;
+
+    let received = server.join().unwrap()?;
+    assert_eq!(received.path, "/test.Service/Stream");
+    assert_eq!(received.requests, requests);
+    assert_eq!(responses, (0..100u8).map(|index| vec![index]).collect::<Vec<_>>());
+    Ok(())
+  }
+
+  #[test]
+  fn call_fails_with_the_status_of_the_server() -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?.to_string();
+    let server = serve(listener, 65_535, "3", |_| Vec::new());
+
+    let call = GrpcCall::start(&address, "/test.Service/Stream")?;
+    let err = call.finish().unwrap_err();
+    assert_eq!(err.to_string(), "The call failed with status 3: Rejected.");
+    server.join().unwrap()?;
+    Ok(())
+  }
+
+  #[test]
+  fn headers_round_trip_through_hpack() -> Result<(), Box<dyn Error>> {
+    let mut block = vec![0x88, 0x3f, 0xe1, 0x1f];
+    let long = "x".repeat(300);
+    encode_header("grpc-message", &long, &mut block);
+    // A literal with an indexed name and a Huffman coded literal are skipped.
+    block.extend([0x40 | 31, 0x01, b'a', 0x00, 0x81, 0xff, 0x00]);
+    encode_header("grpc-status", "0", &mut block);
+
+    assert_eq!(decode_headers(&block)?, [
+      ("grpc-message".to_owned(), long),
+      ("grpc-status".to_owned(), "0".to_owned()),
+    ]);
+    assert!(decode_headers(&[0x00, 0x05, b'a']).is_err());
+    Ok(())
+  }
+}
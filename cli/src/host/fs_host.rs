@@ -1,62 +1,398 @@
-use std::{error::Error, fs, path::{self, Path, PathBuf}};
-use super::host::{Entry, EntryKind, ExternalPathError, Host};
-
-/// A `Host` implementation which reads off the file system.
+use std::{env, error::Error, fs, io, os::unix::{ffi::OsStrExt, fs::{symlink, PermissionsExt}}, path::{self, Path, PathBuf}, process::Command, time::SystemTime};
+use rand::random;
+use sha2::{Digest, Sha256};
+use super::host::{
+  Entry, EntryKind, ExternalPathError, Host, Process, ProcessOutput,
+  SourceWriteError, EXEC_ROOT,
+};
+use super::ignore::CONVENIENCE_SYMLINK_PREFIX;
+
+/// A `Host` implementation which reads off the file system. Sources are read
+/// from the workspace root while outputs are written to a separate output base.
 pub struct FsHost {
   wksp_root: PathBuf,
+  output_base: PathBuf,
 }
 
 impl FsHost {
-  /// Returns an `FsHost` using the given path as the workspace root.
+  /// Returns an `FsHost` using the given path as the workspace root and the
+  /// default output base for that workspace. The output base is not created
+  /// until something is written to it.
   pub fn from(wksp_root: &Path) -> Result<FsHost, Box<dyn Error>> {
-    Ok(FsHost {
-      wksp_root: wksp_root.canonicalize()?,
-    })
+    let wksp_root = wksp_root.canonicalize()?;
+    let output_base = default_output_base(&wksp_root)?;
+
+    FsHost::new(wksp_root, output_base)
+  }
+
+  /// Returns an `FsHost` using the given paths as the workspace root and output
+  /// base, creating the output base if it does not exist. Fails if either
+  /// directory contains the other.
+  #[allow(dead_code)] // Not used by any commands yet.
+  pub fn with_output_base(wksp_root: &Path, output_base: &Path) ->
+      Result<FsHost, Box<dyn Error>> {
+    fs::create_dir_all(output_base)?;
+
+    FsHost::new(wksp_root.canonicalize()?, output_base.canonicalize()?)
+  }
+
+  fn new(wksp_root: PathBuf, output_base: PathBuf) ->
+      Result<FsHost, Box<dyn Error>> {
+    let output_base = canonicalize_existing(&output_base)?;
+    if output_base.starts_with(&wksp_root) || wksp_root.starts_with(&output_base) {
+      return Err(Box::new(SourceWriteError(format!(
+        "Output base \"{}\" must be distinct from workspace \"{}\".",
+        output_base.to_str().unwrap(),
+        wksp_root.to_str().unwrap(),
+      ))));
+    }
+
+    Ok(FsHost { wksp_root, output_base })
+  }
+
+  /// Resolves the given workspace-relative path to an absolute path on the file
+  /// system with all symlinks followed. Fails with an `ExternalPathError` if the
+  /// path refers to anything outside the workspace, either lexically or through
+  /// a symlink.
+  fn resolve_in_workspace(&self, path: &Path) ->
+      Result<PathBuf, Box<dyn Error>> {
+    let normalized = normalize(&self.wksp_root.join(path))?;
+    if !normalized.starts_with(&self.wksp_root) {
+      return Err(Box::new(ExternalPathError(format!(
+        "Path \"{}\" is outside the workspace.",
+        path.to_str().unwrap(),
+      ))));
+    }
+
+    let resolved = normalized.canonicalize()?;
+    if !resolved.starts_with(&self.wksp_root) {
+      return Err(Box::new(ExternalPathError(format!(
+        "Path \"{}\" is outside the workspace (symlinked to \"{}\").",
+        path.to_str().unwrap(),
+        resolved.to_str().unwrap(),
+      ))));
+    }
+
+    Ok(resolved)
+  }
+
+  /// Resolves the given output-base-relative path to an absolute path on the
+  /// file system. Fails with an `ExternalPathError` if the path is outside the
+  /// output base and a `SourceWriteError` if it refers to the source tree
+  /// through a symlink. The final path component is only followed when it is a
+  /// symlink if `follow_final` is set.
+  fn resolve_in_output_base(&self, path: &Path, follow_final: bool) ->
+      Result<PathBuf, Box<dyn Error>> {
+    let normalized = normalize(&self.output_base.join(path))?;
+    if !normalized.starts_with(&self.output_base) {
+      return Err(Box::new(ExternalPathError(format!(
+        "Path \"{}\" is outside the output base.",
+        path.to_str().unwrap(),
+      ))));
+    }
+
+    let real = match (follow_final, normalized.parent()) {
+      (false, Some(parent)) => canonicalize_existing(parent)?,
+      _ => canonicalize_existing(&normalized)?,
+    };
+    if real.starts_with(&self.wksp_root) {
+      return Err(Box::new(SourceWriteError(format!(
+        "Path \"{}\" is in the source tree (symlinked to \"{}\").",
+        path.to_str().unwrap(),
+        real.to_str().unwrap(),
+      ))));
+    }
+    if !real.starts_with(&self.output_base) {
+      return Err(Box::new(ExternalPathError(format!(
+        "Path \"{}\" is outside the output base (symlinked to \"{}\").",
+        path.to_str().unwrap(),
+        real.to_str().unwrap(),
+      ))));
+    }
+
+    Ok(normalized)
+  }
+
+  /// Creates the exec root and symlinks every top-level workspace entry into
+  /// it which is not already linked. Convenience symlinks are skipped since
+  /// they point back into the output base.
+  fn link_exec_root(&self) -> Result<PathBuf, Box<dyn Error>> {
+    let exec_root = self.output_base.join(EXEC_ROOT);
+    fs::create_dir_all(&exec_root)?;
+
+    for entry in fs::read_dir(&self.wksp_root)? {
+      let entry = entry?;
+      let name = entry.file_name();
+      if name.to_str().is_some_and(|name| name.starts_with(CONVENIENCE_SYMLINK_PREFIX)) {
+        continue;
+      }
+
+      let link = exec_root.join(&name);
+      if fs::symlink_metadata(&link).is_ok() {
+        continue;
+      }
+      match symlink(entry.path(), &link) {
+        Err(err) if err.kind() != io::ErrorKind::AlreadyExists => return Err(Box::new(err)),
+        _ => {},
+      }
+    }
+
+    Ok(exec_root)
   }
 }
 
 impl Host for FsHost {
   fn read_to_string(&self, path: &Path) -> Result<String, Box<dyn Error>> {
-    let resolved = normalize(&self.wksp_root.join(path))?;
-
-    if !&resolved.starts_with(&self.wksp_root) {
-      return Err(Box::new(ExternalPathError(
-        format!("Path \"{}\" is outside the workspace.",
-        &path.to_str().unwrap()),
-      )));
-    }
+    let resolved = self.resolve_in_workspace(path)?;
 
     Ok(fs::read_to_string(resolved)?)
   }
 
-  fn list(&self, path: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
-    let resolved = normalize(&self.wksp_root.join(&path))?;
+  fn read(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let resolved = self.resolve_in_workspace(path)?;
 
-    if !&resolved.starts_with(&self.wksp_root) {
-      return Err(Box::new(ExternalPathError(
-        format!("Path \"{}\" is outside the workspace.",
-        &path.to_str().unwrap()),
-      )));
-    }
+    Ok(fs::read(resolved)?)
+  }
+
+  fn is_executable(&self, path: &Path) -> Result<bool, Box<dyn Error>> {
+    let resolved = self.resolve_in_workspace(path)?;
+
+    Ok(fs::metadata(resolved)?.permissions().mode() & 0o111 != 0)
+  }
+
+  fn modified(&self, path: &Path) -> Result<SystemTime, Box<dyn Error>> {
+    let resolved = self.resolve_in_workspace(path)?;
+
+    Ok(fs::metadata(resolved)?.modified()?)
+  }
+
+  fn with_source_root(&self, root: &Path) -> Result<Box<dyn Host>, Box<dyn Error>> {
+    let root = self.wksp_root.join(root).canonicalize()
+      .map_err(|err| format!("Failed to read \"{}\": {}", root.to_str().unwrap(), err))?;
+
+    // Repositories may be materialized inside the output base, so unlike the
+    // workspace their roots need not be distinct from it.
+    Ok(Box::new(FsHost { wksp_root: root, output_base: self.output_base.clone() }))
+  }
+
+  fn source_root(&self) -> &Path {
+    &self.wksp_root
+  }
+
+  fn output_base(&self) -> &Path {
+    &self.output_base
+  }
+
+  fn list(&self, path: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let resolved = self.resolve_in_workspace(path)?;
 
-    Ok(fs::read_dir(&resolved)?
-      .map(|entry_result| entry_result
-        .and_then(|dir_entry| Ok(Entry {
+    fs::read_dir(&resolved)?
+      .map(|entry_result| {
+        let dir_entry = entry_result?;
+        let file_type = dir_entry.file_type()?;
+        Ok(Entry {
           path: path.join(dir_entry.file_name()),
-          kind: if dir_entry.file_type()?.is_file() {
+          kind: if file_type.is_file() {
             EntryKind::File
-          } else {
+          } else if file_type.is_dir() {
             EntryKind::Directory
+          } else if file_type.is_symlink() {
+            EntryKind::Symlink(fs::read_link(dir_entry.path())?)
+          } else {
+            EntryKind::Other
           },
-        }))
-      ).collect::<Vec<Result<_, _>>>()
-      .into_iter()
-      .collect::<Result<Vec<_>, _>>()?)
+        })
+      })
+      .collect()
+  }
+
+  fn resolve(&self, path: &Path) -> Result<Entry, Box<dyn Error>> {
+    let resolved = self.resolve_in_workspace(path)?;
+    let file_type = fs::metadata(&resolved)?.file_type();
+
+    Ok(Entry {
+      path: resolved.strip_prefix(&self.wksp_root)?.to_path_buf(),
+      kind: if file_type.is_file() {
+        EntryKind::File
+      } else if file_type.is_dir() {
+        EntryKind::Directory
+      } else {
+        EntryKind::Other
+      },
+    })
+  }
+
+  fn create_output_dir(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+    let resolved = self.resolve_in_output_base(path, true)?;
+
+    Ok(fs::create_dir_all(resolved)?)
+  }
+
+  fn write_output(&self, path: &Path, contents: &[u8]) ->
+      Result<(), Box<dyn Error>> {
+    let resolved = self.resolve_in_output_base(path, false)?;
+    fs::create_dir_all(resolved.parent().unwrap())?;
+
+    write_atomically(&resolved, contents)
+  }
+
+  fn set_output_executable(&self, path: &Path, executable: bool) ->
+      Result<(), Box<dyn Error>> {
+    let resolved = self.resolve_in_output_base(path, true)?;
+
+    set_executable(&resolved, executable)
+  }
+
+  fn symlink_output(&self, path: &Path, target: &Path) ->
+      Result<(), Box<dyn Error>> {
+    let resolved = self.resolve_in_output_base(path, false)?;
+    fs::create_dir_all(resolved.parent().unwrap())?;
+
+    if let Ok(metadata) = fs::symlink_metadata(&resolved) {
+      if metadata.is_dir() {
+        return Err(Box::new(SourceWriteError(format!(
+          "Cannot replace directory \"{}\" with a symlink.",
+          path.to_str().unwrap(),
+        ))));
+      }
+      fs::remove_file(&resolved)?;
+    }
+
+    Ok(symlink(target, &resolved)?)
+  }
+
+  fn delete_output(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+    let resolved = self.resolve_in_output_base(path, false)?;
+
+    delete(&resolved)
+  }
+
+  fn read_output(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let resolved = self.resolve_in_output_base(path, true)?;
+
+    Ok(fs::read(resolved)?)
+  }
+
+  fn output_kind(&self, path: &Path) -> Result<Option<EntryKind>, Box<dyn Error>> {
+    let resolved = self.resolve_in_output_base(path, false)?;
+
+    let file_type = match fs::symlink_metadata(&resolved) {
+      Ok(metadata) => metadata.file_type(),
+      Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+      Err(err) => return Err(Box::new(err)),
+    };
+    Ok(Some(if file_type.is_file() {
+      EntryKind::File
+    } else if file_type.is_dir() {
+      EntryKind::Directory
+    } else if file_type.is_symlink() {
+      EntryKind::Symlink(fs::read_link(&resolved)?)
+    } else {
+      EntryKind::Other
+    }))
   }
+
+  fn execute(&self, process: &Process) -> Result<ProcessOutput, Box<dyn Error>> {
+    let exec_root = self.link_exec_root()?;
+
+    // Relative program paths are resolved against the exec root explicitly,
+    // since `Command` does not define whether they are relative to the child's
+    // working directory.
+    let program = if process.program.contains('/') {
+      exec_root.join(&process.program)
+    } else {
+      PathBuf::from(&process.program)
+    };
+    let mut command = Command::new(program);
+    command.args(&process.args).current_dir(&exec_root).env_clear();
+    if let Some(path) = env::var_os("PATH") {
+      command.env("PATH", path);
+    }
+    command.envs(&process.env);
+
+    let output = command.output().map_err(|err| {
+      format!("Failed to run `{}`: {}", process.program, err)
+    })?;
+    Ok(ProcessOutput {
+      status: output.status.code(),
+      stdout: output.stdout,
+      stderr: output.stderr,
+    })
+  }
+}
+
+/// Sets or clears the executable bit of a file.
+pub fn set_executable(path: &Path, executable: bool) -> Result<(), Box<dyn Error>> {
+  let mut permissions = fs::metadata(path)?.permissions();
+  let mode = permissions.mode();
+  permissions.set_mode(if executable { mode | 0o111 } else { mode & !0o111 });
+
+  Ok(fs::set_permissions(path, permissions)?)
+}
+
+/// Deletes a file, symlink or directory tree without following symlinks.
+pub fn delete(path: &Path) -> Result<(), Box<dyn Error>> {
+  match fs::symlink_metadata(path) {
+    Err(_) => Ok(()),
+    Ok(metadata) if metadata.is_dir() => Ok(fs::remove_dir_all(path)?),
+    Ok(_) => Ok(fs::remove_file(path)?),
+  }
+}
+
+/// Writes to a temporary file in the same directory as `path` and then renames
+/// it over the real path, which is atomic on the same file system.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+  let tmp = path.parent().unwrap().join(format!(
+    ".{}.tmp-{:0>10}",
+    path.file_name().unwrap().to_str().unwrap(),
+    random::<u32>(),
+  ));
+  fs::write(&tmp, contents)?;
+  if let Err(err) = fs::rename(&tmp, path) {
+    fs::remove_file(&tmp)?;
+    return Err(Box::new(err));
+  }
+
+  Ok(())
+}
+
+/// Returns the default output base for the given workspace, which is a
+/// directory in the user's cache unique to the workspace's absolute path.
+fn default_output_base(wksp_root: &Path) -> Result<PathBuf, Box<dyn Error>> {
+  let cache = match (env::var_os("XDG_CACHE_HOME"), env::var_os("HOME")) {
+    (Some(cache), _) => PathBuf::from(cache),
+    (None, Some(home)) => PathBuf::from(home).join(".cache"),
+    (None, None) => env::temp_dir(),
+  };
+
+  Ok(cache.join("razel").join(output_base_name(wksp_root)))
 }
 
-fn normalize(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
-  let p = path::absolute(&path)?;
+/// The directory name of a workspace's default output base. This must stay
+/// the same across razel and compiler versions so existing caches are reused.
+fn output_base_name(wksp_root: &Path) -> String {
+  let digest = Sha256::digest(wksp_root.as_os_str().as_bytes());
+  digest[..8].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Canonicalizes the longest existing prefix of the given path and appends the
+/// remaining components which do not exist yet.
+pub fn canonicalize_existing(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+  let path = normalize(path)?;
+  let mut existing = path.as_path();
+  while !existing.exists() {
+    match existing.parent() {
+      Some(parent) => existing = parent,
+      None => return Ok(path.clone()),
+    }
+  }
+
+  Ok(existing.canonicalize()?.join(path.strip_prefix(existing)?))
+}
+
+/// Makes a path absolute and lexically resolves its `.` and `..` components.
+pub fn normalize(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+  let p = path::absolute(path)?;
   let mut stack = Vec::new();
   for component in p.components() {
     let component = component.as_os_str().to_str().unwrap();
@@ -75,7 +411,8 @@ fn normalize(path: &Path) -> Result<PathBuf, Box<dyn Error>> {
 #[cfg(test)]
 mod test {
   use super::*;
-  use assertables::{assert_err, assert_set_eq, assert_set_impl_prep};
+  use assertables::{assert_contains, assert_err, assert_set_eq, assert_set_impl_prep};
+  use std::os::unix::net::UnixListener;
   use std::path::PathBuf;
   use crate::host::test_dir::{TestContents, TestDir};
 
@@ -215,4 +552,384 @@ mod test {
 
     Ok(())
   }
+
+  #[test]
+  fn read_to_string_follows_symlinks() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("foo.txt"), TestContents::File("Hello, World!")),
+      (Path::new("bar/link.txt"), TestContents::Symlink("../foo.txt")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(
+      host.read_to_string(Path::new("bar/link.txt"))?,
+      "Hello, World!",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn read_to_string_errors_on_symlink_outside_workspace() -> Result<(), Box<dyn Error>> {
+    let external = TestDir::from([
+      (Path::new("secret.txt"), TestContents::File("secret")),
+    ])?;
+    let external_file = external.root.join("secret.txt");
+    let dir = TestDir::from([
+      (Path::new("link.txt"), TestContents::Symlink(
+        external_file.to_str().unwrap(),
+      )),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    let err = host.read_to_string(Path::new("link.txt")).unwrap_err();
+    assert!(err.is::<ExternalPathError>());
+
+    Ok(())
+  }
+
+  #[test]
+  fn list_reports_symlinks_and_other_entries() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("foo.txt"), TestContents::File("")),
+      (Path::new("link.txt"), TestContents::Symlink("foo.txt")),
+    ])?;
+    UnixListener::bind(dir.root.join("socket"))?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_set_eq!(
+      host.list(Path::new(""))?,
+      [
+        Entry {
+          path: PathBuf::from("foo.txt"),
+          kind: EntryKind::File,
+        },
+        Entry {
+          path: PathBuf::from("link.txt"),
+          kind: EntryKind::Symlink(PathBuf::from("foo.txt")),
+        },
+        Entry {
+          path: PathBuf::from("socket"),
+          kind: EntryKind::Other,
+        },
+      ],
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn resolve_follows_symlinks() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("foo/bar.txt"), TestContents::File("")),
+      (Path::new("link"), TestContents::Symlink("foo")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(host.resolve(Path::new("link"))?, Entry {
+      path: PathBuf::from("foo"),
+      kind: EntryKind::Directory,
+    });
+    assert_eq!(host.resolve(Path::new("link/bar.txt"))?, Entry {
+      path: PathBuf::from("foo/bar.txt"),
+      kind: EntryKind::File,
+    });
+
+    Ok(())
+  }
+
+  #[test]
+  fn resolve_errors_on_symlink_outside_workspace() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("link"), TestContents::Symlink("/")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    let err = host.resolve(Path::new("link")).unwrap_err();
+    assert!(err.is::<ExternalPathError>());
+
+    Ok(())
+  }
+
+  #[test]
+  fn output_base_name_is_stable() {
+    // Pinned so a change of hash function, which would orphan every existing
+    // output base, is caught.
+    assert_eq!(output_base_name(Path::new("/home/user/wksp")), "3763fdf6c5d5f73f");
+    assert_eq!(output_base_name(Path::new("/home/user/other")), "05fbd965be387584");
+  }
+
+  #[test]
+  fn with_output_base_errors_on_output_base_in_workspace() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([])?;
+
+    let err = FsHost::with_output_base(&dir.root, &dir.root.join("out"))
+      .err().unwrap();
+    assert!(err.is::<SourceWriteError>());
+
+    Ok(())
+  }
+
+  #[test]
+  fn with_output_base_errors_on_workspace_in_output_base() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp"), TestContents::Directory),
+    ])?;
+
+    let err = FsHost::with_output_base(&dir.root.join("wksp"), &dir.root)
+      .err().unwrap();
+    assert!(err.is::<SourceWriteError>());
+
+    Ok(())
+  }
+
+  #[test]
+  fn create_output_dir_creates_nested_directories() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([])?;
+    let out = TestDir::from([])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+    host.create_output_dir(Path::new("foo/bar"))?;
+
+    assert!(out.root.join("foo/bar").is_dir());
+
+    Ok(())
+  }
+
+  #[test]
+  fn write_output_writes_file() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([])?;
+    let out = TestDir::from([
+      (Path::new("foo/bar.txt"), TestContents::File("old")),
+    ])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+    host.write_output(Path::new("foo/bar.txt"), b"new")?;
+    host.write_output(Path::new("baz/hello.txt"), b"Hello, World!")?;
+
+    assert_eq!(fs::read_to_string(out.root.join("foo/bar.txt"))?, "new");
+    assert_eq!(
+      fs::read_to_string(out.root.join("baz/hello.txt"))?,
+      "Hello, World!",
+    );
+    assert_eq!(fs::read_dir(out.root.join("foo"))?.count(), 1);
+
+    Ok(())
+  }
+
+  #[test]
+  fn with_source_root_reads_sources_from_other_directory() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([
+      (Path::new("third_party/lib/BUILD"), TestContents::File("lib")),
+    ])?;
+    let out = TestDir::from([])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+    let repo = host.with_source_root(Path::new("third_party/lib"))?;
+
+    assert_eq!(repo.read_to_string(Path::new("BUILD"))?, "lib");
+    assert_eq!(repo.source_root(), wksp.root.canonicalize()?.join("third_party/lib"));
+    repo.write_output(Path::new("foo.txt"), b"out")?;
+    assert_eq!(fs::read_to_string(out.root.join("foo.txt"))?, "out");
+
+    Ok(())
+  }
+
+  #[test]
+  fn write_output_errors_outside_output_base() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([])?;
+    let out = TestDir::from([])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+
+    let err = host.write_output(Path::new("../foo.txt"), b"").unwrap_err();
+    assert!(err.is::<ExternalPathError>());
+
+    Ok(())
+  }
+
+  #[test]
+  fn write_output_errors_on_symlink_into_workspace() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([
+      (Path::new("src/foo.txt"), TestContents::File("source")),
+    ])?;
+    let src = wksp.root.join("src");
+    let out = TestDir::from([
+      (Path::new("src"), TestContents::Symlink(src.to_str().unwrap())),
+    ])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+
+    let err = host.write_output(Path::new("src/foo.txt"), b"").unwrap_err();
+    assert!(err.is::<SourceWriteError>());
+    let err = host.create_output_dir(Path::new("src/dir")).unwrap_err();
+    assert!(err.is::<SourceWriteError>());
+    assert_eq!(fs::read_to_string(src.join("foo.txt"))?, "source");
+
+    Ok(())
+  }
+
+  #[test]
+  fn set_output_executable_sets_and_clears_executable_bit() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([])?;
+    let out = TestDir::from([
+      (Path::new("tool.sh"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+    let mode = || -> Result<u32, Box<dyn Error>> {
+      Ok(fs::metadata(out.root.join("tool.sh"))?.permissions().mode())
+    };
+
+    host.set_output_executable(Path::new("tool.sh"), true)?;
+    assert_eq!(mode()? & 0o111, 0o111);
+
+    host.set_output_executable(Path::new("tool.sh"), false)?;
+    assert_eq!(mode()? & 0o111, 0);
+
+    Ok(())
+  }
+
+  #[test]
+  fn set_output_executable_errors_on_symlink_into_workspace() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([
+      (Path::new("tool.sh"), TestContents::File("")),
+    ])?;
+    let tool = wksp.root.join("tool.sh");
+    let out = TestDir::from([
+      (Path::new("tool.sh"), TestContents::Symlink(tool.to_str().unwrap())),
+    ])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+
+    let err = host.set_output_executable(Path::new("tool.sh"), true)
+      .unwrap_err();
+    assert!(err.is::<SourceWriteError>());
+
+    Ok(())
+  }
+
+  #[test]
+  fn symlink_output_creates_and_replaces_symlinks() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([])?;
+    let out = TestDir::from([])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+
+    host.symlink_output(Path::new("foo/link"), Path::new("first"))?;
+    assert_eq!(
+      fs::read_link(out.root.join("foo/link"))?,
+      PathBuf::from("first"),
+    );
+
+    host.symlink_output(Path::new("foo/link"), Path::new("second"))?;
+    assert_eq!(
+      fs::read_link(out.root.join("foo/link"))?,
+      PathBuf::from("second"),
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn delete_output_deletes_trees_without_following_symlinks() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([
+      (Path::new("foo.txt"), TestContents::File("source")),
+    ])?;
+    let out = TestDir::from([
+      (Path::new("dir/nested/file.txt"), TestContents::File("")),
+      (Path::new("dir/link"), TestContents::Symlink(
+        wksp.root.to_str().unwrap(),
+      )),
+    ])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+    host.delete_output(Path::new("dir"))?;
+    host.delete_output(Path::new("does/not/exist"))?;
+
+    assert!(!fs::exists(out.root.join("dir"))?);
+    assert_eq!(fs::read_to_string(wksp.root.join("foo.txt"))?, "source");
+
+    Ok(())
+  }
+
+  #[test]
+  fn read_output_and_output_kind_inspect_outputs() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([])?;
+    let out = TestDir::from([
+      (Path::new("foo.txt"), TestContents::File("output")),
+      (Path::new("dir/link"), TestContents::Symlink("../foo.txt")),
+    ])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+
+    assert_eq!(host.read_output(Path::new("dir/link"))?, b"output");
+    assert_eq!(host.output_kind(Path::new("foo.txt"))?, Some(EntryKind::File));
+    assert_eq!(host.output_kind(Path::new("dir"))?, Some(EntryKind::Directory));
+    assert_eq!(
+      host.output_kind(Path::new("dir/link"))?,
+      Some(EntryKind::Symlink(PathBuf::from("../foo.txt"))),
+    );
+    assert_eq!(host.output_kind(Path::new("missing"))?, None);
+
+    Ok(())
+  }
+
+  #[test]
+  fn execute_runs_in_exec_root_with_linked_sources() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([
+      (Path::new("src/input.txt"), TestContents::File("hello")),
+      (Path::new("razel-bin"), TestContents::Symlink("/does/not/exist")),
+    ])?;
+    let out = TestDir::from([])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+    let output = host.execute(&Process {
+      program: "sh".to_owned(),
+      args: vec![
+        "-c".to_owned(),
+        "cat src/input.txt && echo \" $GREETING\" && ls".to_owned(),
+      ],
+      env: [("GREETING".to_owned(), "world".to_owned())].into(),
+    })?;
+
+    assert_eq!(output.status, Some(0));
+    assert_eq!(
+      String::from_utf8(output.stdout)?,
+      "hello world\nsrc\n",
+    );
+    assert!(!fs::exists(out.root.join(EXEC_ROOT).join("razel-bin"))?);
+
+    Ok(())
+  }
+
+  #[test]
+  fn execute_reports_failures() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([])?;
+    let out = TestDir::from([])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+    let output = host.execute(&Process {
+      program: "sh".to_owned(),
+      args: vec!["-c".to_owned(), "echo oops >&2; exit 3".to_owned()],
+      ..Process::default()
+    })?;
+    assert_eq!(output.status, Some(3));
+    assert_eq!(output.stderr, b"oops\n");
+
+    assert_contains!(
+      host.execute(&Process {
+        program: "does/not/exist".to_owned(),
+        ..Process::default()
+      }).unwrap_err().to_string(),
+      "Failed to run `does/not/exist`",
+    );
+
+    Ok(())
+  }
 }
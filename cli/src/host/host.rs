@@ -1,34 +1,132 @@
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use super::walk::{walk, WalkOptions};
 
 /// The host environment of the build system which allows Razel to interact with
-/// the outside world.
-pub trait Host {
+/// the outside world. Hosts are shared between threads.
+pub trait Host: Sync {
   /// Reads a file at the given path and returns it as a string. The path is
   /// resolved relative to the workspace root.
   fn read_to_string(&self, path: &Path) -> Result<String, Box<dyn Error>>;
 
-  /// Lists the directory at the given path and returns its entries. The path is
+  /// Reads a file at the given path and returns its bytes. The path is
   /// resolved relative to the workspace root.
+  fn read(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>>;
+
+  /// Returns whether the file at the given path has its executable bit set.
+  /// The path is resolved relative to the workspace root.
+  fn is_executable(&self, path: &Path) -> Result<bool, Box<dyn Error>>;
+
+  /// Returns when the file or directory at the given path was last modified,
+  /// following symlinks. The path is resolved relative to the workspace root.
+  fn modified(&self, path: &Path) -> Result<SystemTime, Box<dyn Error>>;
+
+  /// Lists the directory at the given path and returns its entries. The path is
+  /// resolved relative to the workspace root. Symlinks are listed as
+  /// `EntryKind::Symlink` and are *not* followed.
   fn list(&self, path: &Path) -> Result<Vec<Entry>, Box<dyn Error>>;
+
+  /// Resolves all symlinks in the given path and returns the entry it
+  /// ultimately refers to, which is never a `EntryKind::Symlink`. The path is
+  /// resolved relative to the workspace root and the returned path is also
+  /// workspace-relative. Fails with an `ExternalPathError` if the path resolves
+  /// outside the workspace.
+  fn resolve(&self, path: &Path) -> Result<Entry, Box<dyn Error>>;
+
+  /// Returns a host reading sources from the directory `root` instead of the
+  /// workspace, sharing this host's output base. `root` is resolved relative to
+  /// the workspace root unless it is absolute, and may be outside of it.
+  fn with_source_root(&self, root: &Path) -> Result<Box<dyn Host>, Box<dyn Error>>;
+
+  /// The absolute path of the directory sources are read from.
+  fn source_root(&self) -> &Path;
+
+  /// The absolute path of the output base.
+  fn output_base(&self) -> &Path;
+
+  /// Creates a directory and all its missing parents at the given path. The
+  /// path is resolved relative to the output base.
+  fn create_output_dir(&self, path: &Path) -> Result<(), Box<dyn Error>>;
+
+  /// Atomically writes the given contents to a file at the given path, creating
+  /// any missing parent directories. Readers either see the previous file or
+  /// the complete new contents, never a partial write. The path is resolved
+  /// relative to the output base.
+  fn write_output(&self, path: &Path, contents: &[u8]) ->
+      Result<(), Box<dyn Error>>;
+
+  /// Sets or clears the executable bit of the file at the given path. The path
+  /// is resolved relative to the output base.
+  fn set_output_executable(&self, path: &Path, executable: bool) ->
+      Result<(), Box<dyn Error>>;
+
+  /// Creates a symlink at the given path pointing to `target`, replacing any
+  /// existing file or symlink at that path. `target` is stored exactly as
+  /// given. The path is resolved relative to the output base.
+  fn symlink_output(&self, path: &Path, target: &Path) ->
+      Result<(), Box<dyn Error>>;
+
+  /// Deletes the file, symlink or entire directory tree at the given path.
+  /// Symlinks are deleted, never followed. Succeeds if nothing exists at the
+  /// path. The path is resolved relative to the output base.
+  fn delete_output(&self, path: &Path) -> Result<(), Box<dyn Error>>;
+
+  /// Reads the file at the given path, following symlinks. The path is
+  /// resolved relative to the output base.
+  fn read_output(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>>;
+
+  /// Returns the kind of entry at the given path without following a final
+  /// symlink, or `None` if nothing exists there. The path is resolved relative
+  /// to the output base.
+  fn output_kind(&self, path: &Path) -> Result<Option<EntryKind>, Box<dyn Error>>;
+
+  /// Runs a process to completion in the exec root, the `EXEC_ROOT` directory
+  /// of the output base. Every top-level entry of the workspace is symlinked
+  /// into the exec root so workspace-relative source paths resolve there.
+  fn execute(&self, process: &Process) -> Result<ProcessOutput, Box<dyn Error>>;
+}
+
+/// The directory of the output base in which processes are executed.
+pub const EXEC_ROOT: &str = "execroot";
+
+/// A process to execute.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Process {
+  /// The program to run, either a path relative to the exec root containing a
+  /// `/` or a bare name looked up on `PATH`.
+  pub program: String,
+  pub args: Vec<String>,
+
+  /// Environment variables set in addition to `PATH`, which is inherited.
+  pub env: BTreeMap<String, String>,
+}
+
+/// The result of an executed process.
+#[derive(Debug, PartialEq)]
+pub struct ProcessOutput {
+  /// The exit code, or `None` if the process was killed by a signal.
+  pub status: Option<i32>,
+  pub stdout: Vec<u8>,
+  pub stderr: Vec<u8>,
 }
 
 /// List all recursive files in the given directory. Directories are *not*
-/// returned.
+/// returned. Symlinks are followed and files are returned at their path through
+/// the symlink. Paths ignored by the workspace's `.razelignore` file are
+/// skipped. Fails if a symlink points at one of its own ancestors.
 pub fn list_all_files(host: &dyn Host, path: &Path) ->
     Result<Vec<PathBuf>, Box<dyn Error>> {
-  Ok(
-    host.list(path)?.into_iter().map(|entry| match entry.kind {
-      EntryKind::File => Ok(vec![entry.path.to_path_buf()]),
-      EntryKind::Directory => list_all_files(host, &entry.path)
-    }).collect::<Vec<Result<Vec<PathBuf>, Box<dyn Error>>>>()
-      .into_iter()
-      .collect::<Result<Vec<Vec<PathBuf>>, Box<dyn Error>>>()?
-      .into_iter()
-      .flatten()
-      .collect()
-  )
+  let files = walk(host, path, &WalkOptions::default(), |entries| {
+    entries
+      .filter(|entry| entry.as_ref().map_or(true, |entry| entry.kind == EntryKind::File))
+      .map(|entry| entry.map(|entry| entry.path))
+      .collect::<Result<Vec<_>, _>>()
+  })??;
+
+  Ok(files)
 }
 
 /// A file entry.
@@ -37,14 +135,21 @@ pub struct Entry {
   /// The workspace-relative path of the file.
   pub path: PathBuf,
 
-  /// Whether this is a file or directory.
+  /// Whether this is a file, directory or symlink.
   pub kind: EntryKind,
 }
 
-#[derive(Debug, Eq, Ord, PartialOrd, PartialEq)]
+#[derive(Clone, Debug, Eq, Ord, PartialOrd, PartialEq)]
 pub enum EntryKind {
-  File = 1,
-  Directory = 2,
+  File,
+  Directory,
+
+  /// A symbolic link with the given target, exactly as it is stored in the link
+  /// (it may be relative to the link's directory).
+  Symlink(PathBuf),
+
+  /// Anything else, such as a socket, FIFO or device.
+  Other,
 }
 
 /// An error thrown when a file external to the current workspace is requested.
@@ -63,6 +168,22 @@ impl Error for ExternalPathError {
   }
 }
 
+/// An error thrown when writing an output would modify the source tree.
+#[derive(Debug)]
+pub struct SourceWriteError(pub String);
+
+impl Display for SourceWriteError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", &self.0)
+  }
+}
+
+impl Error for SourceWriteError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
 #[cfg(test)]
 mod test {
   use super::*;
@@ -132,4 +253,72 @@ mod test {
 
     Ok(())
   }
+
+  #[test]
+  fn find_all_files_follows_symlinks() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("foo/bar.txt"), TestContents::File("")),
+      (Path::new("foo/baz.txt"), TestContents::Symlink("bar.txt")),
+      (Path::new("link"), TestContents::Symlink("foo")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_set_eq!(list_all_files(&host, Path::new(""))?, [
+      PathBuf::from("foo/bar.txt"),
+      PathBuf::from("foo/baz.txt"),
+      PathBuf::from("link/bar.txt"),
+      PathBuf::from("link/baz.txt"),
+    ]);
+
+    Ok(())
+  }
+
+  #[test]
+  fn find_all_files_errors_on_symlink_cycle() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("foo/bar/loop"), TestContents::Symlink("../../foo")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    let err = list_all_files(&host, Path::new("")).unwrap_err();
+    assert_contains!(err.to_string(), "points at its own ancestor");
+
+    Ok(())
+  }
+
+  #[test]
+  fn find_all_files_skips_ignored_paths() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new(".razelignore"), TestContents::File("node_modules/\n*.log\n")),
+      (Path::new("app/index.ts"), TestContents::File("")),
+      (Path::new("app/debug.log"), TestContents::File("")),
+      (Path::new("app/node_modules/dep/index.js"), TestContents::File("")),
+      (Path::new("razel-out"), TestContents::Symlink("app")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_set_eq!(list_all_files(&host, Path::new(""))?, [
+      PathBuf::from(".razelignore"),
+      PathBuf::from("app/index.ts"),
+    ]);
+
+    Ok(())
+  }
+
+  #[test]
+  fn find_all_files_errors_on_external_symlink() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("foo/escape"), TestContents::Symlink("/")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    let err = list_all_files(&host, Path::new("foo")).unwrap_err();
+    assert_contains!(err.to_string(), "outside the workspace");
+
+    Ok(())
+  }
 }
@@ -0,0 +1,207 @@
+use std::error::Error;
+use std::io;
+use std::path::Path;
+use super::host::{Entry, EntryKind, Host};
+use super::path_pattern::PathPattern;
+
+/// The workspace-relative path of the file listing paths to ignore.
+pub const IGNORE_FILE: &str = ".razelignore";
+
+/// The name prefix of convenience symlinks in the workspace root, which are
+/// always ignored.
+pub const CONVENIENCE_SYMLINK_PREFIX: &str = "razel-";
+
+/// A set of paths to skip while traversing the workspace, parsed from a
+/// `.razelignore` file using gitignore syntax.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IgnoreRules {
+  rules: Vec<IgnoreRule>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct IgnoreRule {
+  pattern: PathPattern,
+
+  /// Whether this rule un-ignores paths matched by an earlier rule (`!foo`).
+  negated: bool,
+
+  /// Whether this rule only matches directories (`foo/`).
+  directory_only: bool,
+}
+
+impl IgnoreRules {
+  /// Parses the contents of a `.razelignore` file. Blank lines and lines
+  /// starting with `#` are skipped. Patterns without a `/` (other than a
+  /// trailing one) match at any depth, all others are relative to the
+  /// workspace root.
+  pub fn parse(contents: &str) -> IgnoreRules {
+    IgnoreRules {
+      rules: contents.lines().filter_map(parse_rule).collect(),
+    }
+  }
+
+  /// Reads the workspace's `.razelignore` file, returning no rules if the file
+  /// does not exist.
+  pub fn load(host: &dyn Host) -> Result<IgnoreRules, Box<dyn Error>> {
+    match host.read_to_string(Path::new(IGNORE_FILE)) {
+      Ok(contents) => Ok(IgnoreRules::parse(&contents)),
+      Err(err) if err.downcast_ref::<io::Error>()
+          .is_some_and(|err| err.kind() == io::ErrorKind::NotFound) => {
+        Ok(IgnoreRules::default())
+      },
+      Err(err) => Err(err),
+    }
+  }
+
+  /// Returns whether the given entry should be skipped. If a directory is
+  /// ignored, callers are expected to skip its contents as well. Symlinks in
+  /// the workspace root starting with `razel-` are always ignored.
+  pub fn is_ignored(&self, entry: &Entry) -> bool {
+    if let EntryKind::Symlink(_) = entry.kind {
+      let is_convenience_symlink = entry.path.parent() == Some(Path::new(""))
+        && entry.path.to_str().unwrap().starts_with(CONVENIENCE_SYMLINK_PREFIX);
+      if is_convenience_symlink {
+        return true;
+      }
+    }
+
+    // The last matching rule wins.
+    self.rules.iter().rev()
+      .find(|rule| {
+        (!rule.directory_only || entry.kind == EntryKind::Directory)
+          && rule.pattern.matches(&entry.path)
+      })
+      .is_some_and(|rule| !rule.negated)
+  }
+}
+
+fn parse_rule(line: &str) -> Option<IgnoreRule> {
+  // Trailing whitespace is insignificant unless escaped.
+  let line = if line.ends_with("\\ ") {
+    line
+  } else {
+    line.trim_end()
+  };
+  if line.is_empty() || line.starts_with('#') {
+    return None;
+  }
+
+  let (negated, line) = match line.strip_prefix('!') {
+    Some(rest) => (true, rest),
+    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+  };
+  let (directory_only, line) = match line.strip_suffix('/') {
+    Some(rest) => (true, rest),
+    None => (false, line),
+  };
+
+  // Patterns without a slash match a name at any depth.
+  let pattern = if line.contains('/') {
+    PathPattern::parse(line)
+  } else {
+    PathPattern::parse(&format!("**/{}", line))
+  };
+
+  Some(IgnoreRule { pattern, negated, directory_only })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::path::PathBuf;
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+
+  fn file(path: &str) -> Entry {
+    Entry { path: PathBuf::from(path), kind: EntryKind::File }
+  }
+
+  fn dir(path: &str) -> Entry {
+    Entry { path: PathBuf::from(path), kind: EntryKind::Directory }
+  }
+
+  #[test]
+  fn is_ignored_matches_names_at_any_depth() {
+    let rules = IgnoreRules::parse("node_modules\n*.log\n");
+
+    assert!(rules.is_ignored(&dir("node_modules")));
+    assert!(rules.is_ignored(&dir("web/app/node_modules")));
+    assert!(rules.is_ignored(&file("foo/debug.log")));
+    assert!(!rules.is_ignored(&file("foo/debug.txt")));
+  }
+
+  #[test]
+  fn is_ignored_anchors_patterns_with_slashes() {
+    let rules = IgnoreRules::parse("/dist\nfoo/bar\n");
+
+    assert!(rules.is_ignored(&dir("dist")));
+    assert!(!rules.is_ignored(&dir("web/dist")));
+    assert!(rules.is_ignored(&dir("foo/bar")));
+    assert!(!rules.is_ignored(&dir("baz/foo/bar")));
+  }
+
+  #[test]
+  fn is_ignored_restricts_trailing_slash_to_directories() {
+    let rules = IgnoreRules::parse("out/\n");
+
+    assert!(rules.is_ignored(&dir("out")));
+    assert!(!rules.is_ignored(&file("out")));
+  }
+
+  #[test]
+  fn is_ignored_applies_last_matching_rule() {
+    let rules = IgnoreRules::parse("*.log\n!keep.log\n");
+
+    assert!(rules.is_ignored(&file("debug.log")));
+    assert!(!rules.is_ignored(&file("keep.log")));
+  }
+
+  #[test]
+  fn is_ignored_skips_comments_and_blank_lines() {
+    let rules = IgnoreRules::parse("# comment\n\n   \n\\#literal\n");
+
+    assert!(!rules.is_ignored(&file("comment")));
+    assert!(rules.is_ignored(&file("#literal")));
+  }
+
+  #[test]
+  fn is_ignored_always_ignores_convenience_symlinks() {
+    let rules = IgnoreRules::default();
+    let symlink = |path: &str| Entry {
+      path: PathBuf::from(path),
+      kind: EntryKind::Symlink(PathBuf::from("/some/output/base")),
+    };
+
+    assert!(rules.is_ignored(&symlink("razel-out")));
+    assert!(rules.is_ignored(&symlink("razel-bin")));
+    assert!(!rules.is_ignored(&symlink("foo/razel-out")));
+    assert!(!rules.is_ignored(&dir("razel-tools")));
+  }
+
+  #[test]
+  fn load_reads_ignore_file() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new(".razelignore"), TestContents::File("node_modules/\n")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(
+      IgnoreRules::load(&host)?,
+      IgnoreRules::parse("node_modules/\n"),
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn load_returns_no_rules_without_ignore_file() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(IgnoreRules::load(&host)?, IgnoreRules::default());
+
+    Ok(())
+  }
+}
@@ -1,5 +1,11 @@
 pub mod fs_host;
+#[allow(clippy::module_inception)]
 pub mod host;
+pub mod ignore;
+pub mod overlay_host;
+pub mod path_pattern;
+pub mod source_writer;
+pub mod walk;
 
 #[cfg(test)]
-mod test_dir;
+pub mod test_dir;
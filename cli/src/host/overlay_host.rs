@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use super::host::{Entry, EntryKind, Host, Process, ProcessOutput};
+
+/// A `Host` which reads the unsaved contents of files open in an editor in
+/// place of the files themselves, so they are analyzed as they are being
+/// edited. Everything else is delegated to the underlying host.
+pub struct OverlayHost {
+  host: Box<dyn Host>,
+
+  /// The contents of open files by workspace-relative path.
+  buffers: Mutex<BTreeMap<PathBuf, String>>,
+}
+
+impl OverlayHost {
+  pub fn new(host: Box<dyn Host>) -> OverlayHost {
+    OverlayHost { host, buffers: Mutex::new(BTreeMap::new()) }
+  }
+
+  /// Sets the contents the file at the given workspace-relative path is read
+  /// with, or reads it from the underlying host again with `None`.
+  pub fn set_buffer(&self, path: &Path, contents: Option<String>) {
+    let mut buffers = self.buffers.lock().unwrap();
+    match contents {
+      Some(contents) => buffers.insert(path.to_owned(), contents),
+      None => buffers.remove(path),
+    };
+  }
+
+  /// Returns the workspace-relative paths of the files with buffers.
+  pub fn buffered(&self) -> Vec<PathBuf> {
+    self.buffers.lock().unwrap().keys().cloned().collect()
+  }
+
+  fn buffer(&self, path: &Path) -> Option<String> {
+    self.buffers.lock().unwrap().get(path).cloned()
+  }
+}
+
+impl Host for OverlayHost {
+  fn read_to_string(&self, path: &Path) -> Result<String, Box<dyn Error>> {
+    match self.buffer(path) {
+      Some(contents) => Ok(contents),
+      None => self.host.read_to_string(path),
+    }
+  }
+
+  fn read(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    match self.buffer(path) {
+      Some(contents) => Ok(contents.into_bytes()),
+      None => self.host.read(path),
+    }
+  }
+
+  fn is_executable(&self, path: &Path) -> Result<bool, Box<dyn Error>> {
+    self.host.is_executable(path)
+  }
+
+  fn modified(&self, path: &Path) -> Result<SystemTime, Box<dyn Error>> {
+    self.host.modified(path)
+  }
+
+  /// Lists the directory like the underlying host, adding files which are
+  /// open but not saved yet.
+  fn list(&self, path: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let mut entries = self.host.list(path)?;
+    for buffer in self.buffers.lock().unwrap().keys() {
+      if buffer.parent() == Some(path) && !entries.iter().any(|entry| entry.path == *buffer) {
+        entries.push(Entry { path: buffer.clone(), kind: EntryKind::File });
+      }
+    }
+    entries.sort();
+    Ok(entries)
+  }
+
+  fn resolve(&self, path: &Path) -> Result<Entry, Box<dyn Error>> {
+    match self.host.resolve(path) {
+      Err(_) if self.buffer(path).is_some() => {
+        Ok(Entry { path: path.to_owned(), kind: EntryKind::File })
+      },
+      resolved => resolved,
+    }
+  }
+
+  /// Returns a host of the other root without this host's buffers, which are
+  /// only of files in the workspace.
+  fn with_source_root(&self, root: &Path) -> Result<Box<dyn Host>, Box<dyn Error>> {
+    self.host.with_source_root(root)
+  }
+
+  fn source_root(&self) -> &Path {
+    self.host.source_root()
+  }
+
+  fn output_base(&self) -> &Path {
+    self.host.output_base()
+  }
+
+  fn create_output_dir(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+    self.host.create_output_dir(path)
+  }
+
+  fn write_output(&self, path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    self.host.write_output(path, contents)
+  }
+
+  fn set_output_executable(&self, path: &Path, executable: bool) -> Result<(), Box<dyn Error>> {
+    self.host.set_output_executable(path, executable)
+  }
+
+  fn symlink_output(&self, path: &Path, target: &Path) -> Result<(), Box<dyn Error>> {
+    self.host.symlink_output(path, target)
+  }
+
+  fn delete_output(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+    self.host.delete_output(path)
+  }
+
+  fn read_output(&self, path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    self.host.read_output(path)
+  }
+
+  fn output_kind(&self, path: &Path) -> Result<Option<EntryKind>, Box<dyn Error>> {
+    self.host.output_kind(path)
+  }
+
+  fn execute(&self, process: &Process) -> Result<ProcessOutput, Box<dyn Error>> {
+    self.host.execute(process)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+  use super::*;
+
+  #[test]
+  fn overlay_host_reads_buffers_in_place_of_files() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/pkg/BUILD"), TestContents::File("saved")),
+    ])?;
+    let host = OverlayHost::new(Box::new(FsHost::with_output_base(
+      &dir.root.join("wksp"),
+      &dir.root.join("out"),
+    )?));
+
+    host.set_buffer(Path::new("pkg/BUILD"), Some("unsaved".to_owned()));
+    host.set_buffer(Path::new("pkg/new.bzl"), Some("new".to_owned()));
+    assert_eq!(host.read_to_string(Path::new("pkg/BUILD"))?, "unsaved");
+    assert_eq!(host.read(Path::new("pkg/new.bzl"))?, b"new");
+    assert_eq!(host.list(Path::new("pkg"))?, [
+      Entry { path: PathBuf::from("pkg/BUILD"), kind: EntryKind::File },
+      Entry { path: PathBuf::from("pkg/new.bzl"), kind: EntryKind::File },
+    ]);
+    assert_eq!(host.resolve(Path::new("pkg/new.bzl"))?.kind, EntryKind::File);
+
+    host.set_buffer(Path::new("pkg/BUILD"), None);
+    assert_eq!(host.read_to_string(Path::new("pkg/BUILD"))?, "saved");
+    Ok(())
+  }
+}
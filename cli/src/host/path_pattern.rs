@@ -0,0 +1,230 @@
+use std::path::Path;
+
+/// A pattern matching slash-separated paths. Each segment may contain `*` to
+/// match any number of characters, `?` to match exactly one character and
+/// `[...]` to match one character in a set (`[!...]` negates the set). A
+/// segment of exactly `**` matches zero or more whole segments. No wildcard
+/// ever matches a `/`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathPattern {
+  segments: Vec<String>,
+}
+
+impl PathPattern {
+  /// Parses a slash-separated pattern. Empty segments are dropped, so leading,
+  /// trailing and repeated slashes are insignificant.
+  pub fn parse(pattern: &str) -> PathPattern {
+    PathPattern {
+      segments: pattern.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| segment.to_owned())
+        .collect(),
+    }
+  }
+
+  /// Returns whether this pattern matches the given relative path.
+  pub fn matches(&self, path: &Path) -> bool {
+    let path: Vec<_> = path.iter()
+      .map(|segment| segment.to_str().unwrap())
+      .collect();
+
+    matches_segments(&self.segments, &path)
+  }
+
+  /// Returns whether this pattern could match any path nested inside the given
+  /// directory, used to avoid listing directories which cannot match.
+  pub fn matches_descendant_of(&self, dir: &Path) -> bool {
+    let dir: Vec<_> = dir.iter()
+      .map(|segment| segment.to_str().unwrap())
+      .collect();
+
+    matches_prefix(&self.segments, &dir)
+  }
+
+  /// Returns whether any segment of this pattern contains a `**` without being
+  /// exactly `**`, which is not supported.
+  pub fn has_partial_recursive_wildcard(&self) -> bool {
+    self.segments.iter()
+      .any(|segment| segment != "**" && segment.contains("**"))
+  }
+}
+
+/// Returns whether the pattern segments match the path segments in their
+/// entirety.
+fn matches_segments(pattern: &[String], path: &[&str]) -> bool {
+  match pattern.split_first() {
+    None => path.is_empty(),
+    Some((first, rest)) if first == "**" => {
+      // Try consuming every possible number of path segments.
+      (0..=path.len()).any(|skip| matches_segments(rest, &path[skip..]))
+    },
+    Some((first, rest)) => match path.split_first() {
+      None => false,
+      Some((segment, path_rest)) => {
+        matches_segment(first, segment) && matches_segments(rest, path_rest)
+      },
+    },
+  }
+}
+
+/// Returns whether the pattern segments could match some path strictly nested
+/// inside the given path segments.
+fn matches_prefix(pattern: &[String], path: &[&str]) -> bool {
+  match (pattern.split_first(), path.split_first()) {
+    (None, _) => false,
+    (Some((first, _)), _) if first == "**" => true,
+    (Some(_), None) => true,
+    (Some((first, rest)), Some((segment, path_rest))) => {
+      matches_segment(first, segment) && matches_prefix(rest, path_rest)
+    },
+  }
+}
+
+/// Returns whether a single segment pattern matches a single path segment.
+pub fn matches_segment(pattern: &str, segment: &str) -> bool {
+  let pattern: Vec<_> = pattern.chars().collect();
+  let segment: Vec<_> = segment.chars().collect();
+
+  matches_chars(&pattern, &segment)
+}
+
+fn matches_chars(pattern: &[char], text: &[char]) -> bool {
+  match pattern.first() {
+    None => text.is_empty(),
+    Some('*') => {
+      (0..=text.len()).any(|skip| matches_chars(&pattern[1..], &text[skip..]))
+    },
+    Some('?') => !text.is_empty() && matches_chars(&pattern[1..], &text[1..]),
+    Some('[') => match (parse_class(pattern), text.first()) {
+      (Some((matches, len)), Some(c)) => {
+        matches(*c) && matches_chars(&pattern[len..], &text[1..])
+      },
+      // An unterminated `[` is treated as a literal.
+      (None, Some('[')) => matches_chars(&pattern[1..], &text[1..]),
+      _ => false,
+    },
+    Some('\\') if pattern.len() > 1 => {
+      text.first() == Some(&pattern[1])
+        && matches_chars(&pattern[2..], &text[1..])
+    },
+    Some(c) => text.first() == Some(c) && matches_chars(&pattern[1..], &text[1..]),
+  }
+}
+
+/// Parses a `[...]` character class at the start of the pattern. Returns a
+/// predicate for the class and the number of pattern characters it consumed,
+/// or `None` if the class is unterminated.
+fn parse_class(pattern: &[char]) -> Option<(impl Fn(char) -> bool, usize)> {
+  let mut index = 1;
+  let negated = matches!(pattern.get(index), Some('!') | Some('^'));
+  if negated {
+    index += 1;
+  }
+
+  let mut ranges = Vec::new();
+  let start = index;
+  loop {
+    let c = *pattern.get(index)?;
+    // A `]` immediately after the opening bracket is a literal.
+    if c == ']' && index != start {
+      break;
+    }
+
+    if pattern.get(index + 1) == Some(&'-')
+        && pattern.get(index + 2).is_some_and(|end| *end != ']') {
+      ranges.push((c, pattern[index + 2]));
+      index += 3;
+    } else {
+      ranges.push((c, c));
+      index += 1;
+    }
+  }
+
+  let predicate = move |c: char| {
+    ranges.iter().any(|(low, high)| *low <= c && c <= *high) != negated
+  };
+  Some((predicate, index + 1))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn matches_literal_paths() {
+    let pattern = PathPattern::parse("foo/bar.txt");
+
+    assert!(pattern.matches(Path::new("foo/bar.txt")));
+    assert!(!pattern.matches(Path::new("foo/baz.txt")));
+    assert!(!pattern.matches(Path::new("foo")));
+    assert!(!pattern.matches(Path::new("foo/bar.txt/baz")));
+  }
+
+  #[test]
+  fn matches_star_within_a_segment() {
+    let pattern = PathPattern::parse("foo/*.ts");
+
+    assert!(pattern.matches(Path::new("foo/bar.ts")));
+    assert!(pattern.matches(Path::new("foo/.ts")));
+    assert!(!pattern.matches(Path::new("foo/bar/baz.ts")));
+    assert!(!pattern.matches(Path::new("foo/bar.js")));
+  }
+
+  #[test]
+  fn matches_question_mark_as_one_character() {
+    let pattern = PathPattern::parse("file?.txt");
+
+    assert!(pattern.matches(Path::new("file1.txt")));
+    assert!(!pattern.matches(Path::new("file.txt")));
+    assert!(!pattern.matches(Path::new("file12.txt")));
+  }
+
+  #[test]
+  fn matches_double_star_across_segments() {
+    let pattern = PathPattern::parse("src/**/*.ts");
+
+    assert!(pattern.matches(Path::new("src/foo.ts")));
+    assert!(pattern.matches(Path::new("src/foo/bar.ts")));
+    assert!(pattern.matches(Path::new("src/foo/bar/baz.ts")));
+    assert!(!pattern.matches(Path::new("lib/foo.ts")));
+    assert!(!pattern.matches(Path::new("src/foo/bar.js")));
+  }
+
+  #[test]
+  fn matches_descendant_of_checks_directory_prefixes() {
+    let pattern = PathPattern::parse("src/*/test/*.ts");
+
+    assert!(pattern.matches_descendant_of(Path::new("")));
+    assert!(pattern.matches_descendant_of(Path::new("src")));
+    assert!(pattern.matches_descendant_of(Path::new("src/foo")));
+    assert!(pattern.matches_descendant_of(Path::new("src/foo/test")));
+    assert!(!pattern.matches_descendant_of(Path::new("src/foo/test/bar")));
+    assert!(!pattern.matches_descendant_of(Path::new("lib")));
+    assert!(PathPattern::parse("src/**").matches_descendant_of(
+      Path::new("src/foo/bar/baz"),
+    ));
+  }
+
+  #[test]
+  fn has_partial_recursive_wildcard_detects_misused_double_star() {
+    assert!(!PathPattern::parse("src/**/*.ts").has_partial_recursive_wildcard());
+    assert!(PathPattern::parse("src/**.ts").has_partial_recursive_wildcard());
+  }
+
+  #[test]
+  fn matches_character_classes() {
+    assert!(matches_segment("[abc].txt", "a.txt"));
+    assert!(!matches_segment("[abc].txt", "d.txt"));
+    assert!(matches_segment("[a-c].txt", "b.txt"));
+    assert!(!matches_segment("[!a-c].txt", "b.txt"));
+    assert!(matches_segment("[!a-c].txt", "d.txt"));
+    assert!(matches_segment("[]].txt", "].txt"));
+    assert!(matches_segment("[abc", "[abc"));
+  }
+
+  #[test]
+  fn matches_escaped_characters_literally() {
+    assert!(matches_segment("\\*.txt", "*.txt"));
+    assert!(!matches_segment("\\*.txt", "foo.txt"));
+  }
+}
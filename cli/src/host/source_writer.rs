@@ -0,0 +1,177 @@
+use std::error::Error;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use super::fs_host::{canonicalize_existing, delete, normalize, set_executable, write_atomically};
+use super::host::{ExternalPathError, Host, SourceWriteError};
+
+/// The file marking a directory as a copy of a repository written by `razel
+/// vendor`, which a later run may replace.
+pub const VENDOR_MARKER: &str = ".razel-vendored";
+
+/// Writes files in the source tree, which a `Host` never does. Only the
+/// commands which exist to modify sources, such as updating the lockfile,
+/// vendoring repositories and formatting BUILD files, create one, and builds
+/// to link to their outputs.
+pub struct SourceWriter {
+  root: PathBuf,
+}
+
+impl SourceWriter {
+  /// Returns a writer of the source tree `host` reads from.
+  pub fn new(host: &dyn Host) -> SourceWriter {
+    SourceWriter { root: host.source_root().to_owned() }
+  }
+
+  /// Atomically writes the given contents to a file, creating any missing
+  /// parent directories. The path is resolved relative to the source root.
+  pub fn write(&self, path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    let resolved = self.resolve(path)?;
+    fs::create_dir_all(resolved.parent().unwrap())?;
+
+    write_atomically(&resolved, contents)
+  }
+
+  /// Sets or clears the executable bit of a file. The path is resolved
+  /// relative to the source root.
+  pub fn set_executable(&self, path: &Path, executable: bool) -> Result<(), Box<dyn Error>> {
+    set_executable(&self.resolve(path)?, executable)
+  }
+
+  /// Points a symlink at `target`, replacing an earlier symlink but never
+  /// anything else, which fails with a `SourceWriteError`. The path is
+  /// resolved relative to the source root.
+  pub fn symlink(&self, path: &Path, target: &Path) -> Result<(), Box<dyn Error>> {
+    let resolved = self.resolve(path)?;
+    match fs::symlink_metadata(&resolved) {
+      Ok(metadata) if metadata.is_symlink() => {
+        if fs::read_link(&resolved)? == target {
+          return Ok(());
+        }
+        fs::remove_file(&resolved)?;
+      },
+      Ok(_) => return Err(Box::new(SourceWriteError(format!(
+        "Refusing to replace \"{}\", which is not a symlink.",
+        path.to_str().unwrap(),
+      )))),
+      Err(_) => fs::create_dir_all(resolved.parent().unwrap())?,
+    }
+
+    symlink(target, &resolved)?;
+    Ok(())
+  }
+
+  /// Deletes a directory previously written by `razel vendor`, which carries
+  /// a `VENDOR_MARKER`. Succeeds if nothing exists at the path and fails with
+  /// a `SourceWriteError` rather than delete anything else, so a mistyped
+  /// vendor directory never destroys sources. The path is resolved relative
+  /// to the source root.
+  pub fn delete_vendored(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+    let resolved = self.resolve(path)?;
+    let Ok(metadata) = fs::symlink_metadata(&resolved) else {
+      return Ok(());
+    };
+    if resolved == self.root || !metadata.is_dir() || !resolved.join(VENDOR_MARKER).is_file() {
+      return Err(Box::new(SourceWriteError(format!(
+        "Refusing to replace \"{}\", which was not written by `razel vendor`.",
+        path.to_str().unwrap(),
+      ))));
+    }
+
+    delete(&resolved)
+  }
+
+  /// Resolves the given path of a file to write or delete to an absolute path
+  /// on the file system, following symlinks in all but the final path
+  /// component. Fails with an `ExternalPathError` if the path refers to
+  /// anything outside the source root.
+  fn resolve(&self, path: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let normalized = normalize(&self.root.join(path))?;
+    let real = match (normalized.parent(), normalized.file_name()) {
+      (Some(parent), Some(name)) => canonicalize_existing(parent)?.join(name),
+      _ => normalized.clone(),
+    };
+    if !normalized.starts_with(&self.root) || !real.starts_with(&self.root) {
+      return Err(Box::new(ExternalPathError(format!(
+        "Path \"{}\" is outside the workspace.",
+        path.to_str().unwrap(),
+      ))));
+    }
+
+    Ok(real)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+
+  #[test]
+  fn write_writes_file_in_workspace() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([
+      (Path::new("MODULE.razel.lock"), TestContents::File("old")),
+    ])?;
+    let out = TestDir::from([])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+    let writer = SourceWriter::new(&host);
+    writer.write(Path::new("MODULE.razel.lock"), b"new")?;
+
+    assert_eq!(fs::read_to_string(wksp.root.join("MODULE.razel.lock"))?, "new");
+    assert!(writer.write(Path::new("../foo.txt"), b"").unwrap_err().is::<ExternalPathError>());
+
+    Ok(())
+  }
+
+  #[test]
+  fn delete_vendored_only_deletes_marked_directories() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([
+      (Path::new("vendor/lib/.razel-vendored"), TestContents::File("")),
+      (Path::new("vendor/lib/BUILD.razel"), TestContents::File("")),
+      (Path::new("src/BUILD.razel"), TestContents::File("")),
+      (Path::new("file.txt"), TestContents::File("")),
+    ])?;
+    let out = TestDir::from([])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+    let writer = SourceWriter::new(&host);
+
+    writer.delete_vendored(Path::new("vendor/lib"))?;
+    assert!(!wksp.root.join("vendor/lib").exists());
+    writer.delete_vendored(Path::new("vendor/missing"))?;
+
+    for path in ["src", "file.txt", "", "."] {
+      let err = writer.delete_vendored(Path::new(path)).unwrap_err();
+      assert!(err.is::<SourceWriteError>());
+    }
+    assert!(wksp.root.join("src/BUILD.razel").exists());
+    assert!(wksp.root.join("file.txt").exists());
+
+    Ok(())
+  }
+
+  #[test]
+  fn symlink_only_replaces_symlinks() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([
+      (Path::new("razel-out"), TestContents::Symlink("/old")),
+      (Path::new("file.txt"), TestContents::File("")),
+    ])?;
+    let out = TestDir::from([])?;
+
+    let host = FsHost::with_output_base(&wksp.root, &out.root)?;
+    let writer = SourceWriter::new(&host);
+
+    writer.symlink(Path::new("razel-out"), Path::new("/new"))?;
+    assert_eq!(fs::read_link(wksp.root.join("razel-out"))?, Path::new("/new"));
+    writer.symlink(Path::new("razel-bin"), Path::new("/new"))?;
+    assert_eq!(fs::read_link(wksp.root.join("razel-bin"))?, Path::new("/new"));
+
+    let err = writer.symlink(Path::new("file.txt"), Path::new("/new")).unwrap_err();
+    assert!(err.is::<SourceWriteError>());
+    assert!(wksp.root.join("file.txt").is_file());
+
+    Ok(())
+  }
+}
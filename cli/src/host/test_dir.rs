@@ -1,5 +1,5 @@
 use rand::random;
-use std::{collections::HashSet, env::temp_dir, error::Error, fmt::Display, fs, path::{Path, PathBuf}};
+use std::{collections::HashSet, env::temp_dir, error::Error, fmt::Display, fs, os::unix::fs::symlink, path::{Path, PathBuf}};
 
 /// A temporary directory to contain arbitrary test content which is
 /// automatically cleaned up when dropped.
@@ -34,7 +34,7 @@ impl TestDir {
         // Write file.
         TestContents::File(contents) => {
           let dir = resolved.parent().unwrap();
-          fs::create_dir_all(&dir)?;
+          fs::create_dir_all(dir)?;
 
           fs::write(resolved, contents)?;
           written_files.insert(path);
@@ -44,6 +44,15 @@ impl TestDir {
         TestContents::Directory => {
           fs::create_dir_all(resolved)?;
         },
+
+        // Create symlink.
+        TestContents::Symlink(target) => {
+          let dir = resolved.parent().unwrap();
+          fs::create_dir_all(dir)?;
+
+          symlink(target, resolved)?;
+          written_files.insert(path);
+        },
       }
     }
 
@@ -66,6 +75,10 @@ pub enum TestContents<'a> {
 
   /// An empty directory.
   Directory,
+
+  /// A symlink pointing at the given target, which may be relative to the
+  /// symlink's directory.
+  Symlink(&'a str),
 }
 
 #[derive(Debug)]
@@ -94,13 +107,13 @@ mod test {
     let test_dir = {
       let dir = TestDir::from([])?;
 
-      assert_eq!(fs::exists(&dir.root)?, true);
+      assert!(fs::exists(&dir.root)?);
 
       dir.root.clone()
       // `dir` falls out of scope and should delete the test dir.
     };
 
-    assert_eq!(fs::exists(test_dir)?, false);
+    assert!(!fs::exists(test_dir)?);
 
     Ok(())
   }
@@ -112,8 +125,8 @@ mod test {
       (Path::new("bar/baz.txt"), TestContents::File("baz")),
     ])?;
 
-    assert_eq!(fs::read_to_string(&dir.root.join("foo.txt"))?, "foo");
-    assert_eq!(fs::read_to_string(&dir.root.join("bar/baz.txt"))?, "baz");
+    assert_eq!(fs::read_to_string(dir.root.join("foo.txt"))?, "foo");
+    assert_eq!(fs::read_to_string(dir.root.join("bar/baz.txt"))?, "baz");
 
     Ok(())
   }
@@ -125,9 +138,9 @@ mod test {
       (Path::new("bar/baz"), TestContents::Directory),
     ])?;
 
-    assert_is_empty!(fs::read_dir(&dir.root.join("foo"))?.collect::<Vec<_>>());
+    assert_is_empty!(fs::read_dir(dir.root.join("foo"))?.collect::<Vec<_>>());
     assert_is_empty!(
-        fs::read_dir(&dir.root.join("bar/baz"))?.collect::<Vec<_>>());
+        fs::read_dir(dir.root.join("bar/baz"))?.collect::<Vec<_>>());
 
     Ok(())
   }
@@ -167,4 +180,20 @@ mod test {
 
     Ok(())
   }
+
+  #[test]
+  fn test_dir_creates_symlinks() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("foo.txt"), TestContents::File("foo")),
+      (Path::new("bar/link.txt"), TestContents::Symlink("../foo.txt")),
+    ])?;
+
+    assert_eq!(
+      fs::read_link(dir.root.join("bar/link.txt"))?,
+      PathBuf::from("../foo.txt"),
+    );
+    assert_eq!(fs::read_to_string(dir.root.join("bar/link.txt"))?, "foo");
+
+    Ok(())
+  }
 }
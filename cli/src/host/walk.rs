@@ -0,0 +1,473 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use super::host::{Entry, EntryKind, Host};
+use super::ignore::IgnoreRules;
+
+/// The number of entries which may be buffered before workers wait for the
+/// consumer to catch up.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Options for a parallel traversal of the workspace.
+pub struct WalkOptions<'a> {
+  /// The maximum number of directories listed concurrently.
+  pub threads: usize,
+
+  /// Decides whether to report and traverse the contents of a directory, given
+  /// its workspace-relative path and its (non-ignored) entries. Never called
+  /// for the root of the walk, whose contents are always reported.
+  pub descend: &'a (dyn Fn(&Path, &[Entry]) -> bool + Sync),
+}
+
+impl Default for WalkOptions<'_> {
+  fn default() -> Self {
+    WalkOptions {
+      threads: thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(1),
+      descend: &always_descend,
+    }
+  }
+}
+
+fn always_descend(_dir: &Path, _entries: &[Entry]) -> bool {
+  true
+}
+
+/// An entry found while walking the workspace.
+#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct WalkEntry {
+  /// The workspace-relative path of the entry. Entries found through a symlink
+  /// are reported at their path through the symlink.
+  pub path: PathBuf,
+
+  /// The kind of the entry with symlinks resolved, so this is never
+  /// `EntryKind::Symlink`.
+  pub kind: EntryKind,
+
+  /// For directories, whether their contents were reported and traversed as
+  /// decided by `WalkOptions::descend`. Always `false` for other entries.
+  pub descended: bool,
+}
+
+/// Walks all descendants of `root` in parallel, passing a stream of the
+/// entries found to `consume` and returning its result. Entries arrive in no
+/// particular order. Directories are reported once they have been listed.
+/// Ignored paths are skipped and symlinks are followed. The first error
+/// cancels the walk and is the last item of the stream. Returning from
+/// `consume` early cancels any remaining work.
+pub fn walk<T>(
+  host: &dyn Host,
+  root: &Path,
+  options: &WalkOptions,
+  consume: impl FnOnce(Walk) -> T,
+) -> Result<T, Box<dyn Error>> {
+  let ignore = IgnoreRules::load(host)?;
+  let resolved = host.resolve(root)?;
+  let threads = options.threads.max(1);
+
+  let shared = Shared {
+    host,
+    ignore,
+    options,
+    queues: (0..threads).map(|_| Mutex::new(VecDeque::new())).collect(),
+    pending: AtomicUsize::new(1),
+    cancelled: AtomicBool::new(false),
+    idle: (Mutex::new(()), Condvar::new()),
+  };
+  shared.queues[0].lock().unwrap().push_back(Work {
+    path: root.to_path_buf(),
+    ancestors: Arc::new(vec![resolved.path]),
+    is_root: true,
+  });
+
+  let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+  Ok(thread::scope(|scope| {
+    for index in 0..threads {
+      let shared = &shared;
+      let sender = sender.clone();
+      scope.spawn(move || shared.work(index, sender));
+    }
+    drop(sender);
+
+    let result = consume(Walk { receiver, cancelled: &shared.cancelled, done: false });
+    shared.cancelled.store(true, Ordering::SeqCst);
+    result
+  }))
+}
+
+/// A stream of entries found while walking the workspace.
+pub struct Walk<'a> {
+  receiver: Receiver<Result<WalkEntry, WalkError>>,
+  cancelled: &'a AtomicBool,
+  done: bool,
+}
+
+impl Iterator for Walk<'_> {
+  type Item = Result<WalkEntry, WalkError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.done {
+      return None;
+    }
+
+    let item = self.receiver.recv().ok();
+    if !matches!(item, Some(Ok(_))) {
+      self.done = true;
+      self.cancelled.store(true, Ordering::SeqCst);
+    }
+    item
+  }
+}
+
+/// A directory waiting to be listed.
+struct Work {
+  /// The workspace-relative path of the directory.
+  path: PathBuf,
+
+  /// Canonical paths of this directory and all its ancestors, used to detect
+  /// symlink cycles. The last item is this directory.
+  ancestors: Arc<Vec<PathBuf>>,
+
+  /// Whether this is the root of the walk.
+  is_root: bool,
+}
+
+/// State shared between all workers.
+struct Shared<'a> {
+  host: &'a dyn Host,
+  ignore: IgnoreRules,
+  options: &'a WalkOptions<'a>,
+
+  /// One queue of directories per worker. Workers take from the back of their
+  /// own queue and steal from the front of others.
+  queues: Vec<Mutex<VecDeque<Work>>>,
+
+  /// The number of directories queued or currently being listed.
+  pending: AtomicUsize,
+
+  cancelled: AtomicBool,
+
+  /// Notified when work is queued or the walk finishes.
+  idle: (Mutex<()>, Condvar),
+}
+
+impl Shared<'_> {
+  fn work(&self, index: usize, sender: SyncSender<Result<WalkEntry, WalkError>>) {
+    while !self.cancelled.load(Ordering::SeqCst) {
+      match self.next_work(index) {
+        Some(work) => {
+          if let Err(err) = self.list(index, work, &sender) {
+            self.cancelled.store(true, Ordering::SeqCst);
+            // The consumer may have already stopped listening.
+            let _ = sender.send(Err(WalkError(err.to_string())));
+          }
+
+          if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.idle.1.notify_all();
+          }
+        },
+        None => {
+          if self.pending.load(Ordering::SeqCst) == 0 {
+            break;
+          }
+
+          // Wait for more work, with a timeout in case a notification is
+          // missed between checking the queues and waiting.
+          let guard = self.idle.0.lock().unwrap();
+          let _ = self.idle.1.wait_timeout(guard, Duration::from_millis(1));
+        },
+      }
+    }
+
+    self.idle.1.notify_all();
+  }
+
+  /// Takes work from this worker's own queue or steals it from another.
+  fn next_work(&self, index: usize) -> Option<Work> {
+    if let Some(work) = self.queues[index].lock().unwrap().pop_back() {
+      return Some(work);
+    }
+
+    (1..self.queues.len())
+      .map(|offset| (index + offset) % self.queues.len())
+      .find_map(|victim| self.queues[victim].lock().unwrap().pop_front())
+  }
+
+  /// Lists a single directory, reports its entries and queues its
+  /// subdirectories.
+  fn list(
+    &self,
+    index: usize,
+    work: Work,
+    sender: &SyncSender<Result<WalkEntry, WalkError>>,
+  ) -> Result<(), Box<dyn Error>> {
+    let entries: Vec<_> = self.host.list(&work.path)?.into_iter()
+      .filter(|entry| !self.ignore.is_ignored(entry))
+      .collect();
+
+    let descended = work.is_root || (self.options.descend)(&work.path, &entries);
+    if !work.is_root {
+      self.send(sender, WalkEntry {
+        path: work.path.clone(),
+        kind: EntryKind::Directory,
+        descended,
+      })?;
+    }
+    if !descended {
+      return Ok(());
+    }
+
+    let canonical = work.ancestors.last().unwrap();
+    for entry in entries {
+      let (kind, target) = match entry.kind {
+        EntryKind::Symlink(_) => {
+          let resolved = self.host.resolve(&entry.path)?;
+          (resolved.kind, resolved.path)
+        },
+        kind => (kind, canonical.join(entry.path.file_name().unwrap())),
+      };
+
+      match kind {
+        EntryKind::Directory => {
+          if work.ancestors.contains(&target) {
+            return Err(Box::new(WalkError(format!(
+              "Symlink \"{}\" points at its own ancestor \"{}\".",
+              entry.path.to_str().unwrap(),
+              target.to_str().unwrap(),
+            ))));
+          }
+
+          let mut ancestors = work.ancestors.as_ref().clone();
+          ancestors.push(target);
+          self.pending.fetch_add(1, Ordering::SeqCst);
+          self.queues[index].lock().unwrap().push_back(Work {
+            path: entry.path,
+            ancestors: Arc::new(ancestors),
+            is_root: false,
+          });
+          self.idle.1.notify_one();
+        },
+        kind => self.send(sender, WalkEntry {
+          path: entry.path,
+          kind,
+          descended: false,
+        })?,
+      }
+    }
+
+    Ok(())
+  }
+
+  fn send(
+    &self,
+    sender: &SyncSender<Result<WalkEntry, WalkError>>,
+    entry: WalkEntry,
+  ) -> Result<(), Box<dyn Error>> {
+    sender.send(Ok(entry)).map_err(|_| {
+      Box::new(WalkError("Walk was cancelled.".to_owned())) as Box<dyn Error>
+    })
+  }
+}
+
+/// An error encountered while walking the workspace.
+#[derive(Debug, PartialEq)]
+pub struct WalkError(pub String);
+
+impl Display for WalkError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", &self.0)
+  }
+}
+
+impl Error for WalkError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use assertables::{assert_contains, assert_set_eq, assert_set_impl_prep};
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+
+  fn collect(host: &dyn Host, root: &Path, options: &WalkOptions) ->
+      Result<Vec<WalkEntry>, Box<dyn Error>> {
+    Ok(walk(host, root, options, |entries| entries.collect::<Result<_, _>>())??)
+  }
+
+  fn file(path: &str) -> WalkEntry {
+    WalkEntry {
+      path: PathBuf::from(path),
+      kind: EntryKind::File,
+      descended: false,
+    }
+  }
+
+  fn directory(path: &str, descended: bool) -> WalkEntry {
+    WalkEntry {
+      path: PathBuf::from(path),
+      kind: EntryKind::Directory,
+      descended,
+    }
+  }
+
+  #[test]
+  fn walk_finds_all_descendants() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("foo/bar/baz.txt"), TestContents::File("")),
+      (Path::new("foo/hello.txt"), TestContents::File("")),
+      (Path::new("empty"), TestContents::Directory),
+      (Path::new("world.txt"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_set_eq!(collect(&host, Path::new(""), &WalkOptions::default())?, [
+      directory("foo", true),
+      directory("foo/bar", true),
+      file("foo/bar/baz.txt"),
+      file("foo/hello.txt"),
+      directory("empty", true),
+      file("world.txt"),
+    ]);
+
+    Ok(())
+  }
+
+  #[test]
+  fn walk_finds_the_same_entries_with_one_thread() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("a/b/c/d.txt"), TestContents::File("")),
+      (Path::new("a/e.txt"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+    let options = WalkOptions { threads: 1, ..WalkOptions::default() };
+
+    assert_set_eq!(collect(&host, Path::new("a"), &options)?, [
+      directory("a/b", true),
+      directory("a/b/c", true),
+      file("a/b/c/d.txt"),
+      file("a/e.txt"),
+    ]);
+
+    Ok(())
+  }
+
+  #[test]
+  fn walk_skips_directories_rejected_by_descend() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("keep/foo.txt"), TestContents::File("")),
+      (Path::new("skip/BUILD"), TestContents::File("")),
+      (Path::new("skip/bar.txt"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+    let descend = |_: &Path, entries: &[Entry]| !entries.iter()
+      .any(|entry| entry.path.ends_with("BUILD"));
+    let options = WalkOptions { descend: &descend, ..WalkOptions::default() };
+
+    assert_set_eq!(collect(&host, Path::new(""), &options)?, [
+      directory("keep", true),
+      file("keep/foo.txt"),
+      directory("skip", false),
+    ]);
+
+    Ok(())
+  }
+
+  #[test]
+  fn walk_skips_ignored_paths() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new(".razelignore"), TestContents::File("node_modules/\n")),
+      (Path::new("node_modules/dep/index.js"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_set_eq!(collect(&host, Path::new(""), &WalkOptions::default())?, [
+      file(".razelignore"),
+    ]);
+
+    Ok(())
+  }
+
+  #[test]
+  fn walk_follows_symlinks() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("real/foo.txt"), TestContents::File("")),
+      (Path::new("link"), TestContents::Symlink("real")),
+      (Path::new("file_link.txt"), TestContents::Symlink("real/foo.txt")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_set_eq!(collect(&host, Path::new(""), &WalkOptions::default())?, [
+      directory("real", true),
+      file("real/foo.txt"),
+      directory("link", true),
+      file("link/foo.txt"),
+      file("file_link.txt"),
+    ]);
+
+    Ok(())
+  }
+
+  #[test]
+  fn walk_ends_with_first_error() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("foo/bar/loop"), TestContents::Symlink("../../foo")),
+      (Path::new("other/file.txt"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    let items = walk(&host, Path::new(""), &WalkOptions::default(), |entries| {
+      entries.collect::<Vec<_>>()
+    })?;
+    let err = items.last().unwrap().as_ref().unwrap_err();
+    assert_contains!(err.to_string(), "points at its own ancestor");
+    assert_eq!(items.iter().filter(|item| item.is_err()).count(), 1);
+
+    Ok(())
+  }
+
+  #[test]
+  fn walk_stops_when_consumer_returns_early() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("a/1.txt"), TestContents::File("")),
+      (Path::new("a/2.txt"), TestContents::File("")),
+      (Path::new("b/3.txt"), TestContents::File("")),
+      (Path::new("c/4.txt"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    let first = walk(&host, Path::new(""), &WalkOptions::default(), |mut entries| {
+      entries.next()
+    })?;
+    assert!(first.is_some());
+
+    Ok(())
+  }
+
+  #[test]
+  fn walk_errors_on_missing_root() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert!(walk(&host, Path::new("foo"), &WalkOptions::default(), |_| ()).is_err());
+
+    Ok(())
+  }
+}
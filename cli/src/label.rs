@@ -0,0 +1,249 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+use crate::starlark::value::{Object, Value};
+use crate::target_pattern::{PatternScope, TargetPattern};
+
+/// The directory of the exec root external repositories are linked into.
+pub const EXTERNAL_DIR: &str = "external";
+
+/// A reference to a single target in a specific package, such as
+/// `//path/to/pkg:target` or `@repo//path/to/pkg:target`.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Label {
+  /// The name of the external repository containing the package, empty for
+  /// the main repository.
+  pub repo: String,
+
+  /// The workspace-relative path of the package containing the target.
+  pub package: String,
+
+  /// The name of the target, which may contain slashes for files in
+  /// subdirectories of the package.
+  pub name: String,
+}
+
+impl Label {
+  /// Parses a label as written in a BUILD or .bzl file in `package` of the
+  /// main repository.
+  pub fn parse(label: &str, package: &str) -> Result<Label, LabelError> {
+    Label::parse_in(label, "", package)
+  }
+
+  /// Parses a label as written in a BUILD or .bzl file in `package` of the
+  /// repository `repo`. Absolute labels use the same `//pkg:target` grammar
+  /// as target patterns, and `//pkg` is short for the target named after the
+  /// last package segment. They refer to `repo` unless prefixed with
+  /// `@name`, or `@` for the main repository, and `@name` alone is short for
+  /// `@name//:name`. Relative labels such as `:target` or `target` refer to
+  /// `package`.
+  pub fn parse_in(label: &str, repo: &str, package: &str) -> Result<Label, LabelError> {
+    let (repo, label) = match split_repo(label)? {
+      (Some(repo), "") => return Ok(Label { repo: repo.to_owned(), package: "".to_owned(), name: repo.to_owned() }),
+      (Some(repo), label) => (repo, label),
+      (None, label) => (repo, label),
+    };
+
+    let parsed = if label.starts_with("//") {
+      let pattern = if label.contains(':') || label.ends_with("/...") {
+        label.to_owned()
+      } else {
+        let name = label.rsplit('/').next().unwrap();
+        format!("{}:{}", label, name)
+      };
+      match TargetPattern::parse(&pattern) {
+        Ok(TargetPattern { package, scope: PatternScope::SingleTarget(name), .. }) => {
+          Label { repo: repo.to_owned(), package, name }
+        },
+        Ok(TargetPattern { package, scope: PatternScope::Package, .. }) => {
+          Label { repo: repo.to_owned(), package, name: "all".to_owned() }
+        },
+        Ok(_) => return Err(LabelError(format!(
+          "Invalid label `{}`, wildcards are only allowed in target patterns.",
+          label,
+        ))),
+        Err(err) => return Err(LabelError(err.0)),
+      }
+    } else {
+      Label {
+        repo: repo.to_owned(),
+        package: package.to_owned(),
+        name: label.strip_prefix(':').unwrap_or(label).to_owned(),
+      }
+    };
+
+    let invalid = |reason: &str| Err(LabelError(format!("Invalid label `{}`, {}.", label, reason)));
+    if parsed.name.is_empty() {
+      return invalid("the target name is empty");
+    }
+    if parsed.name.contains(':') {
+      return invalid("labels may contain at most one `:`");
+    }
+    for (what, path) in [("package", &parsed.package), ("target name", &parsed.name)] {
+      if path.starts_with('/') || path.ends_with('/') ||
+          path.split('/').any(|segment| matches!(segment, "." | "..")) ||
+          path.contains("//") {
+        return invalid(&format!("the {} is not a normalized relative path", what));
+      }
+    }
+
+    Ok(parsed)
+  }
+
+  /// The path of the package relative to the exec root, which is under
+  /// `external/<repo>` for external repositories.
+  pub fn package_path(&self) -> String {
+    package_path(&self.repo, &self.package)
+  }
+}
+
+/// The path of a package of the repository `repo` relative to the exec root.
+pub fn package_path(repo: &str, package: &str) -> String {
+  match (repo, package) {
+    ("", package) => package.to_owned(),
+    (repo, "") => format!("{}/{}", EXTERNAL_DIR, repo),
+    (repo, package) => format!("{}/{}/{}", EXTERNAL_DIR, repo, package),
+  }
+}
+
+/// Splits the `@repo` prefix from a label or target pattern, returning the
+/// repository name if there is one. `@//` and `@@//` refer to the main
+/// repository, whose name is empty.
+pub fn split_repo(label: &str) -> Result<(Option<&str>, &str), LabelError> {
+  let Some(rest) = label.strip_prefix('@') else {
+    return Ok((None, label));
+  };
+  let rest = rest.strip_prefix('@').unwrap_or(rest);
+  let (repo, rest) = rest.find("//").map_or((rest, ""), |index| rest.split_at(index));
+  if !repo.chars().all(|char| char.is_ascii_alphanumeric() || matches!(char, '_' | '-' | '.')) ||
+      (repo.is_empty() && rest.is_empty()) {
+    return Err(LabelError(format!("Invalid repository name in `{}`.", label)));
+  }
+  Ok((Some(repo), rest))
+}
+
+impl Display for Label {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    if !self.repo.is_empty() {
+      write!(f, "@{}", self.repo)?;
+    }
+    write!(f, "//{}:{}", self.package, self.name)
+  }
+}
+
+impl Object for Label {
+  fn type_name(&self) -> String {
+    "Label".to_owned()
+  }
+
+  fn get_attr(&self, name: &str) -> Option<Value> {
+    match name {
+      "package" => Some(Value::str(&self.package)),
+      "repo_name" => Some(Value::str(&self.repo)),
+      "name" => Some(Value::str(&self.name)),
+      _ => None,
+    }
+  }
+
+  fn attr_names(&self) -> Vec<String> {
+    vec!["name".to_owned(), "package".to_owned(), "repo_name".to_owned()]
+  }
+
+  fn equals(&self, other: &dyn Object) -> bool {
+    let other: &dyn Any = other;
+    other.downcast_ref::<Label>() == Some(self)
+  }
+
+  fn is_hashable(&self) -> bool {
+    true
+  }
+
+  fn to_str(&self) -> String {
+    self.to_string()
+  }
+
+  fn repr(&self) -> String {
+    format!("Label(\"{}\")", self)
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
+
+/// An error from parsing an incorrectly formatted `Label`.
+#[derive(Debug, PartialEq)]
+pub struct LabelError(pub String);
+
+impl Display for LabelError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for LabelError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn label(package: &str, name: &str) -> Label {
+    Label { repo: "".to_owned(), package: package.to_owned(), name: name.to_owned() }
+  }
+
+  fn external(repo: &str, package: &str, name: &str) -> Label {
+    Label { repo: repo.to_owned(), package: package.to_owned(), name: name.to_owned() }
+  }
+
+  #[test]
+  fn parse_parses_absolute_labels() {
+    assert_eq!(Label::parse("//foo/bar:baz", "other"), Ok(label("foo/bar", "baz")));
+    assert_eq!(Label::parse("//foo/bar", "other"), Ok(label("foo/bar", "bar")));
+    assert_eq!(Label::parse("//:root", "other"), Ok(label("", "root")));
+  }
+
+  #[test]
+  fn parse_parses_relative_labels() {
+    assert_eq!(Label::parse(":baz", "foo"), Ok(label("foo", "baz")));
+    assert_eq!(Label::parse("dir/file.txt", "foo"), Ok(label("foo", "dir/file.txt")));
+  }
+
+  #[test]
+  fn parse_parses_labels_in_repositories() {
+    assert_eq!(Label::parse("@dep//foo:bar", "other"), Ok(external("dep", "foo", "bar")));
+    assert_eq!(Label::parse("@dep", "other"), Ok(external("dep", "", "dep")));
+    assert_eq!(Label::parse_in("//foo", "dep", "other"), Ok(external("dep", "foo", "foo")));
+    assert_eq!(Label::parse_in(":baz", "dep", "foo"), Ok(external("dep", "foo", "baz")));
+    assert_eq!(Label::parse_in("@//foo:bar", "dep", "other"), Ok(label("foo", "bar")));
+    assert_eq!(Label::parse_in("@@//foo:bar", "dep", "other"), Ok(label("foo", "bar")));
+    assert!(Label::parse("@de p//foo", "").unwrap_err().0.contains("Invalid repository name"));
+    assert_eq!(external("dep", "foo", "bar").to_string(), "@dep//foo:bar");
+    assert_eq!(external("dep", "foo", "bar").package_path(), "external/dep/foo");
+    assert_eq!(external("dep", "", "bar").package_path(), "external/dep");
+  }
+
+  #[test]
+  fn parse_errors_on_invalid_labels() {
+    assert!(Label::parse("//foo/...", "").unwrap_err().0.contains("wildcards"));
+    assert!(Label::parse("//foo:", "").unwrap_err().0.contains("empty"));
+    assert!(Label::parse("../foo", "pkg").unwrap_err().0.contains("normalized"));
+    assert!(Label::parse("a:b:c", "pkg").unwrap_err().0.contains("at most one"));
+  }
+
+  #[test]
+  fn displays_label() {
+    assert_eq!(
+      format!("{}", Label {
+        repo: "".to_owned(),
+        package: "path/to/pkg".to_owned(),
+        name: "dir/file.txt".to_owned(),
+      }),
+      "//path/to/pkg:dir/file.txt",
+    );
+  }
+}
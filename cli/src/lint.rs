@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::build_file::BuildFileEditor;
+use crate::fmt::starlark_files;
+use crate::host::host::Host;
+use crate::host::source_writer::SourceWriter;
+use crate::package::BUILD_FILE_NAMES;
+use crate::starlark::error::{EvalError, Location};
+use crate::starlark::lexer::{tokenize, Token};
+
+/// Attributes of Bazel rules which razel rules do not support, found in BUILD
+/// files carried over from Bazel.
+const DEPRECATED_ATTRIBUTES: [&str; 3] = ["distribs", "licenses", "output_licenses"];
+
+/// A problem found in a BUILD or `.bzl` file.
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+  pub location: Location,
+
+  /// The kind of problem, such as `unused-load`.
+  pub category: &'static str,
+
+  pub message: String,
+
+  /// Whether `fix()` fixes it.
+  pub fixable: bool,
+}
+
+impl Display for Finding {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}: {} [{}]", self.location, self.message, self.category)
+  }
+}
+
+/// An edit fixing a finding, by the indices of the call and argument of the
+/// file's `BuildFileEditor`.
+enum Fix {
+  RemoveArg(usize, usize),
+  RemoveCall(usize),
+}
+
+/// Returns the problems of a BUILD or `.bzl` file: symbols which are loaded
+/// but never used and, in BUILD files, targets with the same name and
+/// deprecated attributes.
+pub fn lint(file: &str, source: &str) -> Result<Vec<Finding>, EvalError> {
+  Ok(check(file, source)?.1.into_iter().map(|(finding, _)| finding).collect())
+}
+
+/// Returns the source of a file with every fixable problem fixed, removing
+/// unused loads and deprecated attributes.
+pub fn fix(file: &str, source: &str) -> Result<String, EvalError> {
+  let mut source = source.to_owned();
+  loop {
+    // Fixes are applied one at a time since removing an argument can change
+    // how its neighbour is removed.
+    let (mut editor, findings) = check(file, &source)?;
+    match findings.into_iter().find_map(|(_, fix)| fix) {
+      Some(Fix::RemoveArg(call, arg)) => editor.remove_arg(call, arg),
+      Some(Fix::RemoveCall(call)) => editor.remove_call(call),
+      None => return Ok(source),
+    }
+    source = editor.finish();
+  }
+}
+
+/// Lints the BUILD, `.bzl` and `MODULE.razel` files under the given
+/// workspace-relative paths, or the whole workspace without any, first fixing
+/// what can be if `fix`. Returns the files which were fixed and the problems
+/// which remain.
+pub fn lint_files(host: &dyn Host, paths: &[PathBuf], fix: bool) ->
+    Result<(Vec<PathBuf>, Vec<Finding>), Box<dyn Error>> {
+  let mut fixed = Vec::new();
+  let mut findings = Vec::new();
+  for path in starlark_files(host, paths)? {
+    let file = path.to_string_lossy();
+    let mut source = host.read_to_string(&path)?;
+    if fix {
+      let fixed_source = self::fix(&file, &source)?;
+      if fixed_source != source {
+        SourceWriter::new(host).write(&path, fixed_source.as_bytes())?;
+        fixed.push(path.clone());
+        source = fixed_source;
+      }
+    }
+    findings.extend(lint(&file, &source)?);
+  }
+  Ok((fixed, findings))
+}
+
+/// Findings with the edits fixing them, if any.
+type Findings = Vec<(Finding, Option<Fix>)>;
+
+fn check(file: &str, source: &str) -> Result<(BuildFileEditor, Findings), EvalError> {
+  let editor = BuildFileEditor::parse(file, source)?;
+  let mut findings = Vec::new();
+  let finding = |location: &Location, category, message| {
+    Finding { location: location.clone(), category, message, fixable: false }
+  };
+
+  let mut uses: HashMap<String, usize> = HashMap::new();
+  for spanned in tokenize(&Rc::from(file), source)? {
+    if let Token::Ident(name) = spanned.token {
+      *uses.entry(name).or_default() += 1;
+    }
+  }
+  for (index, call) in editor.calls.iter().enumerate().filter(|(_, call)| call.kind == "load") {
+    let module = call.args.first().and_then(|arg| arg.string.clone()).unwrap_or_default();
+    let unused: Vec<usize> = (1..call.args.len()).filter(|&i| {
+      let arg = &call.args[i];
+      let symbol = arg.name.as_ref().or(arg.string.as_ref());
+      // An aliased symbol's own name is one use.
+      symbol.is_some_and(|symbol| {
+        uses.get(symbol).copied().unwrap_or_default() <= arg.name.is_some() as usize
+      })
+    }).collect();
+    for &i in &unused {
+      let arg = &call.args[i];
+      let symbol = arg.name.as_ref().or(arg.string.as_ref()).unwrap();
+      let fix = if unused.len() == call.args.len() - 1 {
+        Fix::RemoveCall(index)
+      } else {
+        Fix::RemoveArg(index, i)
+      };
+      let message = format!("`{}` is loaded from \"{}\" but never used.", symbol, module);
+      let finding = finding(&arg.location, "unused-load", message);
+      findings.push((Finding { fixable: true, ..finding }, Some(fix)));
+    }
+  }
+
+  let name = Path::new(file).file_name().and_then(|name| name.to_str()).unwrap_or_default();
+  if BUILD_FILE_NAMES.contains(&name) {
+    let mut declared: HashMap<&str, usize> = HashMap::new();
+    for (index, call) in editor.calls.iter().enumerate().filter(|(_, call)| call.kind != "load") {
+      if let Some(name) = &call.name {
+        match declared.get(name.as_str()) {
+          Some(line) => findings.push((finding(
+            &call.location,
+            "duplicate-name",
+            format!("Target `{}` is already declared on line {}.", name, line),
+          ), None)),
+          None => {
+            declared.insert(name, call.location.line);
+          },
+        }
+      }
+      for (i, arg) in call.args.iter().enumerate() {
+        let deprecated = arg.name.as_deref()
+          .filter(|attr| DEPRECATED_ATTRIBUTES.contains(attr));
+        if let Some(attr) = deprecated {
+          let message = format!(
+            "`{}` is deprecated and not supported by razel rules.",
+            attr,
+          );
+          let finding = finding(&arg.location, "deprecated-attribute", message);
+          findings.push((
+            Finding { fixable: true, ..finding },
+            Some(Fix::RemoveArg(index, i)),
+          ));
+        }
+      }
+    }
+  }
+
+  findings.sort_by_key(|(finding, _)| (finding.location.line, finding.location.column));
+  Ok((editor, findings))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const SOURCE: &str = r#"load("//:defs.bzl", "rule", "unused", alias = "other")
+load("//:more.bzl", "gone")
+
+rule(
+    name = "a",
+    licenses = ["notice"],  # Old.
+    deps = [":b"],
+)
+
+rule(name = "a", distribs = [])
+"#;
+
+  #[test]
+  fn lint_finds_unused_loads_duplicate_names_and_deprecated_attributes() -> Result<(), EvalError> {
+    let findings: Vec<String> = lint("pkg/BUILD", SOURCE)?.iter()
+      .map(ToString::to_string)
+      .collect();
+    assert_eq!(findings, [
+      "pkg/BUILD:1:29: `unused` is loaded from \"//:defs.bzl\" but never used. [unused-load]",
+      "pkg/BUILD:1:39: `alias` is loaded from \"//:defs.bzl\" but never used. [unused-load]",
+      "pkg/BUILD:2:21: `gone` is loaded from \"//:more.bzl\" but never used. [unused-load]",
+      "pkg/BUILD:6:5: `licenses` is deprecated and not supported by razel \
+      rules. [deprecated-attribute]",
+      "pkg/BUILD:10:1: Target `a` is already declared on line 4. [duplicate-name]",
+      "pkg/BUILD:10:18: `distribs` is deprecated and not supported by razel \
+      rules. [deprecated-attribute]",
+    ]);
+    assert_eq!(lint("pkg/defs.bzl", SOURCE)?.len(), 3);
+    Ok(())
+  }
+
+  #[test]
+  fn fix_removes_unused_loads_and_deprecated_attributes() -> Result<(), EvalError> {
+    let fixed = fix("pkg/BUILD", SOURCE)?;
+    assert_eq!(fixed, r#"load("//:defs.bzl", "rule")
+
+rule(
+    name = "a",
+    deps = [":b"],
+)
+
+rule(name = "a")
+"#);
+    let categories: Vec<_> = lint("pkg/BUILD", &fixed)?.iter()
+      .map(|finding| finding.category)
+      .collect();
+    assert_eq!(categories, ["duplicate-name"]);
+    Ok(())
+  }
+}
@@ -0,0 +1,1026 @@
+use serde_json::{json, Map, Value as Json};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::analysis::rule::{AttrSpec, RuleDef};
+use crate::build_file::{quote, BuildFileEditor};
+use crate::bzl::BzlLoader;
+use crate::fmt::starlark_files;
+use crate::host::host::Host;
+use crate::host::overlay_host::OverlayHost;
+use crate::label::Label;
+use crate::lint::lint;
+use crate::module::{self, Module, RepositoryOptions};
+use crate::package::{PackageLoader, BUILD_FILE_NAMES, BUILTIN_RULES};
+use crate::repository::Repositories;
+use crate::starlark::error::{EvalError, Location};
+use crate::starlark::lexer::{tokenize, Token};
+use crate::target_pattern::TargetPattern;
+use crate::workspace::WORKSPACE_FILE;
+
+/// The JSON-RPC error code of requests for methods the server lacks.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The LSP error code of valid requests which could not be carried out.
+const REQUEST_FAILED: i64 = -32803;
+
+/// Diagnostic severities.
+const ERROR: u8 = 1;
+const WARNING: u8 = 2;
+
+/// Completion item kinds.
+const FIELD: u8 = 5;
+const VALUE: u8 = 12;
+const FOLDER: u8 = 19;
+
+/// A language server for the BUILD, `.bzl` and `MODULE.razel` files of a
+/// workspace. Files open in the editor are analyzed with their unsaved
+/// contents.
+///
+/// Positions are counted in characters rather than UTF-16 code units, which
+/// only differ outside the Basic Multilingual Plane.
+pub struct LanguageServer {
+  host: Rc<OverlayHost>,
+  options: RepositoryOptions,
+
+  /// Whether the client asked the server to shut down before exiting.
+  shutdown: bool,
+}
+
+impl LanguageServer {
+  pub fn new(host: Box<dyn Host>, options: RepositoryOptions) -> LanguageServer {
+    LanguageServer { host: Rc::new(OverlayHost::new(host)), options, shutdown: false }
+  }
+
+  /// Handles a request or notification, returning the response to a request
+  /// followed by any notifications for the client.
+  pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+    let method = message["method"].as_str().unwrap_or_default();
+    let params = &message["params"];
+    let result: Result<Json, Box<dyn Error>> = match method {
+      "initialize" => Ok(capabilities()),
+      "shutdown" => {
+        self.shutdown = true;
+        Ok(Json::Null)
+      },
+      "textDocument/definition" => self.definition(params),
+      "textDocument/completion" => self.completion(params),
+      "textDocument/hover" => self.hover(params),
+      "textDocument/rename" => self.rename(params),
+      "textDocument/didOpen" | "textDocument/didChange" | "textDocument/didSave" |
+          "textDocument/didClose" => {
+        return self.synchronize(method, params).unwrap_or_else(|err| vec![json!({
+          "jsonrpc": "2.0",
+          "method": "window/logMessage",
+          "params": { "type": ERROR, "message": err.to_string() },
+        })]);
+      },
+      _ => {
+        let error = json!({
+          "code": METHOD_NOT_FOUND,
+          "message": format!("Unknown method `{}`.", method),
+        });
+        return message.get("id")
+          .map(|id| json!({ "jsonrpc": "2.0", "id": id, "error": error }))
+          .into_iter()
+          .collect();
+      },
+    };
+
+    // Notifications are not answered.
+    let Some(id) = message.get("id") else {
+      return Vec::new();
+    };
+    match result {
+      Ok(result) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+      Err(err) => vec![json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": REQUEST_FAILED, "message": err.to_string() },
+      })],
+    }
+  }
+
+  /// Tracks the contents of open files, returning fresh diagnostics for every
+  /// open file since they may depend on the one which changed.
+  fn synchronize(&self, method: &str, params: &Json) -> Result<Vec<Json>, Box<dyn Error>> {
+    let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+    let path = self.path(uri)?;
+    let mut notifications = Vec::new();
+    match method {
+      "textDocument/didOpen" => {
+        self.host.set_buffer(&path, Some(text(&params["textDocument"]["text"])?));
+      },
+      // Only whole documents are synchronized, so the last change is the
+      // current contents.
+      "textDocument/didChange" => {
+        let changes = params["contentChanges"].as_array();
+        if let Some(change) = changes.and_then(|changes| changes.last()) {
+          self.host.set_buffer(&path, Some(text(&change["text"])?));
+        }
+      },
+      "textDocument/didClose" => {
+        self.host.set_buffer(&path, None);
+        notifications.push(publish(uri, Vec::new()));
+      },
+      _ => {},
+    }
+    for open in self.host.buffered() {
+      notifications.push(publish(&self.uri(&open), self.diagnostics(&open)));
+    }
+    Ok(notifications)
+  }
+
+  /// Returns the errors of evaluating a file and the problems found by
+  /// `lint()`.
+  fn diagnostics(&self, path: &Path) -> Vec<Json> {
+    let Ok(source) = self.host.read_to_string(path) else {
+      return Vec::new();
+    };
+    let file = path.to_string_lossy();
+    let name = file_name(path);
+    let evaluated: Result<(), Box<dyn Error>> = if BUILD_FILE_NAMES.contains(&name) {
+      PackageLoader::with_repositories(self.repositories()).load("", &parent(path)).map(drop)
+    } else if name.ends_with(".bzl") {
+      let label = Label { repo: String::new(), package: parent(path), name: name.to_owned() };
+      BzlLoader::new(Rc::new(self.repositories())).load(&label).map(drop).map_err(Into::into)
+    } else if name == WORKSPACE_FILE {
+      Module::load(self.host.as_ref()).map(drop)
+    } else {
+      Ok(())
+    };
+
+    let mut diagnostics = Vec::new();
+    if let Err(err) = evaluated {
+      diagnostics.push(error_diagnostic(&file, &source, err.as_ref()));
+    }
+    for finding in lint(&file, &source).unwrap_or_default() {
+      let message = format!("{} [{}]", finding.message, finding.category);
+      diagnostics.push(diagnostic(&source, Some(&finding.location), WARNING, &message));
+    }
+    diagnostics
+  }
+
+  /// Goes from a label in a string to the target, output or source file it
+  /// refers to, or from a `load()`ed module to the .bzl file.
+  fn definition(&self, params: &Json) -> Result<Json, Box<dyn Error>> {
+    let (path, line, character) = self.position(params)?;
+    let source = self.host.read_to_string(&path)?;
+    let Some(literal) = literal_at(&path, &source, line, character) else {
+      return Ok(Json::Null);
+    };
+    let Ok(label) = Label::parse(&literal.value, &parent(&path)) else {
+      return Ok(Json::Null);
+    };
+
+    let repositories = self.repositories();
+    let host = repositories.get(&label.repo)?;
+    let file = Path::new(&label.package).join(&label.name);
+    let source_file = || host.resolve(&file).ok().map(|_| (file.clone(), 0, 0));
+    let location = if label.name.ends_with(".bzl") {
+      source_file()
+    } else {
+      match PackageLoader::with_repositories(repositories).load(&label.repo, &label.package) {
+        Ok(package) => {
+          let generator = package.outputs.get(&label.name).unwrap_or(&label.name);
+          match package.targets.get(generator).and_then(|target| target.location.clone()) {
+            Some(location) => Some((
+              package.build_file.clone(),
+              location.line - 1,
+              location.column - 1,
+            )),
+            None => source_file(),
+          }
+        },
+        Err(_) => source_file(),
+      }
+    };
+    Ok(match location {
+      Some((file, line, character)) => json!({
+        "uri": file_uri(&host.source_root().join(file)),
+        "range": range(line, character, character),
+      }),
+      None => Json::Null,
+    })
+  }
+
+  /// Completes labels in strings and the attributes of rules between the
+  /// parentheses of their calls.
+  fn completion(&self, params: &Json) -> Result<Json, Box<dyn Error>> {
+    let (path, line, character) = self.position(params)?;
+    let source = self.host.read_to_string(&path)?;
+    let offset = offset(&source, line, character);
+    let context = Context::scan(&source[..offset]);
+    let items = match (context.string, context.brackets.as_slice()) {
+      (Some(start), _) => {
+        // The string being typed is unterminated, so it is left out when
+        // reading the targets of this file.
+        let end = source[offset..].find('\n').map_or(source.len(), |end| offset + end);
+        let current = format!("{}{}", &source[..start - 1], &source[end..]);
+        self.complete_label(&path, &current, &source[start..offset], line, character)
+      },
+      (None, [('(', Some(callee))]) => {
+        let argument = source[..offset].rsplit([',', '(']).next().unwrap_or_default().trim_start();
+        // Only the loads before the call are needed, and the rest may not
+        // tokenize while being typed.
+        if argument.chars().all(is_identifier) {
+          self.complete_attributes(&path, &source[..offset], callee)
+        } else {
+          Vec::new()
+        }
+      },
+      _ => Vec::new(),
+    };
+    Ok(Json::Array(items))
+  }
+
+  fn complete_label(&self, path: &Path, source: &str, prefix: &str, line: usize,
+      character: usize) -> Vec<Json> {
+    let labels: Vec<(String, u8)> = if prefix.starts_with("//") || prefix.starts_with('@') {
+      let repositories = self.repositories();
+      match prefix.rsplit_once(':') {
+        Some((package, _)) => {
+          let Ok(label) = Label::parse(&format!("{}:_", package), "") else {
+            return Vec::new();
+          };
+          let names = repositories.get(&label.repo).ok()
+            .and_then(|host| build_file(host.as_ref(), &label.package))
+            .map(|(file, source)| target_names(&file, &source))
+            .unwrap_or_default();
+          names.into_iter().map(|name| (format!("{}:{}", package, name), VALUE)).collect()
+        },
+        None => {
+          let Some((repo, _)) = prefix.split_once("//") else {
+            return Vec::new();
+          };
+          let packages = TargetPattern::parse(&format!("{}//...", repo)).ok().and_then(|pattern| {
+            pattern.packages(repositories.get(&pattern.repo).ok()?.as_ref()).ok()
+          });
+          packages.unwrap_or_default().into_iter()
+            .map(|package| (format!("{}//{}", repo, package), FOLDER))
+            .collect()
+        },
+      }
+    } else {
+      target_names(path, source).into_iter().map(|name| (format!(":{}", name), VALUE)).collect()
+    };
+
+    let start = character - prefix.chars().count();
+    labels.into_iter().filter(|(label, _)| label.starts_with(prefix)).map(|(label, kind)| json!({
+      "label": label,
+      "kind": kind,
+      "textEdit": { "range": range(line, start, character), "newText": label },
+    })).collect()
+  }
+
+  fn complete_attributes(&self, path: &Path, source: &str, callee: &str) -> Vec<Json> {
+    let Some(rule) = self.rule(path, source, callee) else {
+      return Vec::new();
+    };
+    let item = |name: &str, detail: String, doc: &str| json!({
+      "label": name,
+      "kind": FIELD,
+      "detail": detail,
+      "documentation": doc,
+      "insertText": format!("{} = ", name),
+    });
+    let mut items = vec![item("name", "string, mandatory".to_owned(), "")];
+    for (name, spec) in public_attrs(&rule) {
+      items.push(item(name, attr_detail(spec.kind.name(), spec.mandatory), &spec.doc));
+    }
+    items.push(item("visibility", "label_list".to_owned(), ""));
+    items
+  }
+
+  /// Shows the documentation of a rule being called or of an attribute
+  /// being set.
+  fn hover(&self, params: &Json) -> Result<Json, Box<dyn Error>> {
+    let (path, line, character) = self.position(params)?;
+    let source = self.host.read_to_string(&path)?;
+    let text: Vec<char> = source.lines().nth(line).unwrap_or_default().chars().collect();
+    let mut start = character.min(text.len());
+    while start > 0 && is_identifier(text[start - 1]) {
+      start -= 1;
+    }
+    let mut end = start;
+    while end < text.len() && is_identifier(text[end]) {
+      end += 1;
+    }
+    if start == end || end < character {
+      return Ok(Json::Null);
+    }
+    let word: String = text[start..end].iter().collect();
+    let rest: String = text[end..].iter().collect();
+    let rest = rest.trim_start();
+
+    let contents = if rest.starts_with('=') && !rest.starts_with("==") {
+      let context = Context::scan(&source[..offset(&source, line, start)]);
+      let callee = match context.brackets.last() {
+        Some(('(', Some(callee))) => callee.clone(),
+        _ => return Ok(Json::Null),
+      };
+      self.rule(&path, &source, &callee).and_then(|rule| {
+        let spec = rule.attrs.get(&word)?;
+        let detail = attr_detail(spec.kind.name(), spec.mandatory);
+        Some(format!("`{}` ({})\n\n{}", word, detail, spec.doc))
+      })
+    } else {
+      self.rule(&path, &source, &word).map(|rule| rule_doc(&word, &rule))
+    };
+    Ok(match contents {
+      Some(contents) => json!({
+        "contents": { "kind": "markdown", "value": contents.trim_end() },
+        "range": range(line, start, end),
+      }),
+      None => Json::Null,
+    })
+  }
+
+  /// Renames the target whose name or label is under the cursor, in its
+  /// `name` attribute and in every label referring to it in the workspace's
+  /// BUILD files. Labels keep their form, so `//pkg` becomes `//pkg:new`.
+  fn rename(&self, params: &Json) -> Result<Json, Box<dyn Error>> {
+    let (path, line, character) = self.position(params)?;
+    let new_name = params["newName"].as_str().unwrap_or_default();
+    if new_name.is_empty() || new_name.starts_with(['/', '@']) ||
+        new_name.contains([':', '"', '\\', '\n']) {
+      return Err(LspError(format!("`{}` is not a valid target name.", new_name)).into());
+    }
+    let source = self.host.read_to_string(&path)?;
+    let literal = literal_at(&path, &source, line, character)
+      .ok_or_else(|| LspError(
+        "Rename a target by its name or a label referring to it.".to_owned(),
+      ))?;
+    let package = parent(&path);
+    let label = if BUILD_FILE_NAMES.contains(&file_name(&path)) &&
+        is_name_value(&literal.before(&source)) {
+      Label { repo: String::new(), package, name: literal.value.clone() }
+    } else {
+      Label::parse(&literal.value, &package).map_err(|err| LspError(err.0))?
+    };
+    if !label.repo.is_empty() {
+      return Err(LspError(format!(
+        "{} is in an external repository and cannot be renamed.",
+        label,
+      )).into());
+    }
+
+    let mut changes = Map::new();
+    let build_files = starlark_files(self.host.as_ref(), &[])?.into_iter()
+      .filter(|path| BUILD_FILE_NAMES.contains(&file_name(path)));
+    for build_file in build_files {
+      let source = self.host.read_to_string(&build_file)?;
+      let package = parent(&build_file);
+      let mut edits = Vec::new();
+      for literal in literals(&build_file, &source) {
+        let renamed = if is_name_value(&literal.before(&source)) {
+          (package == label.package && literal.value == label.name).then(|| new_name.to_owned())
+        } else if literal.value.starts_with([':', '/', '@']) &&
+            Label::parse(&literal.value, &package).ok() == Some(label.clone()) {
+          match literal.value.rsplit_once(':') {
+            Some((prefix, _)) => Some(format!("{}:{}", prefix, new_name)),
+            None => Some(format!("{}:{}", literal.value, new_name)),
+          }
+        } else {
+          None
+        };
+        if let Some(renamed) = renamed {
+          edits.push(json!({
+            "range": range(literal.line, literal.start, literal.end),
+            "newText": quote(&renamed),
+          }));
+        }
+      }
+      if !edits.is_empty() {
+        changes.insert(self.uri(&build_file), Json::Array(edits));
+      }
+    }
+    Ok(json!({ "changes": changes }))
+  }
+
+  /// Returns the rule a name refers to in a file: one loaded by `load()`,
+  /// else a builtin rule in BUILD files or the file's own in .bzl files.
+  fn rule(&self, path: &Path, source: &str, symbol: &str) -> Option<Rc<RuleDef>> {
+    let editor = BuildFileEditor::parse(&path.to_string_lossy(), source).ok()?;
+    let loaded = editor.calls.iter().filter(|call| call.kind == "load").find_map(|call| {
+      let module = call.args.first()?.string.clone()?;
+      let name = call.args[1..].iter().find_map(|arg| match &arg.name {
+        Some(name) => arg.string.clone().filter(|_| name == symbol),
+        None => arg.string.clone().filter(|name| name == symbol),
+      })?;
+      Some((module, name))
+    });
+    let (module, name) = match loaded {
+      Some(loaded) => loaded,
+      None if file_name(path).ends_with(".bzl") => {
+        (format!(":{}", file_name(path)), symbol.to_owned())
+      },
+      None => BUILTIN_RULES.iter().find(|(rule, _)| *rule == symbol)
+        .map(|(rule, file)| (file.to_string(), rule.to_string()))?,
+    };
+    let label = Label::parse(&module, &parent(path)).ok()?;
+    let module = BzlLoader::new(Rc::new(self.repositories())).load(&label).ok()?;
+    let rule = module.globals.borrow().get(&name)?.downcast::<RuleDef>();
+    rule
+  }
+
+  /// Returns the workspace's repositories, or just the main and builtin ones
+  /// while `MODULE.razel` is broken so other files can still be analyzed.
+  fn repositories(&self) -> Repositories {
+    let host: Rc<dyn Host> = self.host.clone();
+    module::load_repositories(host.clone(), &self.options)
+      .unwrap_or_else(|_| Repositories::new(host))
+  }
+
+  fn position(&self, params: &Json) -> Result<(PathBuf, usize, usize), Box<dyn Error>> {
+    let path = self.path(params["textDocument"]["uri"].as_str().unwrap_or_default())?;
+    let index = |value: &Json| value.as_u64().unwrap_or_default() as usize;
+    Ok((path, index(&params["position"]["line"]), index(&params["position"]["character"])))
+  }
+
+  /// Returns the workspace-relative path of a `file://` URI.
+  fn path(&self, uri: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let path = uri.strip_prefix("file://").map(decode)
+      .ok_or_else(|| LspError(format!("`{}` is not a file URI.", uri)))?;
+    let path = PathBuf::from(path);
+    match path.strip_prefix(self.host.source_root()) {
+      Ok(relative) => Ok(relative.to_owned()),
+      Err(_) => Err(LspError(format!("{} is not in the workspace.", path.display())).into()),
+    }
+  }
+
+  fn uri(&self, path: &Path) -> String {
+    file_uri(&self.host.source_root().join(path))
+  }
+}
+
+/// Serves a client over stdin and stdout until it exits.
+pub fn run(server: LanguageServer) -> Result<(), Box<dyn Error>> {
+  serve(server, &mut io::stdin().lock(), &mut io::stdout().lock())
+}
+
+/// Reads messages framed by `Content-Length` headers, writing the replies
+/// framed the same way, until the `exit` notification or the end of input.
+fn serve(mut server: LanguageServer, reader: &mut impl BufRead,
+    writer: &mut impl Write) -> Result<(), Box<dyn Error>> {
+  while let Some(message) = read_message(reader)? {
+    for reply in server.handle(&message) {
+      let body = reply.to_string();
+      write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+      writer.flush()?;
+    }
+    if message["method"] == "exit" {
+      return match server.shutdown {
+        true => Ok(()),
+        false => Err(LspError(
+          "The client exited without shutting the server down.".to_owned(),
+        ).into()),
+      };
+    }
+  }
+  Ok(())
+}
+
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Json>, Box<dyn Error>> {
+  let mut length = None;
+  loop {
+    let mut header = String::new();
+    if reader.read_line(&mut header)? == 0 {
+      return Ok(None);
+    }
+    let header = header.trim_end();
+    if header.is_empty() {
+      break;
+    }
+    if let Some((name, value)) = header.split_once(':') {
+      if name.eq_ignore_ascii_case("Content-Length") {
+        length = Some(value.trim().parse::<usize>()?);
+      }
+    }
+  }
+  let length = length.ok_or_else(|| {
+    LspError("A message has no Content-Length header.".to_owned())
+  })?;
+  let mut body = vec![0; length];
+  reader.read_exact(&mut body)?;
+  Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn capabilities() -> Json {
+  json!({
+    "capabilities": {
+      "textDocumentSync": { "openClose": true, "change": 1, "save": true },
+      "definitionProvider": true,
+      "completionProvider": { "triggerCharacters": [":", "/", "\"", "@"] },
+      "hoverProvider": true,
+      "renameProvider": true,
+    },
+    "serverInfo": { "name": "razel", "version": env!("CARGO_PKG_VERSION") },
+  })
+}
+
+fn publish(uri: &str, diagnostics: Vec<Json>) -> Json {
+  json!({
+    "jsonrpc": "2.0",
+    "method": "textDocument/publishDiagnostics",
+    "params": { "uri": uri, "diagnostics": diagnostics },
+  })
+}
+
+/// Returns the diagnostic of an error evaluating a file, placed where it
+/// occurred if that is in the file, else where the file called the code
+/// which failed.
+fn error_diagnostic(file: &str, source: &str, err: &(dyn Error + 'static)) -> Json {
+  let Some(err) = err.downcast_ref::<EvalError>() else {
+    return diagnostic(source, None, ERROR, &err.to_string());
+  };
+  let location = err.location.iter().chain(&err.callers).find(|location| &*location.file == file);
+  let message = match &err.location {
+    Some(origin) if location != Some(origin) => format!("{}: {}", origin, err.message),
+    _ => err.message.clone(),
+  };
+  diagnostic(source, location, ERROR, &message)
+}
+
+/// Returns a diagnostic spanning from a location to the end of its line.
+fn diagnostic(source: &str, location: Option<&Location>, severity: u8, message: &str) -> Json {
+  let (line, character) = location
+    .map_or((0, 0), |location| (location.line - 1, location.column - 1));
+  let end = source.lines().nth(line)
+    .map_or(character, |text| text.chars().count().max(character));
+  json!({
+    "range": range(line, character, end),
+    "severity": severity,
+    "source": "razel",
+    "message": message,
+  })
+}
+
+fn range(line: usize, start: usize, end: usize) -> Json {
+  json!({
+    "start": { "line": line, "character": start },
+    "end": { "line": line, "character": end },
+  })
+}
+
+fn text(value: &Json) -> Result<String, Box<dyn Error>> {
+  value.as_str().map(str::to_owned)
+    .ok_or_else(|| LspError("A document has no text.".to_owned()).into())
+}
+
+/// What surrounds a position in a file, found by scanning the text before it
+/// since what is being typed is rarely valid Starlark yet.
+struct Context {
+  /// The offset just past the opening quote if the position is in a string.
+  string: Option<usize>,
+
+  /// The brackets open at the position, each with the name called if it is
+  /// the `(` of a call.
+  brackets: Vec<(char, Option<String>)>,
+}
+
+impl Context {
+  fn scan(text: &str) -> Context {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let quoted = |i: usize, quote: char, count: usize| {
+      (0..count).all(|j| chars.get(i + j).map(|&(_, c)| c) == Some(quote))
+    };
+    let mut string: Option<(char, usize, usize)> = None;
+    let mut brackets = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+      let (offset, c) = chars[i];
+      match string {
+        Some((quote, delimiter, _)) => {
+          if c == '\\' {
+            i += 1;
+          } else if quoted(i, quote, delimiter) {
+            i += delimiter - 1;
+            string = None;
+          } else if c == '\n' && delimiter == 1 {
+            string = None;
+          }
+        },
+        None => match c {
+          '#' => {
+            while i + 1 < chars.len() && chars[i + 1].1 != '\n' {
+              i += 1;
+            }
+          },
+          '"' | '\'' => {
+            let delimiter = if quoted(i, c, 3) { 3 } else { 1 };
+            i += delimiter - 1;
+            string = Some((c, delimiter, offset + delimiter));
+          },
+          '(' | '[' | '{' => {
+            let callee: String = text[..offset].trim_end().chars().rev()
+              .take_while(|&c| is_identifier(c))
+              .collect();
+            let callee = (c == '(' && !callee.is_empty()).then(|| callee.chars().rev().collect());
+            brackets.push((c, callee));
+          },
+          ')' | ']' | '}' => {
+            brackets.pop();
+          },
+          _ => {},
+        },
+      }
+      i += 1;
+    }
+    Context { string: string.map(|(_, _, start)| start), brackets }
+  }
+}
+
+/// A string literal on a single line, with character columns.
+struct Literal {
+  value: String,
+  line: usize,
+  start: usize,
+
+  /// The column just past the closing quote.
+  end: usize,
+}
+
+impl Literal {
+  /// Returns the text of the literal's line before it.
+  fn before(&self, source: &str) -> String {
+    source.lines().nth(self.line).unwrap_or_default().chars().take(self.start).collect()
+  }
+}
+
+/// Returns the single-line string literals of a file, none if it cannot be
+/// tokenized.
+fn literals(path: &Path, source: &str) -> Vec<Literal> {
+  let lines: Vec<&str> = source.lines().collect();
+  let Ok(tokens) = tokenize(&Rc::from(path.to_string_lossy().as_ref()), source) else {
+    return Vec::new();
+  };
+  tokens.into_iter().filter_map(|spanned| {
+    let Token::Str(value) = spanned.token else {
+      return None;
+    };
+    let line = spanned.location.line - 1;
+    let start = spanned.location.column - 1;
+    let end = literal_end(lines.get(line)?, start)?;
+    Some(Literal { value, line, start, end })
+  }).collect()
+}
+
+fn literal_at(path: &Path, source: &str, line: usize, character: usize) -> Option<Literal> {
+  literals(path, source).into_iter()
+    .find(|literal| literal.line == line && literal.start <= character && character < literal.end)
+}
+
+/// Returns the column just past a string literal starting at a column, if it
+/// ends on the same line.
+fn literal_end(line: &str, start: usize) -> Option<usize> {
+  let chars: Vec<char> = line.chars().collect();
+  let mut i = start;
+  // Skips prefixes such as `r`.
+  while chars.get(i)?.is_ascii_alphabetic() {
+    i += 1;
+  }
+  let quote = chars[i];
+  let delimiter = if chars[i..].starts_with(&[quote; 3]) { 3 } else { 1 };
+  i += delimiter;
+  while i < chars.len() {
+    if chars[i] == '\\' {
+      i += 2;
+    } else if chars[i..].starts_with(&[quote; 3][..delimiter]) {
+      return Some(i + delimiter);
+    } else {
+      i += 1;
+    }
+  }
+  None
+}
+
+/// Returns the byte offset of a position, clamped to its line.
+fn offset(source: &str, line: usize, character: usize) -> usize {
+  let mut start = 0;
+  for (i, text) in source.split_inclusive('\n').enumerate() {
+    if i == line {
+      let text = text.trim_end_matches(['\n', '\r']);
+      return start + text.char_indices().nth(character).map_or(text.len(), |(offset, _)| offset);
+    }
+    start += text.len();
+  }
+  source.len()
+}
+
+/// Returns whether the text before a string ends with `name =`, making the
+/// string a target's name.
+fn is_name_value(before: &str) -> bool {
+  let Some(rest) = before.trim_end().strip_suffix('=') else {
+    return false;
+  };
+  rest.trim_end().strip_suffix("name").is_some_and(|rest| !rest.ends_with(is_identifier))
+}
+
+fn is_identifier(c: char) -> bool {
+  c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the BUILD file of a package and its contents.
+fn build_file(host: &dyn Host, package: &str) -> Option<(PathBuf, String)> {
+  BUILD_FILE_NAMES.iter().map(|name| Path::new(package).join(name))
+    .find_map(|path| host.read_to_string(&path).ok().map(|source| (path, source)))
+}
+
+/// Returns the names of the targets declared in a BUILD file.
+fn target_names(file: &Path, source: &str) -> Vec<String> {
+  BuildFileEditor::parse(&file.to_string_lossy(), source)
+    .map(|editor| {
+      editor.calls.into_iter()
+        .filter(|call| call.kind != "load")
+        .filter_map(|call| call.name)
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Returns the attributes of a rule which BUILD files can set.
+fn public_attrs(rule: &RuleDef) -> impl Iterator<Item = (&String, &Rc<AttrSpec>)> {
+  rule.attrs.iter().filter(|(name, _)| !name.starts_with('_'))
+}
+
+fn attr_detail(kind: &str, mandatory: bool) -> String {
+  if mandatory { format!("{}, mandatory", kind) } else { kind.to_owned() }
+}
+
+/// Returns the Markdown documentation of a rule.
+fn rule_doc(name: &str, rule: &RuleDef) -> String {
+  let attrs: Vec<&str> = public_attrs(rule).map(|(name, _)| name.as_str()).collect();
+  let attrs: String = attrs.iter().map(|attr| format!("{}, ", attr)).collect();
+  let mut doc = format!("```python\n{}(name, {}visibility)\n```\n\n", name, attrs);
+  if !rule.doc.is_empty() {
+    doc.push_str(&format!("{}\n\n", rule.doc.trim()));
+  }
+  doc.push_str("- `name` (string, mandatory)\n");
+  for (attr, spec) in public_attrs(rule) {
+    let detail = attr_detail(spec.kind.name(), spec.mandatory);
+    match spec.doc.is_empty() {
+      true => doc.push_str(&format!("- `{}` ({})\n", attr, detail)),
+      false => doc.push_str(&format!("- `{}` ({}): {}\n", attr, detail, spec.doc.trim())),
+    }
+  }
+  doc
+}
+
+fn file_name(path: &Path) -> &str {
+  path.file_name().and_then(|name| name.to_str()).unwrap_or_default()
+}
+
+/// Returns the workspace-relative path of a file's package.
+fn parent(path: &Path) -> String {
+  path.parent().map(|parent| parent.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+pub fn file_uri(path: &Path) -> String {
+  let mut uri = "file://".to_owned();
+  for byte in path.to_string_lossy().bytes() {
+    match byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+      true => uri.push(byte as char),
+      false => uri.push_str(&format!("%{:02X}", byte)),
+    }
+  }
+  uri
+}
+
+/// Decodes the `%XX` escapes of a URI path.
+fn decode(text: &str) -> String {
+  let bytes = text.as_bytes();
+  let mut decoded = Vec::new();
+  let mut i = 0;
+  while i < bytes.len() {
+    match (bytes[i], text.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+      (b'%', Some(byte)) => {
+        decoded.push(byte);
+        i += 3;
+      },
+      (byte, _) => {
+        decoded.push(byte);
+        i += 1;
+      },
+    }
+  }
+  String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[derive(Debug)]
+pub struct LspError(pub String);
+
+impl Display for LspError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for LspError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::io::Cursor;
+  use assertables::assert_contains;
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+  use super::*;
+
+  const APP: &str = r#"load("//:defs.bzl", "greeting")
+
+greeting(
+    name = "app",
+    message = "hello",
+    deps = ["//lib"],
+)
+"#;
+
+  fn server(dir: &TestDir) -> Result<LanguageServer, Box<dyn Error>> {
+    let host = FsHost::with_output_base(&dir.root.join("wksp"), &dir.root.join("out"))?;
+    Ok(LanguageServer::new(Box::new(host), RepositoryOptions::default()))
+  }
+
+  fn workspace() -> Result<TestDir, Box<dyn Error>> {
+    TestDir::from([
+      (Path::new("wksp/defs.bzl"), TestContents::File(r#"def _impl(ctx):
+    pass
+
+greeting = rule(
+    implementation = _impl,
+    doc = "Greets someone.",
+    attrs = {
+        "deps": attr.label_list(doc = "Other greetings."),
+        "message": attr.string(mandatory = True),
+    },
+)
+"#)),
+      (Path::new("wksp/lib/BUILD"), TestContents::File(
+        "load(\"//:defs.bzl\", \"greeting\")\n\n\
+        greeting(name = \"lib\", message = \"hi\")\n",
+      )),
+      (Path::new("wksp/app/BUILD"), TestContents::File(APP)),
+    ])
+  }
+
+  fn request(server: &mut LanguageServer, method: &str, params: Json) -> Json {
+    let replies = server.handle(&json!({
+      "jsonrpc": "2.0",
+      "id": 1,
+      "method": method,
+      "params": params,
+    }));
+    replies[0]["result"].clone()
+  }
+
+  fn position(uri: &str, line: usize, character: usize) -> Json {
+    json!({
+      "textDocument": { "uri": uri },
+      "position": { "line": line, "character": character },
+    })
+  }
+
+  #[test]
+  fn language_server_publishes_diagnostics_of_unsaved_files() -> Result<(), Box<dyn Error>> {
+    let dir = workspace()?;
+    let mut server = server(&dir)?;
+    let uri = server.uri(Path::new("app/BUILD"));
+
+    let text = APP.replace("deps =", "bogus = 1,\n    deps =");
+    let notifications = server.handle(&json!({
+      "jsonrpc": "2.0",
+      "method": "textDocument/didOpen",
+      "params": {
+        "textDocument": {
+          "uri": uri,
+          "languageId": "starlark",
+          "version": 1,
+          "text": text,
+        },
+      },
+    }));
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0]["params"]["uri"], uri);
+    let diagnostics = notifications[0]["params"]["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_contains!(diagnostics[0]["message"].as_str().unwrap(), "bogus");
+    assert_eq!(diagnostics[0]["severity"], ERROR);
+
+    let notifications = server.handle(&json!({
+      "jsonrpc": "2.0",
+      "method": "textDocument/didChange",
+      "params": {
+        "textDocument": { "uri": uri, "version": 2 },
+        "contentChanges": [{ "text": APP }],
+      },
+    }));
+    assert_eq!(notifications[0]["params"]["diagnostics"], json!([]));
+    Ok(())
+  }
+
+  #[test]
+  fn language_server_goes_to_definitions_and_completes_labels_and_attributes() ->
+      Result<(), Box<dyn Error>> {
+    let dir = workspace()?;
+    let mut server = server(&dir)?;
+    let uri = server.uri(Path::new("app/BUILD"));
+
+    let definition = request(&mut server, "textDocument/definition", position(&uri, 5, 14));
+    assert_eq!(definition, json!({
+      "uri": server.uri(Path::new("lib/BUILD")),
+      "range": range(2, 8, 8),
+    }));
+    let definition = request(&mut server, "textDocument/definition", position(&uri, 0, 8));
+    assert_eq!(definition["uri"], server.uri(Path::new("defs.bzl")));
+
+    let text = "load(\"//:defs.bzl\", \"greeting\")\n\ngreeting(\n    \
+      name = \"app\",\n    deps = [\"//lib:\n";
+    server.handle(&json!({
+      "jsonrpc": "2.0",
+      "method": "textDocument/didOpen",
+      "params": {
+        "textDocument": {
+          "uri": uri,
+          "languageId": "starlark",
+          "version": 1,
+          "text": text,
+        },
+      },
+    }));
+    let completion = request(&mut server, "textDocument/completion", position(&uri, 4, 19));
+    let labels: Vec<&Json> = completion.as_array().unwrap().iter()
+      .map(|item| &item["label"])
+      .collect();
+    assert_eq!(labels, ["//lib:lib"]);
+    let completion = request(&mut server, "textDocument/completion", position(&uri, 4, 15));
+    let labels: Vec<&Json> = completion.as_array().unwrap().iter()
+      .map(|item| &item["label"])
+      .collect();
+    assert_eq!(labels, ["//app", "//lib"]);
+    let completion = request(&mut server, "textDocument/completion", position(&uri, 3, 4));
+    let labels: Vec<&Json> = completion.as_array().unwrap().iter()
+      .map(|item| &item["label"])
+      .collect();
+    assert_eq!(labels, ["name", "deps", "message", "visibility"]);
+    Ok(())
+  }
+
+  #[test]
+  fn language_server_shows_rule_docs_and_renames_targets() -> Result<(), Box<dyn Error>> {
+    let dir = workspace()?;
+    let mut server = server(&dir)?;
+    let app = server.uri(Path::new("app/BUILD"));
+    let lib = server.uri(Path::new("lib/BUILD"));
+
+    let hover = request(&mut server, "textDocument/hover", position(&app, 2, 3));
+    let contents = hover["contents"]["value"].as_str().unwrap();
+    assert_contains!(contents, "greeting(name, deps, message, visibility)");
+    assert_contains!(contents, "Greets someone.");
+    assert_contains!(contents, "- `deps` (label_list): Other greetings.");
+    let hover = request(&mut server, "textDocument/hover", position(&app, 5, 5));
+    assert_eq!(hover["contents"]["value"], "`deps` (label_list)\n\nOther greetings.");
+
+    let mut params = position(&lib, 2, 17);
+    params["newName"] = json!("core");
+    let rename = request(&mut server, "textDocument/rename", params);
+    assert_eq!(rename, json!({ "changes": {
+      app: [{ "range": range(5, 12, 19), "newText": "\"//lib:core\"" }],
+      lib: [{ "range": range(2, 16, 21), "newText": "\"core\"" }],
+    } }));
+    Ok(())
+  }
+
+  #[test]
+  fn serve_frames_messages_with_content_length() -> Result<(), Box<dyn Error>> {
+    let dir = workspace()?;
+    let mut input = String::new();
+    for message in [
+      json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+      json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+      json!({ "jsonrpc": "2.0", "id": 2, "method": "workspace/symbol", "params": {} }),
+      json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+      json!({ "jsonrpc": "2.0", "method": "exit" }),
+    ] {
+      let body = message.to_string();
+      input.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    }
+    let mut output = Vec::new();
+    serve(server(&dir)?, &mut Cursor::new(input), &mut output)?;
+
+    let mut reader = Cursor::new(output);
+    let initialized = read_message(&mut reader)?.unwrap();
+    assert_eq!(initialized["result"]["capabilities"]["hoverProvider"], true);
+    assert_eq!(read_message(&mut reader)?.unwrap()["error"]["code"], METHOD_NOT_FOUND);
+    assert_eq!(
+      read_message(&mut reader)?.unwrap(),
+      json!({ "jsonrpc": "2.0", "id": 3, "result": null }),
+    );
+    assert_eq!(read_message(&mut reader)?, None);
+    Ok(())
+  }
+}
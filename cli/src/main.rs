@@ -1,15 +1,56 @@
+mod analysis;
+mod bep;
+mod build;
+mod build_file;
+mod bzl;
+mod execution;
+mod fmt;
+mod gen;
+mod glob;
+mod grpc;
 mod host;
+mod lint;
+mod lsp;
+mod label;
+mod module;
+mod npm;
+mod package;
+mod profile;
+mod rc;
+mod repository;
+mod serve;
+mod starlark;
 mod target_pattern;
+mod workspace;
 
-use clap::{Parser, Subcommand};
+use analysis::config::{Configuration, COMPILATION_MODES};
+use bep::BuildEventStream;
+use build::BuildOptions;
+use clap::{CommandFactory, Parser, Subcommand};
+use host::fs_host::FsHost;
+use label::Label;
+use module::{LockfileMode, RepositoryOptions, LOCKFILE_MODES};
 use target_pattern::TargetPattern;
-use std::process::ExitCode;
+use profile::Profiler;
+use std::{
+  env, error::Error, fs, path::PathBuf, process::ExitCode, rc::Rc, sync::Arc,
+  time::Instant,
+};
 
 #[derive(Parser)]
-#[command(name = "Razel", version)]
+#[command(name = "Razel", version, args_override_self = true)]
 struct Args {
   #[command(subcommand)]
   command: Command,
+
+  /// Prints the options applied from rc files.
+  #[arg(long = "announce_rc", global = true)]
+  announce_rc: bool,
+
+  /// Writes a trace of where the invocation spent its time to a JSON file
+  /// viewable in chrome://tracing or Perfetto.
+  #[arg(long = "profile", value_name = "FILE", global = true)]
+  profile: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -17,36 +58,512 @@ enum Command {
   #[command(about = "Build some targets.")]
   Build {
     patterns: Vec<String>,
+
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    /// Prints how toolchains are resolved for targets and toolchain types
+    /// whose label contains the filter, or for all of them without one.
+    #[arg(long = "toolchain_resolution_debug", value_name = "FILTER", num_args = 0..=1, require_equals = true,
+      default_missing_value = "")]
+    toolchain_resolution_debug: Option<String>,
+
+    #[command(flatten)]
+    repositories: RepositoryArgs,
+
+    #[command(flatten)]
+    build_events: BuildEventArgs,
+  },
+
+  #[command(about = "Serve a target's outputs on localhost, rebuilding and \
+    reloading browsers on changes.")]
+  Serve {
+    pattern: String,
+
+    /// The port to listen on, on the loopback interface only.
+    #[arg(long = "port", default_value_t = 8080)]
+    port: u16,
+
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    #[command(flatten)]
+    repositories: RepositoryArgs,
+  },
+
+  #[command(about = "Generate or update the BUILD files of directories with \
+    TypeScript or JavaScript sources.")]
+  Gen,
+
+  #[command(about = "Format BUILD and .bzl files, preserving comments.")]
+  Fmt {
+    /// Workspace-relative files or directories, the whole workspace by
+    /// default.
+    paths: Vec<PathBuf>,
+
+    /// Lists the files which are not formatted and fails if there are any,
+    /// without changing them.
+    #[arg(long = "check")]
+    check: bool,
+  },
+
+  #[command(about = "Find unused loads, duplicate target names and deprecated \
+    attributes in BUILD and .bzl files.")]
+  Lint {
+    /// Workspace-relative files or directories, the whole workspace by
+    /// default.
+    paths: Vec<PathBuf>,
+
+    /// Fixes the problems which can be fixed automatically.
+    #[arg(long = "fix")]
+    fix: bool,
+
+    /// Fails if any problem remains.
+    #[arg(long = "check")]
+    check: bool,
+  },
+
+  #[command(about = "Run a language server for BUILD and .bzl files over stdin and stdout.")]
+  Lsp {
+    #[command(flatten)]
+    repositories: RepositoryArgs,
+  },
+
+  #[command(about = "Print the time spent in each phase and the critical path \
+    of a profile written by --profile.")]
+  AnalyzeProfile {
+    file: PathBuf,
+  },
+
+  #[command(about = "Copy every external repository into the workspace for offline builds.")]
+  Vendor {
+    #[command(flatten)]
+    repositories: RepositoryArgs,
   },
 }
 
+/// Flags which control where external repositories come from.
+#[derive(clap::Args)]
+struct RepositoryArgs {
+  /// The directory of the module registry to resolve `razel_dep()`s against.
+  #[arg(long = "registry", value_name = "DIR")]
+  registry: Option<PathBuf>,
+
+  /// Whether `MODULE.razel.lock` is updated, must already be up to date or is
+  /// ignored.
+  #[arg(long = "lockfile_mode", default_value = "update", value_parser = LOCKFILE_MODES)]
+  lockfile_mode: String,
+
+  /// The directory vendored repositories are copied to by `vendor` and used
+  /// from by other commands.
+  #[arg(long = "vendor_dir", value_name = "DIR")]
+  vendor_dir: Option<PathBuf>,
+}
+
+impl RepositoryArgs {
+  /// Returns the options with directories made absolute, since they are
+  /// relative to the working directory.
+  fn options(&self) -> RepositoryOptions {
+    let absolute = |dir: &Option<PathBuf>| dir.as_ref().map(|dir| env::current_dir().unwrap_or_default().join(dir));
+    RepositoryOptions {
+      registry: absolute(&self.registry),
+      lockfile_mode: LockfileMode::from_name(&self.lockfile_mode).unwrap(),
+      vendor_dir: absolute(&self.vendor_dir),
+    }
+  }
+}
+
+/// Flags which control where the events of a build are published.
+#[derive(clap::Args)]
+struct BuildEventArgs {
+  /// Writes the build's events to a file as newline-delimited JSON.
+  #[arg(long = "build_event_json_file", value_name = "FILE")]
+  json_file: Option<PathBuf>,
+
+  /// Writes the build's events to a file as length-delimited `BuildEvent`
+  /// protobufs.
+  #[arg(long = "build_event_binary_file", value_name = "FILE")]
+  binary_file: Option<PathBuf>,
+
+  /// Publishes the build's events to a build event service at
+  /// `[grpc://]HOST:PORT`, over gRPC without TLS.
+  #[arg(long = "bes_backend", value_name = "ADDRESS")]
+  backend: Option<String>,
+}
+
+impl BuildEventArgs {
+  /// Returns the stream events are posted to for a build in the workspace of
+  /// `host`, or none if they are not published anywhere.
+  fn stream(&self, host: &FsHost) -> Result<Option<BuildEventStream>, Box<dyn Error>> {
+    if self.json_file.is_none() && self.binary_file.is_none() && self.backend.is_none() {
+      return Ok(None);
+    }
+    let mut stream = BuildEventStream::new(host);
+    if let Some(path) = &self.json_file {
+      stream = stream.with_json_file(path)?;
+    }
+    if let Some(path) = &self.binary_file {
+      stream = stream.with_binary_file(path)?;
+    }
+    if let Some(address) = &self.backend {
+      stream = stream.with_backend(address)?;
+    }
+    Ok(Some(stream))
+  }
+}
+
+/// Flags which make up the top-level configuration.
+#[derive(clap::Args)]
+struct ConfigArgs {
+  #[arg(short = 'c', long = "compilation_mode", default_value = "fastbuild", value_parser = COMPILATION_MODES)]
+  compilation_mode: String,
+
+  /// Sets a `KEY=VALUE` pair matched by `config_setting(define_values = ...)`.
+  #[arg(long = "define", value_name = "KEY=VALUE")]
+  defines: Vec<String>,
+
+  /// The platform to build for, a `platform()` target.
+  #[arg(long = "platforms", value_name = "LABEL")]
+  platform: Option<String>,
+
+  /// The platform the build runs on, a `platform()` target.
+  #[arg(long = "host_platform", value_name = "LABEL")]
+  host_platform: Option<String>,
+}
+
+impl ConfigArgs {
+  fn configuration(&self, build_settings: &[(String, String)]) -> Result<Configuration, String> {
+    let mut config = Configuration::default();
+    config.options.insert("compilation_mode".to_owned(), self.compilation_mode.clone());
+    for define in &self.defines {
+      let (key, value) = define.split_once('=')
+        .ok_or_else(|| format!("Invalid `--define={}`, expected `KEY=VALUE`.", define))?;
+      config.defines.insert(key.to_owned(), value.to_owned());
+    }
+    let platform = |label: &Option<String>, flag: &str| label.as_ref()
+      .map(|label| Label::parse(label, "").map_err(|err| format!("Invalid `--{}`: {}", flag, err.0)))
+      .transpose();
+    config.platform = platform(&self.platform, "platforms")?;
+    config.host_platform = platform(&self.host_platform, "host_platform")?;
+    for (label, value) in build_settings {
+      let label = Label::parse(label, "").map_err(|err| format!("Invalid flag `--{}`: {}", label, err.0))?;
+      config.build_settings.insert(label, value.clone());
+    }
+    Ok(config)
+  }
+}
+
+/// Removes the flags setting build settings, such as `--//pkg:flag=value` or
+/// `--@repo//pkg:flag=value`, from the command line since clap cannot parse
+/// them, and returns their labels and values. `--//pkg:flag` sets a flag to
+/// `true` and `--no//pkg:flag` to `false`.
+fn take_build_settings(args: impl Iterator<Item = String>) -> (Vec<String>, Vec<(String, String)>) {
+  let mut remaining = Vec::new();
+  let mut build_settings = Vec::new();
+  let mut positional_only = false;
+  for arg in args {
+    if positional_only || arg == "--" {
+      positional_only = true;
+    } else if let Some(setting) = arg.strip_prefix("--")
+        .filter(|setting| is_build_setting(setting)) {
+      let (label, value) = setting.split_once('=').unwrap_or((setting, "true"));
+      build_settings.push((label.to_owned(), value.to_owned()));
+      continue;
+    } else if let Some(label) = arg.strip_prefix("--no").filter(|label| is_build_setting(label)) {
+      build_settings.push((label.to_owned(), "false".to_owned()));
+      continue;
+    }
+    remaining.push(arg);
+  }
+  (remaining, build_settings)
+}
+
+/// Returns whether an option name is the label of a build setting.
+fn is_build_setting(name: &str) -> bool {
+  name.starts_with("//") || (name.starts_with('@') && name.contains("//"))
+}
+
+/// Returns whether `command` supports the option with the given name, and if
+/// so whether it takes a value, for applying `common` rc options.
+fn supports_option(command: &str, name: &str) -> Option<bool> {
+  let args = Args::command();
+  let subcommand = args.find_subcommand(command)?;
+
+  // Build settings are supported by every command taking a configuration.
+  if is_build_setting(name) || name.strip_prefix("no").is_some_and(is_build_setting) {
+    return subcommand.get_arguments().any(|arg| arg.get_long() == Some("compilation_mode")).then_some(false);
+  }
+
+  let arg = subcommand.get_arguments().chain(args.get_arguments()).find(|arg| arg.get_long() == Some(name))?;
+  Some(arg.get_action().takes_values() && !arg.is_require_equals_set())
+}
+
 fn main() -> ExitCode {
-  let args = Args::parse();
+  let start = Instant::now();
+  let expanded = match rc::expand_args(env::args().collect(), &supports_option) {
+    Ok(expanded) => expanded,
+    Err(err) => {
+      eprintln!("ERROR: {}", err);
+      return ExitCode::FAILURE;
+    },
+  };
+  let (args, build_settings) = take_build_settings(expanded.args.into_iter());
+  let command = args.iter().skip(1)
+    .find(|arg| Args::command().find_subcommand(arg).is_some())
+    .cloned();
+  let args = Args::parse_from(args);
+  if args.announce_rc {
+    for announcement in &expanded.announcements {
+      eprintln!("INFO: {}", announcement);
+    }
+  }
+
+  let Some(path) = &args.profile else {
+    return run(&args, &build_settings, None);
+  };
+  let profiler = Arc::new(Profiler::new(start));
+  drop(profiler.span_from("phase", "parse options", start));
+  let code = {
+    let _span = profiler.span("command", command.unwrap_or_default());
+    run(&args, &build_settings, Some(profiler.clone()))
+  };
+  match fs::write(path, profiler.to_json().to_string()) {
+    Ok(()) => code,
+    Err(err) => {
+      eprintln!("ERROR: Cannot write the profile to {}: {}", path.display(), err);
+      ExitCode::FAILURE
+    },
+  }
+}
 
+/// Runs the command given on the command line, recording it with the
+/// profiler if any.
+fn run(args: &Args, build_settings: &[(String, String)],
+    profiler: Option<Arc<Profiler>>) -> ExitCode {
   match &args.command {
-    Command::Build { patterns } => {
+    Command::Build { patterns, config, toolchain_resolution_debug, repositories, build_events } => {
       // Parse target patterns.
       let (patterns, errors): (Vec<_>, Vec<_>) = patterns.iter()
           .map(|target| TargetPattern::parse(target))
           .partition(|result| result.is_ok());
 
       // Fail with any parsing errors.
-      if errors.len() != 0 {
+      if !errors.is_empty() {
         for result in errors {
           eprintln!("ERROR: {}", result.unwrap_err().0);
         }
         return ExitCode::FAILURE;
       }
+      let patterns: Vec<_> = patterns.into_iter()
+          .map(|pattern| pattern.unwrap())
+          .collect();
+
+      let config = match config.configuration(build_settings) {
+        Ok(config) => config,
+        Err(err) => {
+          eprintln!("ERROR: {}", err);
+          return ExitCode::FAILURE;
+        },
+      };
+
+      let host = match find_workspace() {
+        Ok(host) => host,
+        Err(err) => {
+          eprintln!("ERROR: {}", err);
+          return ExitCode::FAILURE;
+        },
+      };
 
       // Print targets being built.
       println!(
         "Building targets: {}",
-        patterns.into_iter()
-            .map(|lbl| format!("{}", lbl.unwrap()))
+        patterns.iter()
+            .map(|lbl| format!("{}", lbl))
             .collect::<Vec<_>>()
             .join(" "),
       );
+
+      let build_events = match build_events.stream(&host) {
+        Ok(build_events) => build_events.map(Rc::new),
+        Err(err) => {
+          eprintln!("ERROR: {}", err);
+          return ExitCode::FAILURE;
+        },
+      };
+      if let Some(build_events) = &build_events {
+        let patterns: Vec<_> = patterns.iter().map(ToString::to_string).collect();
+        build_events.started("build", &patterns);
+      }
+
+      let options = BuildOptions {
+        toolchain_resolution_debug: toolchain_resolution_debug.clone(),
+        repositories: repositories.options(),
+        profiler,
+        build_events: build_events.clone(),
+      };
+      let host = Rc::new(host);
+      let result = build::build(host.clone(), &patterns, config, &options);
+      if let Some(build_events) = &build_events {
+        if let Err(err) = build_events.finished(result.is_ok()) {
+          eprintln!("ERROR: {}", err);
+          if result.is_ok() {
+            return ExitCode::FAILURE;
+          }
+        }
+      }
+      let built = match result {
+        Ok(built) => built,
+        Err(err) => {
+          eprintln!("ERROR: {}", err);
+          return ExitCode::FAILURE;
+        },
+      };
+      if let Err(err) = build::link_outputs(host.as_ref()) {
+        eprintln!("WARNING: Cannot link to the outputs from the workspace: {}", err);
+      }
+      for target in built {
+        println!("Target {} up-to-date:", target.label);
+        for file in target.files {
+          println!("  {}", file.path);
+        }
+      }
       ExitCode::SUCCESS
     }
+    Command::Serve { pattern, port, config, repositories } => {
+      let served = TargetPattern::parse(pattern).map_err(|err| err.0.into())
+        .and_then(|pattern| Ok((pattern, config.configuration(build_settings)?)))
+        .and_then(|(pattern, config)| {
+          // Serving never returns, so its builds are not profiled.
+          let options = BuildOptions {
+            repositories: repositories.options(),
+            ..BuildOptions::default()
+          };
+          serve::serve(Rc::new(find_workspace()?), &pattern, config, &options, *port)
+        });
+      match served {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+          eprintln!("ERROR: {}", err);
+          ExitCode::FAILURE
+        },
+      }
+    }
+    Command::Gen => {
+      match find_workspace().and_then(|host| gen::generate(&host)) {
+        Ok(paths) => {
+          for path in paths {
+            println!("Wrote {}", path.display());
+          }
+          ExitCode::SUCCESS
+        },
+        Err(err) => {
+          eprintln!("ERROR: {}", err);
+          ExitCode::FAILURE
+        },
+      }
+    }
+    Command::Fmt { paths, check } => {
+      match find_workspace().and_then(|host| fmt::format_files(&host, paths, *check)) {
+        Ok(paths) => {
+          for path in &paths {
+            if *check {
+              println!("{} is not formatted", path.display());
+            } else {
+              println!("Formatted {}", path.display());
+            }
+          }
+          if *check && !paths.is_empty() { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+        },
+        Err(err) => {
+          eprintln!("ERROR: {}", err);
+          ExitCode::FAILURE
+        },
+      }
+    }
+    Command::Lint { paths, fix, check } => {
+      match find_workspace().and_then(|host| lint::lint_files(&host, paths, *fix)) {
+        Ok((fixed, findings)) => {
+          for path in fixed {
+            println!("Fixed {}", path.display());
+          }
+          for finding in &findings {
+            let fixable = if finding.fixable { " (fixable with --fix)" } else { "" };
+            println!("{}{}", finding, fixable);
+          }
+          if *check && !findings.is_empty() { ExitCode::FAILURE } else { ExitCode::SUCCESS }
+        },
+        Err(err) => {
+          eprintln!("ERROR: {}", err);
+          ExitCode::FAILURE
+        },
+      }
+    }
+    Command::Lsp { repositories } => {
+      // Analyzing unsaved files must not rewrite the lockfile.
+      let options = match repositories.options() {
+        RepositoryOptions { lockfile_mode: LockfileMode::Update, .. } => {
+          RepositoryOptions { lockfile_mode: LockfileMode::Off, ..repositories.options() }
+        },
+        options => options,
+      };
+      let served = find_workspace().and_then(|host| {
+        lsp::run(lsp::LanguageServer::new(Box::new(host), options))
+      });
+      match served {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+          eprintln!("ERROR: {}", err);
+          ExitCode::FAILURE
+        },
+      }
+    }
+    Command::AnalyzeProfile { file } => {
+      let analyzed = fs::read_to_string(file).map_err(Into::into)
+        .and_then(|profile| -> Result<String, Box<dyn Error>> {
+          Ok(profile::analyze(&serde_json::from_str(&profile)?)?)
+        });
+      match analyzed {
+        Ok(report) => {
+          print!("{}", report);
+          ExitCode::SUCCESS
+        },
+        Err(err) => {
+          eprintln!("ERROR: Cannot analyze {}: {}", file.display(), err);
+          ExitCode::FAILURE
+        },
+      }
+    }
+    Command::Vendor { repositories } => {
+      let options = repositories.options();
+      let Some(vendor_dir) = options.vendor_dir.clone() else {
+        eprintln!("ERROR: vendor requires --vendor_dir=<dir>.");
+        return ExitCode::FAILURE;
+      };
+
+      // Fetch from the original sources rather than the previously vendored
+      // copies which are about to be replaced.
+      let options = RepositoryOptions { vendor_dir: None, ..options };
+      let vendored = find_workspace()
+          .and_then(|host| module::load_repositories(Rc::new(host), &options))
+          .and_then(|repositories| repository::vendor(&repositories, &vendor_dir));
+      match vendored {
+        Ok(names) => {
+          for name in names {
+            println!("Vendored @{}", name);
+          }
+          ExitCode::SUCCESS
+        },
+        Err(err) => {
+          eprintln!("ERROR: {}", err);
+          ExitCode::FAILURE
+        },
+      }
+    }
   }
 }
+
+/// Finds the workspace containing the working directory.
+fn find_workspace() -> Result<FsHost, Box<dyn Error>> {
+  let cwd = env::current_dir()?;
+  FsHost::from(&workspace::find_root(&cwd)?)
+}
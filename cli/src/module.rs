@@ -0,0 +1,672 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use serde::{Deserialize, Serialize};
+use crate::host::host::Host;
+use crate::host::source_writer::SourceWriter;
+use crate::label::Label;
+use crate::repository::{BuildFile, Repositories, RepositoryRule, BUILTIN_REPO};
+use crate::starlark::error::EvalError;
+use crate::starlark::eval::{Evaluator, ModuleEnv};
+use crate::starlark::parser::parse;
+use crate::starlark::value::Value;
+use crate::target_pattern::TargetPattern;
+use crate::workspace::WORKSPACE_FILE;
+
+/// The file recording the resolved versions of every module, next to the
+/// `MODULE.razel` file.
+pub const LOCKFILE: &str = "MODULE.razel.lock";
+
+/// The format version of the lockfile, bumped on incompatible changes.
+const LOCKFILE_VERSION: u32 = 1;
+
+/// The declarations of a `MODULE.razel` file.
+#[derive(Debug, Default)]
+pub struct Module {
+  /// The name of the module from `module()`, empty if it has none.
+  pub name: String,
+  pub version: String,
+
+  /// Maps the names of the modules this one depends on, from `razel_dep()`,
+  /// to the minimum versions it requires.
+  pub deps: BTreeMap<String, String>,
+
+  /// Patterns of the toolchains available to toolchain resolution, in order
+  /// of preference, from `register_toolchains()`.
+  pub toolchains: Vec<TargetPattern>,
+
+  /// The repositories declared by repository rules, by name. Only those of
+  /// the root module are used.
+  pub repositories: BTreeMap<String, RepositoryRule>,
+}
+
+impl Module {
+  /// Fails if a dependency or repository is already named `name`.
+  fn check_undeclared(&self, name: &str) -> Result<(), EvalError> {
+    if name == BUILTIN_REPO || self.deps.contains_key(name) ||
+        self.repositories.contains_key(name) {
+      return Err(EvalError::msg(format!("Repository `{}` is already declared.", name)));
+    }
+    Ok(())
+  }
+}
+
+impl Module {
+  /// Evaluates the `MODULE.razel` file at the root of the workspace. A missing
+  /// file declares nothing.
+  pub fn load(host: &dyn Host) -> Result<Module, Box<dyn Error>> {
+    match host.read_to_string(Path::new(WORKSPACE_FILE)) {
+      Ok(source) => Module::evaluate(WORKSPACE_FILE, &source),
+      Err(err) if err.downcast_ref::<io::Error>()
+          .is_some_and(|err| err.kind() == io::ErrorKind::NotFound) => {
+        Ok(Module::default())
+      },
+      Err(err) => Err(err),
+    }
+  }
+
+  /// Evaluates the source of a `MODULE.razel` file.
+  fn evaluate(file: &str, source: &str) -> Result<Module, Box<dyn Error>> {
+    let declared = Rc::new(RefCell::new(Module::default()));
+    let module_called = Rc::new(RefCell::new(false));
+    let (module_decl, dep_decl, toolchain_decl) = (declared.clone(), declared.clone(), declared.clone());
+    let (local_decl, new_local_decl, archive_decl) = (declared.clone(), declared.clone(), declared.clone());
+    let npm_decl = declared.clone();
+    let predeclared = HashMap::from([
+      ("module".to_owned(), Value::builtin("module", move |_, args| {
+        let [name, version] = args.bind("module", &[], &["name", "version"])?.try_into().unwrap();
+        if module_called.replace(true) {
+          return Err(EvalError::msg("module() can only be called once."));
+        }
+        let mut module = module_decl.borrow_mut();
+        if let Some(name) = name {
+          module.name = module_name(name.expect_str("name")?)?.to_owned();
+        }
+        if let Some(version) = version {
+          module.version = version.expect_str("version")?.to_owned();
+        }
+        Ok(Value::None)
+      })),
+      ("razel_dep".to_owned(), Value::builtin("razel_dep", move |_, args| {
+        let [name, version] = args.bind("razel_dep", &["name", "version"], &[])?.try_into().unwrap();
+        let name = module_name(name.as_ref().unwrap().expect_str("name")?)?;
+        let version = version.unwrap();
+        let version = version.expect_str("version")?;
+        let mut module = dep_decl.borrow_mut();
+        module.check_undeclared(name)?;
+        module.deps.insert(name.to_owned(), version.to_owned());
+        Ok(Value::None)
+      })),
+      ("local_repository".to_owned(), Value::builtin("local_repository", move |_, args| {
+        let [name, path] = args.bind("local_repository", &["name", "path"], &[])?.try_into().unwrap();
+        let path = path.unwrap().expect_str("path")?.to_owned();
+        declare_repository(&local_decl, name.unwrap(), RepositoryRule::Local { path })
+      })),
+      ("new_local_repository".to_owned(), Value::builtin("new_local_repository", move |_, args| {
+        let [name, path, build_file, build_file_content] = args.bind(
+          "new_local_repository",
+          &["name", "path"],
+          &["build_file", "build_file_content"],
+        )?.try_into().unwrap();
+        let path = path.unwrap().expect_str("path")?.to_owned();
+        let build_file = build_file_arg(build_file, build_file_content)?.ok_or_else(|| {
+          EvalError::msg("new_local_repository() requires `build_file` or `build_file_content`.")
+        })?;
+        declare_repository(&new_local_decl, name.unwrap(), RepositoryRule::NewLocal { path, build_file })
+      })),
+      ("http_archive".to_owned(), Value::builtin("http_archive", move |_, args| {
+        let [name, sha256, url, urls, strip_prefix, build_file, build_file_content] = args.bind(
+          "http_archive",
+          &["name", "sha256"],
+          &["url", "urls", "strip_prefix", "build_file", "build_file_content"],
+        )?.try_into().unwrap();
+        let mut all_urls = Vec::new();
+        if let Some(url) = url {
+          all_urls.push(url.expect_str("url")?.to_owned());
+        }
+        if let Some(urls) = urls {
+          all_urls.extend(urls.expect_str_list("urls")?);
+        }
+        if all_urls.is_empty() {
+          return Err(EvalError::msg("http_archive() requires `url` or `urls`."));
+        }
+        let sha256 = sha256.unwrap().expect_str("sha256")?.to_lowercase();
+        if sha256.len() != 64 || !sha256.chars().all(|char| char.is_ascii_hexdigit()) {
+          return Err(EvalError::msg(format!("Invalid sha256 `{}`, expected 64 hexadecimal digits.", sha256)));
+        }
+        let strip_prefix = strip_prefix.map_or(Ok(String::new()), |prefix| {
+          prefix.expect_str("strip_prefix").map(|prefix| prefix.trim_end_matches('/').to_owned())
+        })?;
+        let build_file = build_file_arg(build_file, build_file_content)?;
+        declare_repository(&archive_decl, name.unwrap(), RepositoryRule::HttpArchive {
+          urls: all_urls,
+          sha256,
+          strip_prefix,
+          build_file,
+        })
+      })),
+      ("npm_translate_lock".to_owned(), Value::builtin("npm_translate_lock", move |_, args| {
+        let [name, lockfile, mirror] = args.bind("npm_translate_lock", &["name", "lockfile"], &["mirror"])?
+          .try_into()
+          .unwrap();
+        let lockfile = Label::parse(lockfile.unwrap().expect_str("lockfile")?, "").map_err(|err| EvalError::msg(err.0))?;
+        if !lockfile.repo.is_empty() || !matches!(lockfile.name.rsplit('/').next(), Some("package-lock.json" | "pnpm-lock.yaml")) {
+          return Err(EvalError::msg(format!(
+            "Invalid lockfile `{}`, expected a package-lock.json or pnpm-lock.yaml file of the main repository.",
+            lockfile,
+          )));
+        }
+        let mirror = mirror.map(|mirror| mirror.expect_str("mirror").map(|mirror| mirror.to_owned())).transpose()?;
+        declare_repository(&npm_decl, name.unwrap(), RepositoryRule::NpmLock { lockfile, mirror })
+      })),
+      ("register_toolchains".to_owned(), Value::builtin("register_toolchains", move |_, args| {
+        if !args.named.is_empty() {
+          return Err(EvalError::msg("register_toolchains() only accepts positional arguments."));
+        }
+        for pattern in args.positional {
+          let pattern = pattern.expect_str("toolchain pattern")?;
+          toolchain_decl.borrow_mut().toolchains.push(TargetPattern::parse(pattern).map_err(|err| EvalError::msg(err.0))?);
+        }
+        Ok(Value::None)
+      })),
+    ]);
+
+    let module = parse(file, source)?;
+    let env = ModuleEnv::new(file, Rc::new(predeclared));
+    Evaluator::new().eval_module(&module, &env)?;
+    drop(env);
+
+    Ok(declared.take())
+  }
+}
+
+/// Adds a repository declared by a repository rule called with `name`.
+fn declare_repository(module: &RefCell<Module>, name: Value, rule: RepositoryRule) -> Result<Value, EvalError> {
+  let name = module_name(name.expect_str("name")?)?;
+  let mut module = module.borrow_mut();
+  module.check_undeclared(name)?;
+  module.repositories.insert(name.to_owned(), rule);
+  Ok(Value::None)
+}
+
+/// Returns the BUILD file of a repository rule from its `build_file` label or
+/// its `build_file_content`, of which at most one may be given.
+fn build_file_arg(label: Option<Value>, content: Option<Value>) -> Result<Option<BuildFile>, EvalError> {
+  match (label, content) {
+    (Some(_), Some(_)) => Err(EvalError::msg("Only one of `build_file` and `build_file_content` may be given.")),
+    (Some(label), None) => {
+      let label = Label::parse(label.expect_str("build_file")?, "").map_err(|err| EvalError::msg(err.0))?;
+      Ok(Some(BuildFile::Label(label)))
+    },
+    (None, Some(content)) => Ok(Some(BuildFile::Content(content.expect_str("build_file_content")?.to_owned()))),
+    (None, None) => Ok(None),
+  }
+}
+
+/// Fails unless `name` can be used as a repository name in labels.
+fn module_name(name: &str) -> Result<&str, EvalError> {
+  if name.is_empty() || !name.chars().all(|char| char.is_ascii_alphanumeric() || matches!(char, '_' | '-' | '.')) {
+    return Err(EvalError::msg(format!("Invalid module name `{}`.", name)));
+  }
+  Ok(name)
+}
+
+/// Compares module versions segment by segment, numerically where both
+/// segments are numbers. A prerelease such as `1.0.0-rc1` sorts before its
+/// release.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+  let segments = |version: &str| version.split('.').map(str::to_owned).collect::<Vec<_>>();
+  let compare = |a: &[String], b: &[String]| {
+    for (a, b) in a.iter().zip(b) {
+      let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+      };
+      if ordering != Ordering::Equal {
+        return ordering;
+      }
+    }
+    a.len().cmp(&b.len())
+  };
+
+  let (a_release, a_pre) = a.split_once('-').map_or((a, None), |(release, pre)| (release, Some(pre)));
+  let (b_release, b_pre) = b.split_once('-').map_or((b, None), |(release, pre)| (release, Some(pre)));
+  compare(&segments(a_release), &segments(b_release)).then_with(|| match (a_pre, b_pre) {
+    (None, None) => Ordering::Equal,
+    (None, Some(_)) => Ordering::Greater,
+    (Some(_), None) => Ordering::Less,
+    (Some(a), Some(b)) => compare(&segments(a), &segments(b)),
+  })
+}
+
+/// A local directory of modules laid out as `modules/<name>/<version>/`, each
+/// containing the module's `MODULE.razel` file and a `source.json` file
+/// locating its sources.
+pub struct Registry {
+  host: Box<dyn Host>,
+}
+
+/// The contents of a registry's `source.json` file.
+#[derive(Deserialize)]
+struct Source {
+  #[serde(rename = "type")]
+  kind: String,
+
+  /// The directory of the sources, relative to the registry.
+  path: String,
+}
+
+impl Registry {
+  /// Opens the registry at the given directory, which is relative to the
+  /// workspace root unless it is absolute.
+  pub fn open(host: &dyn Host, root: &Path) -> Result<Registry, Box<dyn Error>> {
+    Ok(Registry { host: host.with_source_root(root)? })
+  }
+
+  /// Evaluates the `MODULE.razel` file of a module in the registry.
+  fn module(&self, name: &str, version: &str) -> Result<Module, Box<dyn Error>> {
+    let path = Path::new("modules").join(name).join(version).join(WORKSPACE_FILE);
+    let source = self.host.read_to_string(&path)
+      .map_err(|_| ModuleError(format!("Module `{}@{}` not found in the registry.", name, version)))?;
+    let module = Module::evaluate(path.to_str().unwrap(), &source)?;
+    if module.name != name || module.version != version {
+      return Err(Box::new(ModuleError(format!(
+        "Module `{}@{}` in the registry declares itself as `{}@{}`.",
+        name,
+        version,
+        module.name,
+        module.version,
+      ))));
+    }
+
+    Ok(module)
+  }
+
+  /// Returns a host reading the sources of a module.
+  fn source(&self, name: &str, version: &str) -> Result<Box<dyn Host>, Box<dyn Error>> {
+    let path = Path::new("modules").join(name).join(version).join("source.json");
+    let source: Source = serde_json::from_str(&self.host.read_to_string(&path)?)
+      .map_err(|err| ModuleError(format!("Invalid `{}` in the registry: {}", path.to_str().unwrap(), err)))?;
+    if source.kind != "local_path" {
+      return Err(Box::new(ModuleError(format!(
+        "Module `{}@{}` has unsupported source type `{}`.",
+        name,
+        version,
+        source.kind,
+      ))));
+    }
+
+    self.host.with_source_root(Path::new(&source.path))
+  }
+}
+
+/// The version of a module selected by resolution, with the dependencies that
+/// version declares.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ResolvedModule {
+  pub version: String,
+  pub deps: BTreeMap<String, String>,
+}
+
+/// Resolves the transitive dependencies of `root` with minimal version
+/// selection: every module is used at the highest version required by any
+/// module depending on it. Modules only required by versions which were not
+/// selected are dropped.
+pub fn resolve(root: &Module, registry: &Registry) -> Result<BTreeMap<String, ResolvedModule>, Box<dyn Error>> {
+  // Load every version reachable from the root.
+  let mut loaded: BTreeMap<(String, String), Module> = BTreeMap::new();
+  let mut pending: Vec<_> = root.deps.iter().map(|(name, version)| (name.clone(), version.clone())).collect();
+  while let Some((name, version)) = pending.pop() {
+    if loaded.contains_key(&(name.clone(), version.clone())) {
+      continue;
+    }
+    let module = registry.module(&name, &version)?;
+    pending.extend(module.deps.iter().map(|(name, version)| (name.clone(), version.clone())));
+    loaded.insert((name, version), module);
+  }
+
+  let mut selected: BTreeMap<&str, &str> = BTreeMap::new();
+  for (name, version) in loaded.keys() {
+    let current = selected.entry(name).or_insert(version);
+    if compare_versions(version, current) == Ordering::Greater {
+      *current = version;
+    }
+  }
+
+  // Keep only the selected versions reachable from the root.
+  let mut resolved = BTreeMap::new();
+  let mut pending: Vec<_> = root.deps.keys().collect();
+  while let Some(name) = pending.pop() {
+    if resolved.contains_key(name) {
+      continue;
+    }
+    let version = selected[name.as_str()];
+    let module = &loaded[&(name.clone(), version.to_owned())];
+    pending.extend(module.deps.keys());
+    resolved.insert(name.clone(), ResolvedModule { version: version.to_owned(), deps: module.deps.clone() });
+  }
+
+  Ok(resolved)
+}
+
+/// How the lockfile is used when resolving modules.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LockfileMode {
+  /// Reuses the lockfile while the root module's dependencies are unchanged
+  /// and rewrites it otherwise.
+  #[default]
+  Update,
+
+  /// Fails unless the lockfile is up to date.
+  Error,
+
+  /// Resolves without reading or writing the lockfile.
+  Off,
+}
+
+/// The names of the lockfile modes accepted by `--lockfile_mode`.
+pub const LOCKFILE_MODES: [&str; 3] = ["update", "error", "off"];
+
+impl LockfileMode {
+  /// Returns the mode with the given name, one of `LOCKFILE_MODES`.
+  pub fn from_name(name: &str) -> Option<LockfileMode> {
+    match name {
+      "update" => Some(LockfileMode::Update),
+      "error" => Some(LockfileMode::Error),
+      "off" => Some(LockfileMode::Off),
+      _ => None,
+    }
+  }
+}
+
+/// The contents of the lockfile.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Lockfile {
+  lockfile_version: u32,
+
+  /// The dependencies of the root module the lockfile was resolved for.
+  root_deps: BTreeMap<String, String>,
+
+  modules: BTreeMap<String, ResolvedModule>,
+}
+
+/// Where the repositories of a workspace come from.
+#[derive(Default)]
+pub struct RepositoryOptions {
+  /// The directory of the module registry `razel_dep()`s are resolved
+  /// against, relative to the workspace root unless it is absolute.
+  pub registry: Option<PathBuf>,
+
+  pub lockfile_mode: LockfileMode,
+
+  /// The directory of vendored repositories, which are used instead of
+  /// fetching them.
+  pub vendor_dir: Option<PathBuf>,
+}
+
+/// Resolves the dependencies of the workspace's root module and returns the
+/// repositories labels can refer to, with every resolved module and every
+/// repository rule available as `@name`. Resolution is skipped while the
+/// lockfile is up to date, but the registry is still needed to locate the
+/// sources of modules which are not vendored.
+pub fn load_repositories(host: Rc<dyn Host>, options: &RepositoryOptions) -> Result<Repositories, Box<dyn Error>> {
+  let root = Module::load(host.as_ref())?;
+  let mut repositories = Repositories::new(host.clone()).with_vendor_dir(options.vendor_dir.clone());
+  for (name, rule) in &root.repositories {
+    repositories.insert_rule(name, rule.clone());
+  }
+  if root.deps.is_empty() {
+    return Ok(repositories);
+  }
+  let registry = || -> Result<Registry, Box<dyn Error>> {
+    let registry = options.registry.as_ref().ok_or_else(|| ModuleError(format!(
+      "`{}` has dependencies, pass --registry=<dir> to resolve them.",
+      WORKSPACE_FILE,
+    )))?;
+    Registry::open(host.as_ref(), registry)
+  };
+
+  let mode = options.lockfile_mode;
+  let existing = match mode {
+    LockfileMode::Off => None,
+    _ => host.read_to_string(Path::new(LOCKFILE)).ok(),
+  };
+  let locked = existing.as_ref()
+    .and_then(|contents| serde_json::from_str::<Lockfile>(contents).ok())
+    .filter(|lockfile| lockfile.lockfile_version == LOCKFILE_VERSION && lockfile.root_deps == root.deps);
+  let modules = match (locked, mode) {
+    (Some(lockfile), _) => lockfile.modules,
+    (None, LockfileMode::Error) => return Err(Box::new(ModuleError(format!(
+      "`{}` is out of date, rerun with --lockfile_mode=update.",
+      LOCKFILE,
+    )))),
+    (None, _) => resolve(&root, &registry()?)?,
+  };
+
+  if mode == LockfileMode::Update {
+    let lockfile = Lockfile { lockfile_version: LOCKFILE_VERSION, root_deps: root.deps, modules: modules.clone() };
+    let contents = serde_json::to_string_pretty(&lockfile)? + "\n";
+    if existing.as_ref() != Some(&contents) {
+      SourceWriter::new(host.as_ref()).write(Path::new(LOCKFILE), contents.as_bytes())?;
+    }
+  }
+  for (name, module) in &modules {
+    let source = match repositories.vendored(name) {
+      Some(source) => source,
+      None => Rc::from(registry()?.source(name, &module.version)?),
+    };
+    repositories.insert(name, source);
+  }
+
+  Ok(repositories)
+}
+
+/// An error thrown when modules cannot be resolved.
+#[derive(Debug)]
+pub struct ModuleError(pub String);
+
+impl Display for ModuleError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for ModuleError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::fs;
+  use assertables::assert_contains;
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+  use crate::target_pattern::PatternScope;
+  use super::*;
+
+  #[test]
+  fn load_evaluates_module_file() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("MODULE.razel"), TestContents::File(r#"
+module(name = "app", version = "1.0")
+razel_dep(name = "lib", version = "2.1")
+register_toolchains("//toolchains:node_linux")
+register_toolchains("//toolchains/...")
+"#)),
+    ])?;
+
+    let module = Module::load(&FsHost::from(&dir.root)?)?;
+    assert_eq!((module.name.as_str(), module.version.as_str()), ("app", "1.0"));
+    assert_eq!(module.deps, BTreeMap::from([("lib".to_owned(), "2.1".to_owned())]));
+    assert_eq!(module.toolchains, [
+      TargetPattern {
+        repo: "".to_owned(),
+        package: "toolchains".to_owned(),
+        scope: PatternScope::SingleTarget("node_linux".to_owned()),
+      },
+      TargetPattern { repo: "".to_owned(), package: "toolchains".to_owned(), scope: PatternScope::Descendants },
+    ]);
+
+    let dir = TestDir::from([
+      (Path::new("MODULE.razel"), TestContents::File("register_toolchains(\"toolchains\")")),
+    ])?;
+    assert_contains!(
+      Module::load(&FsHost::from(&dir.root)?).err().unwrap().to_string(),
+      "MODULE.razel:1:20: Failed to parse `toolchains`",
+    );
+
+    let dir = TestDir::from([
+      (Path::new("MODULE.razel"), TestContents::File(
+        "razel_dep(name = \"lib\", version = \"1\")\nrazel_dep(name = \"lib\", version = \"2\")",
+      )),
+    ])?;
+    assert_contains!(
+      Module::load(&FsHost::from(&dir.root)?).err().unwrap().to_string(),
+      "Repository `lib` is already declared.",
+    );
+
+    let dir = TestDir::from([])?;
+    assert!(Module::load(&FsHost::from(&dir.root)?)?.toolchains.is_empty());
+
+    // Only a missing file declares nothing.
+    let dir = TestDir::from([(Path::new("MODULE.razel"), TestContents::Directory)])?;
+    assert!(Module::load(&FsHost::from(&dir.root)?).is_err());
+
+    Ok(())
+  }
+
+  #[test]
+  fn load_declares_repository_rules() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("MODULE.razel"), TestContents::File(r#"
+local_repository(name = "tools", path = "../tools")
+npm_translate_lock(name = "npm", lockfile = "//web:pnpm-lock.yaml")
+http_archive(
+  name = "lib",
+  url = "file:///archives/lib.tar.gz",
+  sha256 = "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855",
+  strip_prefix = "lib-1.0/",
+  build_file = "//third_party:lib.BUILD",
+)
+"#)),
+    ])?;
+
+    let module = Module::load(&FsHost::from(&dir.root)?)?;
+    assert_eq!(module.repositories, BTreeMap::from([
+      ("lib".to_owned(), RepositoryRule::HttpArchive {
+        urls: vec!["file:///archives/lib.tar.gz".to_owned()],
+        sha256: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_owned(),
+        strip_prefix: "lib-1.0".to_owned(),
+        build_file: Some(BuildFile::Label(Label::parse("//third_party:lib.BUILD", "")?)),
+      }),
+      ("npm".to_owned(), RepositoryRule::NpmLock { lockfile: Label::parse("//web:pnpm-lock.yaml", "")?, mirror: None }),
+      ("tools".to_owned(), RepositoryRule::Local { path: "../tools".to_owned() }),
+    ]));
+
+    for (source, error) in [
+      ("http_archive(name = \"lib\", sha256 = \"abc\", url = \"file:///lib.tar\")", "Invalid sha256 `abc`"),
+      ("http_archive(name = \"lib\", sha256 = \"\" + \"0\" * 64)", "http_archive() requires `url` or `urls`."),
+      ("new_local_repository(name = \"lib\", path = \"lib\")", "requires `build_file` or `build_file_content`."),
+      ("npm_translate_lock(name = \"npm\", lockfile = \"//:yarn.lock\")", "Invalid lockfile `//:yarn.lock`"),
+      ("local_repository(name = \"lib\", path = \"a\")\nlocal_repository(name = \"lib\", path = \"b\")",
+        "Repository `lib` is already declared."),
+    ] {
+      let dir = TestDir::from([(Path::new("MODULE.razel"), TestContents::File(source))])?;
+      assert_contains!(Module::load(&FsHost::from(&dir.root)?).err().unwrap().to_string(), error);
+    }
+
+    Ok(())
+  }
+
+  #[test]
+  fn compare_versions_compares_numeric_segments() {
+    assert_eq!(compare_versions("1.10.0", "1.9.0"), Ordering::Greater);
+    assert_eq!(compare_versions("1.0", "1.0.1"), Ordering::Less);
+    assert_eq!(compare_versions("2.0.0-rc1", "2.0.0"), Ordering::Less);
+    assert_eq!(compare_versions("2.0.0-rc.2", "2.0.0-rc.10"), Ordering::Less);
+    assert_eq!(compare_versions("1.2", "1.2"), Ordering::Equal);
+  }
+
+  fn registry_module(name: &str, version: &str, deps: &[(&str, &str)]) -> String {
+    let mut module = format!("module(name = \"{}\", version = \"{}\")\n", name, version);
+    for (name, version) in deps {
+      module += &format!("razel_dep(name = \"{}\", version = \"{}\")\n", name, version);
+    }
+    module
+  }
+
+  fn options(lockfile_mode: LockfileMode) -> RepositoryOptions {
+    RepositoryOptions { registry: Some(PathBuf::from("registry")), lockfile_mode, vendor_dir: None }
+  }
+
+  #[test]
+  fn load_repositories_selects_minimal_versions_and_writes_lockfile() -> Result<(), Box<dyn Error>> {
+    let modules = [
+      ("a", "1.0", registry_module("a", "1.0", &[("c", "1.0")])),
+      ("b", "1.0", registry_module("b", "1.0", &[("c", "1.1")])),
+      ("c", "1.0", registry_module("c", "1.0", &[("e", "1.0")])),
+      ("c", "1.1", registry_module("c", "1.1", &[("d", "1.0")])),
+      ("d", "1.0", registry_module("d", "1.0", &[])),
+      ("e", "1.0", registry_module("e", "1.0", &[])),
+    ];
+    let dir = TestDir::from([
+      (Path::new("MODULE.razel"), TestContents::File(
+        "razel_dep(name = \"a\", version = \"1.0\")\nrazel_dep(name = \"b\", version = \"1.0\")\n",
+      )),
+    ])?;
+    for (name, version, module) in &modules {
+      let module_dir = dir.root.join(format!("registry/modules/{}/{}", name, version));
+      let source_dir = dir.root.join(format!("registry/sources/{}-{}", name, version));
+      fs::create_dir_all(&module_dir)?;
+      fs::create_dir_all(&source_dir)?;
+      fs::write(module_dir.join("MODULE.razel"), module)?;
+      fs::write(
+        module_dir.join("source.json"),
+        format!(r#"{{"type": "local_path", "path": "sources/{}-{}"}}"#, name, version),
+      )?;
+      fs::write(source_dir.join("BUILD"), "")?;
+    }
+    let host: Rc<dyn Host> = Rc::new(FsHost::from(&dir.root)?);
+
+    let repositories = load_repositories(host.clone(), &options(LockfileMode::Update))?;
+    assert_eq!(repositories.external().keys().collect::<Vec<_>>(), ["a", "b", "c", "d"]);
+    assert_eq!(
+      repositories.get("c")?.source_root(),
+      dir.root.canonicalize()?.join("registry/sources/c-1.1"),
+    );
+    assert_contains!(repositories.get("e").err().unwrap().to_string(), "No such repository `@e`.");
+
+    let lockfile: Lockfile = serde_json::from_str(&host.read_to_string(Path::new(LOCKFILE))?)?;
+    assert_eq!(lockfile.modules["c"], ResolvedModule {
+      version: "1.1".to_owned(),
+      deps: BTreeMap::from([("d".to_owned(), "1.0".to_owned())]),
+    });
+
+    // An up to date lockfile is reused without consulting the registry's
+    // module files, while a stale one is an error unless it may be updated.
+    fs::remove_file(dir.root.join("registry/modules/a/1.0/MODULE.razel"))?;
+    load_repositories(host.clone(), &options(LockfileMode::Error))?;
+    fs::write(dir.root.join("MODULE.razel"), "razel_dep(name = \"b\", version = \"1.0\")\n")?;
+    assert_contains!(
+      load_repositories(host.clone(), &options(LockfileMode::Error)).err().unwrap().to_string(),
+      "`MODULE.razel.lock` is out of date",
+    );
+    let repositories = load_repositories(host.clone(), &options(LockfileMode::Update))?;
+    assert_eq!(repositories.external().keys().collect::<Vec<_>>(), ["b", "c", "d"]);
+
+    assert_contains!(
+      load_repositories(host, &RepositoryOptions::default()).err().unwrap().to_string(),
+      "pass --registry=<dir>",
+    );
+
+    Ok(())
+  }
+}
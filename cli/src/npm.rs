@@ -0,0 +1,749 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Component, Path, PathBuf};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use serde_yaml::Value as Yaml;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use tar::Archive;
+use crate::host::host::{list_all_files, Host};
+use crate::label::package_path;
+use crate::package::BUILD_FILE_NAMES;
+use crate::repository::extract;
+
+/// The directory of the output base which stores npm tarballs by the
+/// algorithm and hex digest of their integrity.
+pub const NPM_CACHE: &str = "cache/npm";
+
+/// The directory of an npm repository every package is extracted into, as
+/// `<name>@<version>/node_modules/<name>` next to links to its dependencies.
+const STORE_DIR: &str = "node_modules/.store";
+
+/// The registry tarball URLs are derived from when a lockfile omits them.
+const DEFAULT_REGISTRY: &str = "https://registry.npmjs.org";
+
+/// A package installed by a lockfile.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NpmPackage {
+  pub name: String,
+  pub version: String,
+
+  /// The URL of the package's tarball.
+  pub url: String,
+
+  /// The Subresource Integrity of the tarball, such as `sha512-<base64>`.
+  pub integrity: String,
+
+  /// Maps the names the package requires its dependencies by to their keys.
+  pub deps: BTreeMap<String, String>,
+}
+
+impl NpmPackage {
+  /// The key identifying the package in a lockfile, `<name>@<version>`.
+  pub fn key(&self) -> String {
+    format!("{}@{}", self.name, self.version)
+  }
+}
+
+/// The packages of a `package-lock.json` or `pnpm-lock.yaml` file.
+#[derive(Debug, Default, PartialEq)]
+pub struct NpmLock {
+  /// The installed packages by key.
+  pub packages: BTreeMap<String, NpmPackage>,
+
+  /// Maps the names the workspace requires its direct dependencies by to
+  /// their keys.
+  pub deps: BTreeMap<String, String>,
+}
+
+impl NpmLock {
+  /// Parses a lockfile, whose format is chosen by its file name. Workspace
+  /// packages linked by the lockfile are not included.
+  pub fn parse(path: &Path, contents: &str) -> Result<NpmLock, Box<dyn Error>> {
+    let lock = match path.file_name().and_then(|name| name.to_str()) {
+      Some("package-lock.json") => NpmLock::parse_package_lock(contents),
+      Some("pnpm-lock.yaml") => NpmLock::parse_pnpm_lock(contents),
+      _ => Err(Box::new(NpmError(
+        "Unsupported lockfile, expected a package-lock.json or pnpm-lock.yaml file.".to_owned(),
+      )) as Box<dyn Error>),
+    };
+    lock.map_err(|err| format!("Failed to parse `{}`: {}", path.to_str().unwrap(), err).into())
+  }
+
+  /// Parses a `package-lock.json` file of version 2 or 3, whose packages are
+  /// keyed by where npm installs them. Dependencies are wired the way Node.js
+  /// finds them, in the closest `node_modules` directory.
+  fn parse_package_lock(contents: &str) -> Result<NpmLock, Box<dyn Error>> {
+    let lockfile: PackageLock = serde_json::from_str(contents)?;
+    if lockfile.lockfile_version < 2 {
+      return Err(Box::new(NpmError(format!(
+        "lockfileVersion {} is not supported, regenerate it with npm 7 or later.",
+        lockfile.lockfile_version,
+      ))));
+    }
+
+    let installed = |path: &str| lockfile.packages.get(path)
+      .filter(|entry| path.starts_with("node_modules/") && !entry.link && !entry.in_bundle);
+    let key = |path: &str| {
+      let entry = installed(path).unwrap();
+      let name = entry.name.clone()
+        .unwrap_or_else(|| path.rsplit_once("node_modules/").unwrap().1.to_owned());
+      format!("{}@{}", name, entry.version)
+    };
+
+    // Finds the package required by the package installed at `from`.
+    let resolve = |from: &str, name: &str| -> Option<String> {
+      let mut dir = from;
+      loop {
+        let candidate = if dir.is_empty() {
+          format!("node_modules/{}", name)
+        } else {
+          format!("{}/node_modules/{}", dir, name)
+        };
+        if installed(&candidate).is_some() {
+          return Some(candidate);
+        }
+        if dir.is_empty() {
+          return None;
+        }
+        dir = dir.rfind("/node_modules/").map_or("", |index| &dir[..index]);
+      }
+    };
+    let deps = |from: &str, entry: &PackageLockEntry, required: &[&BTreeMap<String, String>]| {
+      let mut deps = BTreeMap::new();
+      for (is_required, names) in required.iter().map(|names| (true, *names))
+          .chain([(false, &entry.optional_dependencies), (false, &entry.peer_dependencies)]) {
+        for name in names.keys() {
+          match resolve(from, name) {
+            Some(path) => { deps.insert(name.clone(), key(&path)); },
+            None if is_required => return Err(NpmError(format!(
+              "`{}` requires `{}`, which is not installed by the lockfile.",
+              if from.is_empty() { "The workspace" } else { from },
+              name,
+            ))),
+            None => {},
+          }
+        }
+      }
+      Ok(deps)
+    };
+
+    let mut lock = NpmLock::default();
+    if let Some(root) = lockfile.packages.get("") {
+      lock.deps = deps("", root, &[&root.dependencies, &root.dev_dependencies])?;
+    }
+    for path in lockfile.packages.keys().filter(|path| installed(path).is_some()) {
+      let entry = &lockfile.packages[path];
+      let key = key(path);
+      if lock.packages.contains_key(&key) {
+        continue;
+      }
+      let (name, version) = key.rsplit_once('@').unwrap();
+      let url = entry.resolved.clone().unwrap_or_else(|| registry_url(name, version));
+      let integrity = entry.integrity.clone()
+        .ok_or_else(|| NpmError(format!("`{}` has no integrity.", path)))?;
+      lock.packages.insert(key.clone(), NpmPackage {
+        name: name.to_owned(),
+        version: version.to_owned(),
+        url,
+        integrity,
+        deps: deps(path, entry, &[&entry.dependencies])?,
+      });
+    }
+
+    Ok(lock)
+  }
+
+  /// Parses a `pnpm-lock.yaml` file of version 6 or 9. Version 9 moved the
+  /// dependencies of packages to `snapshots`. Peer dependency suffixes such
+  /// as `(react@18.2.0)` are dropped from versions.
+  fn parse_pnpm_lock(contents: &str) -> Result<NpmLock, Box<dyn Error>> {
+    let lockfile: Yaml = serde_yaml::from_str(contents)?;
+    let version = match &lockfile["lockfileVersion"] {
+      Yaml::String(version) => version.clone(),
+      Yaml::Number(version) => version.to_string(),
+      _ => String::new(),
+    };
+    let major = version.split('.').next().unwrap_or_default();
+    if major != "6" && major != "9" {
+      return Err(Box::new(NpmError(format!(
+        "lockfileVersion `{}` is not supported, expected 6.0 or 9.0.",
+        version,
+      ))));
+    }
+
+    // Returns the key of a dependency from its name and resolved version,
+    // which is the key itself for aliased dependencies.
+    let dep_key = |name: &str, version: &str| -> Option<String> {
+      let version = version.split('(').next().unwrap().trim_start_matches('/');
+      if version.starts_with("link:") || version.starts_with("file:") {
+        None
+      } else if version.rfind('@').is_some_and(|index| index > 0) {
+        Some(version.to_owned())
+      } else {
+        Some(format!("{}@{}", name, version))
+      }
+    };
+    let deps = |entry: &Yaml, fields: &[&str]| -> BTreeMap<String, String> {
+      fields.iter()
+        .filter_map(|field| entry[*field].as_mapping())
+        .flat_map(|deps| deps.iter())
+        .filter_map(|(name, version)| {
+          let name = name.as_str()?;
+          // Importers record `{specifier, version}`, packages just the version.
+          let version = version.as_str().or_else(|| version["version"].as_str())?;
+          Some((name.to_owned(), dep_key(name, version)?))
+        })
+        .collect()
+    };
+    let direct = ["dependencies", "devDependencies", "optionalDependencies"];
+    let transitive = ["dependencies", "optionalDependencies"];
+
+    let mut lock = NpmLock::default();
+    let root = match &lockfile["importers"] {
+      Yaml::Mapping(importers) => importers.get(".").unwrap_or(&Yaml::Null),
+      _ => &lockfile,
+    };
+    lock.deps = deps(root, &direct);
+
+    let snapshots = lockfile["snapshots"].as_mapping().into_iter().flat_map(|snapshots| snapshots.iter());
+    let entries = lockfile["packages"].as_mapping().into_iter().flat_map(|packages| packages.iter());
+    for (key, entry) in entries.chain(snapshots) {
+      let key = key.as_str().and_then(|key| dep_key("", key))
+        .ok_or_else(|| NpmError(format!("Invalid package key `{:?}`.", key)))?;
+      let Some((name, version)) = key.rsplit_once('@').filter(|(name, _)| !name.is_empty()) else {
+        return Err(Box::new(NpmError(format!("Invalid package key `{}`.", key))));
+      };
+      let package = lock.packages.entry(key.clone()).or_insert_with(|| NpmPackage {
+        name: name.to_owned(),
+        version: version.to_owned(),
+        url: registry_url(name, version),
+        integrity: String::new(),
+        deps: BTreeMap::new(),
+      });
+      if let Some(integrity) = entry["resolution"]["integrity"].as_str() {
+        package.integrity = integrity.to_owned();
+      }
+      if let Some(tarball) = entry["resolution"]["tarball"].as_str() {
+        package.url = tarball.to_owned();
+      }
+      package.deps.extend(deps(entry, &transitive));
+    }
+
+    for package in lock.packages.values() {
+      if package.integrity.is_empty() {
+        return Err(Box::new(NpmError(format!("`{}` has no integrity.", package.key()))));
+      }
+      if let Some(dep) = package.deps.values().chain(lock.deps.values()).find(|dep| !lock.packages.contains_key(*dep)) {
+        return Err(Box::new(NpmError(format!("`{}` is required but not in `packages`.", dep))));
+      }
+    }
+
+    Ok(lock)
+  }
+
+  /// The name of the target of a package in the generated repository. Direct
+  /// dependencies of the workspace are named after the package, such as
+  /// `@npm//:react`, others after their key, such as `@npm//:scheduler@0.23.0`.
+  pub fn target_name(&self, key: &str) -> String {
+    let package = &self.packages[key];
+    match self.deps.get(&package.name) {
+      Some(direct) if direct == key => package.name.clone(),
+      _ => key.to_owned(),
+    }
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PackageLock {
+  lockfile_version: u32,
+
+  /// Every package by its install path, `""` for the workspace itself.
+  #[serde(default)]
+  packages: BTreeMap<String, PackageLockEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PackageLockEntry {
+  /// The name of the package if it is installed under an alias.
+  name: Option<String>,
+
+  #[serde(default)]
+  version: String,
+
+  resolved: Option<String>,
+  integrity: Option<String>,
+
+  /// Whether this is a symlink to a workspace package.
+  #[serde(default)]
+  link: bool,
+
+  /// Whether the package is shipped inside its parent's tarball.
+  #[serde(default)]
+  in_bundle: bool,
+
+  #[serde(default)]
+  dependencies: BTreeMap<String, String>,
+  #[serde(default)]
+  dev_dependencies: BTreeMap<String, String>,
+  #[serde(default)]
+  optional_dependencies: BTreeMap<String, String>,
+  #[serde(default)]
+  peer_dependencies: BTreeMap<String, String>,
+}
+
+/// The URL the npm registry serves a package's tarball at.
+fn registry_url(name: &str, version: &str) -> String {
+  let basename = name.rsplit('/').next().unwrap();
+  format!("{}/{}/-/{}-{}.tgz", DEFAULT_REGISTRY, name, basename, version)
+}
+
+/// Generates the repository `repo` of a lockfile in the directory `dest` of
+/// the output base. Tarballs are read from the npm cache or else from
+/// `mirror`, a directory laid out like the registry, and must match their
+/// integrity. Every package is extracted into the store next to links to its
+/// dependencies, and `node_modules/<name>` links to each direct dependency of
+/// the workspace, so Node.js resolves packages from the exec root.
+pub fn fetch(host: &dyn Host, repo: &str, lock: &NpmLock, mirror: Option<&Path>, dest: &Path) ->
+    Result<(), Box<dyn Error>> {
+  let mut build = String::from("load(\":defs.bzl\", \"npm_package\")\n");
+  for (key, package) in &lock.packages {
+    let dir = dest.join(package_dir(package));
+    let tarball = tarball(host, package, mirror)?;
+    let root = archive_root(&tarball)?;
+    extract(host, &package.url, &tarball, &root, &dir)?;
+
+    // Packages never define targets, so stray BUILD files must not turn
+    // their directories into packages.
+    let extracted = host.with_source_root(&host.output_base().join(&dir))?;
+    for file in list_all_files(extracted.as_ref(), Path::new(""))? {
+      if BUILD_FILE_NAMES.iter().any(|name| file.file_name().unwrap() == *name) {
+        host.delete_output(&dir.join(file))?;
+      }
+    }
+
+    for (alias, dep) in &package.deps {
+      if alias == &package.name {
+        continue;
+      }
+      let link = dest.join(STORE_DIR).join(store_name(key)).join("node_modules").join(alias);
+      let up = "../".repeat(alias.matches('/').count() + 2);
+      host.symlink_output(&link, &Path::new(&up).join(package_dir_in_store(&lock.packages[dep])))?;
+    }
+
+    build.push_str(&format!(
+      r#"
+npm_package(
+    name = "{name}",
+    package_name = "{package_name}",
+    version = "{version}",
+    srcs = glob(["{dir}/**"]),
+    deps = [{deps}],
+    directory = "{exec_dir}",
+    visibility = ["//visibility:public"],
+)
+"#,
+      name = lock.target_name(key),
+      package_name = package.name,
+      version = package.version,
+      dir = package_dir(package),
+      deps = package.deps.values()
+        .filter(|dep| *dep != key)
+        .map(|dep| format!("\":{}\"", lock.target_name(dep)))
+        .collect::<Vec<_>>()
+        .join(", "),
+      exec_dir = package_path(repo, &package_dir(package)),
+    ));
+  }
+
+  for (alias, dep) in &lock.deps {
+    let up = "../".repeat(alias.matches('/').count());
+    let target = Path::new(&up).join(".store").join(package_dir_in_store(&lock.packages[dep]));
+    host.symlink_output(&dest.join("node_modules").join(alias), &target)?;
+  }
+  host.write_output(&dest.join(BUILD_FILE_NAMES[0]), build.as_bytes())?;
+  host.write_output(
+    &dest.join("defs.bzl"),
+    DEFS.replace("{NODE_MODULES}", &package_path(repo, "node_modules")).as_bytes(),
+  )?;
+  Ok(())
+}
+
+/// Defines the rule of the packages of a generated npm repository.
+const DEFS: &str = r#"load("@razel//js:defs.bzl", "JsInfo")
+
+NpmPackageInfo = provider(
+    doc = "An npm package and the files of its transitive dependencies.",
+    fields = ["package_name", "version", "directory", "node_modules", "files"],
+)
+
+def _npm_package_impl(ctx):
+    files = depset(ctx.files.srcs, transitive = [dep[NpmPackageInfo].files for dep in ctx.attr.deps])
+    return [
+        DefaultInfo(files = files),
+        NpmPackageInfo(
+            package_name = ctx.attr.package_name,
+            version = ctx.attr.version,
+            directory = ctx.attr.directory,
+            node_modules = "{NODE_MODULES}",
+            files = files,
+        ),
+        JsInfo(
+            sources = files,
+            node_modules = depset(["{NODE_MODULES}"]),
+            direct_sources = [],
+            package_name = ctx.attr.package_name,
+            deps = ctx.attr.deps,
+            strict_deps = False,
+        ),
+    ]
+
+npm_package = rule(
+    implementation = _npm_package_impl,
+    attrs = {
+        "package_name": attr.string(mandatory = True),
+        "version": attr.string(mandatory = True),
+        "srcs": attr.label_list(allow_files = True),
+        "deps": attr.label_list(),
+        "directory": attr.string(),
+    },
+)
+"#;
+
+/// The name of a package's directory in the store, with the `/` of scoped
+/// packages replaced.
+fn store_name(key: &str) -> String {
+  key.replace('/', "+")
+}
+
+/// The path of a package's files relative to the store.
+fn package_dir_in_store(package: &NpmPackage) -> PathBuf {
+  Path::new(&store_name(&package.key())).join("node_modules").join(&package.name)
+}
+
+/// The path of a package's files relative to the repository root.
+fn package_dir(package: &NpmPackage) -> String {
+  format!("{}/{}", STORE_DIR, package_dir_in_store(package).to_str().unwrap())
+}
+
+/// Returns the tarball of a package from the npm cache, or else from the
+/// mirror or its `file://` URL, after verifying it against its integrity.
+fn tarball(host: &dyn Host, package: &NpmPackage, mirror: Option<&Path>) -> Result<Vec<u8>, Box<dyn Error>> {
+  let (algorithm, expected) = package.integrity.split_whitespace()
+    .filter_map(|integrity| integrity.split_once('-'))
+    .find(|(algorithm, _)| matches!(*algorithm, "sha512" | "sha384" | "sha256" | "sha1"))
+    .ok_or_else(|| NpmError(format!("Unsupported integrity `{}` of `{}`.", package.integrity, package.key())))?;
+  let expected = BASE64.decode(expected)
+    .map_err(|err| NpmError(format!("Invalid integrity `{}` of `{}`: {}", package.integrity, package.key(), err)))?;
+  let cached = Path::new(NPM_CACHE).join(algorithm).join(hex(&expected));
+  if let Ok(tarball) = host.read_output(&cached) {
+    if digest(algorithm, &tarball) == expected {
+      return Ok(tarball);
+    }
+  }
+
+  let path = match (package.url.strip_prefix("file://"), mirror) {
+    (Some(path), _) => PathBuf::from(path),
+    (None, Some(mirror)) => {
+      let path = package.url.split_once("://").map_or(package.url.as_str(), |(_, rest)| rest);
+      let path = Path::new(path.split_once('/').map_or("", |(_, path)| path).split(['?', '#']).next().unwrap());
+      if !path.components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(Box::new(NpmError(format!("Invalid tarball URL `{}`.", package.url))));
+      }
+      mirror.join(path)
+    },
+    (None, None) => return Err(Box::new(NpmError(format!(
+      "`{}` is not in the npm cache, pass `mirror` to npm_translate_lock() to fetch it.",
+      package.key(),
+    )))),
+  };
+  let (Some(dir), Some(file)) = (path.parent(), path.file_name()) else {
+    return Err(Box::new(NpmError(format!("Invalid tarball URL `{}`.", package.url))));
+  };
+  let tarball = host.with_source_root(dir)
+    .and_then(|dir| dir.read(Path::new(file)))
+    .map_err(|err| NpmError(format!("Failed to read the tarball of `{}`: {}", package.key(), err)))?;
+
+  let actual = digest(algorithm, &tarball);
+  if actual != expected {
+    return Err(Box::new(NpmError(format!(
+      "Integrity mismatch for `{}`: expected {}-{} but got {}-{}.",
+      package.key(),
+      algorithm,
+      BASE64.encode(&expected),
+      algorithm,
+      BASE64.encode(&actual),
+    ))));
+  }
+  host.write_output(&cached, &tarball)?;
+  Ok(tarball)
+}
+
+fn digest(algorithm: &str, contents: &[u8]) -> Vec<u8> {
+  match algorithm {
+    "sha512" => Sha512::digest(contents).to_vec(),
+    "sha384" => Sha384::digest(contents).to_vec(),
+    "sha256" => Sha256::digest(contents).to_vec(),
+    _ => Sha1::digest(contents).to_vec(),
+  }
+}
+
+fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Returns the directory every file of a package tarball is in, which is
+/// usually `package`.
+fn archive_root(tarball: &[u8]) -> Result<String, Box<dyn Error>> {
+  let mut archive = Archive::new(GzDecoder::new(tarball));
+  for entry in archive.entries()? {
+    let entry = entry?;
+    if let Some(Component::Normal(root)) = entry.path()?.components().next() {
+      return Ok(root.to_str().unwrap_or_default().to_owned());
+    }
+  }
+  Err(Box::new(NpmError("Package tarball is empty.".to_owned())))
+}
+
+/// An error thrown when a lockfile cannot be used.
+#[derive(Debug)]
+pub struct NpmError(pub String);
+
+impl Display for NpmError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for NpmError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::fs;
+  use assertables::assert_contains;
+  use flate2::{write::GzEncoder, Compression};
+  use tar::{Builder, Header};
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+  use super::*;
+
+  fn package(name: &str, version: &str, integrity: &str, deps: &[(&str, &str)]) -> NpmPackage {
+    NpmPackage {
+      name: name.to_owned(),
+      version: version.to_owned(),
+      url: registry_url(name, version),
+      integrity: integrity.to_owned(),
+      deps: deps.iter().map(|(name, key)| (name.to_string(), key.to_string())).collect(),
+    }
+  }
+
+  #[test]
+  fn parse_wires_package_lock_deps_by_install_path() -> Result<(), Box<dyn Error>> {
+    let lock = NpmLock::parse(Path::new("web/package-lock.json"), r#"{
+      "lockfileVersion": 3,
+      "packages": {
+        "": {"name": "web", "dependencies": {"react": "^18"}, "devDependencies": {"@types/react": "^18"}},
+        "node_modules/react": {
+          "version": "18.2.0",
+          "resolved": "https://registry.npmjs.org/react/-/react-18.2.0.tgz",
+          "integrity": "sha512-react",
+          "dependencies": {"loose-envify": "^1.1.0"}
+        },
+        "node_modules/loose-envify": {"version": "1.4.0", "integrity": "sha512-le1", "dependencies": {"js-tokens": "^4"}},
+        "node_modules/js-tokens": {"version": "4.0.0", "integrity": "sha512-jt4"},
+        "node_modules/@types/react": {
+          "version": "18.2.1",
+          "integrity": "sha512-types",
+          "dependencies": {"loose-envify": "^2"},
+          "optionalDependencies": {"missing": "^1"}
+        },
+        "node_modules/@types/react/node_modules/loose-envify": {"version": "2.0.0", "integrity": "sha512-le2"},
+        "packages/lib": {"version": "1.0.0"},
+        "node_modules/lib": {"resolved": "packages/lib", "link": true}
+      }
+    }"#)?;
+
+    assert_eq!(lock.deps, BTreeMap::from([
+      ("@types/react".to_owned(), "@types/react@18.2.1".to_owned()),
+      ("react".to_owned(), "react@18.2.0".to_owned()),
+    ]));
+    assert_eq!(lock.packages.keys().collect::<Vec<_>>(), [
+      "@types/react@18.2.1", "js-tokens@4.0.0", "loose-envify@1.4.0", "loose-envify@2.0.0", "react@18.2.0",
+    ]);
+    assert_eq!(lock.packages["@types/react@18.2.1"], package(
+      "@types/react",
+      "18.2.1",
+      "sha512-types",
+      &[("loose-envify", "loose-envify@2.0.0")],
+    ));
+    assert_eq!(lock.packages["loose-envify@1.4.0"].deps["js-tokens"], "js-tokens@4.0.0");
+    assert_eq!(lock.target_name("react@18.2.0"), "react");
+    assert_eq!(lock.target_name("js-tokens@4.0.0"), "js-tokens@4.0.0");
+
+    assert_eq!(
+      NpmLock::parse(Path::new("package-lock.json"), r#"{"lockfileVersion": 1}"#).err().unwrap().to_string(),
+      "Failed to parse `package-lock.json`: lockfileVersion 1 is not supported, regenerate it with npm 7 or later.",
+    );
+    assert_contains!(
+      NpmLock::parse(Path::new("package-lock.json"), r#"{
+        "lockfileVersion": 2,
+        "packages": {"node_modules/a": {"version": "1.0.0", "integrity": "sha1-a", "dependencies": {"b": "1"}}}
+      }"#).err().unwrap().to_string(),
+      "`node_modules/a` requires `b`, which is not installed by the lockfile.",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn parse_reads_pnpm_locks() -> Result<(), Box<dyn Error>> {
+    let v9 = NpmLock::parse(Path::new("pnpm-lock.yaml"), r#"
+lockfileVersion: '9.0'
+importers:
+  .:
+    dependencies:
+      react-dom:
+        specifier: ^18
+        version: 18.2.0(react@18.2.0)
+      react:
+        specifier: ^18
+        version: 18.2.0
+packages:
+  react@18.2.0:
+    resolution: {integrity: sha512-react}
+  react-dom@18.2.0:
+    resolution: {integrity: sha512-dom, tarball: file:///mirror/react-dom.tgz}
+    peerDependencies:
+      react: ^18
+snapshots:
+  react@18.2.0: {}
+  react-dom@18.2.0(react@18.2.0):
+    dependencies:
+      react: 18.2.0
+"#)?;
+    let v6 = NpmLock::parse(Path::new("pnpm-lock.yaml"), r#"
+lockfileVersion: '6.0'
+dependencies:
+  react-dom:
+    specifier: ^18
+    version: 18.2.0(react@18.2.0)
+  react:
+    specifier: ^18
+    version: 18.2.0
+packages:
+  /react@18.2.0:
+    resolution: {integrity: sha512-react}
+  /react-dom@18.2.0(react@18.2.0):
+    resolution: {integrity: sha512-dom, tarball: file:///mirror/react-dom.tgz}
+    dependencies:
+      react: 18.2.0
+"#)?;
+
+    for lock in [v9, v6] {
+      assert_eq!(lock.deps, BTreeMap::from([
+        ("react".to_owned(), "react@18.2.0".to_owned()),
+        ("react-dom".to_owned(), "react-dom@18.2.0".to_owned()),
+      ]));
+      assert_eq!(lock.packages, BTreeMap::from([
+        ("react@18.2.0".to_owned(), package("react", "18.2.0", "sha512-react", &[])),
+        ("react-dom@18.2.0".to_owned(), NpmPackage {
+          url: "file:///mirror/react-dom.tgz".to_owned(),
+          ..package("react-dom", "18.2.0", "sha512-dom", &[("react", "react@18.2.0")])
+        }),
+      ]));
+    }
+
+    assert_contains!(
+      NpmLock::parse(Path::new("pnpm-lock.yaml"), "lockfileVersion: 5.4").err().unwrap().to_string(),
+      "lockfileVersion `5.4` is not supported",
+    );
+
+    Ok(())
+  }
+
+  /// Returns a package tarball of the given files and its sha512 integrity.
+  fn tarball(files: &[(&str, &str)]) -> Result<(Vec<u8>, String), Box<dyn Error>> {
+    let mut builder = Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    for (path, contents) in files {
+      let mut header = Header::new_gnu();
+      header.set_size(contents.len() as u64);
+      header.set_mode(0o644);
+      header.set_cksum();
+      builder.append_data(&mut header, path, contents.as_bytes())?;
+    }
+    let tarball = builder.into_inner()?.finish()?;
+    let integrity = format!("sha512-{}", BASE64.encode(Sha512::digest(&tarball)));
+    Ok((tarball, integrity))
+  }
+
+  #[test]
+  fn fetch_lays_out_node_modules() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([(Path::new("MODULE.razel"), TestContents::File(""))])?;
+    let output_base = TestDir::from([])?;
+    let (react, react_integrity) = tarball(&[
+      ("package/package.json", "{\"name\": \"react\"}"),
+      ("package/index.js", "require('loose-envify');"),
+      ("package/BUILD", "broken("),
+    ])?;
+    let (types, types_integrity) = tarball(&[("node/index.d.ts", "export {};")])?;
+    fs::create_dir_all(wksp.root.join("mirror/react/-"))?;
+    fs::write(wksp.root.join("mirror/react/-/react-18.2.0.tgz"), &react)?;
+    fs::create_dir_all(wksp.root.join("mirror/@types/node/-"))?;
+    fs::write(wksp.root.join("mirror/@types/node/-/node-20.1.0.tgz"), &types)?;
+
+    let lock = NpmLock {
+      packages: BTreeMap::from([
+        ("react@18.2.0".to_owned(), package("react", "18.2.0", &react_integrity, &[("@types/node", "@types/node@20.1.0")])),
+        ("@types/node@20.1.0".to_owned(), package("@types/node", "20.1.0", &types_integrity, &[])),
+      ]),
+      deps: BTreeMap::from([("react".to_owned(), "react@18.2.0".to_owned())]),
+    };
+    let host = FsHost::with_output_base(&wksp.root, &output_base.root)?;
+    fetch(&host, "npm", &lock, Some(Path::new("mirror")), Path::new("external/npm"))?;
+
+    let npm = output_base.root.join("external/npm");
+    assert_eq!(fs::read_to_string(npm.join("node_modules/react/index.js"))?, "require('loose-envify');");
+    assert!(!npm.join("node_modules/react/BUILD").exists());
+    assert_eq!(
+      fs::read_to_string(npm.join("node_modules/.store/react@18.2.0/node_modules/@types/node/index.d.ts"))?,
+      "export {};",
+    );
+    assert_eq!(
+      fs::read_link(npm.join("node_modules/.store/react@18.2.0/node_modules/@types/node"))?,
+      Path::new("../../../@types+node@20.1.0/node_modules/@types/node"),
+    );
+    let build = fs::read_to_string(npm.join("BUILD.razel"))?;
+    assert_contains!(build, "name = \"react\",");
+    assert_contains!(build, "deps = [\":@types/node@20.1.0\"],");
+    assert_contains!(build, "directory = \"external/npm/node_modules/.store/react@18.2.0/node_modules/react\",");
+
+    // Tarballs are reused from the cache.
+    fs::remove_dir_all(wksp.root.join("mirror"))?;
+    fetch(&host, "npm", &lock, None, Path::new("external/other"))?;
+
+    let other_integrity = format!("sha512-{}", BASE64.encode(Sha512::digest(b"other")));
+    let mut lock = lock;
+    lock.packages.get_mut("react@18.2.0").unwrap().integrity = other_integrity.clone();
+    assert_eq!(
+      fetch(&host, "npm", &lock, None, Path::new("external/npm")).err().unwrap().to_string(),
+      "`react@18.2.0` is not in the npm cache, pass `mirror` to npm_translate_lock() to fetch it.",
+    );
+    fs::create_dir_all(wksp.root.join("mirror/react/-"))?;
+    fs::write(wksp.root.join("mirror/react/-/react-18.2.0.tgz"), &react)?;
+    assert_eq!(
+      fetch(&host, "npm", &lock, Some(Path::new("mirror")), Path::new("external/npm")).err().unwrap().to_string(),
+      format!("Integrity mismatch for `react@18.2.0`: expected {} but got {}.", other_integrity, react_integrity),
+    );
+
+    Ok(())
+  }
+}
@@ -0,0 +1,915 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use crate::analysis::config::{Configuration, OPTIONS};
+use crate::analysis::rule::{AttrKind, RuleDef};
+use crate::analysis::select::{select_builtin, Select};
+use crate::bzl::BzlLoader;
+use crate::glob::{glob, GlobArgs};
+use crate::host::host::Host;
+use crate::label::{package_path, Label};
+use crate::profile::Profiler;
+use crate::repository::Repositories;
+use crate::starlark::error::{EvalError, Location};
+use crate::starlark::eval::{Evaluator, ModuleEnv};
+use crate::starlark::parser::parse;
+use crate::starlark::value::{Args, Value};
+use crate::target_pattern::TargetPattern;
+
+/// The file names which mark a directory as a package, in order of precedence.
+pub const BUILD_FILE_NAMES: [&str; 2] = ["BUILD.razel", "BUILD"];
+
+/// The builtin rules available in every BUILD file without a `load()`, with
+/// the files of the builtin repository defining them.
+pub const BUILTIN_RULES: [(&str, &str); 3] = [
+  ("ts_library", "@razel//js:defs.bzl"),
+  ("js_library", "@razel//js:defs.bzl"),
+  ("js_bundle", "@razel//js:defs.bzl"),
+];
+
+/// The targets defined by evaluating a package's BUILD file.
+#[allow(dead_code)] // Only the targets are used by any commands yet.
+pub struct Package {
+  /// The name of the repository containing the package, empty for the main
+  /// repository.
+  pub repo: String,
+
+  /// The repository-relative path of the package.
+  pub name: String,
+
+  /// The repository-relative path of the BUILD file.
+  pub build_file: PathBuf,
+
+  pub targets: BTreeMap<String, Rc<Target>>,
+  pub package_groups: BTreeMap<String, Rc<PackageGroup>>,
+  pub config_settings: BTreeMap<String, Rc<ConfigSetting>>,
+  pub build_settings: BTreeMap<String, Rc<BuildSetting>>,
+  pub constraint_settings: BTreeMap<String, Rc<ConstraintSetting>>,
+  pub constraint_values: BTreeMap<String, Rc<ConstraintValue>>,
+  pub platforms: BTreeMap<String, Rc<Platform>>,
+  pub toolchain_types: BTreeSet<String>,
+  pub toolchains: BTreeMap<String, Rc<Toolchain>>,
+
+  /// Maps the names of predeclared output files to the targets generating them.
+  pub outputs: BTreeMap<String, String>,
+
+  /// Paths whose contents affected `glob()` results.
+  pub dependencies: Vec<PathBuf>,
+}
+
+/// A target instantiated by calling a rule in a BUILD file.
+pub struct Target {
+  pub label: Label,
+  pub rule: Rc<RuleDef>,
+
+  /// Explicitly set attributes, not including `name` and `visibility`.
+  pub attrs: BTreeMap<String, Value>,
+
+  /// Who may depend on the target, from its `visibility` attribute or the
+  /// package's default visibility.
+  pub visibility: Vec<Label>,
+
+  /// Where the rule was called.
+  pub location: Option<Location>,
+}
+
+/// A named set of packages, from a `package_group()` call, which targets can
+/// be made visible to.
+pub struct PackageGroup {
+  /// Package specs such as `//foo` or `//foo/...`.
+  pub packages: Vec<TargetPattern>,
+
+  /// Other package groups whose packages are part of this one.
+  pub includes: Vec<Label>,
+}
+
+/// A named condition on the configuration, from a `config_setting()` call,
+/// which `select()` branches are keyed by.
+#[derive(Debug, PartialEq)]
+pub struct ConfigSetting {
+  /// Options which must have the given values, such as `compilation_mode`.
+  pub values: BTreeMap<String, String>,
+
+  /// `--define` values which must be set.
+  pub define_values: BTreeMap<String, String>,
+
+  /// Build settings which must have the given values, as written.
+  pub flag_values: BTreeMap<Label, String>,
+
+  /// Constraint values the target platform must have.
+  pub constraint_values: BTreeSet<Label>,
+}
+
+impl ConfigSetting {
+  /// Returns whether the configuration matches `values` and `define_values`.
+  /// Matching `flag_values` and `constraint_values` requires looking up their
+  /// targets.
+  pub fn matches(&self, config: &Configuration) -> bool {
+    self.values.iter().all(|(name, value)| config.option(name) == Some(value.as_str())) &&
+      self.define_values.iter().all(|(name, value)| config.defines.get(name) == Some(value))
+  }
+
+  /// Returns whether this setting requires everything `other` does, so it
+  /// takes precedence when both match.
+  pub fn refines(&self, other: &ConfigSetting) -> bool {
+    other.values.iter().all(|(name, value)| self.values.get(name) == Some(value)) &&
+      other.define_values.iter().all(|(name, value)| self.define_values.get(name) == Some(value)) &&
+      other.flag_values.iter().all(|(label, value)| self.flag_values.get(label) == Some(value)) &&
+      other.constraint_values.is_subset(&self.constraint_values)
+  }
+}
+
+/// A user-defined option, from a `string_flag()` or `bool_flag()` call,
+/// which is set on the command line as `--//pkg:name=value`.
+#[derive(Debug, PartialEq)]
+pub struct BuildSetting {
+  pub kind: BuildSettingKind,
+
+  /// The value used unless set on the command line, in canonical form.
+  pub default: String,
+
+  /// The allowed values of a string flag, if restricted.
+  pub values: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BuildSettingKind {
+  String,
+  Bool,
+}
+
+impl BuildSetting {
+  /// Parses a value as written on the command line or in a `config_setting`
+  /// into its canonical form, which configurations store.
+  pub fn parse(&self, value: &str) -> Result<String, String> {
+    match self.kind {
+      BuildSettingKind::Bool => match value {
+        "true" | "1" | "yes" => Ok("true".to_owned()),
+        "false" | "0" | "no" => Ok("false".to_owned()),
+        _ => Err(format!("`{}` is not a boolean.", value)),
+      },
+      BuildSettingKind::String => {
+        if !self.values.is_empty() && !self.values.iter().any(|allowed| allowed == value) {
+          return Err(format!("`{}` is not one of: {}.", value, self.values.join(", ")));
+        }
+        Ok(value.to_owned())
+      },
+    }
+  }
+
+  /// Returns a canonical value as seen by rule implementations.
+  pub fn value(&self, canonical: &str) -> Value {
+    match self.kind {
+      BuildSettingKind::Bool => Value::Bool(canonical == "true"),
+      BuildSettingKind::String => Value::str(canonical),
+    }
+  }
+}
+
+/// A property platforms differ in, such as their OS, from a
+/// `constraint_setting()` call.
+#[derive(Debug, PartialEq)]
+pub struct ConstraintSetting {
+  /// The value of platforms which do not have any value of this setting.
+  pub default_value: Option<Label>,
+}
+
+/// One possible value of a constraint setting, from a `constraint_value()`
+/// call.
+#[derive(Debug, PartialEq)]
+pub struct ConstraintValue {
+  pub setting: Label,
+}
+
+/// A platform described by its constraint values, from a `platform()` call.
+/// Builds run on an execution platform and produce outputs for a target
+/// platform.
+#[derive(Debug, PartialEq)]
+pub struct Platform {
+  pub constraint_values: Vec<Label>,
+}
+
+/// An implementation of a toolchain type with the platforms it supports,
+/// from a `toolchain()` call.
+#[derive(Debug, PartialEq)]
+pub struct Toolchain {
+  pub toolchain_type: Label,
+
+  /// The target providing `ToolchainInfo`.
+  pub toolchain: Label,
+
+  /// Constraint values the execution platform must have.
+  pub exec_compatible_with: Vec<Label>,
+
+  /// Constraint values the target platform must have.
+  pub target_compatible_with: Vec<Label>,
+}
+
+/// The state of a BUILD file being evaluated, used by its builtins.
+pub struct PackageContext {
+  repo: String,
+  name: String,
+  host: Rc<dyn Host>,
+  default_visibility: RefCell<Option<Vec<Label>>>,
+  targets: RefCell<BTreeMap<String, Rc<Target>>>,
+  package_groups: RefCell<BTreeMap<String, Rc<PackageGroup>>>,
+  config_settings: RefCell<BTreeMap<String, Rc<ConfigSetting>>>,
+  build_settings: RefCell<BTreeMap<String, Rc<BuildSetting>>>,
+  constraint_settings: RefCell<BTreeMap<String, Rc<ConstraintSetting>>>,
+  constraint_values: RefCell<BTreeMap<String, Rc<ConstraintValue>>>,
+  platforms: RefCell<BTreeMap<String, Rc<Platform>>>,
+  toolchain_types: RefCell<BTreeSet<String>>,
+  toolchains: RefCell<BTreeMap<String, Rc<Toolchain>>>,
+  outputs: RefCell<BTreeMap<String, String>>,
+  dependencies: RefCell<Vec<PathBuf>>,
+}
+
+impl PackageContext {
+  /// Adds a target of the given rule, called with `args`.
+  pub fn add_target(&self, rule: &Rc<RuleDef>, args: Args, location: Option<Location>) ->
+      Result<(), EvalError> {
+    if rule.name.borrow().is_none() {
+      return Err(EvalError::msg(
+        "Rules must be assigned to a global variable in a .bzl file before use.",
+      ));
+    }
+    if !args.positional.is_empty() {
+      return Err(EvalError::msg(format!("{}() only accepts keyword arguments.", rule.name())));
+    }
+
+    let mut name = None;
+    let mut visibility = None;
+    let mut attrs = BTreeMap::new();
+    for (attr, value) in args.named {
+      if attr == "name" {
+        name = Some(value.expect_str("name")?.to_owned());
+      } else if attr == "visibility" {
+        visibility = Some(self.labels(&value, "visibility")?);
+      } else if let Some(spec) = rule.attrs.get(&attr) {
+        if matches!(spec.kind, AttrKind::Output | AttrKind::OutputList) && value.downcast::<Select>().is_some() {
+          return Err(EvalError::msg(format!("{}() attribute `{}` is not configurable.", rule.name(), attr)));
+        }
+        value.freeze();
+        attrs.insert(attr, value);
+      } else {
+        return Err(EvalError::msg(format!("{}() has no attribute `{}`.", rule.name(), attr)));
+      }
+    }
+    let name = name.ok_or_else(|| EvalError::msg(format!(
+      "{}() missing required argument `name`.",
+      rule.name(),
+    )))?;
+    let label = Label::parse_in(&format!(":{}", name), &self.repo, &self.name).map_err(|err| EvalError::msg(err.0))?;
+    for (attr, spec) in &rule.attrs {
+      if spec.mandatory && !attrs.contains_key(attr) {
+        return Err(EvalError::msg(format!("{}: missing mandatory attribute `{}`.", label, attr)));
+      }
+    }
+
+    // Predeclared outputs are part of the package so they can be referenced
+    // by label before analysis.
+    let mut outputs = Vec::new();
+    for (attr, value) in &attrs {
+      let values = match rule.attrs[attr].kind {
+        AttrKind::Output if *value == Value::None => Vec::new(),
+        AttrKind::Output => vec![value.clone()],
+        AttrKind::OutputList => value.expect_list(attr)?,
+        _ => continue,
+      };
+      for value in values {
+        let output = Label::parse_in(value.expect_str(attr)?, &self.repo, &self.name).map_err(|err| EvalError::msg(err.0))?;
+        if output.repo != self.repo || output.package != self.name {
+          return Err(EvalError::msg(format!(
+            "{}: output `{}` must be in the target's own package.",
+            label,
+            output,
+          )));
+        }
+        outputs.push(output.name);
+      }
+    }
+
+    for name in outputs.iter().chain([&label.name]) {
+      self.check_undefined(name)?;
+    }
+    let mut declared = self.outputs.borrow_mut();
+    for output in outputs {
+      declared.insert(output, label.name.clone());
+    }
+    let visibility = visibility.unwrap_or_else(|| self.default_visibility());
+    self.targets.borrow_mut().insert(label.name.clone(), Rc::new(Target {
+      label,
+      rule: rule.clone(),
+      attrs,
+      visibility,
+      location,
+    }));
+
+    Ok(())
+  }
+
+  /// Fails if anything in the package is named `name`.
+  fn check_undefined(&self, name: &str) -> Result<(), EvalError> {
+    if self.names().any(|defined| defined == name) {
+      return Err(EvalError::msg(format!(
+        "`{}` is already defined in package `{}`.",
+        name,
+        package_id(&self.repo, &self.name),
+      )));
+    }
+    Ok(())
+  }
+
+  /// The names of everything defined in the package so far.
+  fn names(&self) -> impl Iterator<Item = String> {
+    let names: Vec<String> = [
+      self.targets.borrow().keys().cloned().collect::<Vec<_>>(),
+      self.outputs.borrow().keys().cloned().collect(),
+      self.package_groups.borrow().keys().cloned().collect(),
+      self.config_settings.borrow().keys().cloned().collect(),
+      self.build_settings.borrow().keys().cloned().collect(),
+      self.constraint_settings.borrow().keys().cloned().collect(),
+      self.constraint_values.borrow().keys().cloned().collect(),
+      self.platforms.borrow().keys().cloned().collect(),
+      self.toolchain_types.borrow().iter().cloned().collect(),
+      self.toolchains.borrow().keys().cloned().collect(),
+    ].concat();
+    names.into_iter()
+  }
+
+  /// Targets are private to their package unless `package()` says otherwise.
+  fn default_visibility(&self) -> Vec<Label> {
+    self.default_visibility.borrow().clone().unwrap_or_else(|| vec![private()])
+  }
+
+  /// Parses a list of labels relative to this package.
+  fn labels(&self, value: &Value, what: &str) -> Result<Vec<Label>, EvalError> {
+    value.expect_str_list(what)?.iter()
+      .map(|label| Label::parse_in(label, &self.repo, &self.name).map_err(|err| EvalError::msg(err.0)))
+      .collect()
+  }
+
+  fn package(&self, args: Args) -> Result<Value, EvalError> {
+    if !args.positional.is_empty() {
+      return Err(EvalError::msg("package() only accepts keyword arguments."));
+    }
+    if self.default_visibility.borrow().is_some() {
+      return Err(EvalError::msg("package() can only be called once."));
+    }
+    if self.names().next().is_some() {
+      return Err(EvalError::msg("package() must be called before any targets are defined."));
+    }
+
+    let mut default_visibility = vec![private()];
+    for (name, value) in args.named {
+      match name.as_str() {
+        "default_visibility" => default_visibility = self.labels(&value, "default_visibility")?,
+        _ => return Err(EvalError::msg(format!("package() has no argument `{}`.", name))),
+      }
+    }
+    *self.default_visibility.borrow_mut() = Some(default_visibility);
+
+    Ok(Value::None)
+  }
+
+  fn package_group(&self, args: Args) -> Result<Value, EvalError> {
+    let [name, packages, includes] = args.bind("package_group", &["name"], &["packages", "includes"])?
+      .try_into()
+      .unwrap();
+    let name = name.unwrap();
+    let name = name.expect_str("name")?;
+    Label::parse_in(&format!(":{}", name), &self.repo, &self.name).map_err(|err| EvalError::msg(err.0))?;
+
+    let packages = match packages {
+      Some(packages) => packages.expect_str_list("packages")?.iter()
+        .map(|spec| package_spec(spec, &self.repo))
+        .collect::<Result<Vec<_>, _>>()?,
+      None => Vec::new(),
+    };
+    let includes = match includes {
+      Some(includes) => self.labels(&includes, "includes")?,
+      None => Vec::new(),
+    };
+
+    self.check_undefined(name)?;
+    self.package_groups.borrow_mut().insert(name.to_owned(), Rc::new(PackageGroup { packages, includes }));
+
+    Ok(Value::None)
+  }
+
+  fn config_setting(&self, args: Args) -> Result<Value, EvalError> {
+    let [name, values, define_values, flag_values, constraint_values] = args.bind(
+      "config_setting",
+      &["name"],
+      &["values", "define_values", "flag_values", "constraint_values"],
+    )?
+      .try_into()
+      .unwrap();
+    let name = name.unwrap();
+    let name = name.expect_str("name")?;
+    Label::parse_in(&format!(":{}", name), &self.repo, &self.name).map_err(|err| EvalError::msg(err.0))?;
+
+    let string_dict = |value: Option<Value>, what: &str| match value {
+      Some(value) => value.expect_dict(what)?.iter()
+        .map(|(key, value)| Ok((key.expect_str(what)?.to_owned(), value.expect_str(what)?.to_owned())))
+        .collect::<Result<BTreeMap<_, _>, EvalError>>(),
+      None => Ok(BTreeMap::new()),
+    };
+    let values = string_dict(values, "values")?;
+    let define_values = string_dict(define_values, "define_values")?;
+    let flag_values = string_dict(flag_values, "flag_values")?.into_iter()
+      .map(|(label, value)| Ok((Label::parse_in(&label, &self.repo, &self.name).map_err(|err| EvalError::msg(err.0))?, value)))
+      .collect::<Result<BTreeMap<_, _>, EvalError>>()?;
+    let constraint_values = match constraint_values {
+      Some(constraint_values) => self.labels(&constraint_values, "constraint_values")?.into_iter().collect(),
+      None => BTreeSet::new(),
+    };
+    if let Some(option) = values.keys().find(|option| !OPTIONS.contains(&option.as_str())) {
+      return Err(EvalError::msg(format!("config_setting() has unknown option `{}` in `values`.", option)));
+    }
+    if values.is_empty() && define_values.is_empty() && flag_values.is_empty() && constraint_values.is_empty() {
+      return Err(EvalError::msg(
+        "config_setting() requires `values`, `define_values`, `flag_values` or `constraint_values`.",
+      ));
+    }
+
+    self.check_undefined(name)?;
+    let setting = ConfigSetting { values, define_values, flag_values, constraint_values };
+    self.config_settings.borrow_mut().insert(name.to_owned(), Rc::new(setting));
+
+    Ok(Value::None)
+  }
+
+  fn build_setting(&self, kind: BuildSettingKind, function: &str, args: Args) -> Result<Value, EvalError> {
+    let mut optional = vec!["visibility"];
+    if kind == BuildSettingKind::String {
+      optional.push("values");
+    }
+    let bound = args.bind(function, &["name", "build_setting_default"], &optional)?;
+    let name = bound[0].clone().unwrap();
+    let name = name.expect_str("name")?;
+    Label::parse_in(&format!(":{}", name), &self.repo, &self.name).map_err(|err| EvalError::msg(err.0))?;
+
+    let default = match (kind, bound[1].as_ref().unwrap()) {
+      (BuildSettingKind::Bool, default) => default.expect_bool("build_setting_default")?.to_string(),
+      (BuildSettingKind::String, default) => default.expect_str("build_setting_default")?.to_owned(),
+    };
+    let values = match bound.get(3) {
+      Some(Some(values)) => values.expect_str_list("values")?,
+      _ => Vec::new(),
+    };
+    let setting = BuildSetting { kind, default, values };
+    setting.parse(&setting.default).map_err(|err| EvalError::msg(format!("Invalid build_setting_default: {}", err)))?;
+
+    self.check_undefined(name)?;
+    self.build_settings.borrow_mut().insert(name.to_owned(), Rc::new(setting));
+
+    Ok(Value::None)
+  }
+
+  /// Binds the arguments of a function defining a platform or toolchain
+  /// target, returning its name and the other arguments.
+  fn bind_named(&self, function: &str, required: &[&str], optional: &[&str], args: Args) ->
+      Result<(String, Vec<Option<Value>>), EvalError> {
+    let mut optional = optional.to_vec();
+    optional.push("visibility");
+    let mut bound = args.bind(function, &[&["name"], required].concat(), &optional)?;
+    bound.pop();
+    let name = bound.remove(0).unwrap().expect_str("name")?.to_owned();
+    Label::parse_in(&format!(":{}", name), &self.repo, &self.name).map_err(|err| EvalError::msg(err.0))?;
+    self.check_undefined(&name)?;
+    Ok((name, bound))
+  }
+
+  fn optional_labels(&self, value: Option<Value>, what: &str) -> Result<Vec<Label>, EvalError> {
+    value.map_or(Ok(Vec::new()), |value| self.labels(&value, what))
+  }
+
+  fn label(&self, value: &Value, what: &str) -> Result<Label, EvalError> {
+    Label::parse_in(value.expect_str(what)?, &self.repo, &self.name).map_err(|err| EvalError::msg(err.0))
+  }
+
+  fn constraint_setting(&self, args: Args) -> Result<Value, EvalError> {
+    let (name, bound) = self.bind_named("constraint_setting", &[], &["default_constraint_value"], args)?;
+    let default_value = match &bound[0] {
+      Some(Value::None) | None => None,
+      Some(value) => Some(self.label(value, "default_constraint_value")?),
+    };
+    self.constraint_settings.borrow_mut().insert(name, Rc::new(ConstraintSetting { default_value }));
+    Ok(Value::None)
+  }
+
+  fn constraint_value(&self, args: Args) -> Result<Value, EvalError> {
+    let (name, bound) = self.bind_named("constraint_value", &["constraint_setting"], &[], args)?;
+    let setting = self.label(bound[0].as_ref().unwrap(), "constraint_setting")?;
+    self.constraint_values.borrow_mut().insert(name, Rc::new(ConstraintValue { setting }));
+    Ok(Value::None)
+  }
+
+  fn platform(&self, args: Args) -> Result<Value, EvalError> {
+    let (name, mut bound) = self.bind_named("platform", &[], &["constraint_values"], args)?;
+    let constraint_values = self.optional_labels(bound.remove(0), "constraint_values")?;
+    self.platforms.borrow_mut().insert(name, Rc::new(Platform { constraint_values }));
+    Ok(Value::None)
+  }
+
+  fn toolchain_type(&self, args: Args) -> Result<Value, EvalError> {
+    let (name, _) = self.bind_named("toolchain_type", &[], &[], args)?;
+    self.toolchain_types.borrow_mut().insert(name);
+    Ok(Value::None)
+  }
+
+  fn toolchain(&self, args: Args) -> Result<Value, EvalError> {
+    let (name, mut bound) = self.bind_named(
+      "toolchain",
+      &["toolchain_type", "toolchain"],
+      &["exec_compatible_with", "target_compatible_with"],
+      args,
+    )?;
+    let toolchain = Toolchain {
+      toolchain_type: self.label(bound[0].as_ref().unwrap(), "toolchain_type")?,
+      toolchain: self.label(bound[1].as_ref().unwrap(), "toolchain")?,
+      target_compatible_with: self.optional_labels(bound.pop().unwrap(), "target_compatible_with")?,
+      exec_compatible_with: self.optional_labels(bound.pop().unwrap(), "exec_compatible_with")?,
+    };
+    self.toolchains.borrow_mut().insert(name, Rc::new(toolchain));
+    Ok(Value::None)
+  }
+
+  fn glob(&self, args: Args) -> Result<Value, EvalError> {
+    let [include, exclude, exclude_directories, allow_empty] = args.bind(
+      "glob",
+      &["include"],
+      &["exclude", "exclude_directories", "allow_empty"],
+    )?.try_into().unwrap();
+
+    let args = GlobArgs {
+      include: include.unwrap().expect_str_list("include")?,
+      exclude: exclude.map_or(Ok(Vec::new()), |exclude| exclude.expect_str_list("exclude"))?,
+      exclude_directories: exclude_directories.is_none_or(|value| value.truthy()),
+      allow_empty: allow_empty.is_none_or(|value| value.truthy()),
+    };
+    let result = glob(self.host.as_ref(), &self.name, &args)?;
+    self.dependencies.borrow_mut().extend(result.dependencies);
+
+    Ok(Value::list(result.labels.iter().map(|label| Value::str(&label.name)).collect()))
+  }
+}
+
+/// Loads packages by evaluating their BUILD files, at most once each.
+pub struct PackageLoader {
+  repositories: Rc<Repositories>,
+  bzl: Rc<BzlLoader>,
+  packages: RefCell<HashMap<(String, String), Rc<Package>>>,
+
+  profiler: Option<Arc<Profiler>>,
+}
+
+impl PackageLoader {
+  /// Returns a loader for packages of the main repository read from `host`.
+  #[allow(dead_code)] // Not used by any commands yet.
+  pub fn new(host: Rc<dyn Host>) -> PackageLoader {
+    PackageLoader::with_repositories(Repositories::new(host))
+  }
+
+  /// Returns a loader for packages of the main and the external repositories.
+  pub fn with_repositories(repositories: Repositories) -> PackageLoader {
+    let repositories = Rc::new(repositories);
+    PackageLoader {
+      bzl: BzlLoader::new(repositories.clone()),
+      repositories,
+      packages: RefCell::new(HashMap::new()),
+      profiler: None,
+    }
+  }
+
+  /// Records the time spent loading each package with the profiler, if any.
+  pub fn with_profiler(mut self, profiler: Option<Arc<Profiler>>) -> PackageLoader {
+    self.profiler = profiler;
+    self
+  }
+
+  /// The host of the main repository.
+  pub fn host(&self) -> &Rc<dyn Host> {
+    self.repositories.main()
+  }
+
+  pub fn repositories(&self) -> &Repositories {
+    &self.repositories
+  }
+
+  /// Returns the workspace-relative paths of the main repository's BUILD and
+  /// .bzl files evaluated so far and of the directories their globs listed.
+  pub fn loaded_files(&self) -> Vec<PathBuf> {
+    let packages = self.packages.borrow();
+    let mut files: Vec<_> = packages.values()
+      .filter(|package| package.repo.is_empty())
+      .flat_map(|package| {
+        [package.build_file.clone()].into_iter()
+          .chain(package.dependencies.iter().cloned())
+      })
+      .chain(self.bzl.loaded().into_iter()
+        .filter(|label| label.repo.is_empty())
+        .map(|label| Path::new(&label.package).join(&label.name)))
+      .collect();
+    files.sort();
+    files.dedup();
+    files
+  }
+
+  /// Returns the package containing the target with the given label.
+  pub fn package_of(&self, label: &Label) -> Result<Rc<Package>, Box<dyn Error>> {
+    self.load(&label.repo, &label.package)
+  }
+
+  /// Returns the build setting with the given label.
+  pub fn build_setting(&self, label: &Label) -> Result<Rc<BuildSetting>, Box<dyn Error>> {
+    self.package_of(label)?.build_settings.get(&label.name).cloned()
+      .ok_or_else(|| Box::new(PackageError(format!("`{}` is not a build setting.", label))).into())
+  }
+
+  pub fn constraint_setting(&self, label: &Label) -> Result<Rc<ConstraintSetting>, Box<dyn Error>> {
+    self.declaration(label, |package| package.constraint_settings.get(&label.name).cloned(), "constraint_setting")
+  }
+
+  pub fn constraint_value(&self, label: &Label) -> Result<Rc<ConstraintValue>, Box<dyn Error>> {
+    self.declaration(label, |package| package.constraint_values.get(&label.name).cloned(), "constraint_value")
+  }
+
+  pub fn platform(&self, label: &Label) -> Result<Rc<Platform>, Box<dyn Error>> {
+    self.declaration(label, |package| package.platforms.get(&label.name).cloned(), "platform")
+  }
+
+  pub fn toolchain(&self, label: &Label) -> Result<Rc<Toolchain>, Box<dyn Error>> {
+    self.declaration(label, |package| package.toolchains.get(&label.name).cloned(), "toolchain")
+  }
+
+  /// Fails unless the label refers to a `toolchain_type`.
+  pub fn check_toolchain_type(&self, label: &Label) -> Result<(), Box<dyn Error>> {
+    self.declaration(label, |package| package.toolchain_types.get(&label.name).map(|_| ()), "toolchain_type")
+  }
+
+  fn declaration<T>(&self, label: &Label, get: impl Fn(&Package) -> Option<T>, kind: &str) ->
+      Result<T, Box<dyn Error>> {
+    get(&*self.package_of(label)?)
+      .ok_or_else(|| Box::new(PackageError(format!("`{}` is not a {}.", label, kind))).into())
+  }
+
+  /// Returns the package at the given path of the repository `repo`, which is
+  /// empty for the main repository.
+  pub fn load(&self, repo: &str, name: &str) -> Result<Rc<Package>, Box<dyn Error>> {
+    let key = (repo.to_owned(), name.to_owned());
+    if let Some(package) = self.packages.borrow().get(&key) {
+      return Ok(package.clone());
+    }
+
+    let _span = self.profiler.as_ref()
+      .map(|profiler| profiler.span("package", package_id(repo, name)));
+    let host = self.repositories.get(repo)?;
+    let (build_file, source) = BUILD_FILE_NAMES.iter()
+      .map(|file| Path::new(name).join(file))
+      .find_map(|path| host.read_to_string(&path).ok().map(|source| (path, source)))
+      .ok_or_else(|| PackageError(format!("No such package `{}`: BUILD file not found.", package_id(repo, name))))?;
+
+    let context = Rc::new(PackageContext {
+      repo: repo.to_owned(),
+      name: name.to_owned(),
+      host: host.clone(),
+      default_visibility: RefCell::new(None),
+      targets: RefCell::new(BTreeMap::new()),
+      package_groups: RefCell::new(BTreeMap::new()),
+      config_settings: RefCell::new(BTreeMap::new()),
+      build_settings: RefCell::new(BTreeMap::new()),
+      constraint_settings: RefCell::new(BTreeMap::new()),
+      constraint_values: RefCell::new(BTreeMap::new()),
+      platforms: RefCell::new(BTreeMap::new()),
+      toolchain_types: RefCell::new(BTreeSet::new()),
+      toolchains: RefCell::new(BTreeMap::new()),
+      outputs: RefCell::new(BTreeMap::new()),
+      dependencies: RefCell::new(Vec::new()),
+    });
+    let glob_context = context.clone();
+    let package_context = context.clone();
+    let group_context = context.clone();
+    let setting_context = context.clone();
+    let string_flag_context = context.clone();
+    let bool_flag_context = context.clone();
+    let constraint_setting_context = context.clone();
+    let constraint_value_context = context.clone();
+    let platform_context = context.clone();
+    let toolchain_type_context = context.clone();
+    let toolchain_context = context.clone();
+    let package_name = name.to_owned();
+    let mut predeclared = HashMap::from([
+      ("glob".to_owned(), Value::builtin("glob", move |_, args| glob_context.glob(args))),
+      ("package".to_owned(), Value::builtin("package", move |_, args| package_context.package(args))),
+      ("package_group".to_owned(), Value::builtin("package_group", move |_, args| {
+        group_context.package_group(args)
+      })),
+      ("config_setting".to_owned(), Value::builtin("config_setting", move |_, args| {
+        setting_context.config_setting(args)
+      })),
+      ("string_flag".to_owned(), Value::builtin("string_flag", move |_, args| {
+        string_flag_context.build_setting(BuildSettingKind::String, "string_flag", args)
+      })),
+      ("bool_flag".to_owned(), Value::builtin("bool_flag", move |_, args| {
+        bool_flag_context.build_setting(BuildSettingKind::Bool, "bool_flag", args)
+      })),
+      ("constraint_setting".to_owned(), Value::builtin("constraint_setting", move |_, args| {
+        constraint_setting_context.constraint_setting(args)
+      })),
+      ("constraint_value".to_owned(), Value::builtin("constraint_value", move |_, args| {
+        constraint_value_context.constraint_value(args)
+      })),
+      ("platform".to_owned(), Value::builtin("platform", move |_, args| platform_context.platform(args))),
+      ("toolchain_type".to_owned(), Value::builtin("toolchain_type", move |_, args| {
+        toolchain_type_context.toolchain_type(args)
+      })),
+      ("toolchain".to_owned(), Value::builtin("toolchain", move |_, args| toolchain_context.toolchain(args))),
+      ("select".to_owned(), select_builtin()),
+      ("package_name".to_owned(), Value::builtin("package_name", move |_, args| {
+        args.none("package_name")?;
+        Ok(Value::str(&package_name))
+      })),
+    ]);
+
+    for (rule, file) in BUILTIN_RULES {
+      let bzl = self.bzl.clone();
+      predeclared.insert(rule.to_owned(), Value::builtin(rule, move |eval, args| {
+        let label = Label::parse(file, "").unwrap();
+        let rule = bzl.load(&label)?.globals.borrow().get(rule).cloned()
+          .ok_or_else(|| EvalError::msg(format!("`{}` does not define `{}`.", label, rule)))?;
+        eval.call(&rule, args)
+      }));
+    }
+
+    let file_path = Path::new(&package_path(repo, name)).join(build_file.file_name().unwrap());
+    let file = file_path.to_str().unwrap();
+    let module = parse(file, &source)?;
+    let env = ModuleEnv::new(file, Rc::new(predeclared));
+    let mut eval = Evaluator::new();
+    eval.set_loader(self.bzl.for_package(repo, name));
+    eval.set_context(context.clone());
+    eval.eval_module(&module, &env)?;
+
+    let package = Rc::new(Package {
+      repo: repo.to_owned(),
+      name: name.to_owned(),
+      build_file,
+      targets: context.targets.take(),
+      package_groups: context.package_groups.take(),
+      config_settings: context.config_settings.take(),
+      build_settings: context.build_settings.take(),
+      constraint_settings: context.constraint_settings.take(),
+      constraint_values: context.constraint_values.take(),
+      platforms: context.platforms.take(),
+      toolchain_types: context.toolchain_types.take(),
+      toolchains: context.toolchains.take(),
+      outputs: context.outputs.take(),
+      dependencies: context.dependencies.take(),
+    });
+    self.packages.borrow_mut().insert(key, package.clone());
+
+    Ok(package)
+  }
+}
+
+/// The visibility of targets only visible within their own package.
+pub fn private() -> Label {
+  Label { repo: String::new(), package: "visibility".to_owned(), name: "private".to_owned() }
+}
+
+/// The visibility of targets visible from every package.
+pub fn public() -> Label {
+  Label { repo: String::new(), package: "visibility".to_owned(), name: "public".to_owned() }
+}
+
+/// Parses a package spec of a package group, either `//foo` for a single
+/// package or `//foo/...` for a package and all its descendants.
+fn package_spec(spec: &str, repo: &str) -> Result<TargetPattern, EvalError> {
+  let pattern = if spec.ends_with("...") { spec.to_owned() } else { format!("{}:all", spec) };
+  let pattern = TargetPattern::parse(&pattern)
+    .map_err(|err| EvalError::msg(format!("Invalid package spec `{}`: {}", spec, err.0)))?;
+  if spec.starts_with('@') {
+    Ok(pattern)
+  } else {
+    Ok(TargetPattern { repo: repo.to_owned(), ..pattern })
+  }
+}
+
+/// Formats a package as written in labels, such as `@repo//path/to/pkg`.
+pub fn package_id(repo: &str, name: &str) -> String {
+  if repo.is_empty() { format!("//{}", name) } else { format!("@{}//{}", repo, name) }
+}
+
+/// An error thrown when a package cannot be loaded.
+#[derive(Debug)]
+pub struct PackageError(pub String);
+
+impl Display for PackageError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for PackageError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use assertables::assert_contains;
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+  use super::*;
+
+  const DEFS: &str = r#"
+def _impl(ctx):
+    pass
+
+gen = rule(implementation = _impl, attrs = {
+    "srcs": attr.label_list(allow_files = True),
+    "out": attr.output(),
+    "required": attr.string(mandatory = True),
+})
+"#;
+
+  #[test]
+  fn load_evaluates_build_file() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("pkg/BUILD.razel"), TestContents::File(r#"
+load("//:defs.bzl", "gen")
+
+gen(name = "a", srcs = glob(["*.txt"]), out = "a.out", required = package_name())
+"#)),
+      (Path::new("pkg/BUILD"), TestContents::File("fail(\"Lower precedence.\")")),
+      (Path::new("pkg/file.txt"), TestContents::File("")),
+    ])?;
+    let loader = PackageLoader::new(Rc::new(FsHost::from(&dir.root)?));
+
+    let package = loader.load("", "pkg")?;
+    assert_eq!(package.build_file, PathBuf::from("pkg/BUILD.razel"));
+    assert_eq!(package.targets.keys().collect::<Vec<_>>(), ["a"]);
+    assert_eq!(package.outputs, BTreeMap::from([("a.out".to_owned(), "a".to_owned())]));
+    assert_eq!(package.dependencies, vec![PathBuf::from("pkg")]);
+
+    let target = &package.targets["a"];
+    assert_eq!(target.rule.name(), "gen");
+    assert_eq!(target.attrs["srcs"].repr(), "[\"file.txt\"]");
+    assert_eq!(target.attrs["required"], Value::str("pkg"));
+    assert!(Rc::ptr_eq(&package, &loader.load("", "pkg")?));
+
+    Ok(())
+  }
+
+  #[test]
+  fn load_errors_on_invalid_targets() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("defs.bzl"), TestContents::File(DEFS)),
+      (Path::new("unknown/BUILD"), TestContents::File(
+        "load(\"//:defs.bzl\", \"gen\")\ngen(name = \"a\", required = \"\", bogus = 1)",
+      )),
+      (Path::new("mandatory/BUILD"), TestContents::File(
+        "load(\"//:defs.bzl\", \"gen\")\ngen(name = \"a\")",
+      )),
+      (Path::new("late_package/BUILD"), TestContents::File(
+        "load(\"//:defs.bzl\", \"gen\")\ngen(name = \"a\", required = \"\")\npackage(default_visibility = [])",
+      )),
+      (Path::new("duplicate/BUILD"), TestContents::File(
+        "load(\"//:defs.bzl\", \"gen\")\ngen(name = \"a\", required = \"\")\n\
+        gen(name = \"b\", out = \"a\", required = \"\")",
+      )),
+    ])?;
+    let loader = PackageLoader::new(Rc::new(FsHost::from(&dir.root)?));
+
+    assert_eq!(
+      loader.load("", "unknown").err().unwrap().to_string(),
+      "unknown/BUILD:2:4: gen() has no attribute `bogus`.",
+    );
+    assert_eq!(
+      loader.load("", "mandatory").err().unwrap().to_string(),
+      "mandatory/BUILD:2:4: //mandatory:a: missing mandatory attribute `required`.",
+    );
+    assert_eq!(
+      loader.load("", "late_package").err().unwrap().to_string(),
+      "late_package/BUILD:3:8: package() must be called before any targets are defined.",
+    );
+    assert_eq!(
+      loader.load("", "duplicate").err().unwrap().to_string(),
+      "duplicate/BUILD:3:4: `a` is already defined in package `//duplicate`.",
+    );
+    assert_contains!(
+      loader.load("", "missing").err().unwrap().to_string(),
+      "No such package `//missing`",
+    );
+
+    Ok(())
+  }
+}
@@ -0,0 +1,286 @@
+use serde_json::{json, Map, Value as Json};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+use std::time::Instant;
+
+/// Records what an invocation spends its time on as trace events, which are
+/// written in the Chrome trace event format viewable in `chrome://tracing` or
+/// Perfetto. Every thread recording events gets its own lane.
+pub struct Profiler {
+  start: Instant,
+  events: Mutex<Vec<Json>>,
+
+  /// The threads which recorded events, with their names, by lane.
+  threads: Mutex<Vec<(ThreadId, String)>>,
+
+  /// The number of actions executing.
+  actions: AtomicUsize,
+}
+
+impl Profiler {
+  /// Returns a profiler whose timestamps count from `start`, when the
+  /// invocation started.
+  pub fn new(start: Instant) -> Profiler {
+    Profiler {
+      start,
+      events: Mutex::new(Vec::new()),
+      threads: Mutex::new(Vec::new()),
+      actions: AtomicUsize::new(0),
+    }
+  }
+
+  /// Starts a span of the current thread, recorded when it is dropped.
+  pub fn span(&self, category: &'static str, name: impl Into<String>) -> Span<'_> {
+    self.span_from(category, name, Instant::now())
+  }
+
+  /// Starts a span of the current thread which began at an earlier time.
+  pub fn span_from(&self, category: &'static str, name: impl Into<String>,
+      start: Instant) -> Span<'_> {
+    Span { profiler: self, category, name: name.into(), start, args: Map::new() }
+  }
+
+  /// Starts the span of an action, which counts as executing until the span
+  /// is dropped.
+  pub fn action(&self, name: impl Into<String>) -> Span<'_> {
+    let running = self.actions.fetch_add(1, Ordering::SeqCst) + 1;
+    self.counter("concurrent actions", "actions", running as f64);
+    self.span("action", name)
+  }
+
+  /// Records a span of the current thread which began at `start` and ends
+  /// now, followed by the memory in use.
+  fn record(&self, category: &str, name: &str, start: Instant, args: Map<String, Json>) {
+    let event = json!({
+      "name": name,
+      "cat": category,
+      "ph": "X",
+      "ts": self.timestamp(start),
+      "dur": start.elapsed().as_micros() as u64,
+      "pid": 1,
+      "tid": self.lane(),
+      "args": args,
+    });
+    self.events.lock().unwrap().push(event);
+    if let Some(resident) = resident_memory() {
+      self.counter("memory", "resident MB", resident as f64 / 1_000_000.0);
+    }
+  }
+
+  fn counter(&self, name: &str, series: &str, value: f64) {
+    let event = json!({
+      "name": name,
+      "ph": "C",
+      "ts": self.timestamp(Instant::now()),
+      "pid": 1,
+      "tid": self.lane(),
+      "args": { series: value },
+    });
+    self.events.lock().unwrap().push(event);
+  }
+
+  fn timestamp(&self, time: Instant) -> u64 {
+    time.saturating_duration_since(self.start).as_micros() as u64
+  }
+
+  /// Returns the lane of the current thread.
+  fn lane(&self) -> usize {
+    let current = thread::current();
+    let mut threads = self.threads.lock().unwrap();
+    match threads.iter().position(|(id, _)| *id == current.id()) {
+      Some(lane) => lane,
+      None => {
+        let name = current.name()
+          .map_or_else(|| format!("thread {}", threads.len()), str::to_owned);
+        threads.push((current.id(), name));
+        threads.len() - 1
+      },
+    }
+  }
+
+  /// Returns the recorded trace, naming the process and every lane.
+  pub fn to_json(&self) -> Json {
+    let mut events = vec![json!({
+      "name": "process_name", "ph": "M", "pid": 1, "args": { "name": "razel" },
+    })];
+    for (lane, (_, name)) in self.threads.lock().unwrap().iter().enumerate() {
+      events.push(json!({
+        "name": "thread_name", "ph": "M", "pid": 1, "tid": lane,
+        "args": { "name": name },
+      }));
+    }
+    events.extend(self.events.lock().unwrap().iter().cloned());
+    json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+  }
+}
+
+/// A span being timed, recorded by its profiler when dropped.
+pub struct Span<'a> {
+  profiler: &'a Profiler,
+  category: &'static str,
+  name: String,
+  start: Instant,
+  args: Map<String, Json>,
+}
+
+impl Span<'_> {
+  /// Adds an argument shown with the span.
+  pub fn arg(mut self, name: &str, value: Json) -> Self {
+    self.args.insert(name.to_owned(), value);
+    self
+  }
+}
+
+impl Drop for Span<'_> {
+  fn drop(&mut self) {
+    self.profiler.record(self.category, &self.name, self.start, std::mem::take(&mut self.args));
+    if self.category == "action" {
+      let running = self.profiler.actions.fetch_sub(1, Ordering::SeqCst) - 1;
+      self.profiler.counter("concurrent actions", "actions", running as f64);
+    }
+  }
+}
+
+/// Returns the resident memory of the process in bytes, where the platform
+/// reports it.
+fn resident_memory() -> Option<u64> {
+  let status = fs::read_to_string("/proc/self/status").ok()?;
+  let line = status.lines().find_map(|line| line.strip_prefix("VmRSS:"))?;
+  let kilobytes: u64 = line.trim().trim_end_matches("kB").trim().parse().ok()?;
+  Some(kilobytes * 1024)
+}
+
+/// Summarizes a profile written by `--profile`: the time spent in each phase
+/// and the critical path, the chain of actions each waiting on the previous
+/// one which took longest.
+pub fn analyze(profile: &Json) -> Result<String, ProfileError> {
+  // Chrome also accepts a bare array of events.
+  let events = profile["traceEvents"].as_array().or(profile.as_array())
+    .ok_or_else(|| ProfileError("The profile has no trace events.".to_owned()))?;
+  let spans: Vec<&Json> = events.iter().filter(|event| event["ph"] == "X").collect();
+  let seconds = |micros: u64| micros as f64 / 1_000_000.0;
+  let micros = |event: &Json, field: &str| event[field].as_u64().unwrap_or_default();
+  let total = spans.iter()
+    .map(|span| micros(span, "ts") + micros(span, "dur"))
+    .max()
+    .unwrap_or_default();
+  let percentage = |micros: u64| {
+    if total == 0 { 0.0 } else { micros as f64 * 100.0 / total as f64 }
+  };
+
+  let mut report = String::new();
+  report.push_str("Phases:\n");
+  for phase in spans.iter().filter(|span| span["cat"] == "phase") {
+    let duration = micros(phase, "dur");
+    let name = phase["name"].as_str().unwrap_or_default();
+    report.push_str(&format!(
+      "  {:<20} {:>9.3} s {:>6.2}%\n",
+      name,
+      seconds(duration),
+      percentage(duration),
+    ));
+  }
+  report.push_str(&format!("Total time: {:.3} s\n\n", seconds(total)));
+
+  // Actions only start once the actions generating their inputs finished, so
+  // in order of their start every input's producer is visited first.
+  let mut actions: Vec<&Json> = spans.into_iter().filter(|span| span["cat"] == "action").collect();
+  actions.sort_by_key(|action| micros(action, "ts"));
+  let strings = |value: &Json| -> Vec<String> {
+    value.as_array().into_iter().flatten()
+      .filter_map(|item| item.as_str().map(str::to_owned))
+      .collect()
+  };
+  let mut producers: HashMap<String, usize> = HashMap::new();
+  let mut paths: Vec<(u64, Option<usize>)> = Vec::new();
+  for (index, action) in actions.iter().enumerate() {
+    let previous = strings(&action["args"]["inputs"]).iter()
+      .filter_map(|input| producers.get(input).copied())
+      .max_by_key(|&producer| paths[producer].0);
+    let length = micros(action, "dur") + previous.map_or(0, |previous| paths[previous].0);
+    paths.push((length, previous));
+    for output in strings(&action["args"]["outputs"]) {
+      producers.insert(output, index);
+    }
+  }
+
+  let Some(last) = (0..paths.len()).max_by_key(|&index| paths[index].0) else {
+    report.push_str("Critical path: no actions were executed.\n");
+    return Ok(report);
+  };
+  let mut path = vec![last];
+  while let Some(previous) = paths[*path.last().unwrap()].1 {
+    path.push(previous);
+  }
+  let length = paths[last].0;
+  report.push_str(&format!("Critical path ({:.3} s):\n", seconds(length)));
+  for &index in path.iter().rev() {
+    let duration = micros(actions[index], "dur");
+    let share = if length == 0 { 0.0 } else { duration as f64 * 100.0 / length as f64 };
+    let name = actions[index]["name"].as_str().unwrap_or_default();
+    report.push_str(&format!("  {:>9.3} s {:>6.2}%  {}\n", seconds(duration), share, name));
+  }
+  Ok(report)
+}
+
+/// An error thrown when a profile cannot be analyzed.
+#[derive(Debug)]
+pub struct ProfileError(pub String);
+
+impl Display for ProfileError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for ProfileError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn analyze_prints_phases_and_the_longest_chain_of_actions() -> Result<(), ProfileError> {
+    let action = |name: &str, ts: u64, dur: u64, inputs: &[&str], outputs: &[&str]| json!({
+      "name": name, "cat": "action", "ph": "X", "ts": ts, "dur": dur, "pid": 1, "tid": 0,
+      "args": { "inputs": inputs, "outputs": outputs },
+    });
+    let phase = |name: &str, ts: u64, dur: u64| json!({
+      "name": name, "cat": "phase", "ph": "X", "ts": ts, "dur": dur, "pid": 1, "tid": 0,
+    });
+    let profile = json!({ "traceEvents": [
+      phase("analyze", 0, 1_000_000),
+      phase("execute", 1_000_000, 3_000_000),
+      action("Write a", 1_000_000, 500_000, &[], &["a"]),
+      action("Write b", 1_500_000, 1_000_000, &[], &["b"]),
+      action("Compile c", 2_500_000, 1_500_000, &["a", "b"], &["c"]),
+      { "name": "memory", "ph": "C", "ts": 4_000_000, "pid": 1, "tid": 0,
+        "args": { "resident MB": 20.0 } },
+    ] });
+
+    assert_eq!(analyze(&profile)?, "\
+Phases:
+  analyze                  1.000 s  25.00%
+  execute                  3.000 s  75.00%
+Total time: 4.000 s
+
+Critical path (2.500 s):
+      1.000 s  40.00%  Write b
+      1.500 s  60.00%  Compile c
+");
+    assert_eq!(
+      analyze(&json!({ "traceEvents": [] }))?,
+      "Phases:\nTotal time: 0.000 s\n\nCritical path: no actions were executed.\n",
+    );
+    Ok(())
+  }
+}
@@ -0,0 +1,470 @@
+use std::env;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::workspace;
+
+/// The rc file read by every user of the machine.
+pub const SYSTEM_RC: &str = "/etc/razel.razelrc";
+
+/// The name of the rc files read from the root of the workspace and the
+/// user's home directory.
+pub const RC_FILE: &str = ".razelrc";
+
+/// The startup option reading an additional rc file after the default ones.
+const RAZELRC_OPTION: &str = "--razelrc=";
+
+/// The command whose options apply to every command which supports them.
+const COMMON: &str = "common";
+
+/// Looks up whether `command` supports the option with the given name, such
+/// as `compilation_mode`. Returns `None` for unsupported options, otherwise
+/// whether it takes a value.
+pub type OptionLookup<'a> = &'a dyn Fn(&str, &str) -> Option<bool>;
+
+/// One line of an rc file, such as `build:ci --compilation_mode=opt`.
+struct RcLine {
+  file: PathBuf,
+  command: String,
+
+  /// The named config the options are part of, enabled with `--config`.
+  config: Option<String>,
+
+  options: Vec<String>,
+}
+
+/// The lines of every rc file read, in order of precedence.
+pub struct RcFiles {
+  lines: Vec<RcLine>,
+}
+
+/// A command line with the options of rc files expanded into it.
+pub struct Expanded {
+  pub args: Vec<String>,
+
+  /// Which rc options were applied from where, printed by `--announce_rc`.
+  pub announcements: Vec<String>,
+}
+
+/// Expands the options of rc files into the command line before it is
+/// parsed. Reads the system, workspace and user rc files and any given by
+/// `--razelrc=` before the command, then inserts their `common` and command
+/// options after the command and expands every `--config`.
+pub fn expand_args(args: Vec<String>, supports: OptionLookup) -> Result<Expanded, Box<dyn Error>> {
+  let mut args = args.into_iter();
+  let mut expanded: Vec<String> = args.next().into_iter().collect();
+  let mut extra_files = Vec::new();
+  let mut command = None;
+  for arg in args.by_ref() {
+    if let Some(path) = arg.strip_prefix(RAZELRC_OPTION) {
+      extra_files.push(PathBuf::from(path));
+    } else if arg.starts_with('-') {
+      expanded.push(arg);
+    } else {
+      command = Some(arg);
+      break;
+    }
+  }
+  let Some(command) = command else {
+    return Ok(Expanded { args: expanded, announcements: Vec::new() });
+  };
+
+  let workspace = env::current_dir().ok().and_then(|cwd| workspace::find_root(&cwd).ok());
+  let mut files = vec![(PathBuf::from(SYSTEM_RC), false)];
+  if let Some(workspace) = &workspace {
+    files.push((workspace.join(RC_FILE), false));
+  }
+  if let Some(home) = env::var_os("HOME") {
+    files.push((Path::new(&home).join(RC_FILE), false));
+  }
+  files.extend(extra_files.into_iter().map(|file| (file, true)));
+
+  let rc_files = RcFiles::read(&files, workspace.as_deref())?;
+  let rest = rc_files.expand(&command, &args.collect::<Vec<_>>(), supports)?;
+  expanded.push(command);
+  expanded.extend(rest.args);
+  Ok(Expanded { args: expanded, announcements: rest.announcements })
+}
+
+impl RcFiles {
+  /// Reads the given rc files in order. Files which are not required are
+  /// skipped when missing. `%workspace%` in imported paths is replaced with
+  /// the workspace root.
+  pub fn read(files: &[(PathBuf, bool)], workspace: Option<&Path>) -> Result<RcFiles, Box<dyn Error>> {
+    let mut rc_files = RcFiles { lines: Vec::new() };
+    for (file, required) in files {
+      rc_files.read_file(file, *required, workspace, &mut Vec::new())?;
+    }
+    Ok(rc_files)
+  }
+
+  fn read_file(&mut self, file: &Path, required: bool, workspace: Option<&Path>, stack: &mut Vec<PathBuf>) ->
+      Result<(), Box<dyn Error>> {
+    let source = match fs::read_to_string(file) {
+      Ok(source) => source,
+      Err(_) if !required => return Ok(()),
+      Err(err) => return Err(Box::new(RcError(format!("Failed to read rc file `{}`: {}", file.display(), err)))),
+    };
+    if stack.iter().any(|other| other == file) {
+      let cycle: Vec<_> = stack.iter().chain([&file.to_path_buf()]).map(|file| file.display().to_string()).collect();
+      return Err(Box::new(RcError(format!("Import cycle in rc files: {}", cycle.join(" -> ")))));
+    }
+    stack.push(file.to_path_buf());
+
+    for (index, line) in join_continued_lines(&source).iter().enumerate() {
+      let error = |message: String| RcError(format!("{}:{}: {}", file.display(), index + 1, message));
+      let mut words = split_words(line).map_err(error)?.into_iter();
+      let Some(command) = words.next() else {
+        continue;
+      };
+
+      if command == "import" || command == "try-import" {
+        let [path] = words.collect::<Vec<_>>().try_into()
+          .map_err(|_| error(format!("`{}` takes exactly one path.", command)))?;
+        let path = match workspace {
+          Some(workspace) => path.replace("%workspace%", workspace.to_str().unwrap()),
+          None if path.contains("%workspace%") => continue,
+          None => path,
+        };
+        let path = file.parent().unwrap_or(Path::new("")).join(path);
+        self.read_file(&path, command == "import", workspace, stack)?;
+        continue;
+      }
+
+      let (command, config) = match command.split_once(':') {
+        Some((command, config)) => (command.to_owned(), Some(config.to_owned())),
+        None => (command, None),
+      };
+      self.lines.push(RcLine { file: file.to_path_buf(), command, config, options: words.collect() });
+    }
+
+    stack.pop();
+    Ok(())
+  }
+
+  /// Returns the arguments of `command` preceded by the options of every rc
+  /// line for it, with `--config` expanded.
+  pub fn expand(&self, command: &str, args: &[String], supports: OptionLookup) -> Result<Expanded, RcError> {
+    let mut expansion = Expansion {
+      rc_files: self,
+      command,
+      supports,
+      expanded: Expanded { args: Vec::new(), announcements: Vec::new() },
+      configs: Vec::new(),
+    };
+
+    let mut files: Vec<&Path> = Vec::new();
+    for line in &self.lines {
+      if !files.contains(&line.file.as_path()) {
+        files.push(&line.file);
+      }
+    }
+    for file in files {
+      let lines = |of: &str| self.lines.iter()
+        .filter(|line| line.file == file && line.command == of && line.config.is_none())
+        .flat_map(|line| expansion.applicable(&line.command, &line.options))
+        .collect::<Vec<_>>();
+      let common = lines(COMMON);
+      let own = lines(command);
+      if common.is_empty() && own.is_empty() {
+        continue;
+      }
+
+      let announcements = &mut expansion.expanded.announcements;
+      announcements.push(format!("Reading rc options for '{}' from {}:", command, file.display()));
+      if !common.is_empty() {
+        announcements.push(format!("  Inherited '{}' options: {}", COMMON, common.join(" ")));
+      }
+      if !own.is_empty() {
+        announcements.push(format!("  '{}' options: {}", command, own.join(" ")));
+      }
+      expansion.push(&common)?;
+      expansion.push(&own)?;
+    }
+
+    // Arguments after `--` are never options.
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    expansion.push(&args[..end])?;
+    expansion.expanded.args.extend(args[end..].iter().cloned());
+    Ok(expansion.expanded)
+  }
+}
+
+/// The state of expanding rc options into the arguments of a command.
+struct Expansion<'a> {
+  rc_files: &'a RcFiles,
+  command: &'a str,
+  supports: OptionLookup<'a>,
+  expanded: Expanded,
+
+  /// The configs being expanded, to detect cycles.
+  configs: Vec<String>,
+}
+
+impl Expansion<'_> {
+  /// Appends options, replacing every `--config` with the options of the
+  /// config.
+  fn push(&mut self, options: &[String]) -> Result<(), RcError> {
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+      let config = match option.strip_prefix("--config") {
+        Some("") => options.next()
+          .ok_or_else(|| RcError("`--config` requires a config name.".to_owned()))?,
+        Some(value) if value.starts_with('=') => &value[1..],
+        _ => {
+          self.expanded.args.push(option.clone());
+          continue;
+        },
+      };
+      self.push_config(config)?;
+    }
+    Ok(())
+  }
+
+  fn push_config(&mut self, config: &str) -> Result<(), RcError> {
+    if self.configs.iter().any(|other| other == config) {
+      let cycle: Vec<_> = self.configs.iter().map(|config| config.as_str()).chain([config]).collect();
+      return Err(RcError(format!("Config expansion cycle: {}", cycle.join(" -> "))));
+    }
+
+    let rc_files = self.rc_files;
+    let lines: Vec<_> = rc_files.lines.iter()
+      .filter(|line| line.config.as_deref() == Some(config))
+      .collect();
+    if lines.is_empty() {
+      return Err(RcError(format!("Config value '{}' is not defined in any .rc file.", config)));
+    }
+
+    self.configs.push(config.to_owned());
+    let command = self.command;
+    for line in lines.into_iter().filter(|line| line.command == COMMON || line.command == command) {
+      let options = self.applicable(&line.command, &line.options);
+      self.expanded.announcements.push(format!(
+        "Found applicable config definition {}:{} in file {}: {}",
+        line.command,
+        config,
+        line.file.display(),
+        options.join(" "),
+      ));
+      self.push(&options)?;
+    }
+    self.configs.pop();
+    Ok(())
+  }
+
+  /// Returns the options of an rc line which apply to the command. `common`
+  /// options the command does not support are dropped, along with their
+  /// value.
+  fn applicable(&self, command: &str, options: &[String]) -> Vec<String> {
+    if command != COMMON {
+      return options.to_vec();
+    }
+
+    let mut applicable = Vec::new();
+    let mut options = options.iter().peekable();
+    while let Some(option) = options.next() {
+      let Some(name) = option.strip_prefix("--") else {
+        continue;
+      };
+      let (name, has_value) = name.split_once('=').map_or((name, false), |(name, _)| (name, true));
+      let takes_value = if name == "config" { Some(true) } else { (self.supports)(self.command, name) };
+      let next_is_value = !has_value && options.peek().is_some_and(|next| !next.starts_with('-'));
+      match takes_value {
+        Some(takes_value) => {
+          applicable.push(option.clone());
+          if takes_value && next_is_value {
+            applicable.push(options.next().unwrap().clone());
+          }
+        },
+        None => {
+          if next_is_value {
+            options.next();
+          }
+        },
+      }
+    }
+    applicable
+  }
+}
+
+/// Joins lines ending with a backslash with the next one, and drops comments.
+fn join_continued_lines(source: &str) -> Vec<String> {
+  let mut lines = Vec::new();
+  let mut continued: Option<String> = None;
+  for line in source.lines() {
+    let line = match continued.take() {
+      Some(previous) => previous + line,
+      None => line.to_owned(),
+    };
+    match line.strip_suffix('\\') {
+      Some(line) => continued = Some(line.to_owned()),
+      None => lines.push(line),
+    }
+  }
+  lines.extend(continued);
+  lines
+}
+
+/// Splits a line into words separated by whitespace, which may be quoted or
+/// escape characters with a backslash. Everything after an unquoted `#` is a
+/// comment.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+  let mut words = Vec::new();
+  let mut word: Option<String> = None;
+  let mut quote = None;
+  let mut chars = line.chars();
+  while let Some(char) = chars.next() {
+    match (quote, char) {
+      (Some(open), char) if char == open => quote = None,
+      (Some('"') | None, '\\') => {
+        let escaped = chars.next().ok_or_else(|| "Trailing backslash.".to_owned())?;
+        word.get_or_insert_with(String::new).push(escaped);
+      },
+      (Some(_), char) => word.get_or_insert_with(String::new).push(char),
+      (None, '\'' | '"') => {
+        quote = Some(char);
+        word.get_or_insert_with(String::new);
+      },
+      (None, '#') => break,
+      (None, char) if char.is_whitespace() => words.extend(word.take()),
+      (None, char) => word.get_or_insert_with(String::new).push(char),
+    }
+  }
+  if quote.is_some() {
+    return Err("Unterminated quote.".to_owned());
+  }
+  words.extend(word);
+  Ok(words)
+}
+
+/// An error thrown when rc files are invalid.
+#[derive(Debug)]
+pub struct RcError(pub String);
+
+impl Display for RcError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for RcError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::host::test_dir::{TestContents, TestDir};
+  use super::*;
+
+  fn supports(command: &str, option: &str) -> Option<bool> {
+    match (command, option) {
+      ("build", "compilation_mode" | "define") => Some(true),
+      (_, "announce_rc") => Some(false),
+      _ => None,
+    }
+  }
+
+  fn strings(strings: &[&str]) -> Vec<String> {
+    strings.iter().map(|string| string.to_string()).collect()
+  }
+
+  #[test]
+  fn expand_inserts_rc_options_and_configs() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("wksp/.razelrc"), TestContents::File(r#"
+# Shared defaults.
+common --announce_rc --define env=dev
+build --compilation_mode=fastbuild \
+    --define "greeting=hello world"
+build:ci --compilation_mode=opt --config=release
+common:release --define env=prod
+fmt --check
+
+import %workspace%/tools/ci.razelrc
+try-import %workspace%/user.razelrc
+"#)),
+      (Path::new("wksp/tools/ci.razelrc"), TestContents::File("build:ci --define=ci=true")),
+      (Path::new("home/.razelrc"), TestContents::File("build --define who=me")),
+    ])?;
+    let workspace = dir.root.join("wksp");
+    let rc_files = RcFiles::read(&[
+      (workspace.join(RC_FILE), false),
+      (dir.root.join("home").join(RC_FILE), false),
+      (dir.root.join("missing.razelrc"), false),
+    ], Some(&workspace))?;
+
+    let expanded = rc_files.expand("build", &strings(&["//app", "--config=ci", "--", "--config=x"]), &supports)?;
+    assert_eq!(expanded.args, strings(&[
+      "--announce_rc",
+      "--define",
+      "env=dev",
+      "--compilation_mode=fastbuild",
+      "--define",
+      "greeting=hello world",
+      "--define",
+      "who=me",
+      "//app",
+      "--compilation_mode=opt",
+      "--define",
+      "env=prod",
+      "--define=ci=true",
+      "--",
+      "--config=x",
+    ]));
+    assert_eq!(expanded.announcements, [
+      format!("Reading rc options for 'build' from {}:", workspace.join(RC_FILE).display()),
+      "  Inherited 'common' options: --announce_rc --define env=dev".to_owned(),
+      "  'build' options: --compilation_mode=fastbuild --define greeting=hello world".to_owned(),
+      format!("Reading rc options for 'build' from {}:", dir.root.join("home").join(RC_FILE).display()),
+      "  'build' options: --define who=me".to_owned(),
+      format!(
+        "Found applicable config definition build:ci in file {}: --compilation_mode=opt --config=release",
+        workspace.join(RC_FILE).display(),
+      ),
+      format!(
+        "Found applicable config definition common:release in file {}: --define env=prod",
+        workspace.join(RC_FILE).display(),
+      ),
+      format!(
+        "Found applicable config definition build:ci in file {}: --define=ci=true",
+        workspace.join("tools/ci.razelrc").display(),
+      ),
+    ]);
+
+    // Common options the command does not support are dropped.
+    let expanded = rc_files.expand("fmt", &strings(&["--config=release"]), &supports)?;
+    assert_eq!(expanded.args, strings(&["--announce_rc", "--check"]));
+
+    Ok(())
+  }
+
+  #[test]
+  fn expand_errors_on_invalid_configs() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new(".razelrc"), TestContents::File("build:a --config=b\nbuild:b --config=a")),
+      (Path::new("import.razelrc"), TestContents::File("import missing.razelrc")),
+      (Path::new("quote.razelrc"), TestContents::File("build --define 'a=b")),
+    ])?;
+    let rc_files = RcFiles::read(&[(dir.root.join(RC_FILE), true)], None)?;
+
+    assert_eq!(
+      rc_files.expand("build", &strings(&["--config=c"]), &supports).err().unwrap().to_string(),
+      "Config value 'c' is not defined in any .rc file.",
+    );
+    assert_eq!(
+      rc_files.expand("build", &strings(&["--config", "a"]), &supports).err().unwrap().to_string(),
+      "Config expansion cycle: a -> b -> a",
+    );
+    assert!(RcFiles::read(&[(dir.root.join("import.razelrc"), true)], None).err().unwrap().to_string()
+      .starts_with(&format!("Failed to read rc file `{}`", dir.root.join("missing.razelrc").display())));
+    assert_eq!(
+      RcFiles::read(&[(dir.root.join("quote.razelrc"), true)], None).err().unwrap().to_string(),
+      format!("{}:1: Unterminated quote.", dir.root.join("quote.razelrc").display()),
+    );
+
+    Ok(())
+  }
+}
@@ -0,0 +1,638 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use tar::{Archive, EntryType};
+use crate::host::host::{list_all_files, EntryKind, Host};
+use crate::host::source_writer::{SourceWriter, VENDOR_MARKER};
+use crate::label::{Label, EXTERNAL_DIR};
+use crate::npm::{self, NpmLock};
+use crate::package::BUILD_FILE_NAMES;
+
+/// The directory of the output base which stores downloaded archives by their
+/// SHA-256, shared by every repository using the same archive.
+pub const REPOSITORY_CACHE: &str = "cache/repos/sha256";
+
+/// The name of the repository of the rules built into Razel, such as
+/// `ts_library`, which every workspace can use.
+pub const BUILTIN_REPO: &str = "razel";
+
+/// The files of the builtin repository, embedded in the binary.
+const BUILTIN_FILES: [(&str, &str); 2] = [
+  ("js/BUILD.razel", include_str!("../rules/js/BUILD.razel")),
+  ("js/defs.bzl", include_str!("../rules/js/defs.bzl")),
+];
+
+/// A repository declared in `MODULE.razel` by a repository rule.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RepositoryRule {
+  /// A directory with its own BUILD files, used in place.
+  Local { path: String },
+
+  /// A directory without BUILD files, copied into the output base with a
+  /// BUILD file added at its root.
+  NewLocal { path: String, build_file: BuildFile },
+
+  /// An archive which is downloaded, verified against its SHA-256 and
+  /// extracted into the output base.
+  HttpArchive {
+    urls: Vec<String>,
+    sha256: String,
+
+    /// A directory of the archive to use as the repository root.
+    strip_prefix: String,
+
+    build_file: Option<BuildFile>,
+  },
+
+  /// The packages of an npm or pnpm lockfile in the main repository, with a
+  /// target per package.
+  NpmLock {
+    lockfile: Label,
+
+    /// A directory laid out like the npm registry to read tarballs missing
+    /// from the npm cache from.
+    mirror: Option<String>,
+  },
+
+  /// The builtin repository, written from the files embedded in the binary.
+  Builtin,
+}
+
+/// The BUILD file added to the root of a repository.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BuildFile {
+  /// A file in the main repository.
+  Label(Label),
+  Content(String),
+}
+
+/// The source trees labels can refer to: the main repository and the
+/// external repositories, by name. Repositories declared by repository rules
+/// are fetched the first time they are used.
+pub struct Repositories {
+  main: Rc<dyn Host>,
+  rules: BTreeMap<String, RepositoryRule>,
+  external: RefCell<BTreeMap<String, Rc<dyn Host>>>,
+
+  /// The workspace directory of vendored copies of repositories, which are
+  /// used instead of fetching them.
+  vendor_dir: Option<PathBuf>,
+}
+
+impl Repositories {
+  /// Returns the repositories of a workspace without any external ones but
+  /// the builtin repository.
+  pub fn new(main: Rc<dyn Host>) -> Repositories {
+    Repositories {
+      main,
+      rules: BTreeMap::from([(BUILTIN_REPO.to_owned(), RepositoryRule::Builtin)]),
+      external: RefCell::new(BTreeMap::new()),
+      vendor_dir: None,
+    }
+  }
+
+  pub fn with_vendor_dir(self, vendor_dir: Option<PathBuf>) -> Repositories {
+    Repositories { vendor_dir, ..self }
+  }
+
+  /// Adds a repository whose sources are read from `host`.
+  pub fn insert(&mut self, name: &str, host: Rc<dyn Host>) {
+    self.external.get_mut().insert(name.to_owned(), host);
+  }
+
+  /// Adds a repository which is fetched by `rule` when first used.
+  pub fn insert_rule(&mut self, name: &str, rule: RepositoryRule) {
+    self.rules.insert(name.to_owned(), rule);
+  }
+
+  /// The host of the main repository.
+  pub fn main(&self) -> &Rc<dyn Host> {
+    &self.main
+  }
+
+  /// The external repositories used so far, by name.
+  pub fn external(&self) -> BTreeMap<String, Rc<dyn Host>> {
+    self.external.borrow().clone()
+  }
+
+  /// Returns the vendored copy of a repository, if there is one.
+  pub fn vendored(&self, name: &str) -> Option<Rc<dyn Host>> {
+    let dir = self.vendor_dir.as_ref()?.join(name);
+    self.main.with_source_root(&dir).ok().map(Rc::from)
+  }
+
+  /// Returns the host of the repository with the given name, which is empty
+  /// for the main repository, fetching it if needed.
+  pub fn get(&self, repo: &str) -> Result<Rc<dyn Host>, Box<dyn Error>> {
+    if repo.is_empty() {
+      return Ok(self.main.clone());
+    }
+    if let Some(host) = self.external.borrow().get(repo) {
+      return Ok(host.clone());
+    }
+
+    let rule = self.rules.get(repo)
+      .ok_or_else(|| RepositoryError(format!("No such repository `@{}`.", repo)))?;
+    let host = match self.vendored(repo).filter(|_| rule != &RepositoryRule::Builtin) {
+      Some(host) => host,
+      None => Rc::from(fetch(self.main.as_ref(), repo, rule)
+        .map_err(|err| RepositoryError(format!("Failed to fetch `@{}`: {}", repo, err)))?),
+    };
+    self.external.borrow_mut().insert(repo.to_owned(), host.clone());
+    Ok(host)
+  }
+
+  /// Fetches every repository which was not used yet, except the builtin
+  /// repository which is always available.
+  pub fn fetch_all(&self) -> Result<(), Box<dyn Error>> {
+    for (name, rule) in &self.rules {
+      if rule != &RepositoryRule::Builtin {
+        self.get(name)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Materializes a repository declared by a repository rule and returns a host
+/// reading its sources. Repositories which are not used in place are written
+/// to `external/<name>` of the output base.
+fn fetch(host: &dyn Host, name: &str, rule: &RepositoryRule) -> Result<Box<dyn Host>, Box<dyn Error>> {
+  let dest = Path::new(EXTERNAL_DIR).join(name);
+  match rule {
+    RepositoryRule::Local { path } => return host.with_source_root(Path::new(path)),
+    RepositoryRule::NewLocal { path, build_file } => {
+      let source = host.with_source_root(Path::new(path))?;
+      host.delete_output(&dest)?;
+      for file in list_all_files(source.as_ref(), Path::new(""))? {
+        if !BUILD_FILE_NAMES.iter().any(|build| file == Path::new(build)) {
+          host.write_output(&dest.join(&file), &source.read(&file)?)?;
+          host.set_output_executable(&dest.join(&file), source.is_executable(&file)?)?;
+        }
+      }
+      host.write_output(&dest.join(BUILD_FILE_NAMES[0]), &read_build_file(host, build_file)?)?;
+    },
+    RepositoryRule::HttpArchive { urls, sha256, strip_prefix, build_file } => {
+      let build = build_file.as_ref().map(|build_file| read_build_file(host, build_file)).transpose()?;
+      let marker = format!(
+        "sha256={}\nstrip_prefix={}\nbuild_file={}\n",
+        sha256,
+        strip_prefix,
+        build.as_ref().map_or(String::new(), |build| format!("{:x}", Sha256::digest(build))),
+      );
+      materialize(host, name, &marker, || {
+        let (url, archive) = download(host, urls, sha256)?;
+        extract(host, &url, &archive, strip_prefix, &dest)?;
+        if let Some(build) = build {
+          host.write_output(&dest.join(BUILD_FILE_NAMES[0]), &build)?;
+        }
+        Ok(())
+      })?;
+    },
+    RepositoryRule::Builtin => {
+      let mut digest = Sha256::new();
+      for (path, contents) in BUILTIN_FILES {
+        digest.update(format!("{}\0{}\0", path, contents));
+      }
+      materialize(host, name, &format!("builtin={:x}\n", digest.finalize()), || {
+        for (path, contents) in BUILTIN_FILES {
+          host.write_output(&dest.join(path), contents.as_bytes())?;
+        }
+        Ok(())
+      })?;
+    },
+    RepositoryRule::NpmLock { lockfile, mirror } => {
+      let path = Path::new(&lockfile.package).join(&lockfile.name);
+      let contents = host.read_to_string(&path)?;
+      let marker = format!(
+        "lockfile={:x}\nmirror={}\n",
+        Sha256::digest(&contents),
+        mirror.as_deref().unwrap_or_default(),
+      );
+      materialize(host, name, &marker, || {
+        let lock = NpmLock::parse(&path, &contents)?;
+        npm::fetch(host, name, &lock, mirror.as_deref().map(Path::new), &dest)
+      })?;
+    },
+  }
+
+  host.with_source_root(&host.output_base().join(dest))
+}
+
+/// Creates the directory of the repository `name` in the output base with
+/// `create`, unless it was already created with the same marker contents,
+/// which describe everything the directory depends on.
+fn materialize(host: &dyn Host, name: &str, marker_contents: &str, create: impl FnOnce() -> Result<(), Box<dyn Error>>) ->
+    Result<(), Box<dyn Error>> {
+  let dest = Path::new(EXTERNAL_DIR).join(name);
+  let marker = Path::new(EXTERNAL_DIR).join(format!("@{}.marker", name));
+  let created = host.output_kind(&dest)? == Some(EntryKind::Directory) &&
+    host.read_output(&marker).is_ok_and(|marker| marker == marker_contents.as_bytes());
+  if !created {
+    host.delete_output(&marker)?;
+    host.delete_output(&dest)?;
+    host.create_output_dir(&dest)?;
+    create()?;
+    host.write_output(&marker, marker_contents.as_bytes())?;
+  }
+  Ok(())
+}
+
+fn read_build_file(host: &dyn Host, build_file: &BuildFile) -> Result<Vec<u8>, Box<dyn Error>> {
+  match build_file {
+    BuildFile::Label(label) => host.read(&Path::new(&label.package).join(&label.name))
+      .map_err(|err| format!("Failed to read build file `{}`: {}", label, err).into()),
+    BuildFile::Content(content) => Ok(content.as_bytes().to_vec()),
+  }
+}
+
+/// Returns the archive matching `sha256` with the URL it was downloaded from,
+/// from the repository cache or else the first of `urls` which could be read.
+/// Only `file://` URLs are supported. Downloaded archives are added to the
+/// cache along with their URL, whose extension tells their format.
+fn download(host: &dyn Host, urls: &[String], sha256: &str) -> Result<(String, Vec<u8>), Box<dyn Error>> {
+  let cached = Path::new(REPOSITORY_CACHE).join(sha256).join("file");
+  let cached_url = Path::new(REPOSITORY_CACHE).join(sha256).join("url");
+  if let (Ok(archive), Ok(url)) = (host.read_output(&cached), host.read_output(&cached_url)) {
+    if format!("{:x}", Sha256::digest(&archive)) == sha256 {
+      return Ok((String::from_utf8_lossy(&url).into_owned(), archive));
+    }
+  }
+
+  let mut errors = Vec::new();
+  for url in urls {
+    let Some(path) = url.strip_prefix("file://").map(Path::new) else {
+      errors.push(format!("Unsupported URL `{}`, only file:// URLs are supported.", url));
+      continue;
+    };
+    let (Some(dir), Some(file)) = (path.parent(), path.file_name()) else {
+      errors.push(format!("Invalid URL `{}`.", url));
+      continue;
+    };
+    let archive = match host.with_source_root(dir).and_then(|dir| dir.read(Path::new(file))) {
+      Ok(archive) => archive,
+      Err(err) => {
+        errors.push(format!("Failed to read `{}`: {}", url, err));
+        continue;
+      },
+    };
+
+    let actual = format!("{:x}", Sha256::digest(&archive));
+    if actual != sha256 {
+      return Err(Box::new(RepositoryError(format!(
+        "Checksum mismatch for `{}`: expected sha256 {} but got {}.",
+        url,
+        sha256,
+        actual,
+      ))));
+    }
+    host.write_output(&cached, &archive)?;
+    host.write_output(&cached_url, url.as_bytes())?;
+    return Ok((url.clone(), archive));
+  }
+
+  Err(Box::new(RepositoryError(errors.join(" "))))
+}
+
+/// Extracts the files of an archive under `strip_prefix` into `dest` of the
+/// output base. The format is chosen by the URL's extension.
+pub fn extract(host: &dyn Host, url: &str, archive: &[u8], strip_prefix: &str, dest: &Path) ->
+    Result<(), Box<dyn Error>> {
+  let reader: Box<dyn Read + '_> = if url.ends_with(".tar.gz") || url.ends_with(".tgz") {
+    Box::new(GzDecoder::new(archive))
+  } else if url.ends_with(".tar") {
+    Box::new(archive)
+  } else {
+    return Err(Box::new(RepositoryError(format!(
+      "Unsupported archive `{}`, expected a .tar.gz, .tgz or .tar file.",
+      url,
+    ))));
+  };
+
+  let mut found_prefix = strip_prefix.is_empty();
+  for entry in Archive::new(reader).entries()? {
+    let mut entry = entry?;
+    let path = entry.path()?.to_path_buf();
+    if path.components().any(|component| !matches!(component, Component::Normal(_) | Component::CurDir)) {
+      return Err(Box::new(RepositoryError(format!(
+        "Archive `{}` contains invalid path \"{}\".",
+        url,
+        path.to_str().unwrap_or_default(),
+      ))));
+    }
+    let Ok(path) = path.strip_prefix(strip_prefix) else {
+      continue;
+    };
+    found_prefix = true;
+    if path.as_os_str().is_empty() {
+      continue;
+    }
+
+    let output = dest.join(path);
+    match entry.header().entry_type() {
+      EntryType::Regular => {
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        host.write_output(&output, &contents)?;
+        host.set_output_executable(&output, entry.header().mode()? & 0o111 != 0)?;
+      },
+      EntryType::Directory => host.create_output_dir(&output)?,
+      EntryType::Symlink => {
+        let target = entry.link_name()?.ok_or_else(|| RepositoryError(format!(
+          "Archive `{}` contains a symlink without a target.",
+          url,
+        )))?;
+        host.symlink_output(&output, &target)?;
+      },
+      _ => {},
+    }
+  }
+
+  if !found_prefix {
+    return Err(Box::new(RepositoryError(format!(
+      "Prefix `{}` was not found in archive `{}`.",
+      strip_prefix,
+      url,
+    ))));
+  }
+  Ok(())
+}
+
+/// Copies every external repository into `<vendor_dir>/<name>` of the
+/// workspace, replacing copies written by earlier runs, so builds with the
+/// same vendor directory need neither the registry nor the archives. Fails
+/// rather than replace anything else, and if the vendor directory contains
+/// packages of the workspace. Returns the names of the vendored repositories.
+pub fn vendor(repositories: &Repositories, vendor_dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+  repositories.fetch_all()?;
+  let host = repositories.main();
+  check_vendor_dir(host.as_ref(), vendor_dir)?;
+  let writer = SourceWriter::new(host.as_ref());
+  let mut external = repositories.external();
+  external.remove(BUILTIN_REPO);
+  for (name, repository) in &external {
+    let dest = vendor_dir.join(name);
+    if repository.source_root() == host.source_root().join(&dest) {
+      continue;
+    }
+    writer.delete_vendored(&dest)?;
+    // Marked first so a copy interrupted part way is still replaced.
+    writer.write(&dest.join(VENDOR_MARKER), b"")?;
+    for file in list_all_files(repository.as_ref(), Path::new(""))? {
+      writer.write(&dest.join(&file), &repository.read(&file)?)?;
+      writer.set_executable(&dest.join(&file), repository.is_executable(&file)?)?;
+    }
+  }
+
+  Ok(external.into_keys().collect())
+}
+
+/// Fails if the vendor directory is the workspace root or contains a BUILD
+/// file outside of earlier vendored copies, since those are sources `vendor`
+/// must not touch.
+fn check_vendor_dir(host: &dyn Host, vendor_dir: &Path) -> Result<(), Box<dyn Error>> {
+  let Ok(resolved) = host.resolve(vendor_dir) else {
+    return Ok(());
+  };
+  if resolved.path == Path::new("") {
+    return Err(Box::new(RepositoryError(
+      "The vendor directory cannot be the workspace root.".to_owned(),
+    )));
+  }
+  if resolved.kind != EntryKind::Directory {
+    return Ok(());
+  }
+
+  let mut dirs = vec![resolved.path];
+  while let Some(dir) = dirs.pop() {
+    for entry in host.list(&dir)? {
+      let name = entry.path.file_name().unwrap().to_str().unwrap();
+      match entry.kind {
+        EntryKind::File if BUILD_FILE_NAMES.contains(&name) => {
+          return Err(Box::new(RepositoryError(format!(
+            "Refusing to vendor into \"{}\", which contains the package //{}.",
+            vendor_dir.to_str().unwrap(),
+            dir.to_str().unwrap(),
+          ))));
+        },
+        EntryKind::Directory if !is_vendored(host, &entry.path) => dirs.push(entry.path),
+        _ => {},
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Returns whether the directory at the given path is a copy of a repository
+/// written by `vendor`.
+fn is_vendored(host: &dyn Host, dir: &Path) -> bool {
+  host.resolve(&dir.join(VENDOR_MARKER)).is_ok_and(|entry| entry.kind == EntryKind::File)
+}
+
+/// An error thrown when a repository cannot be fetched.
+#[derive(Debug)]
+pub struct RepositoryError(pub String);
+
+impl Display for RepositoryError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for RepositoryError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::fs;
+  use std::os::unix::fs::PermissionsExt;
+  use assertables::assert_contains;
+  use flate2::{write::GzEncoder, Compression};
+  use tar::{Builder, Header};
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
+  use super::*;
+
+  /// Returns a gzipped tarball of the given files and their modes.
+  fn tar_gz(files: &[(&str, &str, u32)]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut builder = Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    for (path, contents, mode) in files {
+      let mut header = Header::new_gnu();
+      header.set_size(contents.len() as u64);
+      header.set_mode(*mode);
+      header.set_cksum();
+      builder.append_data(&mut header, path, contents.as_bytes())?;
+    }
+    Ok(builder.into_inner()?.finish()?)
+  }
+
+  #[test]
+  fn http_archive_extracts_verified_archive() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([(Path::new("MODULE.razel"), TestContents::File(""))])?;
+    let output_base = TestDir::from([])?;
+    let archive = tar_gz(&[
+      ("lib-1.0/lib.js", "export {};", 0o644),
+      ("lib-1.0/bin/tool", "#!/bin/sh", 0o755),
+      ("README", "Not in the prefix.", 0o644),
+    ])?;
+    fs::write(wksp.root.join("lib.tar.gz"), &archive)?;
+
+    let host: Rc<dyn Host> = Rc::new(FsHost::with_output_base(&wksp.root, &output_base.root)?);
+    let url = format!("file://{}", wksp.root.join("lib.tar.gz").to_str().unwrap());
+    let rule = |sha256: String| RepositoryRule::HttpArchive {
+      urls: vec![url.clone()],
+      sha256,
+      strip_prefix: "lib-1.0".to_owned(),
+      build_file: Some(BuildFile::Content("js_library(name = \"lib\")".to_owned())),
+    };
+    let sha256 = format!("{:x}", Sha256::digest(&archive));
+    let mut repositories = Repositories::new(host.clone());
+    repositories.insert_rule("lib", rule(sha256.clone()));
+
+    let lib = repositories.get("lib")?;
+    assert_eq!(lib.read(Path::new("lib.js"))?, b"export {};");
+    assert_eq!(lib.read(Path::new("BUILD.razel"))?, b"js_library(name = \"lib\")");
+    assert!(lib.is_executable(Path::new("bin/tool"))?);
+    assert!(lib.read(Path::new("README")).is_err());
+    assert_eq!(host.read_output(&Path::new(REPOSITORY_CACHE).join(&sha256).join("file"))?, archive);
+
+    // The cached archive is used once the original is gone.
+    fs::remove_file(wksp.root.join("lib.tar.gz"))?;
+    fs::remove_dir_all(output_base.root.join(EXTERNAL_DIR))?;
+    let mut repositories = Repositories::new(host.clone());
+    repositories.insert_rule("lib", rule(sha256.clone()));
+    assert_eq!(repositories.get("lib")?.read(Path::new("lib.js"))?, b"export {};");
+
+    // Its format comes from the URL it was downloaded from, not the first one.
+    fs::remove_dir_all(output_base.root.join(EXTERNAL_DIR))?;
+    let mut repositories = Repositories::new(host.clone());
+    let mut mirrored = rule(sha256.clone());
+    if let RepositoryRule::HttpArchive { urls, .. } = &mut mirrored {
+      urls.insert(0, format!("file://{}", wksp.root.join("lib.zip").to_str().unwrap()));
+    }
+    repositories.insert_rule("lib", mirrored);
+    assert_eq!(repositories.get("lib")?.read(Path::new("lib.js"))?, b"export {};");
+
+    fs::write(wksp.root.join("lib.tar.gz"), &archive)?;
+    let mut repositories = Repositories::new(host.clone());
+    repositories.insert_rule("lib", rule("0".repeat(64)));
+    assert_contains!(
+      repositories.get("lib").err().unwrap().to_string(),
+      &format!("Failed to fetch `@lib`: Checksum mismatch for `file://{}/lib.tar.gz`: expected sha256 {} but got {}.",
+        wksp.root.to_str().unwrap(), "0".repeat(64), sha256),
+    );
+    assert_eq!(
+      repositories.get("other").err().unwrap().to_string(),
+      "No such repository `@other`.",
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn new_local_repository_replaces_build_file() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([
+      (Path::new("MODULE.razel"), TestContents::File("")),
+      (Path::new("third_party/lib.BUILD"), TestContents::File("filegroup(name = \"all\")")),
+    ])?;
+    let source = TestDir::from([
+      (Path::new("BUILD"), TestContents::File("broken(")),
+      (Path::new("src/lib.js"), TestContents::File("export {};")),
+    ])?;
+    let output_base = TestDir::from([])?;
+
+    let host: Rc<dyn Host> = Rc::new(FsHost::with_output_base(&wksp.root, &output_base.root)?);
+    let mut repositories = Repositories::new(host);
+    repositories.insert_rule("lib", RepositoryRule::NewLocal {
+      path: source.root.to_str().unwrap().to_owned(),
+      build_file: BuildFile::Label(Label::parse("//third_party:lib.BUILD", "")?),
+    });
+
+    let lib = repositories.get("lib")?;
+    assert_eq!(list_all_files(lib.as_ref(), Path::new(""))?, [Path::new("BUILD.razel"), Path::new("src/lib.js")]);
+    assert_eq!(lib.read(Path::new("BUILD.razel"))?, b"filegroup(name = \"all\")");
+
+    Ok(())
+  }
+
+  #[test]
+  fn vendor_copies_repositories_into_workspace() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([
+      (Path::new("MODULE.razel"), TestContents::File("")),
+      (Path::new("vendor/stale/.razel-vendored"), TestContents::File("")),
+      (Path::new("vendor/stale/BUILD.razel"), TestContents::File("")),
+      (Path::new("vendor/stale/removed.txt"), TestContents::File("")),
+    ])?;
+    let source = TestDir::from([
+      (Path::new("BUILD.razel"), TestContents::File("")),
+      (Path::new("tool"), TestContents::File("#!/bin/sh")),
+    ])?;
+    fs::set_permissions(source.root.join("tool"), fs::Permissions::from_mode(0o755))?;
+    let output_base = TestDir::from([])?;
+
+    let host: Rc<dyn Host> = Rc::new(FsHost::with_output_base(&wksp.root, &output_base.root)?);
+    let mut repositories = Repositories::new(host.clone());
+    repositories.insert_rule("stale", RepositoryRule::Local { path: source.root.to_str().unwrap().to_owned() });
+
+    assert_eq!(vendor(&repositories, Path::new("vendor"))?, ["stale"]);
+    let mut vendored = list_all_files(host.as_ref(), Path::new("vendor"))?;
+    vendored.sort();
+    assert_eq!(
+      vendored,
+      [Path::new("vendor/stale/.razel-vendored"), Path::new("vendor/stale/BUILD.razel"), Path::new("vendor/stale/tool")],
+    );
+    assert!(host.is_executable(Path::new("vendor/stale/tool"))?);
+
+    // Builds use the vendored copy once it exists.
+    let repositories = Repositories::new(host.clone()).with_vendor_dir(Some(PathBuf::from("vendor")));
+    assert_eq!(repositories.vendored("stale").unwrap().source_root(), wksp.root.canonicalize()?.join("vendor/stale"));
+
+    Ok(())
+  }
+
+  #[test]
+  fn vendor_refuses_to_replace_sources() -> Result<(), Box<dyn Error>> {
+    let wksp = TestDir::from([
+      (Path::new("MODULE.razel"), TestContents::File("")),
+      (Path::new("BUILD.razel"), TestContents::File("")),
+      (Path::new("src/BUILD.razel"), TestContents::File("")),
+      (Path::new("src/lib/index.ts"), TestContents::File("")),
+      (Path::new("vendor/lib/index.ts"), TestContents::File("")),
+    ])?;
+    let source = TestDir::from([
+      (Path::new("BUILD.razel"), TestContents::File("")),
+    ])?;
+    let output_base = TestDir::from([])?;
+
+    let host: Rc<dyn Host> = Rc::new(FsHost::with_output_base(&wksp.root, &output_base.root)?);
+    let mut repositories = Repositories::new(host.clone());
+    repositories.insert_rule("lib", RepositoryRule::Local { path: source.root.to_str().unwrap().to_owned() });
+
+    assert_contains!(
+      vendor(&repositories, Path::new(".")).unwrap_err().to_string(),
+      "cannot be the workspace root",
+    );
+    assert_contains!(
+      vendor(&repositories, Path::new("src")).unwrap_err().to_string(),
+      "contains the package //src.",
+    );
+    // A directory which is no earlier vendored copy is never replaced.
+    assert_contains!(
+      vendor(&repositories, Path::new("vendor")).unwrap_err().to_string(),
+      "which was not written by `razel vendor`",
+    );
+    assert!(wksp.root.join("src/lib/index.ts").exists());
+    assert!(wksp.root.join("vendor/lib/index.ts").exists());
+
+    Ok(())
+  }
+}
@@ -0,0 +1,512 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::json;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use crate::analysis::config::Configuration;
+use crate::build::{build_with_inputs, BuildOptions, BuiltTarget};
+use crate::execution::exec_path;
+use crate::host::host::{list_all_files, Host};
+use crate::target_pattern::{PatternScope, TargetPattern};
+
+/// The path browsers open a WebSocket on to be notified of rebuilds.
+const SOCKET_PATH: &str = "/__razel/socket";
+
+/// How often the files a build read are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The longest wait between listings of the whole workspace, which is polled
+/// less often the longer it stays unchanged.
+const MAX_SCAN_INTERVAL: Duration = Duration::from_secs(3);
+
+/// The GUID appended to a WebSocket key to compute its accept key, from RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Injected into every served HTML page. Reloads the page when the target is
+/// rebuilt, swaps stylesheets in place when only CSS changed and shows build
+/// errors over the page.
+const CLIENT_SCRIPT: &str = r#"<script>
+(() => {
+  const socket = new WebSocket(`ws://${location.host}/__razel/socket`);
+  socket.onmessage = (event) => {
+    const message = JSON.parse(event.data);
+    if (message.type === "reload") {
+      location.reload();
+    } else if (message.type === "css") {
+      for (const link of document.querySelectorAll('link[rel="stylesheet"]')) {
+        const url = new URL(link.href);
+        if (message.paths.includes(url.pathname.slice(1))) {
+          url.searchParams.set("razel", Date.now());
+          link.href = url;
+        }
+      }
+    } else if (message.type === "error") {
+      let overlay = document.getElementById("razel-error");
+      if (!overlay) {
+        overlay = document.createElement("pre");
+        overlay.id = "razel-error";
+        overlay.style.cssText = "position:fixed;inset:0;margin:0;padding:2em;"
+          + "overflow:auto;z-index:2147483647;background:rgba(24,24,24,0.95);"
+          + "color:#ff8080;font:14px monospace;white-space:pre-wrap";
+        document.body.append(overlay);
+      }
+      overlay.textContent = message.message;
+    }
+  };
+})();
+</script>"#;
+
+/// Serves the outputs of the target matched by `pattern` on localhost and
+/// rebuilds it whenever a file the build read changes. Connected browsers
+/// reload after every rebuild that changed an output. Never returns unless
+/// the server cannot be started.
+pub fn serve(host: Rc<dyn Host>, pattern: &TargetPattern, config: Configuration,
+    options: &BuildOptions, port: u16) -> Result<(), Box<dyn Error>> {
+  if !matches!(pattern.scope, PatternScope::SingleTarget(_)) {
+    return Err(Box::new(ServeError(format!(
+      "Can only serve a single target, got `{}`.",
+      pattern,
+    ))));
+  }
+  let server = Server::bind(port)?;
+  println!("Serving {} at http://{}/", pattern, server.address);
+
+  // Until a build succeeds it is unknown which files it depends on, so any
+  // change in the workspace triggers a rebuild. After that, failed builds
+  // keep watching the inputs of the last successful one. Actions whose inputs
+  // did not change are skipped, so rebuilds only rerun what changed.
+  let mut inputs = None;
+  loop {
+    match build_with_inputs(host.clone(), std::slice::from_ref(pattern), config.clone(), options) {
+      Ok(result) => {
+        println!("Built {}", pattern);
+        server.update(Ok(served_files(host.as_ref(), &result.targets[0])));
+        inputs = Some(result.inputs);
+      },
+      Err(err) => {
+        eprintln!("ERROR: {}", err);
+        server.update(Err(err.to_string()));
+      },
+    }
+
+    let previous = snapshot(host.as_ref(), inputs.as_deref())?;
+    let mut interval = POLL_INTERVAL;
+    while snapshot(host.as_ref(), inputs.as_deref())? == previous {
+      thread::sleep(interval);
+      if inputs.is_none() {
+        interval = (interval * 2).min(MAX_SCAN_INTERVAL);
+      }
+    }
+  }
+}
+
+/// Returns the modification times of the given workspace files, or of every
+/// file in the workspace without any. Missing files map to `None`.
+fn snapshot(host: &dyn Host, inputs: Option<&[PathBuf]>) ->
+    Result<BTreeMap<PathBuf, Option<SystemTime>>, Box<dyn Error>> {
+  let files = match inputs {
+    Some(inputs) => inputs.to_vec(),
+    None => list_all_files(host, Path::new(""))?,
+  };
+  Ok(files.into_iter().map(|file| {
+    let modified = host.modified(&file).ok();
+    (file, modified)
+  }).collect())
+}
+
+/// Maps the URL paths of a built target's files to their absolute paths.
+/// Files are served relative to the target's package and directories serve
+/// the files within them.
+fn served_files(host: &dyn Host, target: &BuiltTarget) -> BTreeMap<String, PathBuf> {
+  let package = format!("{}/", target.label.package);
+  target.files.iter().map(|file| {
+    let path = file.short_path.strip_prefix(&package).unwrap_or(&file.short_path);
+    (path.to_owned(), host.output_base().join(exec_path(&file.path)))
+  }).collect()
+}
+
+/// An HTTP server for the outputs of the latest build, which also accepts the
+/// WebSockets reloads are pushed to.
+struct Server {
+  address: SocketAddr,
+  state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+  /// The URL paths of the served files and directories.
+  files: BTreeMap<String, PathBuf>,
+
+  /// The digest of every served file, by URL path, to tell which ones a
+  /// rebuild changed.
+  digests: BTreeMap<String, Vec<u8>>,
+
+  /// The error of the latest build, if it failed.
+  error: Option<String>,
+
+  sockets: Vec<TcpStream>,
+}
+
+impl Server {
+  /// Starts serving on the given port of the loopback interface, or any free
+  /// port if it is zero.
+  fn bind(port: u16) -> Result<Server, Box<dyn Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+      .map_err(|err| ServeError(format!("Failed to listen on port {}: {}", port, err)))?;
+    let address = listener.local_addr()?;
+    let state = Arc::new(Mutex::new(State::default()));
+
+    let listener_state = state.clone();
+    thread::spawn(move || {
+      for stream in listener.incoming().flatten() {
+        let state = listener_state.clone();
+        thread::spawn(move || {
+          if let Err(err) = handle(stream, &state) {
+            eprintln!("WARNING: Failed to serve a request: {}", err);
+          }
+        });
+      }
+    });
+
+    Ok(Server { address, state })
+  }
+
+  /// Serves the files of a successful build or the error of a failed one, and
+  /// notifies connected browsers.
+  fn update(&self, result: Result<BTreeMap<String, PathBuf>, String>) {
+    let mut state = self.state.lock().unwrap();
+    let message = match result {
+      Ok(files) => {
+        let digests = digest_files(&files);
+        let mut changed: Vec<_> = digests.iter()
+          .filter(|(path, digest)| state.digests.get(*path) != Some(digest))
+          .chain(state.digests.iter().filter(|(path, _)| !digests.contains_key(*path)))
+          .map(|(path, _)| path.clone())
+          .collect();
+        changed.sort();
+        let recovered = state.error.take().is_some();
+        state.files = files;
+        state.digests = digests;
+
+        // Stylesheets are replaced without losing the page's state.
+        if recovered || changed.iter().any(|path| !path.ends_with(".css")) {
+          Some(json!({"type": "reload"}))
+        } else if !changed.is_empty() {
+          Some(json!({"type": "css", "paths": changed}))
+        } else {
+          None
+        }
+      },
+      Err(error) => {
+        state.error = Some(error.clone());
+        Some(json!({"type": "error", "message": error}))
+      },
+    };
+
+    if let Some(message) = message {
+      let frame = text_frame(&message.to_string());
+      state.sockets.retain_mut(|socket| socket.write_all(&frame).is_ok());
+    }
+  }
+}
+
+/// Answers a single request and closes the connection, unless it opens a
+/// WebSocket.
+fn handle(mut stream: TcpStream, state: &Mutex<State>) -> Result<(), Box<dyn Error>> {
+  let mut reader = BufReader::new(stream.try_clone()?);
+  let mut request_line = String::new();
+  reader.read_line(&mut request_line)?;
+  let mut headers = HashMap::new();
+  loop {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+      break;
+    }
+    if let Some((name, value)) = line.split_once(':') {
+      headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_owned());
+    }
+  }
+
+  let mut parts = request_line.split_whitespace();
+  let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+    return respond(&mut stream, "400 Bad Request", "text/plain", b"Bad request.");
+  };
+  if method != "GET" {
+    return respond(
+      &mut stream, "405 Method Not Allowed", "text/plain",
+      b"Only GET is supported.");
+  }
+  let path = target.split(['?', '#']).next().unwrap();
+
+  if path == SOCKET_PATH {
+    let Some(key) = headers.get("sec-websocket-key") else {
+      return respond(
+        &mut stream, "400 Bad Request", "text/plain",
+        b"Expected a WebSocket handshake.");
+    };
+    write!(
+      stream,
+      "HTTP/1.1 101 Switching Protocols\r\n\
+      Upgrade: websocket\r\n\
+      Connection: Upgrade\r\n\
+      Sec-WebSocket-Accept: {}\r\n\r\n",
+      accept_key(key),
+    )?;
+    state.lock().unwrap().sockets.push(stream);
+    return Ok(());
+  }
+
+  let mut path = path.trim_start_matches('/').to_owned();
+  if path.is_empty() || path.ends_with('/') {
+    path.push_str("index.html");
+  }
+  let is_html = path.ends_with(".html");
+  let state = state.lock().unwrap();
+  if let (Some(error), true) = (&state.error, is_html) {
+    let page = format!(
+      "<!DOCTYPE html>\n\
+      <html><head><title>Build failed</title></head><body>\n\
+      <pre id=\"razel-error\">{}</pre>\n\
+      </body></html>\n",
+      escape_html(error),
+    );
+    return respond(
+      &mut stream, "500 Internal Server Error", "text/html",
+      inject_client(&page).as_bytes());
+  }
+  let Some(file) = resolve(&state.files, &path) else {
+    return respond(
+      &mut stream, "404 Not Found", "text/plain",
+      format!("No such file `{}`.", path).as_bytes());
+  };
+  drop(state);
+
+  let contents = fs::read(&file)?;
+  if is_html {
+    let page = inject_client(&String::from_utf8_lossy(&contents));
+    respond(&mut stream, "200 OK", "text/html", page.as_bytes())
+  } else {
+    respond(&mut stream, "200 OK", content_type(&path), &contents)
+  }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) ->
+    Result<(), Box<dyn Error>> {
+  write!(
+    stream,
+    "HTTP/1.1 {}\r\n\
+    Content-Type: {}\r\n\
+    Content-Length: {}\r\n\
+    Cache-Control: no-store\r\n\
+    Connection: close\r\n\r\n",
+    status,
+    content_type,
+    body.len(),
+  )?;
+  stream.write_all(body)?;
+  Ok(())
+}
+
+/// Returns the file served at the given URL path, which is either a served
+/// file or within a served directory.
+fn resolve(files: &BTreeMap<String, PathBuf>, path: &str) -> Option<PathBuf> {
+  if path.split('/').any(|segment| segment.is_empty() || segment == "." || segment == "..") {
+    return None;
+  }
+  let file = files.get(path).cloned().or_else(|| files.iter().find_map(|(served, dir)| {
+    path.strip_prefix(served.as_str())
+      .and_then(|rest| rest.strip_prefix('/'))
+      .map(|rest| dir.join(rest))
+  }))?;
+  file.is_file().then_some(file)
+}
+
+/// Hashes every served file, including those within served directories.
+fn digest_files(files: &BTreeMap<String, PathBuf>) -> BTreeMap<String, Vec<u8>> {
+  fn visit(path: &str, file: &Path, digests: &mut BTreeMap<String, Vec<u8>>) {
+    if file.is_dir() {
+      for entry in fs::read_dir(file).into_iter().flatten().flatten() {
+        let name = entry.file_name();
+        visit(&format!("{}/{}", path, name.to_string_lossy()), &entry.path(), digests);
+      }
+    } else if let Ok(contents) = fs::read(file) {
+      digests.insert(path.to_owned(), Sha256::digest(contents).to_vec());
+    }
+  }
+
+  let mut digests = BTreeMap::new();
+  for (path, file) in files {
+    visit(path, file, &mut digests);
+  }
+  digests
+}
+
+/// Adds the client script to an HTML page, before `</body>` if it has one.
+fn inject_client(html: &str) -> String {
+  match html.rfind("</body>") {
+    Some(end) => format!("{}{}\n{}", &html[..end], CLIENT_SCRIPT, &html[end..]),
+    None => format!("{}\n{}\n", html, CLIENT_SCRIPT),
+  }
+}
+
+fn escape_html(text: &str) -> String {
+  text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn content_type(path: &str) -> &'static str {
+  match path.rsplit_once('.').map_or("", |(_, extension)| extension) {
+    "html" => "text/html",
+    "js" | "mjs" => "text/javascript",
+    "css" => "text/css",
+    "json" | "map" => "application/json",
+    "svg" => "image/svg+xml",
+    "png" => "image/png",
+    "jpg" | "jpeg" => "image/jpeg",
+    "wasm" => "application/wasm",
+    "txt" => "text/plain",
+    _ => "application/octet-stream",
+  }
+}
+
+/// Returns the `Sec-WebSocket-Accept` header answering a handshake with the
+/// given `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+  STANDARD.encode(Sha1::digest(format!("{}{}", key, WEBSOCKET_GUID)))
+}
+
+/// Encodes a WebSocket text frame, which servers send unmasked.
+fn text_frame(text: &str) -> Vec<u8> {
+  let mut frame = vec![0x81];
+  let len = text.len();
+  if len < 126 {
+    frame.push(len as u8);
+  } else if len <= u16::MAX as usize {
+    frame.push(126);
+    frame.extend((len as u16).to_be_bytes());
+  } else {
+    frame.push(127);
+    frame.extend((len as u64).to_be_bytes());
+  }
+  frame.extend(text.as_bytes());
+  frame
+}
+
+/// An error thrown when the dev server cannot serve a target.
+#[derive(Debug)]
+pub struct ServeError(pub String);
+
+impl Display for ServeError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Error for ServeError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::io::Read;
+  use assertables::assert_contains;
+  use crate::host::test_dir::{TestContents, TestDir};
+  use super::*;
+
+  fn get(server: &Server, path: &str) -> Result<String, Box<dyn Error>> {
+    let mut stream = TcpStream::connect(server.address)?;
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+  }
+
+  #[test]
+  fn websocket_handshake_and_frames_follow_rfc_6455() {
+    assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    assert_eq!(text_frame("hi"), [0x81, 2, b'h', b'i']);
+    assert_eq!(text_frame(&"a".repeat(300))[..4], [0x81, 126, 1, 44]);
+  }
+
+  #[test]
+  fn server_serves_outputs_and_notifies_sockets_of_rebuilds() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("index.html"), TestContents::File("<html><body>app</body></html>")),
+      (Path::new("bundle/main-ABC.js"), TestContents::File("console.log(1);")),
+      (Path::new("bundle/style.css"), TestContents::File("body {}")),
+      (Path::new("secret.txt"), TestContents::File("")),
+    ])?;
+    let server = Server::bind(0)?;
+    server.update(Ok(BTreeMap::from([
+      ("index.html".to_owned(), dir.root.join("index.html")),
+      ("bundle".to_owned(), dir.root.join("bundle")),
+    ])));
+
+    let page = get(&server, "/")?;
+    assert_contains!(page, "HTTP/1.1 200 OK");
+    assert_contains!(page, "<html><body>app<script>");
+    assert_contains!(page, "</script>\n</body></html>");
+    let script = get(&server, "/bundle/main-ABC.js?v=1")?;
+    assert_contains!(script, "Content-Type: text/javascript");
+    assert_contains!(script, "console.log(1);");
+    assert_contains!(get(&server, "/bundle/../secret.txt")?, "404 Not Found");
+
+    let mut socket = TcpStream::connect(server.address)?;
+    write!(
+      socket,
+      "GET {} HTTP/1.1\r\n\
+      Upgrade: websocket\r\n\
+      Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+      SOCKET_PATH,
+    )?;
+    let mut reader = BufReader::new(socket);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    assert_eq!(line, "HTTP/1.1 101 Switching Protocols\r\n");
+    while line != "\r\n" {
+      line.clear();
+      reader.read_line(&mut line)?;
+    }
+    let mut read_message = || -> Result<String, Box<dyn Error>> {
+      let mut header = [0; 2];
+      reader.read_exact(&mut header)?;
+      let mut message = vec![0; header[1] as usize];
+      reader.read_exact(&mut message)?;
+      Ok(String::from_utf8(message)?)
+    };
+    // Give the server a moment to register the socket.
+    thread::sleep(Duration::from_millis(50));
+
+    // Only stylesheets changed, so they are swapped without a reload.
+    fs::write(dir.root.join("bundle/style.css"), "body { color: red; }")?;
+    let files = server.state.lock().unwrap().files.clone();
+    server.update(Ok(files.clone()));
+    assert_eq!(read_message()?, r#"{"paths":["bundle/style.css"],"type":"css"}"#);
+
+    // Errors replace pages with an overlay until the next successful build.
+    server.update(Err("Action failed: <boom>".to_owned()));
+    assert_eq!(read_message()?, r#"{"message":"Action failed: <boom>","type":"error"}"#);
+    let overlay = get(&server, "/index.html")?;
+    assert_contains!(overlay, "500 Internal Server Error");
+    assert_contains!(overlay, "<pre id=\"razel-error\">Action failed: &lt;boom&gt;</pre>");
+    assert_contains!(get(&server, "/bundle/main-ABC.js")?, "200 OK");
+
+    server.update(Ok(files));
+    assert_eq!(read_message()?, r#"{"type":"reload"}"#);
+    assert_contains!(get(&server, "/index.html")?, "200 OK");
+
+    Ok(())
+  }
+}
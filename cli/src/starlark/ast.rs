@@ -0,0 +1,237 @@
+use std::rc::Rc;
+use super::error::Location;
+
+/// A parsed Starlark file.
+#[derive(Debug, PartialEq)]
+pub struct Module {
+  /// The workspace-relative path of the file.
+  pub file: Rc<str>,
+  pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Stmt {
+  pub kind: StmtKind,
+  pub location: Location,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StmtKind {
+  Expr(Expr),
+
+  /// `target = value` or an augmented assignment such as `target += value`.
+  Assign {
+    target: Expr,
+    op: Option<BinaryOp>,
+    value: Expr,
+  },
+
+  Def(Rc<FunctionDef>),
+  Return(Option<Expr>),
+
+  If {
+    condition: Expr,
+    then: Vec<Stmt>,
+    otherwise: Vec<Stmt>,
+  },
+
+  For {
+    target: Expr,
+    iterable: Expr,
+    body: Vec<Stmt>,
+  },
+
+  /// `load("//pkg:file.bzl", "symbol", alias = "other_symbol")`.
+  Load {
+    module: String,
+    /// Pairs of `(local_name, exported_name)`.
+    symbols: Vec<(String, String)>,
+  },
+
+  Pass,
+  Break,
+  Continue,
+}
+
+/// A function defined with `def` or `lambda`.
+#[derive(Debug, PartialEq)]
+pub struct FunctionDef {
+  pub name: String,
+  pub params: Vec<Param>,
+  pub body: Vec<Stmt>,
+  pub location: Location,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Param {
+  pub name: String,
+  pub kind: ParamKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParamKind {
+  /// A positional-or-keyword parameter with an optional default.
+  Normal(Option<Expr>),
+
+  /// `*args`, collecting extra positional arguments.
+  Args,
+
+  /// `**kwargs`, collecting extra keyword arguments.
+  Kwargs,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Expr {
+  pub kind: ExprKind,
+  pub location: Location,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExprKind {
+  Ident(String),
+  None,
+  Bool(bool),
+  Int(i64),
+  Str(String),
+  List(Vec<Expr>),
+  Tuple(Vec<Expr>),
+  Dict(Vec<(Expr, Expr)>),
+
+  Call {
+    function: Box<Expr>,
+    args: Vec<Arg>,
+  },
+
+  Dot {
+    object: Box<Expr>,
+    name: String,
+  },
+
+  Index {
+    object: Box<Expr>,
+    index: Box<Expr>,
+  },
+
+  Slice {
+    object: Box<Expr>,
+    start: Option<Box<Expr>>,
+    stop: Option<Box<Expr>>,
+    step: Option<Box<Expr>>,
+  },
+
+  Unary {
+    op: UnaryOp,
+    operand: Box<Expr>,
+  },
+
+  Binary {
+    op: BinaryOp,
+    lhs: Box<Expr>,
+    rhs: Box<Expr>,
+  },
+
+  /// `then if condition else otherwise`.
+  Conditional {
+    condition: Box<Expr>,
+    then: Box<Expr>,
+    otherwise: Box<Expr>,
+  },
+
+  /// `[body for ... if ...]`.
+  ListComprehension {
+    body: Box<Expr>,
+    clauses: Vec<Clause>,
+  },
+
+  /// `{key: value for ... if ...}`.
+  DictComprehension {
+    key: Box<Expr>,
+    value: Box<Expr>,
+    clauses: Vec<Clause>,
+  },
+
+  Lambda(Rc<FunctionDef>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Clause {
+  For {
+    target: Expr,
+    iterable: Expr,
+  },
+  If(Expr),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Arg {
+  Positional(Expr),
+  Keyword(String, Expr),
+
+  /// `*args`, spreading a sequence into positional arguments.
+  Args(Expr),
+
+  /// `**kwargs`, spreading a dict into keyword arguments.
+  Kwargs(Expr),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+  Negate,
+  Plus,
+  Not,
+  Invert,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+  Add,
+  Subtract,
+  Multiply,
+  Divide,
+  FloorDivide,
+  Modulo,
+  Equal,
+  NotEqual,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  In,
+  NotIn,
+  And,
+  Or,
+  BitOr,
+  BitAnd,
+  BitXor,
+  ShiftLeft,
+  ShiftRight,
+}
+
+impl BinaryOp {
+  /// The operator as written in source.
+  pub fn symbol(&self) -> &'static str {
+    match self {
+      BinaryOp::Add => "+",
+      BinaryOp::Subtract => "-",
+      BinaryOp::Multiply => "*",
+      BinaryOp::Divide => "/",
+      BinaryOp::FloorDivide => "//",
+      BinaryOp::Modulo => "%",
+      BinaryOp::Equal => "==",
+      BinaryOp::NotEqual => "!=",
+      BinaryOp::Less => "<",
+      BinaryOp::LessEqual => "<=",
+      BinaryOp::Greater => ">",
+      BinaryOp::GreaterEqual => ">=",
+      BinaryOp::In => "in",
+      BinaryOp::NotIn => "not in",
+      BinaryOp::And => "and",
+      BinaryOp::Or => "or",
+      BinaryOp::BitOr => "|",
+      BinaryOp::BitAnd => "&",
+      BinaryOp::BitXor => "^",
+      BinaryOp::ShiftLeft => "<<",
+      BinaryOp::ShiftRight => ">>",
+    }
+  }
+}
@@ -0,0 +1,357 @@
+use std::collections::{BTreeMap, HashMap};
+use super::error::EvalError;
+use super::eval::{call_each, overflow, Evaluator, MAX_SEQUENCE_LENGTH};
+use super::methods;
+use super::value::{Args, Struct, Value};
+
+/// Returns the builtins available to every Starlark file.
+pub fn universe() -> HashMap<String, Value> {
+  let builtins = [
+    Value::builtin("len", len),
+    Value::builtin("str", |_, args| {
+      let [value] = one("str", args)?;
+      Ok(Value::str(&value.to_str()))
+    }),
+    Value::builtin("repr", |_, args| {
+      let [value] = one("repr", args)?;
+      Ok(Value::str(&value.repr()))
+    }),
+    Value::builtin("bool", |_, args| {
+      let [value] = args.bind("bool", &[], &["x"])?.try_into().unwrap();
+      Ok(Value::Bool(value.is_some_and(|value| value.truthy())))
+    }),
+    Value::builtin("int", int),
+    Value::builtin("list", |_, args| {
+      let [value] = args.bind("list", &[], &["x"])?.try_into().unwrap();
+      Ok(Value::list(value.map_or(Ok(Vec::new()), |value| value.iterate())?))
+    }),
+    Value::builtin("tuple", |_, args| {
+      let [value] = args.bind("tuple", &[], &["x"])?.try_into().unwrap();
+      Ok(Value::tuple(value.map_or(Ok(Vec::new()), |value| value.iterate())?))
+    }),
+    Value::builtin("dict", dict),
+    Value::builtin("range", range),
+    Value::builtin("enumerate", |_, args| {
+      let [items, start] = args.bind("enumerate", &["x"], &["start"])?.try_into().unwrap();
+      let start = start.map_or(Ok(0), |start| start.expect_int("start"))?;
+      Ok(Value::list(items.unwrap().iterate()?.into_iter().zip(start..)
+        .map(|(item, index)| Value::tuple(vec![Value::Int(index), item]))
+        .collect()))
+    }),
+    Value::builtin("zip", |_, args| {
+      let lists = args.positional.iter()
+        .map(|value| value.iterate())
+        .collect::<Result<Vec<_>, _>>()?;
+      let len = lists.iter().map(|list| list.len()).min().unwrap_or(0);
+      Ok(Value::list((0..len)
+        .map(|index| Value::tuple(lists.iter().map(|list| list[index].clone()).collect()))
+        .collect()))
+    }),
+    Value::builtin("sorted", sorted),
+    Value::builtin("reversed", |_, args| {
+      let [items] = one("reversed", args)?;
+      Ok(Value::list(items.iterate()?.into_iter().rev().collect()))
+    }),
+    Value::builtin("any", |_, args| {
+      let [items] = one("any", args)?;
+      Ok(Value::Bool(items.iterate()?.iter().any(|item| item.truthy())))
+    }),
+    Value::builtin("all", |_, args| {
+      let [items] = one("all", args)?;
+      Ok(Value::Bool(items.iterate()?.iter().all(|item| item.truthy())))
+    }),
+    Value::builtin("min", |eval, args| extreme(eval, "min", args, true)),
+    Value::builtin("max", |eval, args| extreme(eval, "max", args, false)),
+    Value::builtin("abs", |_, args| {
+      let [value] = one("abs", args)?;
+      Ok(Value::Int(value.expect_int("abs() argument")?.checked_abs().ok_or_else(overflow)?))
+    }),
+    Value::builtin("hasattr", |_, args| {
+      let [object, name] = two("hasattr", args, "name")?;
+      Ok(Value::Bool(methods::get_attr(&object, name.expect_str("name")?).is_some()))
+    }),
+    Value::builtin("getattr", |_, args| {
+      let [object, name, default] = args.bind("getattr", &["x", "name"], &["default"])?
+        .try_into()
+        .unwrap();
+      let (object, name) = (object.unwrap(), name.unwrap());
+      let name = name.expect_str("name")?;
+      methods::get_attr(&object, name).or(default).ok_or_else(|| {
+        EvalError::msg(format!("`{}` has no field or method `{}`.", object.type_name(), name))
+      })
+    }),
+    Value::builtin("dir", |_, args| {
+      let [object] = one("dir", args)?;
+      let mut names = methods::attr_names(&object);
+      names.sort();
+      Ok(Value::list(names.iter().map(|name| Value::str(name)).collect()))
+    }),
+    Value::builtin("type", |_, args| {
+      let [value] = one("type", args)?;
+      Ok(Value::str(&value.type_name()))
+    }),
+    Value::builtin("struct", |_, args| {
+      if !args.positional.is_empty() {
+        return Err(EvalError::msg("struct() only accepts keyword arguments."));
+      }
+      Ok(Value::object(Struct { fields: args.named.into_iter().collect::<BTreeMap<_, _>>() }))
+    }),
+    Value::builtin("fail", |_, args| {
+      let message = args.positional.iter().map(|value| value.to_str()).collect::<Vec<_>>();
+      let attr = args.named.iter().find(|(name, _)| name == "attr");
+      Err(EvalError::msg(match attr {
+        Some((_, attr)) => format!("attribute {}: {}", attr.to_str(), message.join(" ")),
+        None => message.join(" "),
+      }))
+    }),
+    Value::builtin("print", |eval, args| {
+      let sep = args.named.iter()
+        .find(|(name, _)| name == "sep")
+        .map_or(Ok(" "), |(_, sep)| sep.expect_str("sep"))?;
+      let message = args.positional.iter().map(|value| value.to_str()).collect::<Vec<_>>();
+      match eval.location() {
+        Some(location) => eprintln!("DEBUG: {}: {}", location, message.join(sep)),
+        None => eprintln!("DEBUG: {}", message.join(sep)),
+      }
+      Ok(Value::None)
+    }),
+  ];
+
+  builtins.into_iter()
+    .map(|builtin| match &builtin {
+      Value::Builtin(function) => (function.name.clone(), builtin),
+      _ => unreachable!(),
+    })
+    .collect()
+}
+
+/// Binds the single positional argument of a builtin.
+fn one(function: &str, args: Args) -> Result<[Value; 1], EvalError> {
+  let [value] = args.bind(function, &["x"], &[])?.try_into().unwrap();
+  Ok([value.unwrap()])
+}
+
+/// Binds the two required arguments of a builtin.
+fn two(function: &str, args: Args, second: &str) -> Result<[Value; 2], EvalError> {
+  let [first, second] = args.bind(function, &["x", second], &[])?.try_into().unwrap();
+  Ok([first.unwrap(), second.unwrap()])
+}
+
+fn len(_: &mut Evaluator, args: Args) -> Result<Value, EvalError> {
+  let [value] = one("len", args)?;
+  let len = match &value {
+    Value::Str(value) => value.chars().count(),
+    Value::List(_) | Value::Tuple(_) | Value::Dict(_) => value.iterate()?.len(),
+    _ => return Err(EvalError::msg(format!("`{}` has no length.", value.type_name()))),
+  };
+  Ok(Value::Int(len as i64))
+}
+
+fn int(_: &mut Evaluator, args: Args) -> Result<Value, EvalError> {
+  let [value, base] = args.bind("int", &["x"], &["base"])?.try_into().unwrap();
+  match (value.unwrap(), base) {
+    (Value::Int(value), None) => Ok(Value::Int(value)),
+    (Value::Bool(value), None) => Ok(Value::Int(value as i64)),
+    (Value::Str(value), base) => {
+      let base = base.map_or(Ok(10), |base| base.expect_int("base"))?;
+      let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value.strip_prefix('+').unwrap_or(&value)),
+      };
+      let digits = match base {
+        16 => digits.strip_prefix("0x").unwrap_or(digits),
+        8 => digits.strip_prefix("0o").unwrap_or(digits),
+        2 => digits.strip_prefix("0b").unwrap_or(digits),
+        _ => digits,
+      };
+      let parsed = u32::try_from(base).ok()
+        .filter(|base| (2..=36).contains(base))
+        .and_then(|base| i64::from_str_radix(digits, base).ok())
+        .filter(|_| !digits.starts_with(['-', '+']))
+        .ok_or_else(|| EvalError::msg(format!(
+          "Invalid literal for int(): {}.",
+          Value::Str(value.clone()).repr(),
+        )))?;
+      Ok(Value::Int(if negative { -parsed } else { parsed }))
+    },
+    (value, _) => Err(value.type_error("int() argument", "string, bool or int")),
+  }
+}
+
+fn dict(_: &mut Evaluator, args: Args) -> Result<Value, EvalError> {
+  if args.positional.len() > 1 {
+    return Err(EvalError::msg("dict() accepts at most 1 positional argument."));
+  }
+
+  let mut entries = Vec::new();
+  if let Some(value) = args.positional.first() {
+    entries = pairs(value)?;
+  }
+  entries.extend(args.named.into_iter().map(|(name, value)| (Value::str(&name), value)));
+  Value::dict(entries)
+}
+
+/// Returns the entries of a dict or of a list of pairs.
+pub fn pairs(value: &Value) -> Result<Vec<(Value, Value)>, EvalError> {
+  if let Value::Dict(dict) = value {
+    return Ok(dict.entries().clone());
+  }
+
+  value.iterate()?.into_iter()
+    .map(|pair| match pair.iterate()?.as_slice() {
+      [key, value] => Ok((key.clone(), value.clone())),
+      _ => Err(EvalError::msg("Expected a sequence of key/value pairs.")),
+    })
+    .collect()
+}
+
+fn range(_: &mut Evaluator, args: Args) -> Result<Value, EvalError> {
+  let [a, b, step] = args.bind("range", &["start_or_stop"], &["stop", "step"])?
+    .try_into()
+    .unwrap();
+  let a = a.unwrap().expect_int("range() argument")?;
+  let (start, stop) = match b {
+    Some(b) => (a, b.expect_int("range() argument")?),
+    None => (0, a),
+  };
+  let step = step.map_or(Ok(1), |step| step.expect_int("step"))?;
+  if step == 0 {
+    return Err(EvalError::msg("range() step cannot be zero."));
+  }
+
+  // Computed in 128 bits so neither the length nor the last value overflows.
+  let (start, stop, step) = (start as i128, stop as i128, step as i128);
+  let len = match step > 0 {
+    true => (stop - start + step - 1) / step,
+    false => (start - stop - step - 1) / -step,
+  }.max(0);
+  if len > MAX_SEQUENCE_LENGTH as i128 {
+    return Err(EvalError::msg(format!(
+      "range() of length {} exceeds the maximum length of {}.",
+      len,
+      MAX_SEQUENCE_LENGTH,
+    )));
+  }
+  Ok(Value::list((0..len).map(|index| Value::Int((start + index * step) as i64)).collect()))
+}
+
+fn sorted(eval: &mut Evaluator, args: Args) -> Result<Value, EvalError> {
+  let [items, key, reverse] = args.bind("sorted", &["x"], &["key", "reverse"])?
+    .try_into()
+    .unwrap();
+  let items = items.unwrap().iterate()?;
+  let keys = match key {
+    Some(Value::None) | None => items.clone(),
+    Some(key) => call_each(eval, &key, &items)?,
+  };
+
+  let mut keyed: Vec<_> = keys.into_iter().zip(items).collect();
+  let mut err = None;
+  keyed.sort_by(|(a, _), (b, _)| a.compare(b).unwrap_or_else(|e| {
+    err.get_or_insert(e);
+    std::cmp::Ordering::Equal
+  }));
+  if let Some(err) = err {
+    return Err(err);
+  }
+  if reverse.is_some_and(|reverse| reverse.truthy()) {
+    keyed.reverse();
+  }
+  Ok(Value::list(keyed.into_iter().map(|(_, item)| item).collect()))
+}
+
+fn extreme(eval: &mut Evaluator, function: &str, args: Args, min: bool) ->
+    Result<Value, EvalError> {
+  let key = args.named.iter().find(|(name, _)| name == "key").map(|(_, key)| key.clone());
+  let items = match args.positional.as_slice() {
+    [items] => items.iterate()?,
+    items => items.to_vec(),
+  };
+  let keys = match key {
+    Some(key) => call_each(eval, &key, &items)?,
+    None => items.clone(),
+  };
+
+  let mut best: Option<usize> = None;
+  for index in 0..items.len() {
+    let better = match best {
+      Some(best) => {
+        let ordering = keys[index].compare(&keys[best])?;
+        if min { ordering.is_lt() } else { ordering.is_gt() }
+      },
+      None => true,
+    };
+    if better {
+      best = Some(index);
+    }
+  }
+  best.map(|index| items[index].clone())
+    .ok_or_else(|| EvalError::msg(format!("{}() of an empty sequence.", function)))
+}
+
+#[cfg(test)]
+mod test {
+  use std::rc::Rc;
+  use assertables::*;
+  use super::super::eval::ModuleEnv;
+  use super::super::parser::parse;
+  use super::*;
+
+  fn eval_expr(source: &str) -> Result<Value, EvalError> {
+    let module = parse("test.bzl", &format!("x = {}", source))?;
+    let env = ModuleEnv::new("test.bzl", Rc::new(HashMap::new()));
+    Evaluator::new().eval_module(&module, &env)?;
+    Ok(env.get("x").unwrap())
+  }
+
+  #[test]
+  fn universe_provides_builtins() {
+    assert_eq!(eval_expr("len(\"héllo\")").unwrap(), Value::Int(5));
+    assert_eq!(eval_expr("int(\"-0x1f\", 16)").unwrap(), Value::Int(-31));
+    assert_eq!(eval_expr("str([1, \"a\"])").unwrap(), Value::str("[1, \"a\"]"));
+    assert_eq!(eval_expr("range(5, 0, -2)").unwrap().repr(), "[5, 3, 1]");
+    assert_eq!(
+      eval_expr("sorted([\"bb\", \"a\", \"ccc\"], key = len, reverse = True)")
+        .unwrap().repr(),
+      "[\"ccc\", \"bb\", \"a\"]",
+    );
+    assert_eq!(eval_expr("max([3, 1, 2])").unwrap(), Value::Int(3));
+    assert_eq!(eval_expr("min(\"b\", \"a\")").unwrap(), Value::str("a"));
+    assert_eq!(eval_expr("dict([(\"a\", 1)], b = 2)").unwrap().repr(), "{\"a\": 1, \"b\": 2}");
+    assert_eq!(eval_expr("list(enumerate([\"a\"]))").unwrap().repr(), "[(0, \"a\")]");
+    assert_eq!(
+      eval_expr("zip([1, 2], \"ab\".elems())").unwrap().repr(),
+      "[(1, \"a\"), (2, \"b\")]",
+    );
+    assert_eq!(eval_expr("struct(b = 1, a = \"x\")").unwrap().repr(), "struct(a = \"x\", b = 1)");
+    assert_eq!(eval_expr("getattr(struct(a = 1), \"b\", 2)").unwrap(), Value::Int(2));
+    assert_eq!(eval_expr("type({})").unwrap(), Value::str("dict"));
+  }
+
+  #[test]
+  fn universe_errors_instead_of_overflowing() {
+    assert_eq!(
+      eval_expr("abs(-9223372036854775807 - 1)").unwrap_err().to_string(),
+      "test.bzl:1:8: Integer overflow.",
+    );
+    assert_contains!(
+      eval_expr("list(range(9223372036854775807))").unwrap_err().to_string(),
+      "range() of length 9223372036854775807 exceeds the maximum length of 67108864.",
+    );
+    assert_eq!(
+      eval_expr("range(9223372036854775806, 9223372036854775807)")
+        .unwrap().repr(),
+      "[9223372036854775806]",
+    );
+    assert_eq!(eval_expr("range(0, -5, -2)").unwrap().repr(), "[0, -2, -4]");
+  }
+
+  #[test]
+  fn universe_fail_stops_evaluation() {
+    assert_eq!(eval_expr("fail(\"bad\", 1)").unwrap_err().to_string(), "test.bzl:1:9: bad 1");
+    assert_contains!(
+      eval_expr("int(\"12a\")").unwrap_err().to_string(),
+      "Invalid literal for int()",
+    );
+  }
+}
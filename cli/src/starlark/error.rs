@@ -0,0 +1,83 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+
+/// A position in a Starlark source file.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Location {
+  /// The workspace-relative path of the file.
+  pub file: Rc<str>,
+  pub line: usize,
+  pub column: usize,
+}
+
+impl Location {
+  pub fn new(file: &Rc<str>, line: usize, column: usize) -> Location {
+    Location { file: file.clone(), line, column }
+  }
+}
+
+impl Display for Location {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}:{}:{}", self.file, self.line, self.column)
+  }
+}
+
+/// An error from parsing or evaluating Starlark.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalError {
+  /// Where the error occurred, if known.
+  pub location: Option<Location>,
+
+  pub message: String,
+
+  /// The call sites of every function being evaluated when the error
+  /// occurred, innermost first.
+  pub callers: Vec<Location>,
+}
+
+impl EvalError {
+  /// Creates an error at a known location.
+  pub fn new(location: Location, message: impl Into<String>) -> EvalError {
+    EvalError {
+      location: Some(location),
+      message: message.into(),
+      callers: Vec::new(),
+    }
+  }
+
+  /// Creates an error whose location is filled in by the evaluator, used by
+  /// builtins which do not know where they were called from.
+  pub fn msg(message: impl Into<String>) -> EvalError {
+    EvalError { location: None, message: message.into(), callers: Vec::new() }
+  }
+}
+
+impl Display for EvalError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    match &self.location {
+      Some(location) => write!(f, "{}: {}", location, self.message)?,
+      None => write!(f, "{}", self.message)?,
+    }
+    for caller in &self.callers {
+      write!(f, "\n  called from {}", caller)?;
+    }
+
+    Ok(())
+  }
+}
+
+impl Error for EvalError {
+  fn description(&self) -> &str {
+    &self.message
+  }
+}
+
+impl From<Box<dyn Error>> for EvalError {
+  fn from(err: Box<dyn Error>) -> EvalError {
+    match err.downcast::<EvalError>() {
+      Ok(err) => *err,
+      Err(err) => EvalError::msg(err.to_string()),
+    }
+  }
+}
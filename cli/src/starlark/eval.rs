@@ -0,0 +1,1044 @@
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use super::ast::{
+  Arg, BinaryOp, Clause, Expr, ExprKind, FunctionDef, Module, ParamKind, Stmt,
+  StmtKind, UnaryOp,
+};
+use super::builtins;
+use super::error::{EvalError, Location};
+use super::methods;
+use super::value::{Args, Dict, Function, Value};
+
+/// The global environment of one evaluated Starlark file.
+pub struct ModuleEnv {
+  /// The workspace-relative path of the file.
+  pub file: Rc<str>,
+
+  /// Names defined by the file itself, including loaded symbols.
+  pub globals: RefCell<HashMap<String, Value>>,
+
+  /// Names provided by the kind of file being evaluated, such as `glob` in
+  /// BUILD files or `rule` in .bzl files.
+  pub predeclared: Rc<HashMap<String, Value>>,
+}
+
+impl ModuleEnv {
+  pub fn new(file: &str, predeclared: Rc<HashMap<String, Value>>) -> Rc<ModuleEnv> {
+    Rc::new(ModuleEnv {
+      file: Rc::from(file),
+      globals: RefCell::new(HashMap::new()),
+      predeclared,
+    })
+  }
+
+  /// Returns the value of a global defined by this file.
+  pub fn get(&self, name: &str) -> Option<Value> {
+    self.globals.borrow().get(name).cloned()
+  }
+}
+
+/// Resolves the `load()` statements of one file to the modules they refer to.
+pub trait Loader {
+  /// Loads the module `module` as written in a `load()` statement.
+  fn load(&self, module: &str) -> Result<Rc<ModuleEnv>, EvalError>;
+}
+
+/// Evaluates Starlark syntax trees.
+pub struct Evaluator {
+  universe: Rc<HashMap<String, Value>>,
+  loader: Option<Rc<dyn Loader>>,
+
+  /// Values made available to builtins, keyed by type.
+  context: HashMap<TypeId, Rc<dyn Any>>,
+
+  /// The call sites of every call being evaluated, innermost last.
+  call_stack: Vec<Location>,
+
+  /// Every Starlark function being evaluated, to reject recursion.
+  active: Vec<Rc<FunctionDef>>,
+}
+
+/// How control leaves a statement.
+enum Flow {
+  Normal,
+  Return(Value),
+  Break,
+  Continue,
+}
+
+/// The variables visible to the code being evaluated.
+struct Frame {
+  module: Rc<ModuleEnv>,
+  locals: HashMap<String, Value>,
+
+  /// Whether this frame is a function body, where assignments are local.
+  function: bool,
+}
+
+impl Default for Evaluator {
+  fn default() -> Evaluator {
+    Evaluator::new()
+  }
+}
+
+impl Evaluator {
+  pub fn new() -> Evaluator {
+    Evaluator {
+      universe: Rc::new(builtins::universe()),
+      loader: None,
+      context: HashMap::new(),
+      call_stack: Vec::new(),
+      active: Vec::new(),
+    }
+  }
+
+  pub fn set_loader(&mut self, loader: Rc<dyn Loader>) {
+    self.loader = Some(loader);
+  }
+
+  /// Makes a value available to builtins through `context()`.
+  pub fn set_context<T: Any>(&mut self, value: Rc<T>) {
+    self.context.insert(TypeId::of::<T>(), value);
+  }
+
+  /// Returns the value of the given type made available with `set_context()`.
+  pub fn context<T: Any>(&self) -> Option<Rc<T>> {
+    self.context.get(&TypeId::of::<T>())
+      .and_then(|value| value.clone().downcast::<T>().ok())
+  }
+
+  /// The location of the innermost call being evaluated.
+  pub fn location(&self) -> Option<&Location> {
+    self.call_stack.last()
+  }
+
+  /// Evaluates every statement of a file into its environment.
+  pub fn eval_module(&mut self, module: &Module, env: &Rc<ModuleEnv>) -> Result<(), EvalError> {
+    let mut frame = Frame { module: env.clone(), locals: HashMap::new(), function: false };
+    for stmt in &module.body {
+      match self.exec(stmt, &mut frame)? {
+        Flow::Normal => {},
+        _ => return Err(EvalError::new(
+          stmt.location.clone(),
+          "`return`, `break` and `continue` must be used inside a function or loop.",
+        )),
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Calls a function value.
+  pub fn call(&mut self, function: &Value, args: Args) -> Result<Value, EvalError> {
+    match function {
+      Value::Function(function) => self.call_function(function, args),
+      Value::Builtin(builtin) => (builtin.function)(self, args),
+      Value::Object(object) => object.clone().call(self, args),
+      _ => Err(EvalError::msg(format!("`{}` is not callable.", function.type_name()))),
+    }
+  }
+
+  fn call_at(&mut self, function: &Value, args: Args, location: &Location) ->
+      Result<Value, EvalError> {
+    self.call_stack.push(location.clone());
+    let result = self.call(function, args);
+    self.call_stack.pop();
+
+    result.map_err(|mut err| {
+      if err.location.is_none() {
+        err.location = Some(location.clone());
+      } else if matches!(function, Value::Function(_)) {
+        err.callers.push(location.clone());
+      }
+      err
+    })
+  }
+
+  fn call_function(&mut self, function: &Rc<Function>, args: Args) -> Result<Value, EvalError> {
+    let def = &function.def;
+    if self.active.iter().any(|active| Rc::ptr_eq(active, def)) {
+      return Err(EvalError::msg(format!("Function `{}` called recursively.", def.name)));
+    }
+
+    let mut locals = HashMap::new();
+    let mut positional = args.positional.into_iter();
+    let mut kwargs = None;
+    let mut keyword_only = false;
+    for param in &def.params {
+      match &param.kind {
+        ParamKind::Normal(_) => {
+          if !keyword_only {
+            if let Some(value) = positional.next() {
+              locals.insert(param.name.clone(), value);
+            }
+          }
+        },
+        ParamKind::Args => {
+          keyword_only = true;
+          locals.insert(param.name.clone(), Value::tuple(positional.by_ref().collect()));
+        },
+        ParamKind::Kwargs => kwargs = Some((param.name.clone(), Vec::new())),
+      }
+    }
+    let extra = positional.count();
+    if extra > 0 {
+      return Err(EvalError::msg(format!(
+        "{}() got {} unexpected positional argument(s).",
+        def.name,
+        extra,
+      )));
+    }
+
+    for (name, value) in args.named {
+      let param = def.params.iter()
+        .find(|param| param.name == name && matches!(param.kind, ParamKind::Normal(_)));
+      match (param, &mut kwargs) {
+        (Some(_), _) if locals.contains_key(&name) => {
+          return Err(EvalError::msg(format!(
+            "{}() got multiple values for argument `{}`.",
+            def.name,
+            name,
+          )));
+        },
+        (Some(_), _) => {
+          locals.insert(name, value);
+        },
+        (None, Some((_, entries))) => entries.push((Value::str(&name), value)),
+        (None, None) => {
+          return Err(EvalError::msg(format!(
+            "{}() got an unexpected keyword argument `{}`.",
+            def.name,
+            name,
+          )));
+        },
+      }
+    }
+    if let Some((name, entries)) = kwargs {
+      locals.insert(name, Value::dict(entries)?);
+    }
+
+    for (param, default) in def.params.iter().zip(function.defaults.iter()) {
+      if locals.contains_key(&param.name) {
+        continue;
+      }
+      match default {
+        Some(default) => {
+          locals.insert(param.name.clone(), default.clone());
+        },
+        None => return Err(EvalError::msg(format!(
+          "{}() missing required argument `{}`.",
+          def.name,
+          param.name,
+        ))),
+      }
+    }
+
+    let mut frame = Frame { module: function.module.clone(), locals, function: true };
+    self.active.push(def.clone());
+    let result = self.exec_block(&def.body, &mut frame);
+    self.active.pop();
+
+    match result? {
+      Flow::Return(value) => Ok(value),
+      _ => Ok(Value::None),
+    }
+  }
+
+  fn exec_block(&mut self, body: &[Stmt], frame: &mut Frame) -> Result<Flow, EvalError> {
+    for stmt in body {
+      match self.exec(stmt, frame)? {
+        Flow::Normal => {},
+        flow => return Ok(flow),
+      }
+    }
+
+    Ok(Flow::Normal)
+  }
+
+  fn exec(&mut self, stmt: &Stmt, frame: &mut Frame) -> Result<Flow, EvalError> {
+    match &stmt.kind {
+      StmtKind::Expr(expr) => {
+        self.eval(expr, frame)?;
+      },
+      StmtKind::Assign { target, op: None, value } => {
+        let value = self.eval(value, frame)?;
+        let local = frame.function;
+        self.assign(target, value, frame, local)?;
+      },
+      StmtKind::Assign { target, op: Some(op), value } => {
+        self.exec_augmented(target, *op, value, frame)?;
+      },
+      StmtKind::Def(def) => {
+        let function = self.define(def, frame)?;
+        let local = frame.function;
+        self.bind(&def.name, function, frame, local);
+      },
+      StmtKind::Return(value) => {
+        let value = match value {
+          Some(value) => self.eval(value, frame)?,
+          None => Value::None,
+        };
+        return Ok(Flow::Return(value));
+      },
+      StmtKind::If { condition, then, otherwise } => {
+        return if self.eval(condition, frame)?.truthy() {
+          self.exec_block(then, frame)
+        } else {
+          self.exec_block(otherwise, frame)
+        };
+      },
+      StmtKind::For { target, iterable, body } => {
+        let iterable_value = self.eval(iterable, frame)?;
+        let items = iterable_value.iterate()
+          .map_err(|err| locate(err, &iterable.location))?;
+        let _guard = iterable_value.lock_for_iteration();
+        for item in items {
+          let local = frame.function;
+          self.assign(target, item, frame, local)?;
+          match self.exec_block(body, frame)? {
+            Flow::Normal | Flow::Continue => {},
+            Flow::Break => break,
+            flow => return Ok(flow),
+          }
+        }
+      },
+      StmtKind::Load { module, symbols } => {
+        let loader = self.loader.clone().ok_or_else(|| {
+          EvalError::new(stmt.location.clone(), "`load()` is not allowed here.")
+        })?;
+        let loaded = loader.load(module)
+          .map_err(|err| locate(err, &stmt.location))?;
+        for (local, exported) in symbols {
+          let value = loaded.get(exported).filter(|_| !exported.starts_with('_')).ok_or_else(|| {
+            EvalError::new(
+              stmt.location.clone(),
+              format!("`{}` does not export `{}`.", module, exported),
+            )
+          })?;
+          frame.module.globals.borrow_mut().insert(local.clone(), value);
+        }
+      },
+      StmtKind::Pass => {},
+      StmtKind::Break => return Ok(Flow::Break),
+      StmtKind::Continue => return Ok(Flow::Continue),
+    }
+
+    Ok(Flow::Normal)
+  }
+
+  fn exec_augmented(&mut self, target: &Expr, op: BinaryOp, value: &Expr, frame: &mut Frame) ->
+      Result<(), EvalError> {
+    match &target.kind {
+      ExprKind::Index { object, index } => {
+        let object = self.eval(object, frame)?;
+        let index = self.eval(index, frame)?;
+        let current = self.index(&object, &index).map_err(|err| locate(err, &target.location))?;
+        let value = self.eval(value, frame)?;
+        let value = augment(op, current, value).map_err(|err| locate(err, &target.location))?;
+        set_index(&object, index, value).map_err(|err| locate(err, &target.location))
+      },
+      ExprKind::Ident(_) => {
+        let current = self.eval(target, frame)?;
+        let value = self.eval(value, frame)?;
+        let value = augment(op, current, value).map_err(|err| locate(err, &target.location))?;
+        let local = frame.function;
+        self.assign(target, value, frame, local)
+      },
+      _ => Err(EvalError::new(target.location.clone(), "Cannot assign to this expression.")),
+    }
+  }
+
+  fn define(&mut self, def: &Rc<FunctionDef>, frame: &mut Frame) -> Result<Value, EvalError> {
+    let mut defaults = Vec::new();
+    for param in &def.params {
+      defaults.push(match &param.kind {
+        ParamKind::Normal(Some(default)) => Some(self.eval(default, frame)?),
+        ParamKind::Normal(None) => None,
+        // Variadic parameters are always bound, so never need a default.
+        ParamKind::Args | ParamKind::Kwargs => Some(Value::None),
+      });
+    }
+
+    Ok(Value::Function(Rc::new(Function {
+      def: def.clone(),
+      defaults,
+      module: frame.module.clone(),
+    })))
+  }
+
+  fn bind(&mut self, name: &str, value: Value, frame: &mut Frame, local: bool) {
+    if local {
+      frame.locals.insert(name.to_owned(), value);
+    } else {
+      frame.module.globals.borrow_mut().insert(name.to_owned(), value);
+    }
+  }
+
+  fn assign(&mut self, target: &Expr, value: Value, frame: &mut Frame, local: bool) ->
+      Result<(), EvalError> {
+    match &target.kind {
+      ExprKind::Ident(name) => {
+        self.bind(name, value, frame, local);
+        Ok(())
+      },
+      ExprKind::List(targets) | ExprKind::Tuple(targets) => {
+        let items = value.iterate().map_err(|err| locate(err, &target.location))?;
+        if items.len() != targets.len() {
+          return Err(EvalError::new(target.location.clone(), format!(
+            "Cannot unpack {} value(s) into {} variable(s).",
+            items.len(),
+            targets.len(),
+          )));
+        }
+        for (target, item) in targets.iter().zip(items) {
+          self.assign(target, item, frame, local)?;
+        }
+        Ok(())
+      },
+      ExprKind::Index { object, index } => {
+        let object = self.eval(object, frame)?;
+        let index = self.eval(index, frame)?;
+        set_index(&object, index, value).map_err(|err| locate(err, &target.location))
+      },
+      _ => Err(EvalError::new(target.location.clone(), "Cannot assign to this expression.")),
+    }
+  }
+
+  fn lookup(&self, name: &str, frame: &Frame) -> Option<Value> {
+    frame.locals.get(name).cloned()
+      .or_else(|| frame.module.get(name))
+      .or_else(|| frame.module.predeclared.get(name).cloned())
+      .or_else(|| self.universe.get(name).cloned())
+  }
+
+  fn eval(&mut self, expr: &Expr, frame: &mut Frame) -> Result<Value, EvalError> {
+    self.eval_inner(expr, frame).map_err(|err| locate(err, &expr.location))
+  }
+
+  fn eval_inner(&mut self, expr: &Expr, frame: &mut Frame) -> Result<Value, EvalError> {
+    Ok(match &expr.kind {
+      ExprKind::Ident(name) => self.lookup(name, frame).ok_or_else(|| {
+        EvalError::msg(format!("Name `{}` is not defined.", name))
+      })?,
+      ExprKind::None => Value::None,
+      ExprKind::Bool(value) => Value::Bool(*value),
+      ExprKind::Int(value) => Value::Int(*value),
+      ExprKind::Str(value) => Value::str(value),
+      ExprKind::List(items) => Value::list(self.eval_all(items, frame)?),
+      ExprKind::Tuple(items) => Value::tuple(self.eval_all(items, frame)?),
+      ExprKind::Dict(entries) => {
+        let dict = Dict::default();
+        for (key, value) in entries {
+          let key = self.eval(key, frame)?;
+          let value = self.eval(value, frame)?;
+          dict.insert(key, value)?;
+        }
+        Value::Dict(Rc::new(dict))
+      },
+      ExprKind::Call { function, args } => {
+        let function = self.eval(function, frame)?;
+        let args = self.eval_args(args, frame)?;
+        self.call_at(&function, args, &expr.location)?
+      },
+      ExprKind::Dot { object, name } => {
+        let object = self.eval(object, frame)?;
+        methods::get_attr(&object, name).ok_or_else(|| {
+          EvalError::msg(format!("`{}` has no field or method `{}`.", object.type_name(), name))
+        })?
+      },
+      ExprKind::Index { object, index } => {
+        let object = self.eval(object, frame)?;
+        let index = self.eval(index, frame)?;
+        self.index(&object, &index)?
+      },
+      ExprKind::Slice { object, start, stop, step } => {
+        let object = self.eval(object, frame)?;
+        let mut bound = |expr: &Option<Box<Expr>>| -> Result<Option<i64>, EvalError> {
+          match expr {
+            Some(expr) => match self.eval(expr, frame)? {
+              Value::None => Ok(None),
+              value => value.expect_int("slice index").map(Some),
+            },
+            None => Ok(None),
+          }
+        };
+        let start = bound(start)?;
+        let stop = bound(stop)?;
+        let step = bound(step)?;
+        slice(&object, start, stop, step)?
+      },
+      ExprKind::Unary { op, operand } => {
+        let operand = self.eval(operand, frame)?;
+        match (op, operand) {
+          (UnaryOp::Not, operand) => Value::Bool(!operand.truthy()),
+          (UnaryOp::Negate, Value::Int(value)) => {
+            Value::Int(value.checked_neg().ok_or_else(overflow)?)
+          },
+          (UnaryOp::Plus, Value::Int(value)) => Value::Int(value),
+          (UnaryOp::Invert, Value::Int(value)) => Value::Int(!value),
+          (_, operand) => return Err(EvalError::msg(format!(
+            "Unsupported operand for unary operator: `{}`.",
+            operand.type_name(),
+          ))),
+        }
+      },
+      ExprKind::Binary { op: BinaryOp::And, lhs, rhs } => {
+        let lhs = self.eval(lhs, frame)?;
+        if lhs.truthy() { self.eval(rhs, frame)? } else { lhs }
+      },
+      ExprKind::Binary { op: BinaryOp::Or, lhs, rhs } => {
+        let lhs = self.eval(lhs, frame)?;
+        if lhs.truthy() { lhs } else { self.eval(rhs, frame)? }
+      },
+      ExprKind::Binary { op, lhs, rhs } => {
+        let lhs = self.eval(lhs, frame)?;
+        let rhs = self.eval(rhs, frame)?;
+        binary_op(*op, &lhs, &rhs)?
+      },
+      ExprKind::Conditional { condition, then, otherwise } => {
+        if self.eval(condition, frame)?.truthy() {
+          self.eval(then, frame)?
+        } else {
+          self.eval(otherwise, frame)?
+        }
+      },
+      ExprKind::ListComprehension { body, clauses } => {
+        let mut items = Vec::new();
+        self.comprehension(clauses, frame, &mut |eval, frame| {
+          items.push(eval.eval(body, frame)?);
+          Ok(())
+        })?;
+        Value::list(items)
+      },
+      ExprKind::DictComprehension { key, value, clauses } => {
+        let dict = Dict::default();
+        self.comprehension(clauses, frame, &mut |eval, frame| {
+          let key = eval.eval(key, frame)?;
+          let value = eval.eval(value, frame)?;
+          dict.insert(key, value).map_err(|err| locate(err, &expr.location))
+        })?;
+        Value::Dict(Rc::new(dict))
+      },
+      ExprKind::Lambda(def) => self.define(def, frame)?,
+    })
+  }
+
+  fn eval_all(&mut self, exprs: &[Expr], frame: &mut Frame) -> Result<Vec<Value>, EvalError> {
+    exprs.iter().map(|expr| self.eval(expr, frame)).collect()
+  }
+
+  fn eval_args(&mut self, args: &[Arg], frame: &mut Frame) -> Result<Args, EvalError> {
+    let mut result = Args::default();
+    let add_named = |result: &mut Args, name: String, value: Value| {
+      if result.named.iter().any(|(other, _)| *other == name) {
+        return Err(EvalError::msg(format!("Keyword argument `{}` passed more than once.", name)));
+      }
+      result.named.push((name, value));
+      Ok(())
+    };
+
+    for arg in args {
+      match arg {
+        Arg::Positional(expr) => result.positional.push(self.eval(expr, frame)?),
+        Arg::Keyword(name, expr) => {
+          let value = self.eval(expr, frame)?;
+          add_named(&mut result, name.clone(), value)?;
+        },
+        Arg::Args(expr) => {
+          let value = self.eval(expr, frame)?;
+          result.positional.extend(value.iterate().map_err(|err| locate(err, &expr.location))?);
+        },
+        Arg::Kwargs(expr) => {
+          let value = self.eval(expr, frame)?;
+          for (name, value) in value.expect_dict("`**` argument")? {
+            add_named(&mut result, name.expect_str("keyword argument name")?.to_owned(), value)?;
+          }
+        },
+      }
+    }
+
+    Ok(result)
+  }
+
+  /// Runs `body` once for each combination of values produced by the
+  /// comprehension's clauses. Loop variables are local to the comprehension.
+  fn comprehension(
+    &mut self,
+    clauses: &[Clause],
+    frame: &mut Frame,
+    body: &mut dyn FnMut(&mut Evaluator, &mut Frame) -> Result<(), EvalError>,
+  ) -> Result<(), EvalError> {
+    let Some((clause, rest)) = clauses.split_first() else {
+      return body(self, frame);
+    };
+
+    match clause {
+      Clause::If(condition) => {
+        if self.eval(condition, frame)?.truthy() {
+          self.comprehension(rest, frame, body)?;
+        }
+        Ok(())
+      },
+      Clause::For { target, iterable } => {
+        let iterable_value = self.eval(iterable, frame)?;
+        let items = iterable_value.iterate()
+          .map_err(|err| locate(err, &iterable.location))?;
+        let _guard = iterable_value.lock_for_iteration();
+        let mut names = Vec::new();
+        bound_names(target, &mut names);
+        let saved: Vec<_> = names.iter()
+          .map(|name| (*name, frame.locals.get(*name).cloned()))
+          .collect();
+
+        let mut result = Ok(());
+        for item in items {
+          result = self.assign(target, item, frame, true)
+            .and_then(|_| self.comprehension(rest, frame, body));
+          if result.is_err() {
+            break;
+          }
+        }
+
+        for (name, value) in saved {
+          match value {
+            Some(value) => frame.locals.insert(name.to_owned(), value),
+            None => frame.locals.remove(name),
+          };
+        }
+        result
+      },
+    }
+  }
+
+  /// Returns `object[index]`.
+  pub fn index(&mut self, object: &Value, index: &Value) -> Result<Value, EvalError> {
+    match object {
+      Value::List(_) | Value::Tuple(_) => {
+        let items = object.iterate()?;
+        let index = normalize_index(index.expect_int("index")?, items.len())?;
+        Ok(items[index].clone())
+      },
+      Value::Str(value) => {
+        let chars: Vec<_> = value.chars().collect();
+        let index = normalize_index(index.expect_int("index")?, chars.len())?;
+        Ok(Value::str(&chars[index].to_string()))
+      },
+      Value::Dict(dict) => dict.get(index)?
+        .ok_or_else(|| EvalError::msg(format!("Key {} not found in dict.", index.repr()))),
+      Value::Object(object) => object.index(index),
+      _ => Err(EvalError::msg(format!("`{}` is not indexable.", object.type_name()))),
+    }
+  }
+}
+
+/// Fills in the location of an error raised without one.
+fn locate(mut err: EvalError, location: &Location) -> EvalError {
+  if err.location.is_none() {
+    err.location = Some(location.clone());
+  }
+  err
+}
+
+/// The longest string, list or tuple a repetition or `range()` may produce,
+/// which keeps `"a" * 1000000000000` from exhausting memory.
+pub const MAX_SEQUENCE_LENGTH: usize = 1 << 26;
+
+/// Returns how many times a sequence of the given length is repeated when
+/// multiplied by `n`, failing if the result would be too long.
+fn repeat_count(type_name: &str, len: usize, n: i64) -> Result<usize, EvalError> {
+  if len == 0 || n <= 0 {
+    return Ok(0);
+  }
+  let count = usize::try_from(n).unwrap_or(usize::MAX);
+  match len.checked_mul(count) {
+    Some(total) if total <= MAX_SEQUENCE_LENGTH => Ok(count),
+    _ => Err(EvalError::msg(format!(
+      "Repeating a `{}` of length {} {} times exceeds the maximum length of {}.",
+      type_name,
+      len,
+      n,
+      MAX_SEQUENCE_LENGTH,
+    ))),
+  }
+}
+
+pub fn overflow() -> EvalError {
+  EvalError::msg("Integer overflow.")
+}
+
+fn bound_names<'a>(target: &'a Expr, names: &mut Vec<&'a str>) {
+  match &target.kind {
+    ExprKind::Ident(name) => names.push(name),
+    ExprKind::List(targets) | ExprKind::Tuple(targets) => {
+      targets.iter().for_each(|target| bound_names(target, names));
+    },
+    _ => {},
+  }
+}
+
+/// Evaluates an augmented assignment. `+=` on a list extends it in place.
+fn augment(op: BinaryOp, current: Value, value: Value) -> Result<Value, EvalError> {
+  if let (BinaryOp::Add, Value::List(list)) = (op, &current) {
+    let items = value.iterate()?;
+    list.items_mut()?.extend(items);
+    return Ok(current);
+  }
+  binary_op(op, &current, &value)
+}
+
+fn set_index(object: &Value, index: Value, value: Value) -> Result<(), EvalError> {
+  match object {
+    Value::List(list) => {
+      let mut items = list.items_mut()?;
+      let index = normalize_index(index.expect_int("index")?, items.len())?;
+      items[index] = value;
+      Ok(())
+    },
+    Value::Dict(dict) => dict.insert(index, value),
+    _ => Err(EvalError::msg(format!("`{}` does not support item assignment.", object.type_name()))),
+  }
+}
+
+fn normalize_index(index: i64, len: usize) -> Result<usize, EvalError> {
+  let normalized = if index < 0 { index + len as i64 } else { index };
+  if normalized < 0 || normalized >= len as i64 {
+    return Err(EvalError::msg(format!("Index {} out of range for length {}.", index, len)));
+  }
+  Ok(normalized as usize)
+}
+
+fn slice(object: &Value, start: Option<i64>, stop: Option<i64>, step: Option<i64>) ->
+    Result<Value, EvalError> {
+  let step = step.unwrap_or(1);
+  if step == 0 {
+    return Err(EvalError::msg("Slice step cannot be zero."));
+  }
+
+  let select = |len: usize| -> Vec<usize> {
+    let len = len as i64;
+    let clamp = |index: i64, low: i64, high: i64| {
+      let index = if index < 0 { index + len } else { index };
+      index.clamp(low, high)
+    };
+    let mut indices = Vec::new();
+    if step > 0 {
+      let mut index = start.map_or(0, |start| clamp(start, 0, len));
+      let stop = stop.map_or(len, |stop| clamp(stop, 0, len));
+      while index < stop {
+        indices.push(index as usize);
+        index += step;
+      }
+    } else {
+      let mut index = start.map_or(len - 1, |start| clamp(start, -1, len - 1));
+      let stop = stop.map_or(-1, |stop| clamp(stop, -1, len - 1));
+      while index > stop {
+        indices.push(index as usize);
+        index += step;
+      }
+    }
+    indices
+  };
+
+  match object {
+    Value::List(_) | Value::Tuple(_) => {
+      let items = object.iterate()?;
+      let sliced = select(items.len()).into_iter().map(|index| items[index].clone()).collect();
+      Ok(if matches!(object, Value::List(_)) { Value::list(sliced) } else { Value::tuple(sliced) })
+    },
+    Value::Str(value) => {
+      let chars: Vec<_> = value.chars().collect();
+      Ok(Value::str(&select(chars.len()).into_iter().map(|index| chars[index]).collect::<String>()))
+    },
+    _ => Err(EvalError::msg(format!("`{}` cannot be sliced.", object.type_name()))),
+  }
+}
+
+/// Evaluates a binary operator other than the short-circuiting `and` and `or`.
+pub fn binary_op(op: BinaryOp, lhs: &Value, rhs: &Value) -> Result<Value, EvalError> {
+  if let Value::Object(object) = lhs {
+    if let Some(result) = object.binary_op(op, rhs, true) {
+      return result;
+    }
+  }
+  if let Value::Object(object) = rhs {
+    if let Some(result) = object.binary_op(op, lhs, false) {
+      return result;
+    }
+  }
+
+  let result = match (op, lhs, rhs) {
+    (BinaryOp::Equal, _, _) => Some(Value::Bool(lhs == rhs)),
+    (BinaryOp::NotEqual, _, _) => Some(Value::Bool(lhs != rhs)),
+    (BinaryOp::Less, _, _) => Some(Value::Bool(lhs.compare(rhs)?.is_lt())),
+    (BinaryOp::LessEqual, _, _) => Some(Value::Bool(lhs.compare(rhs)?.is_le())),
+    (BinaryOp::Greater, _, _) => Some(Value::Bool(lhs.compare(rhs)?.is_gt())),
+    (BinaryOp::GreaterEqual, _, _) => Some(Value::Bool(lhs.compare(rhs)?.is_ge())),
+    (BinaryOp::In, _, _) => Some(Value::Bool(contains(rhs, lhs)?)),
+    (BinaryOp::NotIn, _, _) => Some(Value::Bool(!contains(rhs, lhs)?)),
+    (_, Value::Int(a), Value::Int(b)) => Some(int_op(op, *a, *b)?),
+    (BinaryOp::Add, Value::Str(a), Value::Str(b)) => Some(Value::str(&format!("{}{}", a, b))),
+    (BinaryOp::Add, Value::List(a), Value::List(b)) => {
+      Some(Value::list(a.items().iter().chain(b.items().iter()).cloned().collect()))
+    },
+    (BinaryOp::Add, Value::Tuple(a), Value::Tuple(b)) => {
+      Some(Value::tuple(a.iter().chain(b.iter()).cloned().collect()))
+    },
+    (BinaryOp::Multiply, Value::Str(a), Value::Int(b)) |
+    (BinaryOp::Multiply, Value::Int(b), Value::Str(a)) => {
+      Some(Value::str(&a.repeat(repeat_count("string", a.len(), *b)?)))
+    },
+    (BinaryOp::Multiply, Value::List(_) | Value::Tuple(_), Value::Int(n)) |
+    (BinaryOp::Multiply, Value::Int(n), Value::List(_) | Value::Tuple(_)) => {
+      let sequence = if matches!(lhs, Value::Int(_)) { rhs } else { lhs };
+      let items = sequence.iterate()?;
+      let count = repeat_count(&sequence.type_name(), items.len(), *n)?;
+      let repeated = (0..count).flat_map(|_| items.iter().cloned()).collect();
+      Some(if matches!(sequence, Value::List(_)) {
+        Value::list(repeated)
+      } else {
+        Value::tuple(repeated)
+      })
+    },
+    (BinaryOp::Modulo, Value::Str(format), _) => Some(Value::str(&format_percent(format, rhs)?)),
+    (BinaryOp::BitOr, Value::Dict(a), Value::Dict(b)) => {
+      let entries = a.entries().iter().chain(b.entries().iter()).cloned().collect();
+      Some(Value::dict(entries)?)
+    },
+    _ => None,
+  };
+
+  result.ok_or_else(|| EvalError::msg(format!(
+    "Unsupported operand types for `{}`: `{}` and `{}`.",
+    op.symbol(),
+    lhs.type_name(),
+    rhs.type_name(),
+  )))
+}
+
+fn int_op(op: BinaryOp, a: i64, b: i64) -> Result<Value, EvalError> {
+  let divisor = || if b == 0 { Err(EvalError::msg("Division by zero.")) } else { Ok(b) };
+  Ok(Value::Int(match op {
+    BinaryOp::Add => a.checked_add(b).ok_or_else(overflow)?,
+    BinaryOp::Subtract => a.checked_sub(b).ok_or_else(overflow)?,
+    BinaryOp::Multiply => a.checked_mul(b).ok_or_else(overflow)?,
+    BinaryOp::Divide => return Err(EvalError::msg(
+      "Floating point division is not supported; use `//`.",
+    )),
+    BinaryOp::FloorDivide => a.checked_div_euclid(divisor()?).ok_or_else(overflow)?,
+    BinaryOp::Modulo => a.checked_rem_euclid(divisor()?).ok_or_else(overflow)?,
+    BinaryOp::BitOr => a | b,
+    BinaryOp::BitAnd => a & b,
+    BinaryOp::BitXor => a ^ b,
+    BinaryOp::ShiftLeft if (0..64).contains(&b) => a.checked_shl(b as u32).ok_or_else(overflow)?,
+    BinaryOp::ShiftRight if (0..64).contains(&b) => a >> b,
+    BinaryOp::ShiftLeft | BinaryOp::ShiftRight => {
+      return Err(EvalError::msg(format!("Invalid shift count {}.", b)));
+    },
+    _ => return Err(EvalError::msg(format!(
+      "Unsupported operand types for `{}`: `int` and `int`.",
+      op.symbol(),
+    ))),
+  }))
+}
+
+/// Returns `item in container`.
+fn contains(container: &Value, item: &Value) -> Result<bool, EvalError> {
+  match container {
+    Value::Str(haystack) => Ok(haystack.contains(item.expect_str("left operand of `in`")?)),
+    Value::List(_) | Value::Tuple(_) => Ok(container.iterate()?.contains(item)),
+    Value::Dict(dict) => Ok(dict.get(item)?.is_some()),
+    Value::Object(object) => object.contains(item),
+    _ => Err(EvalError::msg(format!("`{}` does not support `in`.", container.type_name()))),
+  }
+}
+
+/// Implements `format % args` with `%s`, `%r`, `%d` and `%%`.
+fn format_percent(format: &str, args: &Value) -> Result<String, EvalError> {
+  let args = match args {
+    Value::Tuple(items) => items.to_vec(),
+    value => vec![value.clone()],
+  };
+  let mut args = args.into_iter();
+  let mut result = String::new();
+  let mut chars = format.chars();
+  while let Some(c) = chars.next() {
+    if c != '%' {
+      result.push(c);
+      continue;
+    }
+    let directive = chars.next();
+    if directive == Some('%') {
+      result.push('%');
+      continue;
+    }
+    let arg = args.next().ok_or_else(|| EvalError::msg("Not enough arguments for format string."))?;
+    match directive {
+      Some('s') => result.push_str(&arg.to_str()),
+      Some('r') => result.push_str(&arg.repr()),
+      Some('d') => result.push_str(&arg.expect_int("`%d` argument")?.to_string()),
+      _ => return Err(EvalError::msg("Unsupported format directive; expected `%s`, `%r` or `%d`.")),
+    }
+  }
+  if args.next().is_some() {
+    return Err(EvalError::msg("Too many arguments for format string."));
+  }
+
+  Ok(result)
+}
+
+/// Calls `function` once per item, for builtins such as `sorted(key = ...)`.
+pub fn call_each(eval: &mut Evaluator, function: &Value, items: &[Value]) ->
+    Result<Vec<Value>, EvalError> {
+  items.iter().map(|item| eval.call(function, Args::positional(vec![item.clone()]))).collect()
+}
+
+#[cfg(test)]
+mod test {
+  use assertables::*;
+  use super::*;
+  use super::super::parser::parse;
+
+  fn eval(source: &str) -> Result<Rc<ModuleEnv>, EvalError> {
+    let module = parse("test.bzl", source)?;
+    let env = ModuleEnv::new("test.bzl", Rc::new(HashMap::new()));
+    Evaluator::new().eval_module(&module, &env)?;
+    Ok(env)
+  }
+
+  fn eval_expr(source: &str) -> Value {
+    eval(&format!("x = {}", source)).unwrap().get("x").unwrap()
+  }
+
+  #[test]
+  fn eval_evaluates_expressions() {
+    assert_eq!(eval_expr("1 + 2 * 3"), Value::Int(7));
+    assert_eq!(eval_expr("-7 // 2"), Value::Int(-4));
+    assert_eq!(eval_expr("\"a\" + \"b\" * 2"), Value::str("abb"));
+    assert_eq!(
+      eval_expr("[1, 2] + [3]"),
+      Value::list(vec![Value::Int(1), Value::Int(2), Value::Int(3)]),
+    );
+    assert_eq!(eval_expr("\"%s-%d\" % (\"a\", 1)"), Value::str("a-1"));
+    assert_eq!(eval_expr("1 if [] else 2"), Value::Int(2));
+    assert_eq!(eval_expr("0 or \"x\""), Value::str("x"));
+    assert_eq!(eval_expr("\"b\" in {\"a\": 1, \"b\": 2}"), Value::Bool(true));
+    assert_eq!(eval_expr("\"abcdef\"[1:-1:2]"), Value::str("bd"));
+    assert_eq!(
+      eval_expr("[1, 2, 3][::-1]"),
+      Value::list(vec![Value::Int(3), Value::Int(2), Value::Int(1)]),
+    );
+  }
+
+  #[test]
+  fn eval_evaluates_comprehensions_in_their_own_scope() {
+    let env = eval(
+      "x = 10\ny = [x * 2 for x in range(3) if x != 1]\n\
+      z = {k: v for k, v in [(\"a\", 1)]}",
+    ).unwrap();
+    assert_eq!(env.get("x"), Some(Value::Int(10)));
+    assert_eq!(env.get("y"), Some(Value::list(vec![Value::Int(0), Value::Int(4)])));
+    assert_eq!(env.get("z"), Some(Value::dict(vec![(Value::str("a"), Value::Int(1))]).unwrap()));
+  }
+
+  #[test]
+  fn eval_calls_functions() {
+    let env = eval(r#"
+def f(a, b = 2, *args, c = 3, **kwargs):
+    return [a, b, args, c, kwargs]
+
+x = f(1)
+y = f(1, 5, 6, c = 7, d = 8)
+
+def g(items):
+    total = 0
+    for item in items:
+        if item > 2:
+            break
+        total += item
+    return total
+
+z = g([1, 2, 3, 4])
+"#).unwrap();
+
+    assert_eq!(env.get("x").unwrap().repr(), "[1, 2, (), 3, {}]");
+    assert_eq!(env.get("y").unwrap().repr(), "[1, 5, (6,), 7, {\"d\": 8}]");
+    assert_eq!(env.get("z"), Some(Value::Int(3)));
+  }
+
+  #[test]
+  fn eval_mutates_shared_values() {
+    let env = eval("a = [1]\nb = a\nb += [2]\nd = {}\nd[\"k\"] = a").unwrap();
+    assert_eq!(env.get("a").unwrap().repr(), "[1, 2]");
+    assert_eq!(env.get("d").unwrap().repr(), "{\"k\": [1, 2]}");
+  }
+
+  #[test]
+  fn eval_errors_on_mutation_during_iteration() {
+    assert_contains!(
+      eval("a = [1, 2]\nfor x in a:\n    a.append(x)").err().unwrap().to_string(),
+      "Cannot modify a list while iterating over it.",
+    );
+    assert_contains!(
+      eval("d = {\"a\": 1}\nfor k in d:\n    d[k + k] = 2").err().unwrap().to_string(),
+      "Cannot modify a dict while iterating over it.",
+    );
+    assert_contains!(
+      eval("a = [1]\nb = [a.append(x) for x in a]").err().unwrap().to_string(),
+      "Cannot modify a list while iterating over it.",
+    );
+
+    let env = eval(
+      "a = [1, 2]\nfor x in a:\n    pass\na.append(3)\n\
+      for x in [1]:\n    a.append(x)",
+    ).unwrap();
+    assert_eq!(env.get("a").unwrap().repr(), "[1, 2, 3, 1]");
+  }
+
+  #[test]
+  fn eval_errors_on_oversized_repetition() {
+    assert_contains!(
+      eval("x = \"abc\" * 9223372036854775807").err().unwrap().to_string(),
+      "Repeating a `string` of length 3 9223372036854775807 times exceeds the maximum length",
+    );
+    assert_contains!(
+      eval("x = \"ab\" * 4000000000000").err().unwrap().to_string(),
+      "exceeds the maximum length",
+    );
+    assert_contains!(
+      eval("x = 10000000000000 * [1]").err().unwrap().to_string(),
+      "Repeating a `list` of length 1 10000000000000 times exceeds the maximum length",
+    );
+    assert_contains!(
+      eval("x = (1, 2) * 9223372036854775807").err().unwrap().to_string(),
+      "`tuple`",
+    );
+
+    assert_eq!(eval_expr("[] * 9223372036854775807"), Value::list(vec![]));
+    assert_eq!(eval_expr("\"\" * 9223372036854775807"), Value::str(""));
+    assert_eq!(eval_expr("(1,) * -1"), Value::tuple(vec![]));
+  }
+
+  #[test]
+  fn eval_errors_with_location_and_callers() {
+    let err = eval(
+      "def f(x):\n    return x + 1\n\ndef g():\n    return f(\"a\")\n\ng()",
+    ).err().unwrap();
+    assert_eq!(
+      err.to_string(),
+      concat!(
+        "test.bzl:2:14: Unsupported operand types for `+`: `string` and `int`.\n",
+        "  called from test.bzl:5:13\n",
+        "  called from test.bzl:7:2",
+      ),
+    );
+
+    assert_eq!(eval("x = y").err().unwrap().to_string(), "test.bzl:1:5: Name `y` is not defined.");
+    assert_contains!(
+      eval("def f():\n    f()\nf()").err().unwrap().to_string(),
+      "called recursively",
+    );
+    assert_contains!(eval("x = {[]: 1}").err().unwrap().to_string(), "Unhashable type `list`.");
+  }
+}
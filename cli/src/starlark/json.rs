@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+use serde_json::{Map, Value as Json};
+use super::error::EvalError;
+use super::value::{Struct, Value};
+
+/// The `json` module of .bzl files.
+pub fn json_module() -> Value {
+  Value::object(Struct { fields: BTreeMap::from([
+    ("encode".to_owned(), Value::builtin("encode", |_, args| {
+      let [x] = args.bind("encode", &["x"], &[])?.try_into().unwrap();
+      Ok(Value::str(&to_json(&x.unwrap())?.to_string()))
+    })),
+  ]) })
+}
+
+/// Converts a value to JSON. Dicts must have string keys and structs are
+/// encoded as objects.
+fn to_json(value: &Value) -> Result<Json, EvalError> {
+  Ok(match value {
+    Value::None => Json::Null,
+    Value::Bool(value) => Json::Bool(*value),
+    Value::Int(value) => Json::from(*value),
+    Value::Str(value) => Json::from(value.as_ref()),
+    Value::List(_) | Value::Tuple(_) => {
+      Json::Array(value.expect_list("value")?.iter().map(to_json).collect::<Result<_, _>>()?)
+    },
+    Value::Dict(_) => Json::Object(value.expect_dict("value")?.iter()
+      .map(|(key, value)| Ok((key.expect_str("dict key")?.to_owned(), to_json(value)?)))
+      .collect::<Result<Map<_, _>, EvalError>>()?),
+    value => match value.downcast::<Struct>() {
+      Some(object) => Json::Object(object.fields.iter()
+        .map(|(name, value)| Ok((name.clone(), to_json(value)?)))
+        .collect::<Result<Map<_, _>, EvalError>>()?),
+      None => return Err(EvalError::msg(format!("Cannot encode `{}` as JSON.", value.type_name()))),
+    },
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use std::collections::HashMap;
+  use std::rc::Rc;
+  use crate::starlark::eval::{Evaluator, ModuleEnv};
+  use crate::starlark::parser::parse;
+  use super::*;
+
+  #[test]
+  fn encode_converts_values() -> Result<(), EvalError> {
+    let module = parse("test.bzl", r#"
+encoded = json.encode({
+    "files": ["a.ts", "b\".ts"],
+    "strict": True,
+    "target": None,
+    "depth": (1, 2),
+})
+"#)?;
+    let env = ModuleEnv::new(
+      "test.bzl",
+      Rc::new(HashMap::from([("json".to_owned(), json_module())])),
+    );
+    Evaluator::new().eval_module(&module, &env)?;
+    assert_eq!(
+      env.globals.borrow()["encoded"].expect_str("encoded")?,
+      r#"{"depth":[1,2],"files":["a.ts","b\".ts"],"strict":true,"target":null}"#,
+    );
+
+    let module = parse("test.bzl", "json.encode({1: 2})")?;
+    let env = ModuleEnv::new(
+      "test.bzl",
+      Rc::new(HashMap::from([("json".to_owned(), json_module())])),
+    );
+    assert_eq!(
+      Evaluator::new().eval_module(&module, &env).err().unwrap().message,
+      "Expected dict key to be a string, got a int (1).",
+    );
+    Ok(())
+  }
+}
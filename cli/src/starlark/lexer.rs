@@ -0,0 +1,514 @@
+use std::rc::Rc;
+use super::error::{EvalError, Location};
+
+/// A single token of Starlark source.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+  Ident(String),
+  Int(i64),
+  Str(String),
+
+  /// A keyword such as `def` or `if`.
+  Keyword(&'static str),
+
+  /// An operator or punctuation such as `+=` or `(`.
+  Punct(&'static str),
+
+  Newline,
+  Indent,
+  Dedent,
+  Eof,
+}
+
+const KEYWORDS: &[&str] = &[
+  "and", "break", "continue", "def", "elif", "else", "for", "if", "in",
+  "lambda", "load", "not", "or", "pass", "return", "None", "True", "False",
+];
+
+// Longest punctuation first so `**=` is not lexed as `**` and `=`.
+const PUNCTUATION: &[&str] = &[
+  "**=", "//=", "<<=", ">>=",
+  "**", "//", "==", "!=", "<=", ">=", "+=", "-=", "*=", "/=", "%=", "&=", "|=",
+  "^=", "<<", ">>", "->",
+  "+", "-", "*", "/", "%", "<", ">", "=", ".", ",", ":", ";", "(", ")", "[",
+  "]", "{", "}",
+];
+
+const SINGLE_PUNCTUATION: &[&str] = &["|", "&", "^", "~"];
+
+/// A token with the location it started at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spanned {
+  pub token: Token,
+  pub location: Location,
+}
+
+/// A `#` comment, with the location of the `#`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment {
+  pub location: Location,
+
+  /// The text of the comment including the `#`, without trailing whitespace.
+  pub text: String,
+}
+
+/// Splits Starlark source into tokens, including `Indent`, `Dedent` and
+/// `Newline` tokens for significant whitespace. Comments are dropped.
+pub fn tokenize(file: &Rc<str>, source: &str) -> Result<Vec<Spanned>, EvalError> {
+  Ok(tokenize_with_comments(file, source)?.0)
+}
+
+/// Splits Starlark source into tokens like `tokenize()`, also returning its
+/// comments in order for tools which print source back.
+pub fn tokenize_with_comments(file: &Rc<str>, source: &str) ->
+    Result<(Vec<Spanned>, Vec<Comment>), EvalError> {
+  Lexer {
+    file,
+    chars: source.chars().collect(),
+    index: 0,
+    line: 1,
+    column: 1,
+    indents: vec![0],
+    depth: 0,
+    tokens: Vec::new(),
+    comments: Vec::new(),
+  }.run()
+}
+
+struct Lexer<'a> {
+  file: &'a Rc<str>,
+  chars: Vec<char>,
+  index: usize,
+  line: usize,
+  column: usize,
+
+  /// Stack of indentation widths of the enclosing blocks.
+  indents: Vec<usize>,
+
+  /// Nesting depth of brackets, inside which newlines are insignificant.
+  depth: usize,
+
+  tokens: Vec<Spanned>,
+  comments: Vec<Comment>,
+}
+
+impl Lexer<'_> {
+  fn run(mut self) -> Result<(Vec<Spanned>, Vec<Comment>), EvalError> {
+    let mut at_line_start = true;
+    while self.index < self.chars.len() {
+      if at_line_start && self.depth == 0 {
+        at_line_start = false;
+        if self.indentation()? {
+          at_line_start = true;
+          continue;
+        }
+      }
+
+      let c = self.chars[self.index];
+      match c {
+        ' ' | '\t' | '\r' => self.advance(),
+        '\\' if self.peek(1) == Some('\n') => {
+          self.advance();
+          self.advance();
+        },
+        '#' => self.comment(),
+        '\n' => {
+          let location = self.location();
+          self.advance();
+          if self.depth == 0 {
+            self.push_newline(location);
+            at_line_start = true;
+          }
+        },
+        c if c.is_ascii_digit() => self.number()?,
+        c if c.is_alphabetic() || c == '_' => self.identifier()?,
+        _ => self.punctuation()?,
+      }
+    }
+
+    let location = self.location();
+    self.push_newline(location.clone());
+    while self.indents.len() > 1 {
+      self.indents.pop();
+      self.tokens.push(Spanned { token: Token::Dedent, location: location.clone() });
+    }
+    self.tokens.push(Spanned { token: Token::Eof, location });
+
+    Ok((self.tokens, self.comments))
+  }
+
+  /// Skips a comment up to the end of its line, recording it.
+  fn comment(&mut self) {
+    let location = self.location();
+    let start = self.index;
+    while self.index < self.chars.len() && self.chars[self.index] != '\n' {
+      self.advance();
+    }
+    let text: String = self.chars[start..self.index].iter().collect();
+    self.comments.push(Comment { location, text: text.trim_end().to_owned() });
+  }
+
+  /// Measures the indentation at the start of a line and emits `Indent` or
+  /// `Dedent` tokens. Returns `true` if the line is blank or a comment, which
+  /// is skipped entirely.
+  fn indentation(&mut self) -> Result<bool, EvalError> {
+    let mut width = 0;
+    while let Some(c) = self.peek(0) {
+      match c {
+        ' ' => width += 1,
+        '\t' => width += 8 - width % 8,
+        _ => break,
+      }
+      self.advance();
+    }
+
+    match self.peek(0) {
+      None => return Ok(true),
+      Some('\n') | Some('#') | Some('\r') => {
+        if self.peek(0) == Some('#') {
+          self.comment();
+        }
+        while let Some(c) = self.peek(0) {
+          self.advance();
+          if c == '\n' {
+            break;
+          }
+        }
+        return Ok(true);
+      },
+      Some(_) => {},
+    }
+
+    let location = self.location();
+    let current = *self.indents.last().unwrap();
+    if width > current {
+      self.indents.push(width);
+      self.tokens.push(Spanned { token: Token::Indent, location });
+    } else {
+      while width < *self.indents.last().unwrap() {
+        self.indents.pop();
+        self.tokens.push(Spanned { token: Token::Dedent, location: location.clone() });
+      }
+      if width != *self.indents.last().unwrap() {
+        return Err(EvalError::new(location, "Inconsistent indentation."));
+      }
+    }
+
+    Ok(false)
+  }
+
+  fn push_newline(&mut self, location: Location) {
+    let last = self.tokens.last().map(|spanned| &spanned.token);
+    if !matches!(last, None | Some(Token::Newline) | Some(Token::Indent) | Some(Token::Dedent)) {
+      self.tokens.push(Spanned { token: Token::Newline, location });
+    }
+  }
+
+  fn number(&mut self) -> Result<(), EvalError> {
+    let location = self.location();
+    let start = self.index;
+    let radix = match (self.peek(0), self.peek(1)) {
+      (Some('0'), Some('x' | 'X')) => 16,
+      (Some('0'), Some('o' | 'O')) => 8,
+      (Some('0'), Some('b' | 'B')) => 2,
+      _ => 10,
+    };
+    if radix != 10 {
+      self.advance();
+      self.advance();
+    }
+
+    let digits_start = self.index;
+    while self.peek(0).is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+      self.advance();
+    }
+    let digits: String = self.chars[digits_start..self.index].iter()
+      .filter(|c| **c != '_')
+      .collect();
+    let value = i64::from_str_radix(&digits, radix).map_err(|_| {
+      EvalError::new(location.clone(), format!(
+        "Invalid integer literal `{}`.",
+        self.chars[start..self.index].iter().collect::<String>(),
+      ))
+    })?;
+
+    self.tokens.push(Spanned { token: Token::Int(value), location });
+    Ok(())
+  }
+
+  fn identifier(&mut self) -> Result<(), EvalError> {
+    let location = self.location();
+    let start = self.index;
+    while self.peek(0).is_some_and(|c| c.is_alphanumeric() || c == '_') {
+      self.advance();
+    }
+    let word: String = self.chars[start..self.index].iter().collect();
+
+    // String prefixes.
+    if matches!(word.as_str(), "r" | "R" | "b" | "rb" | "br")
+        && matches!(self.peek(0), Some('"') | Some('\'')) {
+      let raw = word.to_lowercase().contains('r');
+      return self.string(location, raw);
+    }
+
+    let token = match KEYWORDS.iter().find(|keyword| **keyword == word) {
+      Some(keyword) => Token::Keyword(keyword),
+      None => Token::Ident(word),
+    };
+    self.tokens.push(Spanned { token, location });
+    Ok(())
+  }
+
+  fn string(&mut self, location: Location, raw: bool) -> Result<(), EvalError> {
+    let quote = self.peek(0).unwrap();
+    let triple = self.peek(1) == Some(quote) && self.peek(2) == Some(quote);
+    let quote_len = if triple { 3 } else { 1 };
+    for _ in 0..quote_len {
+      self.advance();
+    }
+
+    let mut value = String::new();
+    loop {
+      let c = match self.peek(0) {
+        None => return Err(EvalError::new(location, "Unterminated string.")),
+        Some(c) => c,
+      };
+
+      if c == quote && (!triple
+          || (self.peek(1) == Some(quote) && self.peek(2) == Some(quote))) {
+        for _ in 0..quote_len {
+          self.advance();
+        }
+        break;
+      }
+      if c == '\n' && !triple {
+        return Err(EvalError::new(location, "Unterminated string."));
+      }
+
+      self.advance();
+      if c != '\\' {
+        value.push(c);
+        continue;
+      }
+
+      let escaped = match self.peek(0) {
+        None => return Err(EvalError::new(location, "Unterminated string.")),
+        Some(escaped) => escaped,
+      };
+      self.advance();
+      if raw {
+        value.push('\\');
+        value.push(escaped);
+        continue;
+      }
+      match escaped {
+        'n' => value.push('\n'),
+        't' => value.push('\t'),
+        'r' => value.push('\r'),
+        '0' => value.push('\0'),
+        '\\' => value.push('\\'),
+        '\'' => value.push('\''),
+        '"' => value.push('"'),
+        '\n' => {},
+        'x' | 'u' | 'U' => {
+          let len = match escaped { 'x' => 2, 'u' => 4, _ => 8 };
+          let hex: String = (0..len).filter_map(|_| {
+            let c = self.peek(0);
+            self.advance();
+            c
+          }).collect();
+          let c = u32::from_str_radix(&hex, 16).ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| EvalError::new(
+              location.clone(),
+              format!("Invalid escape sequence `\\{}{}`.", escaped, hex),
+            ))?;
+          value.push(c);
+        },
+        other => {
+          value.push('\\');
+          value.push(other);
+        },
+      }
+    }
+
+    self.tokens.push(Spanned { token: Token::Str(value), location });
+    Ok(())
+  }
+
+  fn punctuation(&mut self) -> Result<(), EvalError> {
+    let location = self.location();
+    if matches!(self.peek(0), Some('"') | Some('\'')) {
+      return self.string(location, false);
+    }
+
+    let punct = PUNCTUATION.iter().chain(SINGLE_PUNCTUATION.iter())
+      .find(|punct| punct.chars().enumerate()
+        .all(|(offset, c)| self.peek(offset) == Some(c)));
+    let punct = match punct {
+      Some(punct) => *punct,
+      None => return Err(EvalError::new(location, format!(
+        "Unexpected character `{}`.",
+        self.chars[self.index],
+      ))),
+    };
+    for _ in 0..punct.len() {
+      self.advance();
+    }
+
+    match punct {
+      "(" | "[" | "{" => self.depth += 1,
+      ")" | "]" | "}" => self.depth = self.depth.saturating_sub(1),
+      _ => {},
+    }
+    self.tokens.push(Spanned { token: Token::Punct(punct), location });
+    Ok(())
+  }
+
+  fn peek(&self, offset: usize) -> Option<char> {
+    self.chars.get(self.index + offset).copied()
+  }
+
+  fn advance(&mut self) {
+    if self.chars.get(self.index) == Some(&'\n') {
+      self.line += 1;
+      self.column = 1;
+    } else {
+      self.column += 1;
+    }
+    self.index += 1;
+  }
+
+  fn location(&self) -> Location {
+    Location::new(self.file, self.line, self.column)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn tokens(source: &str) -> Vec<Token> {
+    tokenize(&Rc::from("test.bzl"), source).unwrap().into_iter()
+      .map(|spanned| spanned.token)
+      .collect()
+  }
+
+  #[test]
+  fn tokenize_splits_expressions() {
+    assert_eq!(tokens("foo(1, 'bar') ** 2"), vec![
+      Token::Ident("foo".to_owned()),
+      Token::Punct("("),
+      Token::Int(1),
+      Token::Punct(","),
+      Token::Str("bar".to_owned()),
+      Token::Punct(")"),
+      Token::Punct("**"),
+      Token::Int(2),
+      Token::Newline,
+      Token::Eof,
+    ]);
+  }
+
+  #[test]
+  fn tokenize_emits_indentation() {
+    assert_eq!(tokens("def f():\n  if x:\n    pass\n  return\n"), vec![
+      Token::Keyword("def"),
+      Token::Ident("f".to_owned()),
+      Token::Punct("("),
+      Token::Punct(")"),
+      Token::Punct(":"),
+      Token::Newline,
+      Token::Indent,
+      Token::Keyword("if"),
+      Token::Ident("x".to_owned()),
+      Token::Punct(":"),
+      Token::Newline,
+      Token::Indent,
+      Token::Keyword("pass"),
+      Token::Newline,
+      Token::Dedent,
+      Token::Keyword("return"),
+      Token::Newline,
+      Token::Dedent,
+      Token::Eof,
+    ]);
+  }
+
+  #[test]
+  fn tokenize_ignores_newlines_in_brackets_and_comments() {
+    assert_eq!(tokens("x = [\n  1,  # one\n\n  2,\n]\n# done\n"), vec![
+      Token::Ident("x".to_owned()),
+      Token::Punct("="),
+      Token::Punct("["),
+      Token::Int(1),
+      Token::Punct(","),
+      Token::Int(2),
+      Token::Punct(","),
+      Token::Punct("]"),
+      Token::Newline,
+      Token::Eof,
+    ]);
+  }
+
+  #[test]
+  fn tokenize_with_comments_returns_comments_outside_strings() -> Result<(), EvalError> {
+    let (_, comments) = tokenize_with_comments(
+      &Rc::from("BUILD"),
+      "# top\nx = [  # open\n  \"#no\",\n]  \n  # end  \n",
+    )?;
+    assert_eq!(
+      comments.iter()
+        .map(|comment| {
+          let location = &comment.location;
+          (location.line, location.column, comment.text.as_str())
+        })
+        .collect::<Vec<_>>(),
+      [(1, 1, "# top"), (2, 8, "# open"), (5, 3, "# end")],
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn tokenize_parses_string_literals() {
+    assert_eq!(
+      tokens(r#""a\nb" 'c\'d' r"\d" """multi
+line""""#),
+      vec![
+        Token::Str("a\nb".to_owned()),
+        Token::Str("c'd".to_owned()),
+        Token::Str("\\d".to_owned()),
+        Token::Str("multi\nline".to_owned()),
+        Token::Newline,
+        Token::Eof,
+      ],
+    );
+  }
+
+  #[test]
+  fn tokenize_parses_integer_literals() {
+    assert_eq!(tokens("0 42 0x1F 0o17 0b101"), vec![
+      Token::Int(0),
+      Token::Int(42),
+      Token::Int(31),
+      Token::Int(15),
+      Token::Int(5),
+      Token::Newline,
+      Token::Eof,
+    ]);
+  }
+
+  #[test]
+  fn tokenize_errors_on_unterminated_string() {
+    let err = tokenize(&Rc::from("test.bzl"), "x = 'foo\n").unwrap_err();
+
+    assert_eq!(err.to_string(), "test.bzl:1:5: Unterminated string.");
+  }
+
+  #[test]
+  fn tokenize_errors_on_inconsistent_dedent() {
+    let err = tokenize(&Rc::from("test.bzl"), "if x:\n    pass\n  pass\n").unwrap_err();
+
+    assert_eq!(err.to_string(), "test.bzl:3:3: Inconsistent indentation.");
+  }
+}
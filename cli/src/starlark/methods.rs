@@ -0,0 +1,519 @@
+use std::rc::Rc;
+use super::builtins::pairs;
+use super::error::EvalError;
+use super::eval::Evaluator;
+use super::value::{Args, Dict, List, Value};
+
+type Method = fn(&Value, &mut Evaluator, Args) -> Result<Value, EvalError>;
+
+const STRING_METHODS: &[(&str, Method)] = &[
+  ("capitalize", |s, _, args| {
+    args.none("capitalize")?;
+    let s = string(s);
+    let mut chars = s.chars();
+    Ok(Value::str(&match chars.next() {
+      Some(first) => first.to_uppercase().chain(chars.flat_map(|c| c.to_lowercase())).collect(),
+      None => String::new(),
+    }))
+  }),
+  ("count", |s, _, args| {
+    let [sub] = one("count", args, "sub")?;
+    Ok(Value::Int(string(s).matches(sub.expect_str("sub")?).count() as i64))
+  }),
+  ("elems", |s, _, args| {
+    args.none("elems")?;
+    Ok(Value::list(string(s).chars().map(|c| Value::str(&c.to_string())).collect()))
+  }),
+  ("endswith", |s, _, args| {
+    let [suffix] = one("endswith", args, "suffix")?;
+    affix(&suffix, "suffix", |suffix| string(s).ends_with(suffix))
+  }),
+  ("find", |s, _, args| {
+    let [sub] = one("find", args, "sub")?;
+    Ok(Value::Int(char_index(string(s), string(s).find(sub.expect_str("sub")?))))
+  }),
+  ("format", format),
+  ("index", |s, _, args| {
+    let [sub] = one("index", args, "sub")?;
+    match string(s).find(sub.expect_str("sub")?) {
+      Some(index) => Ok(Value::Int(char_index(string(s), Some(index)))),
+      None => Err(EvalError::msg("Substring not found.")),
+    }
+  }),
+  ("isalnum", |s, _, args| test("isalnum", s, args, |c| c.is_alphanumeric())),
+  ("isalpha", |s, _, args| test("isalpha", s, args, |c| c.is_alphabetic())),
+  ("isdigit", |s, _, args| test("isdigit", s, args, |c| c.is_ascii_digit())),
+  ("isspace", |s, _, args| test("isspace", s, args, |c| c.is_whitespace())),
+  ("islower", |s, _, args| {
+    args.none("islower")?;
+    let s = string(s);
+    Ok(Value::Bool(s.chars().any(|c| c.is_alphabetic()) && s.to_lowercase() == s))
+  }),
+  ("isupper", |s, _, args| {
+    args.none("isupper")?;
+    let s = string(s);
+    Ok(Value::Bool(s.chars().any(|c| c.is_alphabetic()) && s.to_uppercase() == s))
+  }),
+  ("join", |s, _, args| {
+    let [items] = one("join", args, "iterable")?;
+    let items = items.iterate()?.iter()
+      .map(|item| item.expect_str("joined item").map(|item| item.to_owned()))
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::str(&items.join(string(s))))
+  }),
+  ("lower", |s, _, args| {
+    args.none("lower")?;
+    Ok(Value::str(&string(s).to_lowercase()))
+  }),
+  ("lstrip", |s, _, args| strip("lstrip", s, args, |s, chars| s.trim_start_matches(chars))),
+  ("partition", |s, _, args| partition("partition", s, args, false)),
+  ("removeprefix", |s, _, args| {
+    let [prefix] = one("removeprefix", args, "prefix")?;
+    let s = string(s);
+    Ok(Value::str(s.strip_prefix(prefix.expect_str("prefix")?).unwrap_or(s)))
+  }),
+  ("removesuffix", |s, _, args| {
+    let [suffix] = one("removesuffix", args, "suffix")?;
+    let s = string(s);
+    Ok(Value::str(s.strip_suffix(suffix.expect_str("suffix")?).unwrap_or(s)))
+  }),
+  ("replace", |s, _, args| {
+    let [old, new, count] = args.bind("replace", &["old", "new"], &["count"])?
+      .try_into()
+      .unwrap();
+    let old = old.unwrap();
+    let new = new.unwrap();
+    let (old, new) = (old.expect_str("old")?, new.expect_str("new")?);
+    Ok(Value::str(&match count {
+      Some(count) if count.expect_int("count")? >= 0 => {
+        string(s).replacen(old, new, count.expect_int("count")? as usize)
+      },
+      _ => string(s).replace(old, new),
+    }))
+  }),
+  ("rfind", |s, _, args| {
+    let [sub] = one("rfind", args, "sub")?;
+    Ok(Value::Int(char_index(string(s), string(s).rfind(sub.expect_str("sub")?))))
+  }),
+  ("rpartition", |s, _, args| partition("rpartition", s, args, true)),
+  ("rsplit", |s, _, args| split("rsplit", s, args, true)),
+  ("rstrip", |s, _, args| strip("rstrip", s, args, |s, chars| s.trim_end_matches(chars))),
+  ("split", |s, _, args| split("split", s, args, false)),
+  ("splitlines", |s, _, args| {
+    args.none("splitlines")?;
+    Ok(Value::list(string(s).lines().map(Value::str).collect()))
+  }),
+  ("startswith", |s, _, args| {
+    let [prefix] = one("startswith", args, "prefix")?;
+    affix(&prefix, "prefix", |prefix| string(s).starts_with(prefix))
+  }),
+  ("strip", |s, _, args| strip("strip", s, args, |s, chars| s.trim_matches(chars))),
+  ("title", |s, _, args| {
+    args.none("title")?;
+    let mut title = String::new();
+    let mut previous_cased = false;
+    for c in string(s).chars() {
+      if previous_cased {
+        title.extend(c.to_lowercase());
+      } else {
+        title.extend(c.to_uppercase());
+      }
+      previous_cased = c.is_alphabetic();
+    }
+    Ok(Value::str(&title))
+  }),
+  ("upper", |s, _, args| {
+    args.none("upper")?;
+    Ok(Value::str(&string(s).to_uppercase()))
+  }),
+];
+
+const LIST_METHODS: &[(&str, Method)] = &[
+  ("append", |l, _, args| {
+    let [item] = one("append", args, "x")?;
+    list(l).items_mut()?.push(item);
+    Ok(Value::None)
+  }),
+  ("clear", |l, _, args| {
+    args.none("clear")?;
+    list(l).items_mut()?.clear();
+    Ok(Value::None)
+  }),
+  ("extend", |l, _, args| {
+    let [items] = one("extend", args, "x")?;
+    let items = items.iterate()?;
+    list(l).items_mut()?.extend(items);
+    Ok(Value::None)
+  }),
+  ("index", |l, _, args| {
+    let [item] = one("index", args, "x")?;
+    list(l).items().iter().position(|other| *other == item)
+      .map(|index| Value::Int(index as i64))
+      .ok_or_else(|| EvalError::msg(format!("Value {} not found in list.", item.repr())))
+  }),
+  ("insert", |l, _, args| {
+    let [index, item] = args.bind("insert", &["index", "x"], &[])?.try_into().unwrap();
+    let mut items = list(l).items_mut()?;
+    let len = items.len() as i64;
+    let index = index.unwrap().expect_int("index")?;
+    let index = if index < 0 { index + len } else { index }.clamp(0, len);
+    items.insert(index as usize, item.unwrap());
+    Ok(Value::None)
+  }),
+  ("pop", |l, _, args| {
+    let [index] = args.bind("pop", &[], &["i"])?.try_into().unwrap();
+    let mut items = list(l).items_mut()?;
+    let len = items.len() as i64;
+    let index = index.map_or(Ok(-1), |index| index.expect_int("index"))?;
+    let normalized = if index < 0 { index + len } else { index };
+    if normalized < 0 || normalized >= len {
+      return Err(EvalError::msg(format!("Index {} out of range for length {}.", index, len)));
+    }
+    Ok(items.remove(normalized as usize))
+  }),
+  ("remove", |l, _, args| {
+    let [item] = one("remove", args, "x")?;
+    let mut items = list(l).items_mut()?;
+    let index = items.iter().position(|other| *other == item)
+      .ok_or_else(|| EvalError::msg(format!("Value {} not found in list.", item.repr())))?;
+    items.remove(index);
+    Ok(Value::None)
+  }),
+];
+
+const DICT_METHODS: &[(&str, Method)] = &[
+  ("clear", |d, _, args| {
+    args.none("clear")?;
+    dict(d).entries_mut()?.clear();
+    Ok(Value::None)
+  }),
+  ("get", |d, _, args| {
+    let [key, default] = args.bind("get", &["key"], &["default"])?.try_into().unwrap();
+    Ok(dict(d).get(&key.unwrap())?.or(default).unwrap_or(Value::None))
+  }),
+  ("items", |d, _, args| {
+    args.none("items")?;
+    Ok(Value::list(dict(d).entries().iter()
+      .map(|(key, value)| Value::tuple(vec![key.clone(), value.clone()]))
+      .collect()))
+  }),
+  ("keys", |d, _, args| {
+    args.none("keys")?;
+    Ok(Value::list(dict(d).entries().iter().map(|(key, _)| key.clone()).collect()))
+  }),
+  ("pop", |d, _, args| {
+    let [key, default] = args.bind("pop", &["key"], &["default"])?.try_into().unwrap();
+    let key = key.unwrap();
+    let dict = dict(d);
+    dict.get(&key)?;
+    let mut entries = dict.entries_mut()?;
+    match entries.iter().position(|(other, _)| *other == key) {
+      Some(index) => Ok(entries.remove(index).1),
+      None => default.ok_or_else(|| {
+        EvalError::msg(format!("Key {} not found in dict.", key.repr()))
+      }),
+    }
+  }),
+  ("popitem", |d, _, args| {
+    args.none("popitem")?;
+    let mut entries = dict(d).entries_mut()?;
+    if entries.is_empty() {
+      return Err(EvalError::msg("popitem() on an empty dict."));
+    }
+    let (key, value) = entries.remove(0);
+    Ok(Value::tuple(vec![key, value]))
+  }),
+  ("setdefault", |d, _, args| {
+    let [key, default] = args.bind("setdefault", &["key"], &["default"])?.try_into().unwrap();
+    let key = key.unwrap();
+    let dict = dict(d);
+    if let Some(value) = dict.get(&key)? {
+      return Ok(value);
+    }
+    let value = default.unwrap_or(Value::None);
+    dict.insert(key, value.clone())?;
+    Ok(value)
+  }),
+  ("update", |d, _, args| {
+    if args.positional.len() > 1 {
+      return Err(EvalError::msg("update() accepts at most 1 positional argument."));
+    }
+    let mut entries = match args.positional.first() {
+      Some(value) => pairs(value)?,
+      None => Vec::new(),
+    };
+    entries.extend(args.named.into_iter().map(|(name, value)| (Value::str(&name), value)));
+    let dict = dict(d);
+    for (key, value) in entries {
+      dict.insert(key, value)?;
+    }
+    Ok(Value::None)
+  }),
+  ("values", |d, _, args| {
+    args.none("values")?;
+    Ok(Value::list(dict(d).entries().iter().map(|(_, value)| value.clone()).collect()))
+  }),
+];
+
+/// Returns the field or bound method `name` of a value.
+pub fn get_attr(value: &Value, name: &str) -> Option<Value> {
+  if let Value::Object(object) = value {
+    return object.get_attr(name);
+  }
+
+  let (_, method) = methods(value).iter().find(|(method, _)| *method == name)?;
+  let receiver = value.clone();
+  let method = *method;
+  Some(Value::builtin(name, move |eval, args| method(&receiver, eval, args)))
+}
+
+/// Returns the names of every field and method of a value.
+pub fn attr_names(value: &Value) -> Vec<String> {
+  match value {
+    Value::Object(object) => object.attr_names(),
+    _ => methods(value).iter().map(|(name, _)| name.to_string()).collect(),
+  }
+}
+
+fn methods(value: &Value) -> &'static [(&'static str, Method)] {
+  match value {
+    Value::Str(_) => STRING_METHODS,
+    Value::List(_) => LIST_METHODS,
+    Value::Dict(_) => DICT_METHODS,
+    _ => &[],
+  }
+}
+
+fn string(value: &Value) -> &str {
+  value.as_str().unwrap()
+}
+
+fn list(value: &Value) -> &Rc<List> {
+  match value {
+    Value::List(list) => list,
+    _ => unreachable!(),
+  }
+}
+
+fn dict(value: &Value) -> &Rc<Dict> {
+  match value {
+    Value::Dict(dict) => dict,
+    _ => unreachable!(),
+  }
+}
+
+fn one(function: &str, args: Args, name: &str) -> Result<[Value; 1], EvalError> {
+  let [value] = args.bind(function, &[name], &[])?.try_into().unwrap();
+  Ok([value.unwrap()])
+}
+
+/// Converts a byte offset into a character offset, or -1 if not found.
+fn char_index(s: &str, index: Option<usize>) -> i64 {
+  index.map_or(-1, |index| s[..index].chars().count() as i64)
+}
+
+/// Tests a prefix or suffix, which may be a string or a tuple of strings.
+fn affix(value: &Value, what: &str, test: impl Fn(&str) -> bool) -> Result<Value, EvalError> {
+  match value {
+    Value::Tuple(items) => {
+      for item in items.iter() {
+        if test(item.expect_str(what)?) {
+          return Ok(Value::Bool(true));
+        }
+      }
+      Ok(Value::Bool(false))
+    },
+    value => Ok(Value::Bool(test(value.expect_str(what)?))),
+  }
+}
+
+fn test(function: &str, s: &Value, args: Args, test: fn(char) -> bool) -> Result<Value, EvalError> {
+  args.none(function)?;
+  let s = string(s);
+  Ok(Value::Bool(!s.is_empty() && s.chars().all(test)))
+}
+
+fn strip(
+  function: &str,
+  s: &Value,
+  args: Args,
+  strip: for<'a> fn(&'a str, &dyn Fn(char) -> bool) -> &'a str,
+) -> Result<Value, EvalError> {
+  let [chars] = args.bind(function, &[], &["chars"])?.try_into().unwrap();
+  Ok(Value::str(match chars {
+    Some(Value::None) | None => strip(string(s), &|c: char| c.is_whitespace()),
+    Some(chars) => {
+      let chars = chars.expect_str("chars")?;
+      strip(string(s), &|c| chars.contains(c))
+    },
+  }))
+}
+
+fn partition(function: &str, s: &Value, args: Args, reverse: bool) -> Result<Value, EvalError> {
+  let [sep] = one(function, args, "sep")?;
+  let sep = sep.expect_str("sep")?;
+  if sep.is_empty() {
+    return Err(EvalError::msg("Empty separator."));
+  }
+  let s = string(s);
+  let found = if reverse { s.rfind(sep) } else { s.find(sep) };
+  let parts = match found {
+    Some(index) => [&s[..index], sep, &s[index + sep.len()..]],
+    None if reverse => ["", "", s],
+    None => [s, "", ""],
+  };
+  Ok(Value::tuple(parts.into_iter().map(Value::str).collect()))
+}
+
+fn split(function: &str, s: &Value, args: Args, reverse: bool) -> Result<Value, EvalError> {
+  let [sep, max] = args.bind(function, &[], &["sep", "maxsplit"])?.try_into().unwrap();
+  let s = string(s);
+  let max = match max {
+    Some(Value::None) | None => usize::MAX,
+    Some(max) => usize::try_from(max.expect_int("maxsplit")?).unwrap_or(usize::MAX),
+  };
+
+  let parts: Vec<&str> = match sep {
+    Some(Value::None) | None => {
+      let mut parts = Vec::new();
+      let mut rest = if reverse { s.trim_end() } else { s.trim_start() };
+      while !rest.is_empty() {
+        let found = if reverse {
+          rest.rfind(char::is_whitespace)
+        } else {
+          rest.find(char::is_whitespace)
+        };
+        match found {
+          Some(index) if parts.len() < max => {
+            let end = index + rest[index..].chars().next().unwrap().len_utf8();
+            if reverse {
+              parts.push(&rest[end..]);
+              rest = rest[..index].trim_end();
+            } else {
+              parts.push(&rest[..index]);
+              rest = rest[end..].trim_start();
+            }
+          },
+          _ => {
+            parts.push(rest);
+            break;
+          },
+        }
+      }
+      if reverse {
+        parts.reverse();
+      }
+      parts
+    },
+    Some(sep) => {
+      let sep = sep.expect_str("sep")?;
+      if sep.is_empty() {
+        return Err(EvalError::msg("Empty separator."));
+      }
+      if reverse {
+        let mut parts: Vec<_> = s.rsplitn(max.saturating_add(1), sep).collect();
+        parts.reverse();
+        parts
+      } else {
+        s.splitn(max.saturating_add(1), sep).collect()
+      }
+    },
+  };
+  Ok(Value::list(parts.into_iter().map(Value::str).collect()))
+}
+
+/// Implements `str.format()` with `{}`, `{0}` and `{name}` fields.
+fn format(s: &Value, _: &mut Evaluator, args: Args) -> Result<Value, EvalError> {
+  let mut result = String::new();
+  let mut chars = string(s).chars().peekable();
+  let mut next_positional = 0;
+  while let Some(c) = chars.next() {
+    match c {
+      '{' if chars.peek() == Some(&'{') => {
+        chars.next();
+        result.push('{');
+      },
+      '}' if chars.peek() == Some(&'}') => {
+        chars.next();
+        result.push('}');
+      },
+      '{' => {
+        let mut field = String::new();
+        loop {
+          match chars.next() {
+            Some('}') => break,
+            Some(c) => field.push(c),
+            None => return Err(EvalError::msg("Unterminated replacement field in format string.")),
+          }
+        }
+        let value = if field.is_empty() {
+          next_positional += 1;
+          args.positional.get(next_positional - 1)
+        } else if let Ok(index) = field.parse::<usize>() {
+          args.positional.get(index)
+        } else {
+          args.named.iter().find(|(name, _)| *name == field).map(|(_, value)| value)
+        };
+        let value = value.ok_or_else(|| {
+          EvalError::msg(format!("No argument for replacement field `{{{}}}`.", field))
+        })?;
+        result.push_str(&value.to_str());
+      },
+      '}' => return Err(EvalError::msg("Single `}` in format string.")),
+      c => result.push(c),
+    }
+  }
+  Ok(Value::str(&result))
+}
+
+#[cfg(test)]
+mod test {
+  use std::collections::HashMap;
+  use super::super::eval::ModuleEnv;
+  use super::super::parser::parse;
+  use super::*;
+
+  fn eval(source: &str) -> Rc<ModuleEnv> {
+    let module = parse("test.bzl", source).unwrap();
+    let env = ModuleEnv::new("test.bzl", Rc::new(HashMap::new()));
+    Evaluator::new().eval_module(&module, &env).unwrap();
+    env
+  }
+
+  fn eval_expr(source: &str) -> String {
+    eval(&format!("x = {}", source)).get("x").unwrap().repr()
+  }
+
+  #[test]
+  fn string_methods() {
+    assert_eq!(eval_expr("\"a,b,,c\".split(\",\")"), "[\"a\", \"b\", \"\", \"c\"]");
+    assert_eq!(eval_expr("\"a/b/c\".rsplit(\"/\", 1)"), "[\"a/b\", \"c\"]");
+    assert_eq!(eval_expr("\"  a  b \".split()"), "[\"a\", \"b\"]");
+    assert_eq!(eval_expr("\"a b  c\".split(None, 1)"), "[\"a\", \"b  c\"]");
+    assert_eq!(eval_expr("\"{} and {name}\".format(1, name = \"x\")"), "\"1 and x\"");
+    assert_eq!(eval_expr("\"-\".join([\"a\", \"b\"])"), "\"a-b\"");
+    assert_eq!(eval_expr("\"xxhixx\".strip(\"x\")"), "\"hi\"");
+    assert_eq!(eval_expr("\"foo.tar.gz\".rpartition(\".\")"), "(\"foo.tar\", \".\", \"gz\")");
+    assert_eq!(eval_expr("\"main.ts\".endswith((\".js\", \".ts\"))"), "True");
+    assert_eq!(eval_expr("\"hello world\".title()"), "\"Hello World\"");
+  }
+
+  #[test]
+  fn list_and_dict_methods() {
+    let env = eval(r#"
+l = [3, 1]
+l.append(2)
+l.insert(0, 4)
+l.remove(1)
+popped = l.pop()
+d = {"a": 1}
+d.update([("b", 2)], c = 3)
+d.setdefault("a", 5)
+removed = d.pop("b")
+"#);
+    assert_eq!(env.get("l").unwrap().repr(), "[4, 3]");
+    assert_eq!(env.get("popped").unwrap(), Value::Int(2));
+    assert_eq!(env.get("d").unwrap().repr(), "{\"a\": 1, \"c\": 3}");
+    assert_eq!(env.get("removed").unwrap(), Value::Int(2));
+  }
+}
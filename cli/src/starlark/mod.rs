@@ -0,0 +1,9 @@
+pub mod ast;
+pub mod builtins;
+pub mod error;
+pub mod eval;
+pub mod json;
+pub mod lexer;
+pub mod methods;
+pub mod parser;
+pub mod value;
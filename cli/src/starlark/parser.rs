@@ -0,0 +1,946 @@
+use std::rc::Rc;
+use super::ast::{
+  Arg, BinaryOp, Clause, Expr, ExprKind, FunctionDef, Module, Param, ParamKind,
+  Stmt, StmtKind, UnaryOp,
+};
+use super::error::{EvalError, Location};
+use super::lexer::{tokenize, Spanned, Token};
+
+/// Parses a Starlark file into its syntax tree.
+pub fn parse(file: &str, source: &str) -> Result<Module, EvalError> {
+  let file: Rc<str> = Rc::from(file);
+  let tokens = tokenize(&file, source)?;
+  let mut parser = Parser { tokens, index: 0, depth: 0 };
+
+  let mut body = Vec::new();
+  while parser.peek() != &Token::Eof {
+    if parser.eat(&Token::Newline) {
+      continue;
+    }
+    body.extend(parser.statement()?);
+  }
+
+  Ok(Module { file, body })
+}
+
+/// How deeply expressions and blocks may nest, which keeps pathological
+/// inputs like thousands of `[` from overflowing the stack of the parser or
+/// the evaluator. Each operator, call, attribute access or index chained onto
+/// an expression nests it one level deeper.
+const MAX_NESTING: usize = 50;
+
+struct Parser {
+  tokens: Vec<Spanned>,
+  index: usize,
+
+  /// The current nesting depth of expressions and blocks.
+  depth: usize,
+}
+
+impl Parser {
+  /// Parses one level of nesting, failing if it is too deep.
+  fn nested<T>(&mut self, parse: impl FnOnce(&mut Parser) -> Result<T, EvalError>) ->
+      Result<T, EvalError> {
+    self.deepen()?;
+    let result = parse(self);
+    self.depth -= 1;
+    result
+  }
+
+  /// Enters one more level of nesting, failing if it is too deep. Loops
+  /// chaining onto an expression call this for every link and restore the
+  /// depth once the chain ends.
+  fn deepen(&mut self) -> Result<(), EvalError> {
+    if self.depth >= MAX_NESTING {
+      return Err(EvalError::new(
+        self.location(),
+        format!("Expression or block is nested more than {} levels deep.", MAX_NESTING),
+      ));
+    }
+    self.depth += 1;
+    Ok(())
+  }
+
+  fn peek(&self) -> &Token {
+    &self.tokens[self.index].token
+  }
+
+  fn peek_at(&self, offset: usize) -> &Token {
+    let index = (self.index + offset).min(self.tokens.len() - 1);
+    &self.tokens[index].token
+  }
+
+  fn location(&self) -> Location {
+    self.tokens[self.index].location.clone()
+  }
+
+  fn next(&mut self) -> Token {
+    let token = self.tokens[self.index].token.clone();
+    if self.index < self.tokens.len() - 1 {
+      self.index += 1;
+    }
+    token
+  }
+
+  fn eat(&mut self, token: &Token) -> bool {
+    if self.peek() == token {
+      self.next();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn eat_punct(&mut self, punct: &str) -> bool {
+    if matches!(self.peek(), Token::Punct(p) if *p == punct) {
+      self.next();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn eat_keyword(&mut self, keyword: &str) -> bool {
+    if matches!(self.peek(), Token::Keyword(k) if *k == keyword) {
+      self.next();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn is_punct(&self, punct: &str) -> bool {
+    matches!(self.peek(), Token::Punct(p) if *p == punct)
+  }
+
+  fn is_keyword(&self, keyword: &str) -> bool {
+    matches!(self.peek(), Token::Keyword(k) if *k == keyword)
+  }
+
+  fn expect_punct(&mut self, punct: &str) -> Result<(), EvalError> {
+    if self.eat_punct(punct) {
+      Ok(())
+    } else {
+      Err(self.unexpected(&format!("`{}`", punct)))
+    }
+  }
+
+  fn expect_ident(&mut self) -> Result<String, EvalError> {
+    match self.peek().clone() {
+      Token::Ident(name) => {
+        self.next();
+        Ok(name)
+      },
+      _ => Err(self.unexpected("an identifier")),
+    }
+  }
+
+  fn unexpected(&self, expected: &str) -> EvalError {
+    let found = match self.peek() {
+      Token::Ident(name) => format!("`{}`", name),
+      Token::Int(value) => format!("`{}`", value),
+      Token::Str(value) => format!("{:?}", value),
+      Token::Keyword(keyword) => format!("`{}`", keyword),
+      Token::Punct(punct) => format!("`{}`", punct),
+      Token::Newline => "end of line".to_owned(),
+      Token::Indent => "indentation".to_owned(),
+      Token::Dedent => "end of block".to_owned(),
+      Token::Eof => "end of file".to_owned(),
+    };
+    EvalError::new(self.location(), format!("Expected {}, found {}.", expected, found))
+  }
+
+  /// Parses a compound statement or a line of simple statements.
+  fn statement(&mut self) -> Result<Vec<Stmt>, EvalError> {
+    let location = self.location();
+    if self.eat_keyword("def") {
+      let name = self.expect_ident()?;
+      self.expect_punct("(")?;
+      let params = self.params(")")?;
+      self.expect_punct(")")?;
+      self.expect_punct(":")?;
+      let body = self.suite()?;
+
+      return Ok(vec![Stmt {
+        kind: StmtKind::Def(Rc::new(FunctionDef {
+          name,
+          params,
+          body,
+          location: location.clone(),
+        })),
+        location,
+      }]);
+    }
+
+    if self.eat_keyword("if") {
+      return Ok(vec![self.if_statement(location)?]);
+    }
+
+    if self.eat_keyword("for") {
+      let target = self.loop_variables()?;
+      if !self.eat_keyword("in") {
+        return Err(self.unexpected("`in`"));
+      }
+      let iterable = self.expression_list()?;
+      self.expect_punct(":")?;
+      let body = self.suite()?;
+
+      return Ok(vec![Stmt {
+        kind: StmtKind::For { target, iterable, body },
+        location,
+      }]);
+    }
+
+    self.simple_statements()
+  }
+
+  /// Parses the remainder of an `if` statement after the `if` or `elif`.
+  fn if_statement(&mut self, location: Location) -> Result<Stmt, EvalError> {
+    let condition = self.test()?;
+    self.expect_punct(":")?;
+    let then = self.suite()?;
+
+    let elif_location = self.location();
+    let otherwise = if self.eat_keyword("elif") {
+      vec![self.if_statement(elif_location)?]
+    } else if self.eat_keyword("else") {
+      self.expect_punct(":")?;
+      self.suite()?
+    } else {
+      Vec::new()
+    };
+
+    Ok(Stmt { kind: StmtKind::If { condition, then, otherwise }, location })
+  }
+
+  /// Parses an indented block or simple statements on the same line.
+  fn suite(&mut self) -> Result<Vec<Stmt>, EvalError> {
+    self.nested(Parser::suite_body)
+  }
+
+  fn suite_body(&mut self) -> Result<Vec<Stmt>, EvalError> {
+    if !self.eat(&Token::Newline) {
+      return self.simple_statements();
+    }
+    if !self.eat(&Token::Indent) {
+      return Err(self.unexpected("an indented block"));
+    }
+
+    let mut body = Vec::new();
+    while !self.eat(&Token::Dedent) {
+      if self.peek() == &Token::Eof {
+        break;
+      }
+      body.extend(self.statement()?);
+    }
+
+    Ok(body)
+  }
+
+  /// Parses simple statements separated by `;` up to the end of the line.
+  fn simple_statements(&mut self) -> Result<Vec<Stmt>, EvalError> {
+    let mut statements = vec![self.simple_statement()?];
+    while self.eat_punct(";") {
+      if self.peek() == &Token::Newline {
+        break;
+      }
+      statements.push(self.simple_statement()?);
+    }
+
+    if !self.eat(&Token::Newline) && self.peek() != &Token::Eof {
+      return Err(self.unexpected("end of line"));
+    }
+
+    Ok(statements)
+  }
+
+  fn simple_statement(&mut self) -> Result<Stmt, EvalError> {
+    let location = self.location();
+    let kind = if self.eat_keyword("return") {
+      if matches!(self.peek(), Token::Newline | Token::Eof) || self.is_punct(";") {
+        StmtKind::Return(None)
+      } else {
+        StmtKind::Return(Some(self.expression_list()?))
+      }
+    } else if self.eat_keyword("pass") {
+      StmtKind::Pass
+    } else if self.eat_keyword("break") {
+      StmtKind::Break
+    } else if self.eat_keyword("continue") {
+      StmtKind::Continue
+    } else if self.eat_keyword("load") {
+      self.load()?
+    } else {
+      let target = self.expression_list()?;
+      let op = match self.peek() {
+        Token::Punct("=") => Some(None),
+        Token::Punct("+=") => Some(Some(BinaryOp::Add)),
+        Token::Punct("-=") => Some(Some(BinaryOp::Subtract)),
+        Token::Punct("*=") => Some(Some(BinaryOp::Multiply)),
+        Token::Punct("/=") => Some(Some(BinaryOp::Divide)),
+        Token::Punct("//=") => Some(Some(BinaryOp::FloorDivide)),
+        Token::Punct("%=") => Some(Some(BinaryOp::Modulo)),
+        Token::Punct("|=") => Some(Some(BinaryOp::BitOr)),
+        Token::Punct("&=") => Some(Some(BinaryOp::BitAnd)),
+        Token::Punct("^=") => Some(Some(BinaryOp::BitXor)),
+        Token::Punct("<<=") => Some(Some(BinaryOp::ShiftLeft)),
+        Token::Punct(">>=") => Some(Some(BinaryOp::ShiftRight)),
+        _ => None,
+      };
+
+      match op {
+        None => StmtKind::Expr(target),
+        Some(op) => {
+          self.next();
+          check_assignable(&target, op.is_some())?;
+          let value = self.expression_list()?;
+          StmtKind::Assign { target, op, value }
+        },
+      }
+    };
+
+    Ok(Stmt { kind, location })
+  }
+
+  /// Parses the remainder of a `load` statement after the keyword.
+  fn load(&mut self) -> Result<StmtKind, EvalError> {
+    self.expect_punct("(")?;
+    let module = match self.next() {
+      Token::Str(module) => module,
+      _ => {
+        self.index -= 1;
+        return Err(self.unexpected("a module label string"));
+      },
+    };
+
+    let mut symbols = Vec::new();
+    while self.eat_punct(",") {
+      if self.is_punct(")") {
+        break;
+      }
+
+      match (self.peek().clone(), self.peek_at(1).clone()) {
+        (Token::Str(name), _) => {
+          self.next();
+          symbols.push((name.clone(), name));
+        },
+        (Token::Ident(local), Token::Punct("=")) => {
+          self.next();
+          self.next();
+          match self.next() {
+            Token::Str(name) => symbols.push((local, name)),
+            _ => {
+              self.index -= 1;
+              return Err(self.unexpected("a symbol name string"));
+            },
+          }
+        },
+        _ => return Err(self.unexpected("a symbol to load")),
+      }
+    }
+    self.expect_punct(")")?;
+
+    if symbols.is_empty() {
+      return Err(EvalError::new(self.location(), "`load` requires at least one symbol."));
+    }
+    Ok(StmtKind::Load { module, symbols })
+  }
+
+  /// Parses function parameters up to (but not including) the closing token.
+  fn params(&mut self, close: &str) -> Result<Vec<Param>, EvalError> {
+    let mut params = Vec::new();
+    while !self.is_punct(close) {
+      if self.eat_punct("**") {
+        params.push(Param { name: self.expect_ident()?, kind: ParamKind::Kwargs });
+      } else if self.eat_punct("*") {
+        params.push(Param { name: self.expect_ident()?, kind: ParamKind::Args });
+      } else {
+        let name = self.expect_ident()?;
+        let default = if self.eat_punct("=") { Some(self.test()?) } else { None };
+        params.push(Param { name, kind: ParamKind::Normal(default) });
+      }
+
+      if !self.eat_punct(",") {
+        break;
+      }
+    }
+
+    Ok(params)
+  }
+
+  /// Parses comma separated expressions, producing a tuple if there is more
+  /// than one or a trailing comma.
+  fn expression_list(&mut self) -> Result<Expr, EvalError> {
+    let location = self.location();
+    let first = self.test()?;
+    if !self.is_punct(",") {
+      return Ok(first);
+    }
+
+    let mut items = vec![first];
+    while self.eat_punct(",") {
+      if self.at_expression_end() {
+        break;
+      }
+      items.push(self.test()?);
+    }
+
+    Ok(Expr { kind: ExprKind::Tuple(items), location })
+  }
+
+  /// Parses the targets of a `for` loop, which may be a bare tuple.
+  fn loop_variables(&mut self) -> Result<Expr, EvalError> {
+    let location = self.location();
+    let first = self.primary()?;
+    if !self.is_punct(",") {
+      check_assignable(&first, false)?;
+      return Ok(first);
+    }
+
+    let mut items = vec![first];
+    while self.eat_punct(",") {
+      if self.is_keyword("in") {
+        break;
+      }
+      items.push(self.primary()?);
+    }
+
+    let target = Expr { kind: ExprKind::Tuple(items), location };
+    check_assignable(&target, false)?;
+    Ok(target)
+  }
+
+  fn at_expression_end(&self) -> bool {
+    matches!(
+      self.peek(),
+      Token::Newline | Token::Eof | Token::Punct("=") | Token::Punct(")")
+        | Token::Punct("]") | Token::Punct("}") | Token::Punct(":")
+        | Token::Punct(";"),
+    ) || matches!(self.peek(), Token::Punct(p) if p.ends_with('=') && *p != "==")
+  }
+
+  /// Parses a full expression including conditionals and lambdas.
+  fn test(&mut self) -> Result<Expr, EvalError> {
+    self.nested(Parser::test_body)
+  }
+
+  fn test_body(&mut self) -> Result<Expr, EvalError> {
+    let location = self.location();
+    if self.eat_keyword("lambda") {
+      let params = self.params(":")?;
+      self.expect_punct(":")?;
+      let body_location = self.location();
+      let body = self.test()?;
+
+      return Ok(Expr {
+        kind: ExprKind::Lambda(Rc::new(FunctionDef {
+          name: "lambda".to_owned(),
+          params,
+          body: vec![Stmt { kind: StmtKind::Return(Some(body)), location: body_location }],
+          location: location.clone(),
+        })),
+        location,
+      });
+    }
+
+    let then = self.or_test()?;
+    if !self.eat_keyword("if") {
+      return Ok(then);
+    }
+
+    let condition = self.or_test()?;
+    if !self.eat_keyword("else") {
+      return Err(self.unexpected("`else`"));
+    }
+    let otherwise = self.test()?;
+
+    Ok(Expr {
+      kind: ExprKind::Conditional {
+        condition: Box::new(condition),
+        then: Box::new(then),
+        otherwise: Box::new(otherwise),
+      },
+      location,
+    })
+  }
+
+  fn or_test(&mut self) -> Result<Expr, EvalError> {
+    let depth = self.depth;
+    let mut lhs = self.and_test()?;
+    loop {
+      let location = self.location();
+      if !self.eat_keyword("or") {
+        self.depth = depth;
+        return Ok(lhs);
+      }
+      self.deepen()?;
+      let rhs = self.and_test()?;
+      lhs = binary(BinaryOp::Or, lhs, rhs, location);
+    }
+  }
+
+  fn and_test(&mut self) -> Result<Expr, EvalError> {
+    let depth = self.depth;
+    let mut lhs = self.not_test()?;
+    loop {
+      let location = self.location();
+      if !self.eat_keyword("and") {
+        self.depth = depth;
+        return Ok(lhs);
+      }
+      self.deepen()?;
+      let rhs = self.not_test()?;
+      lhs = binary(BinaryOp::And, lhs, rhs, location);
+    }
+  }
+
+  fn not_test(&mut self) -> Result<Expr, EvalError> {
+    let location = self.location();
+    if self.eat_keyword("not") {
+      let operand = self.nested(Parser::not_test)?;
+      return Ok(Expr {
+        kind: ExprKind::Unary { op: UnaryOp::Not, operand: Box::new(operand) },
+        location,
+      });
+    }
+
+    self.comparison()
+  }
+
+  fn comparison(&mut self) -> Result<Expr, EvalError> {
+    let depth = self.depth;
+    let mut lhs = self.binary_level(0)?;
+    loop {
+      let location = self.location();
+      let op = match self.peek() {
+        Token::Punct("==") => BinaryOp::Equal,
+        Token::Punct("!=") => BinaryOp::NotEqual,
+        Token::Punct("<") => BinaryOp::Less,
+        Token::Punct("<=") => BinaryOp::LessEqual,
+        Token::Punct(">") => BinaryOp::Greater,
+        Token::Punct(">=") => BinaryOp::GreaterEqual,
+        Token::Keyword("in") => BinaryOp::In,
+        Token::Keyword("not") if self.peek_at(1) == &Token::Keyword("in") => {
+          self.next();
+          BinaryOp::NotIn
+        },
+        _ => {
+          self.depth = depth;
+          return Ok(lhs);
+        },
+      };
+      self.next();
+      self.deepen()?;
+
+      let rhs = self.binary_level(0)?;
+      lhs = binary(op, lhs, rhs, location);
+    }
+  }
+
+  /// Parses left-associative binary operators, from lowest precedence (`|`)
+  /// to highest (`*`).
+  fn binary_level(&mut self, level: usize) -> Result<Expr, EvalError> {
+    const LEVELS: [&[(&str, BinaryOp)]; 6] = [
+      &[("|", BinaryOp::BitOr)],
+      &[("^", BinaryOp::BitXor)],
+      &[("&", BinaryOp::BitAnd)],
+      &[("<<", BinaryOp::ShiftLeft), (">>", BinaryOp::ShiftRight)],
+      &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+      &[
+        ("*", BinaryOp::Multiply),
+        ("/", BinaryOp::Divide),
+        ("//", BinaryOp::FloorDivide),
+        ("%", BinaryOp::Modulo),
+      ],
+    ];
+    if level == LEVELS.len() {
+      return self.factor();
+    }
+
+    let depth = self.depth;
+    let mut lhs = self.binary_level(level + 1)?;
+    loop {
+      let location = self.location();
+      let op = LEVELS[level].iter()
+        .find(|(punct, _)| self.is_punct(punct))
+        .map(|(_, op)| *op);
+      let op = match op {
+        Some(op) => op,
+        None => {
+          self.depth = depth;
+          return Ok(lhs);
+        },
+      };
+      self.next();
+      self.deepen()?;
+
+      let rhs = self.binary_level(level + 1)?;
+      lhs = binary(op, lhs, rhs, location);
+    }
+  }
+
+  fn factor(&mut self) -> Result<Expr, EvalError> {
+    let location = self.location();
+    let op = match self.peek() {
+      Token::Punct("-") => UnaryOp::Negate,
+      Token::Punct("+") => UnaryOp::Plus,
+      Token::Punct("~") => UnaryOp::Invert,
+      _ => return self.primary(),
+    };
+    self.next();
+
+    let operand = self.nested(Parser::factor)?;
+    Ok(Expr { kind: ExprKind::Unary { op, operand: Box::new(operand) }, location })
+  }
+
+  /// Parses an operand followed by any calls, attribute accesses and indexing.
+  fn primary(&mut self) -> Result<Expr, EvalError> {
+    let depth = self.depth;
+    let mut expr = self.operand()?;
+    loop {
+      let location = self.location();
+      if ["(", ".", "["].iter().any(|punct| self.is_punct(punct)) {
+        self.deepen()?;
+      }
+      if self.eat_punct("(") {
+        let args = self.args()?;
+        self.expect_punct(")")?;
+        expr = Expr {
+          kind: ExprKind::Call { function: Box::new(expr), args },
+          location,
+        };
+      } else if self.eat_punct(".") {
+        let name = self.expect_ident()?;
+        expr = Expr { kind: ExprKind::Dot { object: Box::new(expr), name }, location };
+      } else if self.eat_punct("[") {
+        expr = self.index_or_slice(expr, location)?;
+      } else {
+        self.depth = depth;
+        return Ok(expr);
+      }
+    }
+  }
+
+  fn index_or_slice(&mut self, object: Expr, location: Location) ->
+      Result<Expr, EvalError> {
+    let start = if self.is_punct(":") { None } else { Some(Box::new(self.expression_list()?)) };
+    if self.eat_punct("]") {
+      return Ok(Expr {
+        kind: ExprKind::Index { object: Box::new(object), index: start.unwrap() },
+        location,
+      });
+    }
+
+    self.expect_punct(":")?;
+    let stop = if self.is_punct(":") || self.is_punct("]") {
+      None
+    } else {
+      Some(Box::new(self.test()?))
+    };
+    let step = if self.eat_punct(":") && !self.is_punct("]") {
+      Some(Box::new(self.test()?))
+    } else {
+      None
+    };
+    self.expect_punct("]")?;
+
+    Ok(Expr {
+      kind: ExprKind::Slice { object: Box::new(object), start, stop, step },
+      location,
+    })
+  }
+
+  /// Parses call arguments up to (but not including) the closing `)`.
+  fn args(&mut self) -> Result<Vec<Arg>, EvalError> {
+    let mut args = Vec::new();
+    while !self.is_punct(")") {
+      let arg = if self.eat_punct("**") {
+        Arg::Kwargs(self.test()?)
+      } else if self.eat_punct("*") {
+        Arg::Args(self.test()?)
+      } else if matches!(self.peek(), Token::Ident(_)) && self.peek_at(1) == &Token::Punct("=") {
+        let name = self.expect_ident()?;
+        self.next();
+        Arg::Keyword(name, self.test()?)
+      } else {
+        Arg::Positional(self.test()?)
+      };
+      args.push(arg);
+
+      if !self.eat_punct(",") {
+        break;
+      }
+    }
+
+    Ok(args)
+  }
+
+  fn operand(&mut self) -> Result<Expr, EvalError> {
+    let location = self.location();
+    let kind = match self.next() {
+      Token::Ident(name) => ExprKind::Ident(name),
+      Token::Int(value) => ExprKind::Int(value),
+      Token::Str(value) => {
+        // Adjacent string literals are not concatenated in Starlark.
+        ExprKind::Str(value)
+      },
+      Token::Keyword("None") => ExprKind::None,
+      Token::Keyword("True") => ExprKind::Bool(true),
+      Token::Keyword("False") => ExprKind::Bool(false),
+      Token::Punct("(") => {
+        if self.eat_punct(")") {
+          ExprKind::Tuple(Vec::new())
+        } else {
+          let first = self.test()?;
+          if self.eat_punct(")") {
+            return Ok(first);
+          }
+
+          let mut items = vec![first];
+          while self.eat_punct(",") {
+            if self.is_punct(")") {
+              break;
+            }
+            items.push(self.test()?);
+          }
+          self.expect_punct(")")?;
+          ExprKind::Tuple(items)
+        }
+      },
+      Token::Punct("[") => {
+        if self.eat_punct("]") {
+          ExprKind::List(Vec::new())
+        } else {
+          let first = self.test()?;
+          if self.is_keyword("for") {
+            let clauses = self.clauses()?;
+            self.expect_punct("]")?;
+            ExprKind::ListComprehension { body: Box::new(first), clauses }
+          } else {
+            let mut items = vec![first];
+            while self.eat_punct(",") {
+              if self.is_punct("]") {
+                break;
+              }
+              items.push(self.test()?);
+            }
+            self.expect_punct("]")?;
+            ExprKind::List(items)
+          }
+        }
+      },
+      Token::Punct("{") => {
+        if self.eat_punct("}") {
+          ExprKind::Dict(Vec::new())
+        } else {
+          let key = self.test()?;
+          self.expect_punct(":")?;
+          let value = self.test()?;
+          if self.is_keyword("for") {
+            let clauses = self.clauses()?;
+            self.expect_punct("}")?;
+            ExprKind::DictComprehension { key: Box::new(key), value: Box::new(value), clauses }
+          } else {
+            let mut entries = vec![(key, value)];
+            while self.eat_punct(",") {
+              if self.is_punct("}") {
+                break;
+              }
+              let key = self.test()?;
+              self.expect_punct(":")?;
+              entries.push((key, self.test()?));
+            }
+            self.expect_punct("}")?;
+            ExprKind::Dict(entries)
+          }
+        }
+      },
+      _ => {
+        self.index -= 1;
+        return Err(self.unexpected("an expression"));
+      },
+    };
+
+    Ok(Expr { kind, location })
+  }
+
+  /// Parses the `for` and `if` clauses of a comprehension.
+  fn clauses(&mut self) -> Result<Vec<Clause>, EvalError> {
+    let mut clauses = Vec::new();
+    loop {
+      if self.eat_keyword("for") {
+        let target = self.loop_variables()?;
+        if !self.eat_keyword("in") {
+          return Err(self.unexpected("`in`"));
+        }
+        let iterable = self.or_test()?;
+        clauses.push(Clause::For { target, iterable });
+      } else if self.eat_keyword("if") {
+        clauses.push(Clause::If(self.or_test()?));
+      } else {
+        return Ok(clauses);
+      }
+    }
+  }
+}
+
+fn binary(op: BinaryOp, lhs: Expr, rhs: Expr, location: Location) -> Expr {
+  Expr { kind: ExprKind::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs) }, location }
+}
+
+/// Verifies the expression can be assigned to. Augmented assignments may not
+/// assign to tuples.
+fn check_assignable(target: &Expr, augmented: bool) -> Result<(), EvalError> {
+  match &target.kind {
+    ExprKind::Ident(_) | ExprKind::Index { .. } | ExprKind::Dot { .. } => Ok(()),
+    ExprKind::Tuple(items) | ExprKind::List(items) if !augmented => {
+      items.iter().try_for_each(|item| check_assignable(item, false))
+    },
+    _ => Err(EvalError::new(target.location.clone(), "Cannot assign to this expression.")),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use assertables::{assert_contains, assert_ok};
+  use super::*;
+
+  fn parse_expr(source: &str) -> ExprKind {
+    let mut module = parse("test.bzl", source).unwrap();
+    match module.body.remove(0).kind {
+      StmtKind::Expr(expr) => expr.kind,
+      other => panic!("Expected an expression, got {:?}", other),
+    }
+  }
+
+  fn ident(name: &str) -> Box<Expr> {
+    Box::new(Expr {
+      kind: ExprKind::Ident(name.to_owned()),
+      location: Location::new(&Rc::from("test.bzl"), 1, 1),
+    })
+  }
+
+  #[test]
+  fn parse_respects_operator_precedence() {
+    let ExprKind::Binary { op: BinaryOp::Add, rhs, .. } = parse_expr("1 + 2 * 3") else {
+      panic!("Expected addition.");
+    };
+    assert!(matches!(rhs.kind, ExprKind::Binary { op: BinaryOp::Multiply, .. }));
+
+    let ExprKind::Binary { op: BinaryOp::Or, lhs, .. } = parse_expr("a and b or c") else {
+      panic!("Expected `or`.");
+    };
+    assert!(matches!(lhs.kind, ExprKind::Binary { op: BinaryOp::And, .. }));
+  }
+
+  #[test]
+  fn parse_parses_calls_with_all_argument_kinds() {
+    let ExprKind::Call { function, args } = parse_expr("f(1, x = 2, *a, **k)") else {
+      panic!("Expected a call.");
+    };
+    assert_eq!(function.kind, ident("f").kind);
+    assert_eq!(args.len(), 4);
+    assert!(matches!(args[0], Arg::Positional(_)));
+    assert!(matches!(&args[1], Arg::Keyword(name, _) if name == "x"));
+    assert!(matches!(args[2], Arg::Args(_)));
+    assert!(matches!(args[3], Arg::Kwargs(_)));
+  }
+
+  #[test]
+  fn parse_parses_comprehensions() {
+    assert!(matches!(
+      parse_expr("[x for x in y if x]"),
+      ExprKind::ListComprehension { ref clauses, .. } if clauses.len() == 2,
+    ));
+    assert!(matches!(
+      parse_expr("{k: v for k, v in d.items()}"),
+      ExprKind::DictComprehension { ref clauses, .. } if clauses.len() == 1,
+    ));
+  }
+
+  #[test]
+  fn parse_parses_conditional_and_not_in() {
+    assert!(matches!(parse_expr("a if b else c"), ExprKind::Conditional { .. }));
+    assert!(matches!(parse_expr("a not in b"), ExprKind::Binary { op: BinaryOp::NotIn, .. }));
+  }
+
+  #[test]
+  fn parse_parses_slices() {
+    assert!(matches!(
+      parse_expr("x[1:]"),
+      ExprKind::Slice { start: Some(_), stop: None, step: None, .. },
+    ));
+    assert!(matches!(
+      parse_expr("x[::-1]"),
+      ExprKind::Slice { start: None, stop: None, step: Some(_), .. },
+    ));
+  }
+
+  #[test]
+  fn parse_parses_statements() {
+    let module = parse("test.bzl", r#"
+load("//foo:bar.bzl", "baz", qux = "quux")
+
+def f(a, b = 1, *args, **kwargs):
+  if a:
+    return b
+  elif b:
+    pass
+  else:
+    for x, y in args:
+      a += x
+  return None
+
+x, y = 1, 2
+"#).unwrap();
+
+    assert_eq!(module.body.len(), 3);
+    assert_eq!(module.body[0].kind, StmtKind::Load {
+      module: "//foo:bar.bzl".to_owned(),
+      symbols: vec![
+        ("baz".to_owned(), "baz".to_owned()),
+        ("qux".to_owned(), "quux".to_owned()),
+      ],
+    });
+    let StmtKind::Def(def) = &module.body[1].kind else {
+      panic!("Expected a function definition.");
+    };
+    assert_eq!(def.params.len(), 4);
+    assert_eq!(def.body.len(), 2);
+    assert!(matches!(&module.body[2].kind, StmtKind::Assign { op: None, .. }));
+  }
+
+  #[test]
+  fn parse_errors_with_location() {
+    let err = parse("test.bzl", "x = (1,\ny = 2\n").unwrap_err();
+    assert_eq!(err.to_string(), "test.bzl:2:3: Expected `)`, found `=`.");
+
+    let err = parse("test.bzl", "1 = x\n").unwrap_err();
+    assert_eq!(err.to_string(), "test.bzl:1:1: Cannot assign to this expression.");
+  }
+
+  #[test]
+  fn parse_errors_on_deep_nesting() {
+    for source in [
+      format!("x = {}", "[".repeat(100_000)),
+      format!("x = {}1", "-".repeat(100_000)),
+      format!("x = {}True", "not ".repeat(100_000)),
+      (0..300).map(|depth| format!("{}if True:\n", "    ".repeat(depth))).collect::<String>()
+        + &"    ".repeat(300) + "pass\n",
+      format!("x = {}[1]", "[1] + ".repeat(1000)),
+      format!("x = {}True", "True or ".repeat(1000)),
+      format!("x = 1{}", " == 1".repeat(1000)),
+      format!("x = f{}", "()".repeat(1000)),
+      format!("x = a{}", ".b".repeat(1000)),
+      format!("x = a{}", "[0]".repeat(1000)),
+    ] {
+      let err = parse("test.bzl", &source).unwrap_err();
+      assert_contains!(err.to_string(), "nested more than 50 levels deep");
+    }
+
+    let nested = format!("x = {}1{}\n", "[".repeat(45), "]".repeat(45));
+    assert_ok!(parse("test.bzl", &nested));
+    let chained = format!("x = {}[1]\n", "[1] + ".repeat(45));
+    assert_ok!(parse("test.bzl", &chained));
+  }
+}
@@ -0,0 +1,618 @@
+use std::any::Any;
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Formatter};
+use std::rc::Rc;
+use super::ast::{BinaryOp, FunctionDef};
+use super::error::EvalError;
+use super::eval::{Evaluator, ModuleEnv};
+
+/// A Starlark value. Compound values are reference counted and shared, so
+/// cloning a `Value` never copies a list or dict.
+#[derive(Clone)]
+pub enum Value {
+  None,
+  Bool(bool),
+  Int(i64),
+  Str(Rc<str>),
+  List(Rc<List>),
+  Tuple(Rc<[Value]>),
+  Dict(Rc<Dict>),
+  Function(Rc<Function>),
+  Builtin(Rc<Builtin>),
+
+  /// A value of a type defined outside the core language, such as a `File`.
+  Object(Rc<dyn Object>),
+}
+
+/// A value of a type defined outside the core language.
+pub trait Object: Any {
+  /// The name of the type, as returned by `type()`.
+  fn type_name(&self) -> String;
+
+  /// Returns the value of the field or method `name`, if it exists.
+  fn get_attr(&self, _name: &str) -> Option<Value> {
+    None
+  }
+
+  /// Returns the names of all fields and methods, as returned by `dir()`.
+  fn attr_names(&self) -> Vec<String> {
+    Vec::new()
+  }
+
+  /// Calls this value as a function.
+  fn call(self: Rc<Self>, _eval: &mut Evaluator, _args: Args) -> Result<Value, EvalError> {
+    Err(EvalError::msg(format!("`{}` is not callable.", self.type_name())))
+  }
+
+  /// Returns `self[key]`.
+  fn index(&self, _key: &Value) -> Result<Value, EvalError> {
+    Err(EvalError::msg(format!("`{}` is not indexable.", self.type_name())))
+  }
+
+  /// Returns `key in self`.
+  fn contains(&self, _key: &Value) -> Result<bool, EvalError> {
+    Err(EvalError::msg(format!("`{}` does not support `in`.", self.type_name())))
+  }
+
+  /// Evaluates `self <op> other` if `left`, otherwise `other <op> self`.
+  /// Returns `None` if the operation is not supported.
+  fn binary_op(&self, _op: BinaryOp, _other: &Value, _left: bool) ->
+      Option<Result<Value, EvalError>> {
+    None
+  }
+
+  /// Returns whether this value equals another object. Defaults to identity.
+  fn equals(&self, other: &dyn Object) -> bool {
+    std::ptr::addr_eq(self as *const Self, other as *const dyn Object)
+  }
+
+  /// Returns whether this value may be used as a dict key.
+  fn is_hashable(&self) -> bool {
+    false
+  }
+
+  /// The string produced by `str()`.
+  fn to_str(&self) -> String {
+    self.repr()
+  }
+
+  /// The string produced by `repr()`.
+  fn repr(&self) -> String {
+    format!("<{}>", self.type_name())
+  }
+
+  /// Makes this value and everything it references immutable.
+  fn freeze(&self) {}
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any>;
+}
+
+/// A mutable list.
+#[derive(Default)]
+pub struct List {
+  items: RefCell<Vec<Value>>,
+  frozen: Cell<bool>,
+
+  /// The number of loops currently iterating over the list.
+  iterators: Cell<usize>,
+}
+
+impl List {
+  pub fn new(items: Vec<Value>) -> List {
+    List { items: RefCell::new(items), ..List::default() }
+  }
+
+  pub fn items(&self) -> Ref<'_, Vec<Value>> {
+    self.items.borrow()
+  }
+
+  /// Returns the items for modification, failing if the list is frozen.
+  pub fn items_mut(&self) -> Result<RefMut<'_, Vec<Value>>, EvalError> {
+    if self.frozen.get() {
+      return Err(EvalError::msg("Cannot modify a frozen list."));
+    }
+    if self.iterators.get() > 0 {
+      return Err(EvalError::msg("Cannot modify a list while iterating over it."));
+    }
+    self.items.try_borrow_mut()
+      .map_err(|_| EvalError::msg("Cannot modify a list while iterating over it."))
+  }
+}
+
+/// A mutable dict which preserves insertion order.
+#[derive(Default)]
+pub struct Dict {
+  entries: RefCell<Vec<(Value, Value)>>,
+  frozen: Cell<bool>,
+
+  /// The number of loops currently iterating over the dict.
+  iterators: Cell<usize>,
+}
+
+impl Dict {
+  pub fn new(entries: Vec<(Value, Value)>) -> Result<Dict, EvalError> {
+    let dict = Dict::default();
+    for (key, value) in entries {
+      dict.insert(key, value)?;
+    }
+    Ok(dict)
+  }
+
+  pub fn entries(&self) -> Ref<'_, Vec<(Value, Value)>> {
+    self.entries.borrow()
+  }
+
+  pub fn get(&self, key: &Value) -> Result<Option<Value>, EvalError> {
+    check_hashable(key)?;
+    Ok(self.entries.borrow().iter()
+      .find(|(k, _)| k == key)
+      .map(|(_, value)| value.clone()))
+  }
+
+  /// Inserts or replaces the value for a key, failing if the dict is frozen.
+  pub fn insert(&self, key: Value, value: Value) -> Result<(), EvalError> {
+    check_hashable(&key)?;
+    let mut entries = self.entries_mut()?;
+    match entries.iter_mut().find(|(k, _)| *k == key) {
+      Some(entry) => entry.1 = value,
+      None => entries.push((key, value)),
+    }
+    Ok(())
+  }
+
+  /// Returns the entries for modification, failing if the dict is frozen.
+  pub fn entries_mut(&self) -> Result<RefMut<'_, Vec<(Value, Value)>>, EvalError> {
+    if self.frozen.get() {
+      return Err(EvalError::msg("Cannot modify a frozen dict."));
+    }
+    if self.iterators.get() > 0 {
+      return Err(EvalError::msg("Cannot modify a dict while iterating over it."));
+    }
+    self.entries.try_borrow_mut()
+      .map_err(|_| EvalError::msg("Cannot modify a dict while iterating over it."))
+  }
+}
+
+/// Keeps a list or dict locked against modification while it is iterated.
+pub struct IterationGuard(Value);
+
+impl Drop for IterationGuard {
+  fn drop(&mut self) {
+    let iterators = match &self.0 {
+      Value::List(list) => &list.iterators,
+      Value::Dict(dict) => &dict.iterators,
+      _ => return,
+    };
+    iterators.set(iterators.get() - 1);
+  }
+}
+
+/// A function defined in Starlark.
+pub struct Function {
+  pub def: Rc<FunctionDef>,
+
+  /// Evaluated default values for each parameter.
+  pub defaults: Vec<Option<Value>>,
+
+  /// The module the function was defined in, whose globals it can access.
+  pub module: Rc<ModuleEnv>,
+}
+
+/// The signature of functions implemented in Rust.
+pub type BuiltinFn = dyn Fn(&mut Evaluator, Args) -> Result<Value, EvalError>;
+
+/// A function implemented in Rust.
+pub struct Builtin {
+  pub name: String,
+  pub function: Box<BuiltinFn>,
+}
+
+/// The arguments of a function call.
+#[derive(Clone, Default)]
+pub struct Args {
+  pub positional: Vec<Value>,
+  pub named: Vec<(String, Value)>,
+}
+
+impl Args {
+  pub fn positional(positional: Vec<Value>) -> Args {
+    Args { positional, named: Vec::new() }
+  }
+
+  /// Binds the arguments to a builtin's parameters, which may all be passed
+  /// positionally or by name. Returns one entry per parameter in order, with
+  /// `None` for optional parameters which were not passed.
+  pub fn bind(self, function: &str, required: &[&str], optional: &[&str]) ->
+      Result<Vec<Option<Value>>, EvalError> {
+    let names: Vec<_> = required.iter().chain(optional.iter()).collect();
+    if self.positional.len() > names.len() {
+      return Err(EvalError::msg(format!(
+        "{}() accepts at most {} positional argument(s) but got {}.",
+        function,
+        names.len(),
+        self.positional.len(),
+      )));
+    }
+
+    let mut bound: Vec<_> = self.positional.into_iter().map(Some).collect();
+    bound.resize(names.len(), None);
+    for (name, value) in self.named {
+      let index = names.iter().position(|param| ***param == name).ok_or_else(|| {
+        EvalError::msg(format!("{}() got an unexpected keyword argument `{}`.", function, name))
+      })?;
+      if bound[index].is_some() {
+        return Err(EvalError::msg(format!(
+          "{}() got multiple values for argument `{}`.",
+          function,
+          name,
+        )));
+      }
+      bound[index] = Some(value);
+    }
+
+    if let Some(index) = (0..required.len()).find(|index| bound[*index].is_none()) {
+      return Err(EvalError::msg(format!(
+        "{}() missing required argument `{}`.",
+        function,
+        required[index],
+      )));
+    }
+
+    Ok(bound)
+  }
+
+  /// Fails if any arguments were passed, for functions with no parameters.
+  pub fn none(self, function: &str) -> Result<(), EvalError> {
+    self.bind(function, &[], &[])?;
+    Ok(())
+  }
+}
+
+impl Value {
+  /// Returns a function implemented in Rust.
+  pub fn builtin(
+    name: &str,
+    function: impl Fn(&mut Evaluator, Args) -> Result<Value, EvalError> + 'static,
+  ) -> Value {
+    Value::Builtin(Rc::new(Builtin { name: name.to_owned(), function: Box::new(function) }))
+  }
+
+  pub fn str(value: &str) -> Value {
+    Value::Str(Rc::from(value))
+  }
+
+  pub fn list(items: Vec<Value>) -> Value {
+    Value::List(Rc::new(List::new(items)))
+  }
+
+  pub fn tuple(items: Vec<Value>) -> Value {
+    Value::Tuple(Rc::from(items))
+  }
+
+  pub fn dict(entries: Vec<(Value, Value)>) -> Result<Value, EvalError> {
+    Ok(Value::Dict(Rc::new(Dict::new(entries)?)))
+  }
+
+  pub fn object(object: impl Object) -> Value {
+    Value::Object(Rc::new(object))
+  }
+
+  /// The name of this value's type, as returned by `type()`.
+  pub fn type_name(&self) -> String {
+    match self {
+      Value::None => "NoneType".to_owned(),
+      Value::Bool(_) => "bool".to_owned(),
+      Value::Int(_) => "int".to_owned(),
+      Value::Str(_) => "string".to_owned(),
+      Value::List(_) => "list".to_owned(),
+      Value::Tuple(_) => "tuple".to_owned(),
+      Value::Dict(_) => "dict".to_owned(),
+      Value::Function(_) => "function".to_owned(),
+      Value::Builtin(_) => "builtin_function_or_method".to_owned(),
+      Value::Object(object) => object.type_name(),
+    }
+  }
+
+  pub fn truthy(&self) -> bool {
+    match self {
+      Value::None => false,
+      Value::Bool(value) => *value,
+      Value::Int(value) => *value != 0,
+      Value::Str(value) => !value.is_empty(),
+      Value::List(list) => !list.items().is_empty(),
+      Value::Tuple(items) => !items.is_empty(),
+      Value::Dict(dict) => !dict.entries().is_empty(),
+      _ => true,
+    }
+  }
+
+  /// Returns the object of the given type if this value is one.
+  pub fn downcast<T: Object>(&self) -> Option<Rc<T>> {
+    match self {
+      Value::Object(object) => object.clone().as_any().downcast::<T>().ok(),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Value::Str(value) => Some(value),
+      _ => None,
+    }
+  }
+
+  /// Returns the string value or fails with an error naming `what`.
+  pub fn expect_str(&self, what: &str) -> Result<&str, EvalError> {
+    self.as_str().ok_or_else(|| self.type_error(what, "string"))
+  }
+
+  pub fn expect_int(&self, what: &str) -> Result<i64, EvalError> {
+    match self {
+      Value::Int(value) => Ok(*value),
+      _ => Err(self.type_error(what, "int")),
+    }
+  }
+
+  pub fn expect_bool(&self, what: &str) -> Result<bool, EvalError> {
+    match self {
+      Value::Bool(value) => Ok(*value),
+      _ => Err(self.type_error(what, "bool")),
+    }
+  }
+
+  /// Returns the items of a list or tuple or fails with an error naming `what`.
+  pub fn expect_list(&self, what: &str) -> Result<Vec<Value>, EvalError> {
+    match self {
+      Value::List(list) => Ok(list.items().clone()),
+      Value::Tuple(items) => Ok(items.to_vec()),
+      _ => Err(self.type_error(what, "list")),
+    }
+  }
+
+  /// Returns a list of strings or fails with an error naming `what`.
+  pub fn expect_str_list(&self, what: &str) -> Result<Vec<String>, EvalError> {
+    self.expect_list(what)?.iter()
+      .map(|item| item.expect_str(what).map(|item| item.to_owned()))
+      .collect()
+  }
+
+  pub fn expect_dict(&self, what: &str) -> Result<Vec<(Value, Value)>, EvalError> {
+    match self {
+      Value::Dict(dict) => Ok(dict.entries().clone()),
+      _ => Err(self.type_error(what, "dict")),
+    }
+  }
+
+  /// Returns the object of the given type or fails with an error naming
+  /// `what` and the expected type.
+  pub fn expect_object<T: Object>(&self, what: &str, expected: &str) ->
+      Result<Rc<T>, EvalError> {
+    self.downcast::<T>().ok_or_else(|| self.type_error(what, expected))
+  }
+
+  pub fn type_error(&self, what: &str, expected: &str) -> EvalError {
+    EvalError::msg(format!(
+      "Expected {} to be a {}, got a {} ({}).",
+      what,
+      expected,
+      self.type_name(),
+      self.repr(),
+    ))
+  }
+
+  /// The items produced by iterating this value in a `for` loop.
+  pub fn iterate(&self) -> Result<Vec<Value>, EvalError> {
+    match self {
+      Value::List(list) => Ok(list.items().clone()),
+      Value::Tuple(items) => Ok(items.to_vec()),
+      Value::Dict(dict) => Ok(dict.entries().iter().map(|(key, _)| key.clone()).collect()),
+      _ => Err(EvalError::msg(format!("`{}` is not iterable.", self.type_name()))),
+    }
+  }
+
+  /// Prevents modification of the value until the returned guard is dropped,
+  /// for the duration of a loop over it.
+  pub fn lock_for_iteration(&self) -> IterationGuard {
+    let iterators = match self {
+      Value::List(list) => Some(&list.iterators),
+      Value::Dict(dict) => Some(&dict.iterators),
+      _ => None,
+    };
+    if let Some(iterators) = iterators {
+      iterators.set(iterators.get() + 1);
+    }
+    IterationGuard(self.clone())
+  }
+
+  /// The string produced by `str()`.
+  pub fn to_str(&self) -> String {
+    match self {
+      Value::Str(value) => value.to_string(),
+      Value::Object(object) => object.to_str(),
+      _ => self.repr(),
+    }
+  }
+
+  /// The string produced by `repr()`.
+  pub fn repr(&self) -> String {
+    match self {
+      Value::None => "None".to_owned(),
+      Value::Bool(true) => "True".to_owned(),
+      Value::Bool(false) => "False".to_owned(),
+      Value::Int(value) => value.to_string(),
+      Value::Str(value) => quote(value),
+      Value::List(list) => format!(
+        "[{}]",
+        list.items().iter().map(|item| item.repr()).collect::<Vec<_>>().join(", "),
+      ),
+      Value::Tuple(items) if items.len() == 1 => format!("({},)", items[0].repr()),
+      Value::Tuple(items) => format!(
+        "({})",
+        items.iter().map(|item| item.repr()).collect::<Vec<_>>().join(", "),
+      ),
+      Value::Dict(dict) => format!(
+        "{{{}}}",
+        dict.entries().iter()
+          .map(|(key, value)| format!("{}: {}", key.repr(), value.repr()))
+          .collect::<Vec<_>>()
+          .join(", "),
+      ),
+      Value::Function(function) => format!("<function {}>", function.def.name),
+      Value::Builtin(builtin) => format!("<built-in function {}>", builtin.name),
+      Value::Object(object) => object.repr(),
+    }
+  }
+
+  /// Makes this value and everything it references immutable.
+  pub fn freeze(&self) {
+    match self {
+      Value::List(list) if !list.frozen.replace(true) => {
+        list.items().iter().for_each(|item| item.freeze());
+      },
+      Value::Dict(dict) if !dict.frozen.replace(true) => {
+        dict.entries().iter().for_each(|(key, value)| {
+          key.freeze();
+          value.freeze();
+        });
+      },
+      Value::Tuple(items) => items.iter().for_each(|item| item.freeze()),
+      Value::Object(object) => object.freeze(),
+      _ => {},
+    }
+  }
+
+  /// Compares two values for `<` and friends.
+  pub fn compare(&self, other: &Value) -> Result<Ordering, EvalError> {
+    match (self, other) {
+      (Value::Int(a), Value::Int(b)) => Ok(a.cmp(b)),
+      (Value::Str(a), Value::Str(b)) => Ok(a.cmp(b)),
+      (Value::Bool(a), Value::Bool(b)) => Ok(a.cmp(b)),
+      (Value::List(_) | Value::Tuple(_), Value::List(_) | Value::Tuple(_))
+          if std::mem::discriminant(self) == std::mem::discriminant(other) => {
+        let a = self.iterate()?;
+        let b = other.iterate()?;
+        for (a, b) in a.iter().zip(b.iter()) {
+          match a.compare(b)? {
+            Ordering::Equal => continue,
+            ordering => return Ok(ordering),
+          }
+        }
+        Ok(a.len().cmp(&b.len()))
+      },
+      _ => Err(EvalError::msg(format!(
+        "Cannot compare `{}` with `{}`.",
+        self.type_name(),
+        other.type_name(),
+      ))),
+    }
+  }
+}
+
+impl PartialEq for Value {
+  fn eq(&self, other: &Value) -> bool {
+    match (self, other) {
+      (Value::None, Value::None) => true,
+      (Value::Bool(a), Value::Bool(b)) => a == b,
+      (Value::Int(a), Value::Int(b)) => a == b,
+      (Value::Str(a), Value::Str(b)) => a == b,
+      (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b) || *a.items() == *b.items(),
+      (Value::Tuple(a), Value::Tuple(b)) => a == b,
+      (Value::Dict(a), Value::Dict(b)) => {
+        Rc::ptr_eq(a, b) || {
+          let a = a.entries();
+          let b = b.entries();
+          a.len() == b.len() && a.iter().all(|entry| b.contains(entry))
+        }
+      },
+      (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+      (Value::Builtin(a), Value::Builtin(b)) => Rc::ptr_eq(a, b),
+      (Value::Object(a), Value::Object(b)) => a.equals(b.as_ref()),
+      _ => false,
+    }
+  }
+}
+
+impl Debug for Value {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", self.repr())
+  }
+}
+
+/// Fails if the value cannot be used as a dict key.
+pub fn check_hashable(value: &Value) -> Result<(), EvalError> {
+  let hashable = match value {
+    Value::None | Value::Bool(_) | Value::Int(_) | Value::Str(_) => true,
+    Value::Function(_) | Value::Builtin(_) => true,
+    Value::Tuple(items) => return items.iter().try_for_each(check_hashable),
+    Value::Object(object) => object.is_hashable(),
+    Value::List(_) | Value::Dict(_) => false,
+  };
+
+  if hashable {
+    Ok(())
+  } else {
+    Err(EvalError::msg(format!("Unhashable type `{}`.", value.type_name())))
+  }
+}
+
+/// Quotes a string the way `repr()` does.
+pub fn quote(value: &str) -> String {
+  let mut quoted = String::from("\"");
+  for c in value.chars() {
+    match c {
+      '"' => quoted.push_str("\\\""),
+      '\\' => quoted.push_str("\\\\"),
+      '\n' => quoted.push_str("\\n"),
+      '\t' => quoted.push_str("\\t"),
+      '\r' => quoted.push_str("\\r"),
+      c => quoted.push(c),
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+/// An immutable record of named fields created by `struct()`.
+#[derive(Clone)]
+pub struct Struct {
+  pub fields: BTreeMap<String, Value>,
+}
+
+impl Object for Struct {
+  fn type_name(&self) -> String {
+    "struct".to_owned()
+  }
+
+  fn get_attr(&self, name: &str) -> Option<Value> {
+    self.fields.get(name).cloned()
+  }
+
+  fn attr_names(&self) -> Vec<String> {
+    self.fields.keys().cloned().collect()
+  }
+
+  fn equals(&self, other: &dyn Object) -> bool {
+    let other: &dyn Any = other;
+    other.downcast_ref::<Struct>().is_some_and(|other| other.fields == self.fields)
+  }
+
+  fn repr(&self) -> String {
+    format!(
+      "struct({})",
+      self.fields.iter()
+        .map(|(name, value)| format!("{} = {}", name, value.repr()))
+        .collect::<Vec<_>>()
+        .join(", "),
+    )
+  }
+
+  fn freeze(&self) {
+    self.fields.values().for_each(|value| value.freeze());
+  }
+
+  fn as_any(self: Rc<Self>) -> Rc<dyn Any> {
+    self
+  }
+}
@@ -1,10 +1,19 @@
 use std::error::Error;
 use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::path::Path;
+use crate::host::host::{EntryKind, Host};
+use crate::host::walk::{walk, WalkOptions};
+use crate::label::split_repo;
+use crate::package::BUILD_FILE_NAMES;
 
 /// A pattern describing a set of targets.
 #[derive(Clone, Debug, PartialEq)]
 pub struct TargetPattern {
+  /// The name of the external repository containing the packages, empty for
+  /// the main repository.
+  pub repo: String,
+
   pub package: String,
   pub scope: PatternScope,
 }
@@ -24,13 +33,23 @@ pub enum PatternScope {
 }
 
 impl TargetPattern {
-  /// Parses a `//path/to/pkg:target` string into an `Ok(TargetPattern)`.
-  /// Returns an `Err(ParseError)` if the input string does not match the
-  /// expected format.
+  /// Parses a `//path/to/pkg:target` string, optionally prefixed with an
+  /// `@repo`, into an `Ok(TargetPattern)`. Returns an `Err(ParseError)` if
+  /// the input string does not match the expected format.
   pub fn parse(pattern: &str) -> Result<TargetPattern, ParseError> {
+    let (repo, rest) = split_repo(pattern).map_err(|err| ParseError(err.0))?;
+    let parsed = match repo {
+      Some(repo) if rest.is_empty() => TargetPattern::parse_in_repo(&format!("//:{}", repo)),
+      _ => TargetPattern::parse_in_repo(rest),
+    };
+    Ok(TargetPattern { repo: repo.unwrap_or_default().to_owned(), ..parsed? })
+  }
+
+  fn parse_in_repo(pattern: &str) -> Result<TargetPattern, ParseError> {
     // Special case `//...` which will otherwise fail parsing.
     if pattern == "//..." {
       return Ok(TargetPattern {
+        repo: String::new(),
         package: "".to_owned(),
         scope: PatternScope::Descendants,
       })
@@ -38,7 +57,7 @@ impl TargetPattern {
 
     // Require leading `//`.
     let without_leading_slashes = pattern.strip_prefix("//");
-    if let None = without_leading_slashes {
+    if without_leading_slashes.is_none() {
       return Err(ParseError(format!(
         "Failed to parse `{}`, target patterns must start with `//`.",
         pattern,
@@ -52,6 +71,7 @@ impl TargetPattern {
       [ pattern ] => {
         match pattern.strip_suffix("/...") {
           Some(package) => Ok(TargetPattern {
+            repo: String::new(),
             package: package.to_owned(),
             scope: PatternScope::Descendants,
           }),
@@ -66,11 +86,13 @@ impl TargetPattern {
       [ package, target ] => {
         if target == "all" {
           Ok(TargetPattern {
+            repo: String::new(),
             package: package.to_owned(),
             scope: PatternScope::Package,
           })
         } else {
           Ok(TargetPattern {
+            repo: String::new(),
             package: package.to_owned(),
             scope: PatternScope::SingleTarget(target.to_owned()),
           })
@@ -86,8 +108,55 @@ impl TargetPattern {
   }
 }
 
+impl TargetPattern {
+  /// Returns whether the given package is one this pattern refers to, without
+  /// checking that it exists.
+  pub fn matches_package(&self, package: &str) -> bool {
+    match self.scope {
+      PatternScope::SingleTarget(_) | PatternScope::Package => package == self.package,
+      PatternScope::Descendants => {
+        self.package.is_empty() || package == self.package ||
+          package.strip_prefix(&self.package).is_some_and(|rest| rest.starts_with('/'))
+      },
+    }
+  }
+
+  /// Returns the workspace-relative paths of all packages this pattern refers
+  /// to in sorted order. `PatternScope::Descendants` patterns search for BUILD
+  /// files under the package, skipping anything in `.razelignore`. Other
+  /// scopes always refer to exactly their own package.
+  pub fn packages(&self, host: &dyn Host) -> Result<Vec<String>, Box<dyn Error>> {
+    match self.scope {
+      PatternScope::SingleTarget(_) | PatternScope::Package => {
+        Ok(vec![self.package.clone()])
+      },
+      PatternScope::Descendants => {
+        let root = Path::new(&self.package);
+        let mut packages = walk(host, root, &WalkOptions::default(), |entries| {
+          entries
+            .filter(|entry| entry.as_ref().map_or(true, |entry| {
+              entry.kind == EntryKind::File && BUILD_FILE_NAMES.iter()
+                .any(|name| entry.path.file_name().unwrap() == *name)
+            }))
+            .map(|entry| entry.map(|entry| {
+              entry.path.parent().unwrap().to_str().unwrap().to_owned()
+            }))
+            .collect::<Result<Vec<_>, _>>()
+        })??;
+        packages.sort();
+        packages.dedup();
+
+        Ok(packages)
+      },
+    }
+  }
+}
+
 impl Display for TargetPattern {
   fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    if !self.repo.is_empty() {
+      write!(f, "@{}", self.repo)?;
+    }
     match &self.scope {
       PatternScope::SingleTarget(target) => {
         write!(f, "//{}:{}", self.package, target)
@@ -95,6 +164,9 @@ impl Display for TargetPattern {
       PatternScope::Package => {
         write!(f, "//{}:all", self.package)
       },
+      PatternScope::Descendants if self.package.is_empty() => {
+        write!(f, "//...")
+      },
       PatternScope::Descendants => {
         write!(f, "//{}/...", self.package)
       },
@@ -122,10 +194,13 @@ impl Error for ParseError {
 mod test {
   use super::*;
   use assertables::assert_contains;
+  use crate::host::fs_host::FsHost;
+  use crate::host::test_dir::{TestContents, TestDir};
 
   #[test]
   fn parse_parses_single_target() {
     assert_eq!(TargetPattern::parse("//path/to/pkg:target"), Ok(TargetPattern {
+      repo: "".to_owned(),
       package: "path/to/pkg".to_owned(),
       scope: PatternScope::SingleTarget("target".to_owned()),
     }))
@@ -134,6 +209,7 @@ mod test {
   #[test]
   fn parse_parses_package_scope() {
     assert_eq!(TargetPattern::parse("//path/to/pkg:all"), Ok(TargetPattern {
+      repo: "".to_owned(),
       package: "path/to/pkg".to_owned(),
       scope: PatternScope::Package,
     }))
@@ -142,6 +218,7 @@ mod test {
   #[test]
   fn parse_parses_descendants_scope() {
     assert_eq!(TargetPattern::parse("//path/to/pkg/..."), Ok(TargetPattern {
+      repo: "".to_owned(),
       package: "path/to/pkg".to_owned(),
       scope: PatternScope::Descendants,
     }))
@@ -150,6 +227,7 @@ mod test {
   #[test]
   fn parse_parses_everything_pattern() {
     assert_eq!(TargetPattern::parse("//..."), Ok(TargetPattern {
+      repo: "".to_owned(),
       package: "".to_owned(),
       scope: PatternScope::Descendants,
     }))
@@ -176,10 +254,24 @@ mod test {
     assert_contains!(err.0, "may contain at most one `:`");
   }
 
+  #[test]
+  fn matches_package_matches_own_package_or_descendants() {
+    let package = TargetPattern::parse("//foo:all").unwrap();
+    assert!(package.matches_package("foo"));
+    assert!(!package.matches_package("foo/bar"));
+
+    let descendants = TargetPattern::parse("//foo/...").unwrap();
+    assert!(descendants.matches_package("foo"));
+    assert!(descendants.matches_package("foo/bar"));
+    assert!(!descendants.matches_package("foobar"));
+    assert!(TargetPattern::parse("//...").unwrap().matches_package(""));
+  }
+
   #[test]
   fn displays_single_target_pattern() {
     assert_eq!(
       format!("{}", TargetPattern {
+        repo: String::new(),
         package: "path/to/pkg".to_owned(),
         scope: PatternScope::SingleTarget("target".to_owned()),
       }),
@@ -191,6 +283,7 @@ mod test {
   fn displays_package_scope_pattern() {
     assert_eq!(
       format!("{}", TargetPattern {
+        repo: String::new(),
         package: "path/to/pkg".to_owned(),
         scope: PatternScope::Package,
       }),
@@ -202,10 +295,81 @@ mod test {
   fn displays_descendant_scope_pattern() {
     assert_eq!(
       format!("{}", TargetPattern {
+        repo: String::new(),
         package: "path/to/pkg".to_owned(),
         scope: PatternScope::Descendants,
       }),
       "//path/to/pkg/...",
     );
   }
+
+  #[test]
+  fn displays_everything_pattern() {
+    assert_eq!(
+      format!("{}", TargetPattern {
+        repo: String::new(),
+        package: "".to_owned(),
+        scope: PatternScope::Descendants,
+      }),
+      "//...",
+    );
+  }
+
+  #[test]
+  fn packages_returns_own_package_for_single_target_and_package_scopes() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(
+      TargetPattern::parse("//foo/bar:baz")?.packages(&host)?,
+      vec!["foo/bar".to_owned()],
+    );
+    assert_eq!(
+      TargetPattern::parse("//foo/bar:all")?.packages(&host)?,
+      vec!["foo/bar".to_owned()],
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn packages_finds_descendant_packages() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("BUILD"), TestContents::File("")),
+      (Path::new("foo/BUILD.razel"), TestContents::File("")),
+      (Path::new("foo/bar/BUILD"), TestContents::File("")),
+      (Path::new("foo/bar/BUILD.razel"), TestContents::File("")),
+      (Path::new("foo/not_a_package/file.txt"), TestContents::File("")),
+      (Path::new("other/BUILD"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(
+      TargetPattern::parse("//...")?.packages(&host)?,
+      vec!["", "foo", "foo/bar", "other"],
+    );
+    assert_eq!(
+      TargetPattern::parse("//foo/...")?.packages(&host)?,
+      vec!["foo", "foo/bar"],
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn packages_skips_ignored_descendants() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new(".razelignore"), TestContents::File("node_modules/\n")),
+      (Path::new("app/BUILD"), TestContents::File("")),
+      (Path::new("app/node_modules/dep/BUILD"), TestContents::File("")),
+    ])?;
+
+    let host = FsHost::from(&dir.root)?;
+
+    assert_eq!(TargetPattern::parse("//...")?.packages(&host)?, vec!["app"]);
+
+    Ok(())
+  }
 }
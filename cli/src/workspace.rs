@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+
+/// The file which marks the root directory of a workspace.
+pub const WORKSPACE_FILE: &str = "MODULE.razel";
+
+/// Finds the root of the workspace containing `dir` by searching it and its
+/// ancestors for a `MODULE.razel` file.
+pub fn find_root(dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+  dir.canonicalize()?
+    .ancestors()
+    .find(|ancestor| ancestor.join(WORKSPACE_FILE).is_file())
+    .map(|root| root.to_path_buf())
+    .ok_or_else(|| Box::new(NoWorkspaceError(format!(
+      "\"{}\" is not in a workspace, no `{}` file was found.",
+      dir.to_str().unwrap(),
+      WORKSPACE_FILE,
+    ))) as Box<dyn Error>)
+}
+
+/// An error thrown when Razel is not run inside a workspace.
+#[derive(Debug)]
+pub struct NoWorkspaceError(pub String);
+
+impl Display for NoWorkspaceError {
+  fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+    write!(f, "{}", &self.0)
+  }
+}
+
+impl Error for NoWorkspaceError {
+  fn description(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use assertables::assert_contains;
+  use crate::host::test_dir::{TestContents, TestDir};
+
+  #[test]
+  fn find_root_finds_workspace_in_ancestor() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("MODULE.razel"), TestContents::File("")),
+      (Path::new("foo/bar"), TestContents::Directory),
+    ])?;
+
+    assert_eq!(
+      find_root(&dir.root.join("foo/bar"))?,
+      dir.root.canonicalize()?,
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn find_root_finds_innermost_workspace() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("MODULE.razel"), TestContents::File("")),
+      (Path::new("nested/MODULE.razel"), TestContents::File("")),
+    ])?;
+
+    assert_eq!(
+      find_root(&dir.root.join("nested"))?,
+      dir.root.join("nested").canonicalize()?,
+    );
+
+    Ok(())
+  }
+
+  #[test]
+  fn find_root_errors_outside_workspace() -> Result<(), Box<dyn Error>> {
+    let dir = TestDir::from([
+      (Path::new("foo"), TestContents::Directory),
+    ])?;
+
+    let err = find_root(&dir.root.join("foo")).unwrap_err();
+    assert_contains!(err.to_string(), "not in a workspace");
+
+    Ok(())
+  }
+}